@@ -0,0 +1,46 @@
+fn main() {
+    #[cfg(feature = "bindgen")]
+    generate();
+}
+
+/// Generates the bindings into `$OUT_DIR/bindings.rs` with the same options
+/// as `gen.sh`. With `link`, the functions are plain `extern` declarations
+/// and pkg-config emits the link flags; otherwise they are loaded at runtime
+/// through the `va` struct.
+#[cfg(feature = "bindgen")]
+fn generate() {
+    use std::path::PathBuf;
+
+    println!("cargo:rerun-if-changed=wrapper.h");
+
+    let link = cfg!(feature = "link");
+    let mut include_paths = Vec::new();
+    for name in ["libva", "libva-drm"] {
+        let library = pkg_config::Config::new()
+            .cargo_metadata(link)
+            .probe(name)
+            .unwrap_or_else(|err| panic!("{}", err));
+        include_paths.extend(library.include_paths);
+    }
+
+    let mut builder = bindgen::Builder::default()
+        .header("wrapper.h")
+        .allowlist_file(".*/va/.*")
+        .prepend_enum_name(false)
+        .derive_default(true)
+        .clang_args(
+            include_paths
+                .iter()
+                .map(|path| format!("-I{}", path.display())),
+        )
+        .parse_callbacks(Box::new(bindgen::CargoCallbacks::new()));
+    if !link {
+        builder = builder.dynamic_library_name("va");
+    }
+    let out_dir = PathBuf::from(std::env::var_os("OUT_DIR").unwrap());
+    builder
+        .generate()
+        .expect("failed to generate libva bindings")
+        .write_to_file(out_dir.join("bindings.rs"))
+        .expect("failed to write libva bindings");
+}
@@ -5,4 +5,10 @@
     clippy::all
 )]
 
+#[cfg(not(any(feature = "dlopen", feature = "link")))]
+compile_error!("either the `dlopen` or the `link` feature must be enabled");
+
+#[cfg(not(feature = "bindgen"))]
 include!("bindings.rs");
+#[cfg(feature = "bindgen")]
+include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
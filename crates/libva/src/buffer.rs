@@ -1,52 +1,102 @@
-use std::sync::Arc;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::ThreadId;
+use std::time::Duration;
 
-use crate::{sys, va_enum_prefix_suffix, Context, Display, VaResult, VaStatusExt};
+use crate::{
+    sys, va_enum_prefix_suffix, Context, Display, Error, ErrorKind, ErrorStatus, ObjectId,
+    VaResult, VaStatusExt,
+};
 
-#[derive(Debug)]
+/// A VA buffer.
+///
+/// The contents are only reachable through [`BufferMap`] and [`BufferMapRead`]
+/// guards, which follow reader/writer locking semantics: any number of
+/// read-only maps may coexist, but a writable map is exclusive. The driver
+/// mapping itself is reference counted and released when the last guard is
+/// dropped. A map that would wait for a guard held by the calling thread
+/// fails instead of deadlocking.
+///
+/// A buffer created on a context keeps the context alive and is destroyed
+/// before it. The data store of an [`Image`](crate::Image) is owned by the
+/// image and has no context.
 pub struct Buffer {
     handle: sys::VABufferID,
     size: usize,
     display: Arc<Display>,
-    owned: bool,
-    // context: Arc<Context>,
+    context: Option<Arc<Context>>,
+    access: Mutex<Access>,
+    access_released: Condvar,
+    mapping: Mutex<Mapping>,
+}
+
+/// The threads holding guards on the contents of a buffer.
+#[derive(Debug, Default)]
+struct Access {
+    writer: Option<ThreadId>,
+    /// One entry per read guard.
+    readers: Vec<ThreadId>,
+}
+
+/// Access to the contents of a [`Buffer`], released on drop.
+pub(crate) struct AccessGuard<'a> {
+    buffer: &'a Buffer,
+    thread: ThreadId,
+    exclusive: bool,
 }
 
+#[derive(Debug)]
+struct Mapping {
+    data: *mut u8,
+    count: usize,
+}
+
+// Safety: the pointer is only dereferenced through map guards, which are
+// synchronized by `Buffer::access`.
+unsafe impl Send for Mapping {}
+
+/// An exclusive, writable mapping of a [`Buffer`].
 pub struct BufferMap<'a> {
     buffer: &'a Buffer,
-    data: &'a mut [u8],
+    data: *mut u8,
+    _access: AccessGuard<'a>,
+}
+
+/// A shared, read-only mapping of a [`Buffer`].
+pub struct BufferMapRead<'a> {
+    buffer: &'a Buffer,
+    data: *const u8,
+    _access: AccessGuard<'a>,
 }
 
 impl Buffer {
     pub fn new(context: Arc<Context>, buffer_type: BufferType, size: usize) -> VaResult<Arc<Self>> {
-        let display = context.display().clone();
-        let mut handle = 0;
-        unsafe {
-            display
-                .library()
-                .lib()
-                .vaCreateBuffer(
-                    display.handle(),
-                    context.handle(),
-                    buffer_type.into(),
-                    size as _,
-                    1,
-                    std::ptr::null_mut(),
-                    &mut handle,
-                )
-                .va_result()?;
-        }
-        Ok(Arc::new(Self {
-            handle,
-            size,
-            display,
-            owned: true,
-        }))
+        Self::create(context, buffer_type, size, std::ptr::null_mut())
     }
 
     pub fn new_with_data(
         context: Arc<Context>,
         buffer_type: BufferType,
         data: &[u8],
+    ) -> VaResult<Arc<Self>> {
+        Self::create(context, buffer_type, data.len(), data.as_ptr() as *mut _)
+    }
+
+    /// Creates a buffer holding a copy of `value`, one of the parameter
+    /// structs of [`sys`].
+    pub fn new_with_value<T: Copy>(
+        context: Arc<Context>,
+        buffer_type: BufferType,
+        value: &T,
+    ) -> VaResult<Arc<Self>> {
+        let data = (value as *const T).cast_mut().cast();
+        Self::create(context, buffer_type, std::mem::size_of::<T>(), data)
+    }
+
+    fn create(
+        context: Arc<Context>,
+        buffer_type: BufferType,
+        size: usize,
+        data: *mut std::ffi::c_void,
     ) -> VaResult<Arc<Self>> {
         let display = context.display().clone();
         let mut handle = 0;
@@ -58,63 +108,183 @@ impl Buffer {
                     display.handle(),
                     context.handle(),
                     buffer_type.into(),
-                    data.len() as _,
+                    size as _,
                     1,
-                    data.as_ptr() as *mut _,
+                    data,
                     &mut handle,
                 )
-                .va_result()?;
+                .va_check(
+                    display.library(),
+                    "vaCreateBuffer",
+                    &[ObjectId::Context(context.handle())],
+                )?;
         }
-        Ok(Arc::new(Self {
-            handle,
-            size: data.len(),
-            display,
-            owned: true,
-        }))
+        Ok(Arc::new(Self::from_raw(context, handle, size)))
+    }
+
+    /// Takes ownership of a buffer created on `context`.
+    pub fn from_raw(context: Arc<Context>, handle: sys::VABufferID, size: usize) -> Self {
+        let display = context.display().clone();
+        Self::with_owner(display, Some(context), handle, size)
+    }
+
+    /// Wraps the data store of an image, which is destroyed with the image.
+    pub(crate) fn image_data(display: Arc<Display>, handle: sys::VABufferID, size: usize) -> Self {
+        Self::with_owner(display, None, handle, size)
     }
 
-    pub fn from_raw(
+    pub(crate) fn with_owner(
         display: Arc<Display>,
+        context: Option<Arc<Context>>,
         handle: sys::VABufferID,
         size: usize,
-        owned: bool,
     ) -> Self {
         Self {
             display,
+            context,
             handle,
             size,
-            owned,
+            access: Mutex::default(),
+            access_released: Condvar::new(),
+            mapping: Mutex::new(Mapping {
+                data: std::ptr::null_mut(),
+                count: 0,
+            }),
         }
     }
 
+    pub fn handle(&self) -> sys::VABufferID {
+        self.handle
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
     pub fn display(&self) -> &Arc<Display> {
         &self.display
     }
 
+    /// The context the buffer was created on, or `None` for image data.
+    pub fn context(&self) -> Option<&Arc<Context>> {
+        self.context.as_ref()
+    }
+
     pub fn library(&self) -> &Arc<crate::Library> {
         self.display.library()
     }
 
+    /// Maps the buffer for writing, blocking until no other map of this
+    /// buffer is alive. Fails if the calling thread holds one.
     pub fn map(&self) -> VaResult<BufferMap<'_>> {
-        let display = self.display();
-        let mut data = std::ptr::null_mut();
-        unsafe {
-            display
-                .library()
-                .lib()
-                .vaMapBuffer(display.handle(), self.handle, &mut data)
-                .va_result()?;
-        }
+        let access = self.lock_exclusive("Buffer::map")?;
+        let data = self.acquire_mapping()?;
         Ok(BufferMap {
             buffer: self,
-            data: unsafe { std::slice::from_raw_parts_mut(data as *mut u8, self.size) },
+            data,
+            _access: access,
         })
     }
+
+    /// Maps the buffer for reading, blocking while a writable map is alive.
+    /// Fails if the calling thread holds the writable map.
+    pub fn map_read(&self) -> VaResult<BufferMapRead<'_>> {
+        let access = self.lock_shared("Buffer::map_read")?;
+        let data = self.acquire_mapping()?;
+        Ok(BufferMapRead {
+            buffer: self,
+            data,
+            _access: access,
+        })
+    }
+
+    /// Excludes maps and other driver access to the contents, for driver
+    /// calls that write them.
+    pub(crate) fn lock_exclusive(&self, operation: &'static str) -> VaResult<AccessGuard<'_>> {
+        let thread = std::thread::current().id();
+        let access = self.lock_access();
+        if access.writer == Some(thread) || access.readers.contains(&thread) {
+            return Err(self.would_deadlock(operation));
+        }
+        let mut access = self
+            .access_released
+            .wait_while(access, |access| {
+                access.writer.is_some() || !access.readers.is_empty()
+            })
+            .unwrap_or_else(|e| e.into_inner());
+        access.writer = Some(thread);
+        Ok(AccessGuard {
+            buffer: self,
+            thread,
+            exclusive: true,
+        })
+    }
+
+    /// Excludes writable maps, for driver calls that read the contents.
+    pub(crate) fn lock_shared(&self, operation: &'static str) -> VaResult<AccessGuard<'_>> {
+        let thread = std::thread::current().id();
+        let access = self.lock_access();
+        if access.writer == Some(thread) {
+            return Err(self.would_deadlock(operation));
+        }
+        let mut access = self
+            .access_released
+            .wait_while(access, |access| access.writer.is_some())
+            .unwrap_or_else(|e| e.into_inner());
+        access.readers.push(thread);
+        Ok(AccessGuard {
+            buffer: self,
+            thread,
+            exclusive: false,
+        })
+    }
+
+    fn lock_access(&self) -> MutexGuard<'_, Access> {
+        self.access.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn would_deadlock(&self, operation: &'static str) -> Error {
+        Error::with_kind(operation, ErrorKind::WouldDeadlock)
+            .with_objects(&[ObjectId::Buffer(self.handle)])
+    }
+
+    fn acquire_mapping(&self) -> VaResult<*mut u8> {
+        let mut mapping = self.mapping.lock().unwrap_or_else(|e| e.into_inner());
+        if mapping.count == 0 {
+            let mut data = std::ptr::null_mut();
+            unsafe {
+                self.library()
+                    .lib()
+                    .vaMapBuffer(self.display().handle(), self.handle, &mut data)
+                    .va_check(
+                        self.library(),
+                        "vaMapBuffer",
+                        &[ObjectId::Buffer(self.handle)],
+                    )?;
+            }
+            mapping.data = data as *mut u8;
+        }
+        mapping.count += 1;
+        Ok(mapping.data)
+    }
+
+    fn release_mapping(&self) {
+        let mut mapping = self.mapping.lock().unwrap_or_else(|e| e.into_inner());
+        mapping.count -= 1;
+        if mapping.count == 0 {
+            mapping.data = std::ptr::null_mut();
+            unsafe {
+                self.library()
+                    .lib()
+                    .vaUnmapBuffer(self.display().handle(), self.handle);
+            }
+        }
+    }
 }
 
 impl Drop for Buffer {
     fn drop(&mut self) {
-        if self.owned {
+        if self.context.is_some() {
             unsafe {
                 self.library()
                     .lib()
@@ -124,28 +294,200 @@ impl Drop for Buffer {
     }
 }
 
-impl<'a> std::ops::Deref for BufferMap<'a> {
+impl std::fmt::Debug for Buffer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Buffer")
+            .field("handle", &self.handle)
+            .field("size", &self.size)
+            .field("context", &self.context.as_ref().map(|c| c.handle()))
+            .finish()
+    }
+}
+
+impl Drop for AccessGuard<'_> {
+    fn drop(&mut self) {
+        let mut access = self.buffer.lock_access();
+        if self.exclusive {
+            access.writer = None;
+        } else if let Some(i) = access.readers.iter().position(|&t| t == self.thread) {
+            access.readers.swap_remove(i);
+        }
+        self.buffer.access_released.notify_all();
+    }
+}
+
+impl BufferMap<'_> {
+    pub fn buffer(&self) -> &Buffer {
+        self.buffer
+    }
+}
+
+impl std::ops::Deref for BufferMap<'_> {
     type Target = [u8];
 
     fn deref(&self) -> &Self::Target {
-        self.data
+        unsafe { std::slice::from_raw_parts(self.data, self.buffer.size) }
     }
 }
 
-impl<'a> std::ops::DerefMut for BufferMap<'a> {
+impl std::ops::DerefMut for BufferMap<'_> {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        self.data
+        unsafe { std::slice::from_raw_parts_mut(self.data, self.buffer.size) }
     }
 }
 
 impl Drop for BufferMap<'_> {
     fn drop(&mut self) {
+        self.buffer.release_mapping();
+    }
+}
+
+impl BufferMapRead<'_> {
+    pub fn buffer(&self) -> &Buffer {
+        self.buffer
+    }
+}
+
+impl std::ops::Deref for BufferMapRead<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { std::slice::from_raw_parts(self.data, self.buffer.size) }
+    }
+}
+
+impl Drop for BufferMapRead<'_> {
+    fn drop(&mut self) {
+        self.buffer.release_mapping();
+    }
+}
+
+/// A buffer of type [`BufferType::EncCoded`] receiving encoder output.
+///
+/// Mapping a coded buffer yields a list of segments rather than a flat byte
+/// array, so it is only exposed read-only through [`CodedBufferMap`].
+#[derive(Debug)]
+pub struct CodedBuffer {
+    buffer: Buffer,
+}
+
+/// A read-only mapping of a [`CodedBuffer`].
+pub struct CodedBufferMap<'a> {
+    map: BufferMapRead<'a>,
+}
+
+/// One segment of encoder output.
+#[derive(Debug, Clone, Copy)]
+pub struct CodedSegment<'a> {
+    pub data: &'a [u8],
+    pub bit_offset: u32,
+    pub status: u32,
+}
+
+impl CodedBuffer {
+    pub fn new(context: Arc<Context>, size: usize) -> VaResult<Arc<Self>> {
+        let display = context.display().clone();
+        let mut handle = 0;
         unsafe {
-            self.buffer
+            display
                 .library()
                 .lib()
-                .vaUnmapBuffer(self.buffer.display().handle(), self.buffer.handle);
+                .vaCreateBuffer(
+                    display.handle(),
+                    context.handle(),
+                    BufferType::EncCoded.into(),
+                    size as _,
+                    1,
+                    std::ptr::null_mut(),
+                    &mut handle,
+                )
+                .va_check(
+                    display.library(),
+                    "vaCreateBuffer",
+                    &[ObjectId::Context(context.handle())],
+                )?;
         }
+        Ok(Arc::new(Self {
+            buffer: Buffer::from_raw(context, handle, size),
+        }))
+    }
+
+    pub fn handle(&self) -> sys::VABufferID {
+        self.buffer.handle()
+    }
+
+    pub fn buffer(&self) -> &Buffer {
+        &self.buffer
+    }
+
+    /// Waits up to `timeout` for the encode writing this buffer to complete.
+    /// Returns `false` if it is still running.
+    pub fn sync_timeout(&self, timeout: Duration) -> VaResult<bool> {
+        let library = self.buffer.library();
+        let status = unsafe {
+            library.lib().vaSyncBuffer(
+                self.buffer.display().handle(),
+                self.handle(),
+                timeout.as_nanos().try_into().unwrap_or(u64::MAX),
+            )
+        };
+        if ErrorStatus::from_status(status) == Some(ErrorStatus::TimedOut) {
+            return Ok(false);
+        }
+        status.va_check(library, "vaSyncBuffer", &[ObjectId::Buffer(self.handle())])?;
+        Ok(true)
+    }
+
+    /// Maps the buffer, which waits for the encode writing it to complete.
+    pub fn map(&self) -> VaResult<CodedBufferMap<'_>> {
+        Ok(CodedBufferMap {
+            map: self.buffer.map_read()?,
+        })
+    }
+}
+
+impl<'a> CodedBufferMap<'a> {
+    pub fn segments(&self) -> CodedSegments<'_> {
+        CodedSegments {
+            next: self.map.data as *const sys::VACodedBufferSegment,
+            _map: self,
+        }
+    }
+
+    /// Concatenates the data of all segments.
+    pub fn to_vec(&self) -> Vec<u8> {
+        self.segments()
+            .flat_map(|s| s.data.iter().copied())
+            .collect()
+    }
+}
+
+pub struct CodedSegments<'a> {
+    next: *const sys::VACodedBufferSegment,
+    _map: &'a CodedBufferMap<'a>,
+}
+
+impl<'a> Iterator for CodedSegments<'a> {
+    type Item = CodedSegment<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next.is_null() {
+            return None;
+        }
+        // Safety: the segment list lives in the mapped buffer, which the
+        // borrowed `CodedBufferMap` keeps mapped and free of writers.
+        let segment = unsafe { &*self.next };
+        self.next = segment.next as *const sys::VACodedBufferSegment;
+        let data = if segment.buf.is_null() {
+            &[][..]
+        } else {
+            unsafe { std::slice::from_raw_parts(segment.buf as *const u8, segment.size as usize) }
+        };
+        Some(CodedSegment {
+            data,
+            bit_offset: segment.bit_offset,
+            status: segment.status,
+        })
     }
 }
 
@@ -204,3 +546,66 @@ va_enum_prefix_suffix! {
         SliceStruct,
    } BufferType
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fake, Config, ConfigAttributes, ContextFlags, Entrypoint, Profile};
+
+    fn buffer(fake: &fake::FakeBackend) -> Arc<Buffer> {
+        let display = fake::open_display(&fake.library()).unwrap();
+        let config = Config::new(
+            display,
+            Some(Profile::H264Main),
+            Entrypoint::VLD,
+            &ConfigAttributes::default(),
+        )
+        .unwrap();
+        let context = Context::new(config, 64, 64, ContextFlags::PROGRESSIVE, vec![]).unwrap();
+        Buffer::new_with_data(context, BufferType::SliceData, &[1, 2, 3, 4]).unwrap()
+    }
+
+    #[test]
+    fn maps() {
+        let fake = fake::FakeBackend::default();
+        let buffer = buffer(&fake);
+        buffer.map().unwrap()[0] = 5;
+        let a = buffer.map_read().unwrap();
+        let b = buffer.map_read().unwrap();
+        assert_eq!(*a, [5, 2, 3, 4]);
+        assert_eq!(a.as_ptr(), b.as_ptr());
+    }
+
+    #[test]
+    fn nested_maps_fail() {
+        let fake = fake::FakeBackend::default();
+        let buffer = buffer(&fake);
+        let map = buffer.map().unwrap();
+        let err = buffer.map().err().unwrap();
+        assert_eq!(err.operation(), "Buffer::map");
+        assert_eq!(err.kind(), ErrorKind::WouldDeadlock);
+        assert_eq!(err.status(), None);
+        assert!(buffer.map_read().is_err());
+        drop(map);
+
+        let read = buffer.map_read().unwrap();
+        assert!(buffer.map().is_err());
+        drop(read);
+        buffer.map().unwrap();
+    }
+
+    #[test]
+    fn maps_wait_for_other_threads() {
+        let fake = fake::FakeBackend::default();
+        let buffer = buffer(&fake);
+        let read = buffer.map_read().unwrap();
+        std::thread::scope(|scope| {
+            let writer = scope.spawn(|| buffer.map().unwrap()[0] = 9);
+            std::thread::sleep(Duration::from_millis(10));
+            assert_eq!(read[0], 1);
+            drop(read);
+            writer.join().unwrap();
+        });
+        assert_eq!(buffer.map_read().unwrap()[0], 9);
+    }
+}
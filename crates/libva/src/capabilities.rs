@@ -0,0 +1,203 @@
+use std::sync::Arc;
+
+use crate::{
+    Config, ConfigAttributes, Context, ContextFlags, Display, Entrypoint, ErrorStatus, FilterCaps,
+    ImageFormat, PipelineCaps, ProcFilterType, Profile, SurfaceAttributes, VaResult,
+};
+
+/// Everything a display reports about what it can do.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Capabilities {
+    /// The driver's `vaQueryVendorString`, which is not the name of the VA
+    /// driver nor of the kernel one.
+    pub vendor: Option<String>,
+    pub configs: Vec<ConfigCapabilities>,
+    pub image_formats: Vec<ImageFormat>,
+    /// `None` if the display has no `VideoProc` entrypoint.
+    pub video_proc: Option<VideoProcCapabilities>,
+}
+
+/// One supported profile/entrypoint pair.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ConfigCapabilities {
+    /// `None` for entrypoints that take no profile, such as `VideoProc`.
+    pub profile: Option<Profile>,
+    pub entrypoint: Entrypoint,
+    /// `None` if the driver failed to report them.
+    pub attributes: Option<ConfigAttributes>,
+    /// `None` if the driver refused a config with default attributes.
+    pub surface_attributes: Option<SurfaceAttributes>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct VideoProcCapabilities {
+    pub filters: Vec<FilterCapabilities>,
+    pub pipeline: PipelineCaps,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FilterCapabilities {
+    pub filter: ProcFilterType,
+    pub caps: FilterCaps,
+}
+
+impl Display {
+    /// Walks every profile and entrypoint and collects what the driver
+    /// reports for each. A pair the driver fails to describe is still
+    /// listed, without the parts it could not report.
+    pub fn capabilities(self: &Arc<Self>) -> VaResult<Capabilities> {
+        let mut configs = Vec::new();
+        let profiles =
+            std::iter::once(None).chain(self.query_config_profiles()?.into_iter().map(Some));
+        for profile in profiles {
+            let entrypoints = match self.query_config_entrypoints(profile) {
+                Ok(entrypoints) => entrypoints,
+                // Drivers without video processing reject `VAProfileNone`.
+                Err(err)
+                    if profile.is_none()
+                        && err.status() == Some(ErrorStatus::UnsupportedProfile) =>
+                {
+                    continue
+                }
+                Err(err) => return Err(err),
+            };
+            for entrypoint in entrypoints {
+                let attributes = self.get_config_attributes(profile, entrypoint).ok();
+                let surface_attributes = Config::new(
+                    self.clone(),
+                    profile,
+                    entrypoint,
+                    &ConfigAttributes::default(),
+                )
+                .and_then(|config| config.query_surface_attributes())
+                .ok();
+                configs.push(ConfigCapabilities {
+                    profile,
+                    entrypoint,
+                    attributes,
+                    surface_attributes,
+                });
+            }
+        }
+        let video_proc = if configs
+            .iter()
+            .any(|caps| caps.entrypoint == Entrypoint::VideoProc)
+        {
+            Some(self.video_proc_capabilities()?)
+        } else {
+            None
+        };
+        Ok(Capabilities {
+            vendor: self.vendor_string(),
+            configs,
+            image_formats: self.query_image_formats()?,
+            video_proc,
+        })
+    }
+
+    fn video_proc_capabilities(self: &Arc<Self>) -> VaResult<VideoProcCapabilities> {
+        let config = Config::new(
+            self.clone(),
+            None,
+            Entrypoint::VideoProc,
+            &ConfigAttributes::default(),
+        )?;
+        let context = Context::new(config, 0, 0, ContextFlags::empty(), Vec::new())?;
+        let filters = context
+            .query_video_proc_filters()?
+            .into_iter()
+            .map(|filter| {
+                Ok(FilterCapabilities {
+                    filter,
+                    caps: context.query_video_proc_filter_caps(filter)?,
+                })
+            })
+            .collect::<VaResult<_>>()?;
+        Ok(VideoProcCapabilities {
+            filters,
+            pipeline: context.query_video_proc_pipeline_caps()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fake, DeinterlacingType, Fourcc};
+
+    #[test]
+    fn reports_fake_capabilities() {
+        let fake = fake::FakeBackend::default();
+        let display = fake::open_display(&fake.library()).unwrap();
+        let caps = display.capabilities().unwrap();
+        assert_eq!(caps.vendor.as_deref(), Some("vendec fake driver"));
+        assert_eq!(caps.configs.len(), fake.capabilities().configs.len());
+
+        let hevc10 = caps
+            .configs
+            .iter()
+            .find(|c| c.profile == Some(Profile::HEVCMain10))
+            .unwrap();
+        assert_eq!(hevc10.entrypoint, Entrypoint::VLD);
+        let surface = hevc10.surface_attributes.as_ref().unwrap();
+        assert_eq!(surface.max_width, Some(4096));
+        assert_eq!(surface.pixel_formats, vec![Fourcc::NV12, Fourcc::P010]);
+        assert!(caps.image_formats.iter().any(|f| f.fourcc == Fourcc::BGRA));
+
+        let vpp = caps.video_proc.unwrap();
+        let deinterlacing = vpp
+            .filters
+            .iter()
+            .find(|f| f.filter == ProcFilterType::Deinterlacing)
+            .unwrap();
+        assert_eq!(
+            deinterlacing.caps,
+            FilterCaps::Deinterlacing(vec![DeinterlacingType::Bob, DeinterlacingType::Weave])
+        );
+        assert!(vpp.pipeline.output_pixel_formats.contains(&Fourcc::RGBA));
+        assert_eq!(fake.live_objects().configs, 0);
+    }
+
+    #[test]
+    fn without_video_proc() {
+        let mut capabilities = fake::FakeCapabilities::default();
+        capabilities
+            .configs
+            .retain(|c| c.entrypoint != Entrypoint::VideoProc);
+        let fake = fake::FakeBackend::new(capabilities);
+        let display = fake::open_display(&fake.library()).unwrap();
+        let caps = display.capabilities().unwrap();
+        assert!(caps.video_proc.is_none());
+        assert!(caps.configs.iter().all(|c| c.profile.is_some()));
+    }
+
+    #[test]
+    fn failed_attribute_query() {
+        let fake = fake::FakeBackend::default();
+        let display = fake::open_display(&fake.library()).unwrap();
+        fake.inject_error("vaGetConfigAttributes", ErrorStatus::OperationFailed);
+        let caps = display.capabilities().unwrap();
+        assert_eq!(caps.configs.len(), fake.capabilities().configs.len());
+        assert_eq!(caps.configs[0].attributes, None);
+        assert!(caps.configs[1..].iter().all(|c| c.attributes.is_some()));
+        assert!(caps.configs[0].surface_attributes.is_some());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json_round_trip() {
+        let fake = fake::FakeBackend::default();
+        let display = fake::open_display(&fake.library()).unwrap();
+        let caps = display.capabilities().unwrap();
+        let json = serde_json::to_value(&caps).unwrap();
+        assert_eq!(json["configs"][0]["entrypoint"], "VideoProc");
+        assert_eq!(json["configs"][1]["profile"], "H264ConstrainedBaseline");
+        assert_eq!(json["image_formats"][0]["fourcc"], "NV12");
+        let parsed: Capabilities = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed, caps);
+    }
+}
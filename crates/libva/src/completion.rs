@@ -0,0 +1,325 @@
+//! Futures resolving when the GPU is done with a surface or coded buffer.
+//!
+//! One worker thread serves every wait. It goes round the pending targets,
+//! waiting on each in `vaSyncSurface2`/`vaSyncBuffer` for at most
+//! [`WAIT_SLICE`], so no executor thread blocks on the driver and a slow
+//! target holds up the others by one slice at most. Drivers predating the
+//! timed sync calls block the worker until the target is done; with the
+//! `tokio` feature those waits run on the blocking pool of the current
+//! runtime instead, if there is one.
+
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::task::{Poll, Waker};
+use std::time::Duration;
+
+use crate::{CodedBuffer, Error, ErrorKind, ErrorStatus, Surface, VaResult};
+
+/// How long the worker waits on one target before moving to the next.
+const WAIT_SLICE: Duration = Duration::from_millis(1);
+
+enum Target {
+    Surface(Arc<Surface>),
+    CodedBuffer(Arc<CodedBuffer>),
+}
+
+impl Target {
+    /// Waits for at most `timeout`. Returns `false` if the target is still
+    /// busy.
+    fn wait(&self, timeout: Duration) -> VaResult<bool> {
+        match self {
+            Target::Surface(surface) => surface.sync_timeout(timeout),
+            Target::CodedBuffer(buffer) => buffer.sync_timeout(timeout),
+        }
+    }
+
+    /// Blocks until the target is done, without a timeout.
+    fn wait_untimed(&self) -> VaResult<()> {
+        match self {
+            Target::Surface(surface) => surface.sync(),
+            Target::CodedBuffer(buffer) => buffer.map().map(drop),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Slot {
+    result: Option<VaResult<()>>,
+    waker: Option<Waker>,
+}
+
+impl Slot {
+    fn complete(&mut self, result: VaResult<()>) {
+        self.result = Some(result);
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+struct Job {
+    target: Target,
+    slot: Arc<Mutex<Slot>>,
+    /// The runtime the future was created in.
+    #[cfg(feature = "tokio")]
+    runtime: Option<tokio::runtime::Handle>,
+}
+
+impl Job {
+    /// Waits one slice for the target. Returns the job if it is still
+    /// pending.
+    fn step(self) -> Option<Self> {
+        // The future has been dropped.
+        if Arc::strong_count(&self.slot) == 1 {
+            return None;
+        }
+        let result = match self.target.wait(WAIT_SLICE) {
+            Ok(true) => Ok(()),
+            // Still busy: the other targets go first.
+            Ok(false) => return Some(self),
+            Err(err) if err.status() == Some(ErrorStatus::Unimplemented) => {
+                self.wait_untimed();
+                return None;
+            }
+            Err(err) => Err(err),
+        };
+        lock(&self.slot).complete(result);
+        None
+    }
+
+    fn wait_untimed(self) {
+        #[cfg(feature = "tokio")]
+        if let Some(runtime) = self.runtime.clone() {
+            runtime.spawn_blocking(move || {
+                let result = self.target.wait_untimed();
+                lock(&self.slot).complete(result);
+            });
+            return;
+        }
+        let result = self.target.wait_untimed();
+        lock(&self.slot).complete(result);
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// The jobs of the worker thread.
+struct Queue {
+    jobs: Mutex<VecDeque<Job>>,
+    added: Condvar,
+}
+
+static QUEUE: Queue = Queue {
+    jobs: Mutex::new(VecDeque::new()),
+    added: Condvar::new(),
+};
+
+/// Whether the worker thread is running.
+static STARTED: Mutex<bool> = Mutex::new(false);
+
+fn run_worker() {
+    loop {
+        let job = {
+            let mut jobs = QUEUE
+                .added
+                .wait_while(lock(&QUEUE.jobs), |jobs| jobs.is_empty())
+                .unwrap_or_else(|e| e.into_inner());
+            jobs.pop_front().expect("the queue is not empty")
+        };
+        if let Some(job) = job.step() {
+            lock(&QUEUE.jobs).push_back(job);
+        }
+    }
+}
+
+/// Hands `job` to the worker thread, starting it if needed.
+fn submit(job: Job) -> VaResult<()> {
+    let mut started = lock(&STARTED);
+    if !*started {
+        std::thread::Builder::new()
+            .name("va-completion".to_string())
+            .spawn(run_worker)
+            .map_err(|err| {
+                Error::with_kind("Ready", ErrorKind::Thread).with_message(err.to_string())
+            })?;
+        *started = true;
+    }
+    lock(&QUEUE.jobs).push_back(job);
+    QUEUE.added.notify_one();
+    Ok(())
+}
+
+/// Resolves once the GPU has finished with a surface or coded buffer.
+///
+/// The wait starts when the future is created, not when it is first polled.
+/// Dropping the future abandons the wait. The future resolves to an error if
+/// the worker thread cannot be started.
+#[must_use = "futures do nothing unless awaited"]
+pub struct Ready {
+    slot: Arc<Mutex<Slot>>,
+}
+
+impl Ready {
+    fn new(target: Target) -> Self {
+        let slot = Arc::new(Mutex::new(Slot::default()));
+        let job = Job {
+            target,
+            slot: slot.clone(),
+            #[cfg(feature = "tokio")]
+            runtime: tokio::runtime::Handle::try_current().ok(),
+        };
+        if let Err(err) = submit(job) {
+            lock(&slot).complete(Err(err));
+        }
+        Self { slot }
+    }
+}
+
+impl Future for Ready {
+    type Output = VaResult<()>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> Poll<Self::Output> {
+        let mut slot = lock(&self.slot);
+        match slot.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                slot.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl Surface {
+    /// Waits asynchronously for all pending operations on the surface.
+    pub fn ready(self: &Arc<Self>) -> Ready {
+        Ready::new(Target::Surface(self.clone()))
+    }
+}
+
+impl CodedBuffer {
+    /// Waits asynchronously for the encode writing this buffer.
+    pub fn ready(self: &Arc<Self>) -> Ready {
+        Ready::new(Target::CodedBuffer(self.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fake, Config, ConfigAttributes, Context, ContextFlags, Entrypoint, Profile, RtFormat,
+        UsageHint,
+    };
+    use std::task::Wake;
+
+    struct ThreadWaker(std::thread::Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = std::pin::pin!(future);
+        let waker = Waker::from(Arc::new(ThreadWaker(std::thread::current())));
+        let mut cx = std::task::Context::from_waker(&waker);
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+            std::thread::park();
+        }
+    }
+
+    fn surface(fake: &fake::FakeBackend) -> Arc<Surface> {
+        let display = fake::open_display(&fake.library()).unwrap();
+        Surface::new(display, RtFormat::YUV420, 64, 64, None, UsageHint::DECODER).unwrap()
+    }
+
+    #[test]
+    fn surface_ready() {
+        let fake = fake::FakeBackend::default();
+        let surface = surface(&fake);
+        for _ in 0..3 {
+            fake.inject_error("vaSyncSurface2", ErrorStatus::TimedOut);
+        }
+        block_on(surface.ready()).unwrap();
+
+        fake.inject_error("vaSyncSurface2", ErrorStatus::DecodingError);
+        let err = block_on(surface.ready()).unwrap_err();
+        assert_eq!(err.status(), Some(ErrorStatus::DecodingError));
+
+        fake.inject_error("vaSyncSurface2", ErrorStatus::Unimplemented);
+        fake.inject_error("vaSyncSurface", ErrorStatus::HwBusy);
+        let err = block_on(surface.ready()).unwrap_err();
+        assert_eq!(err.operation(), "vaSyncSurface");
+    }
+
+    #[test]
+    fn slow_surface_does_not_delay_others() {
+        let fake = fake::FakeBackend::default();
+        let (slow, fast) = (surface(&fake), surface(&fake));
+        fake.set_surface_busy(slow.handle(), true);
+        let slow_ready = slow.ready();
+        block_on(fast.ready()).unwrap();
+        assert!(lock(&slow_ready.slot).result.is_none());
+
+        fake.set_surface_busy(slow.handle(), false);
+        block_on(slow_ready).unwrap();
+    }
+
+    #[test]
+    fn dropping_the_future_releases_the_target() {
+        let fake = fake::FakeBackend::default();
+        let surface = surface(&fake);
+        fake.set_surface_busy(surface.handle(), true);
+        drop(surface.ready());
+        let weak = Arc::downgrade(&surface);
+        drop(surface);
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while weak.strong_count() > 0 {
+            assert!(std::time::Instant::now() < deadline);
+            std::thread::sleep(WAIT_SLICE);
+        }
+    }
+
+    #[test]
+    fn coded_buffer_ready() {
+        let fake = fake::FakeBackend::default();
+        let surface = surface(&fake);
+        let config = Config::new(
+            surface.display().clone(),
+            Some(Profile::H264Main),
+            Entrypoint::VLD,
+            &ConfigAttributes::default(),
+        )
+        .unwrap();
+        let context = Context::new(config, 64, 64, ContextFlags::PROGRESSIVE, vec![]).unwrap();
+        let coded = CodedBuffer::new(context, 1024).unwrap();
+        fake.inject_error("vaSyncBuffer", ErrorStatus::TimedOut);
+        let (a, b) = (coded.ready(), surface.ready());
+        block_on(b).unwrap();
+        block_on(a).unwrap();
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn tokio_runtime() {
+        let fake = fake::FakeBackend::default();
+        let surface = surface(&fake);
+        fake.inject_error("vaSyncSurface2", ErrorStatus::TimedOut);
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        runtime.block_on(surface.ready()).unwrap();
+
+        fake.inject_error("vaSyncSurface2", ErrorStatus::Unimplemented);
+        runtime.block_on(surface.ready()).unwrap();
+    }
+}
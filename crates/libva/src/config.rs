@@ -2,13 +2,16 @@ use paste::paste;
 use std::sync::Arc;
 
 use crate::{
-    sys, va_bitflags, ConfigAttribValue, Display, Entrypoint, Library, Profile, RtFormat,
-    SurfaceAttributes, VaResult, VaStatusExt,
+    sys, va_bitflags, ConfigAttribValue, ContextPriority, Display, Entrypoint, Library, ObjectId,
+    Profile, RtFormat, SurfaceAttributes, VaResult, VaStatusExt,
 };
 
 pub struct Config {
     handle: sys::VAConfigID,
     display: Arc<Display>,
+    profile: Option<Profile>,
+    entrypoint: Entrypoint,
+    attributes: ConfigAttributes,
 }
 
 impl Config {
@@ -34,9 +37,15 @@ impl Config {
                     raw_attrib_list.len() as _,
                     &mut handle,
                 )
-                .va_result()?;
+                .va_check(display.library(), "vaCreateConfig", &[])?;
         };
-        Ok(Arc::new(Self { handle, display }))
+        Ok(Arc::new(Self {
+            handle,
+            display,
+            profile,
+            entrypoint,
+            attributes: *attributes,
+        }))
     }
 
     pub fn handle(&self) -> sys::VAConfigID {
@@ -51,6 +60,19 @@ impl Config {
         self.display.library()
     }
 
+    pub fn profile(&self) -> Option<Profile> {
+        self.profile
+    }
+
+    pub fn entrypoint(&self) -> Entrypoint {
+        self.entrypoint
+    }
+
+    /// The attributes requested when the config was created.
+    pub fn attributes(&self) -> &ConfigAttributes {
+        &self.attributes
+    }
+
     pub fn query_surface_attributes(&self) -> VaResult<SurfaceAttributes> {
         let mut num_attribs = 0;
         unsafe {
@@ -62,7 +84,11 @@ impl Config {
                     std::ptr::null_mut(),
                     &mut num_attribs,
                 )
-                .va_result()?
+                .va_check(
+                    self.library(),
+                    "vaQuerySurfaceAttributes",
+                    &[ObjectId::Config(self.handle())],
+                )?
         };
         let mut attrib_list = Vec::with_capacity(num_attribs as usize);
         for _ in 0..num_attribs {
@@ -84,7 +110,11 @@ impl Config {
                     attrib_list.as_mut_ptr(),
                     &mut num_attribs,
                 )
-                .va_result()?;
+                .va_check(
+                    self.library(),
+                    "vaQuerySurfaceAttributes",
+                    &[ObjectId::Config(self.handle())],
+                )?;
         }
         Ok(unsafe { SurfaceAttributes::from_raw_attrib_list(&attrib_list) })
     }
@@ -103,6 +133,7 @@ impl Drop for Config {
 macro_rules! va_config_attrib {
     {$name:ident ; $($type_name:ident : $attrib_name:ident : $attrib_type:ty ,)*} => {
         #[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+        #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
         pub struct $name {
             $(
                 pub $attrib_name: Option<$attrib_type>,
@@ -140,10 +171,10 @@ macro_rules! va_config_attrib {
                     for attr in attrs {
                         match attr.type_ {
                             $(
-                                sys::[<VAConfigAttrib $type_name>] => {
-                                    if attr.value != sys::VA_ATTRIB_NOT_SUPPORTED {
-                                        result.$attrib_name = <$attrib_type as ConfigAttribValue>::from_raw(attr.value);
-                                    }
+                                sys::[<VAConfigAttrib $type_name>]
+                                    if attr.value != sys::VA_ATTRIB_NOT_SUPPORTED =>
+                                {
+                                    result.$attrib_name = <$attrib_type as ConfigAttribValue>::from_raw(attr.value);
                                 }
                             )*
                             _ => {}
@@ -237,4 +268,5 @@ va_config_attrib! {
     EncQuantization: enc_quantization: EncQuantization,
     EncIntraRefresh: enc_intra_refresh: EncIntraRefresh,
     EncSkipFrame: enc_skip_frame: bool,
+    ContextPriority: context_priority: ContextPriority,
 }
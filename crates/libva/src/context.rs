@@ -1,13 +1,73 @@
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, MutexGuard};
 
 use bitflags::bitflags;
 
-use crate::{sys, Config, Display, Surface, VaResult, VaStatusExt};
+use crate::{
+    sys, Buffer, BufferType, Config, ConfigAttribValue, ConfigAttributes, Display, ObjectId,
+    Surface, VaResult, VaStatusExt,
+};
 
+/// A VA context.
+///
+/// libva requires the picture calls on a context to be serialized, so only
+/// one [`Picture`] can be in flight at a time; [`Context::begin_picture`]
+/// blocks while another thread has one open.
 pub struct Context {
     handle: sys::VAContextID,
     config: Arc<Config>,
     _targets: Vec<Arc<Surface>>,
+    picture: Mutex<()>,
+    /// Priority to send to the driver with the next picture.
+    pending_priority: Mutex<Option<ContextPriority>>,
+}
+
+/// Scheduling priority of a context on the GPU. Work on higher levels
+/// preempts work on lower ones; the highest level a config supports is
+/// reported in [`ConfigAttributes::context_priority`](crate::ConfigAttributes).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(transparent)
+)]
+pub struct ContextPriority(pub u32);
+
+impl ContextPriority {
+    /// The lowest level, for batch work.
+    pub const BEST_EFFORT: Self = Self(0);
+}
+
+impl ConfigAttribValue for ContextPriority {
+    fn from_raw(raw: u32) -> Option<Self> {
+        let value = sys::VAConfigAttribValContextPriority { value: raw };
+        Some(Self(unsafe { value.bits }.priority()))
+    }
+
+    fn to_raw(self) -> u32 {
+        let mut value = sys::VAConfigAttribValContextPriority { value: 0 };
+        unsafe { value.bits.set_priority(self.0) };
+        unsafe { value.value }
+    }
+}
+
+/// A picture being submitted to a [`Context`], ended by [`Picture::end`]
+/// or on drop.
+pub struct Picture<'a> {
+    context: &'a Context,
+    target: Arc<Surface>,
+    buffers: Vec<Arc<Buffer>>,
+    /// The priority update sent with the picture, destroyed once it ends.
+    priority_update: Option<PriorityUpdate>,
+    ended: bool,
+    _guard: MutexGuard<'a, ()>,
+}
+
+/// A `VAContextParameterUpdateBuffer`, rendered along with the first
+/// buffers of a picture.
+struct PriorityUpdate {
+    priority: ContextPriority,
+    buffer: sys::VABufferID,
+    rendered: bool,
 }
 
 impl Context {
@@ -35,15 +95,62 @@ impl Context {
                     render_target_ids.len() as _,
                     &mut handle,
                 )
-                .va_result()?;
+                .va_check(
+                    display.library(),
+                    "vaCreateContext",
+                    &[ObjectId::Config(config.handle())],
+                )?;
         };
         Ok(Arc::new(Self {
             handle,
             config,
             _targets: render_targets,
+            picture: Mutex::new(()),
+            pending_priority: Mutex::new(None),
         }))
     }
 
+    /// Like [`Context::new`], but schedules the context at `priority`. The
+    /// context is created on a config requesting `priority`, replacing
+    /// `config` if it requests another level, and the first picture sends
+    /// `priority` again as [`Context::set_priority`] does.
+    pub fn with_priority(
+        config: Arc<Config>,
+        picture_width: u32,
+        picture_height: u32,
+        flags: ContextFlags,
+        render_targets: Vec<Arc<Surface>>,
+        priority: ContextPriority,
+    ) -> VaResult<Arc<Self>> {
+        let config = if config.attributes().context_priority == Some(priority) {
+            config
+        } else {
+            let attributes = ConfigAttributes {
+                context_priority: Some(priority),
+                ..*config.attributes()
+            };
+            Config::new(
+                config.display().clone(),
+                config.profile(),
+                config.entrypoint(),
+                &attributes,
+            )?
+        };
+        let context = Self::new(config, picture_width, picture_height, flags, render_targets)?;
+        context.set_priority(priority);
+        Ok(context)
+    }
+
+    /// Changes the scheduling priority. The driver is told with the first
+    /// buffers rendered into the next picture, as a
+    /// `VAContextParameterUpdateBuffer`.
+    pub fn set_priority(&self, priority: ContextPriority) {
+        *self
+            .pending_priority
+            .lock()
+            .unwrap_or_else(|e| e.into_inner()) = Some(priority);
+    }
+
     pub fn handle(&self) -> sys::VAContextID {
         self.handle
     }
@@ -55,6 +162,206 @@ impl Context {
     pub fn display(&self) -> &Arc<Display> {
         self.config.display()
     }
+
+    /// Starts a picture rendering into `target`, waiting for any picture
+    /// another thread has in flight on this context to end.
+    pub fn begin_picture(&self, target: &Arc<Surface>) -> VaResult<Picture<'_>> {
+        let guard = self.picture.lock().unwrap_or_else(|e| e.into_inner());
+        let priority = self
+            .pending_priority
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take();
+        // Created before the picture begins, so that a failure leaves no
+        // picture to end.
+        let update = match priority.map(|p| self.priority_update_buffer(p)).transpose() {
+            Ok(update) => update,
+            Err(err) => {
+                self.restore_priority(priority);
+                return Err(err);
+            }
+        };
+        let begun = unsafe {
+            self.display()
+                .library()
+                .lib()
+                .vaBeginPicture(self.display().handle(), self.handle(), target.handle())
+                .va_check(
+                    self.display().library(),
+                    "vaBeginPicture",
+                    &[
+                        ObjectId::Context(self.handle()),
+                        ObjectId::Surface(target.handle()),
+                    ],
+                )
+        };
+        if let Err(err) = begun {
+            if let Some(update) = update {
+                self.destroy_buffer(update);
+            }
+            self.restore_priority(priority);
+            return Err(err);
+        }
+        Ok(Picture {
+            context: self,
+            target: target.clone(),
+            buffers: Vec::new(),
+            priority_update: priority
+                .zip(update)
+                .map(|(priority, buffer)| PriorityUpdate {
+                    priority,
+                    buffer,
+                    rendered: false,
+                }),
+            ended: false,
+            _guard: guard,
+        })
+    }
+
+    /// Sends a priority that did not reach the driver with the next
+    /// picture, unless a newer one was set.
+    fn restore_priority(&self, priority: Option<ContextPriority>) {
+        if let Some(priority) = priority {
+            self.pending_priority
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .get_or_insert(priority);
+        }
+    }
+
+    /// Creates a `VAContextParameterUpdateBuffer` setting `priority`. The
+    /// buffer is not owned through the context, which would keep it alive
+    /// forever, and is destroyed by the picture it is rendered with.
+    fn priority_update_buffer(&self, priority: ContextPriority) -> VaResult<sys::VABufferID> {
+        let mut update = sys::VAContextParameterUpdateBuffer::default();
+        unsafe {
+            update.flags.bits.set_context_priority_update(1);
+            update.context_priority.value = priority.to_raw();
+        }
+        let mut handle = 0;
+        unsafe {
+            self.display()
+                .library()
+                .lib()
+                .vaCreateBuffer(
+                    self.display().handle(),
+                    self.handle(),
+                    BufferType::ContextParameterUpdate.into(),
+                    std::mem::size_of_val(&update) as _,
+                    1,
+                    (&mut update as *mut sys::VAContextParameterUpdateBuffer).cast(),
+                    &mut handle,
+                )
+                .va_check(
+                    self.display().library(),
+                    "vaCreateBuffer",
+                    &[ObjectId::Context(self.handle())],
+                )?;
+        }
+        Ok(handle)
+    }
+
+    fn destroy_buffer(&self, buffer: sys::VABufferID) {
+        unsafe {
+            self.display()
+                .library()
+                .lib()
+                .vaDestroyBuffer(self.display().handle(), buffer)
+                .va_result()
+                .ok();
+        }
+    }
+}
+
+impl Picture<'_> {
+    pub fn context(&self) -> &Context {
+        self.context
+    }
+
+    pub fn target(&self) -> &Arc<Surface> {
+        &self.target
+    }
+
+    /// Submits `buffers`, which are kept alive until the picture ends. A
+    /// pending priority update goes in the same call.
+    pub fn render(&mut self, buffers: &[Arc<Buffer>]) -> VaResult<()> {
+        let update = self
+            .priority_update
+            .as_ref()
+            .filter(|update| !update.rendered);
+        let mut ids: Vec<_> = update
+            .iter()
+            .map(|update| update.buffer)
+            .chain(buffers.iter().map(|b| b.handle()))
+            .collect();
+        self.render_ids(&mut ids)?;
+        if let Some(update) = &mut self.priority_update {
+            update.rendered = true;
+        }
+        self.buffers.extend(buffers.iter().cloned());
+        Ok(())
+    }
+
+    fn render_ids(&mut self, ids: &mut [sys::VABufferID]) -> VaResult<()> {
+        let context = self.context;
+        unsafe {
+            context
+                .display()
+                .library()
+                .lib()
+                .vaRenderPicture(
+                    context.display().handle(),
+                    context.handle(),
+                    ids.as_mut_ptr(),
+                    ids.len() as _,
+                )
+                .va_check(
+                    context.display().library(),
+                    "vaRenderPicture",
+                    &[ObjectId::Context(context.handle())],
+                )
+        }
+    }
+
+    /// Ends the picture, handing it to the driver for processing.
+    pub fn end(mut self) -> VaResult<()> {
+        self.end_picture()
+    }
+
+    fn end_picture(&mut self) -> VaResult<()> {
+        self.ended = true;
+        let context = self.context;
+        let result = unsafe {
+            context
+                .display()
+                .library()
+                .lib()
+                .vaEndPicture(context.display().handle(), context.handle())
+                .va_check(
+                    context.display().library(),
+                    "vaEndPicture",
+                    &[
+                        ObjectId::Context(context.handle()),
+                        ObjectId::Surface(self.target.handle()),
+                    ],
+                )
+        };
+        if let Some(update) = self.priority_update.take() {
+            context.destroy_buffer(update.buffer);
+            if !update.rendered {
+                context.restore_priority(Some(update.priority));
+            }
+        }
+        result
+    }
+}
+
+impl Drop for Picture<'_> {
+    fn drop(&mut self) {
+        if !self.ended {
+            self.end_picture().ok();
+        }
+    }
 }
 
 impl Drop for Context {
@@ -75,3 +382,191 @@ bitflags! {
         const PROGRESSIVE  = sys::VA_PROGRESSIVE;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fake, BufferType, Entrypoint, ErrorStatus, Profile, RtFormat, UsageHint};
+
+    fn setup(fake: &fake::FakeBackend) -> (Arc<Context>, Vec<Arc<Surface>>) {
+        let display = fake::open_display(&fake.library()).unwrap();
+        let config = Config::new(
+            display.clone(),
+            Some(Profile::H264Main),
+            Entrypoint::VLD,
+            &ConfigAttributes::default(),
+        )
+        .unwrap();
+        let surfaces = Surface::new_many(
+            display,
+            RtFormat::YUV420,
+            64,
+            64,
+            None,
+            UsageHint::DECODER,
+            4,
+        )
+        .unwrap();
+        let context =
+            Context::new(config, 64, 64, ContextFlags::PROGRESSIVE, surfaces.clone()).unwrap();
+        (context, surfaces)
+    }
+
+    /// Submits a picture with one buffer of slice data.
+    fn decode(context: &Arc<Context>, surface: &Arc<Surface>) -> VaResult<()> {
+        let buffer = Buffer::new_with_data(context.clone(), BufferType::SliceData, &[0])?;
+        let mut picture = context.begin_picture(surface)?;
+        picture.render(&[buffer])?;
+        picture.end()
+    }
+
+    #[test]
+    fn pictures_are_serialized() {
+        let fake = fake::FakeBackend::default();
+        let (context, surfaces) = setup(&fake);
+        std::thread::scope(|scope| {
+            for (i, surface) in surfaces.iter().enumerate() {
+                let context = &context;
+                scope.spawn(move || {
+                    for _ in 0..10 {
+                        let buffer = Buffer::new_with_data(
+                            context.clone(),
+                            BufferType::SliceData,
+                            &[i as u8],
+                        )
+                        .unwrap();
+                        let mut picture = context.begin_picture(surface).unwrap();
+                        picture.render(&[buffer]).unwrap();
+                        std::thread::yield_now();
+                        picture.end().unwrap();
+                    }
+                });
+            }
+        });
+        let pictures = fake.pictures();
+        assert_eq!(pictures.len(), 40);
+        for picture in pictures {
+            let index = surfaces
+                .iter()
+                .position(|s| s.handle() == picture.target)
+                .unwrap();
+            assert_eq!(picture.buffers[0].data, [index as u8]);
+        }
+    }
+
+    #[test]
+    fn priority_sent_with_next_picture() {
+        let fake = fake::FakeBackend::default();
+        let (context, surfaces) = setup(&fake);
+        let range = context
+            .display()
+            .query_context_priority_range(Some(Profile::H264Main), Entrypoint::VLD)
+            .unwrap()
+            .unwrap();
+        assert_eq!(range, ContextPriority::BEST_EFFORT..=ContextPriority(1024));
+
+        context.set_priority(*range.end());
+        // A picture without buffers leaves the update to the next one.
+        context.begin_picture(&surfaces[0]).unwrap().end().unwrap();
+        decode(&context, &surfaces[1]).unwrap();
+        decode(&context, &surfaces[2]).unwrap();
+        let pictures = fake.pictures();
+        assert!(pictures[0].buffers.is_empty());
+        let types: Vec<_> = pictures[1].buffers.iter().map(|b| b.buffer_type).collect();
+        assert_eq!(
+            types,
+            [
+                Some(BufferType::ContextParameterUpdate),
+                Some(BufferType::SliceData)
+            ]
+        );
+        assert_eq!(pictures[2].buffers.len(), 1);
+        assert_eq!(
+            fake.context_priority(context.handle()),
+            Some(ContextPriority(1024))
+        );
+
+        // Rejected levels are retried with the next picture.
+        context.set_priority(ContextPriority(2048));
+        let err = decode(&context, &surfaces[2]).unwrap_err();
+        assert_eq!(err.status(), Some(ErrorStatus::InvalidParameter));
+        assert!(decode(&context, &surfaces[3]).is_err());
+        context.set_priority(ContextPriority::BEST_EFFORT);
+        decode(&context, &surfaces[3]).unwrap();
+        assert_eq!(
+            fake.context_priority(context.handle()),
+            Some(ContextPriority::BEST_EFFORT)
+        );
+
+        // The update buffers are not leaked.
+        drop(context);
+        drop(surfaces);
+        assert_eq!(fake.live_objects().buffers, 0);
+        assert!(fake.violations().is_empty());
+    }
+
+    #[test]
+    fn failed_priority_update_begins_no_picture() {
+        let fake = fake::FakeBackend::default();
+        let (context, surfaces) = setup(&fake);
+        context.set_priority(ContextPriority(1));
+        fake.inject_error("vaCreateBuffer", ErrorStatus::AllocationFailed);
+        let err = context.begin_picture(&surfaces[0]).err().unwrap();
+        assert_eq!(err.status(), Some(ErrorStatus::AllocationFailed));
+        assert!(fake.pictures().is_empty());
+
+        decode(&context, &surfaces[0]).unwrap();
+        let pictures = fake.pictures();
+        assert_eq!(pictures.len(), 1);
+        assert_eq!(
+            pictures[0].buffers[0].buffer_type,
+            Some(BufferType::ContextParameterUpdate)
+        );
+        assert_eq!(fake.live_objects().buffers, 0);
+        assert!(fake.violations().is_empty());
+    }
+
+    #[test]
+    fn with_priority_requests_the_level_on_creation() {
+        let fake = fake::FakeBackend::default();
+        let (context, surfaces) = setup(&fake);
+        let priority = ContextPriority(512);
+        let context = Context::with_priority(
+            context.config().clone(),
+            64,
+            64,
+            ContextFlags::PROGRESSIVE,
+            surfaces,
+            priority,
+        )
+        .unwrap();
+        assert_eq!(
+            context.config().attributes().context_priority,
+            Some(priority)
+        );
+        assert_eq!(fake.context_priority(context.handle()), Some(priority));
+
+        let config = context.config().clone();
+        let err = Context::with_priority(
+            config,
+            64,
+            64,
+            ContextFlags::PROGRESSIVE,
+            vec![],
+            ContextPriority(2048),
+        )
+        .err()
+        .unwrap();
+        assert_eq!(err.operation(), "vaCreateConfig");
+    }
+
+    #[test]
+    fn dropped_picture_ends() {
+        let fake = fake::FakeBackend::default();
+        let (context, surfaces) = setup(&fake);
+        drop(context.begin_picture(&surfaces[0]).unwrap());
+        assert_eq!(fake.pictures().len(), 1);
+        context.begin_picture(&surfaces[1]).unwrap().end().unwrap();
+        assert_eq!(fake.pictures().len(), 2);
+    }
+}
@@ -0,0 +1,871 @@
+//! CPU color conversion between packed RGB and YUV surface formats.
+//!
+//! This is the fallback path for drivers without a `VideoProc` entrypoint.
+//! Conversions work directly on [`Plane`] views, so they can be applied to a
+//! mapped [`Image`](crate::Image) as well as to plain memory.
+
+use crate::{Fourcc, ImageMap, ImageMapRead, Plane, PlaneMut};
+
+/// YCbCr matrix coefficients.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Matrix {
+    Bt601,
+    #[default]
+    Bt709,
+    Bt2020,
+}
+
+impl Matrix {
+    /// Returns `(Kr, Kb)`.
+    fn coefficients(self) -> (f32, f32) {
+        match self {
+            Matrix::Bt601 => (0.299, 0.114),
+            Matrix::Bt709 => (0.2126, 0.0722),
+            Matrix::Bt2020 => (0.2627, 0.0593),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Range {
+    /// Studio swing, e.g. 16..=235 for 8-bit luma.
+    #[default]
+    Limited,
+    Full,
+}
+
+/// Position of chroma samples relative to luma samples.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum ChromaSiting {
+    /// Co-sited with the left luma sample horizontally, centered vertically
+    /// (MPEG-2, H.264 and HEVC default).
+    #[default]
+    Left,
+    /// Centered between luma samples in both directions (JPEG, MPEG-1).
+    Center,
+    /// Co-sited with the top-left luma sample (BT.2020 4:2:0).
+    TopLeft,
+}
+
+impl ChromaSiting {
+    fn horizontally_cosited(self) -> bool {
+        matches!(self, ChromaSiting::Left | ChromaSiting::TopLeft)
+    }
+
+    fn vertically_cosited(self) -> bool {
+        matches!(self, ChromaSiting::TopLeft)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct ColorOptions {
+    pub matrix: Matrix,
+    pub range: Range,
+    pub chroma_siting: ChromaSiting,
+}
+
+/// Packed 8-bit RGB layouts, named by memory byte order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RgbFormat {
+    Rgba,
+    Bgra,
+    Rgbx,
+    Bgrx,
+    Rgb24,
+}
+
+impl RgbFormat {
+    pub fn from_fourcc(fourcc: Fourcc) -> Option<Self> {
+        Some(match fourcc {
+            Fourcc::RGBA => RgbFormat::Rgba,
+            Fourcc::BGRA => RgbFormat::Bgra,
+            Fourcc::RGBX => RgbFormat::Rgbx,
+            Fourcc::BGRX => RgbFormat::Bgrx,
+            _ => return None,
+        })
+    }
+
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            RgbFormat::Rgb24 => 3,
+            _ => 4,
+        }
+    }
+
+    /// Returns the byte offsets of red, green, blue and alpha, if present.
+    fn offsets(self) -> (usize, usize, usize, Option<usize>) {
+        match self {
+            RgbFormat::Rgba => (0, 1, 2, Some(3)),
+            RgbFormat::Bgra => (2, 1, 0, Some(3)),
+            RgbFormat::Rgbx => (0, 1, 2, None),
+            RgbFormat::Bgrx => (2, 1, 0, None),
+            RgbFormat::Rgb24 => (0, 1, 2, None),
+        }
+    }
+
+    fn read(self, pixel: &[u8]) -> [u8; 4] {
+        let (r, g, b, a) = self.offsets();
+        [pixel[r], pixel[g], pixel[b], a.map_or(255, |a| pixel[a])]
+    }
+
+    fn write(self, pixel: &mut [u8], rgba: [u8; 4]) {
+        let (r, g, b, a) = self.offsets();
+        pixel[r] = rgba[0];
+        pixel[g] = rgba[1];
+        pixel[b] = rgba[2];
+        match a {
+            Some(a) => pixel[a] = rgba[3],
+            None if self.bytes_per_pixel() == 4 => pixel[3] = 255,
+            None => {}
+        }
+    }
+}
+
+/// YUV layouts supported by the converter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum YuvFormat {
+    /// 8-bit 4:2:0, Y plane followed by an interleaved UV plane.
+    Nv12,
+    /// 8-bit 4:2:0, separate Y, U and V planes.
+    I420,
+    /// 10-bit 4:2:0 laid out like NV12 with MSB-aligned 16-bit samples.
+    P010,
+    /// 8-bit 4:2:2 packed as `Y0 U Y1 V`. The plane covers whole pairs, so
+    /// an odd width takes one pixel of padding.
+    Yuy2,
+}
+
+impl YuvFormat {
+    pub fn from_fourcc(fourcc: Fourcc) -> Option<Self> {
+        Some(match fourcc {
+            Fourcc::NV12 => YuvFormat::Nv12,
+            Fourcc::I420 | Fourcc::IYUV => YuvFormat::I420,
+            Fourcc::P010 => YuvFormat::P010,
+            Fourcc::YUY2 => YuvFormat::Yuy2,
+            _ => return None,
+        })
+    }
+
+    pub fn fourcc(self) -> Fourcc {
+        match self {
+            YuvFormat::Nv12 => Fourcc::NV12,
+            YuvFormat::I420 => Fourcc::I420,
+            YuvFormat::P010 => Fourcc::P010,
+            YuvFormat::Yuy2 => Fourcc::YUY2,
+        }
+    }
+
+    fn num_planes(self) -> usize {
+        match self {
+            YuvFormat::Nv12 | YuvFormat::P010 => 2,
+            YuvFormat::I420 => 3,
+            YuvFormat::Yuy2 => 1,
+        }
+    }
+
+    /// Returns the chroma subsampling divisors.
+    fn subsampling(self) -> (usize, usize) {
+        match self {
+            YuvFormat::Yuy2 => (2, 1),
+            _ => (2, 2),
+        }
+    }
+
+    fn bit_depth(self) -> u32 {
+        match self {
+            YuvFormat::P010 => 10,
+            _ => 8,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConvertError {
+    UnsupportedFormat(Fourcc),
+    /// The destination is smaller than the source, or planes are missing.
+    SizeMismatch,
+    /// The image planes could not be borrowed.
+    InvalidLayout,
+}
+
+impl std::fmt::Display for ConvertError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ConvertError::UnsupportedFormat(fourcc) => {
+                write!(f, "unsupported format for conversion: {}", fourcc)
+            }
+            ConvertError::SizeMismatch => write!(f, "source and destination sizes differ"),
+            ConvertError::InvalidLayout => write!(f, "invalid image plane layout"),
+        }
+    }
+}
+impl std::error::Error for ConvertError {}
+
+/// Full-resolution YCbCr with luma in `0..=1` and chroma in `-0.5..=0.5`.
+struct Ycc {
+    width: usize,
+    height: usize,
+    y: Vec<f32>,
+    cb: Vec<f32>,
+    cr: Vec<f32>,
+}
+
+struct Quantizer {
+    bits: u32,
+    range: Range,
+}
+
+impl Quantizer {
+    fn max(&self) -> f32 {
+        ((1u32 << self.bits) - 1) as f32
+    }
+
+    fn scale(&self) -> f32 {
+        (1u32 << (self.bits - 8)) as f32
+    }
+
+    fn luma(&self, v: f32) -> u16 {
+        let q = match self.range {
+            Range::Limited => (16.0 + 219.0 * v) * self.scale(),
+            Range::Full => v * self.max(),
+        };
+        q.round().clamp(0.0, self.max()) as u16
+    }
+
+    fn chroma(&self, v: f32) -> u16 {
+        let q = match self.range {
+            Range::Limited => (128.0 + 224.0 * v) * self.scale(),
+            Range::Full => v * self.max() + (1u32 << (self.bits - 1)) as f32,
+        };
+        q.round().clamp(0.0, self.max()) as u16
+    }
+
+    fn luma_inv(&self, q: u16) -> f32 {
+        match self.range {
+            Range::Limited => (q as f32 / self.scale() - 16.0) / 219.0,
+            Range::Full => q as f32 / self.max(),
+        }
+    }
+
+    fn chroma_inv(&self, q: u16) -> f32 {
+        match self.range {
+            Range::Limited => (q as f32 / self.scale() - 128.0) / 224.0,
+            Range::Full => (q as f32 - (1u32 << (self.bits - 1)) as f32) / self.max(),
+        }
+    }
+}
+
+/// Converts packed RGB in `src` into `dst`, whose planes are laid out as
+/// `dst_format`.
+///
+/// The converted region is the size of `src`; `dst` may be larger, as is
+/// common for driver images with aligned dimensions.
+pub fn rgb_to_yuv(
+    src: &Plane<'_>,
+    src_format: RgbFormat,
+    dst: &mut [PlaneMut<'_>],
+    dst_format: YuvFormat,
+    options: &ColorOptions,
+) -> Result<(), ConvertError> {
+    let (width, height) = (src.width() as usize, src.height() as usize);
+    check_yuv_planes(dst.iter().map(|p| p.as_plane()), dst_format, width, height)?;
+    if src.bytes_per_element() != src_format.bytes_per_pixel() {
+        return Err(ConvertError::SizeMismatch);
+    }
+    if width == 0 || height == 0 {
+        return Ok(());
+    }
+
+    let (kr, kb) = options.matrix.coefficients();
+    let kg = 1.0 - kr - kb;
+    let mut ycc = Ycc {
+        width,
+        height,
+        y: Vec::with_capacity(width * height),
+        cb: Vec::with_capacity(width * height),
+        cr: Vec::with_capacity(width * height),
+    };
+    for row in src.rows() {
+        for pixel in row.chunks_exact(src_format.bytes_per_pixel()) {
+            let [r, g, b, _] = src_format.read(pixel).map(|c| c as f32 / 255.0);
+            let y = kr * r + kg * g + kb * b;
+            ycc.y.push(y);
+            ycc.cb.push((b - y) / (2.0 * (1.0 - kb)));
+            ycc.cr.push((r - y) / (2.0 * (1.0 - kr)));
+        }
+    }
+
+    let (h_div, v_div) = dst_format.subsampling();
+    let (cw, ch) = (width.div_ceil(h_div), height.div_ceil(v_div));
+    let cb = downsample(&ycc.cb, &ycc, h_div, v_div, options.chroma_siting);
+    let cr = downsample(&ycc.cr, &ycc, h_div, v_div, options.chroma_siting);
+    let q = Quantizer {
+        bits: dst_format.bit_depth(),
+        range: options.range,
+    };
+
+    match dst_format {
+        YuvFormat::Nv12 | YuvFormat::P010 => {
+            let bytes = if dst_format == YuvFormat::P010 { 2 } else { 1 };
+            let shift = bytes as u32 * 8 - q.bits;
+            let (luma, chroma) = dst.split_at_mut(1);
+            for (y, row) in luma[0].rows_mut().take(height).enumerate() {
+                for x in 0..width {
+                    store(row, x, bytes, q.luma(ycc.y[y * width + x]) << shift);
+                }
+            }
+            for (y, row) in chroma[0].rows_mut().take(ch).enumerate() {
+                for x in 0..cw {
+                    store(row, 2 * x, bytes, q.chroma(cb[y * cw + x]) << shift);
+                    store(row, 2 * x + 1, bytes, q.chroma(cr[y * cw + x]) << shift);
+                }
+            }
+        }
+        YuvFormat::I420 => {
+            for (row, values) in dst[0].rows_mut().zip(ycc.y.chunks_exact(width)) {
+                for (out, &v) in row.iter_mut().zip(values) {
+                    *out = q.luma(v) as u8;
+                }
+            }
+            for (plane, values) in [(1, &cb), (2, &cr)] {
+                for (row, values) in dst[plane].rows_mut().zip(values.chunks_exact(cw)) {
+                    for (out, &v) in row.iter_mut().zip(values) {
+                        *out = q.chroma(v) as u8;
+                    }
+                }
+            }
+        }
+        YuvFormat::Yuy2 => {
+            for (y, row) in dst[0].rows_mut().take(height).enumerate() {
+                let luma = &ycc.y[y * width..(y + 1) * width];
+                for x in 0..cw {
+                    // With an odd width the last pair has a single pixel,
+                    // which is repeated to fill it.
+                    let y1 = luma[(2 * x + 1).min(width - 1)];
+                    row[4 * x] = q.luma(luma[2 * x]) as u8;
+                    row[4 * x + 1] = q.chroma(cb[y * cw + x]) as u8;
+                    row[4 * x + 2] = q.luma(y1) as u8;
+                    row[4 * x + 3] = q.chroma(cr[y * cw + x]) as u8;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Converts `src`, whose planes are laid out as `src_format`, into packed RGB
+/// in `dst`.
+///
+/// The converted region is the size of `dst`; `src` may be larger.
+pub fn yuv_to_rgb(
+    src: &[Plane<'_>],
+    src_format: YuvFormat,
+    dst: &mut PlaneMut<'_>,
+    dst_format: RgbFormat,
+    options: &ColorOptions,
+) -> Result<(), ConvertError> {
+    let (width, height) = (dst.width() as usize, dst.height() as usize);
+    check_yuv_planes(src.iter().copied(), src_format, width, height)?;
+    if dst.bytes_per_element() != dst_format.bytes_per_pixel() {
+        return Err(ConvertError::SizeMismatch);
+    }
+    if width == 0 || height == 0 {
+        return Ok(());
+    }
+
+    let (h_div, v_div) = src_format.subsampling();
+    let (cw, ch) = (width.div_ceil(h_div), height.div_ceil(v_div));
+    let q = Quantizer {
+        bits: src_format.bit_depth(),
+        range: options.range,
+    };
+    let mut y_values = Vec::with_capacity(width * height);
+    let mut cb = Vec::with_capacity(cw * ch);
+    let mut cr = Vec::with_capacity(cw * ch);
+
+    match src_format {
+        YuvFormat::Nv12 | YuvFormat::P010 => {
+            let bytes = if src_format == YuvFormat::P010 { 2 } else { 1 };
+            let shift = bytes as u32 * 8 - q.bits;
+            for row in src[0].rows().take(height) {
+                for x in 0..width {
+                    y_values.push(q.luma_inv(load(row, x, bytes) >> shift));
+                }
+            }
+            for row in src[1].rows().take(ch) {
+                for x in 0..cw {
+                    cb.push(q.chroma_inv(load(row, 2 * x, bytes) >> shift));
+                    cr.push(q.chroma_inv(load(row, 2 * x + 1, bytes) >> shift));
+                }
+            }
+        }
+        YuvFormat::I420 => {
+            for row in src[0].rows().take(height) {
+                y_values.extend(row[..width].iter().map(|&v| q.luma_inv(v as u16)));
+            }
+            for row in src[1].rows().take(ch) {
+                cb.extend(row[..cw].iter().map(|&v| q.chroma_inv(v as u16)));
+            }
+            for row in src[2].rows().take(ch) {
+                cr.extend(row[..cw].iter().map(|&v| q.chroma_inv(v as u16)));
+            }
+        }
+        YuvFormat::Yuy2 => {
+            for row in src[0].rows().take(height) {
+                for x in 0..width {
+                    y_values.push(q.luma_inv(row[2 * x] as u16));
+                }
+                for x in 0..cw {
+                    cb.push(q.chroma_inv(row[4 * x + 1] as u16));
+                    cr.push(q.chroma_inv(row[4 * x + 3] as u16));
+                }
+            }
+        }
+    }
+
+    let cb = upsample(
+        &cb,
+        cw,
+        ch,
+        width,
+        height,
+        h_div,
+        v_div,
+        options.chroma_siting,
+    );
+    let cr = upsample(
+        &cr,
+        cw,
+        ch,
+        width,
+        height,
+        h_div,
+        v_div,
+        options.chroma_siting,
+    );
+    let (kr, kb) = options.matrix.coefficients();
+    let kg = 1.0 - kr - kb;
+    let to_u8 = |v: f32| (v * 255.0).round().clamp(0.0, 255.0) as u8;
+    for (y, row) in dst.rows_mut().enumerate() {
+        for (x, pixel) in row
+            .chunks_exact_mut(dst_format.bytes_per_pixel())
+            .enumerate()
+        {
+            let i = y * width + x;
+            let r = y_values[i] + 2.0 * (1.0 - kr) * cr[i];
+            let b = y_values[i] + 2.0 * (1.0 - kb) * cb[i];
+            let g = (y_values[i] - kr * r - kb * b) / kg;
+            dst_format.write(pixel, [to_u8(r), to_u8(g), to_u8(b), 255]);
+        }
+    }
+    Ok(())
+}
+
+/// Copies packed RGB between byte orders.
+pub fn rgb_to_rgb(
+    src: &Plane<'_>,
+    src_format: RgbFormat,
+    dst: &mut PlaneMut<'_>,
+    dst_format: RgbFormat,
+) -> Result<(), ConvertError> {
+    if dst.width() < src.width()
+        || dst.height() < src.height()
+        || src.bytes_per_element() != src_format.bytes_per_pixel()
+        || dst.bytes_per_element() != dst_format.bytes_per_pixel()
+    {
+        return Err(ConvertError::SizeMismatch);
+    }
+    for (src_row, dst_row) in src.rows().zip(dst.rows_mut()) {
+        for (s, d) in src_row
+            .chunks_exact(src_format.bytes_per_pixel())
+            .zip(dst_row.chunks_exact_mut(dst_format.bytes_per_pixel()))
+        {
+            dst_format.write(d, src_format.read(s));
+        }
+    }
+    Ok(())
+}
+
+fn check_yuv_planes<'a>(
+    planes: impl ExactSizeIterator<Item = Plane<'a>>,
+    format: YuvFormat,
+    width: usize,
+    height: usize,
+) -> Result<(), ConvertError> {
+    if planes.len() < format.num_planes() {
+        return Err(ConvertError::SizeMismatch);
+    }
+    let (h_div, v_div) = format.subsampling();
+    let sample_bytes = if format == YuvFormat::P010 { 2 } else { 1 };
+    for (i, plane) in planes.take(format.num_planes()).enumerate() {
+        let (w, h, bytes) = match (format, i) {
+            // Pixels are stored in pairs, so an odd width needs a padding
+            // pixel.
+            (YuvFormat::Yuy2, _) => {
+                let pairs = width.div_ceil(h_div);
+                (2 * pairs, height, 4 * pairs)
+            }
+            (_, 0) => (width, height, width * sample_bytes),
+            (YuvFormat::I420, _) => (
+                width.div_ceil(h_div),
+                height.div_ceil(v_div),
+                width.div_ceil(h_div),
+            ),
+            _ => (
+                width.div_ceil(h_div),
+                height.div_ceil(v_div),
+                2 * width.div_ceil(h_div) * sample_bytes,
+            ),
+        };
+        let layout = plane.layout();
+        if (layout.width as usize) < w || (layout.height as usize) < h || layout.row_bytes() < bytes
+        {
+            return Err(ConvertError::SizeMismatch);
+        }
+    }
+    Ok(())
+}
+
+fn store(row: &mut [u8], index: usize, bytes: usize, value: u16) {
+    if bytes == 1 {
+        row[index] = value as u8;
+    } else {
+        row[2 * index..2 * index + 2].copy_from_slice(&value.to_le_bytes());
+    }
+}
+
+fn load(row: &[u8], index: usize, bytes: usize) -> u16 {
+    if bytes == 1 {
+        row[index] as u16
+    } else {
+        u16::from_le_bytes([row[2 * index], row[2 * index + 1]])
+    }
+}
+
+/// Decimates a full-resolution chroma plane, filtering according to siting.
+fn downsample(
+    values: &[f32],
+    ycc: &Ycc,
+    h_div: usize,
+    v_div: usize,
+    siting: ChromaSiting,
+) -> Vec<f32> {
+    let (w, h) = (ycc.width, ycc.height);
+    let horizontal = decimate(w, h_div, siting.horizontally_cosited());
+    let cw = w.div_ceil(h_div);
+    let mut rows = Vec::with_capacity(cw * h);
+    for y in 0..h {
+        let row = &values[y * w..(y + 1) * w];
+        rows.extend(horizontal.iter().map(|taps| apply(taps, |x| row[x])));
+    }
+    let vertical = decimate(h, v_div, siting.vertically_cosited());
+    let mut result = Vec::with_capacity(cw * vertical.len());
+    for taps in &vertical {
+        for x in 0..cw {
+            result.push(apply(taps, |y| rows[y * cw + x]));
+        }
+    }
+    result
+}
+
+/// Interpolates a subsampled chroma plane to full resolution.
+#[allow(clippy::too_many_arguments)]
+fn upsample(
+    values: &[f32],
+    cw: usize,
+    ch: usize,
+    width: usize,
+    height: usize,
+    h_div: usize,
+    v_div: usize,
+    siting: ChromaSiting,
+) -> Vec<f32> {
+    let horizontal = interpolate(width, cw, h_div, siting.horizontally_cosited());
+    let mut rows = Vec::with_capacity(width * ch);
+    for y in 0..ch {
+        let row = &values[y * cw..(y + 1) * cw];
+        rows.extend(horizontal.iter().map(|taps| apply(taps, |x| row[x])));
+    }
+    let vertical = interpolate(height, ch, v_div, siting.vertically_cosited());
+    let mut result = Vec::with_capacity(width * height);
+    for taps in &vertical {
+        for x in 0..width {
+            result.push(apply(taps, |y| rows[y * width + x]));
+        }
+    }
+    result
+}
+
+type Taps = Vec<(usize, f32)>;
+
+fn apply(taps: &[(usize, f32)], sample: impl Fn(usize) -> f32) -> f32 {
+    taps.iter().map(|&(i, weight)| sample(i) * weight).sum()
+}
+
+/// Filter taps producing each output of a `div`:1 decimation of `len` inputs.
+fn decimate(len: usize, div: usize, cosited: bool) -> Vec<Taps> {
+    if len == 0 {
+        return Vec::new();
+    }
+    let last = len - 1;
+    (0..len.div_ceil(div))
+        .map(|i| {
+            let x = i * div;
+            match (div, cosited) {
+                (1, _) => vec![(x, 1.0)],
+                (2, true) => vec![
+                    (x.saturating_sub(1), 0.25),
+                    (x, 0.5),
+                    ((x + 1).min(last), 0.25),
+                ],
+                (2, false) => vec![(x, 0.5), ((x + 1).min(last), 0.5)],
+                _ => (0..div)
+                    .map(|k| ((x + k).min(last), 1.0 / div as f32))
+                    .collect(),
+            }
+        })
+        .collect()
+}
+
+/// Filter taps producing each of `len` outputs from `sub_len` subsampled
+/// inputs.
+fn interpolate(len: usize, sub_len: usize, div: usize, cosited: bool) -> Vec<Taps> {
+    if len == 0 || sub_len == 0 {
+        return Vec::new();
+    }
+    let last = sub_len - 1;
+    (0..len)
+        .map(|x| {
+            let i = x / div;
+            match (div, cosited, x % div) {
+                (1, _, _) => vec![(i, 1.0)],
+                (2, true, 0) => vec![(i, 1.0)],
+                (2, true, _) => vec![(i, 0.5), ((i + 1).min(last), 0.5)],
+                (2, false, 0) => vec![(i.saturating_sub(1), 0.25), (i, 0.75)],
+                (2, false, _) => vec![(i, 0.75), ((i + 1).min(last), 0.25)],
+                _ => vec![(i.min(last), 1.0)],
+            }
+        })
+        .collect()
+}
+
+impl ImageMap<'_> {
+    /// Writes packed RGB into the image, converting to the image's format.
+    pub fn write_rgb(
+        &mut self,
+        src: &Plane<'_>,
+        src_format: RgbFormat,
+        options: &ColorOptions,
+    ) -> Result<(), ConvertError> {
+        let fourcc = self.image().format().fourcc;
+        if let Some(dst_format) = RgbFormat::from_fourcc(fourcc) {
+            let mut dst = self.plane_mut(0).ok_or(ConvertError::InvalidLayout)?;
+            return rgb_to_rgb(src, src_format, &mut dst, dst_format);
+        }
+        let dst_format =
+            YuvFormat::from_fourcc(fourcc).ok_or(ConvertError::UnsupportedFormat(fourcc))?;
+        let mut planes = self.planes_mut().ok_or(ConvertError::InvalidLayout)?;
+        rgb_to_yuv(src, src_format, &mut planes, dst_format, options)
+    }
+}
+
+impl ImageMapRead<'_> {
+    /// Reads the image into packed RGB, converting from the image's format.
+    pub fn read_rgb(
+        &self,
+        dst: &mut PlaneMut<'_>,
+        dst_format: RgbFormat,
+        options: &ColorOptions,
+    ) -> Result<(), ConvertError> {
+        let fourcc = self.image().format().fourcc;
+        if let Some(src_format) = RgbFormat::from_fourcc(fourcc) {
+            let src = self.plane(0).ok_or(ConvertError::InvalidLayout)?;
+            return rgb_to_rgb(&src, src_format, dst, dst_format);
+        }
+        let src_format =
+            YuvFormat::from_fourcc(fourcc).ok_or(ConvertError::UnsupportedFormat(fourcc))?;
+        yuv_to_rgb(&self.planes(), src_format, dst, dst_format, options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: u32 = 6;
+    const HEIGHT: u32 = 4;
+
+    const COLORS: [[u8; 4]; 4] = [
+        [255, 0, 0, 255],
+        [0, 255, 0, 255],
+        [0, 0, 255, 255],
+        [40, 90, 200, 255],
+    ];
+
+    fn stripes() -> Vec<u8> {
+        (0..HEIGHT)
+            .flat_map(|_| (0..WIDTH).flat_map(|x| COLORS[x as usize / 2]))
+            .collect()
+    }
+
+    fn solid(color: [u8; 4]) -> Vec<u8> {
+        color.repeat((WIDTH * HEIGHT) as usize)
+    }
+
+    fn yuv_buffers(format: YuvFormat) -> Vec<(Vec<u8>, u32, u32, usize)> {
+        let (w, h) = (WIDTH, HEIGHT);
+        match format {
+            YuvFormat::Nv12 => vec![(vec![0; 24], w, h, 1), (vec![0; 12], w / 2, h / 2, 2)],
+            YuvFormat::P010 => vec![(vec![0; 48], w, h, 2), (vec![0; 24], w / 2, h / 2, 4)],
+            YuvFormat::I420 => vec![
+                (vec![0; 24], w, h, 1),
+                (vec![0; 6], w / 2, h / 2, 1),
+                (vec![0; 6], w / 2, h / 2, 1),
+            ],
+            YuvFormat::Yuy2 => vec![(vec![0; 48], w, h, 2)],
+        }
+    }
+
+    fn round_trip(rgba: &[u8], format: YuvFormat, options: &ColorOptions) -> Vec<u8> {
+        let src = Plane::new(rgba, WIDTH, HEIGHT, WIDTH as usize * 4, 4).unwrap();
+        let mut buffers = yuv_buffers(format);
+        let mut planes: Vec<_> = buffers
+            .iter_mut()
+            .map(|(data, w, h, bpe)| PlaneMut::new(data, *w, *h, *w as usize * *bpe, *bpe).unwrap())
+            .collect();
+        rgb_to_yuv(&src, RgbFormat::Rgba, &mut planes, format, options).unwrap();
+
+        let planes: Vec<_> = buffers
+            .iter()
+            .map(|(data, w, h, bpe)| Plane::new(data, *w, *h, *w as usize * *bpe, *bpe).unwrap())
+            .collect();
+        let mut out = vec![0; rgba.len()];
+        let mut dst = PlaneMut::new(&mut out, WIDTH, HEIGHT, WIDTH as usize * 4, 4).unwrap();
+        yuv_to_rgb(&planes, format, &mut dst, RgbFormat::Rgba, options).unwrap();
+        out
+    }
+
+    #[test]
+    fn known_values() {
+        let rgba = [255, 0, 0, 255, 255, 255, 255, 255];
+        let src = Plane::new(&rgba, 2, 1, 8, 4).unwrap();
+        let mut yuy2 = [0u8; 4];
+        let mut dst = [PlaneMut::new(&mut yuy2, 2, 1, 4, 2).unwrap()];
+        let options = ColorOptions {
+            matrix: Matrix::Bt601,
+            chroma_siting: ChromaSiting::Center,
+            ..Default::default()
+        };
+        rgb_to_yuv(&src, RgbFormat::Rgba, &mut dst, YuvFormat::Yuy2, &options).unwrap();
+        // Red is Y=81 and white is Y=235; chroma is the average of the two.
+        assert_eq!(yuy2[0], 81);
+        assert_eq!(yuy2[2], 235);
+        assert_eq!(yuy2[1], 109);
+        assert_eq!(yuy2[3], 184);
+    }
+
+    #[test]
+    fn round_trips() {
+        for (format, color) in [
+            YuvFormat::Nv12,
+            YuvFormat::I420,
+            YuvFormat::P010,
+            YuvFormat::Yuy2,
+        ]
+        .into_iter()
+        .flat_map(|format| COLORS.map(|color| (format, color)))
+        {
+            let expected = solid(color);
+            for matrix in [Matrix::Bt601, Matrix::Bt709, Matrix::Bt2020] {
+                for range in [Range::Limited, Range::Full] {
+                    let options = ColorOptions {
+                        matrix,
+                        range,
+                        chroma_siting: ChromaSiting::Center,
+                    };
+                    let out = round_trip(&expected, format, &options);
+                    for (a, b) in out.iter().zip(&expected) {
+                        assert!(a.abs_diff(*b) <= 2, "{format:?} {options:?}: {out:?}");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn empty_images() {
+        for (width, height) in [(0, 4), (4, 0)] {
+            for format in [
+                YuvFormat::Nv12,
+                YuvFormat::I420,
+                YuvFormat::P010,
+                YuvFormat::Yuy2,
+            ] {
+                let options = ColorOptions::default();
+                let src = Plane::new(&[], width, height, width as usize * 4, 4).unwrap();
+                let mut dst: Vec<_> = (0..format.num_planes())
+                    .map(|_| PlaneMut::new(&mut [], width, height, 0, 4).unwrap())
+                    .collect();
+                rgb_to_yuv(&src, RgbFormat::Rgba, &mut dst, format, &options).unwrap();
+
+                let src: Vec<_> = (0..format.num_planes())
+                    .map(|_| Plane::new(&[], width, height, 0, 4).unwrap())
+                    .collect();
+                let mut dst = PlaneMut::new(&mut [], width, height, width as usize * 4, 4).unwrap();
+                yuv_to_rgb(&src, format, &mut dst, RgbFormat::Rgba, &options).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn yuy2_odd_width() {
+        let rgba = [COLORS[0], COLORS[0], COLORS[2]].concat();
+        let src = Plane::new(&rgba, 3, 1, 12, 4).unwrap();
+        let options = ColorOptions {
+            matrix: Matrix::Bt601,
+            chroma_siting: ChromaSiting::Center,
+            ..Default::default()
+        };
+        // Three pixels do not fit in a plane of three elements.
+        let mut yuy2 = [0u8; 8];
+        let mut dst = [PlaneMut::new(&mut yuy2, 3, 1, 6, 2).unwrap()];
+        assert_eq!(
+            rgb_to_yuv(&src, RgbFormat::Rgba, &mut dst, YuvFormat::Yuy2, &options),
+            Err(ConvertError::SizeMismatch)
+        );
+
+        let mut dst = [PlaneMut::new(&mut yuy2, 4, 1, 8, 2).unwrap()];
+        rgb_to_yuv(&src, RgbFormat::Rgba, &mut dst, YuvFormat::Yuy2, &options).unwrap();
+        // The blue pixel is repeated into the padding and has its own
+        // chroma pair.
+        assert_eq!(yuy2[4], 41);
+        assert_eq!(yuy2[6], 41);
+        assert_eq!(yuy2[5], 240);
+        assert_eq!(yuy2[7], 110);
+
+        let rgba = COLORS[3].repeat(3);
+        let src = Plane::new(&rgba, 3, 1, 12, 4).unwrap();
+        let mut dst = [PlaneMut::new(&mut yuy2, 4, 1, 8, 2).unwrap()];
+        rgb_to_yuv(&src, RgbFormat::Rgba, &mut dst, YuvFormat::Yuy2, &options).unwrap();
+        let src = [Plane::new(&yuy2, 4, 1, 8, 2).unwrap()];
+        let mut out = [0; 12];
+        let mut dst = PlaneMut::new(&mut out, 3, 1, 12, 4).unwrap();
+        yuv_to_rgb(&src, YuvFormat::Yuy2, &mut dst, RgbFormat::Rgba, &options).unwrap();
+        for (a, b) in out.iter().zip(&rgba) {
+            assert!(a.abs_diff(*b) <= 2, "{out:?}");
+        }
+    }
+
+    #[test]
+    fn cosited_chroma_blurs_edges() {
+        let options = ColorOptions {
+            chroma_siting: ChromaSiting::Left,
+            ..Default::default()
+        };
+        let out = round_trip(&stripes(), YuvFormat::Nv12, &options);
+        // The first column is co-sited with a pure chroma sample, but the
+        // second is interpolated towards the green neighbor.
+        assert!(out[0] > 240);
+        assert!(out[4] < out[0]);
+    }
+}
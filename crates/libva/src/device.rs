@@ -0,0 +1,425 @@
+use std::fs;
+use std::io;
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::{Display, Error, Library};
+
+const DEV_DRI: &str = "/dev/dri";
+const SYS_CLASS_DRM: &str = "/sys/class/drm";
+
+/// PCI identity of the GPU behind a render node.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PciInfo {
+    pub vendor_id: u16,
+    pub device_id: u16,
+    /// Bus address in `domain:bus:device.function` form, e.g. `0000:03:00.0`.
+    pub bus_id: String,
+}
+
+/// A DRM render node and what sysfs says about it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RenderNode {
+    pub path: PathBuf,
+    pub pci: Option<PciInfo>,
+    /// Name of the kernel driver bound to the device, e.g. `i915` or `amdgpu`.
+    pub kernel_driver: Option<String>,
+}
+
+impl RenderNode {
+    /// Describes the render node at `path`. Missing sysfs information is
+    /// left empty rather than reported as an error.
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self::with_sysfs(path.as_ref(), Path::new(SYS_CLASS_DRM))
+    }
+
+    fn with_sysfs(path: &Path, sys_class_drm: &Path) -> Self {
+        let name = fs::canonicalize(path)
+            .ok()
+            .and_then(|p| p.file_name().map(|n| n.to_owned()))
+            .or_else(|| path.file_name().map(|n| n.to_owned()));
+        let device = name.map(|name| sys_class_drm.join(name).join("device"));
+        let link_name = |link: &str| -> Option<String> {
+            let target = fs::read_link(device.as_ref()?.join(link)).ok()?;
+            Some(target.file_name()?.to_str()?.to_string())
+        };
+        let pci = (link_name("subsystem").as_deref() == Some("pci"))
+            .then(|| {
+                let device = device.as_ref()?;
+                let read_id = |file: &str| -> Option<u16> {
+                    let text = fs::read_to_string(device.join(file)).ok()?;
+                    u16::from_str_radix(text.trim().trim_start_matches("0x"), 16).ok()
+                };
+                let bus_id = fs::canonicalize(device)
+                    .ok()?
+                    .file_name()?
+                    .to_str()?
+                    .to_string();
+                Some(PciInfo {
+                    vendor_id: read_id("vendor")?,
+                    device_id: read_id("device")?,
+                    bus_id,
+                })
+            })
+            .flatten();
+        Self {
+            path: path.to_owned(),
+            pci,
+            kernel_driver: link_name("driver"),
+        }
+    }
+
+    /// Whether the node sits at the PCI address `bus_id`. The domain may be
+    /// omitted, in which case domain `0000` is assumed.
+    pub fn matches_bus_id(&self, bus_id: &str) -> bool {
+        let Some(pci) = &self.pci else {
+            return false;
+        };
+        let bus_id = bus_id.trim().to_ascii_lowercase();
+        if bus_id.matches(':').count() == 1 {
+            pci.bus_id.eq_ignore_ascii_case(&format!("0000:{}", bus_id))
+        } else {
+            pci.bus_id.eq_ignore_ascii_case(&bus_id)
+        }
+    }
+}
+
+/// Why a render node could not be opened.
+#[derive(Debug)]
+pub enum OpenError {
+    /// The node could not be opened.
+    Io { path: PathBuf, source: io::Error },
+    /// libva failed to initialize a driver for the node.
+    Initialize { path: PathBuf, source: Box<Error> },
+    /// No render node matched the request.
+    NotFound(String),
+}
+
+impl std::fmt::Display for OpenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            OpenError::Io { path, source } => {
+                write!(f, "failed to open {}: {}", path.display(), source)
+            }
+            OpenError::Initialize { path, source } => {
+                write!(f, "failed to initialize {}: {}", path.display(), source)
+            }
+            OpenError::NotFound(what) => write!(f, "no render node found for {}", what),
+        }
+    }
+}
+
+impl std::error::Error for OpenError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            OpenError::Io { source, .. } => Some(source),
+            OpenError::Initialize { source, .. } => Some(source),
+            OpenError::NotFound(_) => None,
+        }
+    }
+}
+
+/// The outcome of opening one render node during enumeration.
+#[derive(Debug)]
+pub struct Probe {
+    pub node: RenderNode,
+    pub display: Result<Arc<Display>, OpenError>,
+}
+
+impl Probe {
+    /// The VA driver's vendor string, if the node initialized.
+    pub fn vendor_string(&self) -> Option<String> {
+        self.display.as_ref().ok()?.vendor_string()
+    }
+}
+
+/// Well-known PCI vendor IDs.
+pub mod pci_vendor {
+    pub const INTEL: u16 = 0x8086;
+    pub const AMD: u16 = 0x1002;
+    pub const NVIDIA: u16 = 0x10de;
+}
+
+/// Ordered preferences for picking a device among several GPUs.
+///
+/// Devices matching an earlier vendor are preferred over later ones, and
+/// vendor preferences take priority over driver preferences. Devices that
+/// match nothing are used only when `strict` is unset.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DevicePreference {
+    pub vendors: Vec<u16>,
+    /// Kernel driver names (e.g. `i915`) or substrings of the VA vendor
+    /// string (e.g. `Mesa Gallium`), matched case-insensitively.
+    pub drivers: Vec<String>,
+    pub strict: bool,
+}
+
+impl DevicePreference {
+    pub fn vendor(mut self, vendor_id: u16) -> Self {
+        self.vendors.push(vendor_id);
+        self
+    }
+
+    pub fn driver(mut self, name: impl Into<String>) -> Self {
+        self.drivers.push(name.into());
+        self
+    }
+
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Lower is better; `None` if the probe matches nothing.
+    fn rank(&self, probe: &Probe) -> Option<usize> {
+        let vendor = probe.node.pci.as_ref().and_then(|pci| {
+            self.vendors
+                .iter()
+                .position(|&vendor| vendor == pci.vendor_id)
+        });
+        let vendor_string = probe.vendor_string().unwrap_or_default().to_lowercase();
+        let kernel_driver = probe.node.kernel_driver.as_deref().unwrap_or_default();
+        let driver = self.drivers.iter().position(|name| {
+            kernel_driver.eq_ignore_ascii_case(name) || vendor_string.contains(&name.to_lowercase())
+        });
+        match (vendor, driver) {
+            (Some(vendor), driver) => {
+                Some(vendor * (self.drivers.len() + 1) + driver.unwrap_or(self.drivers.len()))
+            }
+            (None, Some(driver)) => Some(self.vendors.len() * (self.drivers.len() + 1) + driver),
+            (None, None) if self.vendors.is_empty() && self.drivers.is_empty() => Some(0),
+            (None, None) => None,
+        }
+    }
+
+    fn select(&self, probes: Vec<Probe>) -> Result<Arc<Display>, OpenError> {
+        let mut best: Option<(usize, Arc<Display>)> = None;
+        let mut first_error = None;
+        for probe in probes {
+            let rank = self.rank(&probe);
+            match probe.display {
+                Ok(display) => {
+                    let rank = match rank {
+                        Some(rank) => rank,
+                        None if !self.strict => usize::MAX,
+                        None => continue,
+                    };
+                    if best.as_ref().is_none_or(|(best, _)| rank < *best) {
+                        best = Some((rank, display));
+                    }
+                }
+                Err(error) => {
+                    first_error.get_or_insert(error);
+                }
+            }
+        }
+        match (best, first_error) {
+            (Some((_, display)), _) => Ok(display),
+            (None, Some(error)) => Err(error),
+            (None, None) => Err(OpenError::NotFound(format!("{:?}", self))),
+        }
+    }
+}
+
+/// Lists the render nodes in `dir`, sorted by minor number.
+fn render_nodes(dir: &Path) -> Vec<PathBuf> {
+    let mut nodes: Vec<(u32, PathBuf)> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            if !entry.file_type().ok()?.is_char_device() {
+                return None;
+            }
+            let path = entry.path();
+            let node_id = path
+                .file_name()?
+                .to_str()?
+                .strip_prefix("renderD")?
+                .parse()
+                .ok()?;
+            Some((node_id, path))
+        })
+        .collect();
+    nodes.sort();
+    nodes.into_iter().map(|(_, path)| path).collect()
+}
+
+impl Display {
+    /// Opens and initializes the render node at `path`, e.g.
+    /// `/dev/dri/renderD128` or a `/dev/dri/by-path` link.
+    pub fn open(library: Arc<Library>, path: impl AsRef<Path>) -> Result<Arc<Self>, OpenError> {
+        Self::open_node(library, RenderNode::new(path))
+    }
+
+    fn open_node(library: Arc<Library>, node: RenderNode) -> Result<Arc<Self>, OpenError> {
+        let file = match fs::File::options().read(true).write(true).open(&node.path) {
+            Ok(file) => file,
+            Err(source) => {
+                return Err(OpenError::Io {
+                    path: node.path,
+                    source,
+                })
+            }
+        };
+        let path = node.path.clone();
+        Self::from_drm_node(library, file.into(), Some(node)).map_err(|source| {
+            OpenError::Initialize {
+                path,
+                source: Box::new(source),
+            }
+        })
+    }
+
+    /// Opens every render node under `/dev/dri`, reporting failures instead
+    /// of skipping them.
+    pub fn enumerate(library: Arc<Library>) -> impl Iterator<Item = Probe> {
+        render_nodes(Path::new(DEV_DRI))
+            .into_iter()
+            .map(move |path| {
+                let node = RenderNode::new(&path);
+                Probe {
+                    display: Self::open_node(library.clone(), node.clone()),
+                    node,
+                }
+            })
+    }
+
+    /// Opens the render node of the GPU at PCI address `bus_id`, such as
+    /// `0000:03:00.0` or `03:00.0`.
+    pub fn open_by_pci(library: Arc<Library>, bus_id: &str) -> Result<Arc<Self>, OpenError> {
+        render_nodes(Path::new(DEV_DRI))
+            .into_iter()
+            .map(RenderNode::new)
+            .find(|node| node.matches_bus_id(bus_id))
+            .ok_or_else(|| OpenError::NotFound(format!("PCI address {}", bus_id)))
+            .and_then(|node| Self::open_node(library, node))
+    }
+
+    /// Opens the render node that best matches `preference`.
+    pub fn select(
+        library: Arc<Library>,
+        preference: &DevicePreference,
+    ) -> Result<Arc<Self>, OpenError> {
+        preference.select(Self::enumerate(library).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake::FakeBackend;
+    use std::os::unix::fs::symlink;
+
+    fn sysfs(name: &str) -> PathBuf {
+        let root =
+            std::env::temp_dir().join(format!("vendec-sysfs-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let pci = root.join("devices/pci0000:00/0000:03:00.0");
+        fs::create_dir_all(&pci).unwrap();
+        fs::create_dir_all(root.join("bus/pci/drivers/amdgpu")).unwrap();
+        fs::create_dir_all(root.join("class/drm/renderD129")).unwrap();
+        fs::write(pci.join("vendor"), "0x1002\n").unwrap();
+        fs::write(pci.join("device"), "0x73bf\n").unwrap();
+        symlink(root.join("bus/pci"), pci.join("subsystem")).unwrap();
+        symlink(root.join("bus/pci/drivers/amdgpu"), pci.join("driver")).unwrap();
+        symlink(&pci, root.join("class/drm/renderD129/device")).unwrap();
+        root
+    }
+
+    #[test]
+    fn reads_sysfs() {
+        let root = sysfs("reads");
+        let node =
+            RenderNode::with_sysfs(Path::new("/dev/dri/renderD129"), &root.join("class/drm"));
+        fs::remove_dir_all(&root).unwrap();
+        assert_eq!(
+            node.pci,
+            Some(PciInfo {
+                vendor_id: pci_vendor::AMD,
+                device_id: 0x73bf,
+                bus_id: "0000:03:00.0".to_string(),
+            })
+        );
+        assert_eq!(node.kernel_driver.as_deref(), Some("amdgpu"));
+        assert!(node.matches_bus_id("03:00.0"));
+        assert!(node.matches_bus_id("0000:03:00.0"));
+        assert!(!node.matches_bus_id("0000:04:00.0"));
+
+        let missing = RenderNode::with_sysfs(Path::new("/dev/dri/renderD200"), &root);
+        assert_eq!(missing.pci, None);
+        assert_eq!(missing.kernel_driver, None);
+    }
+
+    #[test]
+    fn open_reports_errors() {
+        let library = FakeBackend::default().library();
+        let display = Display::open(library.clone(), "/dev/null").unwrap();
+        assert_eq!(display.node().unwrap().path, Path::new("/dev/null"));
+        assert!(matches!(
+            Display::open(library, "/nonexistent/renderD128"),
+            Err(OpenError::Io { .. })
+        ));
+
+        let fake = FakeBackend::default();
+        fake.inject_error("vaInitialize", crate::ErrorStatus::UnsupportedProfile);
+        let err = Display::open(fake.library(), "/dev/null").unwrap_err();
+        assert!(matches!(err, OpenError::Initialize { .. }), "{}", err);
+    }
+
+    fn probe(library: &Arc<Library>, vendor_id: u16, driver: &str) -> Probe {
+        let node = RenderNode {
+            path: PathBuf::from("/dev/null"),
+            pci: Some(PciInfo {
+                vendor_id,
+                device_id: 0,
+                bus_id: String::new(),
+            }),
+            kernel_driver: Some(driver.to_string()),
+        };
+        Probe {
+            display: Display::open_node(library.clone(), node.clone()),
+            node,
+        }
+    }
+
+    #[test]
+    fn selects_by_preference() {
+        let library = FakeBackend::default().library();
+        let probes = || {
+            vec![
+                probe(&library, pci_vendor::INTEL, "i915"),
+                probe(&library, pci_vendor::AMD, "amdgpu"),
+            ]
+        };
+        let driver_of = |display: Arc<Display>| display.node().unwrap().kernel_driver.clone();
+
+        let any = DevicePreference::default().select(probes()).unwrap();
+        assert_eq!(driver_of(any).as_deref(), Some("i915"));
+        let amd = DevicePreference::default()
+            .vendor(pci_vendor::AMD)
+            .select(probes())
+            .unwrap();
+        assert_eq!(driver_of(amd).as_deref(), Some("amdgpu"));
+        let by_driver = DevicePreference::default()
+            .driver("AMDGPU")
+            .select(probes())
+            .unwrap();
+        assert_eq!(driver_of(by_driver).as_deref(), Some("amdgpu"));
+        let fallback = DevicePreference::default()
+            .vendor(pci_vendor::NVIDIA)
+            .select(probes())
+            .unwrap();
+        assert_eq!(driver_of(fallback).as_deref(), Some("i915"));
+        assert!(matches!(
+            DevicePreference::default()
+                .vendor(pci_vendor::NVIDIA)
+                .strict(true)
+                .select(probes()),
+            Err(OpenError::NotFound(_))
+        ));
+    }
+}
@@ -1,62 +1,71 @@
-use std::fs;
-use std::os::fd::IntoRawFd;
-use std::os::fd::OwnedFd;
-use std::os::unix::fs::FileTypeExt;
+use std::ops::RangeInclusive;
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, OwnedFd};
 use std::sync::Arc;
 
 use crate::sys;
 use crate::ConfigAttributes;
+use crate::ContextPriority;
 use crate::Entrypoint;
+use crate::Error;
+use crate::ErrorStatus;
+use crate::ImageFormat;
 use crate::Library;
 use crate::Profile;
+use crate::RenderNode;
 use crate::VaResult;
 use crate::VaStatusExt;
 
 pub struct Display {
     handle: sys::VADisplay,
     library: Arc<Library>,
+    // Closed only after the display has been terminated.
+    drm_fd: OwnedFd,
+    node: Option<RenderNode>,
+}
+
+/// The sub-devices (tiles) of a multi-tile GPU, from
+/// `VADisplayAttribSubDevice`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SubDevices {
+    pub count: u32,
+    /// Index of the sub-device new configs and contexts are created on.
+    pub current: u32,
+    /// Bit `i` is set if sub-device `i` is available.
+    pub mask: u32,
 }
 
 impl Display {
     pub fn from_drm(library: Arc<Library>, drm_fd: OwnedFd) -> VaResult<Arc<Self>> {
-        let handle = unsafe { library.lib().vaGetDisplayDRM(drm_fd.into_raw_fd()) };
+        Self::from_drm_node(library, drm_fd, None)
+    }
+
+    pub(crate) fn from_drm_node(
+        library: Arc<Library>,
+        drm_fd: OwnedFd,
+        node: Option<RenderNode>,
+    ) -> VaResult<Arc<Self>> {
+        let handle = unsafe { library.lib().vaGetDisplayDRM(drm_fd.as_raw_fd()) };
+        if handle.is_null() {
+            return Err(Error::new("vaGetDisplayDRM", ErrorStatus::InvalidDisplay));
+        }
         let mut major_version = 0;
         let mut minor_version = 0;
-        unsafe {
+        let initialized = unsafe {
             library
                 .lib()
                 .vaInitialize(handle, &mut major_version, &mut minor_version)
-                .va_result()?;
+                .va_check(&library, "vaInitialize", &[])
+        };
+        if let Err(err) = initialized {
+            unsafe { library.lib().vaTerminate(handle) };
+            return Err(err);
         }
-        Ok(Arc::new(Self { handle, library }))
-    }
-
-    pub fn enumerate(library: Arc<Library>) -> impl Iterator<Item = Arc<Self>> {
-        let mut render_nodes = fs::read_dir("/dev/dri")
-            .ok()
-            .into_iter()
-            .flat_map(move |dir| {
-                dir.filter_map(move |entry| {
-                    let entry = entry.ok()?;
-                    if !entry.file_type().ok()?.is_char_device() {
-                        return None;
-                    }
-                    let path = entry.path();
-                    let file_name = path.file_name()?.to_str()?;
-                    if !file_name.starts_with("renderD") {
-                        return None;
-                    }
-                    let node_id: u32 = file_name["renderD".len()..].parse().ok()?;
-                    Some(node_id)
-                })
-            })
-            .collect::<Vec<_>>();
-        render_nodes.sort();
-        render_nodes.into_iter().filter_map(move |node_id| {
-            let path = format!("/dev/dri/renderD{}", node_id);
-            let drm_file = fs::File::options().read(true).write(true).open(path).ok()?;
-            Self::from_drm(library.clone(), drm_file.into()).ok()
-        })
+        Ok(Arc::new(Self {
+            handle,
+            library,
+            drm_fd,
+            node,
+        }))
     }
 
     pub fn handle(&self) -> sys::VADisplay {
@@ -67,6 +76,30 @@ impl Display {
         &self.library
     }
 
+    pub fn drm_fd(&self) -> BorrowedFd<'_> {
+        self.drm_fd.as_fd()
+    }
+
+    /// The render node the display was opened on, unless it was created
+    /// from a bare file descriptor.
+    pub fn node(&self) -> Option<&RenderNode> {
+        self.node.as_ref()
+    }
+
+    /// The VA driver's description of itself, e.g.
+    /// `Intel iHD driver for Intel(R) Gen Graphics - 24.1.0`.
+    pub fn vendor_string(&self) -> Option<String> {
+        let raw = unsafe { self.library.lib().vaQueryVendorString(self.handle) };
+        if raw.is_null() {
+            return None;
+        }
+        Some(
+            unsafe { std::ffi::CStr::from_ptr(raw) }
+                .to_string_lossy()
+                .into_owned(),
+        )
+    }
+
     pub fn query_config_profiles(&self) -> VaResult<Vec<Profile>> {
         let mut profiles_count = unsafe { self.library.lib().vaMaxNumProfiles(self.handle) };
         let mut raw_profiles = vec![sys::VAProfileNone; profiles_count as usize];
@@ -74,7 +107,7 @@ impl Display {
             self.library()
                 .lib()
                 .vaQueryConfigProfiles(self.handle, raw_profiles.as_mut_ptr(), &mut profiles_count)
-                .va_result()?;
+                .va_check(self.library(), "vaQueryConfigProfiles", &[])?;
         }
         Ok(raw_profiles
             .iter()
@@ -83,6 +116,45 @@ impl Display {
             .collect())
     }
 
+    pub fn query_config_entrypoints(&self, profile: Option<Profile>) -> VaResult<Vec<Entrypoint>> {
+        let raw_profile = profile.map(Into::into).unwrap_or(sys::VAProfileNone);
+        let mut entrypoints_count =
+            unsafe { self.library().lib().vaMaxNumEntrypoints(self.handle()) };
+        let mut raw_entrypoints = vec![0; entrypoints_count as usize];
+        unsafe {
+            self.library()
+                .lib()
+                .vaQueryConfigEntrypoints(
+                    self.handle(),
+                    raw_profile,
+                    raw_entrypoints.as_mut_ptr(),
+                    &mut entrypoints_count,
+                )
+                .va_check(self.library(), "vaQueryConfigEntrypoints", &[])?;
+        }
+        Ok(raw_entrypoints
+            .iter()
+            .take(entrypoints_count as usize)
+            .filter_map(|&raw| Entrypoint::try_from(raw).ok())
+            .collect())
+    }
+
+    pub fn query_image_formats(&self) -> VaResult<Vec<ImageFormat>> {
+        let mut formats_count = unsafe { self.library.lib().vaMaxNumImageFormats(self.handle) };
+        let mut raw_formats = vec![sys::VAImageFormat::default(); formats_count as usize];
+        unsafe {
+            self.library()
+                .lib()
+                .vaQueryImageFormats(self.handle, raw_formats.as_mut_ptr(), &mut formats_count)
+                .va_check(self.library(), "vaQueryImageFormats", &[])?;
+        }
+        Ok(raw_formats
+            .iter()
+            .take(formats_count as usize)
+            .filter_map(|&raw| ImageFormat::try_from(raw).ok())
+            .collect())
+    }
+
     pub fn get_config_attributes(
         &self,
         profile: Option<Profile>,
@@ -101,10 +173,76 @@ impl Display {
                     raw_attrib_list.as_mut_ptr(),
                     raw_attrib_list.len() as _,
                 )
-                .va_result()?;
+                .va_check(self.library(), "vaGetConfigAttributes", &[])?;
         }
         Ok(ConfigAttributes::from_raw_attrib_list(&raw_attrib_list))
     }
+
+    /// Priority levels contexts for `profile` and `entrypoint` can be
+    /// scheduled at, or `None` if the driver has no context priorities. The
+    /// end of the range is the closest to realtime.
+    pub fn query_context_priority_range(
+        &self,
+        profile: Option<Profile>,
+        entrypoint: Entrypoint,
+    ) -> VaResult<Option<RangeInclusive<ContextPriority>>> {
+        let attributes = self.get_config_attributes(profile, entrypoint)?;
+        Ok(attributes
+            .context_priority
+            .map(|max| ContextPriority::BEST_EFFORT..=max))
+    }
+
+    /// Returns `None` if the device is not split into sub-devices.
+    pub fn sub_devices(&self) -> VaResult<Option<SubDevices>> {
+        let mut attrib = sys::VADisplayAttribute {
+            type_: sys::VADisplayAttribSubDevice,
+            ..Default::default()
+        };
+        unsafe {
+            self.library()
+                .lib()
+                .vaGetDisplayAttributes(self.handle(), &mut attrib, 1)
+                .va_check(self.library(), "vaGetDisplayAttributes", &[])?;
+        }
+        if attrib.flags & sys::VA_DISPLAY_ATTRIB_GETTABLE == 0 {
+            return Ok(None);
+        }
+        let value = sys::VADisplayAttribValSubDevice {
+            value: attrib.value as u32,
+        };
+        let bits = unsafe { value.bits };
+        Ok(Some(SubDevices {
+            count: bits.sub_device_count(),
+            current: bits.current_sub_device(),
+            mask: bits.sub_device_mask(),
+        }))
+    }
+
+    /// Selects the sub-device configs and contexts created from now on run
+    /// on.
+    pub fn set_sub_device(&self, index: u32) -> VaResult<()> {
+        const OPERATION: &str = "vaSetDisplayAttributes";
+        let sub_devices = self
+            .sub_devices()?
+            .ok_or(Error::new(OPERATION, ErrorStatus::AttrNotSupported))?;
+        if index >= sub_devices.count || sub_devices.mask & (1 << index) == 0 {
+            return Err(Error::new(OPERATION, ErrorStatus::InvalidParameter));
+        }
+        let mut value = sys::VADisplayAttribValSubDevice { value: 0 };
+        unsafe { value.bits.set_current_sub_device(index) };
+        let mut attrib = sys::VADisplayAttribute {
+            type_: sys::VADisplayAttribSubDevice,
+            value: unsafe { value.value } as i32,
+            flags: sys::VA_DISPLAY_ATTRIB_SETTABLE,
+            ..Default::default()
+        };
+        unsafe {
+            self.library()
+                .lib()
+                .vaSetDisplayAttributes(self.handle(), &mut attrib, 1)
+                .va_check(self.library(), OPERATION, &[])
+        }
+    }
 }
 
 impl Drop for Display {
@@ -119,6 +257,29 @@ impl std::fmt::Debug for Display {
     }
 }
 
-// Safety: VA-API is thread-safe.
+// Safety: VA-API is thread-safe. Objects holding a display are Send + Sync
+// through it; those libva requires serialized access to (context pictures,
+// buffer maps) lock internally.
 unsafe impl Send for Display {}
 unsafe impl Sync for Display {}
+
+#[cfg(test)]
+mod tests {
+    use crate::{fake, ErrorStatus};
+
+    #[test]
+    fn failed_open_leaves_no_display() {
+        let fake = fake::FakeBackend::default();
+        fake.inject_error("vaGetDisplayDRM", ErrorStatus::InvalidDisplay);
+        let err = fake::open_display(&fake.library()).err().unwrap();
+        assert_eq!(err.operation(), "vaGetDisplayDRM");
+
+        fake.inject_error("vaInitialize", ErrorStatus::OperationFailed);
+        let err = fake::open_display(&fake.library()).err().unwrap();
+        assert_eq!(err.operation(), "vaInitialize");
+        assert_eq!(fake.live_objects().displays, 0);
+
+        let _display = fake::open_display(&fake.library()).unwrap();
+        assert_eq!(fake.live_objects().displays, 1);
+    }
+}
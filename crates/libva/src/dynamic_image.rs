@@ -0,0 +1,193 @@
+//! Transfers between [`image`] crate buffers and VA surfaces.
+
+use std::sync::Arc;
+
+use ::image::{DynamicImage, RgbaImage};
+
+use crate::convert::{ColorOptions, ConvertError, RgbFormat, YuvFormat};
+use crate::{Error, ErrorStatus, Fourcc, Image, ImageFormat, Plane, PlaneMut, Surface, VaResult};
+
+#[derive(Debug)]
+pub enum TransferError {
+    Va(Error),
+    Convert(ConvertError),
+    /// Neither a derived image nor any of the display's image formats can be
+    /// converted to or from RGB.
+    NoCompatibleFormat,
+}
+
+impl From<Error> for TransferError {
+    fn from(value: Error) -> Self {
+        Self::Va(value)
+    }
+}
+
+impl From<ConvertError> for TransferError {
+    fn from(value: ConvertError) -> Self {
+        Self::Convert(value)
+    }
+}
+
+impl std::fmt::Display for TransferError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            TransferError::Va(err) => err.fmt(f),
+            TransferError::Convert(err) => err.fmt(f),
+            TransferError::NoCompatibleFormat => write!(f, "no image format compatible with RGB"),
+        }
+    }
+}
+impl std::error::Error for TransferError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TransferError::Va(err) => Some(err),
+            TransferError::Convert(err) => Some(err),
+            TransferError::NoCompatibleFormat => None,
+        }
+    }
+}
+
+fn is_convertible(fourcc: Fourcc) -> bool {
+    RgbFormat::from_fourcc(fourcc).is_some() || YuvFormat::from_fourcc(fourcc).is_some()
+}
+
+impl Surface {
+    /// Uploads `img` into the surface with default color options.
+    ///
+    /// Images larger than the surface are cropped.
+    pub fn upload_image(self: &Arc<Self>, img: &DynamicImage) -> Result<(), TransferError> {
+        self.upload_image_with(img, &ColorOptions::default())
+    }
+
+    pub fn upload_image_with(
+        self: &Arc<Self>,
+        img: &DynamicImage,
+        options: &ColorOptions,
+    ) -> Result<(), TransferError> {
+        let rgba = img.to_rgba8();
+        let width = rgba.width().min(self.width());
+        let height = rgba.height().min(self.height());
+        let src = Plane::new(&rgba, width, height, rgba.width() as usize * 4, 4)
+            .ok_or(ConvertError::SizeMismatch)?;
+
+        self.sync()?;
+        let (image, derived) = self.transfer_image()?;
+        image.map()?.write_rgb(&src, RgbFormat::Rgba, options)?;
+        if !derived {
+            self.put_image(&image, 0, 0, width, height)?;
+        }
+        Ok(())
+    }
+
+    /// Downloads the surface into an RGBA image with default color options.
+    pub fn download_image(self: &Arc<Self>) -> Result<DynamicImage, TransferError> {
+        self.download_image_with(&ColorOptions::default())
+    }
+
+    pub fn download_image_with(
+        self: &Arc<Self>,
+        options: &ColorOptions,
+    ) -> Result<DynamicImage, TransferError> {
+        self.sync()?;
+        let (image, derived) = self.transfer_image()?;
+        if !derived {
+            self.get_image(&image, 0, 0, self.width(), self.height())?;
+        }
+        let mut rgba = RgbaImage::new(self.width(), self.height());
+        let mut dst = PlaneMut::new(
+            &mut rgba,
+            self.width(),
+            self.height(),
+            self.width() as usize * 4,
+            4,
+        )
+        .ok_or(ConvertError::SizeMismatch)?;
+        image
+            .map_read()?
+            .read_rgb(&mut dst, RgbFormat::Rgba, options)?;
+        Ok(DynamicImage::ImageRgba8(rgba))
+    }
+
+    /// Returns an image to stage transfers through, and whether it is derived
+    /// from the surface (and so needs no copy).
+    fn transfer_image(self: &Arc<Self>) -> Result<(Arc<Image>, bool), TransferError> {
+        if let Ok(image) = self.derive_image() {
+            if is_convertible(image.format().fourcc) {
+                return Ok((image, true));
+            }
+        }
+        let format = self.staging_format()?;
+        let image = Image::new(self.display().clone(), &format, self.width(), self.height())?;
+        Ok((image, false))
+    }
+
+    /// Picks the convertible image format closest to the surface's format.
+    fn staging_format(&self) -> VaResult<ImageFormat> {
+        let formats = self.display().query_image_formats()?;
+        formats
+            .into_iter()
+            .filter(|format| is_convertible(format.fourcc))
+            .max_by_key(|format| {
+                let exact = Some(format.fourcc) == self.pixel_format();
+                let same_rt_format = format
+                    .fourcc
+                    .info()
+                    .is_some_and(|info| self.format().intersects(info.rt_format));
+                (exact, same_rt_format)
+            })
+            .ok_or_else(|| Error::new("Surface::staging_format", ErrorStatus::InvalidImageFormat))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fake, UsageHint};
+    use image::{Rgba, RgbaImage};
+
+    #[test]
+    fn round_trip() {
+        let fake = fake::FakeBackend::default();
+        let display = fake::open_display(&fake.library()).unwrap();
+        let surface =
+            Surface::with_pixel_format(display, Fourcc::BGRA, 8, 4, UsageHint::GENERIC).unwrap();
+        let img = RgbaImage::from_fn(8, 4, |x, y| Rgba([x as u8 * 30, y as u8 * 60, 7, 255]));
+        surface.upload_image(&img.clone().into()).unwrap();
+        assert_eq!(surface.download_image().unwrap().to_rgba8(), img);
+    }
+
+    #[test]
+    fn yuv_round_trip() {
+        let fake = fake::FakeBackend::default();
+        let display = fake::open_display(&fake.library()).unwrap();
+        let surface =
+            Surface::with_pixel_format(display, Fourcc::NV12, 8, 4, UsageHint::GENERIC).unwrap();
+        // Chroma is filtered when resampled, so it is kept flat.
+        let images = [
+            RgbaImage::from_fn(8, 4, |x, y| {
+                let level = (x * 30 + y * 10) as u8;
+                Rgba([level, level, level, 255])
+            }),
+            RgbaImage::from_pixel(8, 4, Rgba([60, 120, 200, 255])),
+        ];
+        for img in images {
+            let close = |downloaded: DynamicImage| {
+                let downloaded = downloaded.to_rgba8();
+                downloaded
+                    .pixels()
+                    .zip(img.pixels())
+                    .all(|(a, b)| a.0.iter().zip(b.0).all(|(&a, b)| a.abs_diff(b) <= 2))
+            };
+            surface.upload_image(&img.clone().into()).unwrap();
+            assert!(close(surface.download_image().unwrap()));
+
+            // Without a derived image, through a staging image.
+            surface.upload_image(&RgbaImage::new(8, 4).into()).unwrap();
+            fake.inject_error("vaDeriveImage", ErrorStatus::OperationFailed);
+            surface.upload_image(&img.clone().into()).unwrap();
+            fake.inject_error("vaDeriveImage", ErrorStatus::OperationFailed);
+            assert!(close(surface.download_image().unwrap()));
+        }
+        assert!(fake.violations().is_empty());
+    }
+}
@@ -1,4 +1,6 @@
-use crate::sys;
+use std::ffi::CStr;
+
+use crate::{sys, Library};
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum ErrorStatus {
@@ -77,6 +79,87 @@ impl ErrorStatus {
         })
     }
 
+    /// Returns the raw status code. [`ErrorStatus::Unknown`] maps to
+    /// `VA_STATUS_ERROR_UNKNOWN`; use [`Error::raw_status`] to recover the
+    /// exact code of an unrecognized status.
+    pub fn to_raw(self) -> sys::VAStatus {
+        (match self {
+            ErrorStatus::OperationFailed => sys::VA_STATUS_ERROR_OPERATION_FAILED,
+            ErrorStatus::AllocationFailed => sys::VA_STATUS_ERROR_ALLOCATION_FAILED,
+            ErrorStatus::InvalidDisplay => sys::VA_STATUS_ERROR_INVALID_DISPLAY,
+            ErrorStatus::InvalidConfig => sys::VA_STATUS_ERROR_INVALID_CONFIG,
+            ErrorStatus::InvalidContext => sys::VA_STATUS_ERROR_INVALID_CONTEXT,
+            ErrorStatus::InvalidSurface => sys::VA_STATUS_ERROR_INVALID_SURFACE,
+            ErrorStatus::InvalidBuffer => sys::VA_STATUS_ERROR_INVALID_BUFFER,
+            ErrorStatus::InvalidImage => sys::VA_STATUS_ERROR_INVALID_IMAGE,
+            ErrorStatus::InvalidSubpicture => sys::VA_STATUS_ERROR_INVALID_SUBPICTURE,
+            ErrorStatus::AttrNotSupported => sys::VA_STATUS_ERROR_ATTR_NOT_SUPPORTED,
+            ErrorStatus::MaxNumExceeded => sys::VA_STATUS_ERROR_MAX_NUM_EXCEEDED,
+            ErrorStatus::UnsupportedProfile => sys::VA_STATUS_ERROR_UNSUPPORTED_PROFILE,
+            ErrorStatus::UnsupportedEntrypoint => sys::VA_STATUS_ERROR_UNSUPPORTED_ENTRYPOINT,
+            ErrorStatus::UnsupportedRtFormat => sys::VA_STATUS_ERROR_UNSUPPORTED_RT_FORMAT,
+            ErrorStatus::UnsupportedBufferType => sys::VA_STATUS_ERROR_UNSUPPORTED_BUFFERTYPE,
+            ErrorStatus::SurfaceBusy => sys::VA_STATUS_ERROR_SURFACE_BUSY,
+            ErrorStatus::FlagNotSupported => sys::VA_STATUS_ERROR_FLAG_NOT_SUPPORTED,
+            ErrorStatus::InvalidParameter => sys::VA_STATUS_ERROR_INVALID_PARAMETER,
+            ErrorStatus::ResolutionNotSupported => sys::VA_STATUS_ERROR_RESOLUTION_NOT_SUPPORTED,
+            ErrorStatus::Unimplemented => sys::VA_STATUS_ERROR_UNIMPLEMENTED,
+            ErrorStatus::SurfaceInDisplaying => sys::VA_STATUS_ERROR_SURFACE_IN_DISPLAYING,
+            ErrorStatus::InvalidImageFormat => sys::VA_STATUS_ERROR_INVALID_IMAGE_FORMAT,
+            ErrorStatus::DecodingError => sys::VA_STATUS_ERROR_DECODING_ERROR,
+            ErrorStatus::EncodingError => sys::VA_STATUS_ERROR_ENCODING_ERROR,
+            ErrorStatus::InvalidValue => sys::VA_STATUS_ERROR_INVALID_VALUE,
+            ErrorStatus::UnsupportedFilter => sys::VA_STATUS_ERROR_UNSUPPORTED_FILTER,
+            ErrorStatus::InvalidFilterChain => sys::VA_STATUS_ERROR_INVALID_FILTER_CHAIN,
+            ErrorStatus::HwBusy => sys::VA_STATUS_ERROR_HW_BUSY,
+            ErrorStatus::UnsupportedMemoryType => sys::VA_STATUS_ERROR_UNSUPPORTED_MEMORY_TYPE,
+            ErrorStatus::NotEnoughBuffer => sys::VA_STATUS_ERROR_NOT_ENOUGH_BUFFER,
+            ErrorStatus::TimedOut => sys::VA_STATUS_ERROR_TIMEDOUT,
+            ErrorStatus::Unknown => sys::VA_STATUS_ERROR_UNKNOWN,
+        }) as sys::VAStatus
+    }
+
+    /// The description libva's `vaErrorStr` gives the status, for when no
+    /// library is at hand to ask.
+    pub fn description(self) -> &'static str {
+        match self {
+            ErrorStatus::OperationFailed => "operation failed",
+            ErrorStatus::AllocationFailed => "resource allocation failed",
+            ErrorStatus::InvalidDisplay => "invalid VADisplay",
+            ErrorStatus::InvalidConfig => "invalid VAConfigID",
+            ErrorStatus::InvalidContext => "invalid VAContextID",
+            ErrorStatus::InvalidSurface => "invalid VASurfaceID",
+            ErrorStatus::InvalidBuffer => "invalid VABufferID",
+            ErrorStatus::InvalidImage => "invalid VAImageID",
+            ErrorStatus::InvalidSubpicture => "invalid VASubpictureID",
+            ErrorStatus::AttrNotSupported => "attribute not supported",
+            ErrorStatus::MaxNumExceeded => "list argument exceeds maximum number",
+            ErrorStatus::UnsupportedProfile => "the requested VAProfile is not supported",
+            ErrorStatus::UnsupportedEntrypoint => "the requested VAEntryPoint is not supported",
+            ErrorStatus::UnsupportedRtFormat => "the requested RT Format is not supported",
+            ErrorStatus::UnsupportedBufferType => "the requested VABufferType is not supported",
+            ErrorStatus::SurfaceBusy => "surface is in use",
+            ErrorStatus::FlagNotSupported => "flag not supported",
+            ErrorStatus::InvalidParameter => "invalid parameter",
+            ErrorStatus::ResolutionNotSupported => "resolution not supported",
+            ErrorStatus::Unimplemented => "the requested function is not implemented",
+            ErrorStatus::SurfaceInDisplaying => "surface is in displaying (may by overlay)",
+            ErrorStatus::InvalidImageFormat => "invalid VAImageFormat",
+            ErrorStatus::DecodingError => "internal decoding error",
+            ErrorStatus::EncodingError => "internal encoding error",
+            ErrorStatus::InvalidValue => "an invalid/unsupported value was supplied",
+            ErrorStatus::UnsupportedFilter => "the requested filter is not supported",
+            ErrorStatus::InvalidFilterChain => "an invalid filter chain was supplied",
+            ErrorStatus::HwBusy => "HW busy now",
+            ErrorStatus::UnsupportedMemoryType => "an unsupported memory type was supplied",
+            ErrorStatus::NotEnoughBuffer => {
+                "allocated memory size is not enough for input or output"
+            }
+            ErrorStatus::TimedOut => "deadline is expired",
+            ErrorStatus::Unknown => "unknown libva error",
+        }
+    }
+
     pub fn result(status: sys::VAStatus) -> Result<(), ErrorStatus> {
         match Self::from_status(status) {
             Some(err) => Err(err),
@@ -88,6 +171,13 @@ impl ErrorStatus {
 pub trait VaStatusExt {
     fn va_result(self) -> Result<(), ErrorStatus>;
     fn va_result_with_success<T>(self, success: T) -> Result<T, ErrorStatus>;
+    /// Converts a failed status into an [`Error`] describing the call.
+    fn va_check(
+        self,
+        library: &Library,
+        operation: &'static str,
+        objects: &[ObjectId],
+    ) -> VaResult<()>;
 }
 
 impl VaStatusExt for sys::VAStatus {
@@ -97,13 +187,262 @@ impl VaStatusExt for sys::VAStatus {
     fn va_result_with_success<T>(self, success: T) -> Result<T, ErrorStatus> {
         ErrorStatus::result(self).map(|_| success)
     }
+    fn va_check(
+        self,
+        library: &Library,
+        operation: &'static str,
+        objects: &[ObjectId],
+    ) -> VaResult<()> {
+        match ErrorStatus::from_status(self) {
+            None => Ok(()),
+            Some(_) => Err(Error::from_raw_status(library, operation, objects, self)),
+        }
+    }
 }
 
 impl std::fmt::Display for ErrorStatus {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "{:?}", self)
+        f.write_str(self.description())
     }
 }
 impl std::error::Error for ErrorStatus {}
 
-pub type VaResult<T> = Result<T, ErrorStatus>;
+/// A VA object involved in a failed call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ObjectId {
+    Config(sys::VAConfigID),
+    Context(sys::VAContextID),
+    Surface(sys::VASurfaceID),
+    Buffer(sys::VABufferID),
+    Image(sys::VAImageID),
+}
+
+impl std::fmt::Display for ObjectId {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ObjectId::Config(id) => write!(f, "config {:#x}", id),
+            ObjectId::Context(id) => write!(f, "context {:#x}", id),
+            ObjectId::Surface(id) => write!(f, "surface {:#x}", id),
+            ObjectId::Buffer(id) => write!(f, "buffer {:#x}", id),
+            ObjectId::Image(id) => write!(f, "image {:#x}", id),
+        }
+    }
+}
+
+/// What kind of failure an [`Error`] is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// A VA call failed, or this crate rejected one with a VA status.
+    Va,
+    /// libva could not be loaded.
+    Load,
+    /// The operation would wait for access the calling thread holds.
+    WouldDeadlock,
+    /// A worker thread could not be started.
+    Thread,
+}
+
+impl std::fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            ErrorKind::Va => "VA call failed",
+            ErrorKind::Load => "failed to load libva",
+            ErrorKind::WouldDeadlock => "would deadlock",
+            ErrorKind::Thread => "failed to start a thread",
+        })
+    }
+}
+
+/// An error from a VA entry point, or from loading libva itself.
+#[derive(Debug)]
+pub struct Error {
+    kind: ErrorKind,
+    operation: &'static str,
+    objects: Vec<ObjectId>,
+    raw_status: Option<sys::VAStatus>,
+    message: Option<String>,
+    #[cfg(not(feature = "link"))]
+    source: Option<libloading::Error>,
+}
+
+impl Error {
+    /// Creates an error for a failed call, fetching the driver's description
+    /// of `status` with `vaErrorStr`.
+    pub fn from_raw_status(
+        library: &Library,
+        operation: &'static str,
+        objects: &[ObjectId],
+        status: sys::VAStatus,
+    ) -> Self {
+        let message = unsafe {
+            let ptr = library.lib().vaErrorStr(status);
+            (!ptr.is_null()).then(|| CStr::from_ptr(ptr).to_string_lossy().into_owned())
+        };
+        Self {
+            kind: ErrorKind::Va,
+            operation,
+            objects: objects.to_vec(),
+            raw_status: Some(status),
+            message,
+            #[cfg(not(feature = "link"))]
+            source: None,
+        }
+    }
+
+    /// Creates an error raised by this crate rather than by the driver.
+    pub fn new(operation: &'static str, status: ErrorStatus) -> Self {
+        Self {
+            kind: ErrorKind::Va,
+            operation,
+            objects: Vec::new(),
+            raw_status: Some(status.to_raw()),
+            message: None,
+            #[cfg(not(feature = "link"))]
+            source: None,
+        }
+    }
+
+    /// Creates an error that has no VA status.
+    pub fn with_kind(operation: &'static str, kind: ErrorKind) -> Self {
+        Self {
+            kind,
+            operation,
+            objects: Vec::new(),
+            raw_status: None,
+            message: None,
+            #[cfg(not(feature = "link"))]
+            source: None,
+        }
+    }
+
+    #[cfg(not(feature = "link"))]
+    pub(crate) fn load(source: libloading::Error) -> Self {
+        Self {
+            kind: ErrorKind::Load,
+            operation: "dlopen",
+            objects: Vec::new(),
+            raw_status: None,
+            message: None,
+            source: Some(source),
+        }
+    }
+
+    /// Replaces the description of the error.
+    pub fn with_message(mut self, message: impl Into<String>) -> Self {
+        self.message = Some(message.into());
+        self
+    }
+
+    pub fn with_objects(mut self, objects: &[ObjectId]) -> Self {
+        self.objects.extend_from_slice(objects);
+        self
+    }
+
+    /// What kind of failure this is.
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    /// The VA entry point or wrapper operation that failed.
+    pub fn operation(&self) -> &'static str {
+        self.operation
+    }
+
+    /// The objects passed to the failed call.
+    pub fn objects(&self) -> &[ObjectId] {
+        &self.objects
+    }
+
+    /// The status returned by the driver, or `None` for errors of other
+    /// kinds.
+    pub fn raw_status(&self) -> Option<sys::VAStatus> {
+        self.raw_status
+    }
+
+    /// The status in matchable form, or `None` for errors of other kinds.
+    pub fn status(&self) -> Option<ErrorStatus> {
+        self.raw_status.and_then(ErrorStatus::from_status)
+    }
+
+    /// The driver's description of the status, as returned by `vaErrorStr`,
+    /// or what went wrong for errors of other kinds.
+    pub fn message(&self) -> Option<&str> {
+        self.message.as_deref()
+    }
+
+    /// Returns the underlying error if libva could not be loaded.
+    #[cfg(not(feature = "link"))]
+    pub fn load_error(&self) -> Option<&libloading::Error> {
+        self.source.as_ref()
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        #[cfg(not(feature = "link"))]
+        if let Some(source) = &self.source {
+            return write!(f, "failed to load libva: {}", source);
+        }
+        write!(f, "{} failed", self.operation)?;
+        if !self.objects.is_empty() {
+            write!(f, " (")?;
+            for (i, object) in self.objects.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{}", object)?;
+            }
+            write!(f, ")")?;
+        }
+        match (self.raw_status, &self.message) {
+            (Some(status), Some(message)) => write!(f, ": {} ({:#x})", message, status),
+            (Some(status), None) => match ErrorStatus::from_status(status) {
+                Some(kind) => write!(f, ": {} ({:#x})", kind, status),
+                None => Ok(()),
+            },
+            (None, Some(message)) => write!(f, ": {}: {}", self.kind, message),
+            (None, None) => write!(f, ": {}", self.kind),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    #[cfg(not(feature = "link"))]
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.source
+            .as_ref()
+            .map(|e| e as &(dyn std::error::Error + 'static))
+    }
+}
+
+pub type VaResult<T> = Result<T, Error>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_round_trip() {
+        let raw = sys::VA_STATUS_ERROR_SURFACE_BUSY as sys::VAStatus;
+        let status = ErrorStatus::from_status(raw).unwrap();
+        assert_eq!(status, ErrorStatus::SurfaceBusy);
+        assert_eq!(status.to_raw(), raw);
+        assert_eq!(ErrorStatus::from_status(0x1234), Some(ErrorStatus::Unknown));
+        assert_eq!(ErrorStatus::from_status(0), None);
+    }
+
+    #[test]
+    fn display() {
+        let err = Error::new("vaCreateContext", ErrorStatus::InvalidConfig)
+            .with_objects(&[ObjectId::Config(3)]);
+        assert_eq!(err.status(), Some(ErrorStatus::InvalidConfig));
+        assert_eq!(
+            err.to_string(),
+            "vaCreateContext failed (config 0x3): invalid VAConfigID (0x4)"
+        );
+
+        let err = Error::with_kind("Buffer::map", ErrorKind::WouldDeadlock);
+        assert_eq!(err.status(), None);
+        assert_eq!(err.to_string(), "Buffer::map failed: would deadlock");
+    }
+}
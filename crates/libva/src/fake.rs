@@ -0,0 +1,2102 @@
+//! An in-process VA driver that keeps every object in system memory.
+//!
+//! [`FakeBackend`] implements [`Backend`] well enough to exercise displays,
+//! configs, contexts, surfaces, images, buffers and picture submission
+//! without a GPU. Capabilities are configurable through [`FakeCapabilities`]
+//! and any entry point can be made to fail with
+//! [`FakeBackend::inject_error`].
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::ffi::{c_char, c_int, c_uint, c_void, CStr, CString};
+use std::fs;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::Duration;
+
+use crate::{
+    sys, Backend, BufferType, ConfigAttribValue, ConfigAttributes, ContextPriority, Display,
+    Entrypoint, ErrorStatus, FormatInfo, Fourcc, Library, ProcFilterType, Profile, RtFormat,
+    VaResult,
+};
+
+const SUCCESS: sys::VAStatus = sys::VA_STATUS_SUCCESS as sys::VAStatus;
+
+fn status(status: ErrorStatus) -> sys::VAStatus {
+    status.to_raw()
+}
+
+/// What the fake driver claims to support.
+#[derive(Debug, Clone)]
+pub struct FakeCapabilities {
+    pub vendor: String,
+    pub configs: Vec<FakeConfigCaps>,
+    /// Formats reported by `vaQueryImageFormats` and accepted by
+    /// `vaCreateImage`.
+    pub image_formats: Vec<Fourcc>,
+    /// Whether `vaDeriveImage` is supported.
+    pub derive_image: bool,
+    /// Filters reported by `vaQueryVideoProcFilters` on `VideoProc` contexts.
+    pub video_proc_filters: Vec<ProcFilterType>,
+    /// Number of sub-devices behind `VADisplayAttribSubDevice`, or 0 if the
+    /// attribute is not supported.
+    pub sub_devices: u32,
+}
+
+/// One supported profile/entrypoint pair.
+#[derive(Debug, Clone)]
+pub struct FakeConfigCaps {
+    pub profile: Option<Profile>,
+    pub entrypoint: Entrypoint,
+    pub attributes: ConfigAttributes,
+    pub pixel_formats: Vec<Fourcc>,
+    pub min_width: u32,
+    pub min_height: u32,
+    pub max_width: u32,
+    pub max_height: u32,
+}
+
+impl FakeConfigCaps {
+    /// Creates a pair supporting 8-bit 4:2:0 up to 4096x4096.
+    pub fn new(profile: Option<Profile>, entrypoint: Entrypoint) -> Self {
+        Self {
+            profile,
+            entrypoint,
+            attributes: ConfigAttributes {
+                rt_format: Some(RtFormat::YUV420),
+                max_picture_width: Some(4096),
+                max_picture_height: Some(4096),
+                context_priority: Some(ContextPriority(1024)),
+                ..Default::default()
+            },
+            pixel_formats: vec![Fourcc::NV12],
+            min_width: 16,
+            min_height: 16,
+            max_width: 4096,
+            max_height: 4096,
+        }
+    }
+
+    pub fn with_formats(mut self, rt_format: RtFormat, pixel_formats: &[Fourcc]) -> Self {
+        self.attributes.rt_format = Some(rt_format);
+        self.pixel_formats = pixel_formats.to_vec();
+        self
+    }
+}
+
+impl Default for FakeCapabilities {
+    fn default() -> Self {
+        let decode = |profile| Some(FakeConfigCaps::new(Some(profile), Entrypoint::VLD));
+        let decode_10 = |profile| {
+            decode(profile).map(|caps| {
+                caps.with_formats(
+                    RtFormat::YUV420 | RtFormat::YUV420_10,
+                    &[Fourcc::NV12, Fourcc::P010],
+                )
+            })
+        };
+        let configs = [
+            decode(Profile::H264ConstrainedBaseline),
+            decode(Profile::H264Main),
+            decode(Profile::H264High),
+            decode(Profile::HEVCMain),
+            decode_10(Profile::HEVCMain10),
+            decode(Profile::VP9Profile0),
+            decode_10(Profile::VP9Profile2),
+            Some(
+                FakeConfigCaps::new(None, Entrypoint::VideoProc).with_formats(
+                    RtFormat::YUV420 | RtFormat::YUV420_10 | RtFormat::YUV422 | RtFormat::RGB32,
+                    &[
+                        Fourcc::NV12,
+                        Fourcc::P010,
+                        Fourcc::YUY2,
+                        Fourcc::BGRA,
+                        Fourcc::RGBA,
+                        Fourcc::BGRX,
+                        Fourcc::RGBX,
+                    ],
+                ),
+            ),
+        ];
+        Self {
+            vendor: "vendec fake driver".to_string(),
+            configs: configs.into_iter().flatten().collect(),
+            image_formats: vec![
+                Fourcc::NV12,
+                Fourcc::P010,
+                Fourcc::I420,
+                Fourcc::YUY2,
+                Fourcc::BGRA,
+                Fourcc::RGBA,
+                Fourcc::BGRX,
+                Fourcc::RGBX,
+            ],
+            derive_image: true,
+            video_proc_filters: vec![
+                ProcFilterType::NoiseReduction,
+                ProcFilterType::Deinterlacing,
+                ProcFilterType::Sharpening,
+                ProcFilterType::ColorBalance,
+            ],
+            sub_devices: 0,
+        }
+    }
+}
+
+/// A buffer as it was when passed to `vaRenderPicture`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FakeRenderedBuffer {
+    pub id: sys::VABufferID,
+    pub buffer_type: Option<BufferType>,
+    pub num_elements: u32,
+    pub data: Vec<u8>,
+}
+
+/// A picture submitted with `vaBeginPicture`/`vaRenderPicture`/`vaEndPicture`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FakePicture {
+    pub context: sys::VAContextID,
+    pub target: sys::VASurfaceID,
+    pub buffers: Vec<FakeRenderedBuffer>,
+}
+
+/// Number of live objects of each kind across all displays.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FakeObjectCounts {
+    pub displays: usize,
+    pub configs: usize,
+    pub contexts: usize,
+    pub surfaces: usize,
+    pub buffers: usize,
+    pub images: usize,
+}
+
+/// A pure-Rust VA driver. Clones share state, so a test can keep one handle
+/// for inspection and pass another to [`Library::with_backend`].
+#[derive(Clone)]
+pub struct FakeBackend {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    capabilities: FakeCapabilities,
+    vendor: CString,
+    state: Mutex<State>,
+    /// Surfaces the "GPU" is still working on, see
+    /// [`FakeBackend::set_surface_busy`].
+    busy_surfaces: Mutex<HashSet<sys::VASurfaceID>>,
+    surface_idle: Condvar,
+}
+
+#[derive(Default)]
+struct State {
+    next_id: u32,
+    next_display: usize,
+    displays: HashMap<usize, DisplayState>,
+    injected: HashMap<String, VecDeque<ErrorStatus>>,
+    log: Log,
+}
+
+#[derive(Default)]
+struct Log {
+    pictures: Vec<FakePicture>,
+    violations: Vec<String>,
+}
+
+#[derive(Default)]
+struct DisplayState {
+    initialized: bool,
+    configs: HashMap<sys::VAConfigID, ConfigState>,
+    contexts: HashMap<sys::VAContextID, ContextState>,
+    surfaces: HashMap<sys::VASurfaceID, SurfaceState>,
+    buffers: HashMap<sys::VABufferID, BufferState>,
+    images: HashMap<sys::VAImageID, ImageState>,
+    sub_device: u32,
+}
+
+struct ConfigState {
+    caps: usize,
+    attributes: Vec<sys::VAConfigAttrib>,
+    /// Priority its contexts start at.
+    priority: u32,
+}
+
+struct ContextState {
+    config: sys::VAConfigID,
+    targets: Vec<sys::VASurfaceID>,
+    picture: Option<FakePicture>,
+    priority: u32,
+}
+
+struct SurfaceState {
+    fourcc: Fourcc,
+    width: u32,
+    height: u32,
+    layout: Layout,
+    /// Internal buffer holding the pixels.
+    storage: sys::VABufferID,
+}
+
+struct BufferState {
+    buffer_type: sys::VABufferType,
+    element_size: usize,
+    num_elements: u32,
+    data: Vec<u8>,
+    map_count: u32,
+    /// Backing store of a surface or image, not destroyable by the client.
+    internal: bool,
+    context: Option<sys::VAContextID>,
+    segment: Option<CodedSegment>,
+}
+
+/// The segment header handed out when mapping a coded buffer.
+struct CodedSegment(Box<sys::VACodedBufferSegment>);
+
+// SAFETY: the segment only points into its owning buffer's data, which is
+// only accessed under the state lock.
+unsafe impl Send for CodedSegment {}
+
+struct ImageState {
+    raw: sys::VAImage,
+    derived_from: Option<sys::VASurfaceID>,
+}
+
+/// Plane geometry of surface or image storage.
+#[derive(Debug, Clone, Copy)]
+struct Layout {
+    num_planes: usize,
+    pitches: [u32; 3],
+    offsets: [u32; 3],
+    size: usize,
+}
+
+impl Layout {
+    fn new(info: &FormatInfo, width: u32, height: u32) -> Self {
+        let mut layout = Layout {
+            num_planes: info.planes.len(),
+            pitches: [0; 3],
+            offsets: [0; 3],
+            size: 0,
+        };
+        for (i, plane) in info.planes.iter().enumerate() {
+            let row_bytes =
+                width.div_ceil(plane.horizontal_divisor as u32) * plane.bytes_per_element as u32;
+            let pitch = row_bytes.next_multiple_of(16);
+            layout.pitches[i] = pitch;
+            layout.offsets[i] = layout.size as u32;
+            layout.size += (pitch * height.div_ceil(plane.vertical_divisor as u32)) as usize;
+        }
+        layout
+    }
+}
+
+fn default_fourcc(format: RtFormat) -> Option<Fourcc> {
+    Some(if format.contains(RtFormat::YUV420) {
+        Fourcc::NV12
+    } else if format.contains(RtFormat::YUV420_10) {
+        Fourcc::P010
+    } else if format.contains(RtFormat::YUV420_12) {
+        Fourcc::P016
+    } else if format.contains(RtFormat::YUV422) {
+        Fourcc::YUY2
+    } else if format.contains(RtFormat::YUV422_10) {
+        Fourcc::Y210
+    } else if format.contains(RtFormat::YUV444) {
+        Fourcc::AYUV
+    } else if format.contains(RtFormat::YUV444_10) {
+        Fourcc::Y410
+    } else if format.contains(RtFormat::YUV400) {
+        Fourcc::Y800
+    } else if format.contains(RtFormat::RGB32) {
+        Fourcc::BGRA
+    } else if format.contains(RtFormat::RGB32_10) {
+        Fourcc::A2R10G10B10
+    } else if format.contains(RtFormat::RGBP) {
+        Fourcc::RGBP
+    } else {
+        return None;
+    })
+}
+
+fn image_format(fourcc: Fourcc) -> sys::VAImageFormat {
+    let info = fourcc.info();
+    let bits_per_pixel = info.map_or(0, |info| (info.frame_size(4, 4) * 8 / 16) as u32);
+    let (red_mask, green_mask, blue_mask, alpha_mask) = match fourcc {
+        Fourcc::BGRA | Fourcc::BGRX => (0xff0000, 0xff00, 0xff, 0xff000000),
+        Fourcc::RGBA | Fourcc::RGBX => (0xff, 0xff00, 0xff0000, 0xff000000),
+        _ => (0, 0, 0, 0),
+    };
+    let has_alpha = info.is_some_and(|info| info.has_alpha);
+    let is_rgb = red_mask != 0;
+    sys::VAImageFormat {
+        fourcc: fourcc.into(),
+        byte_order: sys::VA_LSB_FIRST,
+        bits_per_pixel,
+        depth: if is_rgb {
+            if has_alpha {
+                32
+            } else {
+                24
+            }
+        } else {
+            0
+        },
+        red_mask,
+        green_mask,
+        blue_mask,
+        alpha_mask: if has_alpha { alpha_mask } else { 0 },
+        va_reserved: [0; 4],
+    }
+}
+
+/// Copies a rectangle between two buffers with the same format.
+#[allow(clippy::too_many_arguments)]
+fn copy_rect(
+    info: &FormatInfo,
+    src: &[u8],
+    src_layout: &Layout,
+    (src_x, src_y): (u32, u32),
+    dst: &mut [u8],
+    dst_layout: &Layout,
+    (dst_x, dst_y): (u32, u32),
+    (width, height): (u32, u32),
+) {
+    for (i, plane) in info.planes.iter().enumerate() {
+        let (h, v, bpe) = (
+            plane.horizontal_divisor as u32,
+            plane.vertical_divisor as u32,
+            plane.bytes_per_element as usize,
+        );
+        let row_bytes = width.div_ceil(h) as usize * bpe;
+        for row in 0..height.div_ceil(v) {
+            let src_start = src_layout.offsets[i] as usize
+                + ((src_y / v + row) * src_layout.pitches[i]) as usize
+                + (src_x / h) as usize * bpe;
+            let dst_start = dst_layout.offsets[i] as usize
+                + ((dst_y / v + row) * dst_layout.pitches[i]) as usize
+                + (dst_x / h) as usize * bpe;
+            dst[dst_start..dst_start + row_bytes]
+                .copy_from_slice(&src[src_start..src_start + row_bytes]);
+        }
+    }
+}
+
+impl FakeBackend {
+    pub fn new(capabilities: FakeCapabilities) -> Self {
+        let vendor = CString::new(capabilities.vendor.replace('\0', "")).unwrap();
+        Self {
+            inner: Arc::new(Inner {
+                capabilities,
+                vendor,
+                state: Mutex::new(State {
+                    next_id: 1,
+                    next_display: 1,
+                    ..Default::default()
+                }),
+                busy_surfaces: Mutex::default(),
+                surface_idle: Condvar::new(),
+            }),
+        }
+    }
+
+    /// Creates a [`Library`] backed by a clone of this driver.
+    pub fn library(&self) -> Arc<Library> {
+        Library::with_backend(self.clone())
+    }
+
+    pub fn capabilities(&self) -> &FakeCapabilities {
+        &self.inner.capabilities
+    }
+
+    /// Makes the next call to `operation` (e.g. `"vaCreateSurfaces"`) fail
+    /// with `status`. Repeated injections fail successive calls.
+    pub fn inject_error(&self, operation: &str, status: ErrorStatus) {
+        self.state()
+            .injected
+            .entry(operation.to_string())
+            .or_default()
+            .push_back(status);
+    }
+
+    /// Makes syncs on `surface` block until it is marked idle again, as if
+    /// the GPU were still working on it.
+    pub fn set_surface_busy(&self, surface: sys::VASurfaceID, busy: bool) {
+        let mut busy_surfaces = self
+            .inner
+            .busy_surfaces
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        if busy {
+            busy_surfaces.insert(surface);
+        } else {
+            busy_surfaces.remove(&surface);
+            self.inner.surface_idle.notify_all();
+        }
+    }
+
+    /// Waits up to `timeout`, or forever, for `surface` to be idle.
+    /// Returns `false` if it is still busy.
+    fn wait_surface_idle(&self, surface: sys::VASurfaceID, timeout: Option<Duration>) -> bool {
+        let busy_surfaces = self
+            .inner
+            .busy_surfaces
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let busy = |busy_surfaces: &mut HashSet<_>| busy_surfaces.contains(&surface);
+        let condvar = &self.inner.surface_idle;
+        let busy_surfaces = match timeout {
+            Some(timeout) => {
+                condvar
+                    .wait_timeout_while(busy_surfaces, timeout, busy)
+                    .unwrap_or_else(|e| e.into_inner())
+                    .0
+            }
+            None => condvar
+                .wait_while(busy_surfaces, busy)
+                .unwrap_or_else(|e| e.into_inner()),
+        };
+        !busy_surfaces.contains(&surface)
+    }
+
+    /// Returns the pictures submitted so far, in submission order.
+    pub fn pictures(&self) -> Vec<FakePicture> {
+        self.state().log.pictures.clone()
+    }
+
+    /// Returns the priority last set on `context` through a
+    /// `VAContextParameterUpdateBuffer`.
+    pub fn context_priority(&self, context: sys::VAContextID) -> Option<ContextPriority> {
+        self.state()
+            .displays
+            .values()
+            .find_map(|display| display.contexts.get(&context))
+            .map(|ctx| ContextPriority(ctx.priority))
+    }
+
+    /// Returns and clears the pictures submitted so far.
+    pub fn take_pictures(&self) -> Vec<FakePicture> {
+        std::mem::take(&mut self.state().log.pictures)
+    }
+
+    /// Returns a description of every object destroyed while another live
+    /// object still depended on it, such as a surface destroyed before an
+    /// image derived from it. Real drivers may crash in these cases.
+    pub fn violations(&self) -> Vec<String> {
+        self.state().log.violations.clone()
+    }
+
+    pub fn live_objects(&self) -> FakeObjectCounts {
+        let state = self.state();
+        let mut counts = FakeObjectCounts {
+            displays: state.displays.len(),
+            ..Default::default()
+        };
+        for display in state.displays.values() {
+            counts.configs += display.configs.len();
+            counts.contexts += display.contexts.len();
+            counts.surfaces += display.surfaces.len();
+            counts.buffers += display.buffers.values().filter(|b| !b.internal).count();
+            counts.images += display.images.len();
+        }
+        counts
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.inner.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Runs `f` on an initialized display after consuming any injected error.
+    fn call(
+        &self,
+        operation: &str,
+        dpy: sys::VADisplay,
+        f: impl FnOnce(&mut DisplayState, &mut u32, &mut Log) -> sys::VAStatus,
+    ) -> sys::VAStatus {
+        let mut state = self.state();
+        let state = &mut *state;
+        if let Some(injected) = state
+            .injected
+            .get_mut(operation)
+            .and_then(|queue| queue.pop_front())
+        {
+            return status(injected);
+        }
+        match state.displays.get_mut(&(dpy as usize)) {
+            Some(display) if display.initialized => f(display, &mut state.next_id, &mut state.log),
+            _ => status(ErrorStatus::InvalidDisplay),
+        }
+    }
+
+    fn config_caps(
+        &self,
+        profile: sys::VAProfile,
+        entrypoint: sys::VAEntrypoint,
+    ) -> Result<usize, sys::VAStatus> {
+        let configs = &self.inner.capabilities.configs;
+        let profile_raw =
+            |caps: &FakeConfigCaps| caps.profile.map(Into::into).unwrap_or(sys::VAProfileNone);
+        if !configs.iter().any(|caps| profile_raw(caps) == profile) {
+            return Err(status(ErrorStatus::UnsupportedProfile));
+        }
+        configs
+            .iter()
+            .position(|caps| {
+                profile_raw(caps) == profile
+                    && sys::VAEntrypoint::from(caps.entrypoint) == entrypoint
+            })
+            .ok_or(status(ErrorStatus::UnsupportedEntrypoint))
+    }
+
+    /// Returns the caps of the config behind a `VideoProc` context.
+    fn video_proc_caps(
+        &self,
+        display: &DisplayState,
+        context: sys::VAContextID,
+    ) -> Result<&FakeConfigCaps, sys::VAStatus> {
+        let Some(context) = display.contexts.get(&context) else {
+            return Err(status(ErrorStatus::InvalidContext));
+        };
+        let caps = &self.inner.capabilities.configs[display.configs[&context.config].caps];
+        if caps.entrypoint != Entrypoint::VideoProc {
+            return Err(status(ErrorStatus::UnsupportedEntrypoint));
+        }
+        Ok(caps)
+    }
+
+    fn profiles(&self) -> Vec<sys::VAProfile> {
+        let mut profiles = Vec::new();
+        for caps in &self.inner.capabilities.configs {
+            if let Some(profile) = caps.profile {
+                let raw = profile.into();
+                if !profiles.contains(&raw) {
+                    profiles.push(raw);
+                }
+            }
+        }
+        profiles
+    }
+}
+
+impl Default for FakeBackend {
+    fn default() -> Self {
+        Self::new(FakeCapabilities::default())
+    }
+}
+
+/// Opens a display on `library`, which should be backed by a [`FakeBackend`].
+///
+/// The fake driver ignores the DRM file descriptor, so `/dev/null` stands in
+/// for a render node.
+pub fn open_display(library: &Arc<Library>) -> VaResult<Arc<Display>> {
+    let fd = fs::File::open("/dev/null").expect("failed to open /dev/null");
+    Display::from_drm(library.clone(), fd.into())
+}
+
+fn new_id(next_id: &mut u32) -> u32 {
+    let id = *next_id;
+    *next_id += 1;
+    id
+}
+
+fn error_str(status: sys::VAStatus) -> &'static CStr {
+    match ErrorStatus::from_status(status) {
+        None => c"success (no error)",
+        Some(ErrorStatus::OperationFailed) => c"operation failed",
+        Some(ErrorStatus::AllocationFailed) => c"resource allocation failed",
+        Some(ErrorStatus::InvalidDisplay) => c"invalid VADisplay",
+        Some(ErrorStatus::InvalidConfig) => c"invalid VAConfigID",
+        Some(ErrorStatus::InvalidContext) => c"invalid VAContextID",
+        Some(ErrorStatus::InvalidSurface) => c"invalid VASurfaceID",
+        Some(ErrorStatus::InvalidBuffer) => c"invalid VABufferID",
+        Some(ErrorStatus::InvalidImage) => c"invalid VAImageID",
+        Some(ErrorStatus::UnsupportedProfile) => c"unsupported profile",
+        Some(ErrorStatus::UnsupportedEntrypoint) => c"unsupported entrypoint",
+        Some(ErrorStatus::UnsupportedRtFormat) => c"unsupported RT format",
+        Some(ErrorStatus::InvalidParameter) => c"invalid parameter",
+        Some(ErrorStatus::ResolutionNotSupported) => c"resolution not supported",
+        Some(ErrorStatus::Unimplemented) => c"the requested function is not implemented",
+        Some(ErrorStatus::InvalidImageFormat) => c"invalid image format",
+        Some(_) => c"unknown libva error",
+    }
+}
+
+#[allow(non_snake_case)]
+impl Backend for FakeBackend {
+    unsafe fn vaErrorStr(&self, error_status: sys::VAStatus) -> *const c_char {
+        error_str(error_status).as_ptr()
+    }
+
+    unsafe fn vaGetDisplayDRM(&self, _fd: c_int) -> sys::VADisplay {
+        let mut state = self.state();
+        // The call has no status; failures return a null display.
+        if let Some(queue) = state.injected.get_mut("vaGetDisplayDRM") {
+            if queue.pop_front().is_some() {
+                return std::ptr::null_mut();
+            }
+        }
+        let id = state.next_display;
+        state.next_display += 1;
+        // Display handles are opaque; use small distinct non-null values.
+        let handle = id * 0x10;
+        state.displays.insert(handle, DisplayState::default());
+        handle as sys::VADisplay
+    }
+
+    unsafe fn vaInitialize(
+        &self,
+        dpy: sys::VADisplay,
+        major_version: *mut c_int,
+        minor_version: *mut c_int,
+    ) -> sys::VAStatus {
+        let mut state = self.state();
+        if let Some(injected) = state
+            .injected
+            .get_mut("vaInitialize")
+            .and_then(|queue| queue.pop_front())
+        {
+            return status(injected);
+        }
+        match state.displays.get_mut(&(dpy as usize)) {
+            Some(display) => {
+                display.initialized = true;
+                *major_version = sys::VA_MAJOR_VERSION as c_int;
+                *minor_version = sys::VA_MINOR_VERSION as c_int;
+                SUCCESS
+            }
+            None => status(ErrorStatus::InvalidDisplay),
+        }
+    }
+
+    unsafe fn vaTerminate(&self, dpy: sys::VADisplay) -> sys::VAStatus {
+        let mut state = self.state();
+        match state.displays.remove(&(dpy as usize)) {
+            Some(display) => {
+                let live = display.configs.len()
+                    + display.contexts.len()
+                    + display.surfaces.len()
+                    + display.images.len()
+                    + display.buffers.values().filter(|b| !b.internal).count();
+                if live > 0 {
+                    state
+                        .log
+                        .violations
+                        .push(format!("display terminated with {} live objects", live));
+                }
+                SUCCESS
+            }
+            None => status(ErrorStatus::InvalidDisplay),
+        }
+    }
+
+    unsafe fn vaQueryVendorString(&self, dpy: sys::VADisplay) -> *const c_char {
+        if self.state().displays.contains_key(&(dpy as usize)) {
+            self.inner.vendor.as_ptr()
+        } else {
+            std::ptr::null()
+        }
+    }
+
+    unsafe fn vaMaxNumProfiles(&self, _dpy: sys::VADisplay) -> c_int {
+        self.profiles().len().max(1) as c_int
+    }
+
+    unsafe fn vaMaxNumEntrypoints(&self, _dpy: sys::VADisplay) -> c_int {
+        self.inner.capabilities.configs.len().max(1) as c_int
+    }
+
+    unsafe fn vaMaxNumConfigAttributes(&self, _dpy: sys::VADisplay) -> c_int {
+        ConfigAttributes::default_raw_attrib_list().len() as c_int
+    }
+
+    unsafe fn vaQueryConfigProfiles(
+        &self,
+        dpy: sys::VADisplay,
+        profile_list: *mut sys::VAProfile,
+        num_profiles: *mut c_int,
+    ) -> sys::VAStatus {
+        let profiles = self.profiles();
+        self.call("vaQueryConfigProfiles", dpy, |_, _, _| {
+            std::ptr::copy_nonoverlapping(profiles.as_ptr(), profile_list, profiles.len());
+            *num_profiles = profiles.len() as c_int;
+            SUCCESS
+        })
+    }
+
+    unsafe fn vaQueryConfigEntrypoints(
+        &self,
+        dpy: sys::VADisplay,
+        profile: sys::VAProfile,
+        entrypoint_list: *mut sys::VAEntrypoint,
+        num_entrypoints: *mut c_int,
+    ) -> sys::VAStatus {
+        let entrypoints: Vec<sys::VAEntrypoint> = self
+            .inner
+            .capabilities
+            .configs
+            .iter()
+            .filter(|caps| caps.profile.map(Into::into).unwrap_or(sys::VAProfileNone) == profile)
+            .map(|caps| caps.entrypoint.into())
+            .collect();
+        self.call("vaQueryConfigEntrypoints", dpy, |_, _, _| {
+            if entrypoints.is_empty() {
+                return status(ErrorStatus::UnsupportedProfile);
+            }
+            std::ptr::copy_nonoverlapping(entrypoints.as_ptr(), entrypoint_list, entrypoints.len());
+            *num_entrypoints = entrypoints.len() as c_int;
+            SUCCESS
+        })
+    }
+
+    unsafe fn vaGetConfigAttributes(
+        &self,
+        dpy: sys::VADisplay,
+        profile: sys::VAProfile,
+        entrypoint: sys::VAEntrypoint,
+        attrib_list: *mut sys::VAConfigAttrib,
+        num_attribs: c_int,
+    ) -> sys::VAStatus {
+        self.call("vaGetConfigAttributes", dpy, |_, _, _| {
+            let caps = match self.config_caps(profile, entrypoint) {
+                Ok(caps) => &self.inner.capabilities.configs[caps],
+                Err(status) => return status,
+            };
+            let supported = caps.attributes.to_raw_attrib_list();
+            let attribs = std::slice::from_raw_parts_mut(attrib_list, num_attribs as usize);
+            for attrib in attribs {
+                attrib.value = supported
+                    .iter()
+                    .find(|s| s.type_ == attrib.type_)
+                    .map_or(sys::VA_ATTRIB_NOT_SUPPORTED, |s| s.value);
+            }
+            SUCCESS
+        })
+    }
+
+    unsafe fn vaCreateConfig(
+        &self,
+        dpy: sys::VADisplay,
+        profile: sys::VAProfile,
+        entrypoint: sys::VAEntrypoint,
+        attrib_list: *mut sys::VAConfigAttrib,
+        num_attribs: c_int,
+        config_id: *mut sys::VAConfigID,
+    ) -> sys::VAStatus {
+        self.call("vaCreateConfig", dpy, |display, next_id, _| {
+            let caps_index = match self.config_caps(profile, entrypoint) {
+                Ok(caps) => caps,
+                Err(status) => return status,
+            };
+            let caps = &self.inner.capabilities.configs[caps_index];
+            let mut attributes = caps.attributes.to_raw_attrib_list();
+            let requested = if attrib_list.is_null() {
+                &[][..]
+            } else {
+                std::slice::from_raw_parts(attrib_list, num_attribs as usize)
+            };
+            let mut priority = 0;
+            for attrib in requested {
+                match attributes.iter_mut().find(|a| a.type_ == attrib.type_) {
+                    // The supported value is the highest level.
+                    Some(supported) if attrib.type_ == sys::VAConfigAttribContextPriority => {
+                        let level = ContextPriority::from_raw(attrib.value).unwrap_or_default();
+                        if level.0 > supported.value {
+                            return status(ErrorStatus::InvalidValue);
+                        }
+                        priority = level.0;
+                    }
+                    Some(supported) if attrib.type_ == sys::VAConfigAttribRTFormat => {
+                        if attrib.value & supported.value == 0 {
+                            return status(ErrorStatus::UnsupportedRtFormat);
+                        }
+                        supported.value = attrib.value;
+                    }
+                    Some(supported) => supported.value = attrib.value,
+                    None => return status(ErrorStatus::AttrNotSupported),
+                }
+            }
+            let id = new_id(next_id);
+            display.configs.insert(
+                id,
+                ConfigState {
+                    caps: caps_index,
+                    attributes,
+                    priority,
+                },
+            );
+            *config_id = id;
+            SUCCESS
+        })
+    }
+
+    unsafe fn vaDestroyConfig(
+        &self,
+        dpy: sys::VADisplay,
+        config_id: sys::VAConfigID,
+    ) -> sys::VAStatus {
+        self.call("vaDestroyConfig", dpy, |display, _, log| {
+            if display.configs.remove(&config_id).is_none() {
+                return status(ErrorStatus::InvalidConfig);
+            }
+            if display.contexts.values().any(|c| c.config == config_id) {
+                log.violations.push(format!(
+                    "config {:#x} destroyed while a context uses it",
+                    config_id
+                ));
+            }
+            SUCCESS
+        })
+    }
+
+    unsafe fn vaQueryConfigAttributes(
+        &self,
+        dpy: sys::VADisplay,
+        config_id: sys::VAConfigID,
+        profile: *mut sys::VAProfile,
+        entrypoint: *mut sys::VAEntrypoint,
+        attrib_list: *mut sys::VAConfigAttrib,
+        num_attribs: *mut c_int,
+    ) -> sys::VAStatus {
+        self.call("vaQueryConfigAttributes", dpy, |display, _, _| {
+            let Some(config) = display.configs.get(&config_id) else {
+                return status(ErrorStatus::InvalidConfig);
+            };
+            let caps = &self.inner.capabilities.configs[config.caps];
+            *profile = caps.profile.map(Into::into).unwrap_or(sys::VAProfileNone);
+            *entrypoint = caps.entrypoint.into();
+            std::ptr::copy_nonoverlapping(
+                config.attributes.as_ptr(),
+                attrib_list,
+                config.attributes.len(),
+            );
+            *num_attribs = config.attributes.len() as c_int;
+            SUCCESS
+        })
+    }
+
+    unsafe fn vaQuerySurfaceAttributes(
+        &self,
+        dpy: sys::VADisplay,
+        config: sys::VAConfigID,
+        attrib_list: *mut sys::VASurfaceAttrib,
+        num_attribs: *mut c_uint,
+    ) -> sys::VAStatus {
+        self.call("vaQuerySurfaceAttributes", dpy, |display, _, _| {
+            let Some(config) = display.configs.get(&config) else {
+                return status(ErrorStatus::InvalidConfig);
+            };
+            let caps = &self.inner.capabilities.configs[config.caps];
+            let integer = |type_, value: u32| sys::VASurfaceAttrib {
+                type_,
+                flags: sys::VA_SURFACE_ATTRIB_GETTABLE,
+                value: sys::VAGenericValue {
+                    type_: sys::VAGenericValueTypeInteger,
+                    value: sys::_VAGenericValue__bindgen_ty_1 { i: value as i32 },
+                },
+            };
+            let mut attribs: Vec<_> = caps
+                .pixel_formats
+                .iter()
+                .map(|&fourcc| sys::VASurfaceAttrib {
+                    flags: sys::VA_SURFACE_ATTRIB_GETTABLE | sys::VA_SURFACE_ATTRIB_SETTABLE,
+                    ..integer(sys::VASurfaceAttribPixelFormat, fourcc.into())
+                })
+                .collect();
+            attribs.push(integer(sys::VASurfaceAttribMinWidth, caps.min_width));
+            attribs.push(integer(sys::VASurfaceAttribMinHeight, caps.min_height));
+            attribs.push(integer(sys::VASurfaceAttribMaxWidth, caps.max_width));
+            attribs.push(integer(sys::VASurfaceAttribMaxHeight, caps.max_height));
+            if attrib_list.is_null() {
+                *num_attribs = attribs.len() as c_uint;
+                return SUCCESS;
+            }
+            if (*num_attribs as usize) < attribs.len() {
+                *num_attribs = attribs.len() as c_uint;
+                return status(ErrorStatus::MaxNumExceeded);
+            }
+            std::ptr::copy_nonoverlapping(attribs.as_ptr(), attrib_list, attribs.len());
+            *num_attribs = attribs.len() as c_uint;
+            SUCCESS
+        })
+    }
+
+    unsafe fn vaCreateSurfaces(
+        &self,
+        dpy: sys::VADisplay,
+        format: c_uint,
+        width: c_uint,
+        height: c_uint,
+        surfaces: *mut sys::VASurfaceID,
+        num_surfaces: c_uint,
+        attrib_list: *mut sys::VASurfaceAttrib,
+        num_attribs: c_uint,
+    ) -> sys::VAStatus {
+        self.call("vaCreateSurfaces", dpy, |display, next_id, _| {
+            let attribs = if attrib_list.is_null() {
+                &[][..]
+            } else {
+                std::slice::from_raw_parts(attrib_list, num_attribs as usize)
+            };
+            let requested = attribs
+                .iter()
+                .find(|a| a.type_ == sys::VASurfaceAttribPixelFormat)
+                .map(|a| Fourcc::from(a.value.value.i as u32));
+            let rt_format = RtFormat::from_bits_truncate(format);
+            let Some(fourcc) = requested.or_else(|| default_fourcc(rt_format)) else {
+                return status(ErrorStatus::UnsupportedRtFormat);
+            };
+            let Some(info) = fourcc.info() else {
+                return status(ErrorStatus::InvalidImageFormat);
+            };
+            if !rt_format.intersects(info.rt_format) {
+                return status(ErrorStatus::UnsupportedRtFormat);
+            }
+            if width == 0 || height == 0 || width > 16384 || height > 16384 {
+                return status(ErrorStatus::ResolutionNotSupported);
+            }
+            let layout = Layout::new(info, width, height);
+            for i in 0..num_surfaces as usize {
+                let storage = new_id(next_id);
+                display.buffers.insert(
+                    storage,
+                    BufferState {
+                        buffer_type: sys::VAImageBufferType,
+                        element_size: layout.size,
+                        num_elements: 1,
+                        data: vec![0; layout.size],
+                        map_count: 0,
+                        internal: true,
+                        context: None,
+                        segment: None,
+                    },
+                );
+                let id = new_id(next_id);
+                display.surfaces.insert(
+                    id,
+                    SurfaceState {
+                        fourcc,
+                        width,
+                        height,
+                        layout,
+                        storage,
+                    },
+                );
+                *surfaces.add(i) = id;
+            }
+            SUCCESS
+        })
+    }
+
+    unsafe fn vaDestroySurfaces(
+        &self,
+        dpy: sys::VADisplay,
+        surfaces: *mut sys::VASurfaceID,
+        num_surfaces: c_int,
+    ) -> sys::VAStatus {
+        self.call("vaDestroySurfaces", dpy, |display, _, log| {
+            let ids = std::slice::from_raw_parts(surfaces, num_surfaces as usize);
+            if ids.iter().any(|id| !display.surfaces.contains_key(id)) {
+                return status(ErrorStatus::InvalidSurface);
+            }
+            for id in ids {
+                if display.images.values().any(|i| i.derived_from == Some(*id)) {
+                    log.violations.push(format!(
+                        "surface {:#x} destroyed while a derived image is alive",
+                        id
+                    ));
+                }
+                if display.contexts.values().any(|c| c.targets.contains(id)) {
+                    log.violations.push(format!(
+                        "surface {:#x} destroyed while a context renders to it",
+                        id
+                    ));
+                }
+                let surface = display.surfaces.remove(id).unwrap();
+                display.buffers.remove(&surface.storage);
+            }
+            SUCCESS
+        })
+    }
+
+    unsafe fn vaCreateContext(
+        &self,
+        dpy: sys::VADisplay,
+        config_id: sys::VAConfigID,
+        picture_width: c_int,
+        picture_height: c_int,
+        _flag: c_int,
+        render_targets: *mut sys::VASurfaceID,
+        num_render_targets: c_int,
+        context: *mut sys::VAContextID,
+    ) -> sys::VAStatus {
+        self.call("vaCreateContext", dpy, |display, next_id, _| {
+            let Some(config) = display.configs.get(&config_id) else {
+                return status(ErrorStatus::InvalidConfig);
+            };
+            let caps = &self.inner.capabilities.configs[config.caps];
+            if picture_width as u32 > caps.max_width || picture_height as u32 > caps.max_height {
+                return status(ErrorStatus::ResolutionNotSupported);
+            }
+            let targets = if render_targets.is_null() {
+                Vec::new()
+            } else {
+                std::slice::from_raw_parts(render_targets, num_render_targets as usize).to_vec()
+            };
+            if targets.iter().any(|id| !display.surfaces.contains_key(id)) {
+                return status(ErrorStatus::InvalidSurface);
+            }
+            let id = new_id(next_id);
+            display.contexts.insert(
+                id,
+                ContextState {
+                    config: config_id,
+                    targets,
+                    picture: None,
+                    priority: config.priority,
+                },
+            );
+            *context = id;
+            SUCCESS
+        })
+    }
+
+    unsafe fn vaDestroyContext(
+        &self,
+        dpy: sys::VADisplay,
+        context: sys::VAContextID,
+    ) -> sys::VAStatus {
+        self.call("vaDestroyContext", dpy, |display, _, log| {
+            if display.contexts.remove(&context).is_none() {
+                return status(ErrorStatus::InvalidContext);
+            }
+            if display.buffers.values().any(|b| b.context == Some(context)) {
+                log.violations.push(format!(
+                    "context {:#x} destroyed while its buffers are alive",
+                    context
+                ));
+            }
+            SUCCESS
+        })
+    }
+
+    unsafe fn vaCreateBuffer(
+        &self,
+        dpy: sys::VADisplay,
+        context: sys::VAContextID,
+        type_: sys::VABufferType,
+        size: c_uint,
+        num_elements: c_uint,
+        data: *mut c_void,
+        buf_id: *mut sys::VABufferID,
+    ) -> sys::VAStatus {
+        self.call("vaCreateBuffer", dpy, |display, next_id, _| {
+            if !display.contexts.contains_key(&context) {
+                return status(ErrorStatus::InvalidContext);
+            }
+            let total = size as usize * num_elements as usize;
+            let contents = if data.is_null() {
+                vec![0; total]
+            } else {
+                std::slice::from_raw_parts(data as *const u8, total).to_vec()
+            };
+            let segment = (type_ == sys::VAEncCodedBufferType).then(|| {
+                CodedSegment(Box::new(sys::VACodedBufferSegment {
+                    size: 0,
+                    bit_offset: 0,
+                    status: 0,
+                    reserved: 0,
+                    buf: std::ptr::null_mut(),
+                    next: std::ptr::null_mut(),
+                    va_reserved: [0; 4],
+                }))
+            });
+            let id = new_id(next_id);
+            display.buffers.insert(
+                id,
+                BufferState {
+                    buffer_type: type_,
+                    element_size: size as usize,
+                    num_elements,
+                    data: contents,
+                    map_count: 0,
+                    internal: false,
+                    context: Some(context),
+                    segment,
+                },
+            );
+            *buf_id = id;
+            SUCCESS
+        })
+    }
+
+    unsafe fn vaBufferSetNumElements(
+        &self,
+        dpy: sys::VADisplay,
+        buf_id: sys::VABufferID,
+        num_elements: c_uint,
+    ) -> sys::VAStatus {
+        self.call("vaBufferSetNumElements", dpy, |display, _, _| match display
+            .buffers
+            .get_mut(&buf_id)
+        {
+            Some(buffer) if !buffer.internal && buffer.map_count == 0 => {
+                buffer.num_elements = num_elements;
+                buffer
+                    .data
+                    .resize(buffer.element_size * num_elements as usize, 0);
+                SUCCESS
+            }
+            Some(_) => status(ErrorStatus::OperationFailed),
+            None => status(ErrorStatus::InvalidBuffer),
+        })
+    }
+
+    unsafe fn vaMapBuffer(
+        &self,
+        dpy: sys::VADisplay,
+        buf_id: sys::VABufferID,
+        pbuf: *mut *mut c_void,
+    ) -> sys::VAStatus {
+        self.call("vaMapBuffer", dpy, |display, _, _| {
+            let Some(buffer) = display.buffers.get_mut(&buf_id) else {
+                return status(ErrorStatus::InvalidBuffer);
+            };
+            buffer.map_count += 1;
+            *pbuf = match &mut buffer.segment {
+                Some(CodedSegment(segment)) => {
+                    segment.size = buffer.data.len() as u32;
+                    segment.buf = buffer.data.as_mut_ptr() as *mut c_void;
+                    &mut **segment as *mut sys::VACodedBufferSegment as *mut c_void
+                }
+                None => buffer.data.as_mut_ptr() as *mut c_void,
+            };
+            SUCCESS
+        })
+    }
+
+    unsafe fn vaUnmapBuffer(&self, dpy: sys::VADisplay, buf_id: sys::VABufferID) -> sys::VAStatus {
+        self.call("vaUnmapBuffer", dpy, |display, _, _| {
+            match display.buffers.get_mut(&buf_id) {
+                Some(buffer) if buffer.map_count > 0 => {
+                    buffer.map_count -= 1;
+                    SUCCESS
+                }
+                Some(_) => status(ErrorStatus::OperationFailed),
+                None => status(ErrorStatus::InvalidBuffer),
+            }
+        })
+    }
+
+    unsafe fn vaDestroyBuffer(
+        &self,
+        dpy: sys::VADisplay,
+        buffer_id: sys::VABufferID,
+    ) -> sys::VAStatus {
+        self.call("vaDestroyBuffer", dpy, |display, _, _| {
+            match display.buffers.get(&buffer_id) {
+                Some(buffer) if !buffer.internal => {
+                    display.buffers.remove(&buffer_id);
+                    SUCCESS
+                }
+                _ => status(ErrorStatus::InvalidBuffer),
+            }
+        })
+    }
+
+    unsafe fn vaBeginPicture(
+        &self,
+        dpy: sys::VADisplay,
+        context: sys::VAContextID,
+        render_target: sys::VASurfaceID,
+    ) -> sys::VAStatus {
+        self.call("vaBeginPicture", dpy, |display, _, _| {
+            if !display.surfaces.contains_key(&render_target) {
+                return status(ErrorStatus::InvalidSurface);
+            }
+            let Some(ctx) = display.contexts.get_mut(&context) else {
+                return status(ErrorStatus::InvalidContext);
+            };
+            if ctx.picture.is_some() {
+                return status(ErrorStatus::OperationFailed);
+            }
+            if !ctx.targets.is_empty() && !ctx.targets.contains(&render_target) {
+                return status(ErrorStatus::InvalidSurface);
+            }
+            ctx.picture = Some(FakePicture {
+                context,
+                target: render_target,
+                buffers: Vec::new(),
+            });
+            SUCCESS
+        })
+    }
+
+    unsafe fn vaRenderPicture(
+        &self,
+        dpy: sys::VADisplay,
+        context: sys::VAContextID,
+        buffers: *mut sys::VABufferID,
+        num_buffers: c_int,
+    ) -> sys::VAStatus {
+        self.call("vaRenderPicture", dpy, |display, _, _| {
+            let ids = std::slice::from_raw_parts(buffers, num_buffers as usize);
+            let mut rendered = Vec::with_capacity(ids.len());
+            for &id in ids {
+                match display.buffers.get(&id) {
+                    Some(buffer) if !buffer.internal => rendered.push(FakeRenderedBuffer {
+                        id,
+                        buffer_type: BufferType::try_from(buffer.buffer_type).ok(),
+                        num_elements: buffer.num_elements,
+                        data: buffer.data.clone(),
+                    }),
+                    _ => return status(ErrorStatus::InvalidBuffer),
+                }
+            }
+            let max_priority = display.contexts.get(&context).and_then(|ctx| {
+                display
+                    .configs
+                    .get(&ctx.config)?
+                    .attributes
+                    .iter()
+                    .find(|a| a.type_ == sys::VAConfigAttribContextPriority)
+                    .map(|a| a.value)
+            });
+            let mut priority = None;
+            for buffer in &rendered {
+                if buffer.buffer_type != Some(BufferType::ContextParameterUpdate) {
+                    continue;
+                }
+                if buffer.data.len() < std::mem::size_of::<sys::VAContextParameterUpdateBuffer>() {
+                    return status(ErrorStatus::InvalidBuffer);
+                }
+                let update = std::ptr::read_unaligned(
+                    buffer.data.as_ptr() as *const sys::VAContextParameterUpdateBuffer
+                );
+                if update.flags.bits.context_priority_update() == 0 {
+                    continue;
+                }
+                let level = update.context_priority.bits.priority();
+                match max_priority {
+                    Some(max) if level <= max => priority = Some(level),
+                    Some(_) => return status(ErrorStatus::InvalidParameter),
+                    None => return status(ErrorStatus::AttrNotSupported),
+                }
+            }
+            match display.contexts.get_mut(&context) {
+                Some(ContextState {
+                    picture: Some(picture),
+                    priority: current,
+                    ..
+                }) => {
+                    picture.buffers.extend(rendered);
+                    *current = priority.unwrap_or(*current);
+                    SUCCESS
+                }
+                Some(_) => status(ErrorStatus::OperationFailed),
+                None => status(ErrorStatus::InvalidContext),
+            }
+        })
+    }
+
+    unsafe fn vaEndPicture(&self, dpy: sys::VADisplay, context: sys::VAContextID) -> sys::VAStatus {
+        self.call("vaEndPicture", dpy, |display, _, log| {
+            match display.contexts.get_mut(&context) {
+                Some(ctx) => match ctx.picture.take() {
+                    Some(picture) => {
+                        log.pictures.push(picture);
+                        SUCCESS
+                    }
+                    None => status(ErrorStatus::OperationFailed),
+                },
+                None => status(ErrorStatus::InvalidContext),
+            }
+        })
+    }
+
+    unsafe fn vaSyncSurface(
+        &self,
+        dpy: sys::VADisplay,
+        render_target: sys::VASurfaceID,
+    ) -> sys::VAStatus {
+        self.wait_surface_idle(render_target, None);
+        self.call("vaSyncSurface", dpy, |display, _, _| {
+            if display.surfaces.contains_key(&render_target) {
+                SUCCESS
+            } else {
+                status(ErrorStatus::InvalidSurface)
+            }
+        })
+    }
+
+    unsafe fn vaSyncSurface2(
+        &self,
+        dpy: sys::VADisplay,
+        surface: sys::VASurfaceID,
+        timeout_ns: u64,
+    ) -> sys::VAStatus {
+        let timeout = (timeout_ns != sys::VA_TIMEOUT_INFINITE as u64)
+            .then(|| Duration::from_nanos(timeout_ns));
+        if !self.wait_surface_idle(surface, timeout) {
+            return status(ErrorStatus::TimedOut);
+        }
+        self.call("vaSyncSurface2", dpy, |display, _, _| {
+            if display.surfaces.contains_key(&surface) {
+                SUCCESS
+            } else {
+                status(ErrorStatus::InvalidSurface)
+            }
+        })
+    }
+
+    unsafe fn vaQuerySurfaceStatus(
+        &self,
+        dpy: sys::VADisplay,
+        render_target: sys::VASurfaceID,
+        surface_status: *mut sys::VASurfaceStatus,
+    ) -> sys::VAStatus {
+        self.call("vaQuerySurfaceStatus", dpy, |display, _, _| {
+            if display.surfaces.contains_key(&render_target) {
+                *surface_status = sys::VASurfaceReady;
+                SUCCESS
+            } else {
+                status(ErrorStatus::InvalidSurface)
+            }
+        })
+    }
+
+    unsafe fn vaSyncBuffer(
+        &self,
+        dpy: sys::VADisplay,
+        buf_id: sys::VABufferID,
+        _timeout_ns: u64,
+    ) -> sys::VAStatus {
+        self.call("vaSyncBuffer", dpy, |display, _, _| {
+            if display.buffers.contains_key(&buf_id) {
+                SUCCESS
+            } else {
+                status(ErrorStatus::InvalidBuffer)
+            }
+        })
+    }
+
+    unsafe fn vaMaxNumImageFormats(&self, _dpy: sys::VADisplay) -> c_int {
+        self.inner.capabilities.image_formats.len().max(1) as c_int
+    }
+
+    unsafe fn vaGetDisplayAttributes(
+        &self,
+        dpy: sys::VADisplay,
+        attr_list: *mut sys::VADisplayAttribute,
+        num_attributes: c_int,
+    ) -> sys::VAStatus {
+        let sub_devices = self.inner.capabilities.sub_devices;
+        self.call("vaGetDisplayAttributes", dpy, |display, _, _| {
+            let attribs = std::slice::from_raw_parts_mut(attr_list, num_attributes as usize);
+            for attrib in attribs {
+                if attrib.type_ != sys::VADisplayAttribSubDevice || sub_devices == 0 {
+                    attrib.flags = sys::VA_DISPLAY_ATTRIB_NOT_SUPPORTED;
+                    continue;
+                }
+                let mut value = sys::VADisplayAttribValSubDevice { value: 0 };
+                value.bits.set_current_sub_device(display.sub_device);
+                value.bits.set_sub_device_count(sub_devices);
+                value.bits.set_sub_device_mask((1 << sub_devices) - 1);
+                attrib.value = value.value as i32;
+                attrib.flags = sys::VA_DISPLAY_ATTRIB_GETTABLE | sys::VA_DISPLAY_ATTRIB_SETTABLE;
+            }
+            SUCCESS
+        })
+    }
+
+    unsafe fn vaSetDisplayAttributes(
+        &self,
+        dpy: sys::VADisplay,
+        attr_list: *mut sys::VADisplayAttribute,
+        num_attributes: c_int,
+    ) -> sys::VAStatus {
+        let sub_devices = self.inner.capabilities.sub_devices;
+        self.call("vaSetDisplayAttributes", dpy, |display, _, _| {
+            let attribs = std::slice::from_raw_parts(attr_list, num_attributes as usize);
+            for attrib in attribs {
+                if attrib.type_ != sys::VADisplayAttribSubDevice || sub_devices == 0 {
+                    return status(ErrorStatus::AttrNotSupported);
+                }
+                let value = sys::VADisplayAttribValSubDevice {
+                    value: attrib.value as u32,
+                };
+                let index = value.bits.current_sub_device();
+                if index >= sub_devices {
+                    return status(ErrorStatus::InvalidParameter);
+                }
+                display.sub_device = index;
+            }
+            SUCCESS
+        })
+    }
+
+    unsafe fn vaQueryImageFormats(
+        &self,
+        dpy: sys::VADisplay,
+        format_list: *mut sys::VAImageFormat,
+        num_formats: *mut c_int,
+    ) -> sys::VAStatus {
+        self.call("vaQueryImageFormats", dpy, |_, _, _| {
+            let formats = &self.inner.capabilities.image_formats;
+            for (i, &fourcc) in formats.iter().enumerate() {
+                *format_list.add(i) = image_format(fourcc);
+            }
+            *num_formats = formats.len() as c_int;
+            SUCCESS
+        })
+    }
+
+    unsafe fn vaCreateImage(
+        &self,
+        dpy: sys::VADisplay,
+        format: *mut sys::VAImageFormat,
+        width: c_int,
+        height: c_int,
+        image: *mut sys::VAImage,
+    ) -> sys::VAStatus {
+        self.call("vaCreateImage", dpy, |display, next_id, _| {
+            let fourcc = Fourcc::from((*format).fourcc);
+            let info = match fourcc.info() {
+                Some(info) if self.inner.capabilities.image_formats.contains(&fourcc) => info,
+                _ => return status(ErrorStatus::InvalidImageFormat),
+            };
+            if width <= 0 || height <= 0 {
+                return status(ErrorStatus::InvalidParameter);
+            }
+            let layout = Layout::new(info, width as u32, height as u32);
+            let buffer = new_id(next_id);
+            display.buffers.insert(
+                buffer,
+                BufferState {
+                    buffer_type: sys::VAImageBufferType,
+                    element_size: layout.size,
+                    num_elements: 1,
+                    data: vec![0; layout.size],
+                    map_count: 0,
+                    internal: true,
+                    context: None,
+                    segment: None,
+                },
+            );
+            let raw = sys::VAImage {
+                image_id: new_id(next_id),
+                format: image_format(fourcc),
+                buf: buffer,
+                width: width as u16,
+                height: height as u16,
+                data_size: layout.size as u32,
+                num_planes: layout.num_planes as u32,
+                pitches: layout.pitches,
+                offsets: layout.offsets,
+                ..Default::default()
+            };
+            display.images.insert(
+                raw.image_id,
+                ImageState {
+                    raw,
+                    derived_from: None,
+                },
+            );
+            *image = raw;
+            SUCCESS
+        })
+    }
+
+    unsafe fn vaDestroyImage(&self, dpy: sys::VADisplay, image: sys::VAImageID) -> sys::VAStatus {
+        self.call("vaDestroyImage", dpy, |display, _, _| {
+            match display.images.remove(&image) {
+                Some(image) => {
+                    if image.derived_from.is_none() {
+                        display.buffers.remove(&image.raw.buf);
+                    }
+                    SUCCESS
+                }
+                None => status(ErrorStatus::InvalidImage),
+            }
+        })
+    }
+
+    unsafe fn vaGetImage(
+        &self,
+        dpy: sys::VADisplay,
+        surface: sys::VASurfaceID,
+        x: c_int,
+        y: c_int,
+        width: c_uint,
+        height: c_uint,
+        image: sys::VAImageID,
+    ) -> sys::VAStatus {
+        self.call("vaGetImage", dpy, |display, _, _| {
+            let Some(surface) = display.surfaces.get(&surface) else {
+                return status(ErrorStatus::InvalidSurface);
+            };
+            let Some(image) = display.images.get(&image) else {
+                return status(ErrorStatus::InvalidImage);
+            };
+            if Fourcc::from(image.raw.format.fourcc) != surface.fourcc {
+                return status(ErrorStatus::Unimplemented);
+            }
+            if x < 0
+                || y < 0
+                || x as u32 + width > surface.width
+                || y as u32 + height > surface.height
+                || width > image.raw.width as u32
+                || height > image.raw.height as u32
+            {
+                return status(ErrorStatus::InvalidParameter);
+            }
+            let info = surface.fourcc.info().unwrap();
+            let image_layout = Layout {
+                num_planes: image.raw.num_planes as usize,
+                pitches: image.raw.pitches,
+                offsets: image.raw.offsets,
+                size: image.raw.data_size as usize,
+            };
+            let src = display.buffers[&surface.storage].data.clone();
+            let dst = &mut display.buffers.get_mut(&image.raw.buf).unwrap().data;
+            copy_rect(
+                info,
+                &src,
+                &surface.layout,
+                (x as u32, y as u32),
+                dst,
+                &image_layout,
+                (0, 0),
+                (width, height),
+            );
+            SUCCESS
+        })
+    }
+
+    unsafe fn vaPutImage(
+        &self,
+        dpy: sys::VADisplay,
+        surface: sys::VASurfaceID,
+        image: sys::VAImageID,
+        src_x: c_int,
+        src_y: c_int,
+        src_width: c_uint,
+        src_height: c_uint,
+        dest_x: c_int,
+        dest_y: c_int,
+        dest_width: c_uint,
+        dest_height: c_uint,
+    ) -> sys::VAStatus {
+        self.call("vaPutImage", dpy, |display, _, _| {
+            let Some(surface) = display.surfaces.get(&surface) else {
+                return status(ErrorStatus::InvalidSurface);
+            };
+            let Some(image) = display.images.get(&image) else {
+                return status(ErrorStatus::InvalidImage);
+            };
+            if Fourcc::from(image.raw.format.fourcc) != surface.fourcc
+                || src_width != dest_width
+                || src_height != dest_height
+            {
+                return status(ErrorStatus::Unimplemented);
+            }
+            if src_x < 0
+                || src_y < 0
+                || dest_x < 0
+                || dest_y < 0
+                || src_x as u32 + src_width > image.raw.width as u32
+                || src_y as u32 + src_height > image.raw.height as u32
+                || dest_x as u32 + dest_width > surface.width
+                || dest_y as u32 + dest_height > surface.height
+            {
+                return status(ErrorStatus::InvalidParameter);
+            }
+            let info = surface.fourcc.info().unwrap();
+            let image_layout = Layout {
+                num_planes: image.raw.num_planes as usize,
+                pitches: image.raw.pitches,
+                offsets: image.raw.offsets,
+                size: image.raw.data_size as usize,
+            };
+            let src = display.buffers[&image.raw.buf].data.clone();
+            let dst = &mut display.buffers.get_mut(&surface.storage).unwrap().data;
+            copy_rect(
+                info,
+                &src,
+                &image_layout,
+                (src_x as u32, src_y as u32),
+                dst,
+                &surface.layout,
+                (dest_x as u32, dest_y as u32),
+                (src_width, src_height),
+            );
+            SUCCESS
+        })
+    }
+
+    unsafe fn vaDeriveImage(
+        &self,
+        dpy: sys::VADisplay,
+        surface: sys::VASurfaceID,
+        image: *mut sys::VAImage,
+    ) -> sys::VAStatus {
+        self.call("vaDeriveImage", dpy, |display, next_id, _| {
+            if !self.inner.capabilities.derive_image {
+                return status(ErrorStatus::OperationFailed);
+            }
+            let surface_id = surface;
+            let Some(surface) = display.surfaces.get(&surface) else {
+                return status(ErrorStatus::InvalidSurface);
+            };
+            let raw = sys::VAImage {
+                image_id: new_id(next_id),
+                format: image_format(surface.fourcc),
+                buf: surface.storage,
+                width: surface.width as u16,
+                height: surface.height as u16,
+                data_size: surface.layout.size as u32,
+                num_planes: surface.layout.num_planes as u32,
+                pitches: surface.layout.pitches,
+                offsets: surface.layout.offsets,
+                ..Default::default()
+            };
+            display.images.insert(
+                raw.image_id,
+                ImageState {
+                    raw,
+                    derived_from: Some(surface_id),
+                },
+            );
+            *image = raw;
+            SUCCESS
+        })
+    }
+    unsafe fn vaQueryVideoProcFilters(
+        &self,
+        dpy: sys::VADisplay,
+        context: sys::VAContextID,
+        filters: *mut sys::VAProcFilterType,
+        num_filters: *mut c_uint,
+    ) -> sys::VAStatus {
+        self.call("vaQueryVideoProcFilters", dpy, |display, _, _| {
+            if let Err(status) = self.video_proc_caps(display, context) {
+                return status;
+            }
+            let supported = &self.inner.capabilities.video_proc_filters;
+            if (*num_filters as usize) < supported.len() {
+                *num_filters = supported.len() as c_uint;
+                return status(ErrorStatus::MaxNumExceeded);
+            }
+            for (i, &filter) in supported.iter().enumerate() {
+                *filters.add(i) = filter.into();
+            }
+            *num_filters = supported.len() as c_uint;
+            SUCCESS
+        })
+    }
+
+    unsafe fn vaQueryVideoProcFilterCaps(
+        &self,
+        dpy: sys::VADisplay,
+        context: sys::VAContextID,
+        type_: sys::VAProcFilterType,
+        filter_caps: *mut c_void,
+        num_filter_caps: *mut c_uint,
+    ) -> sys::VAStatus {
+        self.call("vaQueryVideoProcFilterCaps", dpy, |display, _, _| {
+            if let Err(status) = self.video_proc_caps(display, context) {
+                return status;
+            }
+            let supported = ProcFilterType::try_from(type_)
+                .is_ok_and(|filter| self.inner.capabilities.video_proc_filters.contains(&filter));
+            if !supported {
+                return status(ErrorStatus::UnsupportedFilter);
+            }
+            let range = |min, max, default, step| sys::VAProcFilterValueRange {
+                min_value: min,
+                max_value: max,
+                default_value: default,
+                step,
+                ..Default::default()
+            };
+            let count = match type_ {
+                sys::VAProcFilterDeinterlacing => {
+                    let caps = [sys::VAProcDeinterlacingBob, sys::VAProcDeinterlacingWeave];
+                    if (*num_filter_caps as usize) < caps.len() {
+                        return status(ErrorStatus::MaxNumExceeded);
+                    }
+                    let out = filter_caps.cast::<sys::VAProcFilterCapDeinterlacing>();
+                    for (i, type_) in caps.into_iter().enumerate() {
+                        *out.add(i) = sys::VAProcFilterCapDeinterlacing {
+                            type_,
+                            ..Default::default()
+                        };
+                    }
+                    caps.len()
+                }
+                sys::VAProcFilterColorBalance => {
+                    let caps = [
+                        (sys::VAProcColorBalanceHue, range(-180.0, 180.0, 0.0, 1.0)),
+                        (
+                            sys::VAProcColorBalanceSaturation,
+                            range(0.0, 10.0, 1.0, 0.1),
+                        ),
+                        (
+                            sys::VAProcColorBalanceBrightness,
+                            range(-100.0, 100.0, 0.0, 1.0),
+                        ),
+                        (sys::VAProcColorBalanceContrast, range(0.0, 10.0, 1.0, 0.1)),
+                    ];
+                    if (*num_filter_caps as usize) < caps.len() {
+                        return status(ErrorStatus::MaxNumExceeded);
+                    }
+                    let out = filter_caps.cast::<sys::VAProcFilterCapColorBalance>();
+                    for (i, (type_, range)) in caps.into_iter().enumerate() {
+                        *out.add(i) = sys::VAProcFilterCapColorBalance {
+                            type_,
+                            range,
+                            ..Default::default()
+                        };
+                    }
+                    caps.len()
+                }
+                _ => {
+                    if *num_filter_caps < 1 {
+                        return status(ErrorStatus::MaxNumExceeded);
+                    }
+                    *filter_caps.cast::<sys::VAProcFilterCap>() = sys::VAProcFilterCap {
+                        range: range(0.0, 100.0, 0.0, 1.0),
+                        ..Default::default()
+                    };
+                    1
+                }
+            };
+            *num_filter_caps = count as c_uint;
+            SUCCESS
+        })
+    }
+
+    unsafe fn vaQueryVideoProcPipelineCaps(
+        &self,
+        dpy: sys::VADisplay,
+        context: sys::VAContextID,
+        _filters: *mut sys::VABufferID,
+        _num_filters: c_uint,
+        pipeline_caps: *mut sys::VAProcPipelineCaps,
+    ) -> sys::VAStatus {
+        self.call("vaQueryVideoProcPipelineCaps", dpy, |display, _, _| {
+            let caps = match self.video_proc_caps(display, context) {
+                Ok(caps) => caps,
+                Err(status) => return status,
+            };
+            let out = &mut *pipeline_caps;
+            // Lists are filled up to the capacity the caller provided.
+            unsafe fn fill<T: Copy>(list: *mut T, capacity: &mut u32, values: &[T]) {
+                let count = values.len().min(*capacity as usize);
+                if !list.is_null() {
+                    std::ptr::copy_nonoverlapping(values.as_ptr(), list, count);
+                }
+                *capacity = count as u32;
+            }
+            let standards = [
+                sys::VAProcColorStandardBT601,
+                sys::VAProcColorStandardBT709,
+                sys::VAProcColorStandardBT2020,
+            ];
+            let formats: Vec<u32> = caps.pixel_formats.iter().map(|&f| f.into()).collect();
+            fill(
+                out.input_color_standards,
+                &mut out.num_input_color_standards,
+                &standards,
+            );
+            fill(
+                out.output_color_standards,
+                &mut out.num_output_color_standards,
+                &standards,
+            );
+            fill(
+                out.input_pixel_format,
+                &mut out.num_input_pixel_formats,
+                &formats,
+            );
+            fill(
+                out.output_pixel_format,
+                &mut out.num_output_pixel_formats,
+                &formats,
+            );
+            out.rotation_flags = (1 << sys::VA_ROTATION_NONE) | (1 << sys::VA_ROTATION_180);
+            out.mirror_flags = sys::VA_MIRROR_HORIZONTAL | sys::VA_MIRROR_VERTICAL;
+            out.min_input_width = caps.min_width;
+            out.min_input_height = caps.min_height;
+            out.max_input_width = caps.max_width;
+            out.max_input_height = caps.max_height;
+            out.min_output_width = caps.min_width;
+            out.min_output_height = caps.min_height;
+            out.max_output_width = caps.max_width;
+            out.max_output_height = caps.max_height;
+            SUCCESS
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Buffer, Config, Context, ContextFlags, Image, Surface, UsageHint};
+
+    fn setup() -> (FakeBackend, Arc<Display>) {
+        let fake = FakeBackend::default();
+        let display = open_display(&fake.library()).unwrap();
+        (fake, display)
+    }
+
+    #[test]
+    fn queries() {
+        let (_fake, display) = setup();
+        let profiles = display.query_config_profiles().unwrap();
+        assert!(profiles.contains(&Profile::H264High));
+        let attrs = display
+            .get_config_attributes(Some(Profile::HEVCMain10), Entrypoint::VLD)
+            .unwrap();
+        assert_eq!(
+            attrs.rt_format,
+            Some(RtFormat::YUV420 | RtFormat::YUV420_10)
+        );
+        assert_eq!(attrs.enc_max_ref_frames, None);
+        let err = display
+            .get_config_attributes(Some(Profile::H264Main), Entrypoint::EncSlice)
+            .unwrap_err();
+        assert_eq!(err.status(), Some(ErrorStatus::UnsupportedEntrypoint));
+        let formats = display.query_image_formats().unwrap();
+        assert!(formats.iter().any(|f| f.fourcc == Fourcc::NV12));
+    }
+
+    #[test]
+    fn sub_devices() {
+        let (_fake, display) = setup();
+        assert_eq!(display.sub_devices().unwrap(), None);
+        let err = display.set_sub_device(0).unwrap_err();
+        assert_eq!(err.status(), Some(ErrorStatus::AttrNotSupported));
+
+        let fake = FakeBackend::new(FakeCapabilities {
+            sub_devices: 2,
+            ..Default::default()
+        });
+        let display = open_display(&fake.library()).unwrap();
+        display.set_sub_device(1).unwrap();
+        assert_eq!(
+            display.sub_devices().unwrap(),
+            Some(crate::SubDevices {
+                count: 2,
+                current: 1,
+                mask: 0b11,
+            })
+        );
+        let err = display.set_sub_device(2).unwrap_err();
+        assert_eq!(err.status(), Some(ErrorStatus::InvalidParameter));
+    }
+
+    #[test]
+    fn surfaces_and_images() {
+        let (fake, display) = setup();
+        let surface =
+            Surface::with_pixel_format(display.clone(), Fourcc::NV12, 64, 32, UsageHint::GENERIC)
+                .unwrap();
+        {
+            let image = surface.derive_image().unwrap();
+            let mut map = image.map().unwrap();
+            map.plane_mut(0).unwrap().row_mut(3).unwrap().fill(42);
+        }
+
+        let format = display
+            .query_image_formats()
+            .unwrap()
+            .into_iter()
+            .find(|f| f.fourcc == Fourcc::NV12)
+            .unwrap();
+        let image = Image::new(display.clone(), &format, 64, 32).unwrap();
+        surface.get_image(&image, 0, 0, 64, 32).unwrap();
+        let map = image.map_read().unwrap();
+        let luma = map.plane(0).unwrap();
+        assert_eq!(luma.row(3).unwrap(), &[42; 64]);
+        assert_eq!(luma.row(4).unwrap(), &[0; 64]);
+        drop(map);
+        drop(image);
+        drop(surface);
+        assert_eq!(fake.live_objects().surfaces, 0);
+        assert_eq!(fake.live_objects().images, 0);
+    }
+
+    #[test]
+    fn picture_submission() {
+        let (fake, display) = setup();
+        let config = Config::new(
+            display.clone(),
+            Some(Profile::H264Main),
+            Entrypoint::VLD,
+            &ConfigAttributes::default(),
+        )
+        .unwrap();
+        let surfaces = Surface::new_many(
+            display.clone(),
+            RtFormat::YUV420,
+            64,
+            64,
+            None,
+            UsageHint::DECODER,
+            2,
+        )
+        .unwrap();
+        let context =
+            Context::new(config, 64, 64, ContextFlags::PROGRESSIVE, surfaces.clone()).unwrap();
+        let buffer =
+            Buffer::new_with_data(context.clone(), BufferType::SliceData, &[1, 2, 3]).unwrap();
+
+        let lib = display.library().lib();
+        unsafe {
+            let dpy = display.handle();
+            let mut ids = [buffer.handle()];
+            assert_eq!(
+                lib.vaRenderPicture(dpy, context.handle(), ids.as_mut_ptr(), 1),
+                status(ErrorStatus::OperationFailed)
+            );
+            assert_eq!(
+                lib.vaBeginPicture(dpy, context.handle(), surfaces[1].handle()),
+                SUCCESS
+            );
+            assert_eq!(
+                lib.vaRenderPicture(dpy, context.handle(), ids.as_mut_ptr(), 1),
+                SUCCESS
+            );
+            assert_eq!(lib.vaEndPicture(dpy, context.handle()), SUCCESS);
+        }
+        let pictures = fake.pictures();
+        assert_eq!(pictures.len(), 1);
+        assert_eq!(pictures[0].target, surfaces[1].handle());
+        assert_eq!(
+            pictures[0].buffers[0].buffer_type,
+            Some(BufferType::SliceData)
+        );
+        assert_eq!(pictures[0].buffers[0].data, [1, 2, 3]);
+    }
+
+    #[test]
+    fn injected_errors() {
+        let (fake, display) = setup();
+        fake.inject_error("vaCreateSurfaces", ErrorStatus::AllocationFailed);
+        let create = || {
+            Surface::new(
+                display.clone(),
+                RtFormat::YUV420,
+                64,
+                64,
+                None,
+                UsageHint::GENERIC,
+            )
+        };
+        let Err(err) = create() else {
+            panic!("injected error was not reported");
+        };
+        assert_eq!(err.status(), Some(ErrorStatus::AllocationFailed));
+        assert_eq!(err.operation(), "vaCreateSurfaces");
+        assert_eq!(
+            err.to_string(),
+            "vaCreateSurfaces failed: resource allocation failed (0x2)"
+        );
+        assert!(create().is_ok());
+    }
+
+    #[test]
+    fn buffer_mapping() {
+        let (_fake, display) = setup();
+        let config = Config::new(
+            display.clone(),
+            None,
+            Entrypoint::VideoProc,
+            &ConfigAttributes::default(),
+        )
+        .unwrap();
+        let context = Context::new(config, 64, 64, ContextFlags::PROGRESSIVE, vec![]).unwrap();
+        let buffer = Buffer::new(context, BufferType::ProcPipelineParameter, 4).unwrap();
+        buffer.map().unwrap().copy_from_slice(&[1, 2, 3, 4]);
+        let a = buffer.map_read().unwrap();
+        let b = buffer.map_read().unwrap();
+        assert_eq!(&*a, &*b);
+        assert_eq!(&*a, &[1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn dependents_keep_owners_alive() {
+        let (fake, display) = setup();
+        let config = Config::new(
+            display.clone(),
+            Some(Profile::H264Main),
+            Entrypoint::VLD,
+            &ConfigAttributes::default(),
+        )
+        .unwrap();
+        let surface = Surface::new(
+            display.clone(),
+            RtFormat::YUV420,
+            64,
+            64,
+            None,
+            UsageHint::DECODER,
+        )
+        .unwrap();
+        let image = surface.derive_image().unwrap();
+        let context = Context::new(
+            config.clone(),
+            64,
+            64,
+            ContextFlags::PROGRESSIVE,
+            vec![surface.clone()],
+        )
+        .unwrap();
+        let buffer = Buffer::new(context.clone(), BufferType::SliceData, 16).unwrap();
+
+        // Drop every owner first; the dependents must keep them alive.
+        drop((display, config, surface, context));
+        let live = fake.live_objects();
+        assert_eq!(
+            live,
+            FakeObjectCounts {
+                displays: 1,
+                configs: 1,
+                contexts: 1,
+                surfaces: 1,
+                buffers: 1,
+                images: 1,
+            }
+        );
+        image.map().unwrap().fill(1);
+        buffer.map().unwrap().fill(2);
+
+        drop(image);
+        assert_eq!(fake.live_objects().images, 0);
+        assert_eq!(fake.live_objects().surfaces, 1);
+        drop(buffer);
+        assert_eq!(fake.live_objects(), FakeObjectCounts::default());
+        assert_eq!(fake.violations(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn detects_out_of_order_destruction() {
+        let (fake, display) = setup();
+        let surface = Surface::new(
+            display.clone(),
+            RtFormat::YUV420,
+            64,
+            64,
+            None,
+            UsageHint::GENERIC,
+        )
+        .unwrap();
+        let image = surface.derive_image().unwrap();
+        unsafe {
+            let mut id = surface.handle();
+            display
+                .library()
+                .lib()
+                .vaDestroySurfaces(display.handle(), &mut id, 1);
+        }
+        assert_eq!(fake.violations().len(), 1);
+        std::mem::forget((image, surface));
+    }
+}
@@ -0,0 +1,333 @@
+use crate::{Fourcc, RtFormat};
+
+impl Fourcc {
+    pub const NV12: Self = Self::from_bytes(*b"NV12");
+    pub const NV21: Self = Self::from_bytes(*b"NV21");
+    pub const P010: Self = Self::from_bytes(*b"P010");
+    pub const P012: Self = Self::from_bytes(*b"P012");
+    pub const P016: Self = Self::from_bytes(*b"P016");
+    pub const I420: Self = Self::from_bytes(*b"I420");
+    pub const IYUV: Self = Self::from_bytes(*b"IYUV");
+    pub const YV12: Self = Self::from_bytes(*b"YV12");
+    pub const YV16: Self = Self::from_bytes(*b"YV16");
+    pub const YUV411P: Self = Self::from_bytes(*b"411P");
+    pub const YUV422H: Self = Self::from_bytes(*b"422H");
+    pub const YUV444P: Self = Self::from_bytes(*b"444P");
+    pub const Y800: Self = Self::from_bytes(*b"Y800");
+    pub const YUY2: Self = Self::from_bytes(*b"YUY2");
+    pub const YVYU: Self = Self::from_bytes(*b"YVYU");
+    pub const UYVY: Self = Self::from_bytes(*b"UYVY");
+    pub const VYUY: Self = Self::from_bytes(*b"VYUY");
+    pub const Y210: Self = Self::from_bytes(*b"Y210");
+    pub const Y212: Self = Self::from_bytes(*b"Y212");
+    pub const Y216: Self = Self::from_bytes(*b"Y216");
+    pub const Y410: Self = Self::from_bytes(*b"Y410");
+    pub const Y412: Self = Self::from_bytes(*b"Y412");
+    pub const Y416: Self = Self::from_bytes(*b"Y416");
+    pub const AYUV: Self = Self::from_bytes(*b"AYUV");
+    pub const XYUV: Self = Self::from_bytes(*b"XYUV");
+    pub const RGBA: Self = Self::from_bytes(*b"RGBA");
+    pub const RGBX: Self = Self::from_bytes(*b"RGBX");
+    pub const BGRA: Self = Self::from_bytes(*b"BGRA");
+    pub const BGRX: Self = Self::from_bytes(*b"BGRX");
+    pub const ARGB: Self = Self::from_bytes(*b"ARGB");
+    pub const XRGB: Self = Self::from_bytes(*b"XRGB");
+    pub const ABGR: Self = Self::from_bytes(*b"ABGR");
+    pub const XBGR: Self = Self::from_bytes(*b"XBGR");
+    pub const A2R10G10B10: Self = Self::from_bytes(*b"AR30");
+    pub const X2R10G10B10: Self = Self::from_bytes(*b"XR30");
+    pub const A2B10G10R10: Self = Self::from_bytes(*b"AB30");
+    pub const X2B10G10R10: Self = Self::from_bytes(*b"XB30");
+    pub const RGB565: Self = Self::from_bytes(*b"RG16");
+    pub const RGBP: Self = Self::from_bytes(*b"RGBP");
+    pub const BGRP: Self = Self::from_bytes(*b"BGRP");
+
+    /// Returns the registry entry for this VA fourcc, if known.
+    pub fn info(self) -> Option<&'static FormatInfo> {
+        FORMATS.iter().find(|info| info.fourcc == self)
+    }
+
+    /// Returns the DRM fourcc describing the same memory layout.
+    pub fn to_drm(self) -> Option<DrmFourcc> {
+        self.info()?.drm_fourcc
+    }
+
+    /// Returns the VA fourcc describing the same memory layout as a DRM
+    /// fourcc.
+    pub fn from_drm(drm: DrmFourcc) -> Option<Self> {
+        FORMATS
+            .iter()
+            .find(|info| info.drm_fourcc == Some(drm))
+            .map(|info| info.fourcc)
+    }
+}
+
+/// A fourcc code from `drm_fourcc.h`.
+///
+/// DRM and VA-API name several formats differently (for example VA `BGRA` is
+/// DRM `AR24`), so the two are kept as distinct types.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DrmFourcc(u32);
+
+impl DrmFourcc {
+    pub const fn from_bytes(bytes: [u8; 4]) -> Self {
+        Self(u32::from_le_bytes(bytes))
+    }
+
+    pub const fn to_bytes(self) -> [u8; 4] {
+        self.0.to_le_bytes()
+    }
+}
+
+impl From<u32> for DrmFourcc {
+    fn from(value: u32) -> Self {
+        Self(value)
+    }
+}
+
+impl From<DrmFourcc> for u32 {
+    fn from(value: DrmFourcc) -> Self {
+        value.0
+    }
+}
+
+impl std::fmt::Debug for DrmFourcc {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        String::from_utf8_lossy(&self.to_bytes()).fmt(f)
+    }
+}
+
+impl std::fmt::Display for DrmFourcc {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        String::from_utf8_lossy(&self.to_bytes()).fmt(f)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ColorModel {
+    Yuv,
+    Rgb,
+}
+
+/// Chroma subsampling as divisors of the luma resolution.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ChromaSubsampling {
+    pub horizontal: u8,
+    pub vertical: u8,
+}
+
+impl ChromaSubsampling {
+    pub const YUV420: Self = Self::new(2, 2);
+    pub const YUV422: Self = Self::new(2, 1);
+    pub const YUV411: Self = Self::new(4, 1);
+    pub const YUV444: Self = Self::new(1, 1);
+
+    const fn new(horizontal: u8, vertical: u8) -> Self {
+        Self {
+            horizontal,
+            vertical,
+        }
+    }
+}
+
+/// Storage of one plane of a format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PlaneInfo {
+    pub horizontal_divisor: u8,
+    pub vertical_divisor: u8,
+    /// Size of one element, i.e. one sample or one group of interleaved
+    /// samples such as a `UV` pair or a `YUYV` macropixel half.
+    pub bytes_per_element: u8,
+}
+
+impl PlaneInfo {
+    const fn new(horizontal_divisor: u8, vertical_divisor: u8, bytes_per_element: u8) -> Self {
+        Self {
+            horizontal_divisor,
+            vertical_divisor,
+            bytes_per_element,
+        }
+    }
+}
+
+/// Description of a pixel format known to VA-API.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FormatInfo {
+    pub fourcc: Fourcc,
+    pub color_model: ColorModel,
+    pub planes: &'static [PlaneInfo],
+    /// Always [`ChromaSubsampling::YUV444`] for RGB formats.
+    pub chroma_subsampling: ChromaSubsampling,
+    /// Significant bits per sample.
+    pub bit_depth: u8,
+    /// Bytes used to store one sample. Samples wider than 8 bits are stored
+    /// MSB-aligned in 16-bit little-endian words, except for the packed 10-bit
+    /// formats whose samples share a 32-bit word.
+    pub bytes_per_sample: u8,
+    pub has_alpha: bool,
+    /// Render target format of surfaces that can hold this format.
+    pub rt_format: RtFormat,
+    pub drm_fourcc: Option<DrmFourcc>,
+}
+
+impl FormatInfo {
+    pub fn num_planes(&self) -> usize {
+        self.planes.len()
+    }
+
+    /// Size in bytes of a tightly packed image of the given dimensions.
+    pub fn frame_size(&self, width: u32, height: u32) -> usize {
+        self.planes
+            .iter()
+            .map(|plane| {
+                width.div_ceil(plane.horizontal_divisor as u32) as usize
+                    * height.div_ceil(plane.vertical_divisor as u32) as usize
+                    * plane.bytes_per_element as usize
+            })
+            .sum()
+    }
+}
+
+/// All formats known to the registry.
+pub fn formats() -> &'static [FormatInfo] {
+    FORMATS
+}
+
+const fn drm(bytes: &[u8; 4]) -> Option<DrmFourcc> {
+    Some(DrmFourcc::from_bytes(*bytes))
+}
+
+const SEMI_PLANAR_420_8: &[PlaneInfo] = &[PlaneInfo::new(1, 1, 1), PlaneInfo::new(2, 2, 2)];
+const SEMI_PLANAR_420_16: &[PlaneInfo] = &[PlaneInfo::new(1, 1, 2), PlaneInfo::new(2, 2, 4)];
+const PLANAR_420: &[PlaneInfo] = &[
+    PlaneInfo::new(1, 1, 1),
+    PlaneInfo::new(2, 2, 1),
+    PlaneInfo::new(2, 2, 1),
+];
+const PLANAR_422: &[PlaneInfo] = &[
+    PlaneInfo::new(1, 1, 1),
+    PlaneInfo::new(2, 1, 1),
+    PlaneInfo::new(2, 1, 1),
+];
+const PLANAR_411: &[PlaneInfo] = &[
+    PlaneInfo::new(1, 1, 1),
+    PlaneInfo::new(4, 1, 1),
+    PlaneInfo::new(4, 1, 1),
+];
+const PLANAR_444: &[PlaneInfo] = &[
+    PlaneInfo::new(1, 1, 1),
+    PlaneInfo::new(1, 1, 1),
+    PlaneInfo::new(1, 1, 1),
+];
+const PACKED_8: &[PlaneInfo] = &[PlaneInfo::new(1, 1, 1)];
+const PACKED_16: &[PlaneInfo] = &[PlaneInfo::new(1, 1, 2)];
+const PACKED_32: &[PlaneInfo] = &[PlaneInfo::new(1, 1, 4)];
+const PACKED_64: &[PlaneInfo] = &[PlaneInfo::new(1, 1, 8)];
+
+macro_rules! format_table {
+    {$(
+        $fourcc:ident: $model:ident $planes:ident $sub:ident
+            bits $depth:literal bytes $bytes:literal alpha $alpha:literal
+            $rt:ident $drm:expr;
+    )*} => {
+        const FORMATS: &[FormatInfo] = &[
+            $(
+                FormatInfo {
+                    fourcc: Fourcc::$fourcc,
+                    color_model: ColorModel::$model,
+                    planes: $planes,
+                    chroma_subsampling: ChromaSubsampling::$sub,
+                    bit_depth: $depth,
+                    bytes_per_sample: $bytes,
+                    has_alpha: $alpha,
+                    rt_format: RtFormat::$rt,
+                    drm_fourcc: $drm,
+                },
+            )*
+        ];
+    };
+}
+
+format_table! {
+    NV12: Yuv SEMI_PLANAR_420_8 YUV420 bits 8 bytes 1 alpha false YUV420 drm(b"NV12");
+    NV21: Yuv SEMI_PLANAR_420_8 YUV420 bits 8 bytes 1 alpha false YUV420 drm(b"NV21");
+    P010: Yuv SEMI_PLANAR_420_16 YUV420 bits 10 bytes 2 alpha false YUV420_10 drm(b"P010");
+    P012: Yuv SEMI_PLANAR_420_16 YUV420 bits 12 bytes 2 alpha false YUV420_12 drm(b"P012");
+    P016: Yuv SEMI_PLANAR_420_16 YUV420 bits 16 bytes 2 alpha false YUV420_12 drm(b"P016");
+    I420: Yuv PLANAR_420 YUV420 bits 8 bytes 1 alpha false YUV420 drm(b"YU12");
+    IYUV: Yuv PLANAR_420 YUV420 bits 8 bytes 1 alpha false YUV420 None;
+    YV12: Yuv PLANAR_420 YUV420 bits 8 bytes 1 alpha false YUV420 drm(b"YV12");
+    YUV411P: Yuv PLANAR_411 YUV411 bits 8 bytes 1 alpha false YUV411 drm(b"YU11");
+    YUV422H: Yuv PLANAR_422 YUV422 bits 8 bytes 1 alpha false YUV422 drm(b"YU16");
+    YV16: Yuv PLANAR_422 YUV422 bits 8 bytes 1 alpha false YUV422 drm(b"YV16");
+    YUV444P: Yuv PLANAR_444 YUV444 bits 8 bytes 1 alpha false YUV444 drm(b"YU24");
+    Y800: Yuv PACKED_8 YUV444 bits 8 bytes 1 alpha false YUV400 drm(b"R8  ");
+    YUY2: Yuv PACKED_16 YUV422 bits 8 bytes 1 alpha false YUV422 drm(b"YUYV");
+    YVYU: Yuv PACKED_16 YUV422 bits 8 bytes 1 alpha false YUV422 drm(b"YVYU");
+    UYVY: Yuv PACKED_16 YUV422 bits 8 bytes 1 alpha false YUV422 drm(b"UYVY");
+    VYUY: Yuv PACKED_16 YUV422 bits 8 bytes 1 alpha false YUV422 drm(b"VYUY");
+    Y210: Yuv PACKED_32 YUV422 bits 10 bytes 2 alpha false YUV422_10 drm(b"Y210");
+    Y212: Yuv PACKED_32 YUV422 bits 12 bytes 2 alpha false YUV422_12 drm(b"Y212");
+    Y216: Yuv PACKED_32 YUV422 bits 16 bytes 2 alpha false YUV422_12 drm(b"Y216");
+    Y410: Yuv PACKED_32 YUV444 bits 10 bytes 4 alpha true YUV444_10 drm(b"Y410");
+    Y412: Yuv PACKED_64 YUV444 bits 12 bytes 2 alpha true YUV444_12 drm(b"Y412");
+    Y416: Yuv PACKED_64 YUV444 bits 16 bytes 2 alpha true YUV444_12 drm(b"Y416");
+    AYUV: Yuv PACKED_32 YUV444 bits 8 bytes 1 alpha true YUV444 drm(b"AYUV");
+    XYUV: Yuv PACKED_32 YUV444 bits 8 bytes 1 alpha false YUV444 drm(b"XYUV");
+    RGBA: Rgb PACKED_32 YUV444 bits 8 bytes 1 alpha true RGB32 drm(b"AB24");
+    RGBX: Rgb PACKED_32 YUV444 bits 8 bytes 1 alpha false RGB32 drm(b"XB24");
+    BGRA: Rgb PACKED_32 YUV444 bits 8 bytes 1 alpha true RGB32 drm(b"AR24");
+    BGRX: Rgb PACKED_32 YUV444 bits 8 bytes 1 alpha false RGB32 drm(b"XR24");
+    ARGB: Rgb PACKED_32 YUV444 bits 8 bytes 1 alpha true RGB32 drm(b"BA24");
+    XRGB: Rgb PACKED_32 YUV444 bits 8 bytes 1 alpha false RGB32 drm(b"BX24");
+    ABGR: Rgb PACKED_32 YUV444 bits 8 bytes 1 alpha true RGB32 drm(b"RA24");
+    XBGR: Rgb PACKED_32 YUV444 bits 8 bytes 1 alpha false RGB32 drm(b"RX24");
+    A2R10G10B10: Rgb PACKED_32 YUV444 bits 10 bytes 4 alpha true RGB32_10 drm(b"AR30");
+    X2R10G10B10: Rgb PACKED_32 YUV444 bits 10 bytes 4 alpha false RGB32_10 drm(b"XR30");
+    A2B10G10R10: Rgb PACKED_32 YUV444 bits 10 bytes 4 alpha true RGB32_10 drm(b"AB30");
+    X2B10G10R10: Rgb PACKED_32 YUV444 bits 10 bytes 4 alpha false RGB32_10 drm(b"XB30");
+    RGB565: Rgb PACKED_16 YUV444 bits 5 bytes 2 alpha false RGB16 drm(b"RG16");
+    RGBP: Rgb PLANAR_444 YUV444 bits 8 bytes 1 alpha false RGBP None;
+    BGRP: Rgb PLANAR_444 YUV444 bits 8 bytes 1 alpha false RGBP None;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lookup() {
+        let nv12 = Fourcc::try_from("NV12").unwrap();
+        assert_eq!(nv12, Fourcc::NV12);
+        let info = nv12.info().unwrap();
+        assert_eq!(info.num_planes(), 2);
+        assert_eq!(info.chroma_subsampling, ChromaSubsampling::YUV420);
+        assert_eq!(info.rt_format, RtFormat::YUV420);
+        assert_eq!(info.frame_size(4, 4), 24);
+        assert_eq!(Fourcc::P010.info().unwrap().bit_depth, 10);
+        assert!(Fourcc::from(0).info().is_none());
+    }
+
+    #[test]
+    fn drm_round_trip() {
+        for info in formats() {
+            if let Some(drm) = info.drm_fourcc {
+                assert_eq!(Fourcc::from_drm(drm), Some(info.fourcc), "{}", info.fourcc);
+            }
+        }
+        assert_eq!(Fourcc::BGRA.to_drm(), Some(DrmFourcc::from_bytes(*b"AR24")));
+        assert_eq!(
+            Fourcc::from_drm(DrmFourcc::from_bytes(*b"YU12")),
+            Some(Fourcc::I420)
+        );
+    }
+
+    #[test]
+    fn fourccs_match_va() {
+        use crate::sys;
+        assert_eq!(u32::from(Fourcc::NV12), sys::VA_FOURCC_NV12);
+        assert_eq!(u32::from(Fourcc::YUV444P), sys::VA_FOURCC_444P);
+        assert_eq!(u32::from(Fourcc::X2R10G10B10), sys::VA_FOURCC_X2R10G10B10);
+        assert_eq!(u32::from(Fourcc::RGB565), sys::VA_FOURCC_RGB565);
+    }
+}
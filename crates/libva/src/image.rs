@@ -1,9 +1,13 @@
 use core::str;
 use std::sync::Arc;
 
-use crate::{sys, Buffer, ByteOrder, Display, Fourcc, Library, VaResult, VaStatusExt};
+use crate::{
+    plane::split_planes_mut, sys, Buffer, BufferMap, BufferMapRead, ByteOrder, Display, Fourcc,
+    Library, Plane, PlaneLayout, PlaneMut, Surface, VaResult, VaStatusExt,
+};
 
 #[derive(Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ImageFormat {
     pub fourcc: Fourcc,
     pub byte_order: Option<ByteOrder>,
@@ -64,11 +68,16 @@ impl std::fmt::Debug for ImageFormat {
     }
 }
 
+/// A VA image.
+///
+/// An image derived from a surface aliases the surface memory, so it keeps
+/// the surface alive and is destroyed before it.
 pub struct Image {
     raw: sys::VAImage,
     format: ImageFormat,
     display: Arc<Display>,
     buffer: Buffer,
+    surface: Option<Arc<Surface>>,
 }
 
 impl Image {
@@ -91,19 +100,26 @@ impl Image {
                     height as _,
                     &mut raw,
                 )
-                .va_result()?;
+                .va_check(display.library(), "vaCreateImage", &[])?;
         }
-        Ok(Self::from_raw(display, raw))
+        Ok(Self::from_raw(display, raw, None))
     }
 
-    pub fn from_raw(display: Arc<Display>, raw: sys::VAImage) -> Arc<Self> {
-        let buffer = Buffer::from_raw(display.clone(), raw.buf, raw.data_size as usize, false);
+    /// Takes ownership of `raw`. `surface` must be set for images returned
+    /// by `vaDeriveImage`.
+    pub(crate) fn from_raw(
+        display: Arc<Display>,
+        raw: sys::VAImage,
+        surface: Option<Arc<Surface>>,
+    ) -> Arc<Self> {
+        let buffer = Buffer::image_data(display.clone(), raw.buf, raw.data_size as usize);
         let format = ImageFormat::try_from(raw.format).unwrap();
         Arc::new(Self {
             raw,
             display,
             buffer,
             format,
+            surface,
         })
     }
 
@@ -123,6 +139,27 @@ impl Image {
         &self.buffer
     }
 
+    /// The surface this image was derived from, if any.
+    pub fn surface(&self) -> Option<&Arc<Surface>> {
+        self.surface.as_ref()
+    }
+
+    /// Maps the image data for writing. See [`Buffer::map`].
+    pub fn map(&self) -> VaResult<ImageMap<'_>> {
+        Ok(ImageMap {
+            image: self,
+            map: self.buffer.map()?,
+        })
+    }
+
+    /// Maps the image data for reading. See [`Buffer::map_read`].
+    pub fn map_read(&self) -> VaResult<ImageMapRead<'_>> {
+        Ok(ImageMapRead {
+            image: self,
+            map: self.buffer.map_read()?,
+        })
+    }
+
     pub fn format(&self) -> &ImageFormat {
         &self.format
     }
@@ -147,6 +184,29 @@ impl Image {
         &self.raw.offsets[..self.num_planes() as usize]
     }
 
+    /// Returns the geometry of plane `index`, accounting for the chroma
+    /// subsampling of the image's fourcc.
+    pub fn plane_layout(&self, index: usize) -> Option<PlaneLayout> {
+        if index >= self.num_planes() as usize {
+            return None;
+        }
+        Some(PlaneLayout::for_plane(
+            self.format.fourcc,
+            index,
+            self.width(),
+            self.height(),
+            self.raw.offsets[index] as usize,
+            self.raw.pitches[index] as usize,
+            self.format.bits_per_pixel as usize / 8,
+        ))
+    }
+
+    pub fn plane_layouts(&self) -> Vec<PlaneLayout> {
+        (0..self.num_planes() as usize)
+            .filter_map(|i| self.plane_layout(i))
+            .collect()
+    }
+
     pub fn num_pallet_entries(&self) -> u32 {
         self.raw.num_palette_entries as _
     }
@@ -163,6 +223,82 @@ impl Image {
     }
 }
 
+/// An exclusive, writable mapping of an [`Image`].
+pub struct ImageMap<'a> {
+    image: &'a Image,
+    map: BufferMap<'a>,
+}
+
+/// A shared, read-only mapping of an [`Image`].
+pub struct ImageMapRead<'a> {
+    image: &'a Image,
+    map: BufferMapRead<'a>,
+}
+
+impl ImageMap<'_> {
+    pub fn image(&self) -> &Image {
+        self.image
+    }
+
+    pub fn plane(&self, index: usize) -> Option<Plane<'_>> {
+        let layout = self.image.plane_layout(index)?;
+        Plane::with_layout(self.get(layout.offset..)?, layout)
+    }
+
+    pub fn plane_mut(&mut self, index: usize) -> Option<PlaneMut<'_>> {
+        let layout = self.image.plane_layout(index)?;
+        PlaneMut::with_layout(self.get_mut(layout.offset..)?, layout)
+    }
+
+    /// Borrows all planes mutably at once.
+    ///
+    /// Returns `None` if the driver reported overlapping planes or planes that
+    /// do not fit in the buffer.
+    pub fn planes_mut(&mut self) -> Option<Vec<PlaneMut<'_>>> {
+        let layouts = self.image.plane_layouts();
+        split_planes_mut(&mut self.map, &layouts)
+    }
+}
+
+impl std::ops::Deref for ImageMap<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        &self.map
+    }
+}
+
+impl std::ops::DerefMut for ImageMap<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.map
+    }
+}
+
+impl ImageMapRead<'_> {
+    pub fn image(&self) -> &Image {
+        self.image
+    }
+
+    pub fn plane(&self, index: usize) -> Option<Plane<'_>> {
+        let layout = self.image.plane_layout(index)?;
+        Plane::with_layout(self.get(layout.offset..)?, layout)
+    }
+
+    pub fn planes(&self) -> Vec<Plane<'_>> {
+        (0..self.image.num_planes() as usize)
+            .filter_map(|i| self.plane(i))
+            .collect()
+    }
+}
+
+impl std::ops::Deref for ImageMapRead<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        &self.map
+    }
+}
+
 impl Drop for Image {
     fn drop(&mut self) {
         unsafe {
@@ -182,6 +318,7 @@ impl std::fmt::Debug for Image {
             .field("handle", &self.raw.image_id)
             .field("display", &self.display)
             .field("buffer", &self.buffer)
+            .field("surface", &self.surface.as_ref().map(|s| s.handle()))
             .field("format", &self.format)
             .field("width", &self.width())
             .field("height", &self.height())
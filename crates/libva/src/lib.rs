@@ -1,28 +1,55 @@
-use bitflags::bitflags;
 use paste::paste;
 
 pub use vendec_libva_sys as sys;
 
+#[cfg(not(any(feature = "dlopen", feature = "link")))]
+compile_error!("either the `dlopen` or the `link` feature must be enabled");
+
 mod buffer;
+mod capabilities;
+#[cfg(feature = "async")]
+mod completion;
 mod config;
 mod context;
+pub mod convert;
+mod device;
 mod display;
+#[cfg(feature = "image")]
+mod dynamic_image;
 mod error;
+#[cfg(any(test, feature = "fake"))]
+pub mod fake;
+mod format;
 mod image;
 mod library;
+mod plane;
+mod pool;
 mod surface;
+pub mod trace;
+mod vpp;
 pub use buffer::*;
+pub use capabilities::*;
+#[cfg(feature = "async")]
+pub use completion::*;
 pub use config::*;
 pub use context::*;
+pub use device::*;
 pub use display::*;
+#[cfg(feature = "image")]
+pub use dynamic_image::*;
 pub use error::*;
+pub use format::*;
 pub use image::*;
 pub use library::*;
+pub use plane::*;
+pub use pool::*;
 pub use surface::*;
+pub use vpp::*;
 
 macro_rules! va_enum_prefix {
     {$name:ident: $sys_type:ty; $prefix:ident { $($elem_name:ident ,)* } } => {
         #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
         pub enum $name {
             $(
                 $elem_name,
@@ -58,6 +85,7 @@ pub(crate) use va_enum_prefix;
 macro_rules! va_enum_prefix_suffix {
     {$name:ident: $sys_type:ty; $prefix:ident { $($elem_name:ident ,)* } $suffix:ident } => {
         #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
         pub enum $name {
             $(
                 $elem_name,
@@ -96,6 +124,7 @@ macro_rules! va_bitflags {
             ::bitflags::bitflags! {
                     #[repr(transparent)]
                     #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+                    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
                     pub struct $name: u32 {
                         $(
                             const $elem_name = sys::[<$prefix $elem_name>] as u32;
@@ -208,6 +237,7 @@ va_enum_prefix! {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ByteOrder {
     MsbFirst,
     LsbFirst,
@@ -263,6 +293,16 @@ va_bitflags! {
 #[derive(Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Fourcc(u32);
 
+impl Fourcc {
+    pub const fn from_bytes(bytes: [u8; 4]) -> Self {
+        Self(u32::from_le_bytes(bytes))
+    }
+
+    pub const fn to_bytes(self) -> [u8; 4] {
+        self.0.to_le_bytes()
+    }
+}
+
 impl TryFrom<&str> for Fourcc {
     type Error = ();
     fn try_from(value: &str) -> Result<Self, Self::Error> {
@@ -303,6 +343,22 @@ impl std::fmt::Debug for Fourcc {
     }
 }
 
+#[cfg(feature = "serde")]
+impl serde::Serialize for Fourcc {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Fourcc {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        Fourcc::try_from(value.as_str())
+            .map_err(|()| serde::de::Error::custom(format!("invalid fourcc {:?}", value)))
+    }
+}
+
 impl std::fmt::Display for Fourcc {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let bytes = [
@@ -318,11 +374,9 @@ impl std::fmt::Display for Fourcc {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::sync::Arc;
 
-    #[test]
-    fn test() {
-        let lib = library::Library::load().unwrap();
-        let display = Display::enumerate(lib).next().unwrap();
+    fn exercise(display: Arc<Display>) {
         let profiles = display.query_config_profiles().unwrap();
         let config_attrs = display
             .get_config_attributes(None, Entrypoint::VideoProc)
@@ -345,4 +399,29 @@ mod tests {
         let image = surface.derive_image();
         println!("{:?}", image);
     }
+
+    #[test]
+    fn objects_are_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Display>();
+        assert_send_sync::<Config>();
+        assert_send_sync::<Context>();
+        assert_send_sync::<Surface>();
+        assert_send_sync::<Buffer>();
+        assert_send_sync::<CodedBuffer>();
+        assert_send_sync::<Image>();
+    }
+
+    #[test]
+    fn test() {
+        let fake = fake::FakeBackend::default();
+        exercise(fake::open_display(&fake.library()).unwrap());
+    }
+
+    #[test]
+    #[ignore = "requires a VA-API capable render node"]
+    fn test_hardware() {
+        let lib = library::Library::load().unwrap();
+        exercise(Display::select(lib, &DevicePreference::default()).unwrap());
+    }
 }
@@ -1,18 +1,150 @@
+use std::ffi::{c_char, c_int, c_uint, c_void};
+use std::io;
+use std::path::Path;
 use std::sync::Arc;
 
-use crate::sys;
+use crate::trace::TracingBackend;
+#[cfg(not(feature = "link"))]
+use crate::Error;
+use crate::{sys, VaResult};
+
+const UNIMPLEMENTED: sys::VAStatus = sys::VA_STATUS_ERROR_UNIMPLEMENTED as sys::VAStatus;
+
+macro_rules! va_backend {
+    {$(fn $name:ident($($arg:ident: $ty:ty),* $(,)?) -> $ret:ty = $default:expr;)*} => {
+        /// The VA entry points used by this crate.
+        ///
+        /// Every method has the signature and contract of the libva function of
+        /// the same name. The default implementations report
+        /// `VA_STATUS_ERROR_UNIMPLEMENTED`, so a backend only needs to provide
+        /// the calls it supports.
+        ///
+        /// [`Library::load`] provides an implementation forwarding to the
+        /// system libva, which is loaded at runtime or, with the `link`
+        /// feature, linked at build time.
+        #[allow(non_snake_case, clippy::missing_safety_doc, clippy::too_many_arguments)]
+        pub trait Backend: Send + Sync {
+            $(
+                unsafe fn $name(&self, $($arg: $ty),*) -> $ret {
+                    let _ = ($($arg,)*);
+                    $default
+                }
+            )*
+        }
+
+        #[cfg(not(feature = "link"))]
+        #[allow(non_snake_case, clippy::too_many_arguments)]
+        impl Backend for sys::va {
+            $(
+                unsafe fn $name(&self, $($arg: $ty),*) -> $ret {
+                    sys::va::$name(self, $($arg),*)
+                }
+            )*
+        }
+
+        #[cfg(feature = "link")]
+        #[allow(non_snake_case, clippy::too_many_arguments)]
+        impl Backend for Linked {
+            $(
+                unsafe fn $name(&self, $($arg: $ty),*) -> $ret {
+                    sys::$name($($arg),*)
+                }
+            )*
+        }
+    };
+}
+
+va_backend! {
+    fn vaErrorStr(error_status: sys::VAStatus) -> *const c_char = std::ptr::null();
+    fn vaGetDisplayDRM(fd: c_int) -> sys::VADisplay = std::ptr::null_mut();
+    fn vaInitialize(dpy: sys::VADisplay, major_version: *mut c_int, minor_version: *mut c_int) -> sys::VAStatus = UNIMPLEMENTED;
+    fn vaTerminate(dpy: sys::VADisplay) -> sys::VAStatus = UNIMPLEMENTED;
+    fn vaQueryVendorString(dpy: sys::VADisplay) -> *const c_char = std::ptr::null();
+    fn vaMaxNumProfiles(dpy: sys::VADisplay) -> c_int = 0;
+    fn vaMaxNumEntrypoints(dpy: sys::VADisplay) -> c_int = 0;
+    fn vaMaxNumConfigAttributes(dpy: sys::VADisplay) -> c_int = 0;
+    fn vaQueryConfigProfiles(dpy: sys::VADisplay, profile_list: *mut sys::VAProfile, num_profiles: *mut c_int) -> sys::VAStatus = UNIMPLEMENTED;
+    fn vaQueryConfigEntrypoints(dpy: sys::VADisplay, profile: sys::VAProfile, entrypoint_list: *mut sys::VAEntrypoint, num_entrypoints: *mut c_int) -> sys::VAStatus = UNIMPLEMENTED;
+    fn vaGetConfigAttributes(dpy: sys::VADisplay, profile: sys::VAProfile, entrypoint: sys::VAEntrypoint, attrib_list: *mut sys::VAConfigAttrib, num_attribs: c_int) -> sys::VAStatus = UNIMPLEMENTED;
+    fn vaCreateConfig(dpy: sys::VADisplay, profile: sys::VAProfile, entrypoint: sys::VAEntrypoint, attrib_list: *mut sys::VAConfigAttrib, num_attribs: c_int, config_id: *mut sys::VAConfigID) -> sys::VAStatus = UNIMPLEMENTED;
+    fn vaDestroyConfig(dpy: sys::VADisplay, config_id: sys::VAConfigID) -> sys::VAStatus = UNIMPLEMENTED;
+    fn vaQueryConfigAttributes(dpy: sys::VADisplay, config_id: sys::VAConfigID, profile: *mut sys::VAProfile, entrypoint: *mut sys::VAEntrypoint, attrib_list: *mut sys::VAConfigAttrib, num_attribs: *mut c_int) -> sys::VAStatus = UNIMPLEMENTED;
+    fn vaQuerySurfaceAttributes(dpy: sys::VADisplay, config: sys::VAConfigID, attrib_list: *mut sys::VASurfaceAttrib, num_attribs: *mut c_uint) -> sys::VAStatus = UNIMPLEMENTED;
+    fn vaCreateSurfaces(dpy: sys::VADisplay, format: c_uint, width: c_uint, height: c_uint, surfaces: *mut sys::VASurfaceID, num_surfaces: c_uint, attrib_list: *mut sys::VASurfaceAttrib, num_attribs: c_uint) -> sys::VAStatus = UNIMPLEMENTED;
+    fn vaDestroySurfaces(dpy: sys::VADisplay, surfaces: *mut sys::VASurfaceID, num_surfaces: c_int) -> sys::VAStatus = UNIMPLEMENTED;
+    fn vaCreateContext(dpy: sys::VADisplay, config_id: sys::VAConfigID, picture_width: c_int, picture_height: c_int, flag: c_int, render_targets: *mut sys::VASurfaceID, num_render_targets: c_int, context: *mut sys::VAContextID) -> sys::VAStatus = UNIMPLEMENTED;
+    fn vaDestroyContext(dpy: sys::VADisplay, context: sys::VAContextID) -> sys::VAStatus = UNIMPLEMENTED;
+    fn vaCreateBuffer(dpy: sys::VADisplay, context: sys::VAContextID, type_: sys::VABufferType, size: c_uint, num_elements: c_uint, data: *mut c_void, buf_id: *mut sys::VABufferID) -> sys::VAStatus = UNIMPLEMENTED;
+    fn vaBufferSetNumElements(dpy: sys::VADisplay, buf_id: sys::VABufferID, num_elements: c_uint) -> sys::VAStatus = UNIMPLEMENTED;
+    fn vaMapBuffer(dpy: sys::VADisplay, buf_id: sys::VABufferID, pbuf: *mut *mut c_void) -> sys::VAStatus = UNIMPLEMENTED;
+    fn vaUnmapBuffer(dpy: sys::VADisplay, buf_id: sys::VABufferID) -> sys::VAStatus = UNIMPLEMENTED;
+    fn vaDestroyBuffer(dpy: sys::VADisplay, buffer_id: sys::VABufferID) -> sys::VAStatus = UNIMPLEMENTED;
+    fn vaBeginPicture(dpy: sys::VADisplay, context: sys::VAContextID, render_target: sys::VASurfaceID) -> sys::VAStatus = UNIMPLEMENTED;
+    fn vaRenderPicture(dpy: sys::VADisplay, context: sys::VAContextID, buffers: *mut sys::VABufferID, num_buffers: c_int) -> sys::VAStatus = UNIMPLEMENTED;
+    fn vaEndPicture(dpy: sys::VADisplay, context: sys::VAContextID) -> sys::VAStatus = UNIMPLEMENTED;
+    fn vaSyncSurface(dpy: sys::VADisplay, render_target: sys::VASurfaceID) -> sys::VAStatus = UNIMPLEMENTED;
+    fn vaSyncSurface2(dpy: sys::VADisplay, surface: sys::VASurfaceID, timeout_ns: u64) -> sys::VAStatus = UNIMPLEMENTED;
+    fn vaQuerySurfaceStatus(dpy: sys::VADisplay, render_target: sys::VASurfaceID, status: *mut sys::VASurfaceStatus) -> sys::VAStatus = UNIMPLEMENTED;
+    fn vaSyncBuffer(dpy: sys::VADisplay, buf_id: sys::VABufferID, timeout_ns: u64) -> sys::VAStatus = UNIMPLEMENTED;
+    fn vaMaxNumImageFormats(dpy: sys::VADisplay) -> c_int = 0;
+    fn vaQueryImageFormats(dpy: sys::VADisplay, format_list: *mut sys::VAImageFormat, num_formats: *mut c_int) -> sys::VAStatus = UNIMPLEMENTED;
+    fn vaCreateImage(dpy: sys::VADisplay, format: *mut sys::VAImageFormat, width: c_int, height: c_int, image: *mut sys::VAImage) -> sys::VAStatus = UNIMPLEMENTED;
+    fn vaDestroyImage(dpy: sys::VADisplay, image: sys::VAImageID) -> sys::VAStatus = UNIMPLEMENTED;
+    fn vaGetImage(dpy: sys::VADisplay, surface: sys::VASurfaceID, x: c_int, y: c_int, width: c_uint, height: c_uint, image: sys::VAImageID) -> sys::VAStatus = UNIMPLEMENTED;
+    fn vaPutImage(dpy: sys::VADisplay, surface: sys::VASurfaceID, image: sys::VAImageID, src_x: c_int, src_y: c_int, src_width: c_uint, src_height: c_uint, dest_x: c_int, dest_y: c_int, dest_width: c_uint, dest_height: c_uint) -> sys::VAStatus = UNIMPLEMENTED;
+    fn vaDeriveImage(dpy: sys::VADisplay, surface: sys::VASurfaceID, image: *mut sys::VAImage) -> sys::VAStatus = UNIMPLEMENTED;
+    fn vaMaxNumDisplayAttributes(dpy: sys::VADisplay) -> c_int = 0;
+    fn vaQueryDisplayAttributes(dpy: sys::VADisplay, attr_list: *mut sys::VADisplayAttribute, num_attributes: *mut c_int) -> sys::VAStatus = UNIMPLEMENTED;
+    fn vaGetDisplayAttributes(dpy: sys::VADisplay, attr_list: *mut sys::VADisplayAttribute, num_attributes: c_int) -> sys::VAStatus = UNIMPLEMENTED;
+    fn vaSetDisplayAttributes(dpy: sys::VADisplay, attr_list: *mut sys::VADisplayAttribute, num_attributes: c_int) -> sys::VAStatus = UNIMPLEMENTED;
+    fn vaQueryVideoProcFilters(dpy: sys::VADisplay, context: sys::VAContextID, filters: *mut sys::VAProcFilterType, num_filters: *mut c_uint) -> sys::VAStatus = UNIMPLEMENTED;
+    fn vaQueryVideoProcFilterCaps(dpy: sys::VADisplay, context: sys::VAContextID, type_: sys::VAProcFilterType, filter_caps: *mut c_void, num_filter_caps: *mut c_uint) -> sys::VAStatus = UNIMPLEMENTED;
+    fn vaQueryVideoProcPipelineCaps(dpy: sys::VADisplay, context: sys::VAContextID, filters: *mut sys::VABufferID, num_filters: c_uint, pipeline_caps: *mut sys::VAProcPipelineCaps) -> sys::VAStatus = UNIMPLEMENTED;
+    fn vaExportSurfaceHandle(dpy: sys::VADisplay, surface_id: sys::VASurfaceID, mem_type: u32, flags: u32, descriptor: *mut c_void) -> sys::VAStatus = UNIMPLEMENTED;
+}
+
+/// The libva linked into the binary at build time.
+#[cfg(feature = "link")]
+struct Linked;
 
 pub struct Library {
-    lib: sys::va,
+    lib: Box<dyn Backend>,
 }
 
 impl Library {
-    pub fn load() -> Result<Arc<Self>, libloading::Error> {
-        let lib = unsafe { sys::va::new(libloading::library_filename("va-drm"))? };
-        Ok(Arc::new(Self { lib }))
+    /// Loads `libva-drm` at runtime, or returns the linked libva with the
+    /// `link` feature.
+    pub fn load() -> VaResult<Arc<Self>> {
+        #[cfg(feature = "link")]
+        return Ok(Self::with_backend(Linked));
+        #[cfg(not(feature = "link"))]
+        {
+            let lib = unsafe { sys::va::new(libloading::library_filename("va-drm")) }
+                .map_err(Error::load)?;
+            Ok(Self::with_backend(lib))
+        }
+    }
+
+    /// Like [`Library::load`], but records every call to a trace file at
+    /// `path`. See [`crate::trace`].
+    pub fn load_traced(path: impl AsRef<Path>) -> io::Result<Arc<Self>> {
+        #[cfg(feature = "link")]
+        let lib = Linked;
+        #[cfg(not(feature = "link"))]
+        let lib = unsafe { sys::va::new(libloading::library_filename("va-drm")) }
+            .map_err(|e| io::Error::other(Error::load(e)))?;
+        Ok(Self::with_backend(TracingBackend::create(lib, path)?))
+    }
+
+    /// Wraps an alternative implementation of the VA entry points, such as
+    /// [`FakeBackend`](crate::fake::FakeBackend).
+    pub fn with_backend(backend: impl Backend + 'static) -> Arc<Self> {
+        Arc::new(Self {
+            lib: Box::new(backend),
+        })
     }
 
-    pub fn lib(&self) -> &sys::va {
-        &self.lib
+    pub fn lib(&self) -> &dyn Backend {
+        &*self.lib
     }
 }
@@ -0,0 +1,330 @@
+use crate::Fourcc;
+
+/// Geometry of one plane of an image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PlaneLayout {
+    pub offset: usize,
+    /// Width in elements. An element is one sample, or one interleaved
+    /// group of samples for packed planes such as the UV plane of NV12.
+    pub width: u32,
+    pub height: u32,
+    /// Distance between rows in bytes.
+    pub pitch: usize,
+    pub bytes_per_element: usize,
+}
+
+impl PlaneLayout {
+    /// Number of meaningful bytes in each row.
+    pub fn row_bytes(&self) -> usize {
+        self.width as usize * self.bytes_per_element
+    }
+
+    /// Number of bytes spanned by the plane, not counting padding after the
+    /// last row.
+    pub fn span(&self) -> usize {
+        if self.height == 0 {
+            0
+        } else {
+            self.pitch * (self.height as usize - 1) + self.row_bytes()
+        }
+    }
+
+    /// Computes the layout of `plane` in an image of the given size.
+    ///
+    /// Unknown formats are treated as unsubsampled with
+    /// `fallback_bytes_per_pixel` bytes per element.
+    pub(crate) fn for_plane(
+        fourcc: Fourcc,
+        plane: usize,
+        width: u32,
+        height: u32,
+        offset: usize,
+        pitch: usize,
+        fallback_bytes_per_pixel: usize,
+    ) -> Self {
+        let (h_div, v_div, bytes_per_element) =
+            subsampling(fourcc, plane).unwrap_or((1, 1, fallback_bytes_per_pixel.max(1)));
+        Self {
+            offset,
+            width: width.div_ceil(h_div),
+            height: height.div_ceil(v_div),
+            pitch,
+            bytes_per_element,
+        }
+    }
+}
+
+/// Returns `(horizontal divisor, vertical divisor, bytes per element)`.
+fn subsampling(fourcc: Fourcc, plane: usize) -> Option<(u32, u32, usize)> {
+    let plane = fourcc.info()?.planes.get(plane)?;
+    Some((
+        plane.horizontal_divisor as u32,
+        plane.vertical_divisor as u32,
+        plane.bytes_per_element as usize,
+    ))
+}
+
+/// A read-only view of one image plane.
+#[derive(Debug, Clone, Copy)]
+pub struct Plane<'a> {
+    data: &'a [u8],
+    layout: PlaneLayout,
+}
+
+/// A writable view of one image plane.
+#[derive(Debug)]
+pub struct PlaneMut<'a> {
+    data: &'a mut [u8],
+    layout: PlaneLayout,
+}
+
+impl<'a> Plane<'a> {
+    /// Creates a view over `data`, which starts at the first byte of the plane.
+    ///
+    /// Returns `None` if `data` is too short for the layout or the pitch is
+    /// smaller than a row.
+    pub fn new(
+        data: &'a [u8],
+        width: u32,
+        height: u32,
+        pitch: usize,
+        bytes_per_element: usize,
+    ) -> Option<Self> {
+        Self::with_layout(
+            data,
+            PlaneLayout {
+                offset: 0,
+                width,
+                height,
+                pitch,
+                bytes_per_element,
+            },
+        )
+    }
+
+    pub(crate) fn with_layout(data: &'a [u8], layout: PlaneLayout) -> Option<Self> {
+        let data = data.get(..validated_span(data.len(), &layout)?)?;
+        Some(Self { data, layout })
+    }
+
+    pub fn width(&self) -> u32 {
+        self.layout.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.layout.height
+    }
+
+    pub fn pitch(&self) -> usize {
+        self.layout.pitch
+    }
+
+    pub fn bytes_per_element(&self) -> usize {
+        self.layout.bytes_per_element
+    }
+
+    pub fn layout(&self) -> &PlaneLayout {
+        &self.layout
+    }
+
+    /// Returns row `y` without padding, or `None` if out of range.
+    pub fn row(&self, y: u32) -> Option<&'a [u8]> {
+        if y >= self.layout.height {
+            return None;
+        }
+        let start = y as usize * self.layout.pitch;
+        Some(&self.data[start..start + self.layout.row_bytes()])
+    }
+
+    /// Iterates over the rows of the plane without padding.
+    pub fn rows(&self) -> impl ExactSizeIterator<Item = &'a [u8]> + 'a {
+        let layout = self.layout;
+        let data = self.data;
+        (0..layout.height as usize).map(move |y| {
+            let start = y * layout.pitch;
+            &data[start..start + layout.row_bytes()]
+        })
+    }
+}
+
+impl<'a> PlaneMut<'a> {
+    /// Creates a writable view over `data`. See [`Plane::new`].
+    pub fn new(
+        data: &'a mut [u8],
+        width: u32,
+        height: u32,
+        pitch: usize,
+        bytes_per_element: usize,
+    ) -> Option<Self> {
+        Self::with_layout(
+            data,
+            PlaneLayout {
+                offset: 0,
+                width,
+                height,
+                pitch,
+                bytes_per_element,
+            },
+        )
+    }
+
+    pub(crate) fn with_layout(data: &'a mut [u8], layout: PlaneLayout) -> Option<Self> {
+        let span = validated_span(data.len(), &layout)?;
+        Some(Self {
+            data: &mut data[..span],
+            layout,
+        })
+    }
+
+    pub fn width(&self) -> u32 {
+        self.layout.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.layout.height
+    }
+
+    pub fn pitch(&self) -> usize {
+        self.layout.pitch
+    }
+
+    pub fn bytes_per_element(&self) -> usize {
+        self.layout.bytes_per_element
+    }
+
+    pub fn layout(&self) -> &PlaneLayout {
+        &self.layout
+    }
+
+    pub fn as_plane(&self) -> Plane<'_> {
+        Plane {
+            data: self.data,
+            layout: self.layout,
+        }
+    }
+
+    pub fn row(&self, y: u32) -> Option<&[u8]> {
+        self.as_plane().row(y)
+    }
+
+    pub fn row_mut(&mut self, y: u32) -> Option<&mut [u8]> {
+        if y >= self.layout.height {
+            return None;
+        }
+        let start = y as usize * self.layout.pitch;
+        Some(&mut self.data[start..start + self.layout.row_bytes()])
+    }
+
+    pub fn rows(&self) -> impl ExactSizeIterator<Item = &[u8]> + '_ {
+        self.as_plane().rows()
+    }
+
+    /// Iterates over the rows of the plane without padding.
+    pub fn rows_mut(&mut self) -> impl Iterator<Item = &mut [u8]> + '_ {
+        let row_bytes = self.layout.row_bytes();
+        let height = self.layout.height as usize;
+        // The last row may be shorter than the pitch, so chunk by pitch and
+        // trim each chunk to the row length.
+        self.data
+            .chunks_mut(self.layout.pitch.max(1))
+            .take(height)
+            .map(move |row| &mut row[..row_bytes])
+    }
+}
+
+fn validated_span(len: usize, layout: &PlaneLayout) -> Option<usize> {
+    if layout.height > 0 && layout.pitch < layout.row_bytes() {
+        return None;
+    }
+    let span = layout.span();
+    (span <= len).then_some(span)
+}
+
+/// Splits `data` into non-overlapping writable planes.
+///
+/// Returns `None` if any two planes overlap or a plane does not fit.
+pub(crate) fn split_planes_mut<'a>(
+    mut data: &'a mut [u8],
+    layouts: &[PlaneLayout],
+) -> Option<Vec<PlaneMut<'a>>> {
+    let mut order: Vec<usize> = (0..layouts.len()).collect();
+    order.sort_by_key(|&i| layouts[i].offset);
+    let mut planes: Vec<Option<PlaneMut<'a>>> = layouts.iter().map(|_| None).collect();
+    let mut consumed = 0;
+    for i in order {
+        let layout = layouts[i];
+        let skip = layout.offset.checked_sub(consumed)?;
+        let rest = std::mem::take(&mut data).get_mut(skip..)?;
+        let span = validated_span(rest.len(), &layout)?;
+        let (plane, rest) = rest.split_at_mut(span);
+        planes[i] = Some(PlaneMut {
+            data: plane,
+            layout,
+        });
+        data = rest;
+        consumed = layout.offset + span;
+    }
+    planes.into_iter().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nv12_layout() {
+        let luma = PlaneLayout::for_plane(Fourcc::NV12, 0, 5, 3, 0, 8, 1);
+        let chroma = PlaneLayout::for_plane(Fourcc::NV12, 1, 5, 3, 24, 8, 1);
+        assert_eq!((luma.width, luma.height, luma.row_bytes()), (5, 3, 5));
+        assert_eq!((chroma.width, chroma.height, chroma.row_bytes()), (3, 2, 6));
+    }
+
+    #[test]
+    fn rows_skip_padding() {
+        let data: Vec<u8> = (0..11).collect();
+        let plane = Plane::new(&data, 3, 3, 4, 1).unwrap();
+        let rows: Vec<_> = plane.rows().collect();
+        assert_eq!(rows, [&[0, 1, 2][..], &[4, 5, 6], &[8, 9, 10]]);
+        assert!(plane.row(3).is_none());
+        assert!(Plane::new(&data[..10], 3, 3, 4, 1).is_none());
+    }
+
+    #[test]
+    fn split_planes() {
+        let mut data = vec![0u8; 24];
+        let layouts = [
+            PlaneLayout {
+                offset: 16,
+                width: 2,
+                height: 2,
+                pitch: 4,
+                bytes_per_element: 2,
+            },
+            PlaneLayout {
+                offset: 0,
+                width: 4,
+                height: 4,
+                pitch: 4,
+                bytes_per_element: 1,
+            },
+        ];
+        let mut planes = split_planes_mut(&mut data, &layouts).unwrap();
+        for row in planes[0].rows_mut() {
+            row.fill(2);
+        }
+        for row in planes[1].rows_mut() {
+            row.fill(1);
+        }
+        assert_eq!(&data[..16], &[1; 16]);
+        assert_eq!(&data[16..], &[2; 8]);
+
+        let overlapping = [
+            layouts[1],
+            PlaneLayout {
+                offset: 8,
+                ..layouts[1]
+            },
+        ];
+        assert!(split_planes_mut(&mut data, &overlapping).is_none());
+    }
+}
@@ -0,0 +1,419 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::{Display, Error, ErrorStatus, Fourcc, RtFormat, Surface, UsageHint, VaResult};
+
+/// The parameters surfaces in a [`SurfacePool`] are created with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SurfaceKey {
+    pub format: RtFormat,
+    pub width: u32,
+    pub height: u32,
+    pub pixel_format: Option<Fourcc>,
+    pub usage_hint: UsageHint,
+}
+
+/// Reuses surfaces across frames and bounds how many exist at once.
+///
+/// Surfaces are handed out as [`SurfaceLease`]s and go back to the pool when
+/// the lease drops. When a limit is set, it counts every surface the pool
+/// owns, leased or idle; at the limit, idle surfaces with a different key are
+/// destroyed to make room, and otherwise acquisition waits for a lease to be
+/// returned. Clones share the same pool.
+#[derive(Clone)]
+pub struct SurfacePool {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    display: Arc<Display>,
+    state: Mutex<State>,
+    returned: Condvar,
+}
+
+struct State {
+    limit: Option<usize>,
+    /// Surfaces owned by the pool, including leased ones and ones being
+    /// allocated.
+    allocated: usize,
+    idle: HashMap<SurfaceKey, Vec<Arc<Surface>>>,
+    /// Bumped by [`SurfacePool::clear`]; leases from older generations are
+    /// destroyed when returned.
+    generation: u64,
+}
+
+impl State {
+    fn num_idle(&self) -> usize {
+        self.idle.values().map(Vec::len).sum()
+    }
+
+    /// Destroys idle surfaces not matching `key` until `count` slots are
+    /// below the limit, or no such surfaces are left.
+    fn evict_for(&mut self, key: &SurfaceKey, count: usize) {
+        let Some(limit) = self.limit else {
+            return;
+        };
+        while self.allocated + count > limit {
+            let Some(surfaces) = self
+                .idle
+                .iter_mut()
+                .find(|(k, surfaces)| *k != key && !surfaces.is_empty())
+                .map(|(_, surfaces)| surfaces)
+            else {
+                return;
+            };
+            surfaces.pop();
+            self.allocated -= 1;
+        }
+    }
+}
+
+/// A surface leased from a [`SurfacePool`].
+///
+/// The surface returns to the pool when the lease drops, even if clones of
+/// the `Arc<Surface>` are still alive, so clones must not outlive the lease.
+pub struct SurfaceLease {
+    surface: Option<Arc<Surface>>,
+    key: SurfaceKey,
+    generation: u64,
+    pool: Arc<Inner>,
+}
+
+impl SurfacePool {
+    /// Creates a pool holding at most `limit` surfaces, or any number if
+    /// `None`.
+    pub fn new(display: Arc<Display>, limit: Option<usize>) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                display,
+                state: Mutex::new(State {
+                    limit,
+                    allocated: 0,
+                    idle: HashMap::new(),
+                    generation: 0,
+                }),
+                returned: Condvar::new(),
+            }),
+        }
+    }
+
+    pub fn display(&self) -> &Arc<Display> {
+        &self.inner.display
+    }
+
+    /// Leases a surface for `key`, waiting for one to be returned if the
+    /// pool is at its limit.
+    pub fn acquire(&self, key: &SurfaceKey) -> VaResult<SurfaceLease> {
+        self.acquire_until(key, None)
+    }
+
+    /// Like [`acquire`](Self::acquire), but fails with
+    /// [`ErrorStatus::TimedOut`] if no surface frees up within `timeout`.
+    pub fn acquire_timeout(&self, key: &SurfaceKey, timeout: Duration) -> VaResult<SurfaceLease> {
+        self.acquire_until(key, Some(Instant::now() + timeout))
+    }
+
+    /// Leases a surface for `key`, failing with
+    /// [`ErrorStatus::MaxNumExceeded`] if the pool is at its limit.
+    pub fn try_acquire(&self, key: &SurfaceKey) -> VaResult<SurfaceLease> {
+        self.inner
+            .take_or_reserve(self.inner.lock(), key)
+            .unwrap_or_else(|_| {
+                Err(Error::new(
+                    "SurfacePool::try_acquire",
+                    ErrorStatus::MaxNumExceeded,
+                ))
+            })
+    }
+
+    fn acquire_until(&self, key: &SurfaceKey, deadline: Option<Instant>) -> VaResult<SurfaceLease> {
+        let mut state = self.inner.lock();
+        loop {
+            state = match self.inner.take_or_reserve(state, key) {
+                Ok(lease) => return lease,
+                Err(state) => state,
+            };
+            state = match deadline {
+                None => self
+                    .inner
+                    .returned
+                    .wait(state)
+                    .unwrap_or_else(|e| e.into_inner()),
+                Some(deadline) => {
+                    let timeout = deadline.saturating_duration_since(Instant::now());
+                    if timeout.is_zero() {
+                        return Err(Error::new(
+                            "SurfacePool::acquire_timeout",
+                            ErrorStatus::TimedOut,
+                        ));
+                    }
+                    self.inner
+                        .returned
+                        .wait_timeout(state, timeout)
+                        .unwrap_or_else(|e| e.into_inner())
+                        .0
+                }
+            };
+        }
+    }
+
+    /// Makes sure at least `count` surfaces for `key` are idle, so that the
+    /// first frames of a stream don't pay for allocation.
+    pub fn preallocate(&self, key: &SurfaceKey, count: usize) -> VaResult<()> {
+        let leases = (0..count)
+            .map(|_| self.try_acquire(key))
+            .collect::<VaResult<Vec<_>>>();
+        leases.map(drop)
+    }
+
+    /// Changes the limit, destroying idle surfaces above a lower one.
+    /// Leased surfaces above it are destroyed as they are returned.
+    pub fn set_limit(&self, limit: Option<usize>) {
+        let mut state = self.inner.lock();
+        state.limit = limit;
+        if let Some(limit) = limit {
+            while state.allocated > limit {
+                let Some(surfaces) = state.idle.values_mut().find(|s| !s.is_empty()) else {
+                    break;
+                };
+                surfaces.pop();
+                state.allocated -= 1;
+            }
+        }
+        self.inner.returned.notify_all();
+    }
+
+    pub fn limit(&self) -> Option<usize> {
+        self.inner.lock().limit
+    }
+
+    /// Destroys all idle surfaces, e.g. after a resolution change. Surfaces
+    /// leased now are destroyed when returned instead of being reused.
+    pub fn clear(&self) {
+        let mut state = self.inner.lock();
+        let idle = state.num_idle();
+        state.idle.clear();
+        state.allocated -= idle;
+        state.generation += 1;
+        self.inner.returned.notify_all();
+    }
+
+    /// Number of surfaces currently leased.
+    pub fn leased(&self) -> usize {
+        let state = self.inner.lock();
+        state.allocated - state.num_idle()
+    }
+
+    /// Number of surfaces waiting in the pool to be reused.
+    pub fn idle(&self) -> usize {
+        self.inner.lock().num_idle()
+    }
+}
+
+impl Inner {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Leases an idle surface, or allocates one if the limit allows.
+    /// Hands the lock back if the caller has to wait.
+    fn take_or_reserve<'a>(
+        self: &'a Arc<Self>,
+        mut state: MutexGuard<'a, State>,
+        key: &SurfaceKey,
+    ) -> Result<VaResult<SurfaceLease>, MutexGuard<'a, State>> {
+        if let Some(surface) = state.idle.get_mut(key).and_then(Vec::pop) {
+            return Ok(Ok(self.lease(surface, key, state.generation)));
+        }
+        state.evict_for(key, 1);
+        if state.limit.is_some_and(|limit| state.allocated >= limit) {
+            return Err(state);
+        }
+        state.allocated += 1;
+        let generation = state.generation;
+        // Allocate without holding the lock; the slot is already reserved.
+        drop(state);
+        let surface = Surface::new(
+            self.display.clone(),
+            key.format,
+            key.width,
+            key.height,
+            key.pixel_format,
+            key.usage_hint,
+        );
+        Ok(match surface {
+            Ok(surface) => Ok(self.lease(surface, key, generation)),
+            Err(err) => {
+                self.release_slot();
+                Err(err)
+            }
+        })
+    }
+
+    fn release_slot(&self) {
+        self.lock().allocated -= 1;
+        self.returned.notify_one();
+    }
+
+    fn lease(
+        self: &Arc<Self>,
+        surface: Arc<Surface>,
+        key: &SurfaceKey,
+        generation: u64,
+    ) -> SurfaceLease {
+        SurfaceLease {
+            surface: Some(surface),
+            key: *key,
+            generation,
+            pool: self.clone(),
+        }
+    }
+}
+
+impl SurfaceLease {
+    pub fn surface(&self) -> &Arc<Surface> {
+        self.surface.as_ref().unwrap()
+    }
+
+    pub fn key(&self) -> &SurfaceKey {
+        &self.key
+    }
+
+    /// Takes the surface out of the pool for good. It no longer counts
+    /// against the pool's limit.
+    pub fn detach(mut self) -> Arc<Surface> {
+        let surface = self.surface.take().unwrap();
+        self.pool.release_slot();
+        surface
+    }
+}
+
+impl std::ops::Deref for SurfaceLease {
+    type Target = Arc<Surface>;
+
+    fn deref(&self) -> &Self::Target {
+        self.surface()
+    }
+}
+
+impl Drop for SurfaceLease {
+    fn drop(&mut self) {
+        let Some(surface) = self.surface.take() else {
+            return;
+        };
+        let mut state = self.pool.lock();
+        let over_limit = state.limit.is_some_and(|limit| state.allocated > limit);
+        // Surfaces leased before a `clear` are destroyed rather than reused.
+        if over_limit || self.generation != state.generation {
+            state.allocated -= 1;
+        } else {
+            state.idle.entry(self.key).or_default().push(surface);
+        }
+        self.pool.returned.notify_one();
+    }
+}
+
+impl std::fmt::Debug for SurfaceLease {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SurfaceLease")
+            .field("surface", &self.surface().handle())
+            .field("key", &self.key)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake;
+
+    fn key(width: u32, height: u32) -> SurfaceKey {
+        SurfaceKey {
+            format: RtFormat::YUV420,
+            width,
+            height,
+            pixel_format: Some(Fourcc::NV12),
+            usage_hint: UsageHint::DECODER,
+        }
+    }
+
+    fn setup(limit: Option<usize>) -> (fake::FakeBackend, SurfacePool) {
+        let fake = fake::FakeBackend::default();
+        let display = fake::open_display(&fake.library()).unwrap();
+        (fake, SurfacePool::new(display, limit))
+    }
+
+    #[test]
+    fn reuses_surfaces() {
+        let (fake, pool) = setup(None);
+        let small = key(64, 64);
+        let handle = pool.acquire(&small).unwrap().handle();
+        assert_eq!(pool.idle(), 1);
+        let lease = pool.acquire(&small).unwrap();
+        assert_eq!(lease.handle(), handle);
+        assert_eq!((lease.width(), lease.height()), (64, 64));
+        let other = pool.acquire(&key(128, 64)).unwrap();
+        assert_ne!(other.handle(), handle);
+        assert_eq!(pool.leased(), 2);
+        drop((lease, other));
+        assert_eq!(fake.live_objects().surfaces, 2);
+        drop(pool);
+        assert_eq!(fake.live_objects().surfaces, 0);
+    }
+
+    #[test]
+    fn limits_outstanding_surfaces() {
+        let (fake, pool) = setup(Some(2));
+        let small = key(64, 64);
+        let a = pool.acquire(&small).unwrap();
+        let _b = pool.acquire(&small).unwrap();
+        let err = pool.try_acquire(&small).unwrap_err();
+        assert_eq!(err.status(), Some(ErrorStatus::MaxNumExceeded));
+        let err = pool
+            .acquire_timeout(&small, Duration::from_millis(10))
+            .unwrap_err();
+        assert_eq!(err.status(), Some(ErrorStatus::TimedOut));
+
+        let handle = a.handle();
+        std::thread::scope(|scope| {
+            let waiter = scope.spawn(|| pool.acquire(&small).unwrap().handle());
+            std::thread::sleep(Duration::from_millis(10));
+            drop(a);
+            assert_eq!(waiter.join().unwrap(), handle);
+        });
+        assert_eq!(fake.live_objects().surfaces, 2);
+    }
+
+    #[test]
+    fn evicts_idle_surfaces_of_other_keys() {
+        let (fake, pool) = setup(Some(2));
+        pool.preallocate(&key(64, 64), 2).unwrap();
+        assert_eq!(pool.idle(), 2);
+        let large = pool.try_acquire(&key(128, 128)).unwrap();
+        assert_eq!(pool.idle(), 1);
+        assert_eq!(fake.live_objects().surfaces, 2);
+        drop(large);
+
+        pool.set_limit(Some(1));
+        assert_eq!(fake.live_objects().surfaces, 1);
+    }
+
+    #[test]
+    fn clear_drops_stale_surfaces() {
+        let (fake, pool) = setup(Some(4));
+        let old = pool.acquire(&key(64, 64)).unwrap();
+        pool.preallocate(&key(64, 64), 2).unwrap();
+        pool.clear();
+        assert_eq!(fake.live_objects().surfaces, 1);
+        drop(old);
+        assert_eq!(fake.live_objects().surfaces, 0);
+        assert_eq!((pool.leased(), pool.idle()), (0, 0));
+
+        let detached = pool.acquire(&key(32, 32)).unwrap().detach();
+        assert_eq!(pool.leased(), 0);
+        assert_eq!(fake.live_objects().surfaces, 1);
+        drop(detached);
+        assert_eq!(fake.live_objects().surfaces, 0);
+    }
+}
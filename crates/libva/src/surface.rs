@@ -1,14 +1,19 @@
 use paste::paste;
 use std::sync::Arc;
+use std::time::Duration;
 
 use crate::{
-    sys, va_bitflags, ConfigAttributes, Display, Fourcc, Image, Library, RtFormat, VaResult,
+    sys, Display, Error, ErrorStatus, Fourcc, Image, Library, ObjectId, RtFormat, VaResult,
     VaStatusExt,
 };
 
 pub struct Surface {
     handle: sys::VASurfaceID,
     display: Arc<Display>,
+    format: RtFormat,
+    width: u32,
+    height: u32,
+    pixel_format: Option<Fourcc>,
 }
 
 impl Surface {
@@ -49,7 +54,7 @@ impl Surface {
                     raw_attributes_list.as_mut_ptr(),
                     raw_attributes_list.len() as _,
                 )
-                .va_result()?;
+                .va_check(display.library(), "vaCreateSurfaces", &[])?;
         };
         handles
             .into_iter()
@@ -57,6 +62,10 @@ impl Surface {
                 Ok(Arc::new(Self {
                     handle,
                     display: display.clone(),
+                    format,
+                    width,
+                    height,
+                    pixel_format,
                 }))
             })
             .collect()
@@ -78,6 +87,34 @@ impl Surface {
         )
     }
 
+    /// Creates a surface for `pixel_format`, deriving the render target format
+    /// from the format registry.
+    pub fn with_pixel_format(
+        display: Arc<Display>,
+        pixel_format: Fourcc,
+        width: u32,
+        height: u32,
+        usage_hint: UsageHint,
+    ) -> VaResult<Arc<Self>> {
+        let format = pixel_format
+            .info()
+            .map(|info| info.rt_format)
+            .ok_or_else(|| {
+                Error::new(
+                    "Surface::with_pixel_format",
+                    ErrorStatus::InvalidImageFormat,
+                )
+            })?;
+        Self::new(
+            display,
+            format,
+            width,
+            height,
+            Some(pixel_format),
+            usage_hint,
+        )
+    }
+
     pub fn handle(&self) -> sys::VASurfaceID {
         self.handle
     }
@@ -90,15 +127,148 @@ impl Surface {
         &self.display
     }
 
-    pub fn derive_image(&self) -> VaResult<Arc<Image>> {
+    pub fn format(&self) -> RtFormat {
+        self.format
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// The pixel format requested at creation, if any.
+    pub fn pixel_format(&self) -> Option<Fourcc> {
+        self.pixel_format
+    }
+
+    /// Blocks until all pending operations on the surface have completed.
+    pub fn sync(&self) -> VaResult<()> {
+        unsafe {
+            self.library()
+                .lib()
+                .vaSyncSurface(self.display().handle(), self.handle())
+                .va_check(
+                    self.library(),
+                    "vaSyncSurface",
+                    &[ObjectId::Surface(self.handle())],
+                )
+        }
+    }
+
+    /// Waits up to `timeout` for pending operations on the surface to
+    /// complete. Returns `false` if they are still running.
+    pub fn sync_timeout(&self, timeout: Duration) -> VaResult<bool> {
+        let status = unsafe {
+            self.library().lib().vaSyncSurface2(
+                self.display().handle(),
+                self.handle(),
+                timeout.as_nanos().try_into().unwrap_or(u64::MAX),
+            )
+        };
+        if ErrorStatus::from_status(status) == Some(ErrorStatus::TimedOut) {
+            return Ok(false);
+        }
+        status.va_check(
+            self.library(),
+            "vaSyncSurface2",
+            &[ObjectId::Surface(self.handle())],
+        )?;
+        Ok(true)
+    }
+
+    /// Copies a rectangle of the surface into `image`, waiting until no map
+    /// of the image is alive.
+    pub fn get_image(
+        &self,
+        image: &Image,
+        x: i32,
+        y: i32,
+        width: u32,
+        height: u32,
+    ) -> VaResult<()> {
+        let _access = image.buffer().lock_exclusive("vaGetImage")?;
+        unsafe {
+            self.library()
+                .lib()
+                .vaGetImage(
+                    self.display().handle(),
+                    self.handle(),
+                    x,
+                    y,
+                    width,
+                    height,
+                    image.handle(),
+                )
+                .va_check(
+                    self.library(),
+                    "vaGetImage",
+                    &[
+                        ObjectId::Surface(self.handle()),
+                        ObjectId::Image(image.handle()),
+                    ],
+                )
+        }
+    }
+
+    /// Copies the top-left `width`x`height` rectangle of `image` into the
+    /// surface at `(x, y)`, waiting while the image is mapped for writing.
+    pub fn put_image(
+        &self,
+        image: &Image,
+        x: i32,
+        y: i32,
+        width: u32,
+        height: u32,
+    ) -> VaResult<()> {
+        let _access = image.buffer().lock_shared("vaPutImage")?;
+        unsafe {
+            self.library()
+                .lib()
+                .vaPutImage(
+                    self.display().handle(),
+                    self.handle(),
+                    image.handle(),
+                    0,
+                    0,
+                    width,
+                    height,
+                    x,
+                    y,
+                    width,
+                    height,
+                )
+                .va_check(
+                    self.library(),
+                    "vaPutImage",
+                    &[
+                        ObjectId::Surface(self.handle()),
+                        ObjectId::Image(image.handle()),
+                    ],
+                )
+        }
+    }
+
+    /// Maps the surface memory as an image, which keeps the surface alive.
+    pub fn derive_image(self: &Arc<Self>) -> VaResult<Arc<Image>> {
         let mut raw_image = sys::VAImage::default();
         unsafe {
             self.library()
                 .lib()
                 .vaDeriveImage(self.display().handle(), self.handle(), &mut raw_image)
-                .va_result()?;
+                .va_check(
+                    self.library(),
+                    "vaDeriveImage",
+                    &[ObjectId::Surface(self.handle())],
+                )?;
         }
-        Ok(Image::from_raw(self.display().clone(), raw_image))
+        Ok(Image::from_raw(
+            self.display().clone(),
+            raw_image,
+            Some(self.clone()),
+        ))
     }
 }
 
@@ -175,6 +345,7 @@ impl GenericValue for Fourcc {
 macro_rules! va_surface_attribs {
     {$name:ident ; $($type_name:ident : $attrib_name:ident : $attrib_type:ty ,)*} => {
         #[derive(Debug, Clone, Eq, PartialEq, Default)]
+        #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
         pub struct $name {
             $(
                 pub $attrib_name: Option<$attrib_type>,
@@ -202,21 +373,24 @@ macro_rules! va_surface_attribs {
                 //     result
                 // }
 
+                /// # Safety
+                ///
+                /// Every attribute value must be initialized for its type tag.
                 pub unsafe fn from_raw_attrib_list(attrs: &[sys::VASurfaceAttrib]) -> Self {
                     let mut result = Self::default();
                     for attr in attrs {
                         match attr.type_ {
                             $(
-                                sys::[<VASurfaceAttrib $type_name>] => {
-                                    if attr.flags != sys::VA_SURFACE_ATTRIB_NOT_SUPPORTED {
-                                        result.$attrib_name = <$attrib_type as GenericValue>::from_raw(attr.value);
-                                    }
+                                sys::[<VASurfaceAttrib $type_name>]
+                                    if attr.flags != sys::VA_SURFACE_ATTRIB_NOT_SUPPORTED =>
+                                {
+                                    result.$attrib_name = <$attrib_type as GenericValue>::from_raw(attr.value);
                                 }
                             )*
-                            sys::VASurfaceAttribPixelFormat => {
-                                if attr.flags != sys::VA_SURFACE_ATTRIB_NOT_SUPPORTED {
-                                    result.pixel_formats.push(Fourcc::from_raw(attr.value).unwrap());
-                                }
+                            sys::VASurfaceAttribPixelFormat
+                                if attr.flags != sys::VA_SURFACE_ATTRIB_NOT_SUPPORTED =>
+                            {
+                                result.pixel_formats.push(Fourcc::from_raw(attr.value).unwrap());
                             }
                             _ => {}
                         }
@@ -234,6 +408,7 @@ macro_rules! va_surface_bitflags {
             ::bitflags::bitflags! {
                     #[repr(transparent)]
                     #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+                    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
                     pub struct $name: u32 {
                         $(
                             const $elem_name = sys::[<$prefix $elem_name>] as u32;
@@ -0,0 +1,2241 @@
+//! Recording and replaying VA calls.
+//!
+//! [`TracingBackend`] wraps another [`Backend`] and writes every call, its
+//! arguments, its return status and the contents of mapped buffers to a
+//! compact binary trace. [`Trace::replay`] feeds a recording back into a
+//! real or fake backend and reports where the results diverge.
+//!
+//! Buffers created with `vaCreateBuffer` are recorded byte for byte when
+//! they are unmapped. Image buffers are recorded in full only when the
+//! client modified them while mapped; otherwise only a checksum of the
+//! visible pixels is kept, which replay compares against.
+
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::{c_char, c_int, c_uint, c_void};
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::os::fd::{AsRawFd, BorrowedFd};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+use crate::{sys, Backend, ErrorStatus, Fourcc, Library, ObjectId, Plane, PlaneLayout};
+
+const MAGIC: &[u8; 4] = b"VATR";
+const VERSION: u64 = 1;
+const SUCCESS: sys::VAStatus = sys::VA_STATUS_SUCCESS as sys::VAStatus;
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+trait Field: Sized {
+    fn encode(&self, out: &mut Vec<u8>);
+    fn decode(input: &mut &[u8]) -> io::Result<Self>;
+}
+
+fn take_byte(input: &mut &[u8]) -> io::Result<u8> {
+    let (&byte, rest) = input
+        .split_first()
+        .ok_or_else(|| invalid("truncated record"))?;
+    *input = rest;
+    Ok(byte)
+}
+
+impl Field for u64 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        let mut value = *self;
+        while value >= 0x80 {
+            out.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        out.push(value as u8);
+    }
+
+    fn decode(input: &mut &[u8]) -> io::Result<Self> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = take_byte(input)?;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(invalid("varint too long"))
+    }
+}
+
+impl Field for i64 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (((*self << 1) ^ (*self >> 63)) as u64).encode(out)
+    }
+
+    fn decode(input: &mut &[u8]) -> io::Result<Self> {
+        let value = u64::decode(input)?;
+        Ok((value >> 1) as i64 ^ -((value & 1) as i64))
+    }
+}
+
+impl Field for u32 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (*self as u64).encode(out)
+    }
+
+    fn decode(input: &mut &[u8]) -> io::Result<Self> {
+        u32::try_from(u64::decode(input)?).map_err(|_| invalid("value out of range"))
+    }
+}
+
+impl Field for i32 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (*self as i64).encode(out)
+    }
+
+    fn decode(input: &mut &[u8]) -> io::Result<Self> {
+        i32::try_from(i64::decode(input)?).map_err(|_| invalid("value out of range"))
+    }
+}
+
+impl Field for Vec<u8> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (self.len() as u64).encode(out);
+        out.extend_from_slice(self);
+    }
+
+    fn decode(input: &mut &[u8]) -> io::Result<Self> {
+        let len = u64::decode(input)? as usize;
+        if input.len() < len {
+            return Err(invalid("truncated record"));
+        }
+        let (bytes, rest) = input.split_at(len);
+        *input = rest;
+        Ok(bytes.to_vec())
+    }
+}
+
+impl Field for String {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.as_bytes().to_vec().encode(out)
+    }
+
+    fn decode(input: &mut &[u8]) -> io::Result<Self> {
+        String::from_utf8(Vec::decode(input)?).map_err(|_| invalid("invalid string"))
+    }
+}
+
+impl<A: Field, B: Field> Field for (A, B) {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.0.encode(out);
+        self.1.encode(out);
+    }
+
+    fn decode(input: &mut &[u8]) -> io::Result<Self> {
+        Ok((A::decode(input)?, B::decode(input)?))
+    }
+}
+
+impl<T: Field> Field for Option<T> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            None => out.push(0),
+            Some(value) => {
+                out.push(1);
+                value.encode(out);
+            }
+        }
+    }
+
+    fn decode(input: &mut &[u8]) -> io::Result<Self> {
+        match take_byte(input)? {
+            0 => Ok(None),
+            1 => Ok(Some(T::decode(input)?)),
+            _ => Err(invalid("invalid option tag")),
+        }
+    }
+}
+
+macro_rules! vec_field {
+    ($($ty:ty),*) => {
+        $(
+            impl Field for Vec<$ty> {
+                fn encode(&self, out: &mut Vec<u8>) {
+                    (self.len() as u64).encode(out);
+                    for item in self {
+                        item.encode(out);
+                    }
+                }
+
+                fn decode(input: &mut &[u8]) -> io::Result<Self> {
+                    let len = u64::decode(input)? as usize;
+                    // Every element takes at least one byte.
+                    if input.len() < len {
+                        return Err(invalid("truncated record"));
+                    }
+                    (0..len).map(|_| <$ty>::decode(input)).collect()
+                }
+            }
+        )*
+    };
+}
+
+vec_field!(u32, i64, (u32, u32), (u32, i32));
+
+/// What was in a buffer when it was unmapped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Contents {
+    /// Not recorded, e.g. coded buffers.
+    None,
+    /// The buffer bytes. For images, the visible rows of every plane
+    /// without padding.
+    Data(Vec<u8>),
+    /// FNV-1a checksum of the visible rows of an unmodified image.
+    Checksum(u64),
+}
+
+impl Field for Contents {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Contents::None => out.push(0),
+            Contents::Data(data) => {
+                out.push(1);
+                data.encode(out);
+            }
+            Contents::Checksum(sum) => {
+                out.push(2);
+                sum.encode(out);
+            }
+        }
+    }
+
+    fn decode(input: &mut &[u8]) -> io::Result<Self> {
+        match take_byte(input)? {
+            0 => Ok(Contents::None),
+            1 => Ok(Contents::Data(Vec::decode(input)?)),
+            2 => Ok(Contents::Checksum(u64::decode(input)?)),
+            _ => Err(invalid("invalid contents tag")),
+        }
+    }
+}
+
+/// The fields of a `VAImageFormat`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RecordedImageFormat {
+    pub fourcc: u32,
+    pub byte_order: u32,
+    pub bits_per_pixel: u32,
+    pub depth: u32,
+    pub red_mask: u32,
+    pub green_mask: u32,
+    pub blue_mask: u32,
+    pub alpha_mask: u32,
+}
+
+impl From<&sys::VAImageFormat> for RecordedImageFormat {
+    fn from(format: &sys::VAImageFormat) -> Self {
+        Self {
+            fourcc: format.fourcc,
+            byte_order: format.byte_order,
+            bits_per_pixel: format.bits_per_pixel,
+            depth: format.depth,
+            red_mask: format.red_mask,
+            green_mask: format.green_mask,
+            blue_mask: format.blue_mask,
+            alpha_mask: format.alpha_mask,
+        }
+    }
+}
+
+impl RecordedImageFormat {
+    fn to_raw(self) -> sys::VAImageFormat {
+        sys::VAImageFormat {
+            fourcc: self.fourcc,
+            byte_order: self.byte_order,
+            bits_per_pixel: self.bits_per_pixel,
+            depth: self.depth,
+            red_mask: self.red_mask,
+            green_mask: self.green_mask,
+            blue_mask: self.blue_mask,
+            alpha_mask: self.alpha_mask,
+            va_reserved: [0; 4],
+        }
+    }
+}
+
+impl Field for RecordedImageFormat {
+    fn encode(&self, out: &mut Vec<u8>) {
+        for value in [
+            self.fourcc,
+            self.byte_order,
+            self.bits_per_pixel,
+            self.depth,
+            self.red_mask,
+            self.green_mask,
+            self.blue_mask,
+            self.alpha_mask,
+        ] {
+            value.encode(out);
+        }
+    }
+
+    fn decode(input: &mut &[u8]) -> io::Result<Self> {
+        Ok(Self {
+            fourcc: u32::decode(input)?,
+            byte_order: u32::decode(input)?,
+            bits_per_pixel: u32::decode(input)?,
+            depth: u32::decode(input)?,
+            red_mask: u32::decode(input)?,
+            green_mask: u32::decode(input)?,
+            blue_mask: u32::decode(input)?,
+            alpha_mask: u32::decode(input)?,
+        })
+    }
+}
+
+macro_rules! trace_calls {
+    {$($tag:literal => $name:ident { $($field:ident: $ty:ty),* $(,)? },)*} => {
+        /// A recorded VA call, named after the entry point without its `va`
+        /// prefix. Object IDs are the ones seen during recording.
+        #[derive(Debug, Clone, PartialEq, Eq)]
+        pub enum TraceCall {
+            $($name { $($field: $ty),* },)*
+        }
+
+        impl TraceCall {
+            fn name(&self) -> &'static str {
+                match self {
+                    $(TraceCall::$name { .. } => concat!("va", stringify!($name)),)*
+                }
+            }
+
+            fn encode(&self, out: &mut Vec<u8>) {
+                match self {
+                    $(TraceCall::$name { $($field),* } => {
+                        out.push($tag);
+                        $(Field::encode($field, out);)*
+                    })*
+                }
+            }
+
+            fn decode(input: &mut &[u8]) -> io::Result<Self> {
+                match take_byte(input)? {
+                    $($tag => Ok(TraceCall::$name { $($field: Field::decode(input)?),* }),)*
+                    _ => Err(invalid("unknown call")),
+                }
+            }
+        }
+    };
+}
+
+trace_calls! {
+    0 => GetDisplayDRM {},
+    1 => Initialize { major: i32, minor: i32 },
+    2 => Terminate {},
+    3 => CreateConfig { profile: i32, entrypoint: u32, attribs: Vec<(u32, u32)>, config: u32 },
+    4 => DestroyConfig { config: u32 },
+    5 => CreateSurfaces { format: u32, width: u32, height: u32, attribs: Vec<(u32, i32)>, surfaces: Vec<u32> },
+    6 => DestroySurfaces { surfaces: Vec<u32> },
+    7 => CreateContext { config: u32, width: i32, height: i32, flag: i32, targets: Vec<u32>, context: u32 },
+    8 => DestroyContext { context: u32 },
+    9 => CreateBuffer { context: u32, buffer_type: u32, size: u32, num_elements: u32, data: Option<Vec<u8>>, buffer: u32 },
+    10 => BufferSetNumElements { buffer: u32, num_elements: u32 },
+    11 => MapBuffer { buffer: u32 },
+    12 => UnmapBuffer { buffer: u32, contents: Contents },
+    13 => DestroyBuffer { buffer: u32 },
+    14 => BeginPicture { context: u32, target: u32 },
+    15 => RenderPicture { context: u32, buffers: Vec<u32> },
+    16 => EndPicture { context: u32 },
+    17 => SyncSurface { surface: u32 },
+    18 => SyncSurface2 { surface: u32, timeout_ns: u64 },
+    19 => QuerySurfaceStatus { surface: u32, surface_status: u32 },
+    20 => SyncBuffer { buffer: u32, timeout_ns: u64 },
+    21 => CreateImage { format: RecordedImageFormat, width: i32, height: i32, image: u32, buffer: u32 },
+    22 => DestroyImage { image: u32 },
+    23 => DeriveImage { surface: u32, image: u32, buffer: u32 },
+    24 => GetImage { surface: u32, x: i32, y: i32, width: u32, height: u32, image: u32 },
+    25 => PutImage { surface: u32, image: u32, src: (i32, i32), src_size: (u32, u32), dest: (i32, i32), dest_size: (u32, u32) },
+    26 => Query { operation: String, args: Vec<i64> },
+}
+
+impl TraceCall {
+    /// Name of the VA entry point.
+    pub fn operation(&self) -> &str {
+        match self {
+            TraceCall::Query { operation, .. } => operation,
+            _ => self.name(),
+        }
+    }
+}
+
+/// One call in a trace.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceRecord {
+    /// Index of the display in the order displays were opened.
+    pub display: u32,
+    pub status: sys::VAStatus,
+    pub call: TraceCall,
+}
+
+impl TraceRecord {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.display.encode(out);
+        self.status.encode(out);
+        self.call.encode(out);
+    }
+
+    fn decode(mut input: &[u8]) -> io::Result<Self> {
+        let record = Self {
+            display: u32::decode(&mut input)?,
+            status: i32::decode(&mut input)?,
+            call: TraceCall::decode(&mut input)?,
+        };
+        if !input.is_empty() {
+            return Err(invalid("trailing bytes in record"));
+        }
+        Ok(record)
+    }
+}
+
+/// A recording read back from a trace file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Trace {
+    records: Vec<TraceRecord>,
+}
+
+impl Trace {
+    pub fn read(mut reader: impl Read) -> io::Result<Self> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        let mut input = data
+            .strip_prefix(MAGIC)
+            .ok_or_else(|| invalid("not a VA trace"))?;
+        if u64::decode(&mut input)? != VERSION {
+            return Err(invalid("unsupported trace version"));
+        }
+        let mut records = Vec::new();
+        while !input.is_empty() {
+            let len = u64::decode(&mut input)? as usize;
+            if input.len() < len {
+                return Err(invalid("truncated record"));
+            }
+            let (record, rest) = input.split_at(len);
+            records.push(TraceRecord::decode(record)?);
+            input = rest;
+        }
+        Ok(Self { records })
+    }
+
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::read(File::open(path)?)
+    }
+
+    pub fn records(&self) -> &[TraceRecord] {
+        &self.records
+    }
+
+    /// Replays the trace against `library`, opening displays on `drm_fd`.
+    ///
+    /// Queries are not replayed. Calls on objects whose creation failed
+    /// during replay are still issued, with `VA_INVALID_ID` in place of the
+    /// missing object.
+    pub fn replay(&self, library: &Library, drm_fd: BorrowedFd) -> ReplayReport {
+        let mut replay = Replay {
+            lib: library.lib(),
+            drm_fd: drm_fd.as_raw_fd(),
+            displays: HashMap::new(),
+            ids: HashMap::new(),
+            objects: Objects::default(),
+            report: ReplayReport::default(),
+        };
+        for (index, record) in self.records.iter().enumerate() {
+            match unsafe { replay.step(index, record) } {
+                Some(status) => {
+                    replay.report.replayed += 1;
+                    if status != record.status {
+                        replay.report.mismatches.push(Mismatch {
+                            index,
+                            operation: record.call.operation().to_string(),
+                            kind: MismatchKind::Status {
+                                recorded: record.status,
+                                replayed: status,
+                            },
+                        });
+                    }
+                }
+                None => replay.report.skipped += 1,
+            }
+        }
+        replay.report
+    }
+}
+
+/// The outcome of [`Trace::replay`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReplayReport {
+    pub replayed: usize,
+    pub skipped: usize,
+    pub mismatches: Vec<Mismatch>,
+}
+
+impl ReplayReport {
+    pub fn is_clean(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+}
+
+/// A difference between a recorded call and its replay.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    /// Index of the record in [`Trace::records`].
+    pub index: usize,
+    pub operation: String,
+    pub kind: MismatchKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MismatchKind {
+    Status {
+        recorded: sys::VAStatus,
+        replayed: sys::VAStatus,
+    },
+    /// The checksums of an image read back by the client differ.
+    Contents { recorded: u64, replayed: u64 },
+}
+
+impl std::fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let status = |status| match ErrorStatus::from_status(status) {
+            Some(error) => format!("{:?}", error),
+            None => "Success".to_string(),
+        };
+        write!(f, "#{} {}: ", self.index, self.operation)?;
+        match self.kind {
+            MismatchKind::Status { recorded, replayed } => write!(
+                f,
+                "recorded {}, replayed {}",
+                status(recorded),
+                status(replayed)
+            ),
+            MismatchKind::Contents { recorded, replayed } => write!(
+                f,
+                "image checksum {:016x}, replayed {:016x}",
+                recorded, replayed
+            ),
+        }
+    }
+}
+
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+
+fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// What the tracer knows about a buffer.
+#[derive(Debug, Clone, Copy)]
+enum BufferKind {
+    Data {
+        element_size: usize,
+        num_elements: usize,
+    },
+    Image(ImageLayout),
+    Opaque,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ImageLayout {
+    fourcc: Fourcc,
+    width: u32,
+    height: u32,
+    bytes_per_pixel: usize,
+    num_planes: usize,
+    pitches: [u32; 3],
+    offsets: [u32; 3],
+    data_size: usize,
+}
+
+impl ImageLayout {
+    fn new(image: &sys::VAImage) -> Self {
+        Self {
+            fourcc: image.format.fourcc.into(),
+            width: image.width as u32,
+            height: image.height as u32,
+            bytes_per_pixel: image.format.bits_per_pixel.div_ceil(8) as usize,
+            num_planes: (image.num_planes as usize).min(3),
+            pitches: image.pitches,
+            offsets: image.offsets,
+            data_size: image.data_size as usize,
+        }
+    }
+
+    fn plane(&self, i: usize) -> PlaneLayout {
+        PlaneLayout::for_plane(
+            self.fourcc,
+            i,
+            self.width,
+            self.height,
+            self.offsets[i] as usize,
+            self.pitches[i] as usize,
+            self.bytes_per_pixel,
+        )
+    }
+
+    /// Calls `f` with the visible rows of every plane, or with the whole
+    /// buffer if the layout is not understood.
+    fn for_each_row(&self, data: &[u8], mut f: impl FnMut(&[u8])) {
+        let planes: Option<Vec<_>> = (0..self.num_planes)
+            .map(|i| {
+                let layout = self.plane(i);
+                Plane::with_layout(data.get(layout.offset..)?, layout)
+            })
+            .collect();
+        match planes {
+            Some(planes) if self.fourcc.info().is_some() => {
+                planes.iter().flat_map(|plane| plane.rows()).for_each(f)
+            }
+            _ => f(data),
+        }
+    }
+
+    unsafe fn data<'a>(&self, ptr: *const u8) -> &'a [u8] {
+        std::slice::from_raw_parts(ptr, self.data_size)
+    }
+
+    unsafe fn checksum(&self, ptr: *const u8) -> u64 {
+        let mut hash = FNV_OFFSET;
+        self.for_each_row(self.data(ptr), |row| hash = fnv1a(hash, row));
+        hash
+    }
+
+    unsafe fn pack(&self, ptr: *const u8) -> Vec<u8> {
+        let mut packed = Vec::new();
+        self.for_each_row(self.data(ptr), |row| packed.extend_from_slice(row));
+        packed
+    }
+
+    unsafe fn unpack(&self, ptr: *mut u8, mut packed: &[u8]) {
+        // Rows are visited in the same order as `pack`, so compute their
+        // offsets first and then copy.
+        let base = ptr as usize;
+        let mut rows = Vec::new();
+        self.for_each_row(self.data(ptr), |row| {
+            rows.push((row.as_ptr() as usize - base, row.len()))
+        });
+        for (offset, len) in rows {
+            let len = len.min(packed.len());
+            std::ptr::copy_nonoverlapping(packed.as_ptr(), ptr.add(offset), len);
+            packed = &packed[len..];
+        }
+    }
+}
+
+struct Mapping {
+    ptr: usize,
+    /// Checksum of an image when it was mapped.
+    checksum: Option<u64>,
+}
+
+/// Buffers and images on each display, keyed by display index and the ID
+/// used by the backend being called.
+#[derive(Default)]
+struct Objects {
+    buffers: HashMap<(u32, u32), BufferKind>,
+    images: HashMap<(u32, u32), u32>,
+    mapped: HashMap<(u32, u32), Mapping>,
+}
+
+impl Objects {
+    fn add_image(&mut self, display: u32, image: &sys::VAImage) {
+        self.images.insert((display, image.image_id), image.buf);
+        self.buffers.insert(
+            (display, image.buf),
+            BufferKind::Image(ImageLayout::new(image)),
+        );
+    }
+
+    fn remove_image(&mut self, display: u32, image: u32) {
+        if let Some(buffer) = self.images.remove(&(display, image)) {
+            self.buffers.remove(&(display, buffer));
+        }
+    }
+
+    unsafe fn map(&mut self, display: u32, buffer: u32, ptr: *mut c_void) {
+        let checksum = match self.buffers.get(&(display, buffer)) {
+            Some(BufferKind::Image(layout)) => Some(layout.checksum(ptr as *const u8)),
+            _ => None,
+        };
+        self.mapped.insert(
+            (display, buffer),
+            Mapping {
+                ptr: ptr as usize,
+                checksum,
+            },
+        );
+    }
+
+    unsafe fn snapshot(&self, display: u32, buffer: u32) -> Contents {
+        let (Some(kind), Some(mapping)) = (
+            self.buffers.get(&(display, buffer)),
+            self.mapped.get(&(display, buffer)),
+        ) else {
+            return Contents::None;
+        };
+        let ptr = mapping.ptr as *const u8;
+        match *kind {
+            BufferKind::Data {
+                element_size,
+                num_elements,
+            } => Contents::Data(
+                std::slice::from_raw_parts(ptr, element_size * num_elements).to_vec(),
+            ),
+            BufferKind::Image(layout) => {
+                let checksum = layout.checksum(ptr);
+                if mapping.checksum == Some(checksum) {
+                    Contents::Checksum(checksum)
+                } else {
+                    Contents::Data(layout.pack(ptr))
+                }
+            }
+            BufferKind::Opaque => Contents::None,
+        }
+    }
+
+    fn set_num_elements(&mut self, display: u32, buffer: u32, count: u32) {
+        if let Some(BufferKind::Data { num_elements, .. }) =
+            self.buffers.get_mut(&(display, buffer))
+        {
+            *num_elements = count as usize;
+        }
+    }
+}
+
+fn buffer_kind(buffer_type: sys::VABufferType, size: c_uint, num_elements: c_uint) -> BufferKind {
+    if buffer_type == sys::VAEncCodedBufferType {
+        BufferKind::Opaque
+    } else {
+        BufferKind::Data {
+            element_size: size as usize,
+            num_elements: num_elements as usize,
+        }
+    }
+}
+
+unsafe fn slice<'a, T>(ptr: *const T, len: impl TryInto<usize>) -> &'a [T] {
+    match len.try_into() {
+        Ok(len) if !ptr.is_null() && len > 0 => std::slice::from_raw_parts(ptr, len),
+        _ => &[],
+    }
+}
+
+struct Recorder {
+    out: Box<dyn Write + Send>,
+    failed: bool,
+    /// Sequence number of the next record to write.
+    next_write: u64,
+    /// Records that wait for those of calls started before them.
+    pending: BTreeMap<u64, TraceRecord>,
+    next_display: u32,
+    displays: HashMap<usize, u32>,
+    objects: Objects,
+}
+
+impl Recorder {
+    fn display(&mut self, dpy: sys::VADisplay) -> u32 {
+        let next = &mut self.next_display;
+        *self.displays.entry(dpy as usize).or_insert_with(|| {
+            *next += 1;
+            *next - 1
+        })
+    }
+
+    /// Writes `record` as number `sequence` of the trace, once the records
+    /// before it are written.
+    fn submit(&mut self, sequence: u64, record: TraceRecord) {
+        self.pending.insert(sequence, record);
+        while let Some(record) = self.pending.remove(&self.next_write) {
+            self.write(&record);
+            self.next_write += 1;
+        }
+    }
+
+    fn write(&mut self, record: &TraceRecord) {
+        if self.failed {
+            return;
+        }
+        let mut payload = Vec::new();
+        record.encode(&mut payload);
+        let mut frame = Vec::with_capacity(payload.len() + 5);
+        (payload.len() as u64).encode(&mut frame);
+        frame.extend_from_slice(&payload);
+        // A trace that stops early is still useful, so keep the session
+        // running if the sink fails.
+        self.failed = self.out.write_all(&frame).is_err();
+    }
+}
+
+/// A [`Backend`] that records every call made through it.
+///
+/// Wrap it in a [`Library`] with [`Library::with_backend`]. The trace is
+/// flushed when a display is terminated and when the backend is dropped.
+/// Calls made from several threads are recorded in the order they started.
+pub struct TracingBackend {
+    inner: Box<dyn Backend>,
+    /// Sequence number of the next call.
+    sequence: AtomicU64,
+    recorder: Mutex<Recorder>,
+}
+
+impl TracingBackend {
+    pub fn new(
+        inner: impl Backend + 'static,
+        mut out: impl Write + Send + 'static,
+    ) -> io::Result<Self> {
+        out.write_all(MAGIC)?;
+        let mut version = Vec::new();
+        VERSION.encode(&mut version);
+        out.write_all(&version)?;
+        Ok(Self {
+            inner: Box::new(inner),
+            sequence: AtomicU64::new(0),
+            recorder: Mutex::new(Recorder {
+                out: Box::new(out),
+                failed: false,
+                next_write: 0,
+                pending: BTreeMap::new(),
+                next_display: 0,
+                displays: HashMap::new(),
+                objects: Objects::default(),
+            }),
+        })
+    }
+
+    /// Records to a new file at `path`.
+    pub fn create(inner: impl Backend + 'static, path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::new(inner, BufWriter::new(File::create(path)?))
+    }
+
+    fn recorder(&self) -> MutexGuard<'_, Recorder> {
+        self.recorder.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Takes the sequence number of a call about to be made. Every number
+    /// taken must be passed to [`Self::record`], or no later call is written.
+    fn reserve(&self) -> u64 {
+        self.sequence.fetch_add(1, Ordering::Relaxed)
+    }
+
+    fn record(
+        &self,
+        sequence: u64,
+        dpy: sys::VADisplay,
+        status: sys::VAStatus,
+        call: impl FnOnce(&mut Recorder, u32) -> TraceCall,
+    ) -> sys::VAStatus {
+        let mut recorder = self.recorder();
+        let display = recorder.display(dpy);
+        let call = call(&mut recorder, display);
+        recorder.submit(
+            sequence,
+            TraceRecord {
+                display,
+                status,
+                call,
+            },
+        );
+        status
+    }
+
+    fn query(
+        &self,
+        sequence: u64,
+        dpy: sys::VADisplay,
+        status: sys::VAStatus,
+        operation: &str,
+        args: Vec<i64>,
+    ) -> sys::VAStatus {
+        self.record(sequence, dpy, status, |_, _| TraceCall::Query {
+            operation: operation.to_string(),
+            args,
+        })
+    }
+}
+
+impl Drop for TracingBackend {
+    fn drop(&mut self) {
+        let _ = self.recorder().out.flush();
+    }
+}
+
+#[allow(non_snake_case)]
+impl Backend for TracingBackend {
+    unsafe fn vaErrorStr(&self, error_status: sys::VAStatus) -> *const c_char {
+        self.inner.vaErrorStr(error_status)
+    }
+
+    unsafe fn vaGetDisplayDRM(&self, fd: c_int) -> sys::VADisplay {
+        let sequence = self.reserve();
+        let dpy = self.inner.vaGetDisplayDRM(fd);
+        let mut recorder = self.recorder();
+        let display = recorder.next_display;
+        recorder.next_display += 1;
+        if !dpy.is_null() {
+            recorder.displays.insert(dpy as usize, display);
+        }
+        let status = if dpy.is_null() {
+            ErrorStatus::InvalidDisplay.to_raw()
+        } else {
+            SUCCESS
+        };
+        recorder.submit(
+            sequence,
+            TraceRecord {
+                display,
+                status,
+                call: TraceCall::GetDisplayDRM {},
+            },
+        );
+        dpy
+    }
+
+    unsafe fn vaInitialize(
+        &self,
+        dpy: sys::VADisplay,
+        major_version: *mut c_int,
+        minor_version: *mut c_int,
+    ) -> sys::VAStatus {
+        let sequence = self.reserve();
+        let status = self.inner.vaInitialize(dpy, major_version, minor_version);
+        self.record(sequence, dpy, status, |_, _| TraceCall::Initialize {
+            major: *major_version,
+            minor: *minor_version,
+        })
+    }
+
+    unsafe fn vaTerminate(&self, dpy: sys::VADisplay) -> sys::VAStatus {
+        let sequence = self.reserve();
+        let status = self.inner.vaTerminate(dpy);
+        self.record(sequence, dpy, status, |_, _| TraceCall::Terminate {});
+        let mut recorder = self.recorder();
+        recorder.displays.remove(&(dpy as usize));
+        let _ = recorder.out.flush();
+        status
+    }
+
+    unsafe fn vaQueryVendorString(&self, dpy: sys::VADisplay) -> *const c_char {
+        self.inner.vaQueryVendorString(dpy)
+    }
+
+    unsafe fn vaMaxNumProfiles(&self, dpy: sys::VADisplay) -> c_int {
+        self.inner.vaMaxNumProfiles(dpy)
+    }
+
+    unsafe fn vaMaxNumEntrypoints(&self, dpy: sys::VADisplay) -> c_int {
+        self.inner.vaMaxNumEntrypoints(dpy)
+    }
+
+    unsafe fn vaMaxNumConfigAttributes(&self, dpy: sys::VADisplay) -> c_int {
+        self.inner.vaMaxNumConfigAttributes(dpy)
+    }
+
+    unsafe fn vaQueryConfigProfiles(
+        &self,
+        dpy: sys::VADisplay,
+        profile_list: *mut sys::VAProfile,
+        num_profiles: *mut c_int,
+    ) -> sys::VAStatus {
+        let sequence = self.reserve();
+        let status = self
+            .inner
+            .vaQueryConfigProfiles(dpy, profile_list, num_profiles);
+        self.query(sequence, dpy, status, "vaQueryConfigProfiles", vec![])
+    }
+
+    unsafe fn vaQueryConfigEntrypoints(
+        &self,
+        dpy: sys::VADisplay,
+        profile: sys::VAProfile,
+        entrypoint_list: *mut sys::VAEntrypoint,
+        num_entrypoints: *mut c_int,
+    ) -> sys::VAStatus {
+        let sequence = self.reserve();
+        let status =
+            self.inner
+                .vaQueryConfigEntrypoints(dpy, profile, entrypoint_list, num_entrypoints);
+        self.query(
+            sequence,
+            dpy,
+            status,
+            "vaQueryConfigEntrypoints",
+            vec![profile as i64],
+        )
+    }
+
+    unsafe fn vaGetConfigAttributes(
+        &self,
+        dpy: sys::VADisplay,
+        profile: sys::VAProfile,
+        entrypoint: sys::VAEntrypoint,
+        attrib_list: *mut sys::VAConfigAttrib,
+        num_attribs: c_int,
+    ) -> sys::VAStatus {
+        let sequence = self.reserve();
+        let status =
+            self.inner
+                .vaGetConfigAttributes(dpy, profile, entrypoint, attrib_list, num_attribs);
+        self.query(
+            sequence,
+            dpy,
+            status,
+            "vaGetConfigAttributes",
+            vec![profile as i64, entrypoint as i64],
+        )
+    }
+
+    unsafe fn vaCreateConfig(
+        &self,
+        dpy: sys::VADisplay,
+        profile: sys::VAProfile,
+        entrypoint: sys::VAEntrypoint,
+        attrib_list: *mut sys::VAConfigAttrib,
+        num_attribs: c_int,
+        config_id: *mut sys::VAConfigID,
+    ) -> sys::VAStatus {
+        let sequence = self.reserve();
+        let status = self.inner.vaCreateConfig(
+            dpy,
+            profile,
+            entrypoint,
+            attrib_list,
+            num_attribs,
+            config_id,
+        );
+        self.record(sequence, dpy, status, |_, _| TraceCall::CreateConfig {
+            profile,
+            entrypoint,
+            attribs: slice(attrib_list, num_attribs)
+                .iter()
+                .map(|attrib| (attrib.type_, attrib.value))
+                .collect(),
+            config: if status == SUCCESS { *config_id } else { 0 },
+        })
+    }
+
+    unsafe fn vaDestroyConfig(
+        &self,
+        dpy: sys::VADisplay,
+        config_id: sys::VAConfigID,
+    ) -> sys::VAStatus {
+        let sequence = self.reserve();
+        let status = self.inner.vaDestroyConfig(dpy, config_id);
+        self.record(sequence, dpy, status, |_, _| TraceCall::DestroyConfig {
+            config: config_id,
+        })
+    }
+
+    unsafe fn vaQueryConfigAttributes(
+        &self,
+        dpy: sys::VADisplay,
+        config_id: sys::VAConfigID,
+        profile: *mut sys::VAProfile,
+        entrypoint: *mut sys::VAEntrypoint,
+        attrib_list: *mut sys::VAConfigAttrib,
+        num_attribs: *mut c_int,
+    ) -> sys::VAStatus {
+        let sequence = self.reserve();
+        let status = self.inner.vaQueryConfigAttributes(
+            dpy,
+            config_id,
+            profile,
+            entrypoint,
+            attrib_list,
+            num_attribs,
+        );
+        self.query(
+            sequence,
+            dpy,
+            status,
+            "vaQueryConfigAttributes",
+            vec![config_id as i64],
+        )
+    }
+
+    unsafe fn vaQuerySurfaceAttributes(
+        &self,
+        dpy: sys::VADisplay,
+        config: sys::VAConfigID,
+        attrib_list: *mut sys::VASurfaceAttrib,
+        num_attribs: *mut c_uint,
+    ) -> sys::VAStatus {
+        let sequence = self.reserve();
+        let status = self
+            .inner
+            .vaQuerySurfaceAttributes(dpy, config, attrib_list, num_attribs);
+        self.query(
+            sequence,
+            dpy,
+            status,
+            "vaQuerySurfaceAttributes",
+            vec![config as i64],
+        )
+    }
+
+    unsafe fn vaCreateSurfaces(
+        &self,
+        dpy: sys::VADisplay,
+        format: c_uint,
+        width: c_uint,
+        height: c_uint,
+        surfaces: *mut sys::VASurfaceID,
+        num_surfaces: c_uint,
+        attrib_list: *mut sys::VASurfaceAttrib,
+        num_attribs: c_uint,
+    ) -> sys::VAStatus {
+        let sequence = self.reserve();
+        let status = self.inner.vaCreateSurfaces(
+            dpy,
+            format,
+            width,
+            height,
+            surfaces,
+            num_surfaces,
+            attrib_list,
+            num_attribs,
+        );
+        self.record(sequence, dpy, status, |_, _| TraceCall::CreateSurfaces {
+            format,
+            width,
+            height,
+            // Pointer-valued attributes such as external buffer descriptors
+            // cannot be replayed and are left out.
+            attribs: slice(attrib_list, num_attribs)
+                .iter()
+                .filter(|attrib| attrib.value.type_ == sys::VAGenericValueTypeInteger)
+                .map(|attrib| (attrib.type_, attrib.value.value.i))
+                .collect(),
+            surfaces: if status == SUCCESS {
+                slice(surfaces, num_surfaces).to_vec()
+            } else {
+                Vec::new()
+            },
+        })
+    }
+
+    unsafe fn vaDestroySurfaces(
+        &self,
+        dpy: sys::VADisplay,
+        surfaces: *mut sys::VASurfaceID,
+        num_surfaces: c_int,
+    ) -> sys::VAStatus {
+        let sequence = self.reserve();
+        let ids = slice(surfaces, num_surfaces).to_vec();
+        let status = self.inner.vaDestroySurfaces(dpy, surfaces, num_surfaces);
+        self.record(sequence, dpy, status, |_, _| TraceCall::DestroySurfaces {
+            surfaces: ids,
+        })
+    }
+
+    unsafe fn vaCreateContext(
+        &self,
+        dpy: sys::VADisplay,
+        config_id: sys::VAConfigID,
+        picture_width: c_int,
+        picture_height: c_int,
+        flag: c_int,
+        render_targets: *mut sys::VASurfaceID,
+        num_render_targets: c_int,
+        context: *mut sys::VAContextID,
+    ) -> sys::VAStatus {
+        let sequence = self.reserve();
+        let status = self.inner.vaCreateContext(
+            dpy,
+            config_id,
+            picture_width,
+            picture_height,
+            flag,
+            render_targets,
+            num_render_targets,
+            context,
+        );
+        self.record(sequence, dpy, status, |_, _| TraceCall::CreateContext {
+            config: config_id,
+            width: picture_width,
+            height: picture_height,
+            flag,
+            targets: slice(render_targets, num_render_targets).to_vec(),
+            context: if status == SUCCESS { *context } else { 0 },
+        })
+    }
+
+    unsafe fn vaDestroyContext(
+        &self,
+        dpy: sys::VADisplay,
+        context: sys::VAContextID,
+    ) -> sys::VAStatus {
+        let sequence = self.reserve();
+        let status = self.inner.vaDestroyContext(dpy, context);
+        self.record(sequence, dpy, status, |_, _| TraceCall::DestroyContext {
+            context,
+        })
+    }
+
+    unsafe fn vaCreateBuffer(
+        &self,
+        dpy: sys::VADisplay,
+        context: sys::VAContextID,
+        type_: sys::VABufferType,
+        size: c_uint,
+        num_elements: c_uint,
+        data: *mut c_void,
+        buf_id: *mut sys::VABufferID,
+    ) -> sys::VAStatus {
+        let sequence = self.reserve();
+        let status =
+            self.inner
+                .vaCreateBuffer(dpy, context, type_, size, num_elements, data, buf_id);
+        self.record(sequence, dpy, status, |recorder, display| {
+            let buffer = if status == SUCCESS { *buf_id } else { 0 };
+            if status == SUCCESS {
+                recorder
+                    .objects
+                    .buffers
+                    .insert((display, buffer), buffer_kind(type_, size, num_elements));
+            }
+            TraceCall::CreateBuffer {
+                context,
+                buffer_type: type_,
+                size,
+                num_elements,
+                data: (!data.is_null()).then(|| {
+                    slice(data as *const u8, size as usize * num_elements as usize).to_vec()
+                }),
+                buffer,
+            }
+        })
+    }
+
+    unsafe fn vaBufferSetNumElements(
+        &self,
+        dpy: sys::VADisplay,
+        buf_id: sys::VABufferID,
+        num_elements: c_uint,
+    ) -> sys::VAStatus {
+        let sequence = self.reserve();
+        let status = self.inner.vaBufferSetNumElements(dpy, buf_id, num_elements);
+        self.record(sequence, dpy, status, |recorder, display| {
+            if status == SUCCESS {
+                recorder
+                    .objects
+                    .set_num_elements(display, buf_id, num_elements);
+            }
+            TraceCall::BufferSetNumElements {
+                buffer: buf_id,
+                num_elements,
+            }
+        })
+    }
+
+    unsafe fn vaMapBuffer(
+        &self,
+        dpy: sys::VADisplay,
+        buf_id: sys::VABufferID,
+        pbuf: *mut *mut c_void,
+    ) -> sys::VAStatus {
+        let sequence = self.reserve();
+        let status = self.inner.vaMapBuffer(dpy, buf_id, pbuf);
+        self.record(sequence, dpy, status, |recorder, display| {
+            if status == SUCCESS {
+                recorder.objects.map(display, buf_id, *pbuf);
+            }
+            TraceCall::MapBuffer { buffer: buf_id }
+        })
+    }
+
+    unsafe fn vaUnmapBuffer(&self, dpy: sys::VADisplay, buf_id: sys::VABufferID) -> sys::VAStatus {
+        let sequence = self.reserve();
+        let contents = {
+            let mut recorder = self.recorder();
+            let display = recorder.display(dpy);
+            recorder.objects.snapshot(display, buf_id)
+        };
+        let status = self.inner.vaUnmapBuffer(dpy, buf_id);
+        self.record(sequence, dpy, status, |recorder, display| {
+            if status == SUCCESS {
+                recorder.objects.mapped.remove(&(display, buf_id));
+            }
+            TraceCall::UnmapBuffer {
+                buffer: buf_id,
+                contents,
+            }
+        })
+    }
+
+    unsafe fn vaDestroyBuffer(
+        &self,
+        dpy: sys::VADisplay,
+        buffer_id: sys::VABufferID,
+    ) -> sys::VAStatus {
+        let sequence = self.reserve();
+        let status = self.inner.vaDestroyBuffer(dpy, buffer_id);
+        self.record(sequence, dpy, status, |recorder, display| {
+            if status == SUCCESS {
+                recorder.objects.buffers.remove(&(display, buffer_id));
+                recorder.objects.mapped.remove(&(display, buffer_id));
+            }
+            TraceCall::DestroyBuffer { buffer: buffer_id }
+        })
+    }
+
+    unsafe fn vaBeginPicture(
+        &self,
+        dpy: sys::VADisplay,
+        context: sys::VAContextID,
+        render_target: sys::VASurfaceID,
+    ) -> sys::VAStatus {
+        let sequence = self.reserve();
+        let status = self.inner.vaBeginPicture(dpy, context, render_target);
+        self.record(sequence, dpy, status, |_, _| TraceCall::BeginPicture {
+            context,
+            target: render_target,
+        })
+    }
+
+    unsafe fn vaRenderPicture(
+        &self,
+        dpy: sys::VADisplay,
+        context: sys::VAContextID,
+        buffers: *mut sys::VABufferID,
+        num_buffers: c_int,
+    ) -> sys::VAStatus {
+        let sequence = self.reserve();
+        let ids = slice(buffers, num_buffers).to_vec();
+        let status = self
+            .inner
+            .vaRenderPicture(dpy, context, buffers, num_buffers);
+        self.record(sequence, dpy, status, |_, _| TraceCall::RenderPicture {
+            context,
+            buffers: ids,
+        })
+    }
+
+    unsafe fn vaEndPicture(&self, dpy: sys::VADisplay, context: sys::VAContextID) -> sys::VAStatus {
+        let sequence = self.reserve();
+        let status = self.inner.vaEndPicture(dpy, context);
+        self.record(sequence, dpy, status, |_, _| TraceCall::EndPicture {
+            context,
+        })
+    }
+
+    unsafe fn vaSyncSurface(
+        &self,
+        dpy: sys::VADisplay,
+        render_target: sys::VASurfaceID,
+    ) -> sys::VAStatus {
+        let sequence = self.reserve();
+        let status = self.inner.vaSyncSurface(dpy, render_target);
+        self.record(sequence, dpy, status, |_, _| TraceCall::SyncSurface {
+            surface: render_target,
+        })
+    }
+
+    unsafe fn vaSyncSurface2(
+        &self,
+        dpy: sys::VADisplay,
+        surface: sys::VASurfaceID,
+        timeout_ns: u64,
+    ) -> sys::VAStatus {
+        let sequence = self.reserve();
+        let status = self.inner.vaSyncSurface2(dpy, surface, timeout_ns);
+        self.record(sequence, dpy, status, |_, _| TraceCall::SyncSurface2 {
+            surface,
+            timeout_ns,
+        })
+    }
+
+    unsafe fn vaQuerySurfaceStatus(
+        &self,
+        dpy: sys::VADisplay,
+        render_target: sys::VASurfaceID,
+        status: *mut sys::VASurfaceStatus,
+    ) -> sys::VAStatus {
+        let sequence = self.reserve();
+        let result = self.inner.vaQuerySurfaceStatus(dpy, render_target, status);
+        self.record(sequence, dpy, result, |_, _| {
+            TraceCall::QuerySurfaceStatus {
+                surface: render_target,
+                surface_status: if result == SUCCESS { *status } else { 0 },
+            }
+        })
+    }
+
+    unsafe fn vaSyncBuffer(
+        &self,
+        dpy: sys::VADisplay,
+        buf_id: sys::VABufferID,
+        timeout_ns: u64,
+    ) -> sys::VAStatus {
+        let sequence = self.reserve();
+        let status = self.inner.vaSyncBuffer(dpy, buf_id, timeout_ns);
+        self.record(sequence, dpy, status, |_, _| TraceCall::SyncBuffer {
+            buffer: buf_id,
+            timeout_ns,
+        })
+    }
+
+    unsafe fn vaMaxNumImageFormats(&self, dpy: sys::VADisplay) -> c_int {
+        self.inner.vaMaxNumImageFormats(dpy)
+    }
+
+    unsafe fn vaQueryImageFormats(
+        &self,
+        dpy: sys::VADisplay,
+        format_list: *mut sys::VAImageFormat,
+        num_formats: *mut c_int,
+    ) -> sys::VAStatus {
+        let sequence = self.reserve();
+        let status = self
+            .inner
+            .vaQueryImageFormats(dpy, format_list, num_formats);
+        self.query(sequence, dpy, status, "vaQueryImageFormats", vec![])
+    }
+
+    unsafe fn vaCreateImage(
+        &self,
+        dpy: sys::VADisplay,
+        format: *mut sys::VAImageFormat,
+        width: c_int,
+        height: c_int,
+        image: *mut sys::VAImage,
+    ) -> sys::VAStatus {
+        let sequence = self.reserve();
+        let status = self.inner.vaCreateImage(dpy, format, width, height, image);
+        self.record(sequence, dpy, status, |recorder, display| {
+            let (id, buffer) = if status == SUCCESS {
+                recorder.objects.add_image(display, &*image);
+                ((*image).image_id, (*image).buf)
+            } else {
+                (0, 0)
+            };
+            TraceCall::CreateImage {
+                format: (&*format).into(),
+                width,
+                height,
+                image: id,
+                buffer,
+            }
+        })
+    }
+
+    unsafe fn vaDestroyImage(&self, dpy: sys::VADisplay, image: sys::VAImageID) -> sys::VAStatus {
+        let sequence = self.reserve();
+        let status = self.inner.vaDestroyImage(dpy, image);
+        self.record(sequence, dpy, status, |recorder, display| {
+            if status == SUCCESS {
+                recorder.objects.remove_image(display, image);
+            }
+            TraceCall::DestroyImage { image }
+        })
+    }
+
+    unsafe fn vaGetImage(
+        &self,
+        dpy: sys::VADisplay,
+        surface: sys::VASurfaceID,
+        x: c_int,
+        y: c_int,
+        width: c_uint,
+        height: c_uint,
+        image: sys::VAImageID,
+    ) -> sys::VAStatus {
+        let sequence = self.reserve();
+        let status = self
+            .inner
+            .vaGetImage(dpy, surface, x, y, width, height, image);
+        self.record(sequence, dpy, status, |_, _| TraceCall::GetImage {
+            surface,
+            x,
+            y,
+            width,
+            height,
+            image,
+        })
+    }
+
+    unsafe fn vaPutImage(
+        &self,
+        dpy: sys::VADisplay,
+        surface: sys::VASurfaceID,
+        image: sys::VAImageID,
+        src_x: c_int,
+        src_y: c_int,
+        src_width: c_uint,
+        src_height: c_uint,
+        dest_x: c_int,
+        dest_y: c_int,
+        dest_width: c_uint,
+        dest_height: c_uint,
+    ) -> sys::VAStatus {
+        let sequence = self.reserve();
+        let status = self.inner.vaPutImage(
+            dpy,
+            surface,
+            image,
+            src_x,
+            src_y,
+            src_width,
+            src_height,
+            dest_x,
+            dest_y,
+            dest_width,
+            dest_height,
+        );
+        self.record(sequence, dpy, status, |_, _| TraceCall::PutImage {
+            surface,
+            image,
+            src: (src_x, src_y),
+            src_size: (src_width, src_height),
+            dest: (dest_x, dest_y),
+            dest_size: (dest_width, dest_height),
+        })
+    }
+
+    unsafe fn vaDeriveImage(
+        &self,
+        dpy: sys::VADisplay,
+        surface: sys::VASurfaceID,
+        image: *mut sys::VAImage,
+    ) -> sys::VAStatus {
+        let sequence = self.reserve();
+        let status = self.inner.vaDeriveImage(dpy, surface, image);
+        self.record(sequence, dpy, status, |recorder, display| {
+            let (id, buffer) = if status == SUCCESS {
+                recorder.objects.add_image(display, &*image);
+                ((*image).image_id, (*image).buf)
+            } else {
+                (0, 0)
+            };
+            TraceCall::DeriveImage {
+                surface,
+                image: id,
+                buffer,
+            }
+        })
+    }
+
+    unsafe fn vaMaxNumDisplayAttributes(&self, dpy: sys::VADisplay) -> c_int {
+        self.inner.vaMaxNumDisplayAttributes(dpy)
+    }
+
+    unsafe fn vaQueryDisplayAttributes(
+        &self,
+        dpy: sys::VADisplay,
+        attr_list: *mut sys::VADisplayAttribute,
+        num_attributes: *mut c_int,
+    ) -> sys::VAStatus {
+        let sequence = self.reserve();
+        let status = self
+            .inner
+            .vaQueryDisplayAttributes(dpy, attr_list, num_attributes);
+        self.query(sequence, dpy, status, "vaQueryDisplayAttributes", vec![])
+    }
+
+    unsafe fn vaGetDisplayAttributes(
+        &self,
+        dpy: sys::VADisplay,
+        attr_list: *mut sys::VADisplayAttribute,
+        num_attributes: c_int,
+    ) -> sys::VAStatus {
+        let sequence = self.reserve();
+        let types = slice(attr_list, num_attributes)
+            .iter()
+            .map(|attr| attr.type_ as i64)
+            .collect();
+        let status = self
+            .inner
+            .vaGetDisplayAttributes(dpy, attr_list, num_attributes);
+        self.query(sequence, dpy, status, "vaGetDisplayAttributes", types)
+    }
+
+    unsafe fn vaSetDisplayAttributes(
+        &self,
+        dpy: sys::VADisplay,
+        attr_list: *mut sys::VADisplayAttribute,
+        num_attributes: c_int,
+    ) -> sys::VAStatus {
+        let sequence = self.reserve();
+        let args = slice(attr_list, num_attributes)
+            .iter()
+            .flat_map(|attr| [attr.type_ as i64, attr.value as i64])
+            .collect();
+        let status = self
+            .inner
+            .vaSetDisplayAttributes(dpy, attr_list, num_attributes);
+        self.query(sequence, dpy, status, "vaSetDisplayAttributes", args)
+    }
+
+    unsafe fn vaQueryVideoProcFilters(
+        &self,
+        dpy: sys::VADisplay,
+        context: sys::VAContextID,
+        filters: *mut sys::VAProcFilterType,
+        num_filters: *mut c_uint,
+    ) -> sys::VAStatus {
+        let sequence = self.reserve();
+        let status = self
+            .inner
+            .vaQueryVideoProcFilters(dpy, context, filters, num_filters);
+        self.query(
+            sequence,
+            dpy,
+            status,
+            "vaQueryVideoProcFilters",
+            vec![context as i64],
+        )
+    }
+
+    unsafe fn vaQueryVideoProcFilterCaps(
+        &self,
+        dpy: sys::VADisplay,
+        context: sys::VAContextID,
+        type_: sys::VAProcFilterType,
+        filter_caps: *mut c_void,
+        num_filter_caps: *mut c_uint,
+    ) -> sys::VAStatus {
+        let sequence = self.reserve();
+        let status = self.inner.vaQueryVideoProcFilterCaps(
+            dpy,
+            context,
+            type_,
+            filter_caps,
+            num_filter_caps,
+        );
+        self.query(
+            sequence,
+            dpy,
+            status,
+            "vaQueryVideoProcFilterCaps",
+            vec![context as i64, type_ as i64],
+        )
+    }
+
+    unsafe fn vaQueryVideoProcPipelineCaps(
+        &self,
+        dpy: sys::VADisplay,
+        context: sys::VAContextID,
+        filters: *mut sys::VABufferID,
+        num_filters: c_uint,
+        pipeline_caps: *mut sys::VAProcPipelineCaps,
+    ) -> sys::VAStatus {
+        let sequence = self.reserve();
+        let mut args = vec![context as i64];
+        args.extend(slice(filters, num_filters).iter().map(|&id| id as i64));
+        let status = self.inner.vaQueryVideoProcPipelineCaps(
+            dpy,
+            context,
+            filters,
+            num_filters,
+            pipeline_caps,
+        );
+        self.query(sequence, dpy, status, "vaQueryVideoProcPipelineCaps", args)
+    }
+
+    unsafe fn vaExportSurfaceHandle(
+        &self,
+        dpy: sys::VADisplay,
+        surface_id: sys::VASurfaceID,
+        mem_type: u32,
+        flags: u32,
+        descriptor: *mut c_void,
+    ) -> sys::VAStatus {
+        let sequence = self.reserve();
+        let status = self
+            .inner
+            .vaExportSurfaceHandle(dpy, surface_id, mem_type, flags, descriptor);
+        self.query(
+            sequence,
+            dpy,
+            status,
+            "vaExportSurfaceHandle",
+            vec![surface_id as i64, mem_type as i64, flags as i64],
+        )
+    }
+}
+
+struct Replay<'a> {
+    lib: &'a dyn Backend,
+    drm_fd: c_int,
+    displays: HashMap<u32, sys::VADisplay>,
+    /// Recorded object IDs to the IDs seen during replay.
+    ids: HashMap<(u32, ObjectId), u32>,
+    objects: Objects,
+    report: ReplayReport,
+}
+
+impl Replay<'_> {
+    fn id(&self, display: u32, recorded: ObjectId) -> u32 {
+        self.ids
+            .get(&(display, recorded))
+            .copied()
+            .unwrap_or(sys::VA_INVALID_ID)
+    }
+
+    fn ids(&self, display: u32, recorded: &[u32], kind: fn(u32) -> ObjectId) -> Vec<u32> {
+        recorded
+            .iter()
+            .map(|&id| self.id(display, kind(id)))
+            .collect()
+    }
+
+    fn bind(&mut self, display: u32, recorded: ObjectId, replayed: u32) {
+        self.ids.insert((display, recorded), replayed);
+    }
+
+    /// Issues one recorded call. Returns `None` if it was skipped.
+    unsafe fn step(&mut self, index: usize, record: &TraceRecord) -> Option<sys::VAStatus> {
+        let lib = self.lib;
+        let d = record.display;
+        let dpy = self
+            .displays
+            .get(&d)
+            .copied()
+            .unwrap_or(std::ptr::null_mut());
+        let ok = |status| status == SUCCESS;
+        let status = match &record.call {
+            TraceCall::GetDisplayDRM {} => {
+                let dpy = lib.vaGetDisplayDRM(self.drm_fd);
+                if dpy.is_null() {
+                    return Some(ErrorStatus::InvalidDisplay.to_raw());
+                }
+                self.displays.insert(d, dpy);
+                SUCCESS
+            }
+            TraceCall::Initialize { .. } => {
+                let (mut major, mut minor) = (0, 0);
+                lib.vaInitialize(dpy, &mut major, &mut minor)
+            }
+            TraceCall::Terminate {} => {
+                self.displays.remove(&d);
+                lib.vaTerminate(dpy)
+            }
+            TraceCall::CreateConfig {
+                profile,
+                entrypoint,
+                attribs,
+                config,
+            } => {
+                let mut attribs: Vec<_> = attribs
+                    .iter()
+                    .map(|&(type_, value)| sys::VAConfigAttrib { type_, value })
+                    .collect();
+                let mut id = 0;
+                let status = lib.vaCreateConfig(
+                    dpy,
+                    *profile,
+                    *entrypoint,
+                    attribs.as_mut_ptr(),
+                    attribs.len() as c_int,
+                    &mut id,
+                );
+                if ok(status) {
+                    self.bind(d, ObjectId::Config(*config), id);
+                }
+                status
+            }
+            TraceCall::DestroyConfig { config } => {
+                lib.vaDestroyConfig(dpy, self.id(d, ObjectId::Config(*config)))
+            }
+            TraceCall::CreateSurfaces {
+                format,
+                width,
+                height,
+                attribs,
+                surfaces,
+            } => {
+                let mut attribs: Vec<_> = attribs
+                    .iter()
+                    .map(|&(type_, value)| sys::VASurfaceAttrib {
+                        type_,
+                        flags: sys::VA_SURFACE_ATTRIB_SETTABLE,
+                        value: sys::VAGenericValue {
+                            type_: sys::VAGenericValueTypeInteger,
+                            value: sys::_VAGenericValue__bindgen_ty_1 { i: value },
+                        },
+                    })
+                    .collect();
+                let mut ids = vec![0; surfaces.len().max(1)];
+                let status = lib.vaCreateSurfaces(
+                    dpy,
+                    *format,
+                    *width,
+                    *height,
+                    ids.as_mut_ptr(),
+                    surfaces.len().max(1) as c_uint,
+                    if attribs.is_empty() {
+                        std::ptr::null_mut()
+                    } else {
+                        attribs.as_mut_ptr()
+                    },
+                    attribs.len() as c_uint,
+                );
+                if ok(status) {
+                    for (&recorded, &id) in surfaces.iter().zip(&ids) {
+                        self.bind(d, ObjectId::Surface(recorded), id);
+                    }
+                }
+                status
+            }
+            TraceCall::DestroySurfaces { surfaces } => {
+                let mut ids = self.ids(d, surfaces, ObjectId::Surface);
+                lib.vaDestroySurfaces(dpy, ids.as_mut_ptr(), ids.len() as c_int)
+            }
+            TraceCall::CreateContext {
+                config,
+                width,
+                height,
+                flag,
+                targets,
+                context,
+            } => {
+                let mut targets = self.ids(d, targets, ObjectId::Surface);
+                let mut id = 0;
+                let status = lib.vaCreateContext(
+                    dpy,
+                    self.id(d, ObjectId::Config(*config)),
+                    *width,
+                    *height,
+                    *flag,
+                    if targets.is_empty() {
+                        std::ptr::null_mut()
+                    } else {
+                        targets.as_mut_ptr()
+                    },
+                    targets.len() as c_int,
+                    &mut id,
+                );
+                if ok(status) {
+                    self.bind(d, ObjectId::Context(*context), id);
+                }
+                status
+            }
+            TraceCall::DestroyContext { context } => {
+                lib.vaDestroyContext(dpy, self.id(d, ObjectId::Context(*context)))
+            }
+            TraceCall::CreateBuffer {
+                context,
+                buffer_type,
+                size,
+                num_elements,
+                data,
+                buffer,
+            } => {
+                let mut data = data.clone();
+                let mut id = 0;
+                let status = lib.vaCreateBuffer(
+                    dpy,
+                    self.id(d, ObjectId::Context(*context)),
+                    *buffer_type,
+                    *size,
+                    *num_elements,
+                    data.as_mut().map_or(std::ptr::null_mut(), |data| {
+                        data.as_mut_ptr() as *mut c_void
+                    }),
+                    &mut id,
+                );
+                if ok(status) {
+                    self.bind(d, ObjectId::Buffer(*buffer), id);
+                    self.objects
+                        .buffers
+                        .insert((d, id), buffer_kind(*buffer_type, *size, *num_elements));
+                }
+                status
+            }
+            TraceCall::BufferSetNumElements {
+                buffer,
+                num_elements,
+            } => {
+                let id = self.id(d, ObjectId::Buffer(*buffer));
+                let status = lib.vaBufferSetNumElements(dpy, id, *num_elements);
+                if ok(status) {
+                    self.objects.set_num_elements(d, id, *num_elements);
+                }
+                status
+            }
+            TraceCall::MapBuffer { buffer } => {
+                let id = self.id(d, ObjectId::Buffer(*buffer));
+                let mut ptr = std::ptr::null_mut();
+                let status = lib.vaMapBuffer(dpy, id, &mut ptr);
+                if ok(status) {
+                    self.objects.map(d, id, ptr);
+                }
+                status
+            }
+            TraceCall::UnmapBuffer { buffer, contents } => {
+                let id = self.id(d, ObjectId::Buffer(*buffer));
+                self.restore(index, d, id, contents);
+                let status = lib.vaUnmapBuffer(dpy, id);
+                if ok(status) {
+                    self.objects.mapped.remove(&(d, id));
+                }
+                status
+            }
+            TraceCall::DestroyBuffer { buffer } => {
+                let id = self.id(d, ObjectId::Buffer(*buffer));
+                let status = lib.vaDestroyBuffer(dpy, id);
+                if ok(status) {
+                    self.objects.buffers.remove(&(d, id));
+                    self.objects.mapped.remove(&(d, id));
+                }
+                status
+            }
+            TraceCall::BeginPicture { context, target } => lib.vaBeginPicture(
+                dpy,
+                self.id(d, ObjectId::Context(*context)),
+                self.id(d, ObjectId::Surface(*target)),
+            ),
+            TraceCall::RenderPicture { context, buffers } => {
+                let mut ids = self.ids(d, buffers, ObjectId::Buffer);
+                lib.vaRenderPicture(
+                    dpy,
+                    self.id(d, ObjectId::Context(*context)),
+                    ids.as_mut_ptr(),
+                    ids.len() as c_int,
+                )
+            }
+            TraceCall::EndPicture { context } => {
+                lib.vaEndPicture(dpy, self.id(d, ObjectId::Context(*context)))
+            }
+            TraceCall::SyncSurface { surface } => {
+                lib.vaSyncSurface(dpy, self.id(d, ObjectId::Surface(*surface)))
+            }
+            TraceCall::SyncSurface2 {
+                surface,
+                timeout_ns,
+            } => lib.vaSyncSurface2(dpy, self.id(d, ObjectId::Surface(*surface)), *timeout_ns),
+            TraceCall::QuerySurfaceStatus { surface, .. } => {
+                let mut status = 0;
+                lib.vaQuerySurfaceStatus(dpy, self.id(d, ObjectId::Surface(*surface)), &mut status)
+            }
+            TraceCall::SyncBuffer { buffer, timeout_ns } => {
+                lib.vaSyncBuffer(dpy, self.id(d, ObjectId::Buffer(*buffer)), *timeout_ns)
+            }
+            TraceCall::CreateImage {
+                format,
+                width,
+                height,
+                image,
+                buffer,
+            } => {
+                let mut raw_format = format.to_raw();
+                let mut raw = sys::VAImage::default();
+                let status = lib.vaCreateImage(dpy, &mut raw_format, *width, *height, &mut raw);
+                if ok(status) {
+                    self.bind_image(d, *image, *buffer, &raw);
+                }
+                status
+            }
+            TraceCall::DestroyImage { image } => {
+                let id = self.id(d, ObjectId::Image(*image));
+                let status = lib.vaDestroyImage(dpy, id);
+                if ok(status) {
+                    self.objects.remove_image(d, id);
+                }
+                status
+            }
+            TraceCall::DeriveImage {
+                surface,
+                image,
+                buffer,
+            } => {
+                let mut raw = sys::VAImage::default();
+                let status =
+                    lib.vaDeriveImage(dpy, self.id(d, ObjectId::Surface(*surface)), &mut raw);
+                if ok(status) {
+                    self.bind_image(d, *image, *buffer, &raw);
+                }
+                status
+            }
+            TraceCall::GetImage {
+                surface,
+                x,
+                y,
+                width,
+                height,
+                image,
+            } => lib.vaGetImage(
+                dpy,
+                self.id(d, ObjectId::Surface(*surface)),
+                *x,
+                *y,
+                *width,
+                *height,
+                self.id(d, ObjectId::Image(*image)),
+            ),
+            TraceCall::PutImage {
+                surface,
+                image,
+                src,
+                src_size,
+                dest,
+                dest_size,
+            } => lib.vaPutImage(
+                dpy,
+                self.id(d, ObjectId::Surface(*surface)),
+                self.id(d, ObjectId::Image(*image)),
+                src.0,
+                src.1,
+                src_size.0,
+                src_size.1,
+                dest.0,
+                dest.1,
+                dest_size.0,
+                dest_size.1,
+            ),
+            TraceCall::Query { .. } => return None,
+        };
+        Some(status)
+    }
+
+    fn bind_image(&mut self, display: u32, image: u32, buffer: u32, raw: &sys::VAImage) {
+        self.bind(display, ObjectId::Image(image), raw.image_id);
+        self.bind(display, ObjectId::Buffer(buffer), raw.buf);
+        self.objects.add_image(display, raw);
+    }
+
+    /// Writes recorded contents into a mapped buffer, or compares them with
+    /// what the replay produced.
+    unsafe fn restore(&mut self, index: usize, display: u32, buffer: u32, contents: &Contents) {
+        let (Some(&kind), Some(mapping)) = (
+            self.objects.buffers.get(&(display, buffer)),
+            self.objects.mapped.get(&(display, buffer)),
+        ) else {
+            return;
+        };
+        let ptr = mapping.ptr as *mut u8;
+        match (kind, contents) {
+            (
+                BufferKind::Data {
+                    element_size,
+                    num_elements,
+                },
+                Contents::Data(data),
+            ) => {
+                let len = data.len().min(element_size * num_elements);
+                std::ptr::copy_nonoverlapping(data.as_ptr(), ptr, len);
+            }
+            (BufferKind::Image(layout), Contents::Data(data)) => layout.unpack(ptr, data),
+            (BufferKind::Image(layout), &Contents::Checksum(recorded)) => {
+                let replayed = layout.checksum(ptr);
+                if replayed != recorded {
+                    self.report.mismatches.push(Mismatch {
+                        index,
+                        operation: "vaUnmapBuffer".to_string(),
+                        kind: MismatchKind::Contents { recorded, replayed },
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake::{self, FakeBackend};
+    use crate::{
+        Buffer, BufferType, Config, ConfigAttributes, Context, ContextFlags, Entrypoint, Image,
+        Profile, RtFormat, Surface, UsageHint,
+    };
+    use std::os::fd::AsFd;
+    use std::sync::Arc;
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Decodes one picture into a surface, then reads it back.
+    fn session(library: Arc<Library>) {
+        let display = fake::open_display(&library).unwrap();
+        let config = Config::new(
+            display.clone(),
+            Some(Profile::H264Main),
+            Entrypoint::VLD,
+            &ConfigAttributes::default(),
+        )
+        .unwrap();
+        let surface = Surface::new(
+            display.clone(),
+            RtFormat::YUV420,
+            32,
+            16,
+            None,
+            UsageHint::DECODER,
+        )
+        .unwrap();
+        {
+            let image = surface.derive_image().unwrap();
+            let mut map = image.map().unwrap();
+            map.plane_mut(0).unwrap().row_mut(1).unwrap().fill(200);
+        }
+        let context = Context::new(
+            config,
+            32,
+            16,
+            ContextFlags::PROGRESSIVE,
+            vec![surface.clone()],
+        )
+        .unwrap();
+        let params = Buffer::new(context.clone(), BufferType::PictureParameter, 8).unwrap();
+        params
+            .map()
+            .unwrap()
+            .copy_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8]);
+        let slice = Buffer::new_with_data(context.clone(), BufferType::SliceData, &[9, 9]).unwrap();
+        unsafe {
+            let lib = library.lib();
+            let mut ids = [params.handle(), slice.handle()];
+            lib.vaBeginPicture(display.handle(), context.handle(), surface.handle());
+            lib.vaRenderPicture(display.handle(), context.handle(), ids.as_mut_ptr(), 2);
+            lib.vaEndPicture(display.handle(), context.handle());
+        }
+        surface.sync().unwrap();
+
+        let format = display
+            .query_image_formats()
+            .unwrap()
+            .into_iter()
+            .find(|f| f.fourcc == Fourcc::NV12)
+            .unwrap();
+        let image = Image::new(display.clone(), &format, 32, 16).unwrap();
+        surface.get_image(&image, 0, 0, 32, 16).unwrap();
+        let _ = image.map_read().unwrap();
+    }
+
+    fn record() -> (FakeBackend, Trace) {
+        let fake = FakeBackend::default();
+        let out = SharedBuffer::default();
+        let tracer = TracingBackend::new(fake.clone(), out.clone()).unwrap();
+        session(Library::with_backend(tracer));
+        let trace = Trace::read(&out.0.lock().unwrap()[..]).unwrap();
+        (fake, trace)
+    }
+
+    #[test]
+    fn encoding_round_trip() {
+        let record = TraceRecord {
+            display: 3,
+            status: ErrorStatus::InvalidSurface.to_raw(),
+            call: TraceCall::PutImage {
+                surface: 0x4000_0001,
+                image: 7,
+                src: (-1, 2),
+                src_size: (u32::MAX, 0),
+                dest: (i32::MIN, i32::MAX),
+                dest_size: (5, 6),
+            },
+        };
+        let mut encoded = Vec::new();
+        record.encode(&mut encoded);
+        assert_eq!(TraceRecord::decode(&encoded).unwrap(), record);
+        assert!(TraceRecord::decode(&encoded[..encoded.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn records_calls() {
+        let (fake, trace) = record();
+        let operations: Vec<_> = trace.records().iter().map(|r| r.call.operation()).collect();
+        for expected in [
+            "vaGetDisplayDRM",
+            "vaInitialize",
+            "vaCreateConfig",
+            "vaCreateSurfaces",
+            "vaDeriveImage",
+            "vaCreateContext",
+            "vaBeginPicture",
+            "vaRenderPicture",
+            "vaEndPicture",
+            "vaSyncSurface",
+            "vaGetImage",
+            "vaTerminate",
+        ] {
+            assert!(operations.contains(&expected), "{} missing", expected);
+        }
+        assert!(trace.records().iter().all(|r| r.status == SUCCESS));
+        assert!(trace.records().iter().any(|r| r.call
+            == TraceCall::UnmapBuffer {
+                buffer: fake.pictures()[0].buffers[0].id,
+                contents: Contents::Data(vec![1, 2, 3, 4, 5, 6, 7, 8]),
+            }));
+    }
+
+    #[test]
+    fn writes_records_in_call_order() {
+        let out = SharedBuffer::default();
+        let tracer = TracingBackend::new(FakeBackend::default(), out.clone()).unwrap();
+        let header = out.0.lock().unwrap().len();
+        let (first, second) = (tracer.reserve(), tracer.reserve());
+        let record = |call| TraceRecord {
+            display: 0,
+            status: SUCCESS,
+            call,
+        };
+        // The second call returns first.
+        tracer
+            .recorder()
+            .submit(second, record(TraceCall::Terminate {}));
+        assert_eq!(out.0.lock().unwrap().len(), header);
+        tracer
+            .recorder()
+            .submit(first, record(TraceCall::GetDisplayDRM {}));
+        let trace = Trace::read(&out.0.lock().unwrap()[..]).unwrap();
+        let operations: Vec<_> = trace.records().iter().map(|r| r.call.operation()).collect();
+        assert_eq!(operations, ["vaGetDisplayDRM", "vaTerminate"]);
+    }
+
+    #[test]
+    fn replay_reproduces_session() {
+        let (recorded, trace) = record();
+        let fake = FakeBackend::default();
+        let devnull = File::open("/dev/null").unwrap();
+        let report = trace.replay(&fake.library(), devnull.as_fd());
+        assert!(report.is_clean(), "{:?}", report.mismatches);
+        assert!(report.skipped > 0);
+        let strip_ids = |pictures: Vec<fake::FakePicture>| -> Vec<Vec<Vec<u8>>> {
+            pictures
+                .into_iter()
+                .map(|p| p.buffers.into_iter().map(|b| b.data).collect())
+                .collect()
+        };
+        assert_eq!(strip_ids(fake.pictures()), strip_ids(recorded.pictures()));
+        assert_eq!(fake.live_objects(), recorded.live_objects());
+    }
+
+    #[test]
+    fn replay_reports_divergence() {
+        let (_, trace) = record();
+        let fake = FakeBackend::default();
+        fake.inject_error("vaEndPicture", ErrorStatus::DecodingError);
+        let devnull = File::open("/dev/null").unwrap();
+        let report = trace.replay(&fake.library(), devnull.as_fd());
+        assert_eq!(report.mismatches.len(), 1);
+        let mismatch = &report.mismatches[0];
+        assert_eq!(mismatch.operation, "vaEndPicture");
+        assert_eq!(
+            mismatch.kind,
+            MismatchKind::Status {
+                recorded: SUCCESS,
+                replayed: ErrorStatus::DecodingError.to_raw(),
+            }
+        );
+    }
+}
@@ -0,0 +1,290 @@
+use paste::paste;
+
+use crate::{sys, va_enum_prefix, Context, Fourcc, ObjectId, VaResult, VaStatusExt};
+
+va_enum_prefix! {
+    ProcFilterType: sys::VAProcFilterType;
+    VAProcFilter {
+        NoiseReduction,
+        Deinterlacing,
+        Sharpening,
+        ColorBalance,
+        SkinToneEnhancement,
+        TotalColorCorrection,
+        HVSNoiseReduction,
+        HighDynamicRangeToneMapping,
+    }
+}
+
+va_enum_prefix! {
+    DeinterlacingType: sys::VAProcDeinterlacingType;
+    VAProcDeinterlacing {
+        Bob,
+        Weave,
+        MotionAdaptive,
+        MotionCompensated,
+    }
+}
+
+va_enum_prefix! {
+    ColorBalanceType: sys::VAProcColorBalanceType;
+    VAProcColorBalance {
+        Hue,
+        Saturation,
+        Brightness,
+        Contrast,
+        AutoSaturation,
+        AutoBrightness,
+        AutoContrast,
+    }
+}
+
+va_enum_prefix! {
+    ColorStandard: sys::VAProcColorStandardType;
+    VAProcColorStandard {
+        BT601,
+        BT709,
+        BT470M,
+        BT470BG,
+        SMPTE170M,
+        SMPTE240M,
+        GenericFilm,
+        SRGB,
+        STRGB,
+        XVYCC601,
+        XVYCC709,
+        BT2020,
+        Explicit,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FilterValueRange {
+    pub min: f32,
+    pub max: f32,
+    pub default: f32,
+    pub step: f32,
+}
+
+impl From<sys::VAProcFilterValueRange> for FilterValueRange {
+    fn from(value: sys::VAProcFilterValueRange) -> Self {
+        Self {
+            min: value.min_value,
+            max: value.max_value,
+            default: value.default_value,
+            step: value.step,
+        }
+    }
+}
+
+/// What a video processing filter can be configured with.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FilterCaps {
+    Range(FilterValueRange),
+    Deinterlacing(Vec<DeinterlacingType>),
+    ColorBalance(Vec<(ColorBalanceType, FilterValueRange)>),
+    /// The filter is supported but its caps are not decoded.
+    Other,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PipelineCaps {
+    pub pipeline_flags: u32,
+    pub filter_flags: u32,
+    pub num_forward_references: u32,
+    pub num_backward_references: u32,
+    pub input_color_standards: Vec<ColorStandard>,
+    pub output_color_standards: Vec<ColorStandard>,
+    /// Supported rotations as a mask of `1 << VA_ROTATION_*`.
+    pub rotation_flags: u32,
+    pub blend_flags: u32,
+    /// Supported mirroring as a mask of `VA_MIRROR_*`.
+    pub mirror_flags: u32,
+    pub num_additional_outputs: u32,
+    pub input_pixel_formats: Vec<Fourcc>,
+    pub output_pixel_formats: Vec<Fourcc>,
+    pub min_input_width: u32,
+    pub min_input_height: u32,
+    pub max_input_width: u32,
+    pub max_input_height: u32,
+    pub min_output_width: u32,
+    pub min_output_height: u32,
+    pub max_output_width: u32,
+    pub max_output_height: u32,
+}
+
+// Upper bound on the pixel formats a driver reports for a pipeline.
+const MAX_PIPELINE_PIXEL_FORMATS: usize = 256;
+
+impl Context {
+    /// Filters supported by a context created on a `VideoProc` config.
+    pub fn query_video_proc_filters(&self) -> VaResult<Vec<ProcFilterType>> {
+        let mut raw_filters = vec![sys::VAProcFilterNone; sys::VAProcFilterCount as usize];
+        let mut num_filters = raw_filters.len() as _;
+        unsafe {
+            self.display()
+                .library()
+                .lib()
+                .vaQueryVideoProcFilters(
+                    self.display().handle(),
+                    self.handle(),
+                    raw_filters.as_mut_ptr(),
+                    &mut num_filters,
+                )
+                .va_check(
+                    self.display().library(),
+                    "vaQueryVideoProcFilters",
+                    &[ObjectId::Context(self.handle())],
+                )?;
+        }
+        Ok(raw_filters
+            .iter()
+            .take(num_filters as usize)
+            .filter_map(|&raw| ProcFilterType::try_from(raw).ok())
+            .collect())
+    }
+
+    pub fn query_video_proc_filter_caps(&self, filter: ProcFilterType) -> VaResult<FilterCaps> {
+        match filter {
+            ProcFilterType::Deinterlacing => {
+                let caps = self.query_filter_caps::<sys::VAProcFilterCapDeinterlacing>(
+                    filter,
+                    sys::VAProcDeinterlacingCount as usize,
+                )?;
+                Ok(FilterCaps::Deinterlacing(
+                    caps.iter()
+                        .filter_map(|cap| DeinterlacingType::try_from(cap.type_).ok())
+                        .collect(),
+                ))
+            }
+            ProcFilterType::ColorBalance => {
+                let caps = self.query_filter_caps::<sys::VAProcFilterCapColorBalance>(
+                    filter,
+                    sys::VAProcColorBalanceCount as usize,
+                )?;
+                Ok(FilterCaps::ColorBalance(
+                    caps.iter()
+                        .filter_map(|cap| {
+                            let type_ = ColorBalanceType::try_from(cap.type_).ok()?;
+                            Some((type_, cap.range.into()))
+                        })
+                        .collect(),
+                ))
+            }
+            ProcFilterType::NoiseReduction
+            | ProcFilterType::Sharpening
+            | ProcFilterType::SkinToneEnhancement
+            | ProcFilterType::HVSNoiseReduction => {
+                let caps = self.query_filter_caps::<sys::VAProcFilterCap>(filter, 1)?;
+                Ok(caps
+                    .first()
+                    .map_or(FilterCaps::Other, |cap| FilterCaps::Range(cap.range.into())))
+            }
+            ProcFilterType::TotalColorCorrection | ProcFilterType::HighDynamicRangeToneMapping => {
+                Ok(FilterCaps::Other)
+            }
+        }
+    }
+
+    fn query_filter_caps<T: Default + Clone>(
+        &self,
+        filter: ProcFilterType,
+        capacity: usize,
+    ) -> VaResult<Vec<T>> {
+        let mut caps = vec![T::default(); capacity];
+        let mut num_caps = capacity as _;
+        unsafe {
+            self.display()
+                .library()
+                .lib()
+                .vaQueryVideoProcFilterCaps(
+                    self.display().handle(),
+                    self.handle(),
+                    filter.into(),
+                    caps.as_mut_ptr().cast(),
+                    &mut num_caps,
+                )
+                .va_check(
+                    self.display().library(),
+                    "vaQueryVideoProcFilterCaps",
+                    &[ObjectId::Context(self.handle())],
+                )?;
+        }
+        caps.truncate(num_caps as usize);
+        Ok(caps)
+    }
+
+    /// Pipeline caps with no filters applied.
+    pub fn query_video_proc_pipeline_caps(&self) -> VaResult<PipelineCaps> {
+        let mut input_standards =
+            vec![sys::VAProcColorStandardNone; sys::VAProcColorStandardCount as usize];
+        let mut output_standards = input_standards.clone();
+        let mut input_formats = vec![0u32; MAX_PIPELINE_PIXEL_FORMATS];
+        let mut output_formats = input_formats.clone();
+        let mut raw = sys::VAProcPipelineCaps {
+            input_color_standards: input_standards.as_mut_ptr(),
+            num_input_color_standards: input_standards.len() as _,
+            output_color_standards: output_standards.as_mut_ptr(),
+            num_output_color_standards: output_standards.len() as _,
+            input_pixel_format: input_formats.as_mut_ptr(),
+            num_input_pixel_formats: input_formats.len() as _,
+            output_pixel_format: output_formats.as_mut_ptr(),
+            num_output_pixel_formats: output_formats.len() as _,
+            ..Default::default()
+        };
+        unsafe {
+            self.display()
+                .library()
+                .lib()
+                .vaQueryVideoProcPipelineCaps(
+                    self.display().handle(),
+                    self.handle(),
+                    std::ptr::null_mut(),
+                    0,
+                    &mut raw,
+                )
+                .va_check(
+                    self.display().library(),
+                    "vaQueryVideoProcPipelineCaps",
+                    &[ObjectId::Context(self.handle())],
+                )?;
+        }
+        let standards = |list: &[sys::VAProcColorStandardType], count: u32| {
+            list.iter()
+                .take(count as usize)
+                .filter_map(|&raw| ColorStandard::try_from(raw).ok())
+                .collect()
+        };
+        let formats = |list: &[u32], count: u32| {
+            list.iter()
+                .take(count as usize)
+                .map(|&raw| Fourcc::from(raw))
+                .collect()
+        };
+        Ok(PipelineCaps {
+            pipeline_flags: raw.pipeline_flags,
+            filter_flags: raw.filter_flags,
+            num_forward_references: raw.num_forward_references,
+            num_backward_references: raw.num_backward_references,
+            input_color_standards: standards(&input_standards, raw.num_input_color_standards),
+            output_color_standards: standards(&output_standards, raw.num_output_color_standards),
+            rotation_flags: raw.rotation_flags,
+            blend_flags: raw.blend_flags,
+            mirror_flags: raw.mirror_flags,
+            num_additional_outputs: raw.num_additional_outputs,
+            input_pixel_formats: formats(&input_formats, raw.num_input_pixel_formats),
+            output_pixel_formats: formats(&output_formats, raw.num_output_pixel_formats),
+            min_input_width: raw.min_input_width,
+            min_input_height: raw.min_input_height,
+            max_input_width: raw.max_input_width,
+            max_input_height: raw.max_input_height,
+            min_output_width: raw.min_output_width,
+            min_output_height: raw.min_output_height,
+            max_output_width: raw.max_output_width,
+            max_output_height: raw.max_output_height,
+        })
+    }
+}
@@ -26,21 +26,12 @@ fn draw_color_bar(width: u32, height: u32, frame: u32) -> ::image::RgbaImage {
     image
 }
 
-fn upload_image(surface: &va::Surface, img: &image::RgbaImage) {
-    let image = surface.derive_image().unwrap();
-    let mut mapped = image.buffer().map().unwrap();
-    let format = image.format();
-    if image.format().fourcc == va::Fourcc::try_from("NV12").unwrap() {
-        let luma = &mut mapped[image.offsets()[0] as usize..];
-    }
-}
-
 fn main() -> Result<()> {
     let width = 1920;
     let height = 1080;
 
     let library = va::Library::load()?;
-    let display = va::Display::enumerate(library).next().unwrap();
+    let display = va::Display::select(library, &va::DevicePreference::default())?;
     let config = display.get_config_attributes(None, va::Entrypoint::VideoProc)?;
     println!("{:#?}", config);
     let config = va::Config::new(
@@ -56,7 +47,7 @@ fn main() -> Result<()> {
         va::RtFormat::YUV420,
         width,
         height,
-        Some(va::Fourcc::try_from("NV12").unwrap()),
+        Some(va::Fourcc::NV12),
         va::UsageHint::GENERIC,
     )?;
     let out_surface = va::Surface::new(
@@ -64,16 +55,19 @@ fn main() -> Result<()> {
         va::RtFormat::RGB32,
         width,
         height,
-        Some(va::Fourcc::try_from("RGB32").unwrap()),
+        Some(va::Fourcc::BGRX),
         va::UsageHint::GENERIC,
     )?;
-    let context = va::Context::new(
+    let _context = va::Context::new(
         config,
         width,
         height,
         va::ContextFlags::PROGRESSIVE,
         vec![out_surface.clone()],
     );
+    in_surface.upload_image(&::image::DynamicImage::ImageRgba8(draw_color_bar(
+        width, height, 0,
+    )))?;
     let in_surface_image = in_surface.derive_image()?;
     let out_surface_image = out_surface.derive_image()?;
     println!("{:#?}", in_surface_image);
@@ -0,0 +1,307 @@
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use vendec_libva as va;
+
+const USAGE: &str = "\
+Usage: vendec-info [--json] [--device PATH]
+
+Lists VA-API render nodes and what their drivers support.
+
+Options:
+      --json         Print a JSON report instead of tables
+      --device PATH  Only report on the render node at PATH
+  -h, --help         Print this help
+";
+
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Args {
+    json: bool,
+    device: Option<PathBuf>,
+}
+
+/// Returns `None` if help was requested.
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Option<Args>> {
+    let mut parsed = Args::default();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--json" => parsed.json = true,
+            "--device" => {
+                let path = args.next().context("--device requires a path")?;
+                parsed.device = Some(path.into());
+            }
+            "-h" | "--help" => return Ok(None),
+            _ => bail!("unexpected argument {:?}\n\n{}", arg, USAGE),
+        }
+    }
+    Ok(Some(parsed))
+}
+
+/// Everything known about one render node.
+#[derive(Debug, Serialize)]
+struct DeviceReport {
+    node: va::RenderNode,
+    capabilities: Option<va::Capabilities>,
+    /// Why the node could not be opened or queried.
+    error: Option<String>,
+}
+
+fn report(node: va::RenderNode, display: Result<Arc<va::Display>, va::OpenError>) -> DeviceReport {
+    let capabilities = display
+        .map_err(|err| err.to_string())
+        .and_then(|display| display.capabilities().map_err(|err| err.to_string()));
+    match capabilities {
+        Ok(capabilities) => DeviceReport {
+            node,
+            capabilities: Some(capabilities),
+            error: None,
+        },
+        Err(error) => DeviceReport {
+            node,
+            capabilities: None,
+            error: Some(error),
+        },
+    }
+}
+
+fn join<T>(items: impl IntoIterator<Item = T>, f: impl Fn(T) -> String) -> String {
+    items.into_iter().map(f).collect::<Vec<_>>().join(" ")
+}
+
+fn size(width: Option<u32>, height: Option<u32>) -> String {
+    match (width, height) {
+        (Some(width), Some(height)) => format!("{}x{}", width, height),
+        _ => "-".to_string(),
+    }
+}
+
+fn filter_caps(caps: &va::FilterCaps) -> String {
+    let range = |r: &va::FilterValueRange| format!("{}..{}", r.min, r.max);
+    match caps {
+        va::FilterCaps::Range(r) => {
+            format!("{} (default {}, step {})", range(r), r.default, r.step)
+        }
+        va::FilterCaps::Deinterlacing(types) => types
+            .iter()
+            .map(|t| format!("{:?}", t))
+            .collect::<Vec<_>>()
+            .join(", "),
+        va::FilterCaps::ColorBalance(types) => types
+            .iter()
+            .map(|(t, r)| format!("{:?} {}", t, range(r)))
+            .collect::<Vec<_>>()
+            .join(", "),
+        va::FilterCaps::Other => String::new(),
+    }
+}
+
+fn write_table(out: &mut impl Write, indent: &str, rows: &[Vec<String>]) -> io::Result<()> {
+    let columns = rows.iter().map(Vec::len).max().unwrap_or(0);
+    let widths: Vec<usize> = (0..columns)
+        .map(|i| {
+            rows.iter()
+                .filter_map(|row| row.get(i))
+                .map(String::len)
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    for row in rows {
+        let mut line = indent.to_string();
+        for (cell, width) in row.iter().zip(&widths) {
+            line.push_str(&format!("{:<width$}  ", cell, width = width));
+        }
+        writeln!(out, "{}", line.trim_end())?;
+    }
+    Ok(())
+}
+
+fn write_report(out: &mut impl Write, report: &DeviceReport) -> io::Result<()> {
+    let node = &report.node;
+    write!(out, "{}", node.path.display())?;
+    if let Some(pci) = &node.pci {
+        write!(
+            out,
+            " [{:04x}:{:04x}] at {}",
+            pci.vendor_id, pci.device_id, pci.bus_id
+        )?;
+    }
+    writeln!(out)?;
+    if let Some(driver) = &node.kernel_driver {
+        writeln!(out, "  kernel driver: {}", driver)?;
+    }
+    if let Some(error) = &report.error {
+        writeln!(out, "  error: {}", error)?;
+    }
+    let Some(caps) = &report.capabilities else {
+        return writeln!(out);
+    };
+    // libva has no query for the name of the VA driver it loaded, only
+    // for the driver's description of itself.
+    writeln!(
+        out,
+        "  vendor: {}",
+        caps.vendor.as_deref().unwrap_or("unknown")
+    )?;
+
+    writeln!(out, "  configs:")?;
+    let mut rows = vec![[
+        "profile",
+        "entrypoint",
+        "max size",
+        "rt formats",
+        "pixel formats",
+    ]
+    .map(String::from)
+    .to_vec()];
+    for config in &caps.configs {
+        let attributes = config.attributes.unwrap_or_default();
+        let surface = config.surface_attributes.clone().unwrap_or_default();
+        rows.push(vec![
+            config
+                .profile
+                .map_or("None".to_string(), |p| format!("{:?}", p)),
+            format!("{:?}", config.entrypoint),
+            size(
+                surface.max_width.or(attributes.max_picture_width),
+                surface.max_height.or(attributes.max_picture_height),
+            ),
+            attributes.rt_format.map_or(String::new(), |rt| {
+                join(rt.iter_names(), |(name, _)| name.to_string())
+            }),
+            join(&surface.pixel_formats, |f| f.to_string()),
+        ]);
+    }
+    write_table(out, "    ", &rows)?;
+
+    writeln!(
+        out,
+        "  image formats: {}",
+        join(&caps.image_formats, |f| f.fourcc.to_string())
+    )?;
+
+    if let Some(vpp) = &caps.video_proc {
+        writeln!(out, "  video processing:")?;
+        let p = &vpp.pipeline;
+        let rows = [
+            vec![
+                "input".to_string(),
+                format!(
+                    "{}x{}..{}x{}",
+                    p.min_input_width, p.min_input_height, p.max_input_width, p.max_input_height
+                ),
+                join(&p.input_pixel_formats, |f| f.to_string()),
+            ],
+            vec![
+                "output".to_string(),
+                format!(
+                    "{}x{}..{}x{}",
+                    p.min_output_width,
+                    p.min_output_height,
+                    p.max_output_width,
+                    p.max_output_height
+                ),
+                join(&p.output_pixel_formats, |f| f.to_string()),
+            ],
+        ];
+        write_table(out, "    ", &rows)?;
+        writeln!(out, "  filters:")?;
+        let rows: Vec<_> = vpp
+            .filters
+            .iter()
+            .map(|f| vec![format!("{:?}", f.filter), filter_caps(&f.caps)])
+            .collect();
+        write_table(out, "    ", &rows)?;
+    }
+    writeln!(out)
+}
+
+fn main() -> Result<()> {
+    let Some(args) = parse_args(std::env::args().skip(1))? else {
+        print!("{}", USAGE);
+        return Ok(());
+    };
+    let library = va::Library::load()?;
+    let reports: Vec<_> = match &args.device {
+        Some(path) => vec![report(
+            va::RenderNode::new(path),
+            va::Display::open(library, path),
+        )],
+        None => va::Display::enumerate(library)
+            .map(|probe| report(probe.node, probe.display))
+            .collect(),
+    };
+
+    let mut out = io::stdout().lock();
+    if args.json {
+        serde_json::to_writer_pretty(&mut out, &reports)?;
+        writeln!(out)?;
+    } else {
+        if reports.is_empty() {
+            bail!("no render nodes found");
+        }
+        for report in &reports {
+            write_report(&mut out, report)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use va::fake;
+
+    fn fake_report() -> DeviceReport {
+        let fake = fake::FakeBackend::default();
+        let display = fake::open_display(&fake.library());
+        report(
+            va::RenderNode::new("/dev/null"),
+            display.map_err(|err| va::OpenError::Initialize {
+                path: "/dev/null".into(),
+                source: Box::new(err),
+            }),
+        )
+    }
+
+    #[test]
+    fn parses_args() {
+        let args = |list: &[&str]| parse_args(list.iter().map(|s| s.to_string()));
+        assert_eq!(args(&[]).unwrap(), Some(Args::default()));
+        assert_eq!(
+            args(&["--device", "/dev/dri/renderD129", "--json"]).unwrap(),
+            Some(Args {
+                json: true,
+                device: Some("/dev/dri/renderD129".into()),
+            })
+        );
+        assert_eq!(args(&["--help"]).unwrap(), None);
+        assert!(args(&["--device"]).is_err());
+        assert!(args(&["--verbose"]).is_err());
+    }
+
+    #[test]
+    fn table_output() {
+        let mut out = Vec::new();
+        write_report(&mut out, &fake_report()).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.contains("\n  vendor: vendec fake driver\n"));
+        let hevc10 = text.lines().find(|l| l.contains("HEVCMain10")).unwrap();
+        assert!(hevc10.contains("4096x4096"));
+        assert!(hevc10.contains("YUV420 YUV420_10"));
+        assert!(hevc10.ends_with("NV12 P010"));
+        let deinterlacing = text.lines().find(|l| l.contains("Deinterlacing")).unwrap();
+        assert!(deinterlacing.ends_with("Bob, Weave"));
+    }
+
+    #[test]
+    fn json_output() {
+        let json = serde_json::to_value(vec![fake_report()]).unwrap();
+        assert_eq!(json[0]["node"]["path"], "/dev/null");
+        assert_eq!(json[0]["error"], serde_json::Value::Null);
+        assert_eq!(json[0]["capabilities"]["vendor"], "vendec fake driver");
+    }
+}
@@ -0,0 +1,569 @@
+//! Bit-level reading and writing shared by the codec header parsers and
+//! packed header generators.
+//!
+//! [`BitReader`] reads MSB-first fields as the H.264, HEVC, VP9 and AV1
+//! specifications describe them: `u(n)`, `ue(v)`, `se(v)`, `le(n)` and
+//! `leb128()`. It expects emulation prevention bytes to have been removed
+//! already, see [`crate::nal::to_rbsp`]. [`BoolDecoder`] decodes VP8's
+//! boolean entropy coded header. [`BitWriter`] produces the same syntax and
+//! can insert emulation prevention bytes as it goes.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// A read ran past the end of the data.
+    OutOfData,
+    /// An Exp-Golomb code has more than 31 leading zero bits.
+    InvalidExpGolomb,
+    /// A `leb128()` value is longer than 8 bytes.
+    InvalidLeb128,
+    /// A syntax element is out of its range.
+    InvalidValue { name: &'static str, value: i64 },
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::OutOfData => write!(f, "unexpected end of bitstream"),
+            Error::InvalidExpGolomb => write!(f, "Exp-Golomb code out of range"),
+            Error::InvalidLeb128 => write!(f, "leb128 value too long"),
+            Error::InvalidValue { name, value } => write!(f, "invalid {}: {}", name, value),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Range-checked syntax element reads.
+pub(crate) trait ReadExt {
+    /// `u(n)` that must be at most `max`.
+    fn read_bits_max(&mut self, name: &'static str, n: u32, max: u32) -> Result<u32>;
+    /// `ue(v)` that must be at most `max`.
+    fn read_ue_max(&mut self, name: &'static str, max: u32) -> Result<u32>;
+    /// `se(v)` that must be within `min..=max`.
+    fn read_se_range(&mut self, name: &'static str, min: i32, max: i32) -> Result<i32>;
+}
+
+impl ReadExt for BitReader<'_> {
+    fn read_bits_max(&mut self, name: &'static str, n: u32, max: u32) -> Result<u32> {
+        let value = self.read_bits(n)?;
+        check(name, value as i64, 0, max as i64).map(|_| value)
+    }
+
+    fn read_ue_max(&mut self, name: &'static str, max: u32) -> Result<u32> {
+        let value = self.read_ue()?;
+        check(name, value as i64, 0, max as i64).map(|_| value)
+    }
+
+    fn read_se_range(&mut self, name: &'static str, min: i32, max: i32) -> Result<i32> {
+        let value = self.read_se()?;
+        check(name, value as i64, min as i64, max as i64).map(|_| value)
+    }
+}
+
+pub(crate) fn check(name: &'static str, value: i64, min: i64, max: i64) -> Result<()> {
+    if (min..=max).contains(&value) {
+        Ok(())
+    } else {
+        Err(Error::InvalidValue { name, value })
+    }
+}
+
+/// Reads fields MSB-first from a byte slice.
+#[derive(Debug, Clone)]
+pub struct BitReader<'a> {
+    data: &'a [u8],
+    /// Position in bits.
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// Number of bits read so far.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn bits_left(&self) -> usize {
+        self.data.len() * 8 - self.position
+    }
+
+    pub fn byte_aligned(&self) -> bool {
+        self.position.is_multiple_of(8)
+    }
+
+    /// Skips to the next byte boundary.
+    pub fn align(&mut self) {
+        self.position = self.position.next_multiple_of(8);
+    }
+
+    pub fn skip_bits(&mut self, n: usize) -> Result<()> {
+        if n > self.bits_left() {
+            return Err(Error::OutOfData);
+        }
+        self.position += n;
+        Ok(())
+    }
+
+    /// `u(n)` for `n` up to 32.
+    pub fn read_bits(&mut self, n: u32) -> Result<u32> {
+        assert!(n <= 32, "cannot read {} bits into a u32", n);
+        self.read_bits_u64(n).map(|value| value as u32)
+    }
+
+    /// `u(n)` for `n` up to 64.
+    pub fn read_bits_u64(&mut self, n: u32) -> Result<u64> {
+        assert!(n <= 64, "cannot read {} bits into a u64", n);
+        if n as usize > self.bits_left() {
+            return Err(Error::OutOfData);
+        }
+        let mut value = 0u64;
+        let mut remaining = n;
+        while remaining > 0 {
+            let byte = self.data[self.position / 8];
+            let offset = (self.position % 8) as u32;
+            let take = remaining.min(8 - offset);
+            let bits = (byte >> (8 - offset - take)) as u64 & ((1 << take) - 1);
+            value = (value << take) | bits;
+            self.position += take as usize;
+            remaining -= take;
+        }
+        Ok(value)
+    }
+
+    pub fn read_bit(&mut self) -> Result<bool> {
+        self.read_bits(1).map(|bit| bit == 1)
+    }
+
+    /// `ue(v)`.
+    pub fn read_ue(&mut self) -> Result<u32> {
+        let mut leading_zeros = 0;
+        while !self.read_bit()? {
+            leading_zeros += 1;
+            if leading_zeros > 31 {
+                return Err(Error::InvalidExpGolomb);
+            }
+        }
+        let suffix = self.read_bits(leading_zeros)?;
+        Ok(((1u64 << leading_zeros) - 1 + suffix as u64) as u32)
+    }
+
+    /// `se(v)`.
+    pub fn read_se(&mut self) -> Result<i32> {
+        let code = self.read_ue()? as i64;
+        let value = if code % 2 == 1 {
+            (code + 1) / 2
+        } else {
+            -(code / 2)
+        };
+        Ok(value as i32)
+    }
+
+    /// AV1 `le(n)`: an `n`-byte little-endian value.
+    pub fn read_le(&mut self, n: u32) -> Result<u64> {
+        assert!(n <= 8, "cannot read {} bytes into a u64", n);
+        let mut value = 0;
+        for i in 0..n {
+            value |= self.read_bits_u64(8)? << (i * 8);
+        }
+        Ok(value)
+    }
+
+    /// AV1 `leb128()`.
+    pub fn read_leb128(&mut self) -> Result<u64> {
+        let mut value = 0;
+        for i in 0..8 {
+            let byte = self.read_bits(8)?;
+            value |= ((byte & 0x7f) as u64) << (i * 7);
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(Error::InvalidLeb128)
+    }
+
+    /// `more_rbsp_data()`: whether anything but the `rbsp_stop_one_bit`,
+    /// the zero bits after it and any `cabac_zero_words` is left.
+    pub fn more_rbsp_data(&self) -> bool {
+        let Some(last) = self.data.iter().rposition(|&b| b != 0) else {
+            return false;
+        };
+        let stop_bit = last * 8 + 7 - self.data[last].trailing_zeros() as usize;
+        self.position < stop_bit
+    }
+}
+
+/// The boolean entropy decoder of VP8 (RFC 6386, section 7).
+///
+/// Reading past the end of the data yields zeros, as in libvpx.
+#[derive(Debug, Clone)]
+pub struct BoolDecoder<'a> {
+    data: &'a [u8],
+    /// Next byte to shift into `value`.
+    position: usize,
+    value: u32,
+    range: u32,
+    bit_count: u32,
+}
+
+impl<'a> BoolDecoder<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        let mut decoder = Self {
+            data,
+            position: 0,
+            value: 0,
+            range: 255,
+            bit_count: 0,
+        };
+        decoder.value = (decoder.next_byte() << 8) | decoder.next_byte();
+        decoder
+    }
+
+    fn next_byte(&mut self) -> u32 {
+        let byte = self.data.get(self.position).copied().unwrap_or(0);
+        self.position += 1;
+        byte as u32
+    }
+
+    /// Number of bytes of the data consumed so far.
+    pub fn position(&self) -> usize {
+        self.position.min(self.data.len())
+    }
+
+    /// Decodes a bool that is false with probability `probability / 256`.
+    pub fn read_bool(&mut self, probability: u8) -> bool {
+        let split = 1 + (((self.range - 1) * probability as u32) >> 8);
+        let big_split = split << 8;
+        let bit = if self.value >= big_split {
+            self.range -= split;
+            self.value -= big_split;
+            true
+        } else {
+            self.range = split;
+            false
+        };
+        while self.range < 128 {
+            self.value <<= 1;
+            self.range <<= 1;
+            self.bit_count += 1;
+            if self.bit_count == 8 {
+                self.bit_count = 0;
+                self.value |= self.next_byte();
+            }
+        }
+        bit
+    }
+
+    pub fn read_flag(&mut self) -> bool {
+        self.read_bool(128)
+    }
+
+    /// `L(n)`: an `n`-bit unsigned literal, MSB first.
+    pub fn read_literal(&mut self, n: u32) -> u32 {
+        (0..n).fold(0, |value, _| (value << 1) | self.read_flag() as u32)
+    }
+}
+
+/// Writes fields MSB-first into a byte vector.
+#[derive(Debug, Clone, Default)]
+pub struct BitWriter {
+    data: Vec<u8>,
+    /// Bits not yet forming a whole byte, in the low `pending_bits` bits.
+    pending: u8,
+    pending_bits: u32,
+    emulation_prevention: bool,
+    /// Zero bytes at the end of `data`, for emulation prevention.
+    zeros: usize,
+}
+
+impl BitWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a writer that inserts an `emulation_prevention_three_byte`
+    /// wherever the output would otherwise contain `00 00 0x` with `x <= 3`,
+    /// so that it can be used as a NAL unit directly.
+    pub fn with_emulation_prevention() -> Self {
+        Self {
+            emulation_prevention: true,
+            ..Self::default()
+        }
+    }
+
+    /// Number of bits written so far, including emulation prevention bytes.
+    pub fn position(&self) -> usize {
+        self.data.len() * 8 + self.pending_bits as usize
+    }
+
+    pub fn byte_aligned(&self) -> bool {
+        self.pending_bits == 0
+    }
+
+    fn push_byte(&mut self, byte: u8) {
+        if self.emulation_prevention && self.zeros >= 2 && byte <= 3 {
+            self.data.push(3);
+            self.zeros = 0;
+        }
+        self.data.push(byte);
+        self.zeros = if byte == 0 { self.zeros + 1 } else { 0 };
+    }
+
+    /// `u(n)` for `n` up to 64; `value` must fit in `n` bits.
+    pub fn write_bits(&mut self, value: u64, n: u32) {
+        assert!(n <= 64, "cannot write {} bits from a u64", n);
+        debug_assert!(
+            n == 64 || value >> n == 0,
+            "{} does not fit in {} bits",
+            value,
+            n
+        );
+        let mut remaining = n;
+        while remaining > 0 {
+            let take = remaining.min(8 - self.pending_bits);
+            let bits = (value >> (remaining - take)) as u8 & ((1u16 << take) - 1) as u8;
+            self.pending = ((self.pending as u16) << take) as u8 | bits;
+            self.pending_bits += take;
+            remaining -= take;
+            if self.pending_bits == 8 {
+                let byte = self.pending;
+                self.push_byte(byte);
+                self.pending = 0;
+                self.pending_bits = 0;
+            }
+        }
+    }
+
+    pub fn write_bit(&mut self, bit: bool) {
+        self.write_bits(bit as u64, 1);
+    }
+
+    /// `ue(v)`.
+    pub fn write_ue(&mut self, value: u32) {
+        let code = value as u64 + 1;
+        let len = 64 - code.leading_zeros();
+        self.write_bits(0, len - 1);
+        self.write_bits(code, len);
+    }
+
+    /// `se(v)`.
+    pub fn write_se(&mut self, value: i32) {
+        let value = value as i64;
+        let code = if value > 0 { 2 * value - 1 } else { -2 * value };
+        self.write_ue(code as u32);
+    }
+
+    /// AV1 `le(n)`.
+    pub fn write_le(&mut self, value: u64, n: u32) {
+        for i in 0..n {
+            self.write_bits((value >> (i * 8)) & 0xff, 8);
+        }
+    }
+
+    /// AV1 `leb128()`, in as few bytes as possible.
+    pub fn write_leb128(&mut self, mut value: u64) {
+        loop {
+            let byte = value & 0x7f;
+            value >>= 7;
+            if value == 0 {
+                self.write_bits(byte, 8);
+                return;
+            }
+            self.write_bits(byte | 0x80, 8);
+        }
+    }
+
+    /// Pads with zero bits to the next byte boundary.
+    pub fn align(&mut self) {
+        if !self.byte_aligned() {
+            self.write_bits(0, 8 - self.pending_bits);
+        }
+    }
+
+    /// `rbsp_trailing_bits()`: a one bit, then zeros up to a byte boundary.
+    pub fn write_trailing_bits(&mut self) {
+        self.write_bit(true);
+        self.align();
+    }
+
+    /// Returns the bytes written, padding the last one with zero bits.
+    pub fn into_bytes(mut self) -> Vec<u8> {
+        self.align();
+        self.data
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::nal;
+
+    /// `count` chunks of `len` pseudo-random bytes, the same for each
+    /// `seed`.
+    pub(crate) fn random_chunks(
+        seed: u32,
+        len: usize,
+        count: usize,
+    ) -> impl Iterator<Item = Vec<u8>> {
+        let mut state = seed;
+        (0..count).map(move |_| {
+            (0..len)
+                .map(|_| {
+                    state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                    (state >> 24) as u8
+                })
+                .collect()
+        })
+    }
+
+    #[test]
+    fn reads_fields() {
+        // 101 | 1 (ue 0) | 010 (ue 1) | 00111 (se -3) | 0001000 (ue 7) | pad
+        let data = [0b1011_0100, 0b0111_0001, 0b0000_0000];
+        let mut reader = BitReader::new(&data);
+        assert_eq!(reader.read_bits(3), Ok(0b101));
+        assert_eq!(reader.read_ue(), Ok(0));
+        assert_eq!(reader.read_ue(), Ok(1));
+        assert_eq!(reader.read_se(), Ok(-3));
+        assert_eq!(reader.read_ue(), Ok(7));
+        assert_eq!(reader.position(), 19);
+        assert!(!reader.byte_aligned());
+        reader.align();
+        assert_eq!(reader.bits_left(), 0);
+        assert_eq!(reader.read_bit(), Err(Error::OutOfData));
+
+        let mut reader = BitReader::new(&[0, 0, 0, 0, 0x80]);
+        assert_eq!(reader.read_ue(), Err(Error::InvalidExpGolomb));
+    }
+
+    #[test]
+    fn more_rbsp_data() {
+        let data = [0b1010_0000, 0b1100_0000, 0, 0];
+        let mut reader = BitReader::new(&data);
+        reader.skip_bits(8).unwrap();
+        assert!(reader.more_rbsp_data());
+        reader.skip_bits(1).unwrap();
+        assert!(!reader.more_rbsp_data());
+        assert!(!BitReader::new(&[0, 0]).more_rbsp_data());
+    }
+
+    #[test]
+    fn av1_fields() {
+        let mut reader = BitReader::new(&[0x34, 0x12, 0xe5, 0x8e, 0x26, 0x05]);
+        assert_eq!(reader.read_le(2), Ok(0x1234));
+        assert_eq!(reader.read_leb128(), Ok(624485));
+        assert_eq!(reader.read_leb128(), Ok(5));
+        assert_eq!(
+            BitReader::new(&[0x80; 9]).read_leb128(),
+            Err(Error::InvalidLeb128)
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut writer = BitWriter::new();
+        writer.write_bits(0b101, 3);
+        writer.write_bits(u64::MAX, 64);
+        for value in [0, 1, 2, 254, 255, 65535, u32::MAX - 1] {
+            writer.write_ue(value);
+        }
+        for value in [0, 1, -1, 100, -100, i32::MAX, i32::MIN + 1] {
+            writer.write_se(value);
+        }
+        writer.write_le(0xabcd, 2);
+        writer.write_leb128(624485);
+        writer.write_trailing_bits();
+        assert!(writer.byte_aligned());
+        let data = writer.into_bytes();
+
+        let mut reader = BitReader::new(&data);
+        assert_eq!(reader.read_bits(3), Ok(0b101));
+        assert_eq!(reader.read_bits_u64(64), Ok(u64::MAX));
+        for value in [0, 1, 2, 254, 255, 65535, u32::MAX - 1] {
+            assert_eq!(reader.read_ue(), Ok(value));
+        }
+        for value in [0, 1, -1, 100, -100, i32::MAX, i32::MIN + 1] {
+            assert_eq!(reader.read_se(), Ok(value));
+        }
+        assert_eq!(reader.read_le(2), Ok(0xabcd));
+        assert_eq!(reader.read_leb128(), Ok(624485));
+        assert!(!reader.more_rbsp_data());
+    }
+
+    #[test]
+    fn emulation_prevention() {
+        let payload = [0x67, 0, 0, 1, 0, 0, 0, 0, 0, 3, 0, 0, 4];
+        let mut writer = BitWriter::with_emulation_prevention();
+        for &b in &payload {
+            writer.write_bits(b as u64, 8);
+        }
+        let nal = writer.into_bytes();
+        assert_eq!(nal, [0x67, 0, 0, 3, 1, 0, 0, 3, 0, 0, 3, 0, 3, 0, 0, 4]);
+        assert_eq!(nal::to_rbsp(&nal), payload);
+    }
+
+    /// The encoder of RFC 6386, section 7.3, flushed with padding.
+    fn bool_encode(bools: &[(u8, bool)]) -> Vec<u8> {
+        let mut output: Vec<u8> = Vec::new();
+        let (mut range, mut bottom, mut bit_count) = (255u32, 0u32, 24);
+        let padding = std::iter::repeat_n((128, false), 32);
+        for (probability, value) in bools.iter().copied().chain(padding) {
+            let split = 1 + (((range - 1) * probability as u32) >> 8);
+            if value {
+                bottom += split;
+                range -= split;
+            } else {
+                range = split;
+            }
+            while range < 128 {
+                range <<= 1;
+                if bottom & (1 << 31) != 0 {
+                    let carry = output.iter().rposition(|&b| b != 255).unwrap();
+                    output[carry] += 1;
+                    output[carry + 1..].fill(0);
+                }
+                bottom <<= 1;
+                bit_count -= 1;
+                if bit_count == 0 {
+                    output.push((bottom >> 24) as u8);
+                    bottom &= (1 << 24) - 1;
+                    bit_count = 8;
+                }
+            }
+        }
+        output
+    }
+
+    #[test]
+    fn bool_decoder() {
+        let mut seed = 1u32;
+        let mut random = move || {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            seed >> 16
+        };
+        let bools: Vec<_> = (0..1000)
+            .map(|_| {
+                let probability = (random() % 255 + 1) as u8;
+                (probability, random() % 256 >= probability as u32)
+            })
+            .collect();
+        let data = bool_encode(&bools);
+        let mut decoder = BoolDecoder::new(&data);
+        for (i, &(probability, value)) in bools.iter().enumerate() {
+            assert_eq!(decoder.read_bool(probability), value, "bool {}", i);
+        }
+        assert_eq!(decoder.read_literal(7), 0);
+
+        let data = bool_encode(&[(128, true), (128, false), (128, true)]);
+        let mut decoder = BoolDecoder::new(&data);
+        assert_eq!(decoder.read_literal(3), 0b101);
+    }
+}
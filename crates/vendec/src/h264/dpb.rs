@@ -0,0 +1,1130 @@
+use std::collections::VecDeque;
+
+use super::{
+    DecRefPicMarking, Error, Mmco, PicOrderCnt, PictureStructure, RefPicListModification, Result,
+    SliceHeader, Sps,
+};
+
+/// How a field is marked by the reference picture marking process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reference {
+    Unused,
+    ShortTerm,
+    LongTerm,
+}
+
+/// A decoded field of a [`DpbFrame`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Field {
+    pub poc: i32,
+    pub reference: Reference,
+}
+
+/// A frame buffer of the DPB: a decoded frame, a complementary field pair or
+/// a non-paired field.
+#[derive(Debug, Clone)]
+pub struct DpbFrame<T> {
+    /// What the picture was decoded into.
+    pub handle: T,
+    pub frame_num: u32,
+    /// Valid while a field is marked long-term.
+    pub long_term_frame_idx: u32,
+    pub top: Option<Field>,
+    pub bottom: Option<Field>,
+    pub needed_for_output: bool,
+    /// Inserted for a gap in `frame_num` (8.2.5.2) rather than decoded.
+    pub non_existing: bool,
+}
+
+impl<T> DpbFrame<T> {
+    pub fn field(&self, parity: PictureStructure) -> Option<&Field> {
+        match parity {
+            PictureStructure::TopField => self.top.as_ref(),
+            PictureStructure::BottomField => self.bottom.as_ref(),
+            PictureStructure::Frame => None,
+        }
+    }
+
+    fn fields(&self) -> impl Iterator<Item = &Field> {
+        self.top.iter().chain(&self.bottom)
+    }
+
+    /// Whether any field is marked as `reference`.
+    pub fn has(&self, reference: Reference) -> bool {
+        self.fields().any(|field| field.reference == reference)
+    }
+
+    /// Whether the frame as a whole, both fields, is marked as `reference`.
+    pub fn is_frame(&self, reference: Reference) -> bool {
+        self.top.is_some_and(|f| f.reference == reference)
+            && self.bottom.is_some_and(|f| f.reference == reference)
+    }
+
+    pub fn is_reference(&self) -> bool {
+        self.fields()
+            .any(|field| field.reference != Reference::Unused)
+    }
+
+    /// `PicOrderCnt()` of the frame, or of its one field.
+    pub fn poc(&self) -> i32 {
+        self.fields().map(|field| field.poc).min().unwrap_or(0)
+    }
+
+    /// `PicOrderCnt()` over the fields marked as `reference`.
+    fn reference_poc(&self, reference: Reference) -> i32 {
+        self.fields()
+            .filter(|field| field.reference == reference)
+            .map(|field| field.poc)
+            .min()
+            .unwrap_or(0)
+    }
+
+    fn mark(&mut self, structure: PictureStructure, reference: Reference) {
+        let (top, bottom) = match structure {
+            PictureStructure::Frame => (true, true),
+            PictureStructure::TopField => (true, false),
+            PictureStructure::BottomField => (false, true),
+        };
+        if let Some(field) = self.top.as_mut().filter(|_| top) {
+            field.reference = reference;
+        }
+        if let Some(field) = self.bottom.as_mut().filter(|_| bottom) {
+            field.reference = reference;
+        }
+    }
+}
+
+/// An entry of a reference picture list: a frame, or one field of a frame,
+/// of [`Dpb::frames`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RefPic {
+    pub frame: usize,
+    pub structure: PictureStructure,
+}
+
+/// What the DPB keeps about the picture being decoded.
+#[derive(Debug, Clone)]
+struct CurrentPicture {
+    structure: PictureStructure,
+    frame_num: u32,
+    poc: PicOrderCnt,
+    idr: bool,
+    is_reference: bool,
+    marking: Option<DecRefPicMarking>,
+    /// For a second field, the frame holding the first.
+    first_field: Option<usize>,
+}
+
+/// The decoded picture buffer: reference picture marking (8.2.5), frame
+/// number gaps, reference picture list construction (8.2.4) and output in
+/// display order by the bumping process of C.4.5.3.
+///
+/// `T` is what pictures are decoded into, typically a surface, and is
+/// cloned into the output queue.
+///
+/// For each picture, call [`Dpb::start_picture`] with its first slice,
+/// [`Dpb::ref_pic_lists`] for each of its slices, and
+/// [`Dpb::finish_picture`] once it has been decoded. Pictures in display
+/// order come out of [`Dpb::pop_output`].
+#[derive(Debug)]
+pub struct Dpb<T> {
+    frames: Vec<DpbFrame<T>>,
+    max_frames: usize,
+    max_num_reorder_frames: usize,
+    max_num_ref_frames: usize,
+    max_frame_num: u32,
+    /// `MaxLongTermFrameIdx`, `None` for "no long-term frame indices".
+    max_long_term_frame_idx: Option<u32>,
+    prev_ref_frame_num: u32,
+    current: Option<CurrentPicture>,
+    /// The frame whose first field was the last picture stored.
+    last_field: Option<usize>,
+    output: VecDeque<T>,
+}
+
+impl<T: Clone> Default for Dpb<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Clone> Dpb<T> {
+    pub fn new() -> Self {
+        Self {
+            frames: Vec::new(),
+            max_frames: 1,
+            max_num_reorder_frames: 0,
+            max_num_ref_frames: 1,
+            max_frame_num: 16,
+            max_long_term_frame_idx: None,
+            prev_ref_frame_num: 0,
+            current: None,
+            last_field: None,
+            output: VecDeque::new(),
+        }
+    }
+
+    pub fn frames(&self) -> &[DpbFrame<T>] {
+        &self.frames
+    }
+
+    /// The frame holding the first field of the current picture, if it is
+    /// a second field.
+    pub fn first_field(&self) -> Option<&DpbFrame<T>> {
+        let index = self.current.as_ref()?.first_field?;
+        Some(&self.frames[index])
+    }
+
+    /// The frame holding the first field of the picture `header` is the
+    /// first slice of, if that picture is a second field.
+    pub fn first_field_of(&self, header: &SliceHeader) -> Option<&DpbFrame<T>> {
+        Some(&self.frames[self.first_field_index(header)?])
+    }
+
+    fn first_field_index(&self, header: &SliceHeader) -> Option<usize> {
+        let structure = header.structure();
+        self.last_field.filter(|&index| {
+            let frame = &self.frames[index];
+            structure.is_field()
+                && !header.idr_pic_flag
+                && frame.frame_num == header.frame_num
+                && frame.field(structure).is_none()
+                && frame.field(structure.opposite()).is_some()
+                && frame.is_reference() == (header.nal_ref_idc != 0)
+        })
+    }
+
+    /// Takes the next picture in display order.
+    pub fn pop_output(&mut self) -> Option<T> {
+        self.output.pop_front()
+    }
+
+    /// Outputs every picture still waiting and empties the DPB, as at the
+    /// end of the stream.
+    pub fn flush(&mut self) {
+        while self.bump() {}
+        self.clear();
+    }
+
+    /// Empties the DPB without outputting anything more. Pictures already
+    /// in the output queue stay there.
+    pub fn clear(&mut self) {
+        self.frames.clear();
+        self.current = None;
+        self.last_field = None;
+        self.max_long_term_frame_idx = None;
+        self.prev_ref_frame_num = 0;
+    }
+
+    /// Begins the picture `header` is the first slice of: handles IDR
+    /// pictures and gaps in `frame_num`, and pairs second fields with their
+    /// first. Frames standing in for missing `frame_num`s are decoded into
+    /// `non_existing()`.
+    pub fn start_picture(
+        &mut self,
+        sps: &Sps,
+        header: &SliceHeader,
+        poc: PicOrderCnt,
+        mut non_existing: impl FnMut() -> T,
+    ) -> Result<()> {
+        self.max_frames = sps.max_dpb_frames() as usize;
+        self.max_num_reorder_frames = sps.max_num_reorder_frames() as usize;
+        self.max_num_ref_frames = (sps.max_num_ref_frames as usize).max(1);
+        self.max_frame_num = sps.max_frame_num();
+
+        let structure = header.structure();
+        let is_reference = header.nal_ref_idc != 0;
+        let first_field = self.first_field_index(header);
+        self.last_field = None;
+
+        if header.idr_pic_flag {
+            let no_output = header
+                .dec_ref_pic_marking
+                .as_ref()
+                .is_some_and(|marking| marking.no_output_of_prior_pics_flag);
+            if no_output {
+                self.frames.clear();
+            } else {
+                while self.bump() {}
+                self.frames.clear();
+            }
+            self.prev_ref_frame_num = 0;
+        } else if first_field.is_none()
+            && header.frame_num != self.prev_ref_frame_num
+            && header.frame_num != (self.prev_ref_frame_num + 1) % self.max_frame_num
+        {
+            self.fill_frame_num_gap(header.frame_num, &mut non_existing)?;
+        }
+
+        self.current = Some(CurrentPicture {
+            structure,
+            frame_num: header.frame_num,
+            poc,
+            idr: header.idr_pic_flag,
+            is_reference,
+            marking: header.dec_ref_pic_marking.clone(),
+            first_field,
+        });
+        Ok(())
+    }
+
+    /// 8.2.5.2: stores a non-existing short-term frame for each `frame_num`
+    /// skipped before `frame_num`.
+    fn fill_frame_num_gap(
+        &mut self,
+        frame_num: u32,
+        non_existing: &mut impl FnMut() -> T,
+    ) -> Result<()> {
+        let mut unused = (self.prev_ref_frame_num + 1) % self.max_frame_num;
+        while unused != frame_num {
+            self.sliding_window(unused);
+            self.remove_unused();
+            self.make_room()?;
+            let field = Field {
+                poc: 0,
+                reference: Reference::ShortTerm,
+            };
+            self.frames.push(DpbFrame {
+                handle: non_existing(),
+                frame_num: unused,
+                long_term_frame_idx: 0,
+                top: Some(field),
+                bottom: Some(field),
+                needed_for_output: false,
+                non_existing: true,
+            });
+            self.prev_ref_frame_num = unused;
+            unused = (unused + 1) % self.max_frame_num;
+        }
+        Ok(())
+    }
+
+    fn current(&self) -> &CurrentPicture {
+        self.current
+            .as_ref()
+            .expect("Dpb::start_picture has not been called")
+    }
+
+    /// `FrameNumWrap` of a short-term frame, relative to `frame_num`.
+    fn frame_num_wrap(&self, frame: &DpbFrame<T>, frame_num: u32) -> i32 {
+        if frame.frame_num > frame_num {
+            frame.frame_num as i32 - self.max_frame_num as i32
+        } else {
+            frame.frame_num as i32
+        }
+    }
+
+    /// `PicNum` or `LongTermPicNum` of a field or frame, scaled for the
+    /// structure of the current picture.
+    fn pic_num(&self, value: i32, structure: PictureStructure) -> i32 {
+        let current = self.current().structure;
+        match current {
+            PictureStructure::Frame => value,
+            _ if structure == current => 2 * value + 1,
+            _ => 2 * value,
+        }
+    }
+
+    /// The short-term pictures that can be referenced, with their `PicNum`.
+    fn short_term_pics(&self) -> Vec<(RefPic, i32)> {
+        let current = self.current();
+        let mut pics = Vec::new();
+        for (index, frame) in self.frames.iter().enumerate() {
+            let wrap = self.frame_num_wrap(frame, current.frame_num);
+            for &structure in self.candidate_structures() {
+                let marked = match structure {
+                    PictureStructure::Frame => frame.is_frame(Reference::ShortTerm),
+                    _ => frame
+                        .field(structure)
+                        .is_some_and(|f| f.reference == Reference::ShortTerm),
+                };
+                if marked {
+                    let pic = RefPic {
+                        frame: index,
+                        structure,
+                    };
+                    pics.push((pic, self.pic_num(wrap, structure)));
+                }
+            }
+        }
+        pics
+    }
+
+    /// The long-term pictures that can be referenced, with their
+    /// `LongTermPicNum`.
+    fn long_term_pics(&self) -> Vec<(RefPic, i32)> {
+        let mut pics = Vec::new();
+        for (index, frame) in self.frames.iter().enumerate() {
+            for &structure in self.candidate_structures() {
+                let marked = match structure {
+                    PictureStructure::Frame => frame.is_frame(Reference::LongTerm),
+                    _ => frame
+                        .field(structure)
+                        .is_some_and(|f| f.reference == Reference::LongTerm),
+                };
+                if marked {
+                    let pic = RefPic {
+                        frame: index,
+                        structure,
+                    };
+                    let idx = frame.long_term_frame_idx as i32;
+                    pics.push((pic, self.pic_num(idx, structure)));
+                }
+            }
+        }
+        pics
+    }
+
+    fn candidate_structures(&self) -> &'static [PictureStructure] {
+        if self.current().structure.is_field() {
+            &[PictureStructure::TopField, PictureStructure::BottomField]
+        } else {
+            &[PictureStructure::Frame]
+        }
+    }
+
+    /// `CurrPicNum` and `MaxPicNum`.
+    fn curr_pic_num(&self) -> (i32, i32) {
+        let current = self.current();
+        if current.structure.is_field() {
+            (
+                2 * current.frame_num as i32 + 1,
+                2 * self.max_frame_num as i32,
+            )
+        } else {
+            (current.frame_num as i32, self.max_frame_num as i32)
+        }
+    }
+
+    /// Builds the reference picture lists of a slice of the current picture,
+    /// `RefPicList0` and `RefPicList1`, including their modification. Lists
+    /// are at most `num_ref_idx_lX_active_minus1 + 1` long; missing trailing
+    /// entries are "no reference picture".
+    pub fn ref_pic_lists(&self, header: &SliceHeader) -> Result<(Vec<RefPic>, Vec<RefPic>)> {
+        if header.slice_type.is_intra() {
+            return Ok((Vec::new(), Vec::new()));
+        }
+        let (mut list0, mut list1) = if header.slice_type.is_b() {
+            self.initial_b_lists()
+        } else {
+            (self.initial_p_list(), Vec::new())
+        };
+        if list1.len() > 1 && list0 == list1 {
+            list1.swap(0, 1);
+        }
+        list0.truncate(header.num_ref_idx_l0_active_minus1 as usize + 1);
+        self.modify(&mut list0, &header.ref_pic_list_modification_l0)?;
+        list0.truncate(header.num_ref_idx_l0_active_minus1 as usize + 1);
+        if header.slice_type.is_b() {
+            list1.truncate(header.num_ref_idx_l1_active_minus1 as usize + 1);
+            self.modify(&mut list1, &header.ref_pic_list_modification_l1)?;
+            list1.truncate(header.num_ref_idx_l1_active_minus1 as usize + 1);
+        }
+        Ok((list0, list1))
+    }
+
+    /// 8.2.4.2.1 and 8.2.4.2.2.
+    fn initial_p_list(&self) -> Vec<RefPic> {
+        let current = self.current();
+        if !current.structure.is_field() {
+            let mut short = self.short_term_pics();
+            short.sort_by_key(|&(_, pic_num)| std::cmp::Reverse(pic_num));
+            let mut long = self.long_term_pics();
+            long.sort_by_key(|&(_, long_term_pic_num)| long_term_pic_num);
+            return short.into_iter().chain(long).map(|(pic, _)| pic).collect();
+        }
+
+        let mut short: Vec<usize> = self.frames_with(Reference::ShortTerm);
+        short.sort_by_key(|&i| {
+            std::cmp::Reverse(self.frame_num_wrap(&self.frames[i], current.frame_num))
+        });
+        let mut long = self.frames_with(Reference::LongTerm);
+        long.sort_by_key(|&i| self.frames[i].long_term_frame_idx);
+        let mut list = self.alternate_fields(&short, Reference::ShortTerm);
+        list.extend(self.alternate_fields(&long, Reference::LongTerm));
+        list
+    }
+
+    /// 8.2.4.2.3 and 8.2.4.2.4.
+    fn initial_b_lists(&self) -> (Vec<RefPic>, Vec<RefPic>) {
+        let current = self.current();
+        let current_poc = current.poc.get(current.structure);
+        // Entries in order of PicOrderCnt, then split at the current one.
+        let split = |mut entries: Vec<(RefPic, i32)>| {
+            entries.sort_by_key(|&(_, poc)| poc);
+            let at = entries.partition_point(|&(_, poc)| poc <= current_poc);
+            let (before, after) = entries.split_at(at);
+            let before = before.iter().rev().map(|&(pic, _)| pic);
+            let after = after.iter().map(|&(pic, _)| pic);
+            (
+                before.clone().chain(after.clone()).collect::<Vec<_>>(),
+                after.chain(before).collect::<Vec<_>>(),
+            )
+        };
+
+        if !current.structure.is_field() {
+            let short = self
+                .short_term_pics()
+                .into_iter()
+                .map(|(pic, _)| (pic, self.frames[pic.frame].poc()))
+                .collect();
+            let (mut list0, mut list1) = split(short);
+            let mut long = self.long_term_pics();
+            long.sort_by_key(|&(_, long_term_pic_num)| long_term_pic_num);
+            list0.extend(long.iter().map(|&(pic, _)| pic));
+            list1.extend(long.iter().map(|&(pic, _)| pic));
+            return (list0, list1);
+        }
+
+        let short = self
+            .frames_with(Reference::ShortTerm)
+            .into_iter()
+            .map(|i| {
+                let pic = RefPic {
+                    frame: i,
+                    structure: PictureStructure::Frame,
+                };
+                (pic, self.frames[i].reference_poc(Reference::ShortTerm))
+            })
+            .collect();
+        let (frames0, frames1) = split(short);
+        let frames0: Vec<_> = frames0.iter().map(|pic| pic.frame).collect();
+        let frames1: Vec<_> = frames1.iter().map(|pic| pic.frame).collect();
+        let mut long = self.frames_with(Reference::LongTerm);
+        long.sort_by_key(|&i| self.frames[i].long_term_frame_idx);
+        let long = self.alternate_fields(&long, Reference::LongTerm);
+        let mut list0 = self.alternate_fields(&frames0, Reference::ShortTerm);
+        list0.extend(&long);
+        let mut list1 = self.alternate_fields(&frames1, Reference::ShortTerm);
+        list1.extend(&long);
+        (list0, list1)
+    }
+
+    fn frames_with(&self, reference: Reference) -> Vec<usize> {
+        (0..self.frames.len())
+            .filter(|&i| self.frames[i].has(reference))
+            .collect()
+    }
+
+    /// 8.2.4.2.5: turns an ordered list of frames into a list of their
+    /// fields marked as `reference`, alternating parity starting with that
+    /// of the current field.
+    fn alternate_fields(&self, frames: &[usize], reference: Reference) -> Vec<RefPic> {
+        let parity = self.current().structure;
+        let with_field = |structure: PictureStructure| {
+            frames
+                .iter()
+                .filter(move |&&i| {
+                    self.frames[i]
+                        .field(structure)
+                        .is_some_and(|f| f.reference == reference)
+                })
+                .map(move |&frame| RefPic { frame, structure })
+        };
+        let mut same = with_field(parity).peekable();
+        let mut opposite = with_field(parity.opposite()).peekable();
+        let mut list = Vec::new();
+        loop {
+            match (same.next(), opposite.peek()) {
+                (Some(pic), _) => {
+                    list.push(pic);
+                    list.extend(opposite.next());
+                }
+                (None, Some(_)) => list.extend(opposite.by_ref()),
+                (None, None) => return list,
+            }
+        }
+    }
+
+    /// 8.2.4.3.
+    fn modify(
+        &self,
+        list: &mut Vec<RefPic>,
+        modifications: &[RefPicListModification],
+    ) -> Result<()> {
+        let (curr_pic_num, max_pic_num) = self.curr_pic_num();
+        let mut pic_num_pred = curr_pic_num;
+        for (ref_idx, &modification) in modifications.iter().enumerate() {
+            let pic = match modification {
+                RefPicListModification::SubtractPicNum(abs_diff_minus1)
+                | RefPicListModification::AddPicNum(abs_diff_minus1) => {
+                    if abs_diff_minus1 >= max_pic_num as u32 {
+                        return Err(Error::InvalidValue {
+                            name: "abs_diff_pic_num_minus1",
+                            value: abs_diff_minus1 as i64,
+                        });
+                    }
+                    let abs_diff = abs_diff_minus1 as i32 + 1;
+                    let no_wrap =
+                        if matches!(modification, RefPicListModification::SubtractPicNum(_)) {
+                            (pic_num_pred - abs_diff).rem_euclid(max_pic_num)
+                        } else {
+                            (pic_num_pred + abs_diff).rem_euclid(max_pic_num)
+                        };
+                    pic_num_pred = no_wrap;
+                    let pic_num = if no_wrap > curr_pic_num {
+                        no_wrap - max_pic_num
+                    } else {
+                        no_wrap
+                    };
+                    self.short_term_pics()
+                        .into_iter()
+                        .find(|&(_, n)| n == pic_num)
+                        .ok_or(Error::InvalidValue {
+                            name: "abs_diff_pic_num_minus1",
+                            value: abs_diff_minus1 as i64,
+                        })?
+                        .0
+                }
+                RefPicListModification::LongTermPicNum(long_term_pic_num) => {
+                    self.long_term_pics()
+                        .into_iter()
+                        .find(|&(_, n)| n as u32 == long_term_pic_num)
+                        .ok_or(Error::InvalidValue {
+                            name: "long_term_pic_num",
+                            value: long_term_pic_num as i64,
+                        })?
+                        .0
+                }
+                RefPicListModification::SubtractViewIdx(_)
+                | RefPicListModification::AddViewIdx(_) => {
+                    return Err(Error::Unsupported("inter-view prediction"));
+                }
+            };
+            list.insert(ref_idx, pic);
+            let mut index = ref_idx + 1;
+            while index < list.len() {
+                if list[index] == pic {
+                    list.remove(index);
+                } else {
+                    index += 1;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Marks the current picture (8.2.5) and stores it (C.4.4 and C.4.5),
+    /// outputting pictures as needed. `handle` is what it was decoded into;
+    /// a second field keeps the handle of its first.
+    pub fn finish_picture(&mut self, handle: T) -> Result<()> {
+        let mut current = self
+            .current
+            .take()
+            .expect("Dpb::start_picture has not been called");
+        // Keep picture numbers relative to the current picture while marking.
+        self.current = Some(current.clone());
+        let mut reference = if current.is_reference {
+            Reference::ShortTerm
+        } else {
+            Reference::Unused
+        };
+        let mut long_term_frame_idx = 0;
+        let mut mmco5 = false;
+
+        if current.idr {
+            let long_term = current
+                .marking
+                .as_ref()
+                .is_some_and(|marking| marking.long_term_reference_flag);
+            if long_term {
+                reference = Reference::LongTerm;
+                self.max_long_term_frame_idx = Some(0);
+            } else {
+                self.max_long_term_frame_idx = None;
+            }
+        } else if current.is_reference {
+            match current.marking.as_ref().and_then(|m| m.adaptive.clone()) {
+                Some(ops) => {
+                    for op in ops {
+                        match op {
+                            Mmco::AllUnused => {
+                                mmco5 = true;
+                                self.mark_all_unused(current.first_field);
+                            }
+                            Mmco::CurrentToLongTerm {
+                                long_term_frame_idx: idx,
+                            } => {
+                                self.check_long_term_frame_idx(idx)?;
+                                self.free_long_term_frame_idx(idx, current.first_field);
+                                reference = Reference::LongTerm;
+                                long_term_frame_idx = idx;
+                            }
+                            op => self.apply_mmco(op)?,
+                        }
+                    }
+                }
+                None => {
+                    let first_field_short_term = current
+                        .first_field
+                        .is_some_and(|i| self.frames[i].has(Reference::ShortTerm));
+                    if !first_field_short_term {
+                        self.sliding_window(current.frame_num);
+                    }
+                }
+            }
+        }
+        self.current = None;
+
+        if mmco5 {
+            // 8.2.1: the picture is treated as frame_num 0 and its order
+            // counts are rebased to 0.
+            let temp = current.poc.get(current.structure);
+            current.poc.top -= temp;
+            current.poc.bottom -= temp;
+            current.frame_num = 0;
+        }
+        if current.is_reference {
+            self.prev_ref_frame_num = current.frame_num;
+        }
+
+        let field = |poc: i32| Field { poc, reference };
+        if let Some(index) = current.first_field {
+            let frame = &mut self.frames[index];
+            match current.structure {
+                PictureStructure::TopField => frame.top = Some(field(current.poc.top)),
+                _ => frame.bottom = Some(field(current.poc.bottom)),
+            }
+            if reference == Reference::LongTerm {
+                frame.long_term_frame_idx = long_term_frame_idx;
+            }
+            self.remove_unused();
+            self.bump_reordered();
+            return Ok(());
+        }
+
+        if mmco5 {
+            while self.bump() {}
+        }
+        self.remove_unused();
+        let frame = DpbFrame {
+            handle,
+            frame_num: current.frame_num,
+            long_term_frame_idx,
+            top: (current.structure != PictureStructure::BottomField)
+                .then(|| field(current.poc.top)),
+            bottom: (current.structure != PictureStructure::TopField)
+                .then(|| field(current.poc.bottom)),
+            needed_for_output: true,
+            non_existing: false,
+        };
+        if current.structure == PictureStructure::Frame && !current.is_reference {
+            // C.4.5.2: a non-reference frame that would come out first
+            // anyway is output without being stored.
+            let poc = frame.poc();
+            let first = self
+                .frames
+                .iter()
+                .filter(|f| f.needed_for_output)
+                .all(|f| f.poc() > poc);
+            if self.frames.len() >= self.max_frames && first {
+                self.output.push_back(frame.handle);
+                return Ok(());
+            }
+        }
+        self.make_room()?;
+        self.frames.push(frame);
+        if current.structure.is_field() {
+            self.last_field = Some(self.frames.len() - 1);
+        } else {
+            self.bump_reordered();
+        }
+        Ok(())
+    }
+
+    fn check_long_term_frame_idx(&self, idx: u32) -> Result<()> {
+        match self.max_long_term_frame_idx {
+            Some(max) if idx <= max => Ok(()),
+            _ => Err(Error::InvalidValue {
+                name: "long_term_frame_idx",
+                value: idx as i64,
+            }),
+        }
+    }
+
+    fn apply_mmco(&mut self, op: Mmco) -> Result<()> {
+        let (curr_pic_num, _) = self.curr_pic_num();
+        let short_term = |dpb: &Self, difference_of_pic_nums_minus1: u32| {
+            let pic_num = curr_pic_num as i64 - difference_of_pic_nums_minus1 as i64 - 1;
+            dpb.short_term_pics()
+                .into_iter()
+                .find(|&(_, n)| n as i64 == pic_num)
+                .map(|(pic, _)| pic)
+                .ok_or(Error::InvalidValue {
+                    name: "difference_of_pic_nums_minus1",
+                    value: difference_of_pic_nums_minus1 as i64,
+                })
+        };
+        match op {
+            Mmco::ShortTermUnused {
+                difference_of_pic_nums_minus1,
+            } => {
+                let pic = short_term(self, difference_of_pic_nums_minus1)?;
+                self.frames[pic.frame].mark(pic.structure, Reference::Unused);
+            }
+            Mmco::LongTermUnused { long_term_pic_num } => {
+                let (pic, _) = self
+                    .long_term_pics()
+                    .into_iter()
+                    .find(|&(_, n)| n as u32 == long_term_pic_num)
+                    .ok_or(Error::InvalidValue {
+                        name: "long_term_pic_num",
+                        value: long_term_pic_num as i64,
+                    })?;
+                self.frames[pic.frame].mark(pic.structure, Reference::Unused);
+            }
+            Mmco::ShortTermToLongTerm {
+                difference_of_pic_nums_minus1,
+                long_term_frame_idx,
+            } => {
+                self.check_long_term_frame_idx(long_term_frame_idx)?;
+                let pic = short_term(self, difference_of_pic_nums_minus1)?;
+                self.free_long_term_frame_idx(long_term_frame_idx, Some(pic.frame));
+                let frame = &mut self.frames[pic.frame];
+                frame.mark(pic.structure, Reference::LongTerm);
+                frame.long_term_frame_idx = long_term_frame_idx;
+            }
+            Mmco::MaxLongTermFrameIdx {
+                max_long_term_frame_idx_plus1,
+            } => {
+                for frame in &mut self.frames {
+                    if frame.has(Reference::LongTerm)
+                        && frame.long_term_frame_idx >= max_long_term_frame_idx_plus1
+                    {
+                        frame.mark(PictureStructure::Frame, Reference::Unused);
+                    }
+                }
+                self.max_long_term_frame_idx = max_long_term_frame_idx_plus1.checked_sub(1);
+            }
+            Mmco::AllUnused | Mmco::CurrentToLongTerm { .. } => unreachable!(),
+        }
+        Ok(())
+    }
+
+    /// Unmarks whatever holds `LongTermFrameIdx` `idx`, except `keep`.
+    fn free_long_term_frame_idx(&mut self, idx: u32, keep: Option<usize>) {
+        for (index, frame) in self.frames.iter_mut().enumerate() {
+            if Some(index) != keep
+                && frame.has(Reference::LongTerm)
+                && frame.long_term_frame_idx == idx
+            {
+                frame.mark(PictureStructure::Frame, Reference::Unused);
+            }
+        }
+    }
+
+    /// `memory_management_control_operation` 5.
+    fn mark_all_unused(&mut self, keep: Option<usize>) {
+        for (index, frame) in self.frames.iter_mut().enumerate() {
+            if Some(index) != keep {
+                frame.mark(PictureStructure::Frame, Reference::Unused);
+            }
+        }
+        self.max_long_term_frame_idx = None;
+    }
+
+    /// 8.2.5.3, for a picture with `frame_num`.
+    fn sliding_window(&mut self, frame_num: u32) {
+        loop {
+            let short_term = self.frames_with(Reference::ShortTerm);
+            let long_term = self
+                .frames
+                .iter()
+                .filter(|f| f.has(Reference::LongTerm) && !f.has(Reference::ShortTerm))
+                .count();
+            if short_term.is_empty() || short_term.len() + long_term < self.max_num_ref_frames {
+                return;
+            }
+            let oldest = short_term
+                .into_iter()
+                .min_by_key(|&i| self.frame_num_wrap(&self.frames[i], frame_num))
+                .unwrap();
+            self.frames[oldest].mark(PictureStructure::Frame, Reference::Unused);
+        }
+    }
+
+    /// Drops frames that are neither references nor waiting for output.
+    fn remove_unused(&mut self) {
+        self.frames
+            .retain(|frame| frame.needed_for_output || frame.is_reference());
+    }
+
+    /// C.4.5.3: outputs the waiting picture that comes first, and drops it if
+    /// it is not a reference. Returns whether there was one.
+    fn bump(&mut self) -> bool {
+        let next = (0..self.frames.len())
+            .filter(|&i| self.frames[i].needed_for_output)
+            .min_by_key(|&i| self.frames[i].poc());
+        let Some(index) = next else {
+            return false;
+        };
+        let frame = &mut self.frames[index];
+        frame.needed_for_output = false;
+        self.output.push_back(frame.handle.clone());
+        if !frame.is_reference() {
+            self.frames.remove(index);
+        }
+        true
+    }
+
+    /// Bumps until there is a free frame buffer.
+    fn make_room(&mut self) -> Result<()> {
+        while self.frames.len() >= self.max_frames {
+            if !self.bump() {
+                return Err(Error::DpbFull);
+            }
+        }
+        Ok(())
+    }
+
+    /// Outputs pictures while more are waiting than may precede another in
+    /// decoding order but follow it in display order.
+    fn bump_reordered(&mut self) {
+        while self.frames.iter().filter(|f| f.needed_for_output).count()
+            > self.max_num_reorder_frames
+        {
+            self.bump();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::h264::slice::tests::{header, idr_header, sps};
+    use crate::h264::{PocState, SliceType};
+
+    const NON_EXISTING: u32 = 99;
+
+    struct Decoder {
+        sps: Sps,
+        poc: PocState,
+        dpb: Dpb<u32>,
+    }
+
+    impl Decoder {
+        fn new(sps: Sps) -> Self {
+            Self {
+                sps,
+                poc: PocState::new(),
+                dpb: Dpb::new(),
+            }
+        }
+
+        /// Decodes a picture into `handle`, returning its reference lists.
+        fn decode(
+            &mut self,
+            header: &SliceHeader,
+            handle: u32,
+        ) -> Result<[Vec<(u32, PictureStructure)>; 2]> {
+            let poc = self.poc.compute(&self.sps, header);
+            self.dpb
+                .start_picture(&self.sps, header, poc, || NON_EXISTING)?;
+            let (list0, list1) = self.dpb.ref_pic_lists(header)?;
+            let frames = self.dpb.frames();
+            let handles = |list: Vec<RefPic>| {
+                list.iter()
+                    .map(|pic| (frames[pic.frame].handle, pic.structure))
+                    .collect()
+            };
+            let lists = [handles(list0), handles(list1)];
+            self.dpb.finish_picture(handle)?;
+            Ok(lists)
+        }
+
+        fn frame_lists(&mut self, header: &SliceHeader, handle: u32) -> [Vec<u32>; 2] {
+            self.decode(header, handle)
+                .unwrap()
+                .map(|list| list.into_iter().map(|(handle, _)| handle).collect())
+        }
+
+        fn output(&mut self) -> Vec<u32> {
+            std::iter::from_fn(|| self.dpb.pop_output()).collect()
+        }
+    }
+
+    #[test]
+    fn sliding_window_and_modification() {
+        let mut decoder = Decoder::new(sps(2, 2, true));
+        decoder.frame_lists(&idr_header(), 0);
+        assert_eq!(decoder.frame_lists(&header(SliceType::P, 1, 1), 1)[0], [0]);
+        assert_eq!(
+            decoder.frame_lists(&header(SliceType::P, 2, 1), 2)[0],
+            [1, 0]
+        );
+        // Frame 0 slid out of the two-frame window.
+        assert_eq!(
+            decoder.frame_lists(&header(SliceType::P, 3, 1), 3)[0],
+            [2, 1]
+        );
+
+        let mut p = header(SliceType::P, 4, 1);
+        p.num_ref_idx_l0_active_minus1 = 1;
+        p.ref_pic_list_modification_l0 = vec![RefPicListModification::SubtractPicNum(1)];
+        assert_eq!(decoder.frame_lists(&p, 4)[0], [2, 3]);
+        p.frame_num = 5;
+        p.ref_pic_list_modification_l0 = vec![RefPicListModification::SubtractPicNum(5)];
+        assert!(decoder.decode(&p, 5).is_err());
+
+        decoder.dpb.flush();
+        assert_eq!(decoder.output(), [0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn b_frames_in_display_order() {
+        let mut decoder = Decoder::new(sps(0, 2, true));
+        let picture = |slice_type, frame_num, nal_ref_idc, lsb| {
+            let mut header = header(slice_type, frame_num, nal_ref_idc);
+            header.pic_order_cnt_lsb = lsb;
+            header
+        };
+        decoder.frame_lists(&idr_header(), 0);
+        decoder.frame_lists(&picture(SliceType::P, 1, 1, 8), 8);
+        assert_eq!(
+            decoder.frame_lists(&picture(SliceType::B, 2, 0, 4), 4),
+            [vec![0, 8], vec![8, 0]]
+        );
+        assert_eq!(
+            decoder.frame_lists(&picture(SliceType::B, 2, 0, 2), 2),
+            [vec![0, 8], vec![8, 0]]
+        );
+        decoder.frame_lists(&picture(SliceType::P, 2, 1, 12), 12);
+        assert_eq!(
+            decoder.frame_lists(&picture(SliceType::B, 3, 0, 10), 10),
+            [vec![8, 12], vec![12, 8]]
+        );
+        // With both references before it, list 1 would equal list 0.
+        assert_eq!(
+            decoder.frame_lists(&picture(SliceType::B, 3, 0, 14), 14),
+            [vec![12, 8], vec![8, 12]]
+        );
+        decoder.dpb.flush();
+        assert_eq!(decoder.output(), [0, 2, 4, 8, 10, 12, 14]);
+    }
+
+    #[test]
+    fn reorder_limit_outputs_early() {
+        let mut sps = sps(0, 1, true);
+        sps.constraint_set3_flag = true;
+        sps.profile_idc = 100;
+        let mut decoder = Decoder::new(sps);
+        decoder.frame_lists(&idr_header(), 0);
+        assert_eq!(decoder.output(), [0]);
+        let mut p = header(SliceType::P, 1, 1);
+        p.pic_order_cnt_lsb = 2;
+        decoder.frame_lists(&p, 2);
+        assert_eq!(decoder.output(), [2]);
+    }
+
+    #[test]
+    fn long_term_references() {
+        let mut decoder = Decoder::new(sps(2, 3, true));
+        decoder.frame_lists(&idr_header(), 0);
+        let mut p = header(SliceType::P, 1, 1);
+        p.dec_ref_pic_marking = Some(DecRefPicMarking {
+            adaptive: Some(vec![
+                Mmco::MaxLongTermFrameIdx {
+                    max_long_term_frame_idx_plus1: 2,
+                },
+                Mmco::ShortTermToLongTerm {
+                    difference_of_pic_nums_minus1: 0,
+                    long_term_frame_idx: 1,
+                },
+                Mmco::CurrentToLongTerm {
+                    long_term_frame_idx: 0,
+                },
+            ]),
+            ..Default::default()
+        });
+        decoder.frame_lists(&p, 1);
+        let [list0, _] = decoder.frame_lists(&header(SliceType::P, 2, 1), 2);
+        assert_eq!(list0, [1, 0]);
+        // Long-term pictures come after short-term ones.
+        let [list0, _] = decoder.frame_lists(&header(SliceType::P, 3, 1), 3);
+        assert_eq!(list0, [2, 1, 0]);
+        let frame = |handle| {
+            decoder
+                .dpb
+                .frames()
+                .iter()
+                .find(|f| f.handle == handle)
+                .map(|f| (f.is_frame(Reference::LongTerm), f.long_term_frame_idx))
+        };
+        assert_eq!(frame(0), Some((true, 1)));
+        assert_eq!(frame(1), Some((true, 0)));
+
+        // Reusing an index drops the frame holding it.
+        let mut p = header(SliceType::P, 4, 1);
+        p.dec_ref_pic_marking = Some(DecRefPicMarking {
+            adaptive: Some(vec![Mmco::CurrentToLongTerm {
+                long_term_frame_idx: 1,
+            }]),
+            ..Default::default()
+        });
+        decoder.frame_lists(&p, 4);
+        let [list0, _] = decoder.frame_lists(&header(SliceType::P, 5, 1), 5);
+        assert_eq!(list0, [3, 1, 4]);
+
+        let mut p = header(SliceType::P, 6, 1);
+        p.dec_ref_pic_marking = Some(DecRefPicMarking {
+            adaptive: Some(vec![Mmco::CurrentToLongTerm {
+                long_term_frame_idx: 2,
+            }]),
+            ..Default::default()
+        });
+        assert_eq!(
+            decoder.decode(&p, 6).err(),
+            Some(Error::InvalidValue {
+                name: "long_term_frame_idx",
+                value: 2
+            })
+        );
+    }
+
+    #[test]
+    fn fills_frame_num_gaps() {
+        let mut decoder = Decoder::new(sps(2, 2, true));
+        decoder.frame_lists(&idr_header(), 0);
+        let [list0, _] = decoder.frame_lists(&header(SliceType::P, 3, 1), 3);
+        assert_eq!(list0, [NON_EXISTING, NON_EXISTING]);
+        let frame_nums: Vec<_> = decoder.dpb.frames().iter().map(|f| f.frame_num).collect();
+        assert_eq!(frame_nums, [0, 2, 3]);
+        decoder.dpb.flush();
+        assert_eq!(decoder.output(), [0, 3]);
+    }
+
+    #[test]
+    fn pairs_fields() {
+        let mut decoder = Decoder::new(sps(0, 2, false));
+        let field = |slice_type, frame_num, bottom, lsb| {
+            let mut header = header(slice_type, frame_num, 1);
+            header.field_pic_flag = true;
+            header.bottom_field_flag = bottom;
+            header.pic_order_cnt_lsb = lsb;
+            header
+        };
+        use PictureStructure::{BottomField as Bottom, TopField as Top};
+
+        let mut idr = field(SliceType::I, 0, false, 0);
+        idr.idr_pic_flag = true;
+        decoder.decode(&idr, 0).unwrap();
+        let [list0, _] = decoder.decode(&field(SliceType::P, 0, true, 1), 0).unwrap();
+        assert_eq!(list0, [(0, Top)]);
+        assert_eq!(decoder.dpb.frames().len(), 1);
+
+        let [list0, _] = decoder
+            .decode(&field(SliceType::P, 1, false, 4), 1)
+            .unwrap();
+        assert_eq!(list0, [(0, Top), (0, Bottom)]);
+        let [list0, _] = decoder.decode(&field(SliceType::P, 1, true, 5), 1).unwrap();
+        assert_eq!(list0, [(0, Bottom), (1, Top), (0, Top)]);
+        let [list0, list1] = decoder
+            .decode(&field(SliceType::B, 2, false, 2), 2)
+            .unwrap();
+        assert_eq!(list0, [(0, Top), (0, Bottom), (1, Top), (1, Bottom)]);
+        assert_eq!(list1, [(1, Top), (1, Bottom), (0, Top), (0, Bottom)]);
+
+        decoder.dpb.flush();
+        assert_eq!(decoder.output(), [0, 2, 1]);
+    }
+}
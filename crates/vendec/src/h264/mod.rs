@@ -0,0 +1,218 @@
+//! H.264 (ITU-T H.264 / ISO/IEC 14496-10) bitstream parsing.
+//!
+//! Syntax structures keep the names of the specification's syntax elements,
+//! so that they can be cross-checked against it and copied into the VA
+//! buffers field by field.
+
+mod dpb;
+mod parameter_sets;
+mod poc;
+mod pps;
+mod slice;
+mod sps;
+
+pub use dpb::*;
+pub use parameter_sets::*;
+pub use poc::*;
+pub use pps::*;
+pub use slice::*;
+pub use sps::*;
+
+use crate::bits::{self, BitReader};
+pub(crate) use crate::bits::{check, ReadExt};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    Bits(bits::Error),
+    /// A syntax element has a value the specification does not allow.
+    InvalidValue {
+        name: &'static str,
+        value: i64,
+    },
+    /// The stream uses a feature this parser does not handle.
+    Unsupported(&'static str),
+    MissingSps(u32),
+    MissingPps(u32),
+    /// A picture has to be stored but every frame buffer holds a reference.
+    DpbFull,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::Bits(err) => write!(f, "{}", err),
+            Error::InvalidValue { name, value } => write!(f, "invalid {}: {}", name, value),
+            Error::Unsupported(what) => write!(f, "unsupported: {}", what),
+            Error::MissingSps(id) => write!(f, "SPS {} has not been received", id),
+            Error::MissingPps(id) => write!(f, "PPS {} has not been received", id),
+            Error::DpbFull => write!(f, "decoded picture buffer overflow"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Bits(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<bits::Error> for Error {
+    fn from(err: bits::Error) -> Self {
+        match err {
+            bits::Error::InvalidValue { name, value } => Error::InvalidValue { name, value },
+            err => Error::Bits(err),
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NalUnitType {
+    Slice,
+    SliceDataA,
+    SliceDataB,
+    SliceDataC,
+    IdrSlice,
+    Sei,
+    Sps,
+    Pps,
+    AccessUnitDelimiter,
+    EndOfSequence,
+    EndOfStream,
+    FillerData,
+    SpsExtension,
+    PrefixNal,
+    SubsetSps,
+    DepthParameterSet,
+    AuxiliarySlice,
+    SliceExtension,
+    SliceExtensionDepth,
+    /// Reserved or unspecified.
+    Other(u8),
+}
+
+impl From<u8> for NalUnitType {
+    fn from(value: u8) -> Self {
+        match value {
+            1 => NalUnitType::Slice,
+            2 => NalUnitType::SliceDataA,
+            3 => NalUnitType::SliceDataB,
+            4 => NalUnitType::SliceDataC,
+            5 => NalUnitType::IdrSlice,
+            6 => NalUnitType::Sei,
+            7 => NalUnitType::Sps,
+            8 => NalUnitType::Pps,
+            9 => NalUnitType::AccessUnitDelimiter,
+            10 => NalUnitType::EndOfSequence,
+            11 => NalUnitType::EndOfStream,
+            12 => NalUnitType::FillerData,
+            13 => NalUnitType::SpsExtension,
+            14 => NalUnitType::PrefixNal,
+            15 => NalUnitType::SubsetSps,
+            16 => NalUnitType::DepthParameterSet,
+            19 => NalUnitType::AuxiliarySlice,
+            20 => NalUnitType::SliceExtension,
+            21 => NalUnitType::SliceExtensionDepth,
+            other => NalUnitType::Other(other),
+        }
+    }
+}
+
+/// `nal_unit_header_mvc_extension()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MvcExtension {
+    pub non_idr_flag: bool,
+    pub priority_id: u8,
+    pub view_id: u16,
+    pub temporal_id: u8,
+    pub anchor_pic_flag: bool,
+    pub inter_view_flag: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NalHeader {
+    pub nal_ref_idc: u8,
+    pub nal_unit_type: NalUnitType,
+    /// Present on prefix and coded slice extension NAL units of MVC streams.
+    pub mvc_extension: Option<MvcExtension>,
+}
+
+impl NalHeader {
+    pub fn parse(nal: &[u8]) -> Result<Self> {
+        let mut r = BitReader::new(nal);
+        if r.read_bit()? {
+            return Err(Error::InvalidValue {
+                name: "forbidden_zero_bit",
+                value: 1,
+            });
+        }
+        let nal_ref_idc = r.read_bits(2)? as u8;
+        let nal_unit_type = NalUnitType::from(r.read_bits(5)? as u8);
+        let mvc_extension = match nal_unit_type {
+            NalUnitType::PrefixNal | NalUnitType::SliceExtension => {
+                if r.read_bit()? {
+                    return Err(Error::Unsupported("SVC NAL units"));
+                }
+                let extension = MvcExtension {
+                    non_idr_flag: r.read_bit()?,
+                    priority_id: r.read_bits(6)? as u8,
+                    view_id: r.read_bits(10)? as u16,
+                    temporal_id: r.read_bits(3)? as u8,
+                    anchor_pic_flag: r.read_bit()?,
+                    inter_view_flag: r.read_bit()?,
+                };
+                r.skip_bits(1)?;
+                Some(extension)
+            }
+            NalUnitType::SliceExtensionDepth => {
+                return Err(Error::Unsupported("3D-AVC NAL units"));
+            }
+            _ => None,
+        };
+        Ok(Self {
+            nal_ref_idc,
+            nal_unit_type,
+            mvc_extension,
+        })
+    }
+
+    /// Number of bytes the header occupies at the start of the NAL unit.
+    pub fn header_size(&self) -> usize {
+        if self.mvc_extension.is_some() {
+            4
+        } else {
+            1
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nal_header() {
+        let header = NalHeader::parse(&[0x67]).unwrap();
+        assert_eq!(header.nal_ref_idc, 3);
+        assert_eq!(header.nal_unit_type, NalUnitType::Sps);
+        assert_eq!(header.header_size(), 1);
+
+        let header = NalHeader::parse(&[0x14, 0x40, 0x00, 0x47]).unwrap();
+        assert_eq!(header.nal_unit_type, NalUnitType::SliceExtension);
+        let mvc = header.mvc_extension.unwrap();
+        assert!(mvc.non_idr_flag);
+        assert_eq!(mvc.view_id, 1);
+        assert!(mvc.anchor_pic_flag && mvc.inter_view_flag);
+        assert_eq!(header.header_size(), 4);
+
+        assert!(NalHeader::parse(&[0x80]).is_err());
+        assert_eq!(
+            NalHeader::parse(&[0x14, 0x40]),
+            Err(Error::Bits(bits::Error::OutOfData))
+        );
+    }
+}
@@ -0,0 +1,453 @@
+use std::sync::Arc;
+
+use super::{Error, NalHeader, NalUnitType, Pps, ReadExt, Result, Sps, SubsetSps};
+use crate::bits::BitReader;
+use crate::nal::to_rbsp;
+
+#[derive(Debug)]
+struct PpsEntry {
+    rbsp: Vec<u8>,
+    /// The PPS as parsed against the SPS it was parsed with. A PPS may
+    /// arrive before its SPS, and its SPS may be replaced afterwards, so
+    /// this is redone on activation when the SPS differs.
+    parsed: Option<(Arc<Pps>, Arc<Sps>)>,
+}
+
+/// The parameter sets received so far, by ID, and the ones active for the
+/// current picture.
+///
+/// A parameter set replaces any earlier one with the same ID. Sets are
+/// handed out as [`Arc`]s so that pictures in flight keep theirs when a
+/// new set arrives.
+#[derive(Debug)]
+pub struct ParameterSets {
+    sps: Vec<Option<Arc<Sps>>>,
+    subset_sps: Vec<Option<Arc<SubsetSps>>>,
+    pps: Vec<Option<PpsEntry>>,
+    active: Option<(Arc<Pps>, Arc<Sps>)>,
+}
+
+impl Default for ParameterSets {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ParameterSets {
+    pub fn new() -> Self {
+        Self {
+            sps: vec![None; 32],
+            subset_sps: vec![None; 32],
+            pps: (0..256).map(|_| None).collect(),
+            active: None,
+        }
+    }
+
+    /// Stores the parameter set in `nal`, a NAL unit with its header and
+    /// emulation prevention bytes. Other NAL units are ignored.
+    pub fn add_nal(&mut self, nal: &[u8]) -> Result<()> {
+        let header = NalHeader::parse(nal)?;
+        let rbsp = || to_rbsp(&nal[header.header_size()..]);
+        match header.nal_unit_type {
+            NalUnitType::Sps => self.add_sps(&rbsp()).map(drop),
+            NalUnitType::SubsetSps => self.add_subset_sps(&rbsp()).map(drop),
+            NalUnitType::Pps => self.add_pps(rbsp()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Parses and stores a `seq_parameter_set_rbsp()`.
+    pub fn add_sps(&mut self, rbsp: &[u8]) -> Result<Arc<Sps>> {
+        let sps = Arc::new(Sps::parse(rbsp)?);
+        self.sps[sps.seq_parameter_set_id as usize] = Some(sps.clone());
+        Ok(sps)
+    }
+
+    /// Parses and stores a `subset_seq_parameter_set_rbsp()`.
+    pub fn add_subset_sps(&mut self, rbsp: &[u8]) -> Result<Arc<SubsetSps>> {
+        let subset_sps = Arc::new(SubsetSps::parse(rbsp)?);
+        self.subset_sps[subset_sps.sps.seq_parameter_set_id as usize] = Some(subset_sps.clone());
+        Ok(subset_sps)
+    }
+
+    /// Stores a `pic_parameter_set_rbsp()`. It is parsed right away if its
+    /// SPS is known, and on activation otherwise.
+    pub fn add_pps(&mut self, rbsp: Vec<u8>) -> Result<()> {
+        let mut r = BitReader::new(&rbsp);
+        let id = r.read_ue_max("pic_parameter_set_id", 255)? as usize;
+        let mut entry = PpsEntry { rbsp, parsed: None };
+        match self.parse_pps(&entry) {
+            Ok(parsed) => entry.parsed = Some(parsed),
+            Err(Error::MissingSps(_)) => {}
+            Err(err) => return Err(err),
+        }
+        self.pps[id] = Some(entry);
+        Ok(())
+    }
+
+    fn parse_pps(&self, entry: &PpsEntry) -> Result<(Arc<Pps>, Arc<Sps>)> {
+        let mut sps = None;
+        let pps = Pps::parse(&entry.rbsp, |id| {
+            sps = self.sps_for_pps(id);
+            sps.as_deref()
+        })?;
+        // `Pps::parse` only succeeds once the lookup has found the SPS.
+        Ok((Arc::new(pps), sps.unwrap()))
+    }
+
+    /// The SPS a PPS with `seq_parameter_set_id` refers to. PPSs used only
+    /// by non-base MVC views refer to a subset SPS instead.
+    fn sps_for_pps(&self, id: u32) -> Option<Arc<Sps>> {
+        let id = id as usize;
+        self.sps.get(id)?.clone().or_else(|| {
+            self.subset_sps[id]
+                .as_ref()
+                .map(|subset| Arc::new(subset.sps.clone()))
+        })
+    }
+
+    pub fn sps(&self, id: u32) -> Option<&Arc<Sps>> {
+        self.sps.get(id as usize)?.as_ref()
+    }
+
+    pub fn subset_sps(&self, id: u32) -> Option<&Arc<SubsetSps>> {
+        self.subset_sps.get(id as usize)?.as_ref()
+    }
+
+    /// Returns the PPS with `id` if it has been received and its SPS is
+    /// known.
+    pub fn pps(&self, id: u32) -> Option<&Arc<Pps>> {
+        let entry = self.pps.get(id as usize)?.as_ref()?;
+        entry.parsed.as_ref().map(|(pps, _)| pps)
+    }
+
+    /// Makes the PPS with `id`, as named by a slice header, and its SPS the
+    /// active parameter sets.
+    pub fn activate_pps(&mut self, id: u32) -> Result<(Arc<Pps>, Arc<Sps>)> {
+        let entry = self
+            .pps
+            .get(id as usize)
+            .and_then(Option::as_ref)
+            .ok_or(Error::MissingPps(id))?;
+        let current = match &entry.parsed {
+            Some((pps, sps)) => self
+                .sps_for_pps(pps.seq_parameter_set_id as u32)
+                .filter(|latest| Arc::ptr_eq(latest, sps) || **latest == **sps)
+                .map(|_| (pps.clone(), sps.clone())),
+            None => None,
+        };
+        let active = match current {
+            Some(active) => active,
+            None => {
+                let parsed = self.parse_pps(entry)?;
+                self.pps[id as usize].as_mut().unwrap().parsed = Some(parsed.clone());
+                parsed
+            }
+        };
+        self.active = Some(active.clone());
+        Ok(active)
+    }
+
+    pub fn active_pps(&self) -> Option<&Arc<Pps>> {
+        self.active.as_ref().map(|(pps, _)| pps)
+    }
+
+    pub fn active_sps(&self) -> Option<&Arc<Sps>> {
+        self.active.as_ref().map(|(_, sps)| sps)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bits::tests::random_chunks;
+    use crate::bits::BitWriter;
+    use crate::h264::{
+        ScalingLists, SliceGroupMap, DEFAULT_4X4_INTER, DEFAULT_4X4_INTRA, DEFAULT_8X8_INTRA,
+    };
+
+    /// A 1280x720 High profile SPS with cropping, VUI and HRD.
+    fn sps_rbsp(id: u32, scaling_matrix: bool) -> Vec<u8> {
+        let mut w = BitWriter::new();
+        write_sps_data(&mut w, 100, id, scaling_matrix);
+        w.write_trailing_bits();
+        w.into_bytes()
+    }
+
+    fn write_sps_data(w: &mut BitWriter, profile_idc: u64, id: u32, scaling_matrix: bool) {
+        w.write_bits(profile_idc, 8);
+        w.write_bits(0, 8);
+        w.write_bits(31, 8); // level_idc
+        w.write_ue(id);
+        w.write_ue(1); // chroma_format_idc
+        w.write_ue(0); // bit_depth_luma_minus8
+        w.write_ue(0);
+        w.write_bit(false);
+        w.write_bit(scaling_matrix);
+        if scaling_matrix {
+            // Only the first 4x4 list, using the default matrix.
+            w.write_bit(true);
+            w.write_se(-8);
+            for _ in 1..8 {
+                w.write_bit(false);
+            }
+        }
+        w.write_ue(0); // log2_max_frame_num_minus4
+        w.write_ue(0); // pic_order_cnt_type
+        w.write_ue(2);
+        w.write_ue(4); // max_num_ref_frames
+        w.write_bit(false);
+        w.write_ue(79); // pic_width_in_mbs_minus1
+        w.write_ue(44);
+        w.write_bit(true); // frame_mbs_only_flag
+        w.write_bit(true);
+        w.write_bit(true); // frame_cropping_flag
+        w.write_ue(0);
+        w.write_ue(0);
+        w.write_ue(0);
+        w.write_ue(4);
+        w.write_bit(true); // vui_parameters_present_flag
+        w.write_bit(true); // aspect_ratio_info_present_flag
+        w.write_bits(1, 8);
+        w.write_bit(false);
+        w.write_bit(true); // video_signal_type_present_flag
+        w.write_bits(5, 3);
+        w.write_bit(true);
+        w.write_bit(true);
+        w.write_bits(1, 8);
+        w.write_bits(1, 8);
+        w.write_bits(1, 8);
+        w.write_bit(false);
+        w.write_bit(true); // timing_info_present_flag
+        w.write_bits(1001, 32);
+        w.write_bits(60000, 32);
+        w.write_bit(true);
+        w.write_bit(true); // nal_hrd_parameters_present_flag
+        w.write_ue(0);
+        w.write_bits(4, 4);
+        w.write_bits(6, 4);
+        w.write_ue(12499);
+        w.write_ue(62499);
+        w.write_bit(false);
+        w.write_bits(23, 5);
+        w.write_bits(23, 5);
+        w.write_bits(23, 5);
+        w.write_bits(24, 5);
+        w.write_bit(false);
+        w.write_bit(false); // low_delay_hrd_flag
+        w.write_bit(true);
+        w.write_bit(true); // bitstream_restriction_flag
+        w.write_bit(true);
+        w.write_ue(2);
+        w.write_ue(1);
+        w.write_ue(16);
+        w.write_ue(16);
+        w.write_ue(2); // max_num_reorder_frames
+        w.write_ue(4);
+    }
+
+    fn pps_rbsp(id: u32, sps_id: u32, extended: bool) -> Vec<u8> {
+        let mut w = BitWriter::new();
+        w.write_ue(id);
+        w.write_ue(sps_id);
+        w.write_bit(true); // entropy_coding_mode_flag
+        w.write_bit(false);
+        w.write_ue(1); // num_slice_groups_minus1
+        w.write_ue(2); // slice_group_map_type
+        w.write_ue(81);
+        w.write_ue(3442);
+        w.write_ue(2);
+        w.write_ue(0);
+        w.write_bit(true);
+        w.write_bits(2, 2); // weighted_bipred_idc
+        w.write_se(-3);
+        w.write_se(0);
+        w.write_se(-2); // chroma_qp_index_offset
+        w.write_bit(true);
+        w.write_bit(false);
+        w.write_bit(false);
+        if extended {
+            w.write_bit(true); // transform_8x8_mode_flag
+            w.write_bit(true);
+            for i in 0..8 {
+                w.write_bit(i == 6);
+                if i == 6 {
+                    w.write_se(-8);
+                }
+            }
+            w.write_se(3);
+        }
+        w.write_trailing_bits();
+        w.into_bytes()
+    }
+
+    #[test]
+    fn parses_sps() {
+        let sps = Sps::parse(&sps_rbsp(3, false)).unwrap();
+        assert_eq!(sps.seq_parameter_set_id, 3);
+        assert_eq!((sps.width(), sps.height()), (1280, 720));
+        let rect = sps.visible_rect().unwrap();
+        assert_eq!((rect.width, rect.height), (1280, 712));
+        assert_eq!(sps.max_frame_num(), 16);
+        assert_eq!(sps.max_pic_order_cnt_lsb(), 64);
+        assert_eq!(sps.scaling_lists, ScalingLists::default());
+        let vui = sps.vui.as_ref().unwrap();
+        assert_eq!((vui.num_units_in_tick, vui.time_scale), (1001, 60000));
+        assert_eq!(
+            vui.nal_hrd.as_ref().unwrap().cpbs[0].bit_rate_value_minus1,
+            12499
+        );
+        assert_eq!(vui.max_num_reorder_frames, 2);
+        assert_eq!(sps.max_dpb_frames(), 4);
+        assert_eq!(sps.level_max_dpb_frames(), 5);
+    }
+
+    #[test]
+    fn scaling_list_fall_back() {
+        let sps = Sps::parse(&sps_rbsp(0, true)).unwrap();
+        let lists = &sps.scaling_lists;
+        assert_eq!(lists.list_4x4[0], DEFAULT_4X4_INTRA);
+        // Rule A: missing lists copy the previous one or use the default.
+        assert_eq!(lists.list_4x4[2], lists.list_4x4[0]);
+        assert_eq!(lists.list_4x4[3], DEFAULT_4X4_INTER);
+        assert_eq!(lists.list_8x8[0], DEFAULT_8X8_INTRA);
+
+        let pps = Pps::parse(&pps_rbsp(0, 0, true), |_| Some(&sps)).unwrap();
+        assert!(pps.transform_8x8_mode_flag);
+        assert_eq!(pps.second_chroma_qp_index_offset, 3);
+        // Rule B: missing lists fall back to the SPS ones.
+        assert_eq!(pps.scaling_lists.list_4x4[0], sps.scaling_lists.list_4x4[0]);
+        assert_eq!(pps.scaling_lists.list_8x8[0], DEFAULT_8X8_INTRA);
+    }
+
+    #[test]
+    fn parses_pps() {
+        let sps = Sps::parse(&sps_rbsp(0, false)).unwrap();
+        let pps = Pps::parse(&pps_rbsp(7, 0, false), |_| Some(&sps)).unwrap();
+        assert_eq!(pps.pic_parameter_set_id, 7);
+        assert_eq!(
+            pps.slice_group_map,
+            Some(SliceGroupMap::Foreground {
+                top_left: vec![81],
+                bottom_right: vec![3442],
+            })
+        );
+        assert_eq!(pps.num_ref_idx_l0_default_active_minus1, 2);
+        assert_eq!(pps.weighted_bipred_idc, 2);
+        assert_eq!(pps.pic_init_qp_minus26, -3);
+        assert_eq!(pps.second_chroma_qp_index_offset, -2);
+        assert!(!pps.transform_8x8_mode_flag);
+        assert_eq!(
+            Pps::parse(&pps_rbsp(7, 0, false), |_| None),
+            Err(Error::MissingSps(0))
+        );
+    }
+
+    #[test]
+    fn tracks_active_sets() {
+        let mut sets = ParameterSets::new();
+        assert_eq!(sets.activate_pps(1), Err(Error::MissingPps(1)));
+        // A PPS may come before its SPS.
+        sets.add_pps(pps_rbsp(1, 2, false)).unwrap();
+        assert!(sets.pps(1).is_none());
+        assert_eq!(sets.activate_pps(1), Err(Error::MissingSps(2)));
+        let mut nal = vec![0x67];
+        nal.extend(sps_rbsp(2, false));
+        sets.add_nal(&nal).unwrap();
+        let (pps, sps) = sets.activate_pps(1).unwrap();
+        assert_eq!(pps.seq_parameter_set_id, 2);
+        assert!(Arc::ptr_eq(sets.active_sps().unwrap(), &sps));
+
+        // A new SPS with the same ID is picked up on the next activation.
+        let mut rbsp = sps_rbsp(2, false);
+        rbsp[2] = 40;
+        sets.add_sps(&rbsp).unwrap();
+        assert_eq!(sps.level_idc, 31);
+        let (_, sps) = sets.activate_pps(1).unwrap();
+        assert_eq!(sps.level_idc, 40);
+    }
+
+    #[test]
+    fn parses_mvc_subset_sps() {
+        let mut w = BitWriter::new();
+        write_sps_data(&mut w, 128, 1, false);
+        w.write_bit(true); // bit_equal_to_one
+        w.write_ue(1); // num_views_minus1
+        w.write_ue(0);
+        w.write_ue(1);
+        w.write_ue(1); // num_anchor_refs_l0[1]
+        w.write_ue(0);
+        w.write_ue(0);
+        w.write_ue(1); // num_non_anchor_refs_l0[1]
+        w.write_ue(0);
+        w.write_ue(0);
+        w.write_ue(0); // num_level_values_signalled_minus1
+        w.write_bits(40, 8);
+        w.write_ue(0);
+        w.write_bits(0, 3);
+        w.write_ue(1); // applicable_op_num_target_views_minus1
+        w.write_ue(0);
+        w.write_ue(1);
+        w.write_ue(1);
+        w.write_bit(false); // mvc_vui_parameters_present_flag
+        w.write_trailing_bits();
+        let rbsp = w.into_bytes();
+
+        let mut sets = ParameterSets::new();
+        let subset = sets.add_subset_sps(&rbsp).unwrap();
+        assert_eq!(subset.sps.profile_idc, 128);
+        assert_eq!(subset.mvc.view_ids, [0, 1]);
+        assert_eq!(subset.mvc.anchor_refs_l0, [vec![], vec![0]]);
+        assert_eq!(subset.mvc.non_anchor_refs_l1, [vec![], vec![]]);
+        let op = &subset.mvc.level_values[0].applicable_ops[0];
+        assert_eq!(op.target_view_ids, [0, 1]);
+        assert!(subset.mvc_vui.is_none());
+        // PPSs of the non-base view refer to the subset SPS.
+        sets.add_pps(pps_rbsp(0, 1, false)).unwrap();
+        assert_eq!(sets.activate_pps(0).unwrap().1.profile_idc, 128);
+
+        let mut w = BitWriter::new();
+        write_sps_data(&mut w, 83, 0, false);
+        w.write_trailing_bits();
+        assert_eq!(
+            SubsetSps::parse(&w.into_bytes()),
+            Err(Error::Unsupported("SVC subset SPS"))
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_sets() {
+        let rbsp = sps_rbsp(0, true);
+        for len in 0..rbsp.len() - 1 {
+            assert!(Sps::parse(&rbsp[..len]).is_err(), "length {}", len);
+        }
+        let sps = Sps::parse(&rbsp).unwrap();
+        let rbsp = pps_rbsp(0, 0, true);
+        for len in 0..rbsp.len() - 1 {
+            assert!(Pps::parse(&rbsp[..len], |_| Some(&sps)).is_err());
+        }
+
+        let mut w = BitWriter::new();
+        w.write_bits(66, 8);
+        w.write_bits(0, 8);
+        w.write_bits(30, 8);
+        w.write_ue(32); // seq_parameter_set_id
+        w.write_trailing_bits();
+        assert_eq!(
+            Sps::parse(&w.into_bytes()),
+            Err(Error::InvalidValue {
+                name: "seq_parameter_set_id",
+                value: 32
+            })
+        );
+
+        // Arbitrary data must never panic.
+        let mut sets = ParameterSets::new();
+        for data in random_chunks(0x1234_5678, 64, 2000) {
+            let _ = Sps::parse(&data);
+            let _ = SubsetSps::parse(&data);
+            let _ = Pps::parse(&data, |_| Some(&sps));
+            let _ = sets.add_pps(data);
+        }
+    }
+}
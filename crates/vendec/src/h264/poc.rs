@@ -0,0 +1,214 @@
+use super::{PictureStructure, SliceHeader, Sps};
+
+/// `TopFieldOrderCnt` and `BottomFieldOrderCnt` of a picture. For a field,
+/// only the one of its parity is meaningful, and both hold it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PicOrderCnt {
+    pub top: i32,
+    pub bottom: i32,
+}
+
+impl PicOrderCnt {
+    /// `PicOrderCnt()` of a picture with this structure.
+    pub fn get(self, structure: PictureStructure) -> i32 {
+        match structure {
+            PictureStructure::Frame => self.top.min(self.bottom),
+            PictureStructure::TopField => self.top,
+            PictureStructure::BottomField => self.bottom,
+        }
+    }
+}
+
+/// Picture order count decoding (8.2.1) for all three
+/// `pic_order_cnt_type`s. Holds what the next picture needs to know about
+/// the previous ones.
+#[derive(Debug, Clone, Default)]
+pub struct PocState {
+    prev_pic_order_cnt_msb: i32,
+    prev_pic_order_cnt_lsb: i32,
+    prev_frame_num_offset: i32,
+    prev_frame_num: u32,
+}
+
+impl PocState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the order counts of the picture `header` starts, and records
+    /// what the following pictures need from it, including the effect of
+    /// `memory_management_control_operation` 5.
+    pub fn compute(&mut self, sps: &Sps, header: &SliceHeader) -> PicOrderCnt {
+        let structure = header.structure();
+        let max_frame_num = sps.max_frame_num() as i32;
+        let frame_num = header.frame_num as i32;
+        let is_reference = header.nal_ref_idc != 0;
+
+        let frame_num_offset = if header.idr_pic_flag {
+            0
+        } else if self.prev_frame_num > header.frame_num {
+            self.prev_frame_num_offset.wrapping_add(max_frame_num)
+        } else {
+            self.prev_frame_num_offset
+        };
+
+        let mut poc = PicOrderCnt::default();
+        match sps.pic_order_cnt_type {
+            0 => {
+                if header.idr_pic_flag {
+                    self.prev_pic_order_cnt_msb = 0;
+                    self.prev_pic_order_cnt_lsb = 0;
+                }
+                let max_lsb = sps.max_pic_order_cnt_lsb() as i32;
+                let lsb = header.pic_order_cnt_lsb as i32;
+                let prev_lsb = self.prev_pic_order_cnt_lsb;
+                let msb = if lsb < prev_lsb && prev_lsb - lsb >= max_lsb / 2 {
+                    self.prev_pic_order_cnt_msb.wrapping_add(max_lsb)
+                } else if lsb > prev_lsb && lsb - prev_lsb > max_lsb / 2 {
+                    self.prev_pic_order_cnt_msb.wrapping_sub(max_lsb)
+                } else {
+                    self.prev_pic_order_cnt_msb
+                };
+                poc.top = msb.wrapping_add(lsb);
+                poc.bottom = match structure {
+                    PictureStructure::Frame => {
+                        poc.top.wrapping_add(header.delta_pic_order_cnt_bottom)
+                    }
+                    _ => poc.top,
+                };
+                if is_reference {
+                    self.prev_pic_order_cnt_msb = msb;
+                    self.prev_pic_order_cnt_lsb = lsb;
+                }
+            }
+            1 => {
+                let cycle = &sps.offset_for_ref_frame;
+                let mut abs_frame_num = if cycle.is_empty() {
+                    0
+                } else {
+                    frame_num_offset.wrapping_add(frame_num)
+                };
+                if !is_reference && abs_frame_num > 0 {
+                    abs_frame_num -= 1;
+                }
+                let mut expected = 0i32;
+                if abs_frame_num > 0 {
+                    let cycle_len = cycle.len() as i32;
+                    let cycle_count = (abs_frame_num - 1) / cycle_len;
+                    let frame_num_in_cycle = (abs_frame_num - 1) % cycle_len;
+                    let delta_per_cycle = cycle.iter().fold(0i32, |sum, &o| sum.wrapping_add(o));
+                    expected = cycle_count.wrapping_mul(delta_per_cycle);
+                    for &offset in &cycle[..=frame_num_in_cycle as usize] {
+                        expected = expected.wrapping_add(offset);
+                    }
+                }
+                if !is_reference {
+                    expected = expected.wrapping_add(sps.offset_for_non_ref_pic);
+                }
+                let [delta0, delta1] = header.delta_pic_order_cnt;
+                let bottom_offset = sps.offset_for_top_to_bottom_field;
+                match structure {
+                    PictureStructure::Frame => {
+                        poc.top = expected.wrapping_add(delta0);
+                        poc.bottom = poc.top.wrapping_add(bottom_offset).wrapping_add(delta1);
+                    }
+                    PictureStructure::TopField => {
+                        poc.top = expected.wrapping_add(delta0);
+                        poc.bottom = poc.top;
+                    }
+                    PictureStructure::BottomField => {
+                        poc.bottom = expected.wrapping_add(bottom_offset).wrapping_add(delta0);
+                        poc.top = poc.bottom;
+                    }
+                }
+            }
+            _ => {
+                let temp = if header.idr_pic_flag {
+                    0
+                } else {
+                    let doubled = frame_num_offset.wrapping_add(frame_num).wrapping_mul(2);
+                    doubled.wrapping_sub(!is_reference as i32)
+                };
+                poc.top = temp;
+                poc.bottom = temp;
+            }
+        }
+
+        self.prev_frame_num_offset = frame_num_offset;
+        self.prev_frame_num = header.frame_num;
+        if header.has_mmco5() {
+            // The picture is treated as having frame_num 0 and its order
+            // counts are rebased to 0 once it has been decoded.
+            self.prev_frame_num_offset = 0;
+            self.prev_frame_num = 0;
+            self.prev_pic_order_cnt_msb = 0;
+            self.prev_pic_order_cnt_lsb = match structure {
+                PictureStructure::Frame => poc.top.wrapping_sub(poc.get(structure)),
+                PictureStructure::TopField | PictureStructure::BottomField => 0,
+            };
+        }
+        poc
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::h264::slice::tests::{header, idr_header, sps};
+    use crate::h264::{DecRefPicMarking, Mmco, SliceType};
+
+    #[test]
+    fn type_0_wraps_lsb() {
+        let sps = sps(0, 2, true);
+        let mut state = PocState::new();
+        let mut decode = |lsb: u32, delta_bottom: i32| {
+            let mut header = header(SliceType::P, 1, 1);
+            header.pic_order_cnt_lsb = lsb;
+            header.delta_pic_order_cnt_bottom = delta_bottom;
+            state.compute(&sps, &header)
+        };
+        assert_eq!(decode(6, 1), PicOrderCnt { top: 6, bottom: 7 });
+        assert_eq!(decode(12, 0).top, 12);
+        // The LSB wrapped around MaxPicOrderCntLsb, 16, and back.
+        assert_eq!(decode(2, 0).top, 18);
+        assert_eq!(decode(12, 0).top, 12);
+    }
+
+    #[test]
+    fn type_2_follows_frame_num() {
+        let sps = sps(2, 2, true);
+        let mut state = PocState::new();
+        assert_eq!(state.compute(&sps, &idr_header()).top, 0);
+        assert_eq!(state.compute(&sps, &header(SliceType::P, 1, 1)).top, 2);
+        assert_eq!(state.compute(&sps, &header(SliceType::P, 2, 0)).top, 3);
+        // frame_num wrapped around MaxFrameNum, 16.
+        assert_eq!(state.compute(&sps, &header(SliceType::P, 0, 1)).top, 32);
+
+        let mut mmco5 = header(SliceType::P, 1, 1);
+        mmco5.dec_ref_pic_marking = Some(DecRefPicMarking {
+            adaptive: Some(vec![Mmco::AllUnused]),
+            ..Default::default()
+        });
+        assert_eq!(state.compute(&sps, &mmco5).top, 34);
+        // The picture after it counts from frame_num 0 again.
+        assert_eq!(state.compute(&sps, &header(SliceType::P, 1, 1)).top, 2);
+    }
+
+    #[test]
+    fn type_1_uses_cycle() {
+        let mut sps = sps(2, 2, true);
+        sps.pic_order_cnt_type = 1;
+        sps.offset_for_ref_frame = vec![4, 2];
+        sps.offset_for_non_ref_pic = -3;
+        sps.offset_for_top_to_bottom_field = 1;
+        let mut state = PocState::new();
+        assert_eq!(
+            state.compute(&sps, &idr_header()),
+            PicOrderCnt { top: 0, bottom: 1 }
+        );
+        assert_eq!(state.compute(&sps, &header(SliceType::P, 1, 1)).top, 4);
+        assert_eq!(state.compute(&sps, &header(SliceType::P, 2, 1)).top, 6);
+        assert_eq!(state.compute(&sps, &header(SliceType::B, 3, 0)).top, 3);
+        assert_eq!(state.compute(&sps, &header(SliceType::P, 4, 1)).top, 12);
+    }
+}
@@ -0,0 +1,213 @@
+use super::{Error, ReadExt, Result, ScalingLists, Sps};
+use crate::bits::{self, BitReader};
+
+/// How macroblocks are mapped to slice groups (FMO), with the parameters of
+/// each `slice_group_map_type`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SliceGroupMap {
+    /// Type 0: `run_length_minus1` per slice group.
+    Interleaved { run_length_minus1: Vec<u32> },
+    /// Type 1.
+    Dispersed,
+    /// Type 2: `top_left` and `bottom_right` per slice group but the last.
+    Foreground {
+        top_left: Vec<u32>,
+        bottom_right: Vec<u32>,
+    },
+    /// Types 3 to 5: box-out, raster and wipe.
+    Changing {
+        slice_group_map_type: u8,
+        slice_group_change_direction_flag: bool,
+        slice_group_change_rate_minus1: u32,
+    },
+    /// Type 6: `slice_group_id` per map unit.
+    Explicit { slice_group_id: Vec<u8> },
+}
+
+impl SliceGroupMap {
+    pub fn slice_group_map_type(&self) -> u8 {
+        match self {
+            SliceGroupMap::Interleaved { .. } => 0,
+            SliceGroupMap::Dispersed => 1,
+            SliceGroupMap::Foreground { .. } => 2,
+            SliceGroupMap::Changing {
+                slice_group_map_type,
+                ..
+            } => *slice_group_map_type,
+            SliceGroupMap::Explicit { .. } => 6,
+        }
+    }
+}
+
+/// Picture parameter set, `pic_parameter_set_rbsp()`. Absent fields hold
+/// their inferred values.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pps {
+    pub pic_parameter_set_id: u8,
+    pub seq_parameter_set_id: u8,
+    pub entropy_coding_mode_flag: bool,
+    pub bottom_field_pic_order_in_frame_present_flag: bool,
+    pub num_slice_groups_minus1: u8,
+    /// Present when `num_slice_groups_minus1` is not zero.
+    pub slice_group_map: Option<SliceGroupMap>,
+    pub num_ref_idx_l0_default_active_minus1: u8,
+    pub num_ref_idx_l1_default_active_minus1: u8,
+    pub weighted_pred_flag: bool,
+    pub weighted_bipred_idc: u8,
+    pub pic_init_qp_minus26: i8,
+    pub pic_init_qs_minus26: i8,
+    pub chroma_qp_index_offset: i8,
+    pub deblocking_filter_control_present_flag: bool,
+    pub constrained_intra_pred_flag: bool,
+    pub redundant_pic_cnt_present_flag: bool,
+    pub transform_8x8_mode_flag: bool,
+    pub pic_scaling_matrix_present_flag: bool,
+    /// The lists for pictures using this PPS: its own if present, otherwise
+    /// the SPS's.
+    pub scaling_lists: ScalingLists,
+    pub second_chroma_qp_index_offset: i8,
+}
+
+impl Pps {
+    /// Parses a `pic_parameter_set_rbsp()`, without the NAL header. `lookup`
+    /// returns the SPS the PPS refers to, whose fields some of its syntax
+    /// depends on.
+    pub fn parse<'a>(rbsp: &[u8], lookup: impl FnOnce(u32) -> Option<&'a Sps>) -> Result<Self> {
+        let mut r = BitReader::new(rbsp);
+        let pic_parameter_set_id = r.read_ue_max("pic_parameter_set_id", 255)? as u8;
+        let seq_parameter_set_id = r.read_ue_max("seq_parameter_set_id", 31)?;
+        let sps = lookup(seq_parameter_set_id).ok_or(Error::MissingSps(seq_parameter_set_id))?;
+        let entropy_coding_mode_flag = r.read_bit()?;
+        let bottom_field_pic_order_in_frame_present_flag = r.read_bit()?;
+        let num_slice_groups_minus1 = r.read_ue_max("num_slice_groups_minus1", 7)? as u8;
+        let slice_group_map = if num_slice_groups_minus1 > 0 {
+            Some(Self::parse_slice_group_map(
+                &mut r,
+                num_slice_groups_minus1 as u32 + 1,
+                sps,
+            )?)
+        } else {
+            None
+        };
+        let num_ref_idx_l0_default_active_minus1 =
+            r.read_ue_max("num_ref_idx_l0_default_active_minus1", 31)? as u8;
+        let num_ref_idx_l1_default_active_minus1 =
+            r.read_ue_max("num_ref_idx_l1_default_active_minus1", 31)? as u8;
+        let weighted_pred_flag = r.read_bit()?;
+        let weighted_bipred_idc = r.read_bits_max("weighted_bipred_idc", 2, 2)? as u8;
+        let qp_bd_offset = 6 * sps.bit_depth_luma_minus8 as i32;
+        let pic_init_qp_minus26 =
+            r.read_se_range("pic_init_qp_minus26", -26 - qp_bd_offset, 25)? as i8;
+        let pic_init_qs_minus26 = r.read_se_range("pic_init_qs_minus26", -26, 25)? as i8;
+        let chroma_qp_index_offset = r.read_se_range("chroma_qp_index_offset", -12, 12)? as i8;
+        let deblocking_filter_control_present_flag = r.read_bit()?;
+        let constrained_intra_pred_flag = r.read_bit()?;
+        let redundant_pic_cnt_present_flag = r.read_bit()?;
+
+        let mut pps = Self {
+            pic_parameter_set_id,
+            seq_parameter_set_id: seq_parameter_set_id as u8,
+            entropy_coding_mode_flag,
+            bottom_field_pic_order_in_frame_present_flag,
+            num_slice_groups_minus1,
+            slice_group_map,
+            num_ref_idx_l0_default_active_minus1,
+            num_ref_idx_l1_default_active_minus1,
+            weighted_pred_flag,
+            weighted_bipred_idc,
+            pic_init_qp_minus26,
+            pic_init_qs_minus26,
+            chroma_qp_index_offset,
+            deblocking_filter_control_present_flag,
+            constrained_intra_pred_flag,
+            redundant_pic_cnt_present_flag,
+            transform_8x8_mode_flag: false,
+            pic_scaling_matrix_present_flag: false,
+            scaling_lists: sps.scaling_lists.clone(),
+            second_chroma_qp_index_offset: chroma_qp_index_offset,
+        };
+        if r.more_rbsp_data() {
+            pps.transform_8x8_mode_flag = r.read_bit()?;
+            pps.pic_scaling_matrix_present_flag = r.read_bit()?;
+            if pps.pic_scaling_matrix_present_flag {
+                let count = match (pps.transform_8x8_mode_flag, sps.chroma_format_idc) {
+                    (false, _) => 6,
+                    (true, 3) => 12,
+                    (true, _) => 8,
+                };
+                // Fall-back rule B applies when the SPS carries lists too.
+                let fallback = sps
+                    .seq_scaling_matrix_present_flag
+                    .then_some(&sps.scaling_lists);
+                pps.scaling_lists = ScalingLists::parse(&mut r, count, fallback)?;
+            }
+            pps.second_chroma_qp_index_offset =
+                r.read_se_range("second_chroma_qp_index_offset", -12, 12)? as i8;
+        }
+        Ok(pps)
+    }
+
+    fn parse_slice_group_map(
+        r: &mut BitReader,
+        num_slice_groups: u32,
+        sps: &Sps,
+    ) -> Result<SliceGroupMap> {
+        let map_units = sps.pic_width_in_mbs() * (sps.pic_height_in_map_units_minus1 + 1);
+        let map = match r.read_ue_max("slice_group_map_type", 6)? {
+            0 => SliceGroupMap::Interleaved {
+                run_length_minus1: (0..num_slice_groups)
+                    .map(|_| r.read_ue_max("run_length_minus1", map_units - 1))
+                    .collect::<bits::Result<_>>()?,
+            },
+            1 => SliceGroupMap::Dispersed,
+            2 => {
+                let mut top_left = Vec::new();
+                let mut bottom_right = Vec::new();
+                for _ in 1..num_slice_groups {
+                    let tl = r.read_ue_max("top_left", map_units - 1)?;
+                    let br = r.read_ue_max("bottom_right", map_units - 1)?;
+                    let width = sps.pic_width_in_mbs();
+                    if tl > br || tl % width > br % width {
+                        return Err(Error::InvalidValue {
+                            name: "top_left",
+                            value: tl as i64,
+                        });
+                    }
+                    top_left.push(tl);
+                    bottom_right.push(br);
+                }
+                SliceGroupMap::Foreground {
+                    top_left,
+                    bottom_right,
+                }
+            }
+            map_type @ 3..=5 => SliceGroupMap::Changing {
+                slice_group_map_type: map_type as u8,
+                slice_group_change_direction_flag: r.read_bit()?,
+                slice_group_change_rate_minus1: r
+                    .read_ue_max("slice_group_change_rate_minus1", map_units - 1)?,
+            },
+            _ => {
+                let count = r.read_ue()? as u64 + 1;
+                if count != map_units as u64 {
+                    return Err(Error::InvalidValue {
+                        name: "pic_size_in_map_units_minus1",
+                        value: count as i64 - 1,
+                    });
+                }
+                let bits = u32::BITS - (num_slice_groups - 1).leading_zeros();
+                SliceGroupMap::Explicit {
+                    slice_group_id: (0..count)
+                        .map(|_| {
+                            Ok(
+                                r.read_bits_max("slice_group_id", bits, num_slice_groups - 1)?
+                                    as u8,
+                            )
+                        })
+                        .collect::<Result<_>>()?,
+                }
+            }
+        };
+        Ok(map)
+    }
+}
@@ -0,0 +1,795 @@
+use super::{check, Error, NalHeader, NalUnitType, ParameterSets, ReadExt, Result};
+use crate::bits::BitReader;
+use crate::nal::{nal_offset, to_rbsp};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SliceType {
+    P,
+    B,
+    I,
+    Sp,
+    Si,
+}
+
+impl SliceType {
+    pub fn is_intra(self) -> bool {
+        matches!(self, SliceType::I | SliceType::Si)
+    }
+
+    /// Whether slices of this type use reference picture list 0.
+    pub fn is_inter(self) -> bool {
+        !self.is_intra()
+    }
+
+    pub fn is_b(self) -> bool {
+        self == SliceType::B
+    }
+}
+
+/// Whether a picture is a frame or one of its fields.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PictureStructure {
+    Frame,
+    TopField,
+    BottomField,
+}
+
+impl PictureStructure {
+    pub fn is_field(self) -> bool {
+        self != PictureStructure::Frame
+    }
+
+    /// The field of the other parity; frames stay frames.
+    pub fn opposite(self) -> Self {
+        match self {
+            PictureStructure::Frame => PictureStructure::Frame,
+            PictureStructure::TopField => PictureStructure::BottomField,
+            PictureStructure::BottomField => PictureStructure::TopField,
+        }
+    }
+}
+
+/// One `modification_of_pic_nums_idc` entry of
+/// `ref_pic_list_modification()` or `ref_pic_list_mvc_modification()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RefPicListModification {
+    /// 0: `abs_diff_pic_num_minus1`, subtracted from the prediction.
+    SubtractPicNum(u32),
+    /// 1: `abs_diff_pic_num_minus1`, added to the prediction.
+    AddPicNum(u32),
+    /// 2: `long_term_pic_num`.
+    LongTermPicNum(u32),
+    /// 4: `abs_diff_view_idx_minus1`, subtracted from the prediction.
+    SubtractViewIdx(u32),
+    /// 5: `abs_diff_view_idx_minus1`, added to the prediction.
+    AddViewIdx(u32),
+}
+
+/// The weights for one reference index of `pred_weight_table()`, inferred
+/// when their flags are not set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PredWeight {
+    pub luma_weight_flag: bool,
+    pub luma_weight: i16,
+    pub luma_offset: i16,
+    pub chroma_weight_flag: bool,
+    /// Cb, then Cr.
+    pub chroma_weight: [i16; 2],
+    pub chroma_offset: [i16; 2],
+}
+
+/// `pred_weight_table()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PredWeightTable {
+    pub luma_log2_weight_denom: u8,
+    pub chroma_log2_weight_denom: u8,
+    /// One entry per active reference index of each list.
+    pub l0: Vec<PredWeight>,
+    pub l1: Vec<PredWeight>,
+}
+
+/// A `memory_management_control_operation` with its operands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mmco {
+    /// 1: `difference_of_pic_nums_minus1`.
+    ShortTermUnused { difference_of_pic_nums_minus1: u32 },
+    /// 2.
+    LongTermUnused { long_term_pic_num: u32 },
+    /// 3.
+    ShortTermToLongTerm {
+        difference_of_pic_nums_minus1: u32,
+        long_term_frame_idx: u32,
+    },
+    /// 4.
+    MaxLongTermFrameIdx { max_long_term_frame_idx_plus1: u32 },
+    /// 5.
+    AllUnused,
+    /// 6.
+    CurrentToLongTerm { long_term_frame_idx: u32 },
+}
+
+/// `dec_ref_pic_marking()`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DecRefPicMarking {
+    /// IDR pictures only.
+    pub no_output_of_prior_pics_flag: bool,
+    /// IDR pictures only.
+    pub long_term_reference_flag: bool,
+    /// The operations of non-IDR pictures with
+    /// `adaptive_ref_pic_marking_mode_flag`; `None` selects the sliding
+    /// window.
+    pub adaptive: Option<Vec<Mmco>>,
+}
+
+impl DecRefPicMarking {
+    pub fn has_mmco5(&self) -> bool {
+        self.adaptive
+            .as_ref()
+            .is_some_and(|ops| ops.contains(&Mmco::AllUnused))
+    }
+}
+
+/// `slice_header()`, with the NAL header fields that go with it. Absent
+/// fields hold their inferred values.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SliceHeader {
+    pub nal_ref_idc: u8,
+    pub idr_pic_flag: bool,
+    pub first_mb_in_slice: u32,
+    pub slice_type: SliceType,
+    pub pic_parameter_set_id: u8,
+    pub colour_plane_id: u8,
+    pub frame_num: u32,
+    pub field_pic_flag: bool,
+    pub bottom_field_flag: bool,
+    pub idr_pic_id: u16,
+    pub pic_order_cnt_lsb: u32,
+    pub delta_pic_order_cnt_bottom: i32,
+    pub delta_pic_order_cnt: [i32; 2],
+    pub redundant_pic_cnt: u8,
+    pub direct_spatial_mv_pred_flag: bool,
+    pub num_ref_idx_active_override_flag: bool,
+    pub num_ref_idx_l0_active_minus1: u8,
+    pub num_ref_idx_l1_active_minus1: u8,
+    pub ref_pic_list_modification_l0: Vec<RefPicListModification>,
+    pub ref_pic_list_modification_l1: Vec<RefPicListModification>,
+    pub pred_weight_table: Option<PredWeightTable>,
+    /// Present on reference pictures.
+    pub dec_ref_pic_marking: Option<DecRefPicMarking>,
+    pub cabac_init_idc: u8,
+    pub slice_qp_delta: i8,
+    pub sp_for_switch_flag: bool,
+    pub slice_qs_delta: i8,
+    pub disable_deblocking_filter_idc: u8,
+    pub slice_alpha_c0_offset_div2: i8,
+    pub slice_beta_offset_div2: i8,
+    pub slice_group_change_cycle: u32,
+    /// Size of the NAL header and slice header in bits, not counting
+    /// emulation prevention bytes: the offset of `slice_data()` in the
+    /// RBSP, as VA's `slice_data_bit_offset` takes it.
+    pub header_bit_size: usize,
+    /// Emulation prevention bytes within the slice header.
+    pub header_emulation_prevention_bytes: usize,
+}
+
+// Bounds on the loops of the slice header, so that malformed input ends
+// up as an error before it turns into a large allocation.
+const MAX_MMCOS: usize = 66;
+
+impl SliceHeader {
+    /// Parses the slice header of `nal`, a coded slice NAL unit with its
+    /// header and emulation prevention bytes. The PPS the slice refers to
+    /// becomes the active one in `sets`.
+    pub fn parse(nal: &[u8], sets: &mut ParameterSets) -> Result<Self> {
+        let nal_header = NalHeader::parse(nal)?;
+        let idr_pic_flag = match nal_header.nal_unit_type {
+            NalUnitType::IdrSlice => true,
+            NalUnitType::Slice => false,
+            NalUnitType::SliceExtension => !nal_header.mvc_extension.unwrap().non_idr_flag,
+            _ => {
+                return Err(Error::Unsupported(
+                    "slice data partitioning and auxiliary slices",
+                ))
+            }
+        };
+        let header_size = nal_header.header_size();
+        let rbsp = to_rbsp(&nal[header_size..]);
+        let mut r = BitReader::new(&rbsp);
+
+        let first_mb_in_slice = r.read_ue()?;
+        let slice_type = match r.read_ue_max("slice_type", 9)? % 5 {
+            0 => SliceType::P,
+            1 => SliceType::B,
+            2 => SliceType::I,
+            3 => SliceType::Sp,
+            _ => SliceType::Si,
+        };
+        if idr_pic_flag && !slice_type.is_intra() {
+            return Err(Error::InvalidValue {
+                name: "slice_type",
+                value: slice_type as i64,
+            });
+        }
+        let pic_parameter_set_id = r.read_ue_max("pic_parameter_set_id", 255)?;
+        let (pps, sps) = sets.activate_pps(pic_parameter_set_id)?;
+        let mut header = Self {
+            nal_ref_idc: nal_header.nal_ref_idc,
+            idr_pic_flag,
+            first_mb_in_slice,
+            slice_type,
+            pic_parameter_set_id: pic_parameter_set_id as u8,
+            colour_plane_id: 0,
+            frame_num: 0,
+            field_pic_flag: false,
+            bottom_field_flag: false,
+            idr_pic_id: 0,
+            pic_order_cnt_lsb: 0,
+            delta_pic_order_cnt_bottom: 0,
+            delta_pic_order_cnt: [0; 2],
+            redundant_pic_cnt: 0,
+            direct_spatial_mv_pred_flag: false,
+            num_ref_idx_active_override_flag: false,
+            num_ref_idx_l0_active_minus1: pps.num_ref_idx_l0_default_active_minus1,
+            num_ref_idx_l1_active_minus1: pps.num_ref_idx_l1_default_active_minus1,
+            ref_pic_list_modification_l0: Vec::new(),
+            ref_pic_list_modification_l1: Vec::new(),
+            pred_weight_table: None,
+            dec_ref_pic_marking: None,
+            cabac_init_idc: 0,
+            slice_qp_delta: 0,
+            sp_for_switch_flag: false,
+            slice_qs_delta: 0,
+            disable_deblocking_filter_idc: 0,
+            slice_alpha_c0_offset_div2: 0,
+            slice_beta_offset_div2: 0,
+            slice_group_change_cycle: 0,
+            header_bit_size: 0,
+            header_emulation_prevention_bytes: 0,
+        };
+        let pic_size_in_mbs = sps.pic_width_in_mbs() * sps.frame_height_in_mbs();
+        check(
+            "first_mb_in_slice",
+            first_mb_in_slice as i64,
+            0,
+            pic_size_in_mbs as i64 - 1,
+        )?;
+
+        if sps.separate_colour_plane_flag {
+            header.colour_plane_id = r.read_bits_max("colour_plane_id", 2, 2)? as u8;
+        }
+        header.frame_num = r.read_bits(sps.log2_max_frame_num_minus4 as u32 + 4)?;
+        if idr_pic_flag && header.frame_num != 0 {
+            return Err(Error::InvalidValue {
+                name: "frame_num",
+                value: header.frame_num as i64,
+            });
+        }
+        if !sps.frame_mbs_only_flag {
+            header.field_pic_flag = r.read_bit()?;
+            if header.field_pic_flag {
+                header.bottom_field_flag = r.read_bit()?;
+            }
+        }
+        if idr_pic_flag {
+            header.idr_pic_id = r.read_ue_max("idr_pic_id", 65535)? as u16;
+        }
+        let bottom_field_pic_order_present =
+            pps.bottom_field_pic_order_in_frame_present_flag && !header.field_pic_flag;
+        if sps.pic_order_cnt_type == 0 {
+            header.pic_order_cnt_lsb =
+                r.read_bits(sps.log2_max_pic_order_cnt_lsb_minus4 as u32 + 4)?;
+            if bottom_field_pic_order_present {
+                header.delta_pic_order_cnt_bottom = r.read_se()?;
+            }
+        }
+        if sps.pic_order_cnt_type == 1 && !sps.delta_pic_order_always_zero_flag {
+            header.delta_pic_order_cnt[0] = r.read_se()?;
+            if bottom_field_pic_order_present {
+                header.delta_pic_order_cnt[1] = r.read_se()?;
+            }
+        }
+        if pps.redundant_pic_cnt_present_flag {
+            header.redundant_pic_cnt = r.read_ue_max("redundant_pic_cnt", 127)? as u8;
+        }
+        if slice_type.is_b() {
+            header.direct_spatial_mv_pred_flag = r.read_bit()?;
+        }
+        let max_ref_idx = if header.field_pic_flag { 31 } else { 15 };
+        if slice_type.is_inter() {
+            header.num_ref_idx_active_override_flag = r.read_bit()?;
+            if header.num_ref_idx_active_override_flag {
+                header.num_ref_idx_l0_active_minus1 =
+                    r.read_ue_max("num_ref_idx_l0_active_minus1", max_ref_idx)? as u8;
+                if slice_type.is_b() {
+                    header.num_ref_idx_l1_active_minus1 =
+                        r.read_ue_max("num_ref_idx_l1_active_minus1", max_ref_idx)? as u8;
+                }
+            }
+            check(
+                "num_ref_idx_l0_active_minus1",
+                header.num_ref_idx_l0_active_minus1 as i64,
+                0,
+                max_ref_idx as i64,
+            )?;
+            check(
+                "num_ref_idx_l1_active_minus1",
+                header.num_ref_idx_l1_active_minus1 as i64,
+                0,
+                max_ref_idx as i64,
+            )?;
+        }
+
+        let mvc = nal_header.nal_unit_type == NalUnitType::SliceExtension;
+        if slice_type.is_inter() {
+            header.ref_pic_list_modification_l0 =
+                parse_ref_pic_list_modification(&mut r, header.num_ref_idx_l0_active_minus1, mvc)?;
+        }
+        if slice_type.is_b() {
+            header.ref_pic_list_modification_l1 =
+                parse_ref_pic_list_modification(&mut r, header.num_ref_idx_l1_active_minus1, mvc)?;
+        }
+
+        if (pps.weighted_pred_flag && matches!(slice_type, SliceType::P | SliceType::Sp))
+            || (pps.weighted_bipred_idc == 1 && slice_type.is_b())
+        {
+            header.pred_weight_table =
+                Some(header.parse_pred_weight_table(&mut r, sps.chroma_array_type() != 0)?);
+        }
+        if header.nal_ref_idc != 0 {
+            header.dec_ref_pic_marking = Some(parse_dec_ref_pic_marking(&mut r, idr_pic_flag)?);
+        }
+        if pps.entropy_coding_mode_flag && slice_type.is_inter() {
+            header.cabac_init_idc = r.read_ue_max("cabac_init_idc", 2)? as u8;
+        }
+        let qp_bd_offset = 6 * sps.bit_depth_luma_minus8 as i32;
+        header.slice_qp_delta = r.read_se_range(
+            "slice_qp_delta",
+            -26 - qp_bd_offset - pps.pic_init_qp_minus26 as i32,
+            25 - pps.pic_init_qp_minus26 as i32,
+        )? as i8;
+        if matches!(slice_type, SliceType::Sp | SliceType::Si) {
+            if slice_type == SliceType::Sp {
+                header.sp_for_switch_flag = r.read_bit()?;
+            }
+            header.slice_qs_delta = r.read_se_range(
+                "slice_qs_delta",
+                -26 - pps.pic_init_qs_minus26 as i32,
+                25 - pps.pic_init_qs_minus26 as i32,
+            )? as i8;
+        }
+        if pps.deblocking_filter_control_present_flag {
+            header.disable_deblocking_filter_idc =
+                r.read_ue_max("disable_deblocking_filter_idc", 2)? as u8;
+            if header.disable_deblocking_filter_idc != 1 {
+                header.slice_alpha_c0_offset_div2 =
+                    r.read_se_range("slice_alpha_c0_offset_div2", -6, 6)? as i8;
+                header.slice_beta_offset_div2 =
+                    r.read_se_range("slice_beta_offset_div2", -6, 6)? as i8;
+            }
+        }
+        if let Some(super::SliceGroupMap::Changing {
+            slice_group_change_rate_minus1,
+            ..
+        }) = &pps.slice_group_map
+        {
+            let pic_size_in_map_units =
+                sps.pic_width_in_mbs() * (sps.pic_height_in_map_units_minus1 + 1);
+            let rate = slice_group_change_rate_minus1 + 1;
+            let max = pic_size_in_map_units.div_ceil(rate);
+            let bits = u32::BITS - max.leading_zeros();
+            header.slice_group_change_cycle =
+                r.read_bits_max("slice_group_change_cycle", bits, max)?;
+        }
+
+        let position = r.position();
+        header.header_bit_size = header_size * 8 + position;
+        header.header_emulation_prevention_bytes =
+            nal_offset(&nal[header_size..], position / 8) - position / 8;
+        Ok(header)
+    }
+
+    pub fn structure(&self) -> PictureStructure {
+        match (self.field_pic_flag, self.bottom_field_flag) {
+            (false, _) => PictureStructure::Frame,
+            (true, false) => PictureStructure::TopField,
+            (true, true) => PictureStructure::BottomField,
+        }
+    }
+
+    pub fn has_mmco5(&self) -> bool {
+        self.dec_ref_pic_marking
+            .as_ref()
+            .is_some_and(DecRefPicMarking::has_mmco5)
+    }
+
+    /// Whether `self` starts a new picture after the slice `previous`, by
+    /// the first VCL NAL unit rules of 7.4.1.2.4.
+    pub fn starts_new_picture(&self, previous: &SliceHeader) -> bool {
+        self.frame_num != previous.frame_num
+            || self.pic_parameter_set_id != previous.pic_parameter_set_id
+            || self.field_pic_flag != previous.field_pic_flag
+            || self.bottom_field_flag != previous.bottom_field_flag
+            || (self.nal_ref_idc == 0) != (previous.nal_ref_idc == 0)
+            || self.pic_order_cnt_lsb != previous.pic_order_cnt_lsb
+            || self.delta_pic_order_cnt_bottom != previous.delta_pic_order_cnt_bottom
+            || self.delta_pic_order_cnt != previous.delta_pic_order_cnt
+            || self.idr_pic_flag != previous.idr_pic_flag
+            || (self.idr_pic_flag && self.idr_pic_id != previous.idr_pic_id)
+    }
+
+    fn parse_pred_weight_table(&self, r: &mut BitReader, chroma: bool) -> Result<PredWeightTable> {
+        let luma_log2_weight_denom = r.read_ue_max("luma_log2_weight_denom", 7)? as u8;
+        let chroma_log2_weight_denom = if chroma {
+            r.read_ue_max("chroma_log2_weight_denom", 7)? as u8
+        } else {
+            0
+        };
+        let mut parse_list = |count: u8| {
+            (0..=count)
+                .map(|_| {
+                    let mut weight = PredWeight {
+                        luma_weight_flag: r.read_bit()?,
+                        luma_weight: 1 << luma_log2_weight_denom,
+                        luma_offset: 0,
+                        chroma_weight_flag: false,
+                        chroma_weight: [1 << chroma_log2_weight_denom; 2],
+                        chroma_offset: [0; 2],
+                    };
+                    if weight.luma_weight_flag {
+                        weight.luma_weight = r.read_se_range("luma_weight", -128, 127)? as i16;
+                        weight.luma_offset = r.read_se_range("luma_offset", -128, 127)? as i16;
+                    }
+                    if chroma {
+                        weight.chroma_weight_flag = r.read_bit()?;
+                        if weight.chroma_weight_flag {
+                            for j in 0..2 {
+                                weight.chroma_weight[j] =
+                                    r.read_se_range("chroma_weight", -128, 127)? as i16;
+                                weight.chroma_offset[j] =
+                                    r.read_se_range("chroma_offset", -128, 127)? as i16;
+                            }
+                        }
+                    }
+                    Ok(weight)
+                })
+                .collect::<Result<Vec<_>>>()
+        };
+        let l0 = parse_list(self.num_ref_idx_l0_active_minus1)?;
+        let l1 = if self.slice_type.is_b() {
+            parse_list(self.num_ref_idx_l1_active_minus1)?
+        } else {
+            Vec::new()
+        };
+        Ok(PredWeightTable {
+            luma_log2_weight_denom,
+            chroma_log2_weight_denom,
+            l0,
+            l1,
+        })
+    }
+}
+
+fn parse_ref_pic_list_modification(
+    r: &mut BitReader,
+    num_ref_idx_active_minus1: u8,
+    mvc: bool,
+) -> Result<Vec<RefPicListModification>> {
+    let mut modifications = Vec::new();
+    if !r.read_bit()? {
+        return Ok(modifications);
+    }
+    let max_idc = if mvc { 5 } else { 3 };
+    loop {
+        let idc = r.read_ue_max("modification_of_pic_nums_idc", max_idc)?;
+        let modification = match idc {
+            0 => RefPicListModification::SubtractPicNum(r.read_ue()?),
+            1 => RefPicListModification::AddPicNum(r.read_ue()?),
+            2 => RefPicListModification::LongTermPicNum(r.read_ue()?),
+            3 => return Ok(modifications),
+            4 => RefPicListModification::SubtractViewIdx(r.read_ue()?),
+            _ => RefPicListModification::AddViewIdx(r.read_ue()?),
+        };
+        if modifications.len() > num_ref_idx_active_minus1 as usize {
+            return Err(Error::InvalidValue {
+                name: "modification_of_pic_nums_idc",
+                value: idc as i64,
+            });
+        }
+        modifications.push(modification);
+    }
+}
+
+fn parse_dec_ref_pic_marking(r: &mut BitReader, idr_pic_flag: bool) -> Result<DecRefPicMarking> {
+    let mut marking = DecRefPicMarking::default();
+    if idr_pic_flag {
+        marking.no_output_of_prior_pics_flag = r.read_bit()?;
+        marking.long_term_reference_flag = r.read_bit()?;
+        return Ok(marking);
+    }
+    if !r.read_bit()? {
+        return Ok(marking);
+    }
+    let mut ops = Vec::new();
+    loop {
+        let op = match r.read_ue_max("memory_management_control_operation", 6)? {
+            0 => break,
+            1 => Mmco::ShortTermUnused {
+                difference_of_pic_nums_minus1: r.read_ue()?,
+            },
+            2 => Mmco::LongTermUnused {
+                long_term_pic_num: r.read_ue()?,
+            },
+            3 => Mmco::ShortTermToLongTerm {
+                difference_of_pic_nums_minus1: r.read_ue()?,
+                long_term_frame_idx: r.read_ue_max("long_term_frame_idx", 15)?,
+            },
+            4 => Mmco::MaxLongTermFrameIdx {
+                max_long_term_frame_idx_plus1: r
+                    .read_ue_max("max_long_term_frame_idx_plus1", 16)?,
+            },
+            5 => Mmco::AllUnused,
+            _ => Mmco::CurrentToLongTerm {
+                long_term_frame_idx: r.read_ue_max("long_term_frame_idx", 15)?,
+            },
+        };
+        if ops.len() == MAX_MMCOS {
+            return Err(Error::InvalidValue {
+                name: "memory_management_control_operation",
+                value: ops.len() as i64,
+            });
+        }
+        ops.push(op);
+    }
+    marking.adaptive = Some(ops);
+    Ok(marking)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::bits::tests::random_chunks;
+    use crate::bits::BitWriter;
+    use crate::h264::Sps;
+
+    /// A Baseline SPS for 11x9 macroblocks (of frames, or of fields when not
+    /// `frame_mbs_only`), with a 4-bit `frame_num` and POC LSB.
+    pub(crate) fn sps(
+        pic_order_cnt_type: u32,
+        max_num_ref_frames: u32,
+        frame_mbs_only: bool,
+    ) -> Sps {
+        let mut w = BitWriter::new();
+        w.write_bits(66, 8);
+        w.write_bits(0, 8);
+        w.write_bits(10, 8); // level_idc
+        w.write_ue(0);
+        w.write_ue(0);
+        w.write_ue(pic_order_cnt_type);
+        if pic_order_cnt_type == 0 {
+            w.write_ue(0);
+        }
+        w.write_ue(max_num_ref_frames);
+        w.write_bit(true); // gaps_in_frame_num_value_allowed_flag
+        w.write_ue(10);
+        w.write_ue(8);
+        w.write_bit(frame_mbs_only);
+        if !frame_mbs_only {
+            w.write_bit(false);
+        }
+        w.write_bit(true);
+        w.write_bit(false);
+        w.write_bit(false);
+        w.write_trailing_bits();
+        Sps::parse(&w.into_bytes()).unwrap()
+    }
+
+    /// A slice header of a picture using the sliding window.
+    pub(crate) fn header(slice_type: SliceType, frame_num: u32, nal_ref_idc: u8) -> SliceHeader {
+        SliceHeader {
+            nal_ref_idc,
+            idr_pic_flag: false,
+            first_mb_in_slice: 0,
+            slice_type,
+            pic_parameter_set_id: 0,
+            colour_plane_id: 0,
+            frame_num,
+            field_pic_flag: false,
+            bottom_field_flag: false,
+            idr_pic_id: 0,
+            pic_order_cnt_lsb: 0,
+            delta_pic_order_cnt_bottom: 0,
+            delta_pic_order_cnt: [0; 2],
+            redundant_pic_cnt: 0,
+            direct_spatial_mv_pred_flag: false,
+            num_ref_idx_active_override_flag: false,
+            num_ref_idx_l0_active_minus1: 15,
+            num_ref_idx_l1_active_minus1: 15,
+            ref_pic_list_modification_l0: Vec::new(),
+            ref_pic_list_modification_l1: Vec::new(),
+            pred_weight_table: None,
+            dec_ref_pic_marking: (nal_ref_idc != 0).then(DecRefPicMarking::default),
+            cabac_init_idc: 0,
+            slice_qp_delta: 0,
+            sp_for_switch_flag: false,
+            slice_qs_delta: 0,
+            disable_deblocking_filter_idc: 0,
+            slice_alpha_c0_offset_div2: 0,
+            slice_beta_offset_div2: 0,
+            slice_group_change_cycle: 0,
+            header_bit_size: 0,
+            header_emulation_prevention_bytes: 0,
+        }
+    }
+
+    pub(crate) fn idr_header() -> SliceHeader {
+        SliceHeader {
+            idr_pic_flag: true,
+            ..header(SliceType::I, 0, 3)
+        }
+    }
+
+    fn parameter_sets() -> ParameterSets {
+        let mut w = BitWriter::new();
+        w.write_bits(77, 8); // profile_idc
+        w.write_bits(0, 8);
+        w.write_bits(30, 8);
+        w.write_ue(0);
+        w.write_ue(0); // log2_max_frame_num_minus4
+        w.write_ue(0); // pic_order_cnt_type
+        w.write_ue(0);
+        w.write_ue(2); // max_num_ref_frames
+        w.write_bit(false);
+        w.write_ue(1);
+        w.write_ue(1);
+        w.write_bit(true); // frame_mbs_only_flag
+        w.write_bit(true);
+        w.write_bit(false);
+        w.write_bit(false);
+        w.write_trailing_bits();
+        let mut sets = ParameterSets::new();
+        sets.add_sps(&w.into_bytes()).unwrap();
+
+        let mut w = BitWriter::new();
+        w.write_ue(0);
+        w.write_ue(0);
+        w.write_bit(true); // entropy_coding_mode_flag
+        w.write_bit(false);
+        w.write_ue(0);
+        w.write_ue(0);
+        w.write_ue(0);
+        w.write_bit(true); // weighted_pred_flag
+        w.write_bits(0, 2);
+        w.write_se(0);
+        w.write_se(0);
+        w.write_se(0);
+        w.write_bit(true); // deblocking_filter_control_present_flag
+        w.write_bit(false);
+        w.write_bit(false);
+        w.write_trailing_bits();
+        sets.add_pps(w.into_bytes()).unwrap();
+        sets
+    }
+
+    /// A P slice using every optional part of the header; it ends with two
+    /// zero bytes of slice data.
+    fn p_slice() -> (Vec<u8>, usize) {
+        let mut w = BitWriter::with_emulation_prevention();
+        w.write_bits(0x41, 8); // nal_ref_idc 2, non-IDR slice
+        w.write_ue(0);
+        w.write_ue(5); // slice_type P
+        w.write_ue(0);
+        w.write_bits(3, 4); // frame_num
+        w.write_bits(6, 4); // pic_order_cnt_lsb
+        w.write_bit(true);
+        w.write_ue(1); // num_ref_idx_l0_active_minus1
+        w.write_bit(true); // ref_pic_list_modification_flag_l0
+        w.write_ue(0);
+        w.write_ue(0);
+        w.write_ue(3);
+        w.write_ue(5); // luma_log2_weight_denom
+        w.write_ue(5);
+        w.write_bit(true);
+        w.write_se(40);
+        w.write_se(-3);
+        w.write_bit(false);
+        w.write_bit(false);
+        w.write_bit(true); // chroma_weight_l0_flag
+        for value in [30, 1, 31, 2] {
+            w.write_se(value);
+        }
+        w.write_bit(true); // adaptive_ref_pic_marking_mode_flag
+        w.write_ue(1);
+        // Long enough a run of zeros to need emulation prevention.
+        w.write_ue(1 << 30);
+        w.write_ue(0);
+        w.write_ue(2); // cabac_init_idc
+        w.write_se(-4);
+        w.write_ue(0); // disable_deblocking_filter_idc
+        w.write_se(2);
+        w.write_se(-1);
+        let header_bit_size = w.position();
+        w.write_bits(0, 16);
+        w.write_trailing_bits();
+        (w.into_bytes(), header_bit_size)
+    }
+
+    #[test]
+    fn parses_p_slice() {
+        let mut sets = parameter_sets();
+        let (nal, header_bit_size) = p_slice();
+        assert!(to_rbsp(&nal).len() < nal.len());
+        let header = SliceHeader::parse(&nal, &mut sets).unwrap();
+        assert_eq!(header.slice_type, SliceType::P);
+        assert_eq!(header.structure(), PictureStructure::Frame);
+        assert_eq!((header.frame_num, header.pic_order_cnt_lsb), (3, 6));
+        assert_eq!(header.num_ref_idx_l0_active_minus1, 1);
+        assert_eq!(
+            header.ref_pic_list_modification_l0,
+            [RefPicListModification::SubtractPicNum(0)]
+        );
+        let table = header.pred_weight_table.as_ref().unwrap();
+        assert_eq!(table.l0[0].luma_weight, 40);
+        assert_eq!(table.l0[0].chroma_weight, [32, 32]);
+        assert_eq!(table.l0[1].luma_weight, 32);
+        assert_eq!(table.l0[1].chroma_offset, [1, 2]);
+        assert!(table.l1.is_empty());
+        assert_eq!(
+            header.dec_ref_pic_marking.as_ref().unwrap().adaptive,
+            Some(vec![Mmco::ShortTermUnused {
+                difference_of_pic_nums_minus1: 1 << 30
+            }])
+        );
+        assert_eq!(header.cabac_init_idc, 2);
+        assert_eq!(header.slice_qp_delta, -4);
+        assert_eq!(
+            (
+                header.slice_alpha_c0_offset_div2,
+                header.slice_beta_offset_div2
+            ),
+            (2, -1)
+        );
+        // The escaped offset is two bytes further.
+        assert_eq!(header.header_emulation_prevention_bytes, 2);
+        assert_eq!(header.header_bit_size, header_bit_size - 16);
+        assert!(sets.active_pps().is_some());
+    }
+
+    #[test]
+    fn rejects_malformed_slices() {
+        let mut sets = parameter_sets();
+        let (nal, _) = p_slice();
+        for len in 0..nal.len() - 4 {
+            assert!(SliceHeader::parse(&nal[..len], &mut sets).is_err());
+        }
+
+        // An IDR slice must be intra.
+        let mut w = BitWriter::new();
+        w.write_bits(0x65, 8);
+        w.write_ue(0);
+        w.write_ue(0);
+        w.write_trailing_bits();
+        assert_eq!(
+            SliceHeader::parse(&w.into_bytes(), &mut sets),
+            Err(Error::InvalidValue {
+                name: "slice_type",
+                value: 0
+            })
+        );
+
+        let mut w = BitWriter::new();
+        w.write_bits(0x41, 8);
+        w.write_ue(0);
+        w.write_ue(0);
+        w.write_ue(5); // pic_parameter_set_id
+        w.write_trailing_bits();
+        assert_eq!(
+            SliceHeader::parse(&w.into_bytes(), &mut sets),
+            Err(Error::MissingPps(5))
+        );
+
+        for mut data in random_chunks(0x8765_4321, 32, 2000) {
+            data[0] = 0x41;
+            let _ = SliceHeader::parse(&data, &mut sets);
+        }
+    }
+}
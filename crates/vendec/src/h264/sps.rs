@@ -0,0 +1,833 @@
+use super::{check, Error, ReadExt, Result};
+use crate::bits::{self, BitReader};
+
+/// `Default_4x4_Intra`, in zigzag scan order like all scaling lists here.
+pub const DEFAULT_4X4_INTRA: [u8; 16] = [
+    6, 13, 13, 20, 20, 20, 28, 28, 28, 28, 32, 32, 32, 37, 37, 42,
+];
+pub const DEFAULT_4X4_INTER: [u8; 16] = [
+    10, 14, 14, 20, 20, 20, 24, 24, 24, 24, 27, 27, 27, 30, 30, 34,
+];
+#[rustfmt::skip]
+pub const DEFAULT_8X8_INTRA: [u8; 64] = [
+    6, 10, 10, 13, 11, 13, 16, 16, 16, 16, 18, 18, 18, 18, 18, 23,
+    23, 23, 23, 23, 23, 25, 25, 25, 25, 25, 25, 25, 27, 27, 27, 27,
+    27, 27, 27, 27, 29, 29, 29, 29, 29, 29, 29, 31, 31, 31, 31, 31,
+    31, 33, 33, 33, 33, 33, 36, 36, 36, 36, 38, 38, 38, 40, 40, 42,
+];
+#[rustfmt::skip]
+pub const DEFAULT_8X8_INTER: [u8; 64] = [
+    9, 13, 13, 15, 13, 15, 17, 17, 17, 17, 19, 19, 19, 19, 19, 21,
+    21, 21, 21, 21, 21, 22, 22, 22, 22, 22, 22, 22, 24, 24, 24, 24,
+    24, 24, 24, 24, 25, 25, 25, 25, 25, 25, 25, 27, 27, 27, 27, 27,
+    27, 28, 28, 28, 28, 28, 30, 30, 30, 30, 32, 32, 32, 33, 33, 35,
+];
+
+/// The scaling lists in effect, with the fall-back rules of table 7-2
+/// already applied. Lists are in zigzag scan order; 8x8 lists 2 to 5 are
+/// only coded for 4:4:4.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScalingLists {
+    /// Intra Y, Cb, Cr, then inter Y, Cb, Cr.
+    pub list_4x4: [[u8; 16]; 6],
+    /// Intra Y, inter Y, intra Cb, inter Cb, intra Cr, inter Cr.
+    pub list_8x8: [[u8; 64]; 6],
+}
+
+impl Default for ScalingLists {
+    /// `Flat_4x4_16` and `Flat_8x8_16`.
+    fn default() -> Self {
+        Self {
+            list_4x4: [[16; 16]; 6],
+            list_8x8: [[16; 64]; 6],
+        }
+    }
+}
+
+impl ScalingLists {
+    /// Parses the `*_scaling_list_present_flag` loop for `count` lists.
+    /// Missing lists follow fall-back rule A, or rule B with `fallback` as
+    /// the sequence-level lists.
+    pub(crate) fn parse(
+        r: &mut BitReader,
+        count: usize,
+        fallback: Option<&ScalingLists>,
+    ) -> Result<Self> {
+        let mut lists = Self::default();
+        for i in 0..12 {
+            let present = i < count && r.read_bit()?;
+            if i < 6 {
+                let default = if i < 3 {
+                    &DEFAULT_4X4_INTRA
+                } else {
+                    &DEFAULT_4X4_INTER
+                };
+                lists.list_4x4[i] = if present {
+                    parse_list(r, default)?
+                } else if i == 0 || i == 3 {
+                    fallback.map_or(*default, |f| f.list_4x4[i])
+                } else {
+                    lists.list_4x4[i - 1]
+                };
+            } else {
+                let j = i - 6;
+                let default = if j % 2 == 0 {
+                    &DEFAULT_8X8_INTRA
+                } else {
+                    &DEFAULT_8X8_INTER
+                };
+                lists.list_8x8[j] = if present {
+                    parse_list(r, default)?
+                } else if j < 2 {
+                    fallback.map_or(*default, |f| f.list_8x8[j])
+                } else {
+                    lists.list_8x8[j - 2]
+                };
+            }
+        }
+        Ok(lists)
+    }
+}
+
+/// `scaling_list()`, returning `default` if `useDefaultScalingMatrixFlag`
+/// ends up set.
+fn parse_list<const N: usize>(r: &mut BitReader, default: &[u8; N]) -> Result<[u8; N]> {
+    let mut list = [0; N];
+    let mut last_scale = 8;
+    let mut next_scale = 8;
+    for (j, scale) in list.iter_mut().enumerate() {
+        if next_scale != 0 {
+            let delta_scale = r.read_se_range("delta_scale", -128, 127)?;
+            next_scale = (last_scale + delta_scale + 256) % 256;
+            if j == 0 && next_scale == 0 {
+                return Ok(*default);
+            }
+        }
+        *scale = if next_scale == 0 {
+            last_scale
+        } else {
+            next_scale
+        } as u8;
+        last_scale = *scale as i32;
+    }
+    Ok(list)
+}
+
+/// `hrd_parameters()`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Hrd {
+    pub bit_rate_scale: u8,
+    pub cpb_size_scale: u8,
+    /// One entry per CPB, `cpb_cnt_minus1 + 1` in total.
+    pub cpbs: Vec<HrdCpb>,
+    pub initial_cpb_removal_delay_length_minus1: u8,
+    pub cpb_removal_delay_length_minus1: u8,
+    pub dpb_output_delay_length_minus1: u8,
+    pub time_offset_length: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct HrdCpb {
+    pub bit_rate_value_minus1: u32,
+    pub cpb_size_value_minus1: u32,
+    pub cbr_flag: bool,
+}
+
+impl Hrd {
+    fn parse(r: &mut BitReader) -> Result<Self> {
+        let cpb_cnt_minus1 = r.read_ue_max("cpb_cnt_minus1", 31)?;
+        let bit_rate_scale = r.read_bits(4)? as u8;
+        let cpb_size_scale = r.read_bits(4)? as u8;
+        let cpbs = (0..=cpb_cnt_minus1)
+            .map(|_| {
+                Ok(HrdCpb {
+                    bit_rate_value_minus1: r.read_ue_max("bit_rate_value_minus1", u32::MAX - 1)?,
+                    cpb_size_value_minus1: r.read_ue_max("cpb_size_value_minus1", u32::MAX - 1)?,
+                    cbr_flag: r.read_bit()?,
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            bit_rate_scale,
+            cpb_size_scale,
+            cpbs,
+            initial_cpb_removal_delay_length_minus1: r.read_bits(5)? as u8,
+            cpb_removal_delay_length_minus1: r.read_bits(5)? as u8,
+            dpb_output_delay_length_minus1: r.read_bits(5)? as u8,
+            time_offset_length: r.read_bits(5)? as u8,
+        })
+    }
+}
+
+/// `vui_parameters()`. Absent fields hold their inferred values.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Vui {
+    pub aspect_ratio_info_present_flag: bool,
+    pub aspect_ratio_idc: u8,
+    pub sar_width: u16,
+    pub sar_height: u16,
+    pub overscan_info_present_flag: bool,
+    pub overscan_appropriate_flag: bool,
+    pub video_signal_type_present_flag: bool,
+    pub video_format: u8,
+    pub video_full_range_flag: bool,
+    pub colour_description_present_flag: bool,
+    pub colour_primaries: u8,
+    pub transfer_characteristics: u8,
+    pub matrix_coefficients: u8,
+    pub chroma_loc_info_present_flag: bool,
+    pub chroma_sample_loc_type_top_field: u8,
+    pub chroma_sample_loc_type_bottom_field: u8,
+    pub timing_info_present_flag: bool,
+    pub num_units_in_tick: u32,
+    pub time_scale: u32,
+    pub fixed_frame_rate_flag: bool,
+    pub nal_hrd: Option<Hrd>,
+    pub vcl_hrd: Option<Hrd>,
+    pub low_delay_hrd_flag: bool,
+    pub pic_struct_present_flag: bool,
+    pub bitstream_restriction_flag: bool,
+    pub motion_vectors_over_pic_boundaries_flag: bool,
+    pub max_bytes_per_pic_denom: u8,
+    pub max_bits_per_mb_denom: u8,
+    pub log2_max_mv_length_horizontal: u8,
+    pub log2_max_mv_length_vertical: u8,
+    pub max_num_reorder_frames: u8,
+    pub max_dec_frame_buffering: u8,
+}
+
+/// `aspect_ratio_idc` value signalling an explicit `sar_width:sar_height`.
+pub const EXTENDED_SAR: u8 = 255;
+
+impl Vui {
+    fn parse(r: &mut BitReader, max_dpb_frames: u32) -> Result<Self> {
+        let mut vui = Self {
+            aspect_ratio_info_present_flag: r.read_bit()?,
+            aspect_ratio_idc: 0,
+            sar_width: 0,
+            sar_height: 0,
+            overscan_info_present_flag: false,
+            overscan_appropriate_flag: false,
+            video_signal_type_present_flag: false,
+            video_format: 5,
+            video_full_range_flag: false,
+            colour_description_present_flag: false,
+            colour_primaries: 2,
+            transfer_characteristics: 2,
+            matrix_coefficients: 2,
+            chroma_loc_info_present_flag: false,
+            chroma_sample_loc_type_top_field: 0,
+            chroma_sample_loc_type_bottom_field: 0,
+            timing_info_present_flag: false,
+            num_units_in_tick: 0,
+            time_scale: 0,
+            fixed_frame_rate_flag: false,
+            nal_hrd: None,
+            vcl_hrd: None,
+            low_delay_hrd_flag: false,
+            pic_struct_present_flag: false,
+            bitstream_restriction_flag: false,
+            motion_vectors_over_pic_boundaries_flag: true,
+            max_bytes_per_pic_denom: 2,
+            max_bits_per_mb_denom: 1,
+            log2_max_mv_length_horizontal: 15,
+            log2_max_mv_length_vertical: 15,
+            max_num_reorder_frames: max_dpb_frames as u8,
+            max_dec_frame_buffering: max_dpb_frames as u8,
+        };
+        if vui.aspect_ratio_info_present_flag {
+            vui.aspect_ratio_idc = r.read_bits(8)? as u8;
+            if vui.aspect_ratio_idc == EXTENDED_SAR {
+                vui.sar_width = r.read_bits(16)? as u16;
+                vui.sar_height = r.read_bits(16)? as u16;
+            }
+        }
+        vui.overscan_info_present_flag = r.read_bit()?;
+        if vui.overscan_info_present_flag {
+            vui.overscan_appropriate_flag = r.read_bit()?;
+        }
+        vui.video_signal_type_present_flag = r.read_bit()?;
+        if vui.video_signal_type_present_flag {
+            vui.video_format = r.read_bits(3)? as u8;
+            vui.video_full_range_flag = r.read_bit()?;
+            vui.colour_description_present_flag = r.read_bit()?;
+            if vui.colour_description_present_flag {
+                vui.colour_primaries = r.read_bits(8)? as u8;
+                vui.transfer_characteristics = r.read_bits(8)? as u8;
+                vui.matrix_coefficients = r.read_bits(8)? as u8;
+            }
+        }
+        vui.chroma_loc_info_present_flag = r.read_bit()?;
+        if vui.chroma_loc_info_present_flag {
+            vui.chroma_sample_loc_type_top_field =
+                r.read_ue_max("chroma_sample_loc_type_top_field", 5)? as u8;
+            vui.chroma_sample_loc_type_bottom_field =
+                r.read_ue_max("chroma_sample_loc_type_bottom_field", 5)? as u8;
+        }
+        vui.timing_info_present_flag = r.read_bit()?;
+        if vui.timing_info_present_flag {
+            vui.num_units_in_tick = r.read_bits(32)?;
+            vui.time_scale = r.read_bits(32)?;
+            vui.fixed_frame_rate_flag = r.read_bit()?;
+            if vui.num_units_in_tick == 0 || vui.time_scale == 0 {
+                return Err(Error::InvalidValue {
+                    name: "num_units_in_tick",
+                    value: vui.num_units_in_tick as i64,
+                });
+            }
+        }
+        if r.read_bit()? {
+            vui.nal_hrd = Some(Hrd::parse(r)?);
+        }
+        if r.read_bit()? {
+            vui.vcl_hrd = Some(Hrd::parse(r)?);
+        }
+        if vui.nal_hrd.is_some() || vui.vcl_hrd.is_some() {
+            vui.low_delay_hrd_flag = r.read_bit()?;
+        }
+        vui.pic_struct_present_flag = r.read_bit()?;
+        vui.bitstream_restriction_flag = r.read_bit()?;
+        if vui.bitstream_restriction_flag {
+            vui.motion_vectors_over_pic_boundaries_flag = r.read_bit()?;
+            vui.max_bytes_per_pic_denom = r.read_ue_max("max_bytes_per_pic_denom", 16)? as u8;
+            vui.max_bits_per_mb_denom = r.read_ue_max("max_bits_per_mb_denom", 16)? as u8;
+            vui.log2_max_mv_length_horizontal =
+                r.read_ue_max("log2_max_mv_length_horizontal", 16)? as u8;
+            vui.log2_max_mv_length_vertical =
+                r.read_ue_max("log2_max_mv_length_vertical", 16)? as u8;
+            vui.max_num_reorder_frames = r.read_ue_max("max_num_reorder_frames", 16)? as u8;
+            vui.max_dec_frame_buffering = r.read_ue_max("max_dec_frame_buffering", 16)? as u8;
+            check(
+                "max_num_reorder_frames",
+                vui.max_num_reorder_frames as i64,
+                0,
+                vui.max_dec_frame_buffering as i64,
+            )?;
+        }
+        Ok(vui)
+    }
+}
+
+/// `frame_crop_*_offset`, in units of `CropUnitX`/`CropUnitY`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FrameCropping {
+    pub left_offset: u32,
+    pub right_offset: u32,
+    pub top_offset: u32,
+    pub bottom_offset: u32,
+}
+
+/// A rectangle in luma samples.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Sequence parameter set, `seq_parameter_set_data()`. Absent fields hold
+/// their inferred values.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sps {
+    pub profile_idc: u8,
+    pub constraint_set0_flag: bool,
+    pub constraint_set1_flag: bool,
+    pub constraint_set2_flag: bool,
+    pub constraint_set3_flag: bool,
+    pub constraint_set4_flag: bool,
+    pub constraint_set5_flag: bool,
+    pub level_idc: u8,
+    pub seq_parameter_set_id: u8,
+    pub chroma_format_idc: u8,
+    pub separate_colour_plane_flag: bool,
+    pub bit_depth_luma_minus8: u8,
+    pub bit_depth_chroma_minus8: u8,
+    pub qpprime_y_zero_transform_bypass_flag: bool,
+    pub seq_scaling_matrix_present_flag: bool,
+    pub scaling_lists: ScalingLists,
+    pub log2_max_frame_num_minus4: u8,
+    pub pic_order_cnt_type: u8,
+    pub log2_max_pic_order_cnt_lsb_minus4: u8,
+    pub delta_pic_order_always_zero_flag: bool,
+    pub offset_for_non_ref_pic: i32,
+    pub offset_for_top_to_bottom_field: i32,
+    /// `offset_for_ref_frame`, `num_ref_frames_in_pic_order_cnt_cycle`
+    /// entries long.
+    pub offset_for_ref_frame: Vec<i32>,
+    pub max_num_ref_frames: u8,
+    pub gaps_in_frame_num_value_allowed_flag: bool,
+    pub pic_width_in_mbs_minus1: u32,
+    pub pic_height_in_map_units_minus1: u32,
+    pub frame_mbs_only_flag: bool,
+    pub mb_adaptive_frame_field_flag: bool,
+    pub direct_8x8_inference_flag: bool,
+    pub frame_cropping: Option<FrameCropping>,
+    pub vui: Option<Vui>,
+}
+
+/// Profiles whose SPS carries `chroma_format_idc` and the fields after it.
+fn has_chroma_info(profile_idc: u8) -> bool {
+    matches!(
+        profile_idc,
+        100 | 110 | 122 | 244 | 44 | 83 | 86 | 118 | 128 | 138 | 139 | 134 | 135
+    )
+}
+
+// Limits keeping derived sizes well inside u32; no level allows more.
+const MAX_WIDTH_IN_MBS: u32 = 1024;
+const MAX_HEIGHT_IN_MAP_UNITS: u32 = 1024;
+
+impl Sps {
+    /// Parses a `seq_parameter_set_rbsp()`, without the NAL header.
+    pub fn parse(rbsp: &[u8]) -> Result<Self> {
+        let mut r = BitReader::new(rbsp);
+        Self::parse_data(&mut r)
+    }
+
+    /// `seq_parameter_set_data()`.
+    pub(crate) fn parse_data(r: &mut BitReader) -> Result<Self> {
+        let profile_idc = r.read_bits(8)? as u8;
+        let constraint_flags = r.read_bits(8)?;
+        let level_idc = r.read_bits(8)? as u8;
+        let seq_parameter_set_id = r.read_ue_max("seq_parameter_set_id", 31)? as u8;
+
+        let mut chroma_format_idc = 1;
+        let mut separate_colour_plane_flag = false;
+        let mut bit_depth_luma_minus8 = 0;
+        let mut bit_depth_chroma_minus8 = 0;
+        let mut qpprime_y_zero_transform_bypass_flag = false;
+        let mut seq_scaling_matrix_present_flag = false;
+        let mut scaling_lists = ScalingLists::default();
+        if has_chroma_info(profile_idc) {
+            chroma_format_idc = r.read_ue_max("chroma_format_idc", 3)? as u8;
+            if chroma_format_idc == 3 {
+                separate_colour_plane_flag = r.read_bit()?;
+            }
+            bit_depth_luma_minus8 = r.read_ue_max("bit_depth_luma_minus8", 6)? as u8;
+            bit_depth_chroma_minus8 = r.read_ue_max("bit_depth_chroma_minus8", 6)? as u8;
+            qpprime_y_zero_transform_bypass_flag = r.read_bit()?;
+            seq_scaling_matrix_present_flag = r.read_bit()?;
+            if seq_scaling_matrix_present_flag {
+                let count = if chroma_format_idc == 3 { 12 } else { 8 };
+                scaling_lists = ScalingLists::parse(r, count, None)?;
+            }
+        }
+
+        let log2_max_frame_num_minus4 = r.read_ue_max("log2_max_frame_num_minus4", 12)? as u8;
+        let pic_order_cnt_type = r.read_ue_max("pic_order_cnt_type", 2)? as u8;
+        let mut log2_max_pic_order_cnt_lsb_minus4 = 0;
+        let mut delta_pic_order_always_zero_flag = false;
+        let mut offset_for_non_ref_pic = 0;
+        let mut offset_for_top_to_bottom_field = 0;
+        let mut offset_for_ref_frame = Vec::new();
+        match pic_order_cnt_type {
+            0 => {
+                log2_max_pic_order_cnt_lsb_minus4 =
+                    r.read_ue_max("log2_max_pic_order_cnt_lsb_minus4", 12)? as u8;
+            }
+            1 => {
+                const MAX: i32 = i32::MAX;
+                delta_pic_order_always_zero_flag = r.read_bit()?;
+                offset_for_non_ref_pic = r.read_se_range("offset_for_non_ref_pic", -MAX, MAX)?;
+                offset_for_top_to_bottom_field =
+                    r.read_se_range("offset_for_top_to_bottom_field", -MAX, MAX)?;
+                let count = r.read_ue_max("num_ref_frames_in_pic_order_cnt_cycle", 255)?;
+                offset_for_ref_frame = (0..count)
+                    .map(|_| r.read_se_range("offset_for_ref_frame", -MAX, MAX))
+                    .collect::<bits::Result<_>>()?;
+            }
+            _ => {}
+        }
+
+        let max_num_ref_frames = r.read_ue_max("max_num_ref_frames", 16)? as u8;
+        let gaps_in_frame_num_value_allowed_flag = r.read_bit()?;
+        let pic_width_in_mbs_minus1 =
+            r.read_ue_max("pic_width_in_mbs_minus1", MAX_WIDTH_IN_MBS - 1)?;
+        let pic_height_in_map_units_minus1 = r.read_ue_max(
+            "pic_height_in_map_units_minus1",
+            MAX_HEIGHT_IN_MAP_UNITS - 1,
+        )?;
+        let frame_mbs_only_flag = r.read_bit()?;
+        let mb_adaptive_frame_field_flag = !frame_mbs_only_flag && r.read_bit()?;
+        let direct_8x8_inference_flag = r.read_bit()?;
+        if !frame_mbs_only_flag && !direct_8x8_inference_flag {
+            return Err(Error::InvalidValue {
+                name: "direct_8x8_inference_flag",
+                value: 0,
+            });
+        }
+        let frame_cropping = if r.read_bit()? {
+            Some(FrameCropping {
+                left_offset: r.read_ue()?,
+                right_offset: r.read_ue()?,
+                top_offset: r.read_ue()?,
+                bottom_offset: r.read_ue()?,
+            })
+        } else {
+            None
+        };
+
+        let flag = |bit: u32| constraint_flags & (0x80 >> bit) != 0;
+        let mut sps = Self {
+            profile_idc,
+            constraint_set0_flag: flag(0),
+            constraint_set1_flag: flag(1),
+            constraint_set2_flag: flag(2),
+            constraint_set3_flag: flag(3),
+            constraint_set4_flag: flag(4),
+            constraint_set5_flag: flag(5),
+            level_idc,
+            seq_parameter_set_id,
+            chroma_format_idc,
+            separate_colour_plane_flag,
+            bit_depth_luma_minus8,
+            bit_depth_chroma_minus8,
+            qpprime_y_zero_transform_bypass_flag,
+            seq_scaling_matrix_present_flag,
+            scaling_lists,
+            log2_max_frame_num_minus4,
+            pic_order_cnt_type,
+            log2_max_pic_order_cnt_lsb_minus4,
+            delta_pic_order_always_zero_flag,
+            offset_for_non_ref_pic,
+            offset_for_top_to_bottom_field,
+            offset_for_ref_frame,
+            max_num_ref_frames,
+            gaps_in_frame_num_value_allowed_flag,
+            pic_width_in_mbs_minus1,
+            pic_height_in_map_units_minus1,
+            frame_mbs_only_flag,
+            mb_adaptive_frame_field_flag,
+            direct_8x8_inference_flag,
+            frame_cropping,
+            vui: None,
+        };
+        // Rejects offsets cropping away the whole picture.
+        sps.visible_rect()?;
+        if r.read_bit()? {
+            sps.vui = Some(Vui::parse(r, sps.level_max_dpb_frames())?);
+        }
+        Ok(sps)
+    }
+
+    /// `ChromaArrayType`.
+    pub fn chroma_array_type(&self) -> u8 {
+        if self.separate_colour_plane_flag {
+            0
+        } else {
+            self.chroma_format_idc
+        }
+    }
+
+    /// `SubWidthC` and `SubHeightC`, or `None` for monochrome and separate
+    /// colour planes.
+    pub fn chroma_subsampling(&self) -> Option<(u32, u32)> {
+        match self.chroma_array_type() {
+            1 => Some((2, 2)),
+            2 => Some((2, 1)),
+            3 => Some((1, 1)),
+            _ => None,
+        }
+    }
+
+    pub fn bit_depth_luma(&self) -> u32 {
+        self.bit_depth_luma_minus8 as u32 + 8
+    }
+
+    pub fn bit_depth_chroma(&self) -> u32 {
+        self.bit_depth_chroma_minus8 as u32 + 8
+    }
+
+    /// `MaxFrameNum`.
+    pub fn max_frame_num(&self) -> u32 {
+        1 << (self.log2_max_frame_num_minus4 + 4)
+    }
+
+    /// `MaxPicOrderCntLsb`.
+    pub fn max_pic_order_cnt_lsb(&self) -> u32 {
+        1 << (self.log2_max_pic_order_cnt_lsb_minus4 + 4)
+    }
+
+    /// `PicWidthInMbs`.
+    pub fn pic_width_in_mbs(&self) -> u32 {
+        self.pic_width_in_mbs_minus1 + 1
+    }
+
+    /// `FrameHeightInMbs`.
+    pub fn frame_height_in_mbs(&self) -> u32 {
+        (2 - self.frame_mbs_only_flag as u32) * (self.pic_height_in_map_units_minus1 + 1)
+    }
+
+    /// Width of the decoded frame in luma samples, before cropping.
+    pub fn width(&self) -> u32 {
+        self.pic_width_in_mbs() * 16
+    }
+
+    /// Height of the decoded frame in luma samples, before cropping.
+    pub fn height(&self) -> u32 {
+        self.frame_height_in_mbs() * 16
+    }
+
+    /// The part of the decoded frame to display.
+    pub fn visible_rect(&self) -> Result<Rect> {
+        let Some(crop) = self.frame_cropping else {
+            return Ok(Rect {
+                x: 0,
+                y: 0,
+                width: self.width(),
+                height: self.height(),
+            });
+        };
+        let (unit_x, unit_y) = self.chroma_subsampling().unwrap_or((1, 1));
+        let unit_y = unit_y * (2 - self.frame_mbs_only_flag as u32);
+        let left = crop.left_offset.saturating_mul(unit_x);
+        let top = crop.top_offset.saturating_mul(unit_y);
+        let horizontal = left.saturating_add(crop.right_offset.saturating_mul(unit_x));
+        let vertical = top.saturating_add(crop.bottom_offset.saturating_mul(unit_y));
+        if horizontal >= self.width() {
+            return Err(Error::InvalidValue {
+                name: "frame_crop_right_offset",
+                value: crop.right_offset as i64,
+            });
+        }
+        if vertical >= self.height() {
+            return Err(Error::InvalidValue {
+                name: "frame_crop_bottom_offset",
+                value: crop.bottom_offset as i64,
+            });
+        }
+        Ok(Rect {
+            x: left,
+            y: top,
+            width: self.width() - horizontal,
+            height: self.height() - vertical,
+        })
+    }
+
+    /// `MaxDpbFrames` for the level, from `MaxDpbMbs` in table A-1.
+    pub fn level_max_dpb_frames(&self) -> u32 {
+        let level_1b = self.level_idc == 9
+            || (self.level_idc == 11
+                && self.constraint_set3_flag
+                && matches!(self.profile_idc, 66 | 77 | 88));
+        let max_dpb_mbs = match self.level_idc {
+            _ if level_1b => 396,
+            10 => 396,
+            11 => 900,
+            12 | 13 | 20 => 2376,
+            21 => 4752,
+            22 | 30 => 8100,
+            31 => 18000,
+            32 => 20480,
+            40 | 41 => 32768,
+            42 => 34816,
+            50 => 110400,
+            51 | 52 => 184320,
+            _ => 696320,
+        };
+        let frame_mbs = self.pic_width_in_mbs() * self.frame_height_in_mbs();
+        (max_dpb_mbs / frame_mbs).clamp(1, 16)
+    }
+
+    /// Frames the decoded picture buffer must hold.
+    pub fn max_dpb_frames(&self) -> u32 {
+        let frames = match &self.vui {
+            Some(vui) if vui.bitstream_restriction_flag => vui.max_dec_frame_buffering as u32,
+            _ => self.level_max_dpb_frames(),
+        };
+        frames.max(self.max_num_ref_frames as u32).max(1)
+    }
+
+    /// `max_num_reorder_frames`, inferred as in E.2.1 when the VUI does not
+    /// carry it.
+    pub fn max_num_reorder_frames(&self) -> u32 {
+        match &self.vui {
+            Some(vui) if vui.bitstream_restriction_flag => vui.max_num_reorder_frames as u32,
+            _ if self.constraint_set3_flag
+                && matches!(self.profile_idc, 44 | 86 | 100 | 110 | 122 | 244) =>
+            {
+                0
+            }
+            _ => self.max_dpb_frames(),
+        }
+    }
+}
+
+/// `seq_parameter_set_mvc_extension()`, for the non-base views of an MVC
+/// stream.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct SpsMvcExtension {
+    /// `view_id` of each view, in view order; `num_views_minus1 + 1` long.
+    pub view_ids: Vec<u16>,
+    /// Per view in view order; empty for the base view.
+    pub anchor_refs_l0: Vec<Vec<u16>>,
+    pub anchor_refs_l1: Vec<Vec<u16>>,
+    pub non_anchor_refs_l0: Vec<Vec<u16>>,
+    pub non_anchor_refs_l1: Vec<Vec<u16>>,
+    pub level_values: Vec<MvcLevelValue>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct MvcLevelValue {
+    pub level_idc: u8,
+    pub applicable_ops: Vec<MvcOperationPoint>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct MvcOperationPoint {
+    pub temporal_id: u8,
+    pub target_view_ids: Vec<u16>,
+    pub num_views_minus1: u16,
+}
+
+/// `mvc_vui_parameters_extension()`, one entry per operation point.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct MvcVuiOperationPoint {
+    pub temporal_id: u8,
+    pub target_output_view_ids: Vec<u16>,
+    pub timing_info_present_flag: bool,
+    pub num_units_in_tick: u32,
+    pub time_scale: u32,
+    pub fixed_frame_rate_flag: bool,
+    pub nal_hrd: Option<Hrd>,
+    pub vcl_hrd: Option<Hrd>,
+    pub low_delay_hrd_flag: bool,
+    pub pic_struct_present_flag: bool,
+}
+
+/// Subset sequence parameter set, `subset_seq_parameter_set_rbsp()`. Only
+/// MVC subset SPSs are supported.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubsetSps {
+    pub sps: Sps,
+    pub mvc: SpsMvcExtension,
+    pub mvc_vui: Option<Vec<MvcVuiOperationPoint>>,
+}
+
+const MAX_VIEWS: u32 = 1024;
+
+fn read_view_ids(r: &mut BitReader, name: &'static str, max: u32) -> Result<Vec<u16>> {
+    let count = r.read_ue_max(name, max)?;
+    (0..count)
+        .map(|_| Ok(r.read_ue_max("view_id", MAX_VIEWS - 1)? as u16))
+        .collect()
+}
+
+impl SubsetSps {
+    pub fn parse(rbsp: &[u8]) -> Result<Self> {
+        let mut r = BitReader::new(rbsp);
+        let sps = Sps::parse_data(&mut r)?;
+        match sps.profile_idc {
+            118 | 128 | 134 => {}
+            83 | 86 => return Err(Error::Unsupported("SVC subset SPS")),
+            _ => return Err(Error::Unsupported("MVCD and 3D-AVC subset SPS")),
+        }
+        if !r.read_bit()? {
+            return Err(Error::InvalidValue {
+                name: "bit_equal_to_one",
+                value: 0,
+            });
+        }
+        let mvc = Self::parse_mvc_extension(&mut r)?;
+        let mvc_vui = if r.read_bit()? {
+            Some(Self::parse_mvc_vui(&mut r)?)
+        } else {
+            None
+        };
+        Ok(Self { sps, mvc, mvc_vui })
+    }
+
+    fn parse_mvc_extension(r: &mut BitReader) -> Result<SpsMvcExtension> {
+        let num_views = r.read_ue_max("num_views_minus1", MAX_VIEWS - 1)? + 1;
+        let mut mvc = SpsMvcExtension {
+            view_ids: (0..num_views)
+                .map(|_| Ok(r.read_ue_max("view_id", MAX_VIEWS - 1)? as u16))
+                .collect::<Result<_>>()?,
+            anchor_refs_l0: vec![Vec::new()],
+            anchor_refs_l1: vec![Vec::new()],
+            non_anchor_refs_l0: vec![Vec::new()],
+            non_anchor_refs_l1: vec![Vec::new()],
+            level_values: Vec::new(),
+        };
+        for _ in 1..num_views {
+            mvc.anchor_refs_l0
+                .push(read_view_ids(r, "num_anchor_refs_l0", 15)?);
+            mvc.anchor_refs_l1
+                .push(read_view_ids(r, "num_anchor_refs_l1", 15)?);
+        }
+        for _ in 1..num_views {
+            mvc.non_anchor_refs_l0
+                .push(read_view_ids(r, "num_non_anchor_refs_l0", 15)?);
+            mvc.non_anchor_refs_l1
+                .push(read_view_ids(r, "num_non_anchor_refs_l1", 15)?);
+        }
+        let num_level_values = r.read_ue_max("num_level_values_signalled_minus1", 63)? + 1;
+        for _ in 0..num_level_values {
+            let level_idc = r.read_bits(8)? as u8;
+            let num_ops = r.read_ue_max("num_applicable_ops_minus1", 1023)? + 1;
+            let applicable_ops = (0..num_ops)
+                .map(|_| {
+                    let temporal_id = r.read_bits(3)? as u8;
+                    let num_targets =
+                        r.read_ue_max("applicable_op_num_target_views_minus1", MAX_VIEWS - 1)? + 1;
+                    let target_view_ids = (0..num_targets)
+                        .map(|_| {
+                            Ok(
+                                r.read_ue_max("applicable_op_target_view_id", MAX_VIEWS - 1)?
+                                    as u16,
+                            )
+                        })
+                        .collect::<Result<_>>()?;
+                    let num_views_minus1 =
+                        r.read_ue_max("applicable_op_num_views_minus1", MAX_VIEWS - 1)? as u16;
+                    Ok(MvcOperationPoint {
+                        temporal_id,
+                        target_view_ids,
+                        num_views_minus1,
+                    })
+                })
+                .collect::<Result<_>>()?;
+            mvc.level_values.push(MvcLevelValue {
+                level_idc,
+                applicable_ops,
+            });
+        }
+        Ok(mvc)
+    }
+
+    fn parse_mvc_vui(r: &mut BitReader) -> Result<Vec<MvcVuiOperationPoint>> {
+        let num_ops = r.read_ue_max("vui_mvc_num_ops_minus1", 1023)? + 1;
+        (0..num_ops)
+            .map(|_| {
+                let temporal_id = r.read_bits(3)? as u8;
+                let num_views =
+                    r.read_ue_max("vui_mvc_num_target_output_views_minus1", MAX_VIEWS - 1)? + 1;
+                let mut op = MvcVuiOperationPoint {
+                    temporal_id,
+                    target_output_view_ids: (0..num_views)
+                        .map(|_| Ok(r.read_ue_max("vui_mvc_view_id", MAX_VIEWS - 1)? as u16))
+                        .collect::<Result<_>>()?,
+                    timing_info_present_flag: r.read_bit()?,
+                    ..Default::default()
+                };
+                if op.timing_info_present_flag {
+                    op.num_units_in_tick = r.read_bits(32)?;
+                    op.time_scale = r.read_bits(32)?;
+                    op.fixed_frame_rate_flag = r.read_bit()?;
+                }
+                if r.read_bit()? {
+                    op.nal_hrd = Some(Hrd::parse(r)?);
+                }
+                if r.read_bit()? {
+                    op.vcl_hrd = Some(Hrd::parse(r)?);
+                }
+                if op.nal_hrd.is_some() || op.vcl_hrd.is_some() {
+                    op.low_delay_hrd_flag = r.read_bit()?;
+                }
+                op.pic_struct_present_flag = r.read_bit()?;
+                Ok(op)
+            })
+            .collect()
+    }
+}
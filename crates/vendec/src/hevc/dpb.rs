@@ -0,0 +1,666 @@
+use std::collections::VecDeque;
+
+use super::{Error, Result, SliceSegmentHeader, Sps};
+
+/// How a picture is marked by the reference picture set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reference {
+    Unused,
+    ShortTerm,
+    LongTerm,
+}
+
+/// A decoded picture of the DPB.
+#[derive(Debug, Clone)]
+pub struct DpbPicture<T> {
+    /// What the picture was decoded into.
+    pub handle: T,
+    pub poc: i32,
+    pub reference: Reference,
+    pub needed_for_output: bool,
+    /// `PicLatencyCount`.
+    pub pic_latency_count: u32,
+    /// Generated for a missing reference (8.3.3) rather than decoded.
+    pub generated: bool,
+}
+
+impl<T> DpbPicture<T> {
+    pub fn is_reference(&self) -> bool {
+        self.reference != Reference::Unused
+    }
+}
+
+/// The reference picture set of the current picture (8.3.2), as indices
+/// into [`Dpb::pictures`] in the order of each list. Pictures of the `Foll`
+/// lists that are not in the DPB are left out.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RefPicSet {
+    pub st_curr_before: Vec<usize>,
+    pub st_curr_after: Vec<usize>,
+    pub st_foll: Vec<usize>,
+    pub lt_curr: Vec<usize>,
+    pub lt_foll: Vec<usize>,
+}
+
+/// The order counts of the reference picture set, which identify its
+/// pictures while others are removed from the DPB.
+#[derive(Debug, Clone, Default)]
+struct RpsPocs {
+    st_curr_before: Vec<i32>,
+    st_curr_after: Vec<i32>,
+    st_foll: Vec<i32>,
+    lt_curr: Vec<i32>,
+    lt_foll: Vec<i32>,
+}
+
+/// What the DPB keeps about the picture being decoded.
+#[derive(Debug, Clone)]
+struct CurrentPicture {
+    poc: i32,
+    pic_output_flag: bool,
+    rps: RefPicSet,
+}
+
+/// The decoded picture buffer: reference picture set decoding (8.3.2),
+/// generation of missing references (8.3.3), reference picture list
+/// construction (8.3.4) and output in display order by the bumping process
+/// of C.5.2.
+///
+/// `T` is what pictures are decoded into, typically a surface, and is
+/// cloned into the output queue.
+///
+/// For each picture, call [`Dpb::start_picture`] with its first slice
+/// segment, [`Dpb::ref_pic_lists`] for each of its slices, and
+/// [`Dpb::finish_picture`] once it has been decoded. Pictures in display
+/// order come out of [`Dpb::pop_output`].
+#[derive(Debug)]
+pub struct Dpb<T> {
+    pictures: Vec<DpbPicture<T>>,
+    max_dec_pic_buffering: usize,
+    max_num_reorder_pics: usize,
+    max_latency_pictures: Option<u32>,
+    current: Option<CurrentPicture>,
+    output: VecDeque<T>,
+}
+
+impl<T: Clone> Default for Dpb<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Clone> Dpb<T> {
+    pub fn new() -> Self {
+        Self {
+            pictures: Vec::new(),
+            max_dec_pic_buffering: 1,
+            max_num_reorder_pics: 0,
+            max_latency_pictures: None,
+            current: None,
+            output: VecDeque::new(),
+        }
+    }
+
+    pub fn pictures(&self) -> &[DpbPicture<T>] {
+        &self.pictures
+    }
+
+    /// The reference picture set of the current picture.
+    pub fn rps(&self) -> &RefPicSet {
+        &self.current().rps
+    }
+
+    /// Takes the next picture in display order.
+    pub fn pop_output(&mut self) -> Option<T> {
+        self.output.pop_front()
+    }
+
+    /// Outputs every picture still waiting and empties the DPB, as at the
+    /// end of the stream.
+    pub fn flush(&mut self) {
+        while self.bump() {}
+        self.clear();
+    }
+
+    /// Empties the DPB without outputting anything more. Pictures already
+    /// in the output queue stay there.
+    pub fn clear(&mut self) {
+        self.pictures.clear();
+        self.current = None;
+    }
+
+    /// Begins the picture `header` is the first slice segment of: marks
+    /// the reference pictures by its RPS and makes room for it (C.5.2.2).
+    /// `no_rasl_output_flag` is `NoRaslOutputFlag` of IRAP pictures.
+    /// Missing references of the current picture are decoded into
+    /// `missing()`.
+    pub fn start_picture(
+        &mut self,
+        sps: &Sps,
+        header: &SliceSegmentHeader,
+        poc: i32,
+        no_rasl_output_flag: bool,
+        mut missing: impl FnMut() -> T,
+    ) -> Result<()> {
+        let ordering = sps.highest_sub_layer_ordering();
+        self.max_dec_pic_buffering = ordering.max_dec_pic_buffering_minus1 as usize + 1;
+        self.max_num_reorder_pics = ordering.max_num_reorder_pics as usize;
+        self.max_latency_pictures = sps.max_latency_pictures();
+        self.current = None;
+
+        let nal_unit_type = header.nal_unit_type;
+        let mut pocs = RpsPocs::default();
+        if nal_unit_type.is_irap() && no_rasl_output_flag {
+            // Nothing before the picture is referenced. Whether what has not
+            // been output yet still is depends on no_output_of_prior_pics_flag,
+            // which CRA pictures cannot set.
+            let no_output = nal_unit_type.is_cra() || header.no_output_of_prior_pics_flag;
+            for picture in &mut self.pictures {
+                picture.reference = Reference::Unused;
+            }
+            if !no_output {
+                while self.bump() {}
+            }
+            self.pictures.clear();
+        } else {
+            if nal_unit_type.is_idr() {
+                for picture in &mut self.pictures {
+                    picture.reference = Reference::Unused;
+                }
+            } else {
+                pocs = self.mark(sps, header, poc, &mut missing);
+            }
+            self.remove_unused();
+            while self.needs_bumping() || self.pictures.len() >= self.max_dec_pic_buffering {
+                if !self.bump() {
+                    if self.pictures.len() >= self.max_dec_pic_buffering {
+                        return Err(Error::DpbFull);
+                    }
+                    break;
+                }
+            }
+        }
+
+        let find = |pocs: &[i32]| -> Vec<usize> {
+            pocs.iter()
+                .filter_map(|&poc| {
+                    self.pictures
+                        .iter()
+                        .position(|picture| picture.is_reference() && picture.poc == poc)
+                })
+                .collect()
+        };
+        let rps = RefPicSet {
+            st_curr_before: find(&pocs.st_curr_before),
+            st_curr_after: find(&pocs.st_curr_after),
+            st_foll: find(&pocs.st_foll),
+            lt_curr: find(&pocs.lt_curr),
+            lt_foll: find(&pocs.lt_foll),
+        };
+        self.current = Some(CurrentPicture {
+            poc,
+            pic_output_flag: header.pic_output_flag,
+            rps,
+        });
+        Ok(())
+    }
+
+    /// Marks the pictures of the DPB by the RPS of `header` (8.3.2),
+    /// generating the missing ones the current picture references (8.3.3),
+    /// and returns the order counts of the pictures in each list.
+    fn mark(
+        &mut self,
+        sps: &Sps,
+        header: &SliceSegmentHeader,
+        poc: i32,
+        missing: &mut impl FnMut() -> T,
+    ) -> RpsPocs {
+        let max_lsb = sps.max_pic_order_cnt_lsb() as i32;
+        let mut in_rps = vec![false; self.pictures.len()];
+        let mut pocs = RpsPocs::default();
+
+        // Long-term pictures first, as they may have been short-term ones.
+        for pic in &header.long_term_pics {
+            let mut poc_lt = pic.poc_lsb_lt as i32;
+            if pic.delta_poc_msb_present_flag {
+                poc_lt = poc_lt
+                    .wrapping_add(poc)
+                    .wrapping_sub((pic.delta_poc_msb_cycle_lt as i32).wrapping_mul(max_lsb))
+                    .wrapping_sub(poc & (max_lsb - 1));
+            }
+            let found = self.pictures.iter().position(|picture| {
+                let picture_poc = if pic.delta_poc_msb_present_flag {
+                    picture.poc
+                } else {
+                    picture.poc & (max_lsb - 1)
+                };
+                picture.is_reference() && picture_poc == poc_lt
+            });
+            let list = if pic.used_by_curr_pic_lt {
+                &mut pocs.lt_curr
+            } else {
+                &mut pocs.lt_foll
+            };
+            match found {
+                Some(index) => {
+                    in_rps[index] = true;
+                    self.pictures[index].reference = Reference::LongTerm;
+                    list.push(self.pictures[index].poc);
+                }
+                None if pic.used_by_curr_pic_lt => {
+                    self.pictures
+                        .push(generated(missing(), poc_lt, Reference::LongTerm));
+                    in_rps.push(true);
+                    list.push(poc_lt);
+                }
+                None => {}
+            }
+        }
+
+        let rps = &header.short_term_ref_pic_set;
+        let short_term = rps
+            .delta_poc_s0
+            .iter()
+            .zip(&rps.used_by_curr_pic_s0)
+            .map(|(&delta, &used)| (delta, used, true))
+            .chain(
+                rps.delta_poc_s1
+                    .iter()
+                    .zip(&rps.used_by_curr_pic_s1)
+                    .map(|(&delta, &used)| (delta, used, false)),
+            );
+        for (delta, used, before) in short_term {
+            let poc_st = poc.wrapping_add(delta);
+            let list = match (used, before) {
+                (true, true) => &mut pocs.st_curr_before,
+                (true, false) => &mut pocs.st_curr_after,
+                (false, _) => &mut pocs.st_foll,
+            };
+            let found = self.pictures.iter().position(|picture| {
+                picture.reference == Reference::ShortTerm && picture.poc == poc_st
+            });
+            match found {
+                Some(index) => {
+                    in_rps[index] = true;
+                    list.push(poc_st);
+                }
+                None if used => {
+                    self.pictures
+                        .push(generated(missing(), poc_st, Reference::ShortTerm));
+                    in_rps.push(true);
+                    list.push(poc_st);
+                }
+                None => {}
+            }
+        }
+
+        for (picture, in_rps) in self.pictures.iter_mut().zip(in_rps) {
+            if !in_rps {
+                picture.reference = Reference::Unused;
+            }
+        }
+        pocs
+    }
+
+    fn current(&self) -> &CurrentPicture {
+        self.current
+            .as_ref()
+            .expect("Dpb::start_picture has not been called")
+    }
+
+    /// `RefPicList0` and `RefPicList1` of a slice of the current picture
+    /// (8.3.4), as indices into [`Dpb::pictures`].
+    pub fn ref_pic_lists(&self, header: &SliceSegmentHeader) -> Result<(Vec<usize>, Vec<usize>)> {
+        if header.slice_type.is_intra() {
+            return Ok((Vec::new(), Vec::new()));
+        }
+        let rps = &self.current().rps;
+        let build =
+            |first: &[usize], second: &[usize], count_minus1: u8, entries: &Option<Vec<u8>>| {
+                let candidates: Vec<usize> = first
+                    .iter()
+                    .chain(second)
+                    .chain(&rps.lt_curr)
+                    .copied()
+                    .collect();
+                if candidates.is_empty() {
+                    return Err(Error::InvalidValue {
+                        name: "NumPicTotalCurr",
+                        value: 0,
+                    });
+                }
+                let count = count_minus1 as usize + 1;
+                let temp: Vec<usize> = candidates
+                    .iter()
+                    .cycle()
+                    .take(count.max(candidates.len()))
+                    .copied()
+                    .collect();
+                (0..count)
+                    .map(|i| {
+                        let entry = entries.as_ref().map_or(i, |entries| entries[i] as usize);
+                        temp.get(entry).copied().ok_or(Error::InvalidValue {
+                            name: "list_entry",
+                            value: entry as i64,
+                        })
+                    })
+                    .collect::<Result<Vec<_>>>()
+            };
+        let list0 = build(
+            &rps.st_curr_before,
+            &rps.st_curr_after,
+            header.num_ref_idx_l0_active_minus1,
+            &header.list_entry_l0,
+        )?;
+        let list1 = if header.slice_type.is_b() {
+            build(
+                &rps.st_curr_after,
+                &rps.st_curr_before,
+                header.num_ref_idx_l1_active_minus1,
+                &header.list_entry_l1,
+            )?
+        } else {
+            Vec::new()
+        };
+        Ok((list0, list1))
+    }
+
+    /// Stores the current picture as a short-term reference (C.5.2.3),
+    /// outputting pictures as needed. `handle` is what it was decoded into.
+    pub fn finish_picture(&mut self, handle: T) -> Result<()> {
+        let current = self
+            .current
+            .take()
+            .expect("Dpb::start_picture has not been called");
+        if current.pic_output_flag {
+            for picture in &mut self.pictures {
+                if picture.needed_for_output {
+                    picture.pic_latency_count = picture.pic_latency_count.saturating_add(1);
+                }
+            }
+        }
+        self.pictures.push(DpbPicture {
+            handle,
+            poc: current.poc,
+            reference: Reference::ShortTerm,
+            needed_for_output: current.pic_output_flag,
+            pic_latency_count: 0,
+            generated: false,
+        });
+        while self.needs_bumping() {
+            self.bump();
+        }
+        Ok(())
+    }
+
+    fn remove_unused(&mut self) {
+        self.pictures
+            .retain(|picture| picture.needed_for_output || picture.is_reference());
+    }
+
+    /// Whether more pictures are waiting than may precede another in
+    /// decoding order but follow it in display order, or one has waited
+    /// longer than allowed.
+    fn needs_bumping(&self) -> bool {
+        let waiting = self.pictures.iter().filter(|p| p.needed_for_output);
+        let count = waiting.clone().count();
+        count > self.max_num_reorder_pics
+            || self.max_latency_pictures.is_some_and(|max| {
+                waiting
+                    .clone()
+                    .any(|picture| picture.pic_latency_count >= max)
+            })
+    }
+
+    /// C.5.2.4: outputs the waiting picture that comes first, and drops it
+    /// if it is not a reference. Returns whether there was one.
+    fn bump(&mut self) -> bool {
+        let next = (0..self.pictures.len())
+            .filter(|&i| self.pictures[i].needed_for_output)
+            .min_by_key(|&i| self.pictures[i].poc);
+        let Some(index) = next else {
+            return false;
+        };
+        let picture = &mut self.pictures[index];
+        picture.needed_for_output = false;
+        self.output.push_back(picture.handle.clone());
+        if !picture.is_reference() {
+            self.pictures.remove(index);
+        }
+        true
+    }
+}
+
+fn generated<T>(handle: T, poc: i32, reference: Reference) -> DpbPicture<T> {
+    DpbPicture {
+        handle,
+        poc,
+        reference,
+        needed_for_output: false,
+        pic_latency_count: 0,
+        generated: true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hevc::slice::tests::{header, sps};
+    use crate::hevc::{LongTermPic, NalUnitType, PocState, ShortTermRps, SliceType};
+
+    const MISSING: i32 = 99;
+
+    /// Decodes pictures into their order count.
+    struct Decoder {
+        sps: Sps,
+        poc: PocState,
+        dpb: Dpb<i32>,
+    }
+
+    impl Decoder {
+        fn new(sps: Sps) -> Self {
+            Self {
+                sps,
+                poc: PocState::new(),
+                dpb: Dpb::new(),
+            }
+        }
+
+        /// Decodes a picture, returning its reference lists as handles.
+        fn decode(&mut self, header: &SliceSegmentHeader) -> Result<[Vec<i32>; 2]> {
+            let poc = self.poc.compute(&self.sps, header, true);
+            self.dpb
+                .start_picture(&self.sps, header, poc, true, || MISSING)?;
+            let (list0, list1) = self.dpb.ref_pic_lists(header)?;
+            let pictures = self.dpb.pictures();
+            let handles = |list: Vec<usize>| list.iter().map(|&i| pictures[i].handle).collect();
+            let lists = [handles(list0), handles(list1)];
+            self.dpb.finish_picture(poc)?;
+            Ok(lists)
+        }
+
+        fn output(&mut self) -> Vec<i32> {
+            std::iter::from_fn(|| self.dpb.pop_output()).collect()
+        }
+    }
+
+    fn idr() -> SliceSegmentHeader {
+        header(NalUnitType::IdrWRadl, SliceType::I, 0)
+    }
+
+    /// A picture referencing the ones at `before` and `after`, which are
+    /// used by it when positive, and the long-term pictures `long_term`.
+    fn picture(
+        slice_type: SliceType,
+        poc: u32,
+        before: &[i32],
+        after: &[i32],
+        long_term: &[u32],
+    ) -> SliceSegmentHeader {
+        let mut header = header(NalUnitType::TrailR, slice_type, poc);
+        header.short_term_ref_pic_set = ShortTermRps {
+            delta_poc_s0: before.iter().map(|&d| -d.abs()).collect(),
+            used_by_curr_pic_s0: before.iter().map(|&d| d > 0).collect(),
+            delta_poc_s1: after.iter().map(|&d| d.abs()).collect(),
+            used_by_curr_pic_s1: after.iter().map(|&d| d > 0).collect(),
+        };
+        header.long_term_pics = long_term
+            .iter()
+            .map(|&lsb| LongTermPic {
+                poc_lsb_lt: lsb,
+                used_by_curr_pic_lt: true,
+                ..Default::default()
+            })
+            .collect();
+        header
+    }
+
+    #[test]
+    fn short_term_references() {
+        let mut decoder = Decoder::new(sps());
+        decoder.decode(&idr()).unwrap();
+        let p = picture(SliceType::P, 8, &[8], &[], &[]);
+        assert_eq!(decoder.decode(&p).unwrap(), [vec![0], vec![]]);
+        let mut b = picture(SliceType::B, 4, &[4], &[4], &[]);
+        b.nal_unit_type = NalUnitType::TrailN;
+        b.num_ref_idx_l0_active_minus1 = 2;
+        b.num_ref_idx_l1_active_minus1 = 1;
+        // Short lists repeat.
+        assert_eq!(decoder.decode(&b).unwrap(), [vec![0, 8, 0], vec![8, 0]]);
+
+        // Picture 0 stays a reference while only later ones might use it.
+        let p = picture(SliceType::P, 16, &[8, -16], &[], &[]);
+        assert_eq!(decoder.decode(&p).unwrap()[0], [8]);
+        assert!(decoder
+            .dpb
+            .pictures()
+            .iter()
+            .any(|p| p.handle == 0 && p.is_reference()));
+        let mut p = picture(SliceType::P, 24, &[8, 16], &[], &[]);
+        decoder
+            .dpb
+            .start_picture(&decoder.sps, &p, 24, false, || MISSING)
+            .unwrap();
+        let rps = decoder.dpb.rps();
+        let handles = |list: &[usize]| -> Vec<i32> {
+            list.iter()
+                .map(|&i| decoder.dpb.pictures()[i].handle)
+                .collect()
+        };
+        assert_eq!(handles(&rps.st_curr_before), [16, 8]);
+        assert!(rps.st_foll.is_empty());
+        assert!(decoder
+            .dpb
+            .pictures()
+            .iter()
+            .all(|p| p.handle != 0 || !p.is_reference()));
+        p.list_entry_l0 = Some(vec![1]);
+        let (list0, _) = decoder.dpb.ref_pic_lists(&p).unwrap();
+        assert_eq!(handles(&list0), [8]);
+        p.list_entry_l0 = Some(vec![2]);
+        assert!(decoder.dpb.ref_pic_lists(&p).is_err());
+        decoder.dpb.finish_picture(24).unwrap();
+
+        decoder.dpb.flush();
+        assert_eq!(decoder.output(), [0, 4, 8, 16, 24]);
+    }
+
+    #[test]
+    fn long_term_references() {
+        let mut decoder = Decoder::new(sps());
+        decoder.decode(&idr()).unwrap();
+        decoder
+            .decode(&picture(SliceType::P, 1, &[1], &[], &[]))
+            .unwrap();
+        // Picture 0 becomes long-term, identified by its LSBs.
+        let mut p = picture(SliceType::P, 2, &[1], &[], &[0]);
+        p.num_ref_idx_l0_active_minus1 = 1;
+        assert_eq!(decoder.decode(&p).unwrap()[0], [1, 0]);
+        let long_term: Vec<_> = decoder
+            .dpb
+            .pictures()
+            .iter()
+            .filter(|p| p.reference == Reference::LongTerm)
+            .map(|p| p.handle)
+            .collect();
+        assert_eq!(long_term, [0]);
+
+        // Once the LSBs wrap, the MSBs tell the pictures apart.
+        let mut p = picture(SliceType::P, 130, &[128], &[], &[0]);
+        p.num_ref_idx_l0_active_minus1 = 1;
+        assert_eq!(decoder.decode(&p).unwrap()[0], [2, 0]);
+        let mut p = picture(SliceType::P, 0, &[126], &[], &[0]);
+        p.long_term_pics[0].delta_poc_msb_present_flag = true;
+        p.long_term_pics[0].delta_poc_msb_cycle_lt = 1;
+        p.num_ref_idx_l0_active_minus1 = 1;
+        assert_eq!(decoder.decode(&p).unwrap()[0], [130, 0]);
+        decoder.dpb.flush();
+        assert_eq!(decoder.output(), [0, 1, 2, 130, 256]);
+    }
+
+    #[test]
+    fn generates_missing_references() {
+        let mut decoder = Decoder::new(sps());
+        decoder
+            .decode(&header(NalUnitType::Cra, SliceType::I, 0))
+            .unwrap();
+        let mut p = picture(SliceType::P, 8, &[4, 8, -6], &[], &[]);
+        p.num_ref_idx_l0_active_minus1 = 1;
+        assert_eq!(decoder.decode(&p).unwrap()[0], [MISSING, 0]);
+        decoder.dpb.flush();
+        assert_eq!(decoder.output(), [0, 8]);
+    }
+
+    #[test]
+    fn irap_pictures_end_output() {
+        for (no_output_of_prior_pics_flag, output) in [(false, vec![0, 8]), (true, vec![])] {
+            let mut decoder = Decoder::new(sps());
+            decoder.decode(&idr()).unwrap();
+            decoder
+                .decode(&picture(SliceType::P, 8, &[8], &[], &[]))
+                .unwrap();
+            assert_eq!(decoder.output(), []);
+            let mut idr = idr();
+            idr.no_output_of_prior_pics_flag = no_output_of_prior_pics_flag;
+            decoder.decode(&idr).unwrap();
+            assert_eq!(decoder.output(), output);
+            assert_eq!(decoder.dpb.pictures().len(), 1);
+        }
+    }
+
+    #[test]
+    fn limits_delay() {
+        let mut sps = sps();
+        sps.sub_layer_ordering[0].max_num_reorder_pics = 0;
+        let mut decoder = Decoder::new(sps.clone());
+        decoder.decode(&idr()).unwrap();
+        assert_eq!(decoder.output(), [0]);
+
+        // Two pictures may wait, but only while fewer than two follow.
+        sps.sub_layer_ordering[0].max_num_reorder_pics = 2;
+        sps.sub_layer_ordering[0].max_latency_increase_plus1 = 1;
+        let mut decoder = Decoder::new(sps.clone());
+        decoder.decode(&idr()).unwrap();
+        decoder
+            .decode(&picture(SliceType::P, 8, &[8], &[], &[]))
+            .unwrap();
+        assert_eq!(decoder.output(), []);
+        decoder
+            .decode(&picture(SliceType::P, 16, &[8], &[], &[]))
+            .unwrap();
+        assert_eq!(decoder.output(), [0]);
+
+        // References fill the DPB.
+        sps.sub_layer_ordering[0].max_dec_pic_buffering_minus1 = 1;
+        sps.sub_layer_ordering[0].max_num_reorder_pics = 0;
+        let mut decoder = Decoder::new(sps);
+        decoder.decode(&idr()).unwrap();
+        decoder
+            .decode(&picture(SliceType::P, 1, &[1], &[], &[]))
+            .unwrap();
+        assert_eq!(
+            decoder.decode(&picture(SliceType::P, 2, &[1, 2], &[], &[])),
+            Err(Error::DpbFull)
+        );
+    }
+}
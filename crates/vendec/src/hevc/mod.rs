@@ -0,0 +1,293 @@
+//! HEVC (ITU-T H.265 / ISO/IEC 23008-2) bitstream parsing.
+//!
+//! Like the [`crate::h264`] parsers, syntax structures keep the names of the
+//! specification's syntax elements. Only single-layer streams are handled;
+//! the multi-layer and 3D extensions are rejected or skipped.
+
+mod dpb;
+mod parameter_sets;
+mod poc;
+mod pps;
+mod ptl;
+mod rps;
+mod slice;
+mod sps;
+mod vps;
+
+pub use dpb::*;
+pub use parameter_sets::*;
+pub use poc::*;
+pub use pps::*;
+pub use ptl::*;
+pub use rps::*;
+pub use slice::*;
+pub use sps::*;
+pub use vps::*;
+
+use crate::bits::{self, BitReader};
+pub(crate) use crate::bits::{check, ReadExt};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    Bits(bits::Error),
+    /// A syntax element has a value the specification does not allow.
+    InvalidValue {
+        name: &'static str,
+        value: i64,
+    },
+    /// The stream uses a feature this parser does not handle.
+    Unsupported(&'static str),
+    MissingSps(u32),
+    MissingPps(u32),
+    /// A picture has to be stored but every picture buffer holds a
+    /// reference.
+    DpbFull,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::Bits(err) => write!(f, "{}", err),
+            Error::InvalidValue { name, value } => write!(f, "invalid {}: {}", name, value),
+            Error::Unsupported(what) => write!(f, "unsupported: {}", what),
+            Error::MissingSps(id) => write!(f, "SPS {} has not been received", id),
+            Error::MissingPps(id) => write!(f, "PPS {} has not been received", id),
+            Error::DpbFull => write!(f, "decoded picture buffer overflow"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Bits(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<bits::Error> for Error {
+    fn from(err: bits::Error) -> Self {
+        match err {
+            bits::Error::InvalidValue { name, value } => Error::InvalidValue { name, value },
+            err => Error::Bits(err),
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// `Ceil(Log2(n))`, the length of `u(v)` fields indexing `n` entries.
+pub(crate) fn ceil_log2(n: u32) -> u32 {
+    u32::BITS - n.saturating_sub(1).leading_zeros()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NalUnitType {
+    TrailN,
+    TrailR,
+    TsaN,
+    TsaR,
+    StsaN,
+    StsaR,
+    RadlN,
+    RadlR,
+    RaslN,
+    RaslR,
+    BlaWLp,
+    BlaWRadl,
+    BlaNLp,
+    IdrWRadl,
+    IdrNLp,
+    Cra,
+    Vps,
+    Sps,
+    Pps,
+    AccessUnitDelimiter,
+    EndOfSequence,
+    EndOfBitstream,
+    FillerData,
+    PrefixSei,
+    SuffixSei,
+    /// Reserved or unspecified.
+    Other(u8),
+}
+
+impl From<u8> for NalUnitType {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => NalUnitType::TrailN,
+            1 => NalUnitType::TrailR,
+            2 => NalUnitType::TsaN,
+            3 => NalUnitType::TsaR,
+            4 => NalUnitType::StsaN,
+            5 => NalUnitType::StsaR,
+            6 => NalUnitType::RadlN,
+            7 => NalUnitType::RadlR,
+            8 => NalUnitType::RaslN,
+            9 => NalUnitType::RaslR,
+            16 => NalUnitType::BlaWLp,
+            17 => NalUnitType::BlaWRadl,
+            18 => NalUnitType::BlaNLp,
+            19 => NalUnitType::IdrWRadl,
+            20 => NalUnitType::IdrNLp,
+            21 => NalUnitType::Cra,
+            32 => NalUnitType::Vps,
+            33 => NalUnitType::Sps,
+            34 => NalUnitType::Pps,
+            35 => NalUnitType::AccessUnitDelimiter,
+            36 => NalUnitType::EndOfSequence,
+            37 => NalUnitType::EndOfBitstream,
+            38 => NalUnitType::FillerData,
+            39 => NalUnitType::PrefixSei,
+            40 => NalUnitType::SuffixSei,
+            other => NalUnitType::Other(other),
+        }
+    }
+}
+
+impl NalUnitType {
+    /// The `nal_unit_type` value.
+    pub fn value(self) -> u8 {
+        match self {
+            NalUnitType::TrailN => 0,
+            NalUnitType::TrailR => 1,
+            NalUnitType::TsaN => 2,
+            NalUnitType::TsaR => 3,
+            NalUnitType::StsaN => 4,
+            NalUnitType::StsaR => 5,
+            NalUnitType::RadlN => 6,
+            NalUnitType::RadlR => 7,
+            NalUnitType::RaslN => 8,
+            NalUnitType::RaslR => 9,
+            NalUnitType::BlaWLp => 16,
+            NalUnitType::BlaWRadl => 17,
+            NalUnitType::BlaNLp => 18,
+            NalUnitType::IdrWRadl => 19,
+            NalUnitType::IdrNLp => 20,
+            NalUnitType::Cra => 21,
+            NalUnitType::Vps => 32,
+            NalUnitType::Sps => 33,
+            NalUnitType::Pps => 34,
+            NalUnitType::AccessUnitDelimiter => 35,
+            NalUnitType::EndOfSequence => 36,
+            NalUnitType::EndOfBitstream => 37,
+            NalUnitType::FillerData => 38,
+            NalUnitType::PrefixSei => 39,
+            NalUnitType::SuffixSei => 40,
+            NalUnitType::Other(value) => value,
+        }
+    }
+
+    /// Whether this is a coded slice segment of one of the defined types.
+    /// Reserved VCL types are left out, as decoders have to ignore them.
+    pub fn is_slice(self) -> bool {
+        self.value() <= 9 || (16..=21).contains(&self.value())
+    }
+
+    /// Intra random access point: BLA, IDR or CRA, and the reserved IRAP
+    /// types.
+    pub fn is_irap(self) -> bool {
+        (16..=23).contains(&self.value())
+    }
+
+    pub fn is_idr(self) -> bool {
+        matches!(self, NalUnitType::IdrWRadl | NalUnitType::IdrNLp)
+    }
+
+    pub fn is_bla(self) -> bool {
+        matches!(
+            self,
+            NalUnitType::BlaWLp | NalUnitType::BlaWRadl | NalUnitType::BlaNLp
+        )
+    }
+
+    pub fn is_cra(self) -> bool {
+        self == NalUnitType::Cra
+    }
+
+    pub fn is_radl(self) -> bool {
+        matches!(self, NalUnitType::RadlN | NalUnitType::RadlR)
+    }
+
+    pub fn is_rasl(self) -> bool {
+        matches!(self, NalUnitType::RaslN | NalUnitType::RaslR)
+    }
+
+    /// Sub-layer non-reference picture: `TRAIL_N`, `TSA_N`, `STSA_N`,
+    /// `RADL_N`, `RASL_N` and the reserved `RSV_VCL_N*` types.
+    pub fn is_sub_layer_non_reference(self) -> bool {
+        self.value() <= 14 && self.value().is_multiple_of(2)
+    }
+}
+
+/// `nal_unit_header()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NalHeader {
+    pub nal_unit_type: NalUnitType,
+    pub nuh_layer_id: u8,
+    pub nuh_temporal_id_plus1: u8,
+}
+
+impl NalHeader {
+    /// Number of bytes the header occupies at the start of the NAL unit.
+    pub const SIZE: usize = 2;
+
+    pub fn parse(nal: &[u8]) -> Result<Self> {
+        let mut r = BitReader::new(nal);
+        if r.read_bit()? {
+            return Err(Error::InvalidValue {
+                name: "forbidden_zero_bit",
+                value: 1,
+            });
+        }
+        let nal_unit_type = NalUnitType::from(r.read_bits(6)? as u8);
+        let nuh_layer_id = r.read_bits(6)? as u8;
+        let nuh_temporal_id_plus1 = r.read_bits(3)? as u8;
+        if nuh_temporal_id_plus1 == 0 {
+            return Err(Error::InvalidValue {
+                name: "nuh_temporal_id_plus1",
+                value: 0,
+            });
+        }
+        Ok(Self {
+            nal_unit_type,
+            nuh_layer_id,
+            nuh_temporal_id_plus1,
+        })
+    }
+
+    /// `TemporalId`.
+    pub fn temporal_id(&self) -> u8 {
+        self.nuh_temporal_id_plus1 - 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nal_header() {
+        let header = NalHeader::parse(&[0x42, 0x01]).unwrap();
+        assert_eq!(header.nal_unit_type, NalUnitType::Sps);
+        assert_eq!((header.nuh_layer_id, header.temporal_id()), (0, 0));
+
+        let header = NalHeader::parse(&[0x26, 0x01]).unwrap();
+        assert_eq!(header.nal_unit_type, NalUnitType::IdrWRadl);
+        assert!(header.nal_unit_type.is_irap() && header.nal_unit_type.is_idr());
+        let header = NalHeader::parse(&[0x10, 0x03]).unwrap();
+        assert_eq!(header.nal_unit_type, NalUnitType::RaslN);
+        assert!(header.nal_unit_type.is_sub_layer_non_reference());
+        assert_eq!(header.temporal_id(), 2);
+        assert!(NalUnitType::from(22).is_irap());
+        assert!(!NalUnitType::from(22).is_slice());
+
+        assert!(NalHeader::parse(&[0x80, 0x01]).is_err());
+        assert!(NalHeader::parse(&[0x40, 0x00]).is_err());
+        assert_eq!(
+            NalHeader::parse(&[0x40]),
+            Err(Error::Bits(bits::Error::OutOfData))
+        );
+    }
+}
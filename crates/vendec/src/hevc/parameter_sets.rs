@@ -0,0 +1,635 @@
+use std::sync::Arc;
+
+use super::{Error, NalHeader, NalUnitType, Pps, Result, Sps, Vps};
+use crate::nal::to_rbsp;
+
+/// The parameter sets received so far, by ID, and the ones active for the
+/// current picture.
+///
+/// A parameter set replaces any earlier one with the same ID. Sets are
+/// handed out as [`Arc`]s so that pictures in flight keep theirs when a
+/// new set arrives.
+#[derive(Debug)]
+pub struct ParameterSets {
+    vps: Vec<Option<Arc<Vps>>>,
+    sps: Vec<Option<Arc<Sps>>>,
+    pps: Vec<Option<Arc<Pps>>>,
+    active: Option<(Arc<Pps>, Arc<Sps>)>,
+}
+
+impl Default for ParameterSets {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ParameterSets {
+    pub fn new() -> Self {
+        Self {
+            vps: vec![None; 16],
+            sps: vec![None; 16],
+            pps: vec![None; 64],
+            active: None,
+        }
+    }
+
+    /// Stores the parameter set in `nal`, a NAL unit with its header and
+    /// emulation prevention bytes. Other NAL units, and parameter sets of
+    /// layers other than the base layer, are ignored.
+    pub fn add_nal(&mut self, nal: &[u8]) -> Result<()> {
+        let header = NalHeader::parse(nal)?;
+        if header.nuh_layer_id != 0 {
+            return Ok(());
+        }
+        let rbsp = || to_rbsp(&nal[NalHeader::SIZE..]);
+        match header.nal_unit_type {
+            NalUnitType::Vps => self.add_vps(&rbsp()).map(drop),
+            NalUnitType::Sps => self.add_sps(&rbsp()).map(drop),
+            NalUnitType::Pps => self.add_pps(&rbsp()).map(drop),
+            _ => Ok(()),
+        }
+    }
+
+    /// Parses and stores a `video_parameter_set_rbsp()`.
+    pub fn add_vps(&mut self, rbsp: &[u8]) -> Result<Arc<Vps>> {
+        let vps = Arc::new(Vps::parse(rbsp)?);
+        self.vps[vps.vps_video_parameter_set_id as usize] = Some(vps.clone());
+        Ok(vps)
+    }
+
+    /// Parses and stores a `seq_parameter_set_rbsp()`.
+    pub fn add_sps(&mut self, rbsp: &[u8]) -> Result<Arc<Sps>> {
+        let sps = Arc::new(Sps::parse(rbsp)?);
+        self.sps[sps.sps_seq_parameter_set_id as usize] = Some(sps.clone());
+        Ok(sps)
+    }
+
+    /// Parses and stores a `pic_parameter_set_rbsp()`. Its SPS does not
+    /// have to be known yet.
+    pub fn add_pps(&mut self, rbsp: &[u8]) -> Result<Arc<Pps>> {
+        let pps = Arc::new(Pps::parse(rbsp)?);
+        self.pps[pps.pps_pic_parameter_set_id as usize] = Some(pps.clone());
+        Ok(pps)
+    }
+
+    pub fn vps(&self, id: u32) -> Option<&Arc<Vps>> {
+        self.vps.get(id as usize)?.as_ref()
+    }
+
+    pub fn sps(&self, id: u32) -> Option<&Arc<Sps>> {
+        self.sps.get(id as usize)?.as_ref()
+    }
+
+    pub fn pps(&self, id: u32) -> Option<&Arc<Pps>> {
+        self.pps.get(id as usize)?.as_ref()
+    }
+
+    /// Makes the PPS with `id`, as named by a slice segment header, and
+    /// its SPS the active parameter sets, after checking that they fit
+    /// together.
+    pub fn activate_pps(&mut self, id: u32) -> Result<(Arc<Pps>, Arc<Sps>)> {
+        let pps = self.pps(id).ok_or(Error::MissingPps(id))?.clone();
+        let sps_id = pps.pps_seq_parameter_set_id as u32;
+        let sps = self.sps(sps_id).ok_or(Error::MissingSps(sps_id))?.clone();
+        let unchanged = self
+            .active
+            .as_ref()
+            .is_some_and(|(active_pps, active_sps)| {
+                Arc::ptr_eq(active_pps, &pps) && Arc::ptr_eq(active_sps, &sps)
+            });
+        if !unchanged {
+            pps.validate(&sps)?;
+            self.active = Some((pps.clone(), sps.clone()));
+        }
+        Ok((pps, sps))
+    }
+
+    pub fn active_pps(&self) -> Option<&Arc<Pps>> {
+        self.active.as_ref().map(|(pps, _)| pps)
+    }
+
+    pub fn active_sps(&self) -> Option<&Arc<Sps>> {
+        self.active.as_ref().map(|(_, sps)| sps)
+    }
+
+    /// The VPS the active SPS refers to, if it has been received. Decoding
+    /// the base layer does not need it.
+    pub fn active_vps(&self) -> Option<&Arc<Vps>> {
+        self.vps(self.active_sps()?.sps_video_parameter_set_id as u32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bits::tests::random_chunks;
+    use crate::bits::{BitReader, BitWriter};
+    use crate::hevc::{
+        HrdCpb, ProfileTierLevel, ScalingLists, ShortTermRps, DEFAULT_8X8_INTER, DEFAULT_8X8_INTRA,
+        PROFILE_RANGE_EXTENSIONS,
+    };
+
+    fn write_profile_tier_level(w: &mut BitWriter, profile_idc: u8, max_sub_layers_minus1: u8) {
+        w.write_bits(0, 2);
+        w.write_bit(false);
+        w.write_bits(profile_idc as u64, 5);
+        w.write_bits(1 << (31 - profile_idc), 32);
+        w.write_bits(0b1001, 4); // progressive, frame only
+                                 // max_12bit, max_10bit and max_422chroma, then lower_bit_rate.
+        w.write_bits(0b110100001, 9);
+        w.write_bits(0, 35);
+        w.write_bits(120, 8); // general_level_idc
+        for _ in 0..max_sub_layers_minus1 {
+            w.write_bit(false);
+            w.write_bit(true); // sub_layer_level_present_flag
+        }
+        if max_sub_layers_minus1 > 0 {
+            w.write_bits(0, 2 * (8 - max_sub_layers_minus1 as u32));
+        }
+        for _ in 0..max_sub_layers_minus1 {
+            w.write_bits(90, 8);
+        }
+    }
+
+    fn vps_rbsp() -> Vec<u8> {
+        let mut w = BitWriter::new();
+        w.write_bits(3, 4); // vps_video_parameter_set_id
+        w.write_bit(true);
+        w.write_bit(true);
+        w.write_bits(0, 6);
+        w.write_bits(1, 3); // vps_max_sub_layers_minus1
+        w.write_bit(true);
+        w.write_bits(0xffff, 16);
+        write_profile_tier_level(&mut w, 1, 1);
+        w.write_bit(true); // vps_sub_layer_ordering_info_present_flag
+        for (buffering, reorder) in [(2, 0), (4, 2)] {
+            w.write_ue(buffering);
+            w.write_ue(reorder);
+            w.write_ue(0);
+        }
+        w.write_bits(1, 6); // vps_max_layer_id
+        w.write_ue(1); // vps_num_layer_sets_minus1
+        w.write_bit(true);
+        w.write_bit(false);
+        w.write_bit(true); // vps_timing_info_present_flag
+        w.write_bits(1001, 32);
+        w.write_bits(30000, 32);
+        w.write_bit(false);
+        w.write_ue(2); // vps_num_hrd_parameters
+        for i in 0..2 {
+            w.write_ue(i); // hrd_layer_set_idx
+            if i > 0 {
+                w.write_bit(false); // cprms_present_flag
+            } else {
+                w.write_bit(false);
+                w.write_bit(true); // vcl_hrd_parameters_present_flag
+                w.write_bit(false);
+                w.write_bits(2, 4);
+                w.write_bits(3, 4);
+                w.write_bits(0x7fff, 15);
+            }
+            for _ in 0..2 {
+                w.write_bit(true); // fixed_pic_rate_general_flag
+                w.write_ue(0);
+                w.write_ue(0); // cpb_cnt_minus1
+                w.write_ue(5000 + i);
+                w.write_ue(9000);
+                w.write_bit(false);
+            }
+        }
+        w.write_bit(false); // vps_extension_flag
+        w.write_trailing_bits();
+        w.into_bytes()
+    }
+
+    /// A 1920x1080 4:2:2 10-bit SPS using every optional part but the
+    /// bitstream restrictions.
+    fn sps_rbsp(id: u32) -> Vec<u8> {
+        let mut w = BitWriter::new();
+        w.write_bits(3, 4); // sps_video_parameter_set_id
+        w.write_bits(1, 3); // sps_max_sub_layers_minus1
+        w.write_bit(true);
+        write_profile_tier_level(&mut w, PROFILE_RANGE_EXTENSIONS, 1);
+        w.write_ue(id);
+        w.write_ue(2); // chroma_format_idc
+        w.write_ue(1920);
+        w.write_ue(1088);
+        w.write_bit(true); // conformance_window_flag
+        w.write_ue(0);
+        w.write_ue(0);
+        w.write_ue(0);
+        w.write_ue(8);
+        w.write_ue(2); // bit_depth_luma_minus8
+        w.write_ue(2);
+        w.write_ue(4); // log2_max_pic_order_cnt_lsb_minus4
+        w.write_bit(false);
+        w.write_ue(4); // sps_max_dec_pic_buffering_minus1
+        w.write_ue(2);
+        w.write_ue(0);
+        w.write_ue(0); // log2_min_luma_coding_block_size_minus3
+        w.write_ue(3);
+        w.write_ue(0);
+        w.write_ue(3);
+        w.write_ue(2); // max_transform_hierarchy_depth_inter
+        w.write_ue(2);
+        w.write_bit(true); // scaling_list_enabled_flag
+        w.write_bit(true);
+        write_scaling_list_data(&mut w);
+        w.write_bit(true); // amp_enabled_flag
+        w.write_bit(true);
+        w.write_bit(true); // pcm_enabled_flag
+        w.write_bits(7, 4);
+        w.write_bits(7, 4);
+        w.write_ue(0);
+        w.write_ue(2);
+        w.write_bit(true);
+        w.write_ue(2); // num_short_term_ref_pic_sets
+        w.write_ue(2);
+        w.write_ue(0);
+        w.write_ue(0);
+        w.write_bit(true);
+        w.write_ue(1);
+        w.write_bit(true);
+        w.write_bit(true); // inter_ref_pic_set_prediction_flag
+        w.write_bit(false);
+        w.write_ue(1);
+        w.write_bit(true);
+        w.write_bit(false);
+        w.write_bit(false);
+        w.write_bit(true);
+        w.write_bit(true); // long_term_ref_pics_present_flag
+        w.write_ue(2);
+        w.write_bits(5, 8);
+        w.write_bit(true);
+        w.write_bits(9, 8);
+        w.write_bit(false);
+        w.write_bit(true); // sps_temporal_mvp_enabled_flag
+        w.write_bit(true);
+        w.write_bit(true); // vui_parameters_present_flag
+        w.write_bit(true);
+        w.write_bits(255, 8); // aspect_ratio_idc
+        w.write_bits(4, 16);
+        w.write_bits(3, 16);
+        w.write_bit(false);
+        w.write_bit(true); // video_signal_type_present_flag
+        w.write_bits(5, 3);
+        w.write_bit(false);
+        w.write_bit(true);
+        w.write_bits(9, 8);
+        w.write_bits(16, 8);
+        w.write_bits(9, 8);
+        w.write_bits(0, 5); // chroma_loc_info_present_flag to default_display_window_flag
+        w.write_bit(true); // vui_timing_info_present_flag
+        w.write_bits(1001, 32);
+        w.write_bits(60000, 32);
+        w.write_bit(false);
+        w.write_bit(true); // vui_hrd_parameters_present_flag
+        w.write_bit(true);
+        w.write_bit(false);
+        w.write_bit(false);
+        w.write_bits(4, 4);
+        w.write_bits(6, 4);
+        w.write_bits(0x7fff, 15);
+        w.write_bit(true); // fixed_pic_rate_general_flag
+        w.write_ue(0);
+        w.write_ue(0);
+        w.write_ue(12499);
+        w.write_ue(62499);
+        w.write_bit(false);
+        w.write_bit(false);
+        w.write_bit(false);
+        w.write_bit(true); // low_delay_hrd_flag
+        w.write_ue(999);
+        w.write_ue(1999);
+        w.write_bit(true);
+        w.write_bit(false); // bitstream_restriction_flag
+        w.write_bit(true); // sps_extension_present_flag
+        w.write_bits(0b1001_0000, 8);
+        w.write_bits(0b001000101, 9);
+        w.write_bit(true); // sps_curr_pic_ref_enabled_flag
+        w.write_bit(true);
+        w.write_ue(32); // palette_max_size
+        w.write_ue(32);
+        w.write_bit(true);
+        w.write_ue(1);
+        for value in [100, 200, 300, 400, 500, 600] {
+            w.write_bits(value, 10);
+        }
+        w.write_bits(2, 2); // motion_vector_resolution_control_idc
+        w.write_bit(false);
+        w.write_trailing_bits();
+        w.into_bytes()
+    }
+
+    /// Explicit 4x4 and 16x16 intra Y lists, copies and defaults.
+    fn write_scaling_list_data(w: &mut BitWriter) {
+        w.write_bit(true); // scaling_list_pred_mode_flag
+        w.write_se(8);
+        w.write_se(1);
+        for _ in 2..16 {
+            w.write_se(0);
+        }
+        for _ in 1..6 {
+            w.write_bit(false);
+            w.write_ue(0);
+        }
+        for _ in 0..6 {
+            w.write_bit(false);
+            w.write_ue(0);
+        }
+        w.write_bit(true);
+        w.write_se(4); // scaling_list_dc_coef_minus8
+        for _ in 0..64 {
+            w.write_se(0);
+        }
+        w.write_bit(false);
+        w.write_ue(1); // scaling_list_pred_matrix_id_delta
+        for _ in 2..6 {
+            w.write_bit(false);
+            w.write_ue(0);
+        }
+        w.write_bit(false);
+        w.write_ue(0);
+        w.write_bit(false);
+        w.write_ue(1);
+    }
+
+    fn pps_rbsp(id: u32, sps_id: u32) -> Vec<u8> {
+        let mut w = BitWriter::new();
+        w.write_ue(id);
+        w.write_ue(sps_id);
+        w.write_bit(true); // dependent_slice_segments_enabled_flag
+        w.write_bit(true);
+        w.write_bits(2, 3); // num_extra_slice_header_bits
+        w.write_bit(true);
+        w.write_bit(true);
+        w.write_ue(2); // num_ref_idx_l0_default_active_minus1
+        w.write_ue(1);
+        w.write_se(-4); // init_qp_minus26
+        w.write_bit(false);
+        w.write_bit(true); // transform_skip_enabled_flag
+        w.write_bit(true);
+        w.write_ue(1); // diff_cu_qp_delta_depth
+        w.write_se(-2);
+        w.write_se(3);
+        w.write_bit(true);
+        w.write_bit(true); // weighted_pred_flag
+        w.write_bit(false);
+        w.write_bit(false);
+        w.write_bit(true); // tiles_enabled_flag
+        w.write_bit(true);
+        w.write_ue(2); // num_tile_columns_minus1
+        w.write_ue(1);
+        w.write_bit(false); // uniform_spacing_flag
+        w.write_ue(9);
+        w.write_ue(9);
+        w.write_ue(7); // row_height_minus1
+        w.write_bit(false);
+        w.write_bit(true);
+        w.write_bit(true); // deblocking_filter_control_present_flag
+        w.write_bit(true);
+        w.write_bit(false);
+        w.write_se(2);
+        w.write_se(-1);
+        w.write_bit(false); // pps_scaling_list_data_present_flag
+        w.write_bit(true);
+        w.write_ue(2); // log2_parallel_merge_level_minus2
+        w.write_bit(true);
+        w.write_bit(true); // pps_extension_present_flag
+        w.write_bits(0b1001_0000, 8);
+        w.write_ue(1); // log2_max_transform_skip_block_size_minus2
+        w.write_bit(true);
+        w.write_bit(true); // chroma_qp_offset_list_enabled_flag
+        w.write_ue(1);
+        w.write_ue(1);
+        for value in [-2, 3, 4, -5] {
+            w.write_se(value);
+        }
+        w.write_ue(0);
+        w.write_ue(0);
+        w.write_bit(false); // pps_curr_pic_ref_enabled_flag
+        w.write_bit(true);
+        w.write_bit(true);
+        w.write_se(0);
+        w.write_se(1);
+        w.write_se(-1);
+        w.write_bit(true); // pps_palette_predictor_initializers_present_flag
+        w.write_ue(1);
+        w.write_bit(false);
+        w.write_ue(2);
+        w.write_ue(2);
+        for value in [7, 8, 9] {
+            w.write_bits(value, 10);
+        }
+        w.write_trailing_bits();
+        w.into_bytes()
+    }
+
+    #[test]
+    fn parses_vps() {
+        let vps = Vps::parse(&vps_rbsp()).unwrap();
+        assert_eq!(vps.vps_video_parameter_set_id, 3);
+        assert_eq!(vps.profile_tier_level.general_profile.profile_idc, 1);
+        assert_eq!(vps.profile_tier_level.sub_layers[0].level_idc, Some(90));
+        assert_eq!(vps.sub_layer_ordering[1].max_dec_pic_buffering_minus1, 4);
+        assert_eq!(vps.layer_sets, [vec![0], vec![0]]);
+        assert_eq!(vps.hrd_parameters.len(), 2);
+        // The second HRD inherits the common information of the first.
+        let hrd = &vps.hrd_parameters[1].hrd;
+        assert!(!vps.hrd_parameters[1].cprms_present_flag);
+        assert!(hrd.vcl_hrd_parameters_present_flag);
+        assert_eq!((hrd.bit_rate_scale, hrd.cpb_size_scale), (2, 3));
+        assert_eq!(hrd.sub_layers[1].vcl_cpbs[0].bit_rate_value_minus1, 5001);
+    }
+
+    #[test]
+    fn parses_sps() {
+        let sps = Sps::parse(&sps_rbsp(5)).unwrap();
+        assert_eq!(sps.sps_seq_parameter_set_id, 5);
+        let ptl = &sps.profile_tier_level;
+        assert!(ptl
+            .general_profile
+            .is_compatible_with(PROFILE_RANGE_EXTENSIONS));
+        assert!(ptl.general_profile.max_10bit_constraint_flag);
+        assert!(!ptl.general_profile.max_8bit_constraint_flag);
+        assert!(ptl.general_profile.max_422chroma_constraint_flag);
+        assert!(ptl.general_profile.lower_bit_rate_constraint_flag);
+        assert_eq!(ptl.general_level_idc, 120);
+        assert_eq!(sps.chroma_subsampling(), Some((2, 1)));
+        assert_eq!(sps.bit_depth_chroma(), 10);
+        let rect = sps.visible_rect().unwrap();
+        assert_eq!((rect.width, rect.height), (1920, 1080));
+        assert_eq!(
+            (sps.pic_width_in_ctbs(), sps.pic_height_in_ctbs()),
+            (30, 17)
+        );
+        assert_eq!(sps.max_pic_order_cnt_lsb(), 256);
+        // Sub-layer 0 takes the values of the highest one.
+        assert_eq!(sps.sub_layer_ordering.len(), 2);
+        assert_eq!(sps.sub_layer_ordering[0].max_num_reorder_pics, 2);
+        assert_eq!(sps.max_latency_pictures(), None);
+
+        let lists = &sps.scaling_lists;
+        assert_eq!(lists.list_4x4[0][..3], [16, 17, 17]);
+        assert_eq!(lists.list_4x4[1], [16; 16]);
+        assert_eq!(lists.list_8x8[3], DEFAULT_8X8_INTER);
+        assert_eq!((lists.list_16x16[1], lists.dc_16x16[1]), ([12; 64], 12));
+        assert_eq!(lists.list_16x16[2], DEFAULT_8X8_INTRA);
+        assert_eq!(
+            (lists.list_32x32[3], lists.dc_32x32[3]),
+            (DEFAULT_8X8_INTRA, 16)
+        );
+        assert_eq!((lists.list_32x32[1], lists.dc_32x32[1]), ([12; 64], 12));
+        assert_eq!(ScalingLists::defaults().list_4x4, [[16; 16]; 6]);
+
+        let pcm = sps.pcm.unwrap();
+        assert_eq!(pcm.log2_diff_max_min_pcm_luma_coding_block_size, 2);
+        assert!(pcm.pcm_loop_filter_disabled_flag);
+        assert_eq!(sps.short_term_ref_pic_sets[0].delta_poc_s0, [-1, -3]);
+        assert_eq!(sps.short_term_ref_pic_sets[1].delta_poc_s1, [1, 2]);
+        assert_eq!(sps.long_term_ref_pics[1].lt_ref_pic_poc_lsb_sps, 9);
+
+        let vui = sps.vui.as_ref().unwrap();
+        assert_eq!((vui.sar_width, vui.sar_height), (4, 3));
+        assert_eq!(vui.transfer_characteristics, 16);
+        assert_eq!(vui.vui_time_scale, 60000);
+        let hrd = vui.hrd.as_ref().unwrap();
+        assert_eq!(hrd.sub_layers[0].nal_cpbs[0].bit_rate_value_minus1, 12499);
+        assert!(hrd.sub_layers[1].low_delay_hrd_flag);
+        assert_eq!(
+            hrd.sub_layers[1].nal_cpbs,
+            [HrdCpb {
+                bit_rate_value_minus1: 999,
+                cpb_size_value_minus1: 1999,
+                cbr_flag: true,
+                ..Default::default()
+            }]
+        );
+
+        let range = &sps.range_extension;
+        assert!(range.implicit_rdpcm_enabled_flag && !range.explicit_rdpcm_enabled_flag);
+        assert!(range.high_precision_offsets_enabled_flag);
+        assert!(range.cabac_bypass_alignment_enabled_flag);
+        let scc = &sps.scc_extension;
+        assert!(scc.sps_curr_pic_ref_enabled_flag);
+        assert_eq!(scc.palette_max_predictor_size(), 64);
+        assert_eq!(scc.sps_palette_predictor_initializers[2], [500, 600]);
+        assert_eq!(scc.motion_vector_resolution_control_idc, 2);
+    }
+
+    #[test]
+    fn parses_pps() {
+        let sps = Sps::parse(&sps_rbsp(0)).unwrap();
+        let pps = Pps::parse(&pps_rbsp(9, 0)).unwrap();
+        assert_eq!(pps.pps_pic_parameter_set_id, 9);
+        assert_eq!(pps.num_extra_slice_header_bits, 2);
+        assert_eq!(pps.init_qp_minus26, -4);
+        assert_eq!((pps.pps_cb_qp_offset, pps.pps_cr_qp_offset), (-2, 3));
+        assert_eq!(pps.column_width_minus1, [9, 9]);
+        assert_eq!(pps.column_widths(&sps), [10, 10, 10]);
+        assert_eq!(pps.row_heights(&sps), [8, 9]);
+        assert!(!pps.loop_filter_across_tiles_enabled_flag);
+        assert_eq!((pps.pps_beta_offset_div2, pps.pps_tc_offset_div2), (2, -1));
+        assert!(pps.scaling_lists.is_none());
+        assert!(pps.slice_segment_header_extension_present_flag);
+        let range = &pps.range_extension;
+        assert_eq!(range.log2_max_transform_skip_block_size_minus2, 1);
+        assert_eq!(range.cb_qp_offset_list, [-2, 4]);
+        assert_eq!(range.cr_qp_offset_list, [3, -5]);
+        let scc = &pps.scc_extension;
+        assert!(scc.pps_slice_act_qp_offsets_present_flag);
+        assert_eq!(scc.pps_act_cr_qp_offset_plus3, -1);
+        assert_eq!(scc.pps_palette_predictor_initializers, [[7], [8], [9]]);
+        pps.validate(&sps).unwrap();
+
+        let mut uniform = pps.clone();
+        uniform.uniform_spacing_flag = true;
+        assert_eq!(uniform.column_widths(&sps), [10, 10, 10]);
+        assert_eq!(uniform.row_heights(&sps), [8, 9]);
+        let mut too_wide = pps;
+        too_wide.column_width_minus1 = vec![19, 9];
+        assert_eq!(
+            too_wide.validate(&sps),
+            Err(Error::InvalidValue {
+                name: "column_width_minus1",
+                value: 30
+            })
+        );
+    }
+
+    #[test]
+    fn tracks_active_sets() {
+        let mut sets = ParameterSets::new();
+        assert_eq!(sets.activate_pps(1), Err(Error::MissingPps(1)));
+        // A PPS may come before its SPS.
+        let mut nal = vec![0x44, 0x01];
+        nal.extend(pps_rbsp(1, 2));
+        sets.add_nal(&nal).unwrap();
+        assert!(sets.pps(1).is_some());
+        assert_eq!(sets.activate_pps(1), Err(Error::MissingSps(2)));
+        let mut nal = vec![0x42, 0x01];
+        nal.extend(sps_rbsp(2));
+        sets.add_nal(&nal).unwrap();
+        let (pps, sps) = sets.activate_pps(1).unwrap();
+        assert_eq!(pps.pps_seq_parameter_set_id, 2);
+        assert!(Arc::ptr_eq(sets.active_sps().unwrap(), &sps));
+        assert!(sets.active_vps().is_none());
+        let mut nal = vec![0x40, 0x01];
+        nal.extend(vps_rbsp());
+        sets.add_nal(&nal).unwrap();
+        assert_eq!(sets.active_vps().unwrap().vps_video_parameter_set_id, 3);
+
+        // A new SPS with the same ID is picked up on the next activation.
+        sets.add_sps(&sps_rbsp(2)).unwrap();
+        let (_, latest) = sets.activate_pps(1).unwrap();
+        assert!(!Arc::ptr_eq(&sps, &latest));
+        // Parameter sets of other layers are ignored.
+        let mut nal = vec![0x42, 0x09];
+        nal.extend(sps_rbsp(3));
+        sets.add_nal(&nal).unwrap();
+        assert!(sets.sps(3).is_none());
+    }
+
+    #[test]
+    fn rejects_malformed_sets() {
+        let rbsp = sps_rbsp(0);
+        for len in 0..rbsp.len() - 1 {
+            assert!(Sps::parse(&rbsp[..len]).is_err(), "length {}", len);
+        }
+        let rbsp = pps_rbsp(0, 0);
+        for len in 0..rbsp.len() - 1 {
+            assert!(Pps::parse(&rbsp[..len]).is_err(), "length {}", len);
+        }
+        let rbsp = vps_rbsp();
+        for len in 0..rbsp.len() - 1 {
+            assert!(Vps::parse(&rbsp[..len]).is_err(), "length {}", len);
+        }
+
+        let mut w = BitWriter::new();
+        w.write_bits(0, 4);
+        w.write_bits(0, 3);
+        w.write_bit(true);
+        write_profile_tier_level(&mut w, 1, 0);
+        w.write_ue(16); // sps_seq_parameter_set_id
+        w.write_trailing_bits();
+        assert_eq!(
+            Sps::parse(&w.into_bytes()),
+            Err(Error::InvalidValue {
+                name: "sps_seq_parameter_set_id",
+                value: 16
+            })
+        );
+
+        // Arbitrary data must never panic.
+        let mut sets = ParameterSets::new();
+        for data in random_chunks(0x1234_5678, 64, 2000) {
+            let _ = Vps::parse(&data);
+            let _ = Sps::parse(&data);
+            let _ = sets.add_pps(&data);
+            let mut r = BitReader::new(&data);
+            let _ = ProfileTierLevel::parse(&mut r, 6);
+            let mut r = BitReader::new(&data);
+            let _ = ShortTermRps::parse(&mut r, &[], false, 15);
+        }
+    }
+}
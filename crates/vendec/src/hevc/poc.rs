@@ -0,0 +1,105 @@
+use super::{SliceSegmentHeader, Sps};
+
+/// Picture order count decoding (8.3.1). Holds the order count of
+/// `prevTid0Pic`, the last picture the next ones count from.
+#[derive(Debug, Clone, Default)]
+pub struct PocState {
+    prev_tid0_pic_order_cnt: i32,
+}
+
+impl PocState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns `PicOrderCntVal` of the picture `header` starts.
+    /// `no_rasl_output_flag` is `NoRaslOutputFlag` of the picture, which
+    /// only matters for IRAP pictures.
+    pub fn compute(
+        &mut self,
+        sps: &Sps,
+        header: &SliceSegmentHeader,
+        no_rasl_output_flag: bool,
+    ) -> i32 {
+        let nal_unit_type = header.nal_unit_type;
+        let max_lsb = sps.max_pic_order_cnt_lsb() as i32;
+        let lsb = header.slice_pic_order_cnt_lsb as i32;
+        let msb = if nal_unit_type.is_irap() && no_rasl_output_flag {
+            0
+        } else {
+            let prev_lsb = self.prev_tid0_pic_order_cnt & (max_lsb - 1);
+            let prev_msb = self.prev_tid0_pic_order_cnt.wrapping_sub(prev_lsb);
+            if lsb < prev_lsb && prev_lsb - lsb >= max_lsb / 2 {
+                prev_msb.wrapping_add(max_lsb)
+            } else if lsb > prev_lsb && lsb - prev_lsb > max_lsb / 2 {
+                prev_msb.wrapping_sub(max_lsb)
+            } else {
+                prev_msb
+            }
+        };
+        let poc = msb.wrapping_add(lsb);
+        if header.temporal_id == 0
+            && !nal_unit_type.is_radl()
+            && !nal_unit_type.is_rasl()
+            && !nal_unit_type.is_sub_layer_non_reference()
+        {
+            self.prev_tid0_pic_order_cnt = poc;
+        }
+        poc
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hevc::slice::tests::{header, sps};
+    use crate::hevc::{NalUnitType, SliceType};
+
+    #[test]
+    fn wraps_lsbs() {
+        // 8-bit LSBs.
+        let sps = sps();
+        let mut state = PocState::new();
+        let mut compute = |nal_unit_type, lsb, temporal_id| {
+            let mut header = header(nal_unit_type, SliceType::P, lsb);
+            header.temporal_id = temporal_id;
+            state.compute(&sps, &header, false)
+        };
+        assert_eq!(compute(NalUnitType::TrailR, 100, 0), 100);
+        assert_eq!(compute(NalUnitType::TrailR, 200, 0), 200);
+        // Forwards past the wrap, then back below it.
+        assert_eq!(compute(NalUnitType::TrailR, 40, 0), 296);
+        assert_eq!(compute(NalUnitType::TrailN, 250, 0), 250);
+        // Neither that non-reference picture, a RASL one nor a higher
+        // sub-layer moves prevTid0Pic.
+        assert_eq!(compute(NalUnitType::RaslR, 200, 0), 200);
+        assert_eq!(compute(NalUnitType::TrailR, 180, 1), 180);
+        assert_eq!(compute(NalUnitType::TrailR, 170, 0), 170);
+        // That one did: 40 is now ahead of it.
+        assert_eq!(compute(NalUnitType::TrailR, 40, 0), 296);
+    }
+
+    #[test]
+    fn resets_on_irap() {
+        let sps = sps();
+        let mut state = PocState::new();
+        let mut compute = |nal_unit_type, lsb, no_rasl_output_flag| {
+            let header = header(nal_unit_type, SliceType::P, lsb);
+            state.compute(&sps, &header, no_rasl_output_flag)
+        };
+        assert_eq!(compute(NalUnitType::TrailR, 100, false), 100);
+        assert_eq!(compute(NalUnitType::TrailR, 200, false), 200);
+        assert_eq!(compute(NalUnitType::TrailR, 60, false), 316);
+
+        // A CRA continuing the sequence keeps the MSB.
+        assert_eq!(compute(NalUnitType::Cra, 80, false), 336);
+        // One starting a new sequence restarts from its LSB, and the
+        // pictures after it count from there.
+        assert_eq!(compute(NalUnitType::Cra, 80, true), 80);
+        assert_eq!(compute(NalUnitType::RaslN, 70, false), 70);
+        assert_eq!(compute(NalUnitType::TrailR, 200, false), 200);
+        // So does an IDR, which always has NoRaslOutputFlag set.
+        assert_eq!(compute(NalUnitType::IdrNLp, 0, true), 0);
+        assert_eq!(compute(NalUnitType::TrailR, 250, false), -6);
+    }
+}
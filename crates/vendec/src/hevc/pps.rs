@@ -0,0 +1,391 @@
+use super::{check, Error, ReadExt, Result, ScalingLists, Sps, MAX_PALETTE_PREDICTOR_SIZE};
+use crate::bits::{self, BitReader};
+
+/// `pps_range_extension()`; everything is unset without it.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct PpsRangeExtension {
+    pub log2_max_transform_skip_block_size_minus2: u8,
+    pub cross_component_prediction_enabled_flag: bool,
+    pub chroma_qp_offset_list_enabled_flag: bool,
+    pub diff_cu_chroma_qp_offset_depth: u8,
+    pub chroma_qp_offset_list_len_minus1: u8,
+    /// `chroma_qp_offset_list_len_minus1 + 1` entries when enabled.
+    pub cb_qp_offset_list: Vec<i8>,
+    pub cr_qp_offset_list: Vec<i8>,
+    pub log2_sao_offset_scale_luma: u8,
+    pub log2_sao_offset_scale_chroma: u8,
+}
+
+impl PpsRangeExtension {
+    fn parse(r: &mut BitReader, transform_skip_enabled_flag: bool) -> Result<Self> {
+        let mut range = Self::default();
+        if transform_skip_enabled_flag {
+            range.log2_max_transform_skip_block_size_minus2 =
+                r.read_ue_max("log2_max_transform_skip_block_size_minus2", 3)? as u8;
+        }
+        range.cross_component_prediction_enabled_flag = r.read_bit()?;
+        range.chroma_qp_offset_list_enabled_flag = r.read_bit()?;
+        if range.chroma_qp_offset_list_enabled_flag {
+            range.diff_cu_chroma_qp_offset_depth =
+                r.read_ue_max("diff_cu_chroma_qp_offset_depth", 3)? as u8;
+            range.chroma_qp_offset_list_len_minus1 =
+                r.read_ue_max("chroma_qp_offset_list_len_minus1", 5)? as u8;
+            for _ in 0..=range.chroma_qp_offset_list_len_minus1 {
+                range
+                    .cb_qp_offset_list
+                    .push(r.read_se_range("cb_qp_offset_list", -12, 12)? as i8);
+                range
+                    .cr_qp_offset_list
+                    .push(r.read_se_range("cr_qp_offset_list", -12, 12)? as i8);
+            }
+        }
+        // At most BitDepth - 10, checked against the SPS on activation.
+        range.log2_sao_offset_scale_luma = r.read_ue_max("log2_sao_offset_scale_luma", 6)? as u8;
+        range.log2_sao_offset_scale_chroma =
+            r.read_ue_max("log2_sao_offset_scale_chroma", 6)? as u8;
+        Ok(range)
+    }
+}
+
+/// `pps_scc_extension()`; everything is unset without it.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct PpsSccExtension {
+    pub pps_curr_pic_ref_enabled_flag: bool,
+    pub residual_adaptive_colour_transform_enabled_flag: bool,
+    pub pps_slice_act_qp_offsets_present_flag: bool,
+    pub pps_act_y_qp_offset_plus5: i8,
+    pub pps_act_cb_qp_offset_plus5: i8,
+    pub pps_act_cr_qp_offset_plus3: i8,
+    pub pps_palette_predictor_initializers_present_flag: bool,
+    pub monochrome_palette_flag: bool,
+    pub luma_bit_depth_entry_minus8: u8,
+    pub chroma_bit_depth_entry_minus8: u8,
+    /// `pps_palette_predictor_initializer[comp][i]`,
+    /// `pps_num_palette_predictor_initializers` entries per component;
+    /// the chroma ones are empty with `monochrome_palette_flag`.
+    pub pps_palette_predictor_initializers: [Vec<u16>; 3],
+}
+
+impl PpsSccExtension {
+    fn parse(r: &mut BitReader) -> Result<Self> {
+        let mut scc = Self {
+            pps_curr_pic_ref_enabled_flag: r.read_bit()?,
+            residual_adaptive_colour_transform_enabled_flag: r.read_bit()?,
+            ..Default::default()
+        };
+        if scc.residual_adaptive_colour_transform_enabled_flag {
+            scc.pps_slice_act_qp_offsets_present_flag = r.read_bit()?;
+            scc.pps_act_y_qp_offset_plus5 =
+                r.read_se_range("pps_act_y_qp_offset_plus5", -7, 17)? as i8;
+            scc.pps_act_cb_qp_offset_plus5 =
+                r.read_se_range("pps_act_cb_qp_offset_plus5", -7, 17)? as i8;
+            scc.pps_act_cr_qp_offset_plus3 =
+                r.read_se_range("pps_act_cr_qp_offset_plus3", -9, 15)? as i8;
+        }
+        scc.pps_palette_predictor_initializers_present_flag = r.read_bit()?;
+        if scc.pps_palette_predictor_initializers_present_flag {
+            let count = r.read_ue_max(
+                "pps_num_palette_predictor_initializers",
+                MAX_PALETTE_PREDICTOR_SIZE,
+            )?;
+            if count > 0 {
+                scc.monochrome_palette_flag = r.read_bit()?;
+                scc.luma_bit_depth_entry_minus8 =
+                    r.read_ue_max("luma_bit_depth_entry_minus8", 8)? as u8;
+                if !scc.monochrome_palette_flag {
+                    scc.chroma_bit_depth_entry_minus8 =
+                        r.read_ue_max("chroma_bit_depth_entry_minus8", 8)? as u8;
+                }
+                let components = if scc.monochrome_palette_flag { 1 } else { 3 };
+                for comp in 0..components {
+                    let bits = if comp == 0 {
+                        scc.luma_bit_depth_entry_minus8
+                    } else {
+                        scc.chroma_bit_depth_entry_minus8
+                    } as u32
+                        + 8;
+                    scc.pps_palette_predictor_initializers[comp] = (0..count)
+                        .map(|_| Ok(r.read_bits(bits)? as u16))
+                        .collect::<Result<_>>()?;
+                }
+            }
+        }
+        Ok(scc)
+    }
+}
+
+/// Picture parameter set, `pic_parameter_set_rbsp()`. Absent fields hold
+/// their inferred values.
+///
+/// Unlike in H.264, parsing does not depend on the SPS; what does is
+/// checked by [`Pps::validate`] when the PPS is activated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pps {
+    pub pps_pic_parameter_set_id: u8,
+    pub pps_seq_parameter_set_id: u8,
+    pub dependent_slice_segments_enabled_flag: bool,
+    pub output_flag_present_flag: bool,
+    pub num_extra_slice_header_bits: u8,
+    pub sign_data_hiding_enabled_flag: bool,
+    pub cabac_init_present_flag: bool,
+    pub num_ref_idx_l0_default_active_minus1: u8,
+    pub num_ref_idx_l1_default_active_minus1: u8,
+    pub init_qp_minus26: i8,
+    pub constrained_intra_pred_flag: bool,
+    pub transform_skip_enabled_flag: bool,
+    pub cu_qp_delta_enabled_flag: bool,
+    pub diff_cu_qp_delta_depth: u8,
+    pub pps_cb_qp_offset: i8,
+    pub pps_cr_qp_offset: i8,
+    pub pps_slice_chroma_qp_offsets_present_flag: bool,
+    pub weighted_pred_flag: bool,
+    pub weighted_bipred_flag: bool,
+    pub transquant_bypass_enabled_flag: bool,
+    pub tiles_enabled_flag: bool,
+    pub entropy_coding_sync_enabled_flag: bool,
+    pub num_tile_columns_minus1: u8,
+    pub num_tile_rows_minus1: u8,
+    pub uniform_spacing_flag: bool,
+    /// Coded without `uniform_spacing_flag`: `num_tile_columns_minus1`
+    /// entries, the last column taking the rest of the picture.
+    pub column_width_minus1: Vec<u32>,
+    pub row_height_minus1: Vec<u32>,
+    pub loop_filter_across_tiles_enabled_flag: bool,
+    pub pps_loop_filter_across_slices_enabled_flag: bool,
+    pub deblocking_filter_control_present_flag: bool,
+    pub deblocking_filter_override_enabled_flag: bool,
+    pub pps_deblocking_filter_disabled_flag: bool,
+    pub pps_beta_offset_div2: i8,
+    pub pps_tc_offset_div2: i8,
+    /// Lists coded in the PPS, which take precedence over the SPS ones.
+    pub scaling_lists: Option<ScalingLists>,
+    pub lists_modification_present_flag: bool,
+    pub log2_parallel_merge_level_minus2: u8,
+    pub slice_segment_header_extension_present_flag: bool,
+    pub range_extension: PpsRangeExtension,
+    pub scc_extension: PpsSccExtension,
+}
+
+/// Levels allow at most 20 tile columns and 22 tile rows.
+pub const MAX_TILE_COLUMNS: u32 = 20;
+pub const MAX_TILE_ROWS: u32 = 22;
+
+impl Pps {
+    /// Parses a `pic_parameter_set_rbsp()`, without the NAL header.
+    pub fn parse(rbsp: &[u8]) -> Result<Self> {
+        let mut r = BitReader::new(rbsp);
+        let mut pps = Self {
+            pps_pic_parameter_set_id: r.read_ue_max("pps_pic_parameter_set_id", 63)? as u8,
+            pps_seq_parameter_set_id: r.read_ue_max("pps_seq_parameter_set_id", 15)? as u8,
+            dependent_slice_segments_enabled_flag: r.read_bit()?,
+            output_flag_present_flag: r.read_bit()?,
+            num_extra_slice_header_bits: r.read_bits(3)? as u8,
+            sign_data_hiding_enabled_flag: r.read_bit()?,
+            cabac_init_present_flag: r.read_bit()?,
+            num_ref_idx_l0_default_active_minus1: r
+                .read_ue_max("num_ref_idx_l0_default_active_minus1", 14)?
+                as u8,
+            num_ref_idx_l1_default_active_minus1: r
+                .read_ue_max("num_ref_idx_l1_default_active_minus1", 14)?
+                as u8,
+            // The lower bound depends on the bit depth; see `validate`.
+            init_qp_minus26: r.read_se_range("init_qp_minus26", -(26 + 48), 25)? as i8,
+            constrained_intra_pred_flag: r.read_bit()?,
+            transform_skip_enabled_flag: r.read_bit()?,
+            cu_qp_delta_enabled_flag: r.read_bit()?,
+            diff_cu_qp_delta_depth: 0,
+            pps_cb_qp_offset: 0,
+            pps_cr_qp_offset: 0,
+            pps_slice_chroma_qp_offsets_present_flag: false,
+            weighted_pred_flag: false,
+            weighted_bipred_flag: false,
+            transquant_bypass_enabled_flag: false,
+            tiles_enabled_flag: false,
+            entropy_coding_sync_enabled_flag: false,
+            num_tile_columns_minus1: 0,
+            num_tile_rows_minus1: 0,
+            uniform_spacing_flag: true,
+            column_width_minus1: Vec::new(),
+            row_height_minus1: Vec::new(),
+            loop_filter_across_tiles_enabled_flag: true,
+            pps_loop_filter_across_slices_enabled_flag: false,
+            deblocking_filter_control_present_flag: false,
+            deblocking_filter_override_enabled_flag: false,
+            pps_deblocking_filter_disabled_flag: false,
+            pps_beta_offset_div2: 0,
+            pps_tc_offset_div2: 0,
+            scaling_lists: None,
+            lists_modification_present_flag: false,
+            log2_parallel_merge_level_minus2: 0,
+            slice_segment_header_extension_present_flag: false,
+            range_extension: PpsRangeExtension::default(),
+            scc_extension: PpsSccExtension::default(),
+        };
+        if pps.cu_qp_delta_enabled_flag {
+            // At most log2_diff_max_min_luma_coding_block_size.
+            pps.diff_cu_qp_delta_depth = r.read_ue_max("diff_cu_qp_delta_depth", 3)? as u8;
+        }
+        pps.pps_cb_qp_offset = r.read_se_range("pps_cb_qp_offset", -12, 12)? as i8;
+        pps.pps_cr_qp_offset = r.read_se_range("pps_cr_qp_offset", -12, 12)? as i8;
+        pps.pps_slice_chroma_qp_offsets_present_flag = r.read_bit()?;
+        pps.weighted_pred_flag = r.read_bit()?;
+        pps.weighted_bipred_flag = r.read_bit()?;
+        pps.transquant_bypass_enabled_flag = r.read_bit()?;
+        pps.tiles_enabled_flag = r.read_bit()?;
+        pps.entropy_coding_sync_enabled_flag = r.read_bit()?;
+        if pps.tiles_enabled_flag {
+            pps.num_tile_columns_minus1 =
+                r.read_ue_max("num_tile_columns_minus1", MAX_TILE_COLUMNS - 1)? as u8;
+            pps.num_tile_rows_minus1 =
+                r.read_ue_max("num_tile_rows_minus1", MAX_TILE_ROWS - 1)? as u8;
+            pps.uniform_spacing_flag = r.read_bit()?;
+            if !pps.uniform_spacing_flag {
+                // Bounded by the picture size in CTBs; see `validate`.
+                const MAX: u32 = u16::MAX as u32;
+                pps.column_width_minus1 = (0..pps.num_tile_columns_minus1)
+                    .map(|_| r.read_ue_max("column_width_minus1", MAX))
+                    .collect::<bits::Result<_>>()?;
+                pps.row_height_minus1 = (0..pps.num_tile_rows_minus1)
+                    .map(|_| r.read_ue_max("row_height_minus1", MAX))
+                    .collect::<bits::Result<_>>()?;
+            }
+            pps.loop_filter_across_tiles_enabled_flag = r.read_bit()?;
+        }
+        pps.pps_loop_filter_across_slices_enabled_flag = r.read_bit()?;
+        pps.deblocking_filter_control_present_flag = r.read_bit()?;
+        if pps.deblocking_filter_control_present_flag {
+            pps.deblocking_filter_override_enabled_flag = r.read_bit()?;
+            pps.pps_deblocking_filter_disabled_flag = r.read_bit()?;
+            if !pps.pps_deblocking_filter_disabled_flag {
+                pps.pps_beta_offset_div2 = r.read_se_range("pps_beta_offset_div2", -6, 6)? as i8;
+                pps.pps_tc_offset_div2 = r.read_se_range("pps_tc_offset_div2", -6, 6)? as i8;
+            }
+        }
+        if r.read_bit()? {
+            pps.scaling_lists = Some(ScalingLists::parse(&mut r)?);
+        }
+        pps.lists_modification_present_flag = r.read_bit()?;
+        pps.log2_parallel_merge_level_minus2 =
+            r.read_ue_max("log2_parallel_merge_level_minus2", 4)? as u8;
+        pps.slice_segment_header_extension_present_flag = r.read_bit()?;
+
+        if r.read_bit()? {
+            // pps_extension_present_flag
+            let range = r.read_bit()?;
+            let multilayer = r.read_bit()?;
+            let three_d = r.read_bit()?;
+            let scc = r.read_bit()?;
+            r.skip_bits(4)?;
+            if range {
+                pps.range_extension =
+                    PpsRangeExtension::parse(&mut r, pps.transform_skip_enabled_flag)?;
+            }
+            if (multilayer || three_d) && scc {
+                return Err(Error::Unsupported("multi-layer and 3D-HEVC PPS extensions"));
+            }
+            if scc {
+                pps.scc_extension = PpsSccExtension::parse(&mut r)?;
+            }
+        }
+        Ok(pps)
+    }
+
+    /// Checks the constraints between the PPS and `sps`, the SPS it refers
+    /// to.
+    pub fn validate(&self, sps: &Sps) -> Result<()> {
+        let qp_bd_offset = 6 * sps.bit_depth_luma_minus8 as i64;
+        check(
+            "init_qp_minus26",
+            self.init_qp_minus26 as i64,
+            -(26 + qp_bd_offset),
+            25,
+        )?;
+        check(
+            "diff_cu_qp_delta_depth",
+            self.diff_cu_qp_delta_depth as i64,
+            0,
+            sps.log2_diff_max_min_luma_coding_block_size as i64,
+        )?;
+        check(
+            "log2_parallel_merge_level_minus2",
+            self.log2_parallel_merge_level_minus2 as i64,
+            0,
+            sps.ctb_log2_size() as i64 - 2,
+        )?;
+        let range = &self.range_extension;
+        check(
+            "log2_sao_offset_scale_luma",
+            range.log2_sao_offset_scale_luma as i64,
+            0,
+            (sps.bit_depth_luma() as i64 - 10).max(0),
+        )?;
+        check(
+            "log2_sao_offset_scale_chroma",
+            range.log2_sao_offset_scale_chroma as i64,
+            0,
+            (sps.bit_depth_chroma() as i64 - 10).max(0),
+        )?;
+        if self.tiles_enabled_flag {
+            check(
+                "num_tile_columns_minus1",
+                self.num_tile_columns_minus1 as i64,
+                0,
+                sps.pic_width_in_ctbs() as i64 - 1,
+            )?;
+            check(
+                "num_tile_rows_minus1",
+                self.num_tile_rows_minus1 as i64,
+                0,
+                sps.pic_height_in_ctbs() as i64 - 1,
+            )?;
+            let coded_width: u32 = self.column_width_minus1.iter().map(|w| w + 1).sum();
+            if coded_width >= sps.pic_width_in_ctbs() {
+                return Err(Error::InvalidValue {
+                    name: "column_width_minus1",
+                    value: coded_width as i64,
+                });
+            }
+            let coded_height: u32 = self.row_height_minus1.iter().map(|h| h + 1).sum();
+            if coded_height >= sps.pic_height_in_ctbs() {
+                return Err(Error::InvalidValue {
+                    name: "row_height_minus1",
+                    value: coded_height as i64,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// `colWidth`: the width of each tile column in CTBs, (6-3).
+    pub fn column_widths(&self, sps: &Sps) -> Vec<u32> {
+        tile_sizes(
+            self.uniform_spacing_flag,
+            self.num_tile_columns_minus1,
+            &self.column_width_minus1,
+            sps.pic_width_in_ctbs(),
+        )
+    }
+
+    /// `rowHeight`: the height of each tile row in CTBs, (6-4).
+    pub fn row_heights(&self, sps: &Sps) -> Vec<u32> {
+        tile_sizes(
+            self.uniform_spacing_flag,
+            self.num_tile_rows_minus1,
+            &self.row_height_minus1,
+            sps.pic_height_in_ctbs(),
+        )
+    }
+}
+
+fn tile_sizes(uniform: bool, count_minus1: u8, coded_minus1: &[u32], total: u32) -> Vec<u32> {
+    let count = count_minus1 as u32 + 1;
+    if uniform {
+        return (0..count)
+            .map(|i| ((i + 1) * total) / count - (i * total) / count)
+            .collect();
+    }
+    let mut sizes: Vec<u32> = coded_minus1.iter().map(|size| size + 1).collect();
+    let used: u32 = sizes.iter().sum();
+    sizes.push(total.saturating_sub(used));
+    sizes
+}
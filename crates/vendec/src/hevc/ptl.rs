@@ -0,0 +1,191 @@
+use super::Result;
+use crate::bits::BitReader;
+
+/// The profile part of `profile_tier_level()`, for the general profile or
+/// a sub-layer.
+///
+/// Only the constraint flags of the format range extensions profiles are
+/// kept; the ones of other profiles sit in the same bit positions, and the
+/// rest of the 43 bits are reserved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ProfileInfo {
+    pub profile_space: u8,
+    pub tier_flag: bool,
+    pub profile_idc: u8,
+    /// `profile_compatibility_flag[j]` in bit `31 - j`.
+    pub profile_compatibility_flags: u32,
+    pub progressive_source_flag: bool,
+    pub interlaced_source_flag: bool,
+    pub non_packed_constraint_flag: bool,
+    pub frame_only_constraint_flag: bool,
+    pub max_12bit_constraint_flag: bool,
+    pub max_10bit_constraint_flag: bool,
+    pub max_8bit_constraint_flag: bool,
+    pub max_422chroma_constraint_flag: bool,
+    pub max_420chroma_constraint_flag: bool,
+    pub max_monochrome_constraint_flag: bool,
+    pub intra_constraint_flag: bool,
+    pub one_picture_only_constraint_flag: bool,
+    pub lower_bit_rate_constraint_flag: bool,
+}
+
+impl ProfileInfo {
+    fn parse(r: &mut BitReader) -> Result<Self> {
+        let mut profile = Self {
+            profile_space: r.read_bits(2)? as u8,
+            tier_flag: r.read_bit()?,
+            profile_idc: r.read_bits(5)? as u8,
+            profile_compatibility_flags: r.read_bits(32)?,
+            progressive_source_flag: r.read_bit()?,
+            interlaced_source_flag: r.read_bit()?,
+            non_packed_constraint_flag: r.read_bit()?,
+            frame_only_constraint_flag: r.read_bit()?,
+            ..Default::default()
+        };
+        for flag in [
+            &mut profile.max_12bit_constraint_flag,
+            &mut profile.max_10bit_constraint_flag,
+            &mut profile.max_8bit_constraint_flag,
+            &mut profile.max_422chroma_constraint_flag,
+            &mut profile.max_420chroma_constraint_flag,
+            &mut profile.max_monochrome_constraint_flag,
+            &mut profile.intra_constraint_flag,
+            &mut profile.one_picture_only_constraint_flag,
+            &mut profile.lower_bit_rate_constraint_flag,
+        ] {
+            *flag = r.read_bit()?;
+        }
+        // The remaining constraint and reserved bits, then
+        // `general_inbld_flag` or its reserved bit.
+        r.skip_bits(43 - 9 + 1)?;
+        Ok(profile)
+    }
+
+    /// Whether the profile is `profile_idc` or declares compatibility with
+    /// it.
+    pub fn is_compatible_with(&self, profile_idc: u8) -> bool {
+        self.profile_idc == profile_idc
+            || (profile_idc < 32
+                && self.profile_compatibility_flags & (1 << (31 - profile_idc)) != 0)
+    }
+}
+
+/// `profile_idc` values of the profiles in annex A.
+pub const PROFILE_MAIN: u8 = 1;
+pub const PROFILE_MAIN_10: u8 = 2;
+pub const PROFILE_MAIN_STILL_PICTURE: u8 = 3;
+pub const PROFILE_RANGE_EXTENSIONS: u8 = 4;
+pub const PROFILE_HIGH_THROUGHPUT: u8 = 5;
+pub const PROFILE_SCREEN_CONTENT: u8 = 9;
+pub const PROFILE_HIGH_THROUGHPUT_SCREEN_CONTENT: u8 = 11;
+
+/// The profile and level of one sub-layer, where signalled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SubLayerProfileLevel {
+    pub profile: Option<ProfileInfo>,
+    pub level_idc: Option<u8>,
+}
+
+/// `profile_tier_level()`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ProfileTierLevel {
+    pub general_profile: ProfileInfo,
+    /// `general_level_idc`: 30 times the level number.
+    pub general_level_idc: u8,
+    /// One entry per sub-layer below the highest, `maxNumSubLayersMinus1`
+    /// in total.
+    pub sub_layers: Vec<SubLayerProfileLevel>,
+}
+
+impl ProfileTierLevel {
+    /// Parses `profile_tier_level(1, max_num_sub_layers_minus1)`.
+    pub(crate) fn parse(r: &mut BitReader, max_num_sub_layers_minus1: u8) -> Result<Self> {
+        let general_profile = ProfileInfo::parse(r)?;
+        let general_level_idc = r.read_bits(8)? as u8;
+        let mut present = Vec::new();
+        for _ in 0..max_num_sub_layers_minus1 {
+            present.push((r.read_bit()?, r.read_bit()?));
+        }
+        if max_num_sub_layers_minus1 > 0 {
+            // `reserved_zero_2bits` up to eight sub-layers.
+            r.skip_bits(2 * (8 - max_num_sub_layers_minus1 as usize))?;
+        }
+        let sub_layers = present
+            .into_iter()
+            .map(|(profile_present, level_present)| {
+                Ok(SubLayerProfileLevel {
+                    profile: profile_present.then(|| ProfileInfo::parse(r)).transpose()?,
+                    level_idc: level_present
+                        .then(|| r.read_bits(8).map(|level| level as u8))
+                        .transpose()?,
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            general_profile,
+            general_level_idc,
+            sub_layers,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bits::BitWriter;
+
+    fn write_profile(w: &mut BitWriter, profile_idc: u8, tier_flag: bool) {
+        w.write_bits(0, 2);
+        w.write_bit(tier_flag);
+        w.write_bits(profile_idc as u64, 5);
+        w.write_bits(1 << (31 - profile_idc), 32);
+        w.write_bits(0b1001, 4); // progressive, frame only
+        w.write_bits(0, 44);
+    }
+
+    #[test]
+    fn parses_sub_layers() {
+        let mut w = BitWriter::new();
+        write_profile(&mut w, PROFILE_MAIN_10, true);
+        w.write_bits(153, 8); // general_level_idc
+                              // Sub-layer 0 has both, 1 neither and 2 only a level.
+        for (profile_present, level_present) in [(true, true), (false, false), (false, true)] {
+            w.write_bit(profile_present);
+            w.write_bit(level_present);
+        }
+        w.write_bits(0, 2 * 5); // reserved_zero_2bits
+        write_profile(&mut w, PROFILE_MAIN, false);
+        w.write_bits(90, 8);
+        w.write_bits(120, 8);
+        w.write_bits(0xa5, 8);
+        let data = w.into_bytes();
+
+        let mut r = BitReader::new(&data);
+        let ptl = ProfileTierLevel::parse(&mut r, 3).unwrap();
+        assert_eq!(ptl.general_profile.profile_idc, PROFILE_MAIN_10);
+        assert!(ptl.general_profile.tier_flag);
+        assert!(ptl.general_profile.frame_only_constraint_flag);
+        assert_eq!(ptl.general_level_idc, 153);
+        let main = ptl.sub_layers[0].profile.unwrap();
+        assert!(main.is_compatible_with(PROFILE_MAIN));
+        assert!(!main.is_compatible_with(PROFILE_MAIN_10));
+        assert_eq!(
+            ptl.sub_layers[1..],
+            [
+                SubLayerProfileLevel::default(),
+                SubLayerProfileLevel {
+                    profile: None,
+                    level_idc: Some(120),
+                },
+            ]
+        );
+        assert_eq!(ptl.sub_layers[0].level_idc, Some(90));
+        assert_eq!(r.read_bits(8).unwrap(), 0xa5);
+
+        // A single layer has no sub-layer flags or reserved bits.
+        let mut r = BitReader::new(&data);
+        let ptl = ProfileTierLevel::parse(&mut r, 0).unwrap();
+        assert!(ptl.sub_layers.is_empty());
+        assert_eq!(r.read_bits(2).unwrap(), 0b11);
+    }
+}
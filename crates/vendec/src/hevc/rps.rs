@@ -0,0 +1,261 @@
+use super::{Error, ReadExt, Result};
+use crate::bits::BitReader;
+
+/// A short-term reference picture set, `st_ref_pic_set()`, with the POC
+/// deltas derived by (7-61) and (7-62) when it is predicted from another
+/// set.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ShortTermRps {
+    /// `DeltaPocS0`: the pictures before the current one, closest first.
+    pub delta_poc_s0: Vec<i32>,
+    pub used_by_curr_pic_s0: Vec<bool>,
+    /// `DeltaPocS1`: the pictures after the current one, closest first.
+    pub delta_poc_s1: Vec<i32>,
+    pub used_by_curr_pic_s1: Vec<bool>,
+}
+
+const MAX_DELTA_POC_MINUS1: u32 = (1 << 15) - 1;
+
+impl ShortTermRps {
+    /// Parses `st_ref_pic_set(stRpsIdx)`, where `stRpsIdx` is
+    /// `sets.len()` and `sets` the sets of the SPS parsed so far. Sets coded
+    /// in a slice header come after all the SPS ones and may be predicted
+    /// from any of them.
+    pub(crate) fn parse(
+        r: &mut BitReader,
+        sets: &[ShortTermRps],
+        in_slice_header: bool,
+        max_dec_pic_buffering_minus1: u8,
+    ) -> Result<Self> {
+        let idx = sets.len();
+        if idx != 0 && r.read_bit()? {
+            let delta_idx_minus1 = if in_slice_header {
+                r.read_ue_max("delta_idx_minus1", idx as u32 - 1)? as usize
+            } else {
+                0
+            };
+            let reference = &sets[idx - (delta_idx_minus1 + 1)];
+            let delta_rps_sign = r.read_bit()?;
+            let abs_delta_rps_minus1 =
+                r.read_ue_max("abs_delta_rps_minus1", MAX_DELTA_POC_MINUS1)?;
+            let delta_rps = (1 - 2 * delta_rps_sign as i32) * (abs_delta_rps_minus1 as i32 + 1);
+            let mut used_by_curr_pic_flag = Vec::new();
+            let mut use_delta_flag = Vec::new();
+            for _ in 0..=reference.num_delta_pocs() {
+                let used = r.read_bit()?;
+                used_by_curr_pic_flag.push(used);
+                use_delta_flag.push(used || r.read_bit()?);
+            }
+            let rps = Self::predict(
+                reference,
+                delta_rps,
+                &used_by_curr_pic_flag,
+                &use_delta_flag,
+            );
+            if rps.num_delta_pocs() > max_dec_pic_buffering_minus1 as usize {
+                return Err(Error::InvalidValue {
+                    name: "NumDeltaPocs",
+                    value: rps.num_delta_pocs() as i64,
+                });
+            }
+            return Ok(rps);
+        }
+
+        let max = max_dec_pic_buffering_minus1 as u32;
+        let num_negative_pics = r.read_ue_max("num_negative_pics", max)?;
+        let num_positive_pics = r.read_ue_max("num_positive_pics", max - num_negative_pics)?;
+        let mut rps = Self::default();
+        let mut poc = 0;
+        for _ in 0..num_negative_pics {
+            poc -= r.read_ue_max("delta_poc_s0_minus1", MAX_DELTA_POC_MINUS1)? as i32 + 1;
+            rps.delta_poc_s0.push(poc);
+            rps.used_by_curr_pic_s0.push(r.read_bit()?);
+        }
+        poc = 0;
+        for _ in 0..num_positive_pics {
+            poc += r.read_ue_max("delta_poc_s1_minus1", MAX_DELTA_POC_MINUS1)? as i32 + 1;
+            rps.delta_poc_s1.push(poc);
+            rps.used_by_curr_pic_s1.push(r.read_bit()?);
+        }
+        Ok(rps)
+    }
+
+    /// Inter RPS prediction, (7-61) and (7-62). The flags have
+    /// `NumDeltaPocs[RefRpsIdx] + 1` entries, the last one for the
+    /// reference set's own picture.
+    fn predict(reference: &Self, delta_rps: i32, used: &[bool], use_delta: &[bool]) -> Self {
+        let negative = reference.num_negative_pics();
+        let all = reference.num_delta_pocs();
+        let mut rps = Self::default();
+        for j in (0..reference.num_positive_pics()).rev() {
+            let d_poc = reference.delta_poc_s1[j] + delta_rps;
+            if d_poc < 0 && use_delta[negative + j] {
+                rps.delta_poc_s0.push(d_poc);
+                rps.used_by_curr_pic_s0.push(used[negative + j]);
+            }
+        }
+        if delta_rps < 0 && use_delta[all] {
+            rps.delta_poc_s0.push(delta_rps);
+            rps.used_by_curr_pic_s0.push(used[all]);
+        }
+        for j in 0..negative {
+            let d_poc = reference.delta_poc_s0[j] + delta_rps;
+            if d_poc < 0 && use_delta[j] {
+                rps.delta_poc_s0.push(d_poc);
+                rps.used_by_curr_pic_s0.push(used[j]);
+            }
+        }
+
+        for j in (0..negative).rev() {
+            let d_poc = reference.delta_poc_s0[j] + delta_rps;
+            if d_poc > 0 && use_delta[j] {
+                rps.delta_poc_s1.push(d_poc);
+                rps.used_by_curr_pic_s1.push(used[j]);
+            }
+        }
+        if delta_rps > 0 && use_delta[all] {
+            rps.delta_poc_s1.push(delta_rps);
+            rps.used_by_curr_pic_s1.push(used[all]);
+        }
+        for j in 0..reference.num_positive_pics() {
+            let d_poc = reference.delta_poc_s1[j] + delta_rps;
+            if d_poc > 0 && use_delta[negative + j] {
+                rps.delta_poc_s1.push(d_poc);
+                rps.used_by_curr_pic_s1.push(used[negative + j]);
+            }
+        }
+        rps
+    }
+
+    /// `NumNegativePics`.
+    pub fn num_negative_pics(&self) -> usize {
+        self.delta_poc_s0.len()
+    }
+
+    /// `NumPositivePics`.
+    pub fn num_positive_pics(&self) -> usize {
+        self.delta_poc_s1.len()
+    }
+
+    /// `NumDeltaPocs`.
+    pub fn num_delta_pocs(&self) -> usize {
+        self.num_negative_pics() + self.num_positive_pics()
+    }
+
+    /// Pictures of the set the current picture may reference.
+    pub fn num_used_by_curr_pic(&self) -> usize {
+        self.used_by_curr_pic_s0
+            .iter()
+            .chain(&self.used_by_curr_pic_s1)
+            .filter(|&&used| used)
+            .count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bits::BitWriter;
+
+    #[test]
+    fn predicts_sets() {
+        let mut w = BitWriter::new();
+        // Set 0: -1 and -3 explicitly, both used.
+        w.write_ue(2);
+        w.write_ue(0);
+        w.write_ue(0);
+        w.write_bit(true);
+        w.write_ue(1);
+        w.write_bit(true);
+        // Set 1: predicted from set 0 with deltaRps = +2, keeping all but
+        // the shifted -3 and using the reference picture itself.
+        w.write_bit(true); // inter_ref_pic_set_prediction_flag
+        w.write_bit(false);
+        w.write_ue(1); // abs_delta_rps_minus1
+        w.write_bit(true);
+        w.write_bit(false);
+        w.write_bit(false); // use_delta_flag
+        w.write_bit(true);
+        // Set 2, as in a slice header: predicted from set 0 with -1.
+        w.write_bit(true);
+        w.write_ue(1); // delta_idx_minus1
+        w.write_bit(true);
+        w.write_ue(0);
+        for _ in 0..3 {
+            w.write_bit(true);
+        }
+        w.write_trailing_bits();
+        let data = w.into_bytes();
+        let mut r = BitReader::new(&data);
+
+        let mut sets = Vec::new();
+        for _ in 0..2 {
+            sets.push(ShortTermRps::parse(&mut r, &sets, false, 4).unwrap());
+        }
+        assert_eq!(sets[0].delta_poc_s0, [-1, -3]);
+        assert_eq!(sets[1].delta_poc_s0, Vec::<i32>::new());
+        assert_eq!(sets[1].delta_poc_s1, [1, 2]);
+        assert_eq!(sets[1].used_by_curr_pic_s1, [true, true]);
+
+        let rps = ShortTermRps::parse(&mut r, &sets, true, 4).unwrap();
+        assert_eq!(rps.delta_poc_s0, [-1, -2, -4]);
+        assert_eq!(rps.num_used_by_curr_pic(), 3);
+
+        // Too many pictures for the DPB.
+        let mut r = BitReader::new(&data);
+        assert_eq!(
+            ShortTermRps::parse(&mut r, &[], false, 1),
+            Err(Error::InvalidValue {
+                name: "num_negative_pics",
+                value: 2
+            })
+        );
+    }
+
+    #[test]
+    fn predicts_across_the_current_picture() {
+        let mut w = BitWriter::new();
+        // Set 0: -2, -4, +1 and +3, all used.
+        w.write_ue(2);
+        w.write_ue(2);
+        for delta_poc_minus1 in [1, 1, 0, 1] {
+            w.write_ue(delta_poc_minus1);
+            w.write_bit(true);
+        }
+        // Set 1: set 0 shifted by deltaRps = -2.
+        w.write_bit(true); // inter_ref_pic_set_prediction_flag
+        w.write_bit(true); // delta_rps_sign
+        w.write_ue(1);
+        // -4 kept but unused, -6 dropped, then -1, +1 and -2 itself used.
+        w.write_bit(false);
+        w.write_bit(true); // use_delta_flag
+        w.write_bit(false);
+        w.write_bit(false);
+        for _ in 0..3 {
+            w.write_bit(true);
+        }
+        w.write_trailing_bits();
+        let data = w.into_bytes();
+
+        let mut r = BitReader::new(&data);
+        let sets = [ShortTermRps::parse(&mut r, &[], false, 4).unwrap()];
+        assert_eq!(sets[0].delta_poc_s1, [1, 3]);
+        let rps = ShortTermRps::parse(&mut r, &sets, false, 4).unwrap();
+        // Positive deltas turned negative come first, then deltaRps.
+        assert_eq!(rps.delta_poc_s0, [-1, -2, -4]);
+        assert_eq!(rps.used_by_curr_pic_s0, [true, true, false]);
+        assert_eq!(rps.delta_poc_s1, [1]);
+        assert_eq!(rps.used_by_curr_pic_s1, [true]);
+
+        // The predicted set must fit the DPB too.
+        let mut r = BitReader::new(&data);
+        ShortTermRps::parse(&mut r, &[], false, 4).unwrap();
+        assert_eq!(
+            ShortTermRps::parse(&mut r, &sets, false, 3),
+            Err(Error::InvalidValue {
+                name: "NumDeltaPocs",
+                value: 4
+            })
+        );
+    }
+}
@@ -0,0 +1,1023 @@
+use super::{
+    ceil_log2, Error, NalHeader, NalUnitType, ParameterSets, Pps, ReadExt, Result, ShortTermRps,
+    Sps,
+};
+use crate::bits::BitReader;
+use crate::nal::{nal_offset, to_rbsp};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SliceType {
+    B,
+    P,
+    I,
+}
+
+impl SliceType {
+    pub fn is_intra(self) -> bool {
+        self == SliceType::I
+    }
+
+    /// Whether slices of this type use reference picture list 0.
+    pub fn is_inter(self) -> bool {
+        !self.is_intra()
+    }
+
+    pub fn is_b(self) -> bool {
+        self == SliceType::B
+    }
+}
+
+/// A long-term reference picture of the slice header, taken from the SPS
+/// candidates or coded explicitly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LongTermPic {
+    /// `PocLsbLt`.
+    pub poc_lsb_lt: u32,
+    /// `UsedByCurrPicLt`.
+    pub used_by_curr_pic_lt: bool,
+    pub delta_poc_msb_present_flag: bool,
+    /// `DeltaPocMsbCycleLt`, accumulated as in (7-52).
+    pub delta_poc_msb_cycle_lt: u32,
+}
+
+/// The weights for one reference index of `pred_weight_table()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PredWeight {
+    pub luma_weight_flag: bool,
+    pub delta_luma_weight: i8,
+    pub luma_offset: i16,
+    pub chroma_weight_flag: bool,
+    /// Cb, then Cr.
+    pub delta_chroma_weight: [i8; 2],
+    /// `ChromaOffset`, derived from `delta_chroma_offset` by (7-56).
+    pub chroma_offset: [i16; 2],
+}
+
+/// `pred_weight_table()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PredWeightTable {
+    pub luma_log2_weight_denom: u8,
+    pub delta_chroma_log2_weight_denom: i8,
+    /// One entry per active reference index of each list.
+    pub l0: Vec<PredWeight>,
+    pub l1: Vec<PredWeight>,
+}
+
+/// `slice_segment_header()`, with the NAL header fields that go with it.
+/// Absent fields hold their inferred values; those of dependent slice
+/// segments are the ones of the independent segment before them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SliceSegmentHeader {
+    pub nal_unit_type: NalUnitType,
+    pub temporal_id: u8,
+    pub first_slice_segment_in_pic_flag: bool,
+    pub no_output_of_prior_pics_flag: bool,
+    pub slice_pic_parameter_set_id: u8,
+    pub dependent_slice_segment_flag: bool,
+    pub slice_segment_address: u32,
+    pub slice_type: SliceType,
+    pub pic_output_flag: bool,
+    pub colour_plane_id: u8,
+    pub slice_pic_order_cnt_lsb: u32,
+    pub short_term_ref_pic_set_sps_flag: bool,
+    pub short_term_ref_pic_set_idx: u8,
+    /// The short-term RPS in effect, from the SPS or the header.
+    pub short_term_ref_pic_set: ShortTermRps,
+    /// Bits taken by `st_ref_pic_set()` in the header.
+    pub short_term_ref_pic_set_bits: u32,
+    pub num_long_term_sps: u8,
+    /// `num_long_term_sps + num_long_term_pics` entries.
+    pub long_term_pics: Vec<LongTermPic>,
+    pub slice_temporal_mvp_enabled_flag: bool,
+    pub slice_sao_luma_flag: bool,
+    pub slice_sao_chroma_flag: bool,
+    pub num_ref_idx_active_override_flag: bool,
+    pub num_ref_idx_l0_active_minus1: u8,
+    pub num_ref_idx_l1_active_minus1: u8,
+    /// `list_entry_l0`, present with `ref_pic_list_modification_flag_l0`.
+    pub list_entry_l0: Option<Vec<u8>>,
+    pub list_entry_l1: Option<Vec<u8>>,
+    pub mvd_l1_zero_flag: bool,
+    pub cabac_init_flag: bool,
+    pub collocated_from_l0_flag: bool,
+    pub collocated_ref_idx: u8,
+    pub pred_weight_table: Option<PredWeightTable>,
+    pub five_minus_max_num_merge_cand: u8,
+    pub use_integer_mv_flag: bool,
+    pub slice_qp_delta: i8,
+    pub slice_cb_qp_offset: i8,
+    pub slice_cr_qp_offset: i8,
+    pub slice_act_y_qp_offset: i8,
+    pub slice_act_cb_qp_offset: i8,
+    pub slice_act_cr_qp_offset: i8,
+    pub cu_chroma_qp_offset_enabled_flag: bool,
+    pub deblocking_filter_override_flag: bool,
+    pub slice_deblocking_filter_disabled_flag: bool,
+    pub slice_beta_offset_div2: i8,
+    pub slice_tc_offset_div2: i8,
+    pub slice_loop_filter_across_slices_enabled_flag: bool,
+    /// `NumPicTotalCurr`.
+    pub num_pic_total_curr: u32,
+    pub offset_len_minus1: u8,
+    /// `num_entry_point_offsets` entries.
+    pub entry_point_offset_minus1: Vec<u32>,
+    /// Size of the NAL header and slice segment header in bits, counting
+    /// emulation prevention bytes: the offset of `slice_segment_data()` in
+    /// the NAL unit. Always a whole number of bytes.
+    pub header_bit_size: usize,
+    /// Emulation prevention bytes within the slice segment header.
+    pub header_emulation_prevention_bytes: usize,
+}
+
+impl SliceSegmentHeader {
+    /// Parses the slice segment header of `nal`, a coded slice segment NAL
+    /// unit with its header and emulation prevention bytes. The PPS the
+    /// slice refers to becomes the active one in `sets`.
+    ///
+    /// Dependent slice segments take most of their header from
+    /// `independent`, the last independent slice segment of the picture.
+    pub fn parse(
+        nal: &[u8],
+        sets: &mut ParameterSets,
+        independent: Option<&SliceSegmentHeader>,
+    ) -> Result<Self> {
+        let nal_header = NalHeader::parse(nal)?;
+        let nal_unit_type = nal_header.nal_unit_type;
+        if !nal_unit_type.is_slice() {
+            return Err(Error::Unsupported("reserved VCL NAL unit types"));
+        }
+        if nal_header.nuh_layer_id != 0 {
+            return Err(Error::Unsupported("slices of layers other than the base"));
+        }
+        let rbsp = to_rbsp(&nal[NalHeader::SIZE..]);
+        let mut r = BitReader::new(&rbsp);
+
+        let first_slice_segment_in_pic_flag = r.read_bit()?;
+        let no_output_of_prior_pics_flag = nal_unit_type.is_irap() && r.read_bit()?;
+        let slice_pic_parameter_set_id = r.read_ue_max("slice_pic_parameter_set_id", 63)?;
+        let (pps, sps) = sets.activate_pps(slice_pic_parameter_set_id)?;
+        let mut dependent_slice_segment_flag = false;
+        let mut slice_segment_address = 0;
+        if !first_slice_segment_in_pic_flag {
+            if pps.dependent_slice_segments_enabled_flag {
+                dependent_slice_segment_flag = r.read_bit()?;
+            }
+            let size = sps.pic_size_in_ctbs();
+            slice_segment_address =
+                r.read_bits_max("slice_segment_address", ceil_log2(size), size - 1)?;
+        }
+
+        let mut header = if dependent_slice_segment_flag {
+            let independent = independent.ok_or(Error::InvalidValue {
+                name: "dependent_slice_segment_flag",
+                value: 1,
+            })?;
+            Self {
+                first_slice_segment_in_pic_flag,
+                no_output_of_prior_pics_flag,
+                dependent_slice_segment_flag,
+                slice_segment_address,
+                ..independent.clone()
+            }
+        } else {
+            let mut header = Self::new(nal_header, &pps);
+            header.first_slice_segment_in_pic_flag = first_slice_segment_in_pic_flag;
+            header.no_output_of_prior_pics_flag = no_output_of_prior_pics_flag;
+            header.slice_pic_parameter_set_id = slice_pic_parameter_set_id as u8;
+            header.slice_segment_address = slice_segment_address;
+            header.parse_independent(&mut r, &pps, &sps)?;
+            header
+        };
+
+        header.offset_len_minus1 = 0;
+        header.entry_point_offset_minus1 = Vec::new();
+        if pps.tiles_enabled_flag || pps.entropy_coding_sync_enabled_flag {
+            let columns = pps.num_tile_columns_minus1 as u32 + 1;
+            let rows = pps.num_tile_rows_minus1 as u32 + 1;
+            let max = match (pps.tiles_enabled_flag, pps.entropy_coding_sync_enabled_flag) {
+                (true, false) => columns * rows - 1,
+                (false, true) => sps.pic_height_in_ctbs() - 1,
+                _ => columns * sps.pic_height_in_ctbs() - 1,
+            };
+            let num_entry_point_offsets = r.read_ue_max("num_entry_point_offsets", max)?;
+            if num_entry_point_offsets > 0 {
+                header.offset_len_minus1 = r.read_ue_max("offset_len_minus1", 31)? as u8;
+                header.entry_point_offset_minus1 = (0..num_entry_point_offsets)
+                    .map(|_| r.read_bits(header.offset_len_minus1 as u32 + 1))
+                    .collect::<std::result::Result<_, _>>()?;
+            }
+        }
+        if pps.slice_segment_header_extension_present_flag {
+            let length = r.read_ue_max("slice_segment_header_extension_length", 256)?;
+            r.skip_bits(length as usize * 8)?;
+        }
+        // byte_alignment()
+        if !r.read_bit()? {
+            return Err(Error::InvalidValue {
+                name: "alignment_bit_equal_to_one",
+                value: 0,
+            });
+        }
+        r.align();
+
+        let rbsp_size = r.position() / 8;
+        let size = nal_offset(&nal[NalHeader::SIZE..], rbsp_size);
+        header.header_bit_size = (NalHeader::SIZE + size) * 8;
+        header.header_emulation_prevention_bytes = size - rbsp_size;
+        Ok(header)
+    }
+
+    /// A header with the values inferred for absent fields.
+    fn new(nal_header: NalHeader, pps: &Pps) -> Self {
+        Self {
+            nal_unit_type: nal_header.nal_unit_type,
+            temporal_id: nal_header.temporal_id(),
+            first_slice_segment_in_pic_flag: false,
+            no_output_of_prior_pics_flag: false,
+            slice_pic_parameter_set_id: 0,
+            dependent_slice_segment_flag: false,
+            slice_segment_address: 0,
+            slice_type: SliceType::I,
+            pic_output_flag: true,
+            colour_plane_id: 0,
+            slice_pic_order_cnt_lsb: 0,
+            short_term_ref_pic_set_sps_flag: false,
+            short_term_ref_pic_set_idx: 0,
+            short_term_ref_pic_set: ShortTermRps::default(),
+            short_term_ref_pic_set_bits: 0,
+            num_long_term_sps: 0,
+            long_term_pics: Vec::new(),
+            slice_temporal_mvp_enabled_flag: false,
+            slice_sao_luma_flag: false,
+            slice_sao_chroma_flag: false,
+            num_ref_idx_active_override_flag: false,
+            num_ref_idx_l0_active_minus1: pps.num_ref_idx_l0_default_active_minus1,
+            num_ref_idx_l1_active_minus1: pps.num_ref_idx_l1_default_active_minus1,
+            list_entry_l0: None,
+            list_entry_l1: None,
+            mvd_l1_zero_flag: false,
+            cabac_init_flag: false,
+            collocated_from_l0_flag: true,
+            collocated_ref_idx: 0,
+            pred_weight_table: None,
+            five_minus_max_num_merge_cand: 0,
+            use_integer_mv_flag: false,
+            slice_qp_delta: 0,
+            slice_cb_qp_offset: 0,
+            slice_cr_qp_offset: 0,
+            slice_act_y_qp_offset: 0,
+            slice_act_cb_qp_offset: 0,
+            slice_act_cr_qp_offset: 0,
+            cu_chroma_qp_offset_enabled_flag: false,
+            deblocking_filter_override_flag: false,
+            slice_deblocking_filter_disabled_flag: pps.pps_deblocking_filter_disabled_flag,
+            slice_beta_offset_div2: pps.pps_beta_offset_div2,
+            slice_tc_offset_div2: pps.pps_tc_offset_div2,
+            slice_loop_filter_across_slices_enabled_flag: pps
+                .pps_loop_filter_across_slices_enabled_flag,
+            num_pic_total_curr: 0,
+            offset_len_minus1: 0,
+            entry_point_offset_minus1: Vec::new(),
+            header_bit_size: 0,
+            header_emulation_prevention_bytes: 0,
+        }
+    }
+
+    /// The part of the header dependent slice segments leave out.
+    fn parse_independent(&mut self, r: &mut BitReader, pps: &Pps, sps: &Sps) -> Result<()> {
+        r.skip_bits(pps.num_extra_slice_header_bits as usize)?;
+        self.slice_type = match r.read_ue_max("slice_type", 2)? {
+            0 => SliceType::B,
+            1 => SliceType::P,
+            _ => SliceType::I,
+        };
+        if self.nal_unit_type.is_irap() && !self.slice_type.is_intra() {
+            // Only intra slices, unless the current picture is a reference.
+            if !pps.scc_extension.pps_curr_pic_ref_enabled_flag {
+                return Err(Error::InvalidValue {
+                    name: "slice_type",
+                    value: self.slice_type as i64,
+                });
+            }
+        }
+        if pps.output_flag_present_flag {
+            self.pic_output_flag = r.read_bit()?;
+        }
+        if sps.separate_colour_plane_flag {
+            self.colour_plane_id = r.read_bits_max("colour_plane_id", 2, 2)? as u8;
+        }
+
+        if !self.nal_unit_type.is_idr() {
+            self.slice_pic_order_cnt_lsb =
+                r.read_bits(sps.log2_max_pic_order_cnt_lsb_minus4 as u32 + 4)?;
+            self.short_term_ref_pic_set_sps_flag = r.read_bit()?;
+            let sets = &sps.short_term_ref_pic_sets;
+            let max_dec_pic_buffering_minus1 = sps
+                .highest_sub_layer_ordering()
+                .max_dec_pic_buffering_minus1;
+            if !self.short_term_ref_pic_set_sps_flag {
+                let start = r.position();
+                self.short_term_ref_pic_set =
+                    ShortTermRps::parse(r, sets, true, max_dec_pic_buffering_minus1)?;
+                self.short_term_ref_pic_set_bits = (r.position() - start) as u32;
+            } else {
+                if sets.is_empty() {
+                    return Err(Error::InvalidValue {
+                        name: "short_term_ref_pic_set_sps_flag",
+                        value: 1,
+                    });
+                }
+                if sets.len() > 1 {
+                    let count = sets.len() as u32;
+                    self.short_term_ref_pic_set_idx =
+                        r.read_bits_max("short_term_ref_pic_set_idx", ceil_log2(count), count - 1)?
+                            as u8;
+                }
+                self.short_term_ref_pic_set =
+                    sets[self.short_term_ref_pic_set_idx as usize].clone();
+            }
+            if sps.long_term_ref_pics_present_flag {
+                self.parse_long_term_pics(r, sps, max_dec_pic_buffering_minus1)?;
+            }
+            if sps.sps_temporal_mvp_enabled_flag {
+                self.slice_temporal_mvp_enabled_flag = r.read_bit()?;
+            }
+        }
+        if sps.sample_adaptive_offset_enabled_flag {
+            self.slice_sao_luma_flag = r.read_bit()?;
+            if sps.chroma_array_type() != 0 {
+                self.slice_sao_chroma_flag = r.read_bit()?;
+            }
+        }
+
+        self.num_pic_total_curr = self.short_term_ref_pic_set.num_used_by_curr_pic() as u32
+            + self
+                .long_term_pics
+                .iter()
+                .filter(|pic| pic.used_by_curr_pic_lt)
+                .count() as u32
+            + pps.scc_extension.pps_curr_pic_ref_enabled_flag as u32;
+        if self.slice_type.is_inter() {
+            self.parse_inter(r, pps, sps)?;
+        }
+
+        let qp_bd_offset = 6 * sps.bit_depth_luma_minus8 as i32;
+        let init_qp = 26 + pps.init_qp_minus26 as i32;
+        self.slice_qp_delta =
+            r.read_se_range("slice_qp_delta", -qp_bd_offset - init_qp, 51 - init_qp)? as i8;
+        if pps.pps_slice_chroma_qp_offsets_present_flag {
+            self.slice_cb_qp_offset = r.read_se_range("slice_cb_qp_offset", -12, 12)? as i8;
+            self.slice_cr_qp_offset = r.read_se_range("slice_cr_qp_offset", -12, 12)? as i8;
+        }
+        if pps.scc_extension.pps_slice_act_qp_offsets_present_flag {
+            self.slice_act_y_qp_offset = r.read_se_range("slice_act_y_qp_offset", -12, 12)? as i8;
+            self.slice_act_cb_qp_offset = r.read_se_range("slice_act_cb_qp_offset", -12, 12)? as i8;
+            self.slice_act_cr_qp_offset = r.read_se_range("slice_act_cr_qp_offset", -12, 12)? as i8;
+        }
+        if pps.range_extension.chroma_qp_offset_list_enabled_flag {
+            self.cu_chroma_qp_offset_enabled_flag = r.read_bit()?;
+        }
+        if pps.deblocking_filter_override_enabled_flag {
+            self.deblocking_filter_override_flag = r.read_bit()?;
+        }
+        if self.deblocking_filter_override_flag {
+            self.slice_deblocking_filter_disabled_flag = r.read_bit()?;
+            if !self.slice_deblocking_filter_disabled_flag {
+                self.slice_beta_offset_div2 =
+                    r.read_se_range("slice_beta_offset_div2", -6, 6)? as i8;
+                self.slice_tc_offset_div2 = r.read_se_range("slice_tc_offset_div2", -6, 6)? as i8;
+            }
+        }
+        if pps.pps_loop_filter_across_slices_enabled_flag
+            && (self.slice_sao_luma_flag
+                || self.slice_sao_chroma_flag
+                || !self.slice_deblocking_filter_disabled_flag)
+        {
+            self.slice_loop_filter_across_slices_enabled_flag = r.read_bit()?;
+        }
+        Ok(())
+    }
+
+    fn parse_long_term_pics(
+        &mut self,
+        r: &mut BitReader,
+        sps: &Sps,
+        max_dec_pic_buffering_minus1: u8,
+    ) -> Result<()> {
+        let candidates = &sps.long_term_ref_pics;
+        if !candidates.is_empty() {
+            self.num_long_term_sps =
+                r.read_ue_max("num_long_term_sps", candidates.len() as u32)? as u8;
+        }
+        let max = (max_dec_pic_buffering_minus1 as u32)
+            .saturating_sub(self.short_term_ref_pic_set.num_delta_pocs() as u32)
+            .saturating_sub(self.num_long_term_sps as u32);
+        let num_long_term_pics = r.read_ue_max("num_long_term_pics", max)?;
+        let log2_max_poc_lsb = sps.log2_max_pic_order_cnt_lsb_minus4 as u32 + 4;
+        for i in 0..self.num_long_term_sps as u32 + num_long_term_pics {
+            let (poc_lsb_lt, used_by_curr_pic_lt) = if i < self.num_long_term_sps as u32 {
+                let count = candidates.len() as u32;
+                let lt_idx_sps = if count > 1 {
+                    r.read_bits_max("lt_idx_sps", ceil_log2(count), count - 1)?
+                } else {
+                    0
+                };
+                let candidate = &candidates[lt_idx_sps as usize];
+                (
+                    candidate.lt_ref_pic_poc_lsb_sps,
+                    candidate.used_by_curr_pic_lt_sps_flag,
+                )
+            } else {
+                (r.read_bits(log2_max_poc_lsb)?, r.read_bit()?)
+            };
+            let delta_poc_msb_present_flag = r.read_bit()?;
+            let mut delta_poc_msb_cycle_lt = 0;
+            if delta_poc_msb_present_flag {
+                delta_poc_msb_cycle_lt =
+                    r.read_ue_max("delta_poc_msb_cycle_lt", (1 << (32 - log2_max_poc_lsb)) - 1)?;
+            }
+            if i != 0 && i != self.num_long_term_sps as u32 {
+                let previous = self.long_term_pics.last().unwrap();
+                delta_poc_msb_cycle_lt =
+                    delta_poc_msb_cycle_lt.saturating_add(previous.delta_poc_msb_cycle_lt);
+            }
+            self.long_term_pics.push(LongTermPic {
+                poc_lsb_lt,
+                used_by_curr_pic_lt,
+                delta_poc_msb_present_flag,
+                delta_poc_msb_cycle_lt,
+            });
+        }
+        Ok(())
+    }
+
+    /// From `num_ref_idx_active_override_flag` to
+    /// `use_integer_mv_flag`, for P and B slices.
+    fn parse_inter(&mut self, r: &mut BitReader, pps: &Pps, sps: &Sps) -> Result<()> {
+        if self.num_pic_total_curr == 0 {
+            return Err(Error::InvalidValue {
+                name: "NumPicTotalCurr",
+                value: 0,
+            });
+        }
+        self.num_ref_idx_active_override_flag = r.read_bit()?;
+        if self.num_ref_idx_active_override_flag {
+            self.num_ref_idx_l0_active_minus1 =
+                r.read_ue_max("num_ref_idx_l0_active_minus1", 14)? as u8;
+            if self.slice_type.is_b() {
+                self.num_ref_idx_l1_active_minus1 =
+                    r.read_ue_max("num_ref_idx_l1_active_minus1", 14)? as u8;
+            }
+        }
+        if pps.lists_modification_present_flag && self.num_pic_total_curr > 1 {
+            let bits = ceil_log2(self.num_pic_total_curr);
+            let max = self.num_pic_total_curr - 1;
+            let mut parse_list = |count_minus1: u8| -> Result<Option<Vec<u8>>> {
+                if !r.read_bit()? {
+                    return Ok(None);
+                }
+                (0..=count_minus1)
+                    .map(|_| Ok(r.read_bits_max("list_entry", bits, max)? as u8))
+                    .collect::<Result<_>>()
+                    .map(Some)
+            };
+            self.list_entry_l0 = parse_list(self.num_ref_idx_l0_active_minus1)?;
+            if self.slice_type.is_b() {
+                self.list_entry_l1 = parse_list(self.num_ref_idx_l1_active_minus1)?;
+            }
+        }
+        if self.slice_type.is_b() {
+            self.mvd_l1_zero_flag = r.read_bit()?;
+        }
+        if pps.cabac_init_present_flag {
+            self.cabac_init_flag = r.read_bit()?;
+        }
+        if self.slice_temporal_mvp_enabled_flag {
+            if self.slice_type.is_b() {
+                self.collocated_from_l0_flag = r.read_bit()?;
+            }
+            let max = if self.collocated_from_l0_flag {
+                self.num_ref_idx_l0_active_minus1
+            } else {
+                self.num_ref_idx_l1_active_minus1
+            };
+            if max > 0 {
+                self.collocated_ref_idx = r.read_ue_max("collocated_ref_idx", max as u32)? as u8;
+            }
+        }
+        if (pps.weighted_pred_flag && self.slice_type == SliceType::P)
+            || (pps.weighted_bipred_flag && self.slice_type.is_b())
+        {
+            if pps.scc_extension.pps_curr_pic_ref_enabled_flag {
+                // Which entries are the current picture decides which
+                // weights are coded, and that needs the reference lists.
+                return Err(Error::Unsupported(
+                    "weighted prediction with current picture referencing",
+                ));
+            }
+            self.pred_weight_table = Some(self.parse_pred_weight_table(r, sps)?);
+        }
+        self.five_minus_max_num_merge_cand =
+            r.read_ue_max("five_minus_max_num_merge_cand", 4)? as u8;
+        match sps.scc_extension.motion_vector_resolution_control_idc {
+            2 => self.use_integer_mv_flag = r.read_bit()?,
+            idc => self.use_integer_mv_flag = idc == 1,
+        }
+        Ok(())
+    }
+
+    fn parse_pred_weight_table(&self, r: &mut BitReader, sps: &Sps) -> Result<PredWeightTable> {
+        let chroma = sps.chroma_array_type() != 0;
+        let luma_log2_weight_denom = r.read_ue_max("luma_log2_weight_denom", 7)? as u8;
+        let mut delta_chroma_log2_weight_denom = 0;
+        if chroma {
+            delta_chroma_log2_weight_denom = r.read_se_range(
+                "delta_chroma_log2_weight_denom",
+                -(luma_log2_weight_denom as i32),
+                7 - luma_log2_weight_denom as i32,
+            )? as i8;
+        }
+        let chroma_log2_weight_denom =
+            (luma_log2_weight_denom as i32 + delta_chroma_log2_weight_denom as i32) as u32;
+        let high_precision = sps.range_extension.high_precision_offsets_enabled_flag;
+        // WpOffsetHalfRangeY and WpOffsetHalfRangeC.
+        let half_range = |bit_depth: u32| 1i32 << if high_precision { bit_depth - 1 } else { 7 };
+        let half_range_y = half_range(sps.bit_depth_luma());
+        let half_range_c = half_range(sps.bit_depth_chroma());
+
+        let mut parse_list = |count_minus1: u8| -> Result<Vec<PredWeight>> {
+            let count = count_minus1 as usize + 1;
+            let mut weights = vec![PredWeight::default(); count];
+            for weight in &mut weights {
+                weight.luma_weight_flag = r.read_bit()?;
+            }
+            if chroma {
+                for weight in &mut weights {
+                    weight.chroma_weight_flag = r.read_bit()?;
+                }
+            }
+            for weight in &mut weights {
+                if weight.luma_weight_flag {
+                    weight.delta_luma_weight =
+                        r.read_se_range("delta_luma_weight", -128, 127)? as i8;
+                    weight.luma_offset =
+                        r.read_se_range("luma_offset", -half_range_y, half_range_y - 1)? as i16;
+                }
+                if weight.chroma_weight_flag {
+                    for j in 0..2 {
+                        weight.delta_chroma_weight[j] =
+                            r.read_se_range("delta_chroma_weight", -128, 127)? as i8;
+                        let delta_chroma_offset = r.read_se_range(
+                            "delta_chroma_offset",
+                            -4 * half_range_c,
+                            4 * half_range_c - 1,
+                        )?;
+                        let chroma_weight =
+                            (1 << chroma_log2_weight_denom) + weight.delta_chroma_weight[j] as i32;
+                        weight.chroma_offset[j] = (half_range_c
+                            - ((half_range_c * chroma_weight) >> chroma_log2_weight_denom)
+                            + delta_chroma_offset)
+                            .clamp(-half_range_c, half_range_c - 1)
+                            as i16;
+                    }
+                }
+            }
+            Ok(weights)
+        };
+        let l0 = parse_list(self.num_ref_idx_l0_active_minus1)?;
+        let l1 = if self.slice_type.is_b() {
+            parse_list(self.num_ref_idx_l1_active_minus1)?
+        } else {
+            Vec::new()
+        };
+        Ok(PredWeightTable {
+            luma_log2_weight_denom,
+            delta_chroma_log2_weight_denom,
+            l0,
+            l1,
+        })
+    }
+
+    /// `SliceQpY`.
+    pub fn slice_qp(&self, pps: &Pps) -> i32 {
+        26 + pps.init_qp_minus26 as i32 + self.slice_qp_delta as i32
+    }
+
+    /// `num_entry_point_offsets`.
+    pub fn num_entry_point_offsets(&self) -> u32 {
+        self.entry_point_offset_minus1.len() as u32
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::bits::BitWriter;
+
+    /// A 64x64 4:2:0 SPS with 16x16 CTBs, one short-term set and one
+    /// long-term candidate, and a PPS with weighted prediction, list
+    /// modification, WPP and header extensions.
+    fn parameter_sets() -> ParameterSets {
+        let mut w = BitWriter::new();
+        w.write_bits(0, 4);
+        w.write_bits(0, 3); // sps_max_sub_layers_minus1
+        w.write_bit(true);
+        w.write_bits(1, 8); // general_profile_idc
+        w.write_bits(1 << 30, 32);
+        w.write_bits(0, 48);
+        w.write_bits(93, 8);
+        w.write_ue(0); // sps_seq_parameter_set_id
+        w.write_ue(1);
+        w.write_ue(64);
+        w.write_ue(64);
+        w.write_bit(false);
+        w.write_ue(0);
+        w.write_ue(0);
+        w.write_ue(4); // log2_max_pic_order_cnt_lsb_minus4
+        w.write_bit(false);
+        w.write_ue(4);
+        w.write_ue(2);
+        w.write_ue(0);
+        w.write_ue(0); // log2_min_luma_coding_block_size_minus3
+        w.write_ue(1);
+        w.write_ue(0);
+        w.write_ue(2);
+        w.write_ue(1);
+        w.write_ue(1);
+        w.write_bit(false); // scaling_list_enabled_flag
+        w.write_bit(false);
+        w.write_bit(true); // sample_adaptive_offset_enabled_flag
+        w.write_bit(false);
+        w.write_ue(1); // num_short_term_ref_pic_sets
+        w.write_ue(1);
+        w.write_ue(0);
+        w.write_ue(0);
+        w.write_bit(true);
+        w.write_bit(true); // long_term_ref_pics_present_flag
+        w.write_ue(1);
+        w.write_bits(20, 8);
+        w.write_bit(true);
+        w.write_bit(true); // sps_temporal_mvp_enabled_flag
+        w.write_bits(0, 3);
+        w.write_trailing_bits();
+        let mut sets = ParameterSets::new();
+        sets.add_sps(&w.into_bytes()).unwrap();
+
+        let mut w = BitWriter::new();
+        w.write_ue(0);
+        w.write_ue(0);
+        w.write_bit(true); // dependent_slice_segments_enabled_flag
+        w.write_bit(true);
+        w.write_bits(1, 3); // num_extra_slice_header_bits
+        w.write_bit(false);
+        w.write_bit(true); // cabac_init_present_flag
+        w.write_ue(0);
+        w.write_ue(0);
+        w.write_se(0);
+        w.write_bits(0, 3); // constrained_intra_pred_flag to cu_qp_delta_enabled_flag
+        w.write_se(0);
+        w.write_se(0);
+        w.write_bit(true); // pps_slice_chroma_qp_offsets_present_flag
+        w.write_bit(true);
+        w.write_bit(true);
+        w.write_bits(0, 2);
+        w.write_bit(true); // entropy_coding_sync_enabled_flag
+        w.write_bit(true);
+        w.write_bit(true); // deblocking_filter_control_present_flag
+        w.write_bit(true);
+        w.write_bit(false);
+        w.write_se(0);
+        w.write_se(0);
+        w.write_bit(false);
+        w.write_bit(true); // lists_modification_present_flag
+        w.write_ue(0);
+        w.write_bit(true);
+        w.write_bit(false);
+        w.write_trailing_bits();
+        sets.add_pps(&w.into_bytes()).unwrap();
+        sets
+    }
+
+    /// The SPS of [`parameter_sets`]: 8-bit POC LSBs, five picture buffers
+    /// and up to two pictures reordered.
+    pub(crate) fn sps() -> Sps {
+        parameter_sets().sps(0).unwrap().as_ref().clone()
+    }
+
+    /// The first slice segment of a picture with no reference pictures.
+    pub(crate) fn header(
+        nal_unit_type: NalUnitType,
+        slice_type: SliceType,
+        pic_order_cnt_lsb: u32,
+    ) -> SliceSegmentHeader {
+        let sets = parameter_sets();
+        let nal_header = NalHeader {
+            nal_unit_type,
+            nuh_layer_id: 0,
+            nuh_temporal_id_plus1: 1,
+        };
+        SliceSegmentHeader {
+            first_slice_segment_in_pic_flag: true,
+            slice_type,
+            slice_pic_order_cnt_lsb: pic_order_cnt_lsb,
+            num_ref_idx_l0_active_minus1: 0,
+            num_ref_idx_l1_active_minus1: 0,
+            ..SliceSegmentHeader::new(nal_header, sets.pps(0).unwrap())
+        }
+    }
+
+    /// A TRAIL_R B slice exercising most of the inter part of the header,
+    /// and the offset of its slice data.
+    fn b_slice() -> (Vec<u8>, usize) {
+        let mut w = BitWriter::with_emulation_prevention();
+        w.write_bits(0x0201, 16);
+        w.write_bit(true); // first_slice_segment_in_pic_flag
+        w.write_ue(0);
+        w.write_bit(false); // slice_reserved_flag
+        w.write_ue(0); // slice_type
+        w.write_bit(false);
+        w.write_bits(40, 8);
+        w.write_bit(false); // short_term_ref_pic_set_sps_flag
+        w.write_bit(false);
+        w.write_ue(1);
+        w.write_ue(1);
+        w.write_ue(1);
+        w.write_bit(true);
+        w.write_ue(0);
+        w.write_bit(true);
+        w.write_ue(1); // num_long_term_sps
+        w.write_ue(1);
+        w.write_bit(true);
+        w.write_ue(1);
+        w.write_bits(30, 8); // poc_lsb_lt
+        w.write_bit(true);
+        w.write_bit(true);
+        w.write_ue(2);
+        w.write_bit(true); // slice_temporal_mvp_enabled_flag
+        w.write_bit(true);
+        w.write_bit(false);
+        w.write_bit(true); // num_ref_idx_active_override_flag
+        w.write_ue(1);
+        w.write_ue(0);
+        w.write_bit(true); // ref_pic_list_modification_flag_l0
+        w.write_bits(3, 2);
+        w.write_bits(0, 2);
+        w.write_bit(false);
+        w.write_bit(true); // mvd_l1_zero_flag
+        w.write_bit(false);
+        w.write_bit(false); // collocated_from_l0_flag
+        w.write_ue(6); // luma_log2_weight_denom
+        w.write_se(-1);
+        w.write_bit(true);
+        w.write_bit(false);
+        w.write_bit(false);
+        w.write_bit(true);
+        w.write_se(3); // delta_luma_weight_l0
+        w.write_se(-5);
+        for value in [2, 10, -1, -10] {
+            w.write_se(value);
+        }
+        w.write_bit(false);
+        w.write_bit(false);
+        w.write_ue(2); // five_minus_max_num_merge_cand
+        w.write_se(3);
+        w.write_se(-1);
+        w.write_se(2);
+        w.write_bit(true); // deblocking_filter_override_flag
+        w.write_bit(true);
+        w.write_bit(false);
+        w.write_ue(3); // num_entry_point_offsets
+        w.write_ue(9);
+        for offset in [99, 500, 1023] {
+            w.write_bits(offset, 10);
+        }
+        w.write_ue(4); // slice_segment_header_extension_length
+        w.write_bits(0, 32);
+        w.write_trailing_bits();
+        let data_offset = w.position() / 8;
+        w.write_bits(0x00_0001, 24);
+        (w.into_bytes(), data_offset)
+    }
+
+    #[test]
+    fn parses_inter_slice() {
+        let mut sets = parameter_sets();
+        let (nal, data_offset) = b_slice();
+        let header = SliceSegmentHeader::parse(&nal, &mut sets, None).unwrap();
+        assert_eq!(header.nal_unit_type, NalUnitType::TrailR);
+        assert_eq!(header.slice_type, SliceType::B);
+        assert!(!header.pic_output_flag);
+        assert_eq!(header.slice_pic_order_cnt_lsb, 40);
+        assert_eq!(header.short_term_ref_pic_set.delta_poc_s0, [-2]);
+        assert_eq!(header.short_term_ref_pic_set.delta_poc_s1, [1]);
+        assert_eq!(header.short_term_ref_pic_set_bits, 13);
+        assert_eq!(header.num_long_term_sps, 1);
+        assert_eq!(
+            header.long_term_pics,
+            [
+                LongTermPic {
+                    poc_lsb_lt: 20,
+                    used_by_curr_pic_lt: true,
+                    delta_poc_msb_present_flag: true,
+                    delta_poc_msb_cycle_lt: 1,
+                },
+                LongTermPic {
+                    poc_lsb_lt: 30,
+                    used_by_curr_pic_lt: true,
+                    delta_poc_msb_present_flag: true,
+                    delta_poc_msb_cycle_lt: 2,
+                },
+            ]
+        );
+        assert_eq!(header.num_pic_total_curr, 4);
+        assert_eq!(header.num_ref_idx_l0_active_minus1, 1);
+        assert_eq!(header.list_entry_l0, Some(vec![3, 0]));
+        assert_eq!(header.list_entry_l1, None);
+        assert!(header.mvd_l1_zero_flag);
+        assert!(!header.collocated_from_l0_flag);
+
+        let weights = header.pred_weight_table.as_ref().unwrap();
+        assert_eq!(weights.luma_log2_weight_denom, 6);
+        assert_eq!(weights.delta_chroma_log2_weight_denom, -1);
+        assert_eq!(
+            (weights.l0[0].delta_luma_weight, weights.l0[0].luma_offset),
+            (3, -5)
+        );
+        assert!(!weights.l0[0].chroma_weight_flag);
+        assert_eq!(weights.l0[1].delta_chroma_weight, [2, -1]);
+        // 128 - ((128 * (32 + 2)) >> 5) + 10 and 128 - ((128 * 31) >> 5) - 10.
+        assert_eq!(weights.l0[1].chroma_offset, [2, -6]);
+        assert_eq!(weights.l1.len(), 1);
+
+        assert_eq!(header.five_minus_max_num_merge_cand, 2);
+        assert_eq!(header.slice_qp(sets.active_pps().unwrap()), 29);
+        assert_eq!(
+            (header.slice_cb_qp_offset, header.slice_cr_qp_offset),
+            (-1, 2)
+        );
+        assert!(header.slice_deblocking_filter_disabled_flag);
+        assert!(!header.slice_loop_filter_across_slices_enabled_flag);
+        assert_eq!(header.entry_point_offset_minus1, [99, 500, 1023]);
+        assert_eq!(header.header_bit_size, data_offset * 8);
+        assert_eq!(header.header_emulation_prevention_bytes, 1);
+
+        let mut w = BitWriter::new();
+        w.write_bits(0x0201, 16);
+        w.write_bit(false);
+        w.write_ue(0);
+        w.write_bit(true); // dependent_slice_segment_flag
+        w.write_bits(8, 4);
+        w.write_ue(0);
+        w.write_ue(0);
+        w.write_trailing_bits();
+        let nal = w.into_bytes();
+        assert_eq!(
+            SliceSegmentHeader::parse(&nal, &mut sets, None),
+            Err(Error::InvalidValue {
+                name: "dependent_slice_segment_flag",
+                value: 1
+            })
+        );
+        let dependent = SliceSegmentHeader::parse(&nal, &mut sets, Some(&header)).unwrap();
+        assert!(dependent.dependent_slice_segment_flag);
+        assert_eq!(dependent.slice_segment_address, 8);
+        assert_eq!(dependent.pred_weight_table, header.pred_weight_table);
+        assert_eq!(dependent.num_entry_point_offsets(), 0);
+        assert_eq!(dependent.header_bit_size, nal.len() * 8);
+    }
+
+    #[test]
+    fn parses_idr_slice() {
+        let mut sets = parameter_sets();
+        let write = |slice_type| {
+            let mut w = BitWriter::new();
+            w.write_bits(0x2601, 16);
+            w.write_bit(true);
+            w.write_bit(true); // no_output_of_prior_pics_flag
+            w.write_ue(0);
+            w.write_bit(false);
+            w.write_ue(slice_type);
+            w.write_bit(true);
+            w.write_bit(true); // slice_sao_luma_flag
+            w.write_bit(false);
+            w.write_se(-2);
+            w.write_se(0);
+            w.write_se(0);
+            w.write_bit(false);
+            w.write_bit(true);
+            w.write_ue(0);
+            w.write_ue(0);
+            w.write_trailing_bits();
+            w.into_bytes()
+        };
+        let header = SliceSegmentHeader::parse(&write(2), &mut sets, None).unwrap();
+        assert_eq!(header.nal_unit_type, NalUnitType::IdrWRadl);
+        assert!(header.no_output_of_prior_pics_flag);
+        assert_eq!(header.slice_type, SliceType::I);
+        assert_eq!(header.short_term_ref_pic_set, ShortTermRps::default());
+        assert!(header.slice_loop_filter_across_slices_enabled_flag);
+        assert_eq!(header.slice_qp(sets.active_pps().unwrap()), 24);
+        assert_eq!(
+            SliceSegmentHeader::parse(&write(1), &mut sets, None),
+            Err(Error::InvalidValue {
+                name: "slice_type",
+                value: 1
+            })
+        );
+    }
+
+    /// A TRAIL_R P slice with POC LSB 41 and three active references,
+    /// taking its short-term set from the SPS, or coding an empty one.
+    fn p_slice(sps_rps: bool) -> Vec<u8> {
+        let mut w = BitWriter::new();
+        w.write_bits(0x0201, 16);
+        w.write_bit(true);
+        w.write_ue(0);
+        w.write_bit(false);
+        w.write_ue(1); // slice_type
+        w.write_bit(true); // pic_output_flag
+        w.write_bits(41, 8);
+        w.write_bit(sps_rps); // short_term_ref_pic_set_sps_flag
+        if !sps_rps {
+            w.write_bit(false); // inter_ref_pic_set_prediction_flag
+            w.write_ue(0);
+            w.write_ue(0);
+        }
+        w.write_ue(0); // num_long_term_sps
+        w.write_ue(0);
+        w.write_bit(true); // slice_temporal_mvp_enabled_flag
+        w.write_bit(false);
+        w.write_bit(false);
+        w.write_bit(true); // num_ref_idx_active_override_flag
+        w.write_ue(2);
+        w.write_bit(true); // cabac_init_flag
+        w.write_ue(2); // collocated_ref_idx
+        w.write_ue(0); // luma_log2_weight_denom
+        w.write_se(0);
+        w.write_bits(0, 6);
+        w.write_ue(0);
+        w.write_se(-4); // slice_qp_delta
+        w.write_se(0);
+        w.write_se(0);
+        w.write_bit(false); // deblocking_filter_override_flag
+        w.write_bit(false);
+        w.write_ue(0); // num_entry_point_offsets
+        w.write_ue(0);
+        w.write_trailing_bits();
+        w.into_bytes()
+    }
+
+    #[test]
+    fn parses_slice_with_sps_rps() {
+        let mut sets = parameter_sets();
+        let header = SliceSegmentHeader::parse(&p_slice(true), &mut sets, None).unwrap();
+        assert_eq!(header.slice_type, SliceType::P);
+        assert!(header.pic_output_flag);
+        assert!(header.short_term_ref_pic_set_sps_flag);
+        assert_eq!(header.short_term_ref_pic_set_idx, 0);
+        assert_eq!(
+            header.short_term_ref_pic_set,
+            sets.sps(0).unwrap().short_term_ref_pic_sets[0]
+        );
+        assert_eq!(header.short_term_ref_pic_set_bits, 0);
+        assert!(header.long_term_pics.is_empty());
+        assert_eq!(header.num_pic_total_curr, 1);
+        assert_eq!(header.num_ref_idx_l0_active_minus1, 2);
+        // Lists are only modified with more than one picture to pick from.
+        assert_eq!(header.list_entry_l0, None);
+        assert!(header.cabac_init_flag);
+        assert!(header.collocated_from_l0_flag);
+        assert_eq!(header.collocated_ref_idx, 2);
+        let weights = header.pred_weight_table.as_ref().unwrap();
+        assert_eq!(weights.l0.len(), 3);
+        assert!(weights.l1.is_empty());
+        assert!(!header.slice_loop_filter_across_slices_enabled_flag);
+        assert_eq!(header.slice_qp(sets.active_pps().unwrap()), 22);
+
+        // A P slice needs a picture to reference.
+        assert_eq!(
+            SliceSegmentHeader::parse(&p_slice(false), &mut sets, None),
+            Err(Error::InvalidValue {
+                name: "NumPicTotalCurr",
+                value: 0
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_slices() {
+        let (nal, data_offset) = b_slice();
+        for len in 0..data_offset {
+            let mut sets = parameter_sets();
+            assert!(
+                SliceSegmentHeader::parse(&nal[..len], &mut sets, None).is_err(),
+                "length {}",
+                len
+            );
+        }
+        assert_eq!(
+            SliceSegmentHeader::parse(&nal, &mut ParameterSets::new(), None),
+            Err(Error::MissingPps(0))
+        );
+    }
+}
@@ -0,0 +1,1128 @@
+use super::{check, Error, ProfileTierLevel, ReadExt, Result, ShortTermRps};
+use crate::bits::BitReader;
+use crate::h264::Rect;
+
+/// Table 7-6 for intra 8x8 to 32x32 lists, in up-right diagonal scan order
+/// like all scaling lists here.
+#[rustfmt::skip]
+pub const DEFAULT_8X8_INTRA: [u8; 64] = [
+    16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 17, 16, 17, 16, 17, 18,
+    17, 18, 18, 17, 18, 21, 19, 20, 21, 20, 19, 21, 24, 22, 22, 24,
+    24, 22, 22, 24, 25, 25, 27, 30, 27, 25, 25, 29, 31, 35, 35, 31,
+    29, 36, 41, 44, 41, 36, 47, 54, 54, 47, 65, 70, 65, 88, 88, 115,
+];
+#[rustfmt::skip]
+pub const DEFAULT_8X8_INTER: [u8; 64] = [
+    16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 17, 17, 17, 17, 17, 18,
+    18, 18, 18, 18, 18, 20, 20, 20, 20, 20, 20, 20, 24, 24, 24, 24,
+    24, 24, 24, 24, 25, 25, 25, 25, 25, 25, 25, 28, 28, 28, 28, 28,
+    28, 33, 33, 33, 33, 33, 41, 41, 41, 41, 54, 54, 54, 71, 71, 91,
+];
+
+/// The scaling lists of `scaling_list_data()`, in up-right diagonal scan
+/// order. Each size has intra Y, Cb, Cr, then inter Y, Cb, Cr; lists of
+/// 16x16 and 32x32 blocks are upsampled from their 8x8 coefficients and a
+/// separate DC value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScalingLists {
+    pub list_4x4: [[u8; 16]; 6],
+    pub list_8x8: [[u8; 64]; 6],
+    pub list_16x16: [[u8; 64]; 6],
+    /// Only the Y lists are coded; the chroma ones, used for 4:4:4, are
+    /// those of 16x16 blocks.
+    pub list_32x32: [[u8; 64]; 6],
+    pub dc_16x16: [u8; 6],
+    pub dc_32x32: [u8; 6],
+}
+
+impl Default for ScalingLists {
+    /// Flat lists, in effect when `scaling_list_enabled_flag` is not set.
+    fn default() -> Self {
+        Self {
+            list_4x4: [[16; 16]; 6],
+            list_8x8: [[16; 64]; 6],
+            list_16x16: [[16; 64]; 6],
+            list_32x32: [[16; 64]; 6],
+            dc_16x16: [16; 6],
+            dc_32x32: [16; 6],
+        }
+    }
+}
+
+impl ScalingLists {
+    /// The default lists of tables 7-5 and 7-6, used when scaling lists
+    /// are enabled but not coded.
+    pub fn defaults() -> Self {
+        let mut lists = Self::default();
+        for matrix_id in 0..6 {
+            let default = default_list(matrix_id);
+            lists.list_8x8[matrix_id] = default;
+            lists.list_16x16[matrix_id] = default;
+            lists.list_32x32[matrix_id] = default;
+        }
+        lists
+    }
+
+    /// `scaling_list_data()`.
+    pub(crate) fn parse(r: &mut BitReader) -> Result<Self> {
+        let mut lists = Self::defaults();
+        for size_id in 0..4 {
+            let step = if size_id == 3 { 3 } else { 1 };
+            for matrix_id in (0..6).step_by(step) {
+                if !r.read_bit()? {
+                    // scaling_list_pred_mode_flag unset: a copy.
+                    let delta = r.read_ue_max(
+                        "scaling_list_pred_matrix_id_delta",
+                        (matrix_id / step) as u32,
+                    )? as usize;
+                    if delta != 0 {
+                        lists.copy(size_id, matrix_id, matrix_id - delta * step);
+                    } else if size_id == 0 {
+                        lists.list_4x4[matrix_id] = [16; 16];
+                    } else {
+                        lists.set(size_id, matrix_id, &default_list(matrix_id), 16);
+                    }
+                    continue;
+                }
+                let mut next_coef = 8;
+                if size_id == 0 {
+                    for coef in lists.list_4x4[matrix_id].iter_mut() {
+                        next_coef = read_coef(r, next_coef)?;
+                        *coef = next_coef as u8;
+                    }
+                    continue;
+                }
+                let mut dc = 16;
+                if size_id > 1 {
+                    dc = r.read_se_range("scaling_list_dc_coef_minus8", -7, 247)? + 8;
+                    next_coef = dc;
+                }
+                let mut list = [0; 64];
+                for coef in list.iter_mut() {
+                    next_coef = read_coef(r, next_coef)?;
+                    *coef = next_coef as u8;
+                }
+                lists.set(size_id, matrix_id, &list, dc as u8);
+            }
+        }
+        // The 4:4:4 chroma 32x32 lists (7.3.4).
+        for matrix_id in [1, 2, 4, 5] {
+            lists.list_32x32[matrix_id] = lists.list_16x16[matrix_id];
+            lists.dc_32x32[matrix_id] = lists.dc_16x16[matrix_id];
+        }
+        Ok(lists)
+    }
+
+    fn set(&mut self, size_id: usize, matrix_id: usize, list: &[u8; 64], dc: u8) {
+        match size_id {
+            1 => self.list_8x8[matrix_id] = *list,
+            2 => {
+                self.list_16x16[matrix_id] = *list;
+                self.dc_16x16[matrix_id] = dc;
+            }
+            _ => {
+                self.list_32x32[matrix_id] = *list;
+                self.dc_32x32[matrix_id] = dc;
+            }
+        }
+    }
+
+    fn copy(&mut self, size_id: usize, matrix_id: usize, ref_matrix_id: usize) {
+        match size_id {
+            0 => self.list_4x4[matrix_id] = self.list_4x4[ref_matrix_id],
+            1 => self.list_8x8[matrix_id] = self.list_8x8[ref_matrix_id],
+            2 => {
+                self.list_16x16[matrix_id] = self.list_16x16[ref_matrix_id];
+                self.dc_16x16[matrix_id] = self.dc_16x16[ref_matrix_id];
+            }
+            _ => {
+                self.list_32x32[matrix_id] = self.list_32x32[ref_matrix_id];
+                self.dc_32x32[matrix_id] = self.dc_32x32[ref_matrix_id];
+            }
+        }
+    }
+}
+
+fn default_list(matrix_id: usize) -> [u8; 64] {
+    if matrix_id < 3 {
+        DEFAULT_8X8_INTRA
+    } else {
+        DEFAULT_8X8_INTER
+    }
+}
+
+/// One `scaling_list_delta_coef` step.
+fn read_coef(r: &mut BitReader, next_coef: i32) -> Result<i32> {
+    let delta = r.read_se_range("scaling_list_delta_coef", -128, 127)?;
+    Ok((next_coef + delta + 256) % 256)
+}
+
+/// `sps_max_dec_pic_buffering_minus1`, `sps_max_num_reorder_pics` and
+/// `sps_max_latency_increase_plus1`, or their VPS counterparts, for one
+/// sub-layer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SubLayerOrdering {
+    pub max_dec_pic_buffering_minus1: u8,
+    pub max_num_reorder_pics: u8,
+    pub max_latency_increase_plus1: u32,
+}
+
+/// `MaxDpbSize`: the most pictures any level lets the DPB hold.
+pub const MAX_DPB_SIZE: u32 = 16;
+
+/// Parses the sub-layer ordering loop, one entry per sub-layer. When only
+/// the highest sub-layer is coded, the lower ones get its values.
+pub(crate) fn parse_sub_layer_ordering(
+    r: &mut BitReader,
+    max_sub_layers_minus1: u8,
+) -> Result<(bool, Vec<SubLayerOrdering>)> {
+    let present = r.read_bit()?;
+    let first = if present { 0 } else { max_sub_layers_minus1 };
+    let mut ordering = Vec::new();
+    for _ in first..=max_sub_layers_minus1 {
+        let max_dec_pic_buffering_minus1 =
+            r.read_ue_max("max_dec_pic_buffering_minus1", MAX_DPB_SIZE - 1)?;
+        let max_num_reorder_pics =
+            r.read_ue_max("max_num_reorder_pics", max_dec_pic_buffering_minus1)?;
+        let max_latency_increase_plus1 =
+            r.read_ue_max("max_latency_increase_plus1", u32::MAX - 1)?;
+        ordering.push(SubLayerOrdering {
+            max_dec_pic_buffering_minus1: max_dec_pic_buffering_minus1 as u8,
+            max_num_reorder_pics: max_num_reorder_pics as u8,
+            max_latency_increase_plus1,
+        });
+    }
+    if !present {
+        ordering.resize(max_sub_layers_minus1 as usize + 1, ordering[0]);
+    }
+    Ok((present, ordering))
+}
+
+/// `hrd_parameters()`. The common information is shared by all
+/// sub-layers; each sub-layer has its own CPB specifications.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Hrd {
+    pub nal_hrd_parameters_present_flag: bool,
+    pub vcl_hrd_parameters_present_flag: bool,
+    pub sub_pic_hrd_params_present_flag: bool,
+    pub tick_divisor_minus2: u8,
+    pub du_cpb_removal_delay_increment_length_minus1: u8,
+    pub sub_pic_cpb_params_in_pic_timing_sei_flag: bool,
+    pub dpb_output_delay_du_length_minus1: u8,
+    pub bit_rate_scale: u8,
+    pub cpb_size_scale: u8,
+    pub cpb_size_du_scale: u8,
+    pub initial_cpb_removal_delay_length_minus1: u8,
+    pub au_cpb_removal_delay_length_minus1: u8,
+    pub dpb_output_delay_length_minus1: u8,
+    /// One entry per sub-layer, `maxNumSubLayersMinus1 + 1` in total.
+    pub sub_layers: Vec<HrdSubLayer>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct HrdSubLayer {
+    pub fixed_pic_rate_general_flag: bool,
+    pub fixed_pic_rate_within_cvs_flag: bool,
+    pub elemental_duration_in_tc_minus1: u16,
+    pub low_delay_hrd_flag: bool,
+    /// `sub_layer_hrd_parameters()` of the NAL HRD, `cpb_cnt_minus1 + 1`
+    /// entries when present.
+    pub nal_cpbs: Vec<HrdCpb>,
+    pub vcl_cpbs: Vec<HrdCpb>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct HrdCpb {
+    pub bit_rate_value_minus1: u32,
+    pub cpb_size_value_minus1: u32,
+    pub cpb_size_du_value_minus1: u32,
+    pub bit_rate_du_value_minus1: u32,
+    pub cbr_flag: bool,
+}
+
+impl Hrd {
+    /// Parses `hrd_parameters()`. Without common information, as VPS
+    /// entries may be coded, it is taken from `common`.
+    pub(crate) fn parse(
+        r: &mut BitReader,
+        common: Option<&Hrd>,
+        max_sub_layers_minus1: u8,
+    ) -> Result<Self> {
+        let mut hrd = match common {
+            Some(common) => Self {
+                sub_layers: Vec::new(),
+                ..common.clone()
+            },
+            None => Self::parse_common(r)?,
+        };
+        for _ in 0..=max_sub_layers_minus1 {
+            let mut sub_layer = HrdSubLayer {
+                fixed_pic_rate_general_flag: r.read_bit()?,
+                fixed_pic_rate_within_cvs_flag: true,
+                ..Default::default()
+            };
+            if !sub_layer.fixed_pic_rate_general_flag {
+                sub_layer.fixed_pic_rate_within_cvs_flag = r.read_bit()?;
+            }
+            if sub_layer.fixed_pic_rate_within_cvs_flag {
+                sub_layer.elemental_duration_in_tc_minus1 =
+                    r.read_ue_max("elemental_duration_in_tc_minus1", 2047)? as u16;
+            } else {
+                sub_layer.low_delay_hrd_flag = r.read_bit()?;
+            }
+            let cpb_cnt_minus1 = if sub_layer.low_delay_hrd_flag {
+                0
+            } else {
+                r.read_ue_max("cpb_cnt_minus1", 31)?
+            };
+            if hrd.nal_hrd_parameters_present_flag {
+                sub_layer.nal_cpbs = hrd.parse_cpbs(r, cpb_cnt_minus1)?;
+            }
+            if hrd.vcl_hrd_parameters_present_flag {
+                sub_layer.vcl_cpbs = hrd.parse_cpbs(r, cpb_cnt_minus1)?;
+            }
+            hrd.sub_layers.push(sub_layer);
+        }
+        Ok(hrd)
+    }
+
+    fn parse_common(r: &mut BitReader) -> Result<Self> {
+        let mut hrd = Self {
+            nal_hrd_parameters_present_flag: r.read_bit()?,
+            vcl_hrd_parameters_present_flag: r.read_bit()?,
+            // Inferred as 23 when absent.
+            initial_cpb_removal_delay_length_minus1: 23,
+            au_cpb_removal_delay_length_minus1: 23,
+            dpb_output_delay_length_minus1: 23,
+            ..Default::default()
+        };
+        if !hrd.nal_hrd_parameters_present_flag && !hrd.vcl_hrd_parameters_present_flag {
+            return Ok(hrd);
+        }
+        hrd.sub_pic_hrd_params_present_flag = r.read_bit()?;
+        if hrd.sub_pic_hrd_params_present_flag {
+            hrd.tick_divisor_minus2 = r.read_bits(8)? as u8;
+            hrd.du_cpb_removal_delay_increment_length_minus1 = r.read_bits(5)? as u8;
+            hrd.sub_pic_cpb_params_in_pic_timing_sei_flag = r.read_bit()?;
+            hrd.dpb_output_delay_du_length_minus1 = r.read_bits(5)? as u8;
+        }
+        hrd.bit_rate_scale = r.read_bits(4)? as u8;
+        hrd.cpb_size_scale = r.read_bits(4)? as u8;
+        if hrd.sub_pic_hrd_params_present_flag {
+            hrd.cpb_size_du_scale = r.read_bits(4)? as u8;
+        }
+        hrd.initial_cpb_removal_delay_length_minus1 = r.read_bits(5)? as u8;
+        hrd.au_cpb_removal_delay_length_minus1 = r.read_bits(5)? as u8;
+        hrd.dpb_output_delay_length_minus1 = r.read_bits(5)? as u8;
+        Ok(hrd)
+    }
+
+    /// `sub_layer_hrd_parameters()`.
+    fn parse_cpbs(&self, r: &mut BitReader, cpb_cnt_minus1: u32) -> Result<Vec<HrdCpb>> {
+        const MAX: u32 = u32::MAX - 1;
+        (0..=cpb_cnt_minus1)
+            .map(|_| {
+                let mut cpb = HrdCpb {
+                    bit_rate_value_minus1: r.read_ue_max("bit_rate_value_minus1", MAX)?,
+                    cpb_size_value_minus1: r.read_ue_max("cpb_size_value_minus1", MAX)?,
+                    ..Default::default()
+                };
+                if self.sub_pic_hrd_params_present_flag {
+                    cpb.cpb_size_du_value_minus1 =
+                        r.read_ue_max("cpb_size_du_value_minus1", MAX)?;
+                    cpb.bit_rate_du_value_minus1 =
+                        r.read_ue_max("bit_rate_du_value_minus1", MAX)?;
+                }
+                cpb.cbr_flag = r.read_bit()?;
+                Ok(cpb)
+            })
+            .collect()
+    }
+}
+
+/// Offsets of a window from the edges of the decoded picture, in chroma
+/// sample units: `conf_win_*_offset` or `def_disp_win_*_offset`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Window {
+    pub left_offset: u32,
+    pub right_offset: u32,
+    pub top_offset: u32,
+    pub bottom_offset: u32,
+}
+
+impl Window {
+    fn parse(r: &mut BitReader) -> Result<Self> {
+        Ok(Self {
+            left_offset: r.read_ue()?,
+            right_offset: r.read_ue()?,
+            top_offset: r.read_ue()?,
+            bottom_offset: r.read_ue()?,
+        })
+    }
+}
+
+/// `vui_parameters()`. Absent fields hold their inferred values.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Vui {
+    pub aspect_ratio_info_present_flag: bool,
+    pub aspect_ratio_idc: u8,
+    pub sar_width: u16,
+    pub sar_height: u16,
+    pub overscan_info_present_flag: bool,
+    pub overscan_appropriate_flag: bool,
+    pub video_signal_type_present_flag: bool,
+    pub video_format: u8,
+    pub video_full_range_flag: bool,
+    pub colour_description_present_flag: bool,
+    pub colour_primaries: u8,
+    pub transfer_characteristics: u8,
+    pub matrix_coeffs: u8,
+    pub chroma_loc_info_present_flag: bool,
+    pub chroma_sample_loc_type_top_field: u8,
+    pub chroma_sample_loc_type_bottom_field: u8,
+    pub neutral_chroma_indication_flag: bool,
+    pub field_seq_flag: bool,
+    pub frame_field_info_present_flag: bool,
+    pub default_display_window: Option<Window>,
+    pub vui_timing_info_present_flag: bool,
+    pub vui_num_units_in_tick: u32,
+    pub vui_time_scale: u32,
+    pub vui_poc_proportional_to_timing_flag: bool,
+    pub vui_num_ticks_poc_diff_one_minus1: u32,
+    pub hrd: Option<Hrd>,
+    pub bitstream_restriction_flag: bool,
+    pub tiles_fixed_structure_flag: bool,
+    pub motion_vectors_over_pic_boundaries_flag: bool,
+    pub restricted_ref_pic_lists_flag: bool,
+    pub min_spatial_segmentation_idc: u16,
+    pub max_bytes_per_pic_denom: u8,
+    pub max_bits_per_min_cu_denom: u8,
+    pub log2_max_mv_length_horizontal: u8,
+    pub log2_max_mv_length_vertical: u8,
+}
+
+impl Vui {
+    fn parse(r: &mut BitReader, max_sub_layers_minus1: u8) -> Result<Self> {
+        let mut vui = Self {
+            aspect_ratio_info_present_flag: r.read_bit()?,
+            aspect_ratio_idc: 0,
+            sar_width: 0,
+            sar_height: 0,
+            overscan_info_present_flag: false,
+            overscan_appropriate_flag: false,
+            video_signal_type_present_flag: false,
+            video_format: 5,
+            video_full_range_flag: false,
+            colour_description_present_flag: false,
+            colour_primaries: 2,
+            transfer_characteristics: 2,
+            matrix_coeffs: 2,
+            chroma_loc_info_present_flag: false,
+            chroma_sample_loc_type_top_field: 0,
+            chroma_sample_loc_type_bottom_field: 0,
+            neutral_chroma_indication_flag: false,
+            field_seq_flag: false,
+            frame_field_info_present_flag: false,
+            default_display_window: None,
+            vui_timing_info_present_flag: false,
+            vui_num_units_in_tick: 0,
+            vui_time_scale: 0,
+            vui_poc_proportional_to_timing_flag: false,
+            vui_num_ticks_poc_diff_one_minus1: 0,
+            hrd: None,
+            bitstream_restriction_flag: false,
+            tiles_fixed_structure_flag: false,
+            motion_vectors_over_pic_boundaries_flag: true,
+            restricted_ref_pic_lists_flag: false,
+            min_spatial_segmentation_idc: 0,
+            max_bytes_per_pic_denom: 2,
+            max_bits_per_min_cu_denom: 1,
+            log2_max_mv_length_horizontal: 15,
+            log2_max_mv_length_vertical: 15,
+        };
+        if vui.aspect_ratio_info_present_flag {
+            vui.aspect_ratio_idc = r.read_bits(8)? as u8;
+            if vui.aspect_ratio_idc == crate::h264::EXTENDED_SAR {
+                vui.sar_width = r.read_bits(16)? as u16;
+                vui.sar_height = r.read_bits(16)? as u16;
+            }
+        }
+        vui.overscan_info_present_flag = r.read_bit()?;
+        if vui.overscan_info_present_flag {
+            vui.overscan_appropriate_flag = r.read_bit()?;
+        }
+        vui.video_signal_type_present_flag = r.read_bit()?;
+        if vui.video_signal_type_present_flag {
+            vui.video_format = r.read_bits(3)? as u8;
+            vui.video_full_range_flag = r.read_bit()?;
+            vui.colour_description_present_flag = r.read_bit()?;
+            if vui.colour_description_present_flag {
+                vui.colour_primaries = r.read_bits(8)? as u8;
+                vui.transfer_characteristics = r.read_bits(8)? as u8;
+                vui.matrix_coeffs = r.read_bits(8)? as u8;
+            }
+        }
+        vui.chroma_loc_info_present_flag = r.read_bit()?;
+        if vui.chroma_loc_info_present_flag {
+            vui.chroma_sample_loc_type_top_field =
+                r.read_ue_max("chroma_sample_loc_type_top_field", 5)? as u8;
+            vui.chroma_sample_loc_type_bottom_field =
+                r.read_ue_max("chroma_sample_loc_type_bottom_field", 5)? as u8;
+        }
+        vui.neutral_chroma_indication_flag = r.read_bit()?;
+        vui.field_seq_flag = r.read_bit()?;
+        vui.frame_field_info_present_flag = r.read_bit()?;
+        if r.read_bit()? {
+            vui.default_display_window = Some(Window::parse(r)?);
+        }
+        vui.vui_timing_info_present_flag = r.read_bit()?;
+        if vui.vui_timing_info_present_flag {
+            vui.vui_num_units_in_tick = r.read_bits(32)?;
+            vui.vui_time_scale = r.read_bits(32)?;
+            if vui.vui_num_units_in_tick == 0 || vui.vui_time_scale == 0 {
+                return Err(Error::InvalidValue {
+                    name: "vui_num_units_in_tick",
+                    value: vui.vui_num_units_in_tick as i64,
+                });
+            }
+            vui.vui_poc_proportional_to_timing_flag = r.read_bit()?;
+            if vui.vui_poc_proportional_to_timing_flag {
+                vui.vui_num_ticks_poc_diff_one_minus1 =
+                    r.read_ue_max("vui_num_ticks_poc_diff_one_minus1", u32::MAX - 1)?;
+            }
+            if r.read_bit()? {
+                vui.hrd = Some(Hrd::parse(r, None, max_sub_layers_minus1)?);
+            }
+        }
+        vui.bitstream_restriction_flag = r.read_bit()?;
+        if vui.bitstream_restriction_flag {
+            vui.tiles_fixed_structure_flag = r.read_bit()?;
+            vui.motion_vectors_over_pic_boundaries_flag = r.read_bit()?;
+            vui.restricted_ref_pic_lists_flag = r.read_bit()?;
+            vui.min_spatial_segmentation_idc =
+                r.read_ue_max("min_spatial_segmentation_idc", 4095)? as u16;
+            vui.max_bytes_per_pic_denom = r.read_ue_max("max_bytes_per_pic_denom", 16)? as u8;
+            vui.max_bits_per_min_cu_denom = r.read_ue_max("max_bits_per_min_cu_denom", 16)? as u8;
+            vui.log2_max_mv_length_horizontal =
+                r.read_ue_max("log2_max_mv_length_horizontal", 15)? as u8;
+            vui.log2_max_mv_length_vertical =
+                r.read_ue_max("log2_max_mv_length_vertical", 15)? as u8;
+        }
+        Ok(vui)
+    }
+}
+
+/// PCM sample parameters, present with `pcm_enabled_flag`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Pcm {
+    pub pcm_sample_bit_depth_luma_minus1: u8,
+    pub pcm_sample_bit_depth_chroma_minus1: u8,
+    pub log2_min_pcm_luma_coding_block_size_minus3: u8,
+    pub log2_diff_max_min_pcm_luma_coding_block_size: u8,
+    pub pcm_loop_filter_disabled_flag: bool,
+}
+
+/// `lt_ref_pic_poc_lsb_sps` and `used_by_curr_pic_lt_sps_flag`: a
+/// long-term reference picture candidate slice headers can refer to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LongTermRefPicSps {
+    pub lt_ref_pic_poc_lsb_sps: u32,
+    pub used_by_curr_pic_lt_sps_flag: bool,
+}
+
+/// `sps_range_extension()`; all flags are unset without it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SpsRangeExtension {
+    pub transform_skip_rotation_enabled_flag: bool,
+    pub transform_skip_context_enabled_flag: bool,
+    pub implicit_rdpcm_enabled_flag: bool,
+    pub explicit_rdpcm_enabled_flag: bool,
+    pub extended_precision_processing_flag: bool,
+    pub intra_smoothing_disabled_flag: bool,
+    pub high_precision_offsets_enabled_flag: bool,
+    pub persistent_rice_adaptation_enabled_flag: bool,
+    pub cabac_bypass_alignment_enabled_flag: bool,
+}
+
+impl SpsRangeExtension {
+    fn parse(r: &mut BitReader) -> Result<Self> {
+        Ok(Self {
+            transform_skip_rotation_enabled_flag: r.read_bit()?,
+            transform_skip_context_enabled_flag: r.read_bit()?,
+            implicit_rdpcm_enabled_flag: r.read_bit()?,
+            explicit_rdpcm_enabled_flag: r.read_bit()?,
+            extended_precision_processing_flag: r.read_bit()?,
+            intra_smoothing_disabled_flag: r.read_bit()?,
+            high_precision_offsets_enabled_flag: r.read_bit()?,
+            persistent_rice_adaptation_enabled_flag: r.read_bit()?,
+            cabac_bypass_alignment_enabled_flag: r.read_bit()?,
+        })
+    }
+}
+
+/// `sps_scc_extension()`; everything is unset without it.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct SpsSccExtension {
+    pub sps_curr_pic_ref_enabled_flag: bool,
+    pub palette_mode_enabled_flag: bool,
+    pub palette_max_size: u8,
+    pub delta_palette_max_predictor_size: u8,
+    pub sps_palette_predictor_initializers_present_flag: bool,
+    /// `sps_palette_predictor_initializer[comp][i]`; the chroma entries
+    /// are empty for monochrome streams.
+    pub sps_palette_predictor_initializers: [Vec<u16>; 3],
+    pub motion_vector_resolution_control_idc: u8,
+    pub intra_boundary_filtering_disabled_flag: bool,
+}
+
+/// `PaletteMaxPredictorSize` may not exceed this.
+pub const MAX_PALETTE_PREDICTOR_SIZE: u32 = 128;
+
+impl SpsSccExtension {
+    fn parse(r: &mut BitReader, sps: &Sps) -> Result<Self> {
+        let mut scc = Self {
+            sps_curr_pic_ref_enabled_flag: r.read_bit()?,
+            palette_mode_enabled_flag: r.read_bit()?,
+            ..Default::default()
+        };
+        if scc.palette_mode_enabled_flag {
+            scc.palette_max_size = r.read_ue_max("palette_max_size", 64)? as u8;
+            scc.delta_palette_max_predictor_size = r.read_ue_max(
+                "delta_palette_max_predictor_size",
+                MAX_PALETTE_PREDICTOR_SIZE - scc.palette_max_size as u32,
+            )? as u8;
+            scc.sps_palette_predictor_initializers_present_flag = r.read_bit()?;
+            if scc.sps_palette_predictor_initializers_present_flag {
+                let count = r.read_ue_max(
+                    "sps_num_palette_predictor_initializers_minus1",
+                    scc.palette_max_predictor_size().saturating_sub(1),
+                )? + 1;
+                let components = if sps.chroma_format_idc == 0 { 1 } else { 3 };
+                for comp in 0..components {
+                    let bits = if comp == 0 {
+                        sps.bit_depth_luma()
+                    } else {
+                        sps.bit_depth_chroma()
+                    };
+                    scc.sps_palette_predictor_initializers[comp] = (0..count)
+                        .map(|_| Ok(r.read_bits(bits)? as u16))
+                        .collect::<Result<_>>()?;
+                }
+            }
+        }
+        scc.motion_vector_resolution_control_idc =
+            r.read_bits_max("motion_vector_resolution_control_idc", 2, 2)? as u8;
+        scc.intra_boundary_filtering_disabled_flag = r.read_bit()?;
+        Ok(scc)
+    }
+
+    /// `PaletteMaxPredictorSize`.
+    pub fn palette_max_predictor_size(&self) -> u32 {
+        self.palette_max_size as u32 + self.delta_palette_max_predictor_size as u32
+    }
+}
+
+/// Sequence parameter set, `seq_parameter_set_rbsp()`. Absent fields hold
+/// their inferred values.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sps {
+    pub sps_video_parameter_set_id: u8,
+    pub sps_max_sub_layers_minus1: u8,
+    pub sps_temporal_id_nesting_flag: bool,
+    pub profile_tier_level: ProfileTierLevel,
+    pub sps_seq_parameter_set_id: u8,
+    pub chroma_format_idc: u8,
+    pub separate_colour_plane_flag: bool,
+    pub pic_width_in_luma_samples: u32,
+    pub pic_height_in_luma_samples: u32,
+    pub conformance_window: Option<Window>,
+    pub bit_depth_luma_minus8: u8,
+    pub bit_depth_chroma_minus8: u8,
+    pub log2_max_pic_order_cnt_lsb_minus4: u8,
+    pub sps_sub_layer_ordering_info_present_flag: bool,
+    /// One entry per sub-layer.
+    pub sub_layer_ordering: Vec<SubLayerOrdering>,
+    pub log2_min_luma_coding_block_size_minus3: u8,
+    pub log2_diff_max_min_luma_coding_block_size: u8,
+    pub log2_min_luma_transform_block_size_minus2: u8,
+    pub log2_diff_max_min_luma_transform_block_size: u8,
+    pub max_transform_hierarchy_depth_inter: u8,
+    pub max_transform_hierarchy_depth_intra: u8,
+    pub scaling_list_enabled_flag: bool,
+    pub sps_scaling_list_data_present_flag: bool,
+    /// The coded lists, the default ones if scaling lists are enabled but
+    /// not coded, and flat ones if they are disabled.
+    pub scaling_lists: ScalingLists,
+    pub amp_enabled_flag: bool,
+    pub sample_adaptive_offset_enabled_flag: bool,
+    pub pcm: Option<Pcm>,
+    /// `st_ref_pic_set(i)`, `num_short_term_ref_pic_sets` entries.
+    pub short_term_ref_pic_sets: Vec<ShortTermRps>,
+    pub long_term_ref_pics_present_flag: bool,
+    /// `num_long_term_ref_pics_sps` entries.
+    pub long_term_ref_pics: Vec<LongTermRefPicSps>,
+    pub sps_temporal_mvp_enabled_flag: bool,
+    pub strong_intra_smoothing_enabled_flag: bool,
+    pub vui: Option<Vui>,
+    pub range_extension: SpsRangeExtension,
+    pub scc_extension: SpsSccExtension,
+}
+
+// Limits keeping derived sizes well inside u32. Level 6.2 allows pictures
+// of up to 8 * MaxLumaPs samples on a side.
+const MAX_PIC_SIZE: u32 = 16888;
+pub const MAX_SHORT_TERM_REF_PIC_SETS: u32 = 64;
+pub const MAX_LONG_TERM_REF_PICS_SPS: u32 = 32;
+
+impl Sps {
+    /// Parses a `seq_parameter_set_rbsp()`, without the NAL header.
+    pub fn parse(rbsp: &[u8]) -> Result<Self> {
+        let mut r = BitReader::new(rbsp);
+        let sps_video_parameter_set_id = r.read_bits(4)? as u8;
+        let sps_max_sub_layers_minus1 = r.read_bits_max("sps_max_sub_layers_minus1", 3, 6)? as u8;
+        let sps_temporal_id_nesting_flag = r.read_bit()?;
+        let profile_tier_level = ProfileTierLevel::parse(&mut r, sps_max_sub_layers_minus1)?;
+        let sps_seq_parameter_set_id = r.read_ue_max("sps_seq_parameter_set_id", 15)? as u8;
+        let chroma_format_idc = r.read_ue_max("chroma_format_idc", 3)? as u8;
+        let separate_colour_plane_flag = chroma_format_idc == 3 && r.read_bit()?;
+        let pic_width_in_luma_samples = r.read_ue_max("pic_width_in_luma_samples", MAX_PIC_SIZE)?;
+        let pic_height_in_luma_samples =
+            r.read_ue_max("pic_height_in_luma_samples", MAX_PIC_SIZE)?;
+        let conformance_window = if r.read_bit()? {
+            Some(Window::parse(&mut r)?)
+        } else {
+            None
+        };
+        let bit_depth_luma_minus8 = r.read_ue_max("bit_depth_luma_minus8", 8)? as u8;
+        let bit_depth_chroma_minus8 = r.read_ue_max("bit_depth_chroma_minus8", 8)? as u8;
+        let log2_max_pic_order_cnt_lsb_minus4 =
+            r.read_ue_max("log2_max_pic_order_cnt_lsb_minus4", 12)? as u8;
+        let (sps_sub_layer_ordering_info_present_flag, sub_layer_ordering) =
+            parse_sub_layer_ordering(&mut r, sps_max_sub_layers_minus1)?;
+
+        let log2_min_luma_coding_block_size_minus3 =
+            r.read_ue_max("log2_min_luma_coding_block_size_minus3", 3)? as u8;
+        let min_cb_log2_size = log2_min_luma_coding_block_size_minus3 as u32 + 3;
+        let log2_diff_max_min_luma_coding_block_size = r.read_ue_max(
+            "log2_diff_max_min_luma_coding_block_size",
+            6 - min_cb_log2_size,
+        )? as u8;
+        let ctb_log2_size = min_cb_log2_size + log2_diff_max_min_luma_coding_block_size as u32;
+        check("CtbLog2SizeY", ctb_log2_size as i64, 4, 6)?;
+        let log2_min_luma_transform_block_size_minus2 = r.read_ue_max(
+            "log2_min_luma_transform_block_size_minus2",
+            min_cb_log2_size - 3,
+        )? as u8;
+        let min_tb_log2_size = log2_min_luma_transform_block_size_minus2 as u32 + 2;
+        let log2_diff_max_min_luma_transform_block_size = r.read_ue_max(
+            "log2_diff_max_min_luma_transform_block_size",
+            ctb_log2_size.min(5) - min_tb_log2_size,
+        )? as u8;
+        let max_depth = ctb_log2_size - min_tb_log2_size;
+        let max_transform_hierarchy_depth_inter =
+            r.read_ue_max("max_transform_hierarchy_depth_inter", max_depth)? as u8;
+        let max_transform_hierarchy_depth_intra =
+            r.read_ue_max("max_transform_hierarchy_depth_intra", max_depth)? as u8;
+        for (name, size) in [
+            ("pic_width_in_luma_samples", pic_width_in_luma_samples),
+            ("pic_height_in_luma_samples", pic_height_in_luma_samples),
+        ] {
+            if size == 0 || size % (1 << min_cb_log2_size) != 0 {
+                return Err(Error::InvalidValue {
+                    name,
+                    value: size as i64,
+                });
+            }
+        }
+
+        let scaling_list_enabled_flag = r.read_bit()?;
+        let mut sps_scaling_list_data_present_flag = false;
+        let mut scaling_lists = ScalingLists::default();
+        if scaling_list_enabled_flag {
+            sps_scaling_list_data_present_flag = r.read_bit()?;
+            scaling_lists = if sps_scaling_list_data_present_flag {
+                ScalingLists::parse(&mut r)?
+            } else {
+                ScalingLists::defaults()
+            };
+        }
+        let amp_enabled_flag = r.read_bit()?;
+        let sample_adaptive_offset_enabled_flag = r.read_bit()?;
+        let pcm = if r.read_bit()? {
+            let pcm_sample_bit_depth_luma_minus1 = r.read_bits_max(
+                "pcm_sample_bit_depth_luma_minus1",
+                4,
+                bit_depth_luma_minus8 as u32 + 7,
+            )? as u8;
+            let pcm_sample_bit_depth_chroma_minus1 = r.read_bits_max(
+                "pcm_sample_bit_depth_chroma_minus1",
+                4,
+                bit_depth_chroma_minus8 as u32 + 7,
+            )? as u8;
+            let log2_min_pcm_luma_coding_block_size_minus3 = r.read_ue_max(
+                "log2_min_pcm_luma_coding_block_size_minus3",
+                ctb_log2_size.min(5) - 3,
+            )? as u8;
+            let log2_diff_max_min_pcm_luma_coding_block_size = r.read_ue_max(
+                "log2_diff_max_min_pcm_luma_coding_block_size",
+                ctb_log2_size.min(5) - 3 - log2_min_pcm_luma_coding_block_size_minus3 as u32,
+            )? as u8;
+            Some(Pcm {
+                pcm_sample_bit_depth_luma_minus1,
+                pcm_sample_bit_depth_chroma_minus1,
+                log2_min_pcm_luma_coding_block_size_minus3,
+                log2_diff_max_min_pcm_luma_coding_block_size,
+                pcm_loop_filter_disabled_flag: r.read_bit()?,
+            })
+        } else {
+            None
+        };
+
+        let max_dec_pic_buffering_minus1 = sub_layer_ordering
+            .last()
+            .unwrap()
+            .max_dec_pic_buffering_minus1;
+        let num_short_term_ref_pic_sets =
+            r.read_ue_max("num_short_term_ref_pic_sets", MAX_SHORT_TERM_REF_PIC_SETS)?;
+        let mut short_term_ref_pic_sets = Vec::new();
+        for _ in 0..num_short_term_ref_pic_sets {
+            let rps = ShortTermRps::parse(
+                &mut r,
+                &short_term_ref_pic_sets,
+                false,
+                max_dec_pic_buffering_minus1,
+            )?;
+            short_term_ref_pic_sets.push(rps);
+        }
+        let long_term_ref_pics_present_flag = r.read_bit()?;
+        let mut long_term_ref_pics = Vec::new();
+        if long_term_ref_pics_present_flag {
+            let count = r.read_ue_max("num_long_term_ref_pics_sps", MAX_LONG_TERM_REF_PICS_SPS)?;
+            for _ in 0..count {
+                long_term_ref_pics.push(LongTermRefPicSps {
+                    lt_ref_pic_poc_lsb_sps: r
+                        .read_bits(log2_max_pic_order_cnt_lsb_minus4 as u32 + 4)?,
+                    used_by_curr_pic_lt_sps_flag: r.read_bit()?,
+                });
+            }
+        }
+        let sps_temporal_mvp_enabled_flag = r.read_bit()?;
+        let strong_intra_smoothing_enabled_flag = r.read_bit()?;
+        let vui = if r.read_bit()? {
+            Some(Vui::parse(&mut r, sps_max_sub_layers_minus1)?)
+        } else {
+            None
+        };
+
+        let mut sps = Self {
+            sps_video_parameter_set_id,
+            sps_max_sub_layers_minus1,
+            sps_temporal_id_nesting_flag,
+            profile_tier_level,
+            sps_seq_parameter_set_id,
+            chroma_format_idc,
+            separate_colour_plane_flag,
+            pic_width_in_luma_samples,
+            pic_height_in_luma_samples,
+            conformance_window,
+            bit_depth_luma_minus8,
+            bit_depth_chroma_minus8,
+            log2_max_pic_order_cnt_lsb_minus4,
+            sps_sub_layer_ordering_info_present_flag,
+            sub_layer_ordering,
+            log2_min_luma_coding_block_size_minus3,
+            log2_diff_max_min_luma_coding_block_size,
+            log2_min_luma_transform_block_size_minus2,
+            log2_diff_max_min_luma_transform_block_size,
+            max_transform_hierarchy_depth_inter,
+            max_transform_hierarchy_depth_intra,
+            scaling_list_enabled_flag,
+            sps_scaling_list_data_present_flag,
+            scaling_lists,
+            amp_enabled_flag,
+            sample_adaptive_offset_enabled_flag,
+            pcm,
+            short_term_ref_pic_sets,
+            long_term_ref_pics_present_flag,
+            long_term_ref_pics,
+            sps_temporal_mvp_enabled_flag,
+            strong_intra_smoothing_enabled_flag,
+            vui,
+            range_extension: SpsRangeExtension::default(),
+            scc_extension: SpsSccExtension::default(),
+        };
+        // Rejects windows cropping away the whole picture.
+        sps.visible_rect()?;
+
+        if r.read_bit()? {
+            // sps_extension_present_flag
+            let range = r.read_bit()?;
+            let multilayer = r.read_bit()?;
+            let three_d = r.read_bit()?;
+            let scc = r.read_bit()?;
+            r.skip_bits(4)?;
+            if range {
+                sps.range_extension = SpsRangeExtension::parse(&mut r)?;
+            }
+            if multilayer {
+                // inter_view_mv_vert_constraint_flag
+                r.skip_bits(1)?;
+            }
+            if three_d && scc {
+                return Err(Error::Unsupported("3D-HEVC SPS extension"));
+            }
+            if scc {
+                sps.scc_extension = SpsSccExtension::parse(&mut r, &sps)?;
+            }
+        }
+        Ok(sps)
+    }
+
+    /// `ChromaArrayType`.
+    pub fn chroma_array_type(&self) -> u8 {
+        if self.separate_colour_plane_flag {
+            0
+        } else {
+            self.chroma_format_idc
+        }
+    }
+
+    /// `SubWidthC` and `SubHeightC`, or `None` for monochrome and separate
+    /// colour planes.
+    pub fn chroma_subsampling(&self) -> Option<(u32, u32)> {
+        match self.chroma_array_type() {
+            1 => Some((2, 2)),
+            2 => Some((2, 1)),
+            3 => Some((1, 1)),
+            _ => None,
+        }
+    }
+
+    pub fn bit_depth_luma(&self) -> u32 {
+        self.bit_depth_luma_minus8 as u32 + 8
+    }
+
+    pub fn bit_depth_chroma(&self) -> u32 {
+        self.bit_depth_chroma_minus8 as u32 + 8
+    }
+
+    /// `MaxPicOrderCntLsb`.
+    pub fn max_pic_order_cnt_lsb(&self) -> u32 {
+        1 << (self.log2_max_pic_order_cnt_lsb_minus4 + 4)
+    }
+
+    /// `MinCbLog2SizeY`.
+    pub fn min_cb_log2_size(&self) -> u32 {
+        self.log2_min_luma_coding_block_size_minus3 as u32 + 3
+    }
+
+    /// `CtbLog2SizeY`.
+    pub fn ctb_log2_size(&self) -> u32 {
+        self.min_cb_log2_size() + self.log2_diff_max_min_luma_coding_block_size as u32
+    }
+
+    /// `PicWidthInCtbsY`.
+    pub fn pic_width_in_ctbs(&self) -> u32 {
+        self.pic_width_in_luma_samples
+            .div_ceil(1 << self.ctb_log2_size())
+    }
+
+    /// `PicHeightInCtbsY`.
+    pub fn pic_height_in_ctbs(&self) -> u32 {
+        self.pic_height_in_luma_samples
+            .div_ceil(1 << self.ctb_log2_size())
+    }
+
+    /// `PicSizeInCtbsY`.
+    pub fn pic_size_in_ctbs(&self) -> u32 {
+        self.pic_width_in_ctbs() * self.pic_height_in_ctbs()
+    }
+
+    /// Width of the decoded picture in luma samples, before cropping.
+    pub fn width(&self) -> u32 {
+        self.pic_width_in_luma_samples
+    }
+
+    /// Height of the decoded picture in luma samples, before cropping.
+    pub fn height(&self) -> u32 {
+        self.pic_height_in_luma_samples
+    }
+
+    /// The conformance cropping window: the part of the decoded picture to
+    /// display.
+    pub fn visible_rect(&self) -> Result<Rect> {
+        let Some(window) = self.conformance_window else {
+            return Ok(Rect {
+                x: 0,
+                y: 0,
+                width: self.width(),
+                height: self.height(),
+            });
+        };
+        let (unit_x, unit_y) = self.chroma_subsampling().unwrap_or((1, 1));
+        let left = window.left_offset.saturating_mul(unit_x);
+        let top = window.top_offset.saturating_mul(unit_y);
+        let horizontal = left.saturating_add(window.right_offset.saturating_mul(unit_x));
+        let vertical = top.saturating_add(window.bottom_offset.saturating_mul(unit_y));
+        if horizontal >= self.width() {
+            return Err(Error::InvalidValue {
+                name: "conf_win_right_offset",
+                value: window.right_offset as i64,
+            });
+        }
+        if vertical >= self.height() {
+            return Err(Error::InvalidValue {
+                name: "conf_win_bottom_offset",
+                value: window.bottom_offset as i64,
+            });
+        }
+        Ok(Rect {
+            x: left,
+            y: top,
+            width: self.width() - horizontal,
+            height: self.height() - vertical,
+        })
+    }
+
+    /// The sub-layer ordering of the highest sub-layer, which applies when
+    /// all sub-layers are decoded.
+    pub fn highest_sub_layer_ordering(&self) -> &SubLayerOrdering {
+        self.sub_layer_ordering.last().unwrap()
+    }
+
+    /// `SpsMaxLatencyPictures` of the highest sub-layer, if limited.
+    pub fn max_latency_pictures(&self) -> Option<u32> {
+        let ordering = self.highest_sub_layer_ordering();
+        (ordering.max_latency_increase_plus1 != 0).then(|| {
+            (ordering.max_num_reorder_pics as u32)
+                .saturating_add(ordering.max_latency_increase_plus1 - 1)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bits::BitWriter;
+
+    /// Codes every prediction path of `scaling_list_data()`.
+    fn scaling_list_data(delta_32x32: u32) -> Vec<u8> {
+        let mut w = BitWriter::new();
+        let copy = |w: &mut BitWriter, delta| {
+            w.write_bit(false); // scaling_list_pred_mode_flag
+            w.write_ue(delta);
+        };
+        // 4x4: 16 to 31 explicitly, a copy of it, flat, a copy three back,
+        // then flat.
+        w.write_bit(true);
+        w.write_se(8);
+        for _ in 1..16 {
+            w.write_se(1);
+        }
+        for delta in [1, 0, 3, 0, 0] {
+            copy(&mut w, delta);
+        }
+        // 8x8: the intra default, all 10, a copy of it, the inter default,
+        // a copy of the first, then the inter default.
+        copy(&mut w, 0);
+        w.write_bit(true);
+        w.write_se(2);
+        for _ in 1..64 {
+            w.write_se(0);
+        }
+        for delta in [1, 0, 4, 0] {
+            copy(&mut w, delta);
+        }
+        // 16x16: all 15 with a DC of 12, a copy of it, then defaults.
+        w.write_bit(true);
+        w.write_se(4); // scaling_list_dc_coef_minus8
+        w.write_se(3);
+        for _ in 1..64 {
+            w.write_se(0);
+        }
+        for delta in [1, 0, 0, 0, 0] {
+            copy(&mut w, delta);
+        }
+        // 32x32: all 20 with a DC of 1, then the inter Y list.
+        w.write_bit(true);
+        w.write_se(-7);
+        w.write_se(19);
+        for _ in 1..64 {
+            w.write_se(0);
+        }
+        copy(&mut w, delta_32x32);
+        w.write_trailing_bits();
+        w.into_bytes()
+    }
+
+    #[test]
+    fn parses_scaling_lists() {
+        let data = scaling_list_data(1);
+        let lists = ScalingLists::parse(&mut BitReader::new(&data)).unwrap();
+        let explicit: [u8; 16] = std::array::from_fn(|i| 16 + i as u8);
+        assert_eq!(
+            lists.list_4x4,
+            [explicit, explicit, [16; 16], explicit, [16; 16], [16; 16]]
+        );
+        assert_eq!(
+            lists.list_8x8,
+            [
+                DEFAULT_8X8_INTRA,
+                [10; 64],
+                [10; 64],
+                DEFAULT_8X8_INTER,
+                DEFAULT_8X8_INTRA,
+                DEFAULT_8X8_INTER,
+            ]
+        );
+        assert_eq!(lists.list_16x16[..2], [[15; 64]; 2]);
+        assert_eq!(lists.list_16x16[2], DEFAULT_8X8_INTRA);
+        assert_eq!(lists.list_16x16[3], DEFAULT_8X8_INTER);
+        assert_eq!(lists.dc_16x16, [12, 12, 16, 16, 16, 16]);
+        // The inter Y list copies the intra one: refMatrixId is
+        // matrixId - delta * 3. The chroma lists come from 16x16.
+        assert_eq!(lists.list_32x32[0], [20; 64]);
+        assert_eq!(lists.list_32x32[3], [20; 64]);
+        assert_eq!(lists.list_32x32[1], [15; 64]);
+        assert_eq!(lists.list_32x32[2], DEFAULT_8X8_INTRA);
+        assert_eq!(lists.list_32x32[5], DEFAULT_8X8_INTER);
+        assert_eq!(lists.dc_32x32, [1, 12, 16, 1, 16, 16]);
+
+        // The default inter Y list for 32x32.
+        let data = scaling_list_data(0);
+        let lists = ScalingLists::parse(&mut BitReader::new(&data)).unwrap();
+        assert_eq!(
+            (lists.list_32x32[3], lists.dc_32x32[3]),
+            (DEFAULT_8X8_INTER, 16)
+        );
+        // Only one 32x32 list precedes it.
+        let data = scaling_list_data(2);
+        assert_eq!(
+            ScalingLists::parse(&mut BitReader::new(&data)),
+            Err(Error::InvalidValue {
+                name: "scaling_list_pred_matrix_id_delta",
+                value: 2
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_out_of_range_dc() {
+        let mut w = BitWriter::new();
+        for _ in 0..12 {
+            w.write_bit(false);
+            w.write_ue(0);
+        }
+        w.write_bit(true);
+        w.write_se(-8); // scaling_list_dc_coef_minus8
+        w.write_trailing_bits();
+        let data = w.into_bytes();
+        assert_eq!(
+            ScalingLists::parse(&mut BitReader::new(&data)),
+            Err(Error::InvalidValue {
+                name: "scaling_list_dc_coef_minus8",
+                value: -8
+            })
+        );
+    }
+}
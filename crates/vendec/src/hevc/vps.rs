@@ -0,0 +1,119 @@
+use super::{
+    parse_sub_layer_ordering, Error, Hrd, ProfileTierLevel, ReadExt, Result, SubLayerOrdering,
+};
+use crate::bits::BitReader;
+
+/// One of the `hrd_parameters()` of the VPS, with the layer set it is for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VpsHrd {
+    pub hrd_layer_set_idx: u16,
+    pub cprms_present_flag: bool,
+    pub hrd: Hrd,
+}
+
+/// Video parameter set, `video_parameter_set_rbsp()`, without
+/// `vps_extension()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Vps {
+    pub vps_video_parameter_set_id: u8,
+    pub vps_base_layer_internal_flag: bool,
+    pub vps_base_layer_available_flag: bool,
+    pub vps_max_layers_minus1: u8,
+    pub vps_max_sub_layers_minus1: u8,
+    pub vps_temporal_id_nesting_flag: bool,
+    pub profile_tier_level: ProfileTierLevel,
+    pub vps_sub_layer_ordering_info_present_flag: bool,
+    /// One entry per sub-layer.
+    pub sub_layer_ordering: Vec<SubLayerOrdering>,
+    pub vps_max_layer_id: u8,
+    /// The `nuh_layer_id`s in each layer set, from
+    /// `layer_id_included_flag`; layer set 0 only has the base layer.
+    pub layer_sets: Vec<Vec<u8>>,
+    pub vps_timing_info_present_flag: bool,
+    pub vps_num_units_in_tick: u32,
+    pub vps_time_scale: u32,
+    pub vps_poc_proportional_to_timing_flag: bool,
+    pub vps_num_ticks_poc_diff_one_minus1: u32,
+    pub hrd_parameters: Vec<VpsHrd>,
+}
+
+impl Vps {
+    /// Parses a `video_parameter_set_rbsp()`, without the NAL header.
+    pub fn parse(rbsp: &[u8]) -> Result<Self> {
+        let mut r = BitReader::new(rbsp);
+        let vps_video_parameter_set_id = r.read_bits(4)? as u8;
+        let vps_base_layer_internal_flag = r.read_bit()?;
+        let vps_base_layer_available_flag = r.read_bit()?;
+        let vps_max_layers_minus1 = r.read_bits(6)? as u8;
+        let vps_max_sub_layers_minus1 = r.read_bits_max("vps_max_sub_layers_minus1", 3, 6)? as u8;
+        let vps_temporal_id_nesting_flag = r.read_bit()?;
+        let reserved = r.read_bits(16)?;
+        if reserved != 0xffff {
+            return Err(Error::InvalidValue {
+                name: "vps_reserved_0xffff_16bits",
+                value: reserved as i64,
+            });
+        }
+        let profile_tier_level = ProfileTierLevel::parse(&mut r, vps_max_sub_layers_minus1)?;
+        let (vps_sub_layer_ordering_info_present_flag, sub_layer_ordering) =
+            parse_sub_layer_ordering(&mut r, vps_max_sub_layers_minus1)?;
+        let vps_max_layer_id = r.read_bits(6)? as u8;
+        let vps_num_layer_sets_minus1 = r.read_ue_max("vps_num_layer_sets_minus1", 1023)?;
+        let mut layer_sets = vec![vec![0]];
+        for _ in 0..vps_num_layer_sets_minus1 {
+            let mut layer_ids = Vec::new();
+            for layer_id in 0..=vps_max_layer_id {
+                if r.read_bit()? {
+                    layer_ids.push(layer_id);
+                }
+            }
+            layer_sets.push(layer_ids);
+        }
+
+        let mut vps = Self {
+            vps_video_parameter_set_id,
+            vps_base_layer_internal_flag,
+            vps_base_layer_available_flag,
+            vps_max_layers_minus1,
+            vps_max_sub_layers_minus1,
+            vps_temporal_id_nesting_flag,
+            profile_tier_level,
+            vps_sub_layer_ordering_info_present_flag,
+            sub_layer_ordering,
+            vps_max_layer_id,
+            layer_sets,
+            vps_timing_info_present_flag: r.read_bit()?,
+            vps_num_units_in_tick: 0,
+            vps_time_scale: 0,
+            vps_poc_proportional_to_timing_flag: false,
+            vps_num_ticks_poc_diff_one_minus1: 0,
+            hrd_parameters: Vec::new(),
+        };
+        if vps.vps_timing_info_present_flag {
+            vps.vps_num_units_in_tick = r.read_bits(32)?;
+            vps.vps_time_scale = r.read_bits(32)?;
+            vps.vps_poc_proportional_to_timing_flag = r.read_bit()?;
+            if vps.vps_poc_proportional_to_timing_flag {
+                vps.vps_num_ticks_poc_diff_one_minus1 =
+                    r.read_ue_max("vps_num_ticks_poc_diff_one_minus1", u32::MAX - 1)?;
+            }
+            let count = r.read_ue_max("vps_num_hrd_parameters", vps_num_layer_sets_minus1 + 1)?;
+            for i in 0..count {
+                let hrd_layer_set_idx =
+                    r.read_ue_max("hrd_layer_set_idx", vps_num_layer_sets_minus1)? as u16;
+                let cprms_present_flag = i == 0 || r.read_bit()?;
+                let common = match vps.hrd_parameters.last() {
+                    Some(previous) if !cprms_present_flag => Some(&previous.hrd),
+                    _ => None,
+                };
+                let hrd = Hrd::parse(&mut r, common, vps_max_sub_layers_minus1)?;
+                vps.hrd_parameters.push(VpsHrd {
+                    hrd_layer_set_idx,
+                    cprms_present_flag,
+                    hrd,
+                });
+            }
+        }
+        Ok(vps)
+    }
+}
@@ -1 +1,6 @@
-mod vaapi;
+pub mod bits;
+pub mod h264;
+pub mod hevc;
+pub mod nal;
+pub mod vaapi;
+pub mod vp9;
@@ -0,0 +1,386 @@
+//! Splitting H.264, HEVC and VVC byte streams into NAL units.
+//!
+//! Streams come either in Annex-B form, where every NAL unit follows a
+//! `00 00 01` start code (optionally with a leading zero byte), or
+//! length-prefixed as in MP4 (`avcC`/`hvcC`), where every NAL unit follows
+//! its big-endian size. [`NalReader`] accepts input in arbitrary pieces and
+//! returns NAL units once they are complete; [`annex_b_nals`] and
+//! [`length_prefixed_nals`] split a buffer that is already whole.
+//!
+//! NAL units are returned with their header and emulation prevention bytes
+//! in place; [`to_rbsp`] strips the latter.
+
+/// How NAL units are delimited in a stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NalFormat {
+    AnnexB,
+    /// Each NAL unit is preceded by its size in this many bytes: 1, 2 or 4.
+    LengthPrefixed(usize),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The stream ended inside a length prefix or the NAL unit it sizes.
+    Truncated { length: usize, available: usize },
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::Truncated { length, available } => write!(
+                f,
+                "truncated NAL unit: needed {} bytes, {} available",
+                length, available
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Returns the offset of the first `00 00 01` at or after `from`.
+fn find_start_code(data: &[u8], from: usize) -> Option<usize> {
+    let mut i = from;
+    while i + 3 <= data.len() {
+        // Skip ahead by how far the window is from containing a start code.
+        match data[i + 2] {
+            1 if data[i] == 0 && data[i + 1] == 0 => return Some(i),
+            0 => i += 1,
+            _ => i += 3,
+        }
+    }
+    None
+}
+
+/// Drops the zero bytes that belong to the next start code: the leading
+/// byte of a 4-byte start code and any `trailing_zero_8bits`.
+fn trim_trailing_zeros(nal: &[u8]) -> &[u8] {
+    let end = nal.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
+    &nal[..end]
+}
+
+/// Splits a complete Annex-B stream. Bytes before the first start code and
+/// empty NAL units are skipped.
+pub fn annex_b_nals(data: &[u8]) -> impl Iterator<Item = &[u8]> {
+    let mut next = find_start_code(data, 0).map(|i| i + 3);
+    std::iter::from_fn(move || loop {
+        let start = next?;
+        let end = find_start_code(data, start);
+        next = end.map(|i| i + 3);
+        let nal = trim_trailing_zeros(&data[start..end.unwrap_or(data.len())]);
+        if !nal.is_empty() {
+            return Some(nal);
+        }
+    })
+}
+
+/// Splits a complete length-prefixed stream with `length_size`-byte sizes.
+///
+/// # Panics
+///
+/// Panics if `length_size` is not 1, 2 or 4.
+pub fn length_prefixed_nals(
+    data: &[u8],
+    length_size: usize,
+) -> impl Iterator<Item = Result<&[u8], Error>> {
+    assert_length_size(length_size);
+    let mut rest = data;
+    std::iter::from_fn(move || {
+        if rest.is_empty() {
+            return None;
+        }
+        let result = split_length_prefixed(rest, length_size);
+        rest = result.as_ref().map_or(&[], |&(_, tail)| tail);
+        Some(result.map(|(nal, _)| nal))
+    })
+}
+
+fn assert_length_size(length_size: usize) {
+    assert!(
+        matches!(length_size, 1 | 2 | 4),
+        "invalid NAL length size {}",
+        length_size
+    );
+}
+
+/// Splits the first NAL unit off `data`.
+fn split_length_prefixed(data: &[u8], length_size: usize) -> Result<(&[u8], &[u8]), Error> {
+    if data.len() < length_size {
+        return Err(Error::Truncated {
+            length: length_size,
+            available: data.len(),
+        });
+    }
+    let (prefix, rest) = data.split_at(length_size);
+    let length = prefix
+        .iter()
+        .fold(0usize, |length, &b| (length << 8) | b as usize);
+    if rest.len() < length {
+        return Err(Error::Truncated {
+            length,
+            available: rest.len(),
+        });
+    }
+    Ok(rest.split_at(length))
+}
+
+/// Splits a stream fed in pieces of any size into NAL units. Call
+/// [`NalReader::next_nal`] until it returns `None` after every
+/// [`NalReader::push`], and again after [`NalReader::finish`].
+#[derive(Debug)]
+pub struct NalReader {
+    format: NalFormat,
+    buffer: Vec<u8>,
+    /// Start of the unconsumed part of `buffer`.
+    start: usize,
+    /// Annex-B only: whether `start` is just past a start code.
+    in_nal: bool,
+    /// Annex-B only: where to resume looking for the next start code.
+    scan: usize,
+    finished: bool,
+}
+
+impl NalReader {
+    /// # Panics
+    ///
+    /// Panics if `format` has a length size other than 1, 2 or 4.
+    pub fn new(format: NalFormat) -> Self {
+        if let NalFormat::LengthPrefixed(length_size) = format {
+            assert_length_size(length_size);
+        }
+        Self {
+            format,
+            buffer: Vec::new(),
+            start: 0,
+            in_nal: false,
+            scan: 0,
+            finished: false,
+        }
+    }
+
+    pub fn format(&self) -> NalFormat {
+        self.format
+    }
+
+    /// Appends stream data.
+    pub fn push(&mut self, data: &[u8]) {
+        if self.start > 0 {
+            self.buffer.drain(..self.start);
+            self.scan -= self.start;
+            self.start = 0;
+        }
+        self.buffer.extend_from_slice(data);
+    }
+
+    /// Marks the end of the stream, so that the last NAL unit of an Annex-B
+    /// stream, which has no start code after it, is returned.
+    pub fn finish(&mut self) {
+        self.finished = true;
+    }
+
+    /// Returns the next complete NAL unit, or `None` if more data is needed.
+    pub fn next_nal(&mut self) -> Result<Option<&[u8]>, Error> {
+        match self.format {
+            NalFormat::AnnexB => Ok(self.next_annex_b()),
+            NalFormat::LengthPrefixed(length_size) => self.next_length_prefixed(length_size),
+        }
+    }
+
+    fn next_annex_b(&mut self) -> Option<&[u8]> {
+        loop {
+            if !self.in_nal {
+                match find_start_code(&self.buffer, self.start) {
+                    Some(i) => {
+                        self.start = i + 3;
+                        self.scan = self.start;
+                        self.in_nal = true;
+                    }
+                    None => {
+                        // Keep bytes that may begin a start code.
+                        self.start = self.start.max(self.buffer.len().saturating_sub(2));
+                        self.scan = self.start;
+                        return None;
+                    }
+                }
+            }
+            let nal_start = self.start;
+            let nal_end = match find_start_code(&self.buffer, self.scan) {
+                Some(i) => {
+                    self.start = i + 3;
+                    self.scan = self.start;
+                    i
+                }
+                None if self.finished => {
+                    self.start = self.buffer.len();
+                    self.scan = self.start;
+                    self.in_nal = false;
+                    self.buffer.len()
+                }
+                None => {
+                    self.scan = self.buffer.len().saturating_sub(2).max(nal_start);
+                    return None;
+                }
+            };
+            let len = trim_trailing_zeros(&self.buffer[nal_start..nal_end]).len();
+            if len > 0 {
+                return Some(&self.buffer[nal_start..nal_start + len]);
+            }
+            if !self.in_nal {
+                return None;
+            }
+        }
+    }
+
+    fn next_length_prefixed(&mut self, length_size: usize) -> Result<Option<&[u8]>, Error> {
+        let data = &self.buffer[self.start..];
+        if data.is_empty() {
+            return Ok(None);
+        }
+        match split_length_prefixed(data, length_size) {
+            Ok((nal, _)) => {
+                let nal_start = self.start + length_size;
+                self.start = nal_start + nal.len();
+                self.scan = self.start;
+                Ok(Some(&self.buffer[nal_start..self.start]))
+            }
+            Err(err) if self.finished => {
+                self.start = self.buffer.len();
+                self.scan = self.start;
+                Err(err)
+            }
+            Err(_) => Ok(None),
+        }
+    }
+}
+
+/// Removes emulation prevention bytes, turning a NAL unit into its raw byte
+/// sequence payload.
+pub fn to_rbsp(nal: &[u8]) -> Vec<u8> {
+    let mut rbsp = Vec::with_capacity(nal.len());
+    to_rbsp_into(nal, &mut rbsp);
+    rbsp
+}
+
+/// Like [`to_rbsp`], but reuses `rbsp`'s allocation.
+pub fn to_rbsp_into(nal: &[u8], rbsp: &mut Vec<u8>) {
+    rbsp.clear();
+    let mut zeros = 0;
+    for &b in nal {
+        if zeros >= 2 && b == 3 {
+            zeros = 0;
+            continue;
+        }
+        zeros = if b == 0 { zeros + 1 } else { 0 };
+        rbsp.push(b);
+    }
+}
+
+/// Maps an offset into the RBSP of `nal` back to the offset of the same byte
+/// in `nal`, counting the emulation prevention bytes before it.
+pub fn nal_offset(nal: &[u8], rbsp_offset: usize) -> usize {
+    let mut zeros = 0;
+    let mut rbsp_position = 0;
+    for (i, &b) in nal.iter().enumerate() {
+        if zeros >= 2 && b == 3 {
+            zeros = 0;
+            continue;
+        }
+        if rbsp_position == rbsp_offset {
+            return i;
+        }
+        zeros = if b == 0 { zeros + 1 } else { 0 };
+        rbsp_position += 1;
+    }
+    nal.len() + rbsp_offset - rbsp_position
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STREAM: &[u8] = &[
+        0xff, // garbage before the first start code
+        0, 0, 0, 1, 0x67, 0x42, 0x00, // 4-byte start code
+        0, 0, 1, 0x68, 0xce, // 3-byte start code
+        0, 0, 1, // empty NAL unit
+        0, 0, 0, 1, 0x65, 0x88, 0, 0, 3, 1, 0x80, 0, 0, // trailing zeros
+    ];
+
+    fn expected() -> Vec<Vec<u8>> {
+        vec![
+            vec![0x67, 0x42],
+            vec![0x68, 0xce],
+            vec![0x65, 0x88, 0, 0, 3, 1, 0x80],
+        ]
+    }
+
+    #[test]
+    fn splits_annex_b() {
+        let nals: Vec<_> = annex_b_nals(STREAM).map(<[u8]>::to_vec).collect();
+        assert_eq!(nals, expected());
+        assert_eq!(annex_b_nals(&[0xff, 0, 0]).count(), 0);
+    }
+
+    #[test]
+    fn streams_annex_b_in_any_chunk_size() {
+        for chunk_size in 1..=STREAM.len() {
+            let mut reader = NalReader::new(NalFormat::AnnexB);
+            let mut nals = Vec::new();
+            for chunk in STREAM.chunks(chunk_size) {
+                reader.push(chunk);
+                while let Some(nal) = reader.next_nal().unwrap() {
+                    nals.push(nal.to_vec());
+                }
+            }
+            // The last NAL unit is only known to be complete at the end.
+            assert_eq!(nals.len(), 2, "chunk size {}", chunk_size);
+            reader.finish();
+            while let Some(nal) = reader.next_nal().unwrap() {
+                nals.push(nal.to_vec());
+            }
+            assert_eq!(nals, expected(), "chunk size {}", chunk_size);
+        }
+    }
+
+    #[test]
+    fn length_prefixed() {
+        let data = [0, 2, 0x67, 0x42, 0, 0, 0, 3, 0x65, 0x88];
+        let nals: Vec<_> = length_prefixed_nals(&data, 2).collect();
+        assert_eq!(nals[0], Ok(&[0x67, 0x42][..]));
+        assert_eq!(nals[1], Ok(&[][..]));
+        assert_eq!(
+            nals[2],
+            Err(Error::Truncated {
+                length: 3,
+                available: 2
+            })
+        );
+
+        let mut reader = NalReader::new(NalFormat::LengthPrefixed(2));
+        for &b in &data {
+            reader.push(&[b]);
+            while reader.next_nal().unwrap().is_some() {}
+        }
+        reader.push(&[0x80]);
+        assert_eq!(reader.next_nal().unwrap(), Some(&[0x65, 0x88, 0x80][..]));
+        reader.push(&[0]);
+        assert_eq!(reader.next_nal().unwrap(), None);
+        reader.finish();
+        assert!(reader.next_nal().is_err());
+        assert_eq!(reader.next_nal().unwrap(), None);
+    }
+
+    #[test]
+    fn removes_emulation_prevention() {
+        assert_eq!(
+            to_rbsp(&[0x65, 0, 0, 3, 1, 0, 0, 3, 0, 0, 3]),
+            [0x65, 0, 0, 1, 0, 0, 0, 0]
+        );
+        // Only a 3 after two zeros is an emulation prevention byte.
+        assert_eq!(to_rbsp(&[0, 3, 0, 0, 3, 3]), [0, 3, 0, 0, 3]);
+
+        let nal = [0x65, 0, 0, 3, 1, 0, 0, 3, 0];
+        let offsets: Vec<_> = (0..=7).map(|i| nal_offset(&nal, i)).collect();
+        assert_eq!(offsets, [0, 1, 2, 4, 5, 6, 8, 9]);
+    }
+}
@@ -0,0 +1,101 @@
+use std::sync::{Arc, Mutex};
+
+use vendec_libva as va;
+
+use crate::h264::Rect;
+
+/// A decoded picture.
+///
+/// The decoder does not decode into its surface again until the frame is
+/// dropped, so frames should not be held on to for longer than needed.
+pub struct Frame {
+    surface: Arc<va::Surface>,
+    visible_rect: Rect,
+    index: usize,
+    held: Arc<Mutex<Vec<bool>>>,
+}
+
+impl Frame {
+    /// The surface holding the picture. The driver may still be decoding
+    /// into it; [`va::Surface::sync`] waits until it is done.
+    pub fn surface(&self) -> &Arc<va::Surface> {
+        &self.surface
+    }
+
+    /// The part of the surface to display, in luma samples.
+    pub fn visible_rect(&self) -> Rect {
+        self.visible_rect
+    }
+}
+
+impl Drop for Frame {
+    fn drop(&mut self) {
+        self.held.lock().unwrap_or_else(|e| e.into_inner())[self.index] = false;
+    }
+}
+
+impl std::fmt::Debug for Frame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Frame")
+            .field("surface", &self.surface.handle())
+            .field("visible_rect", &self.visible_rect)
+            .finish()
+    }
+}
+
+/// The surfaces a context decodes into, which are referred to by index.
+/// A surface is busy while a [`Frame`] of it is alive; the decoder tracks
+/// which ones its reference pictures use.
+pub(crate) struct SurfaceSet {
+    surfaces: Vec<Arc<va::Surface>>,
+    held: Arc<Mutex<Vec<bool>>>,
+}
+
+impl SurfaceSet {
+    pub(crate) fn new(
+        display: Arc<va::Display>,
+        format: va::RtFormat,
+        width: u32,
+        height: u32,
+        count: u32,
+    ) -> va::VaResult<Self> {
+        let surfaces = va::Surface::new_many(
+            display,
+            format,
+            width,
+            height,
+            None,
+            va::UsageHint::DECODER,
+            count,
+        )?;
+        Ok(Self {
+            held: Arc::new(Mutex::new(vec![false; surfaces.len()])),
+            surfaces,
+        })
+    }
+
+    pub(crate) fn surfaces(&self) -> &[Arc<va::Surface>] {
+        &self.surfaces
+    }
+
+    pub(crate) fn get(&self, index: usize) -> &Arc<va::Surface> {
+        &self.surfaces[index]
+    }
+
+    /// Finds a surface that no frame holds and `in_use` is false for.
+    pub(crate) fn find_free(&self, in_use: impl Fn(usize) -> bool) -> Option<usize> {
+        let held = self.held.lock().unwrap_or_else(|e| e.into_inner());
+        (0..self.surfaces.len()).find(|&index| !held[index] && !in_use(index))
+    }
+
+    /// Hands out surface `index` as a frame.
+    pub(crate) fn frame(&self, index: usize, visible_rect: Rect) -> Frame {
+        self.held.lock().unwrap_or_else(|e| e.into_inner())[index] = true;
+        Frame {
+            surface: self.surfaces[index].clone(),
+            visible_rect,
+            index,
+            held: self.held.clone(),
+        }
+    }
+}
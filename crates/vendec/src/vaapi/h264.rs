@@ -0,0 +1,943 @@
+//! H.264 decoding with the `VAEntrypointVLD` entrypoint.
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use vendec_libva::{self as va, sys};
+
+use super::{Error, Frame, Result, SurfaceSet};
+use crate::h264::{
+    Dpb, DpbFrame, NalHeader, NalUnitType, ParameterSets, PicOrderCnt, PictureStructure, PocState,
+    Pps, Rect, RefPic, Reference, ScalingLists, SliceGroupMap, SliceHeader, SliceType, Sps,
+};
+
+/// Surfaces created beyond the DPB size: one for the picture being decoded,
+/// the rest for frames the caller has not dropped yet.
+const EXTRA_SURFACES: u32 = 4;
+
+/// Raster position of each coefficient of a 4x4 block in zigzag order.
+const ZIGZAG_4X4: [usize; 16] = [0, 1, 4, 8, 5, 2, 3, 6, 9, 12, 13, 10, 7, 11, 14, 15];
+
+/// Raster position of each coefficient of an 8x8 block in zigzag order.
+const ZIGZAG_8X8: [usize; 64] = [
+    0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5, 12, 19, 26, 33, 40, 48, 41, 34, 27, 20,
+    13, 6, 7, 14, 21, 28, 35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51, 58, 59,
+    52, 45, 38, 31, 39, 46, 53, 60, 61, 54, 47, 55, 62, 63,
+];
+
+/// The VA objects for streams of one profile, format and size.
+struct Session {
+    profile: va::Profile,
+    format: va::RtFormat,
+    width: u32,
+    height: u32,
+    context: Arc<va::Context>,
+    surfaces: SurfaceSet,
+    /// Index of a surface that is never decoded into, standing in for
+    /// missing frames when no decoded one can.
+    placeholder: usize,
+    /// Cropping of the pictures being output.
+    visible_rect: Rect,
+}
+
+/// The picture whose slices are being received.
+struct Picture {
+    /// Index of the surface in the session.
+    target: usize,
+    parameters: sys::VAPictureParameterBufferH264,
+    iq_matrix: sys::VAIQMatrixBufferH264,
+    slices: Vec<(sys::VASliceParameterBufferH264, Vec<u8>)>,
+    last_slice: SliceHeader,
+}
+
+/// Decodes an H.264 stream NAL unit by NAL unit.
+///
+/// The VA config and context are created once the first picture shows which
+/// profile, format and size the stream needs, and again whenever that
+/// changes. Only the base view of MVC streams is decoded.
+pub struct Decoder {
+    display: Arc<va::Display>,
+    profiles: Vec<va::Profile>,
+    sets: ParameterSets,
+    poc: PocState,
+    /// Holds surface indices.
+    dpb: Dpb<usize>,
+    session: Option<Session>,
+    picture: Option<Picture>,
+    output: VecDeque<Frame>,
+}
+
+impl Decoder {
+    pub fn new(display: Arc<va::Display>) -> Result<Self> {
+        let profiles = display.query_config_profiles()?;
+        Ok(Self {
+            display,
+            profiles,
+            sets: ParameterSets::new(),
+            poc: PocState::new(),
+            dpb: Dpb::new(),
+            session: None,
+            picture: None,
+            output: VecDeque::new(),
+        })
+    }
+
+    /// Decodes `nal`, a NAL unit with its header and emulation prevention
+    /// bytes, as returned by [`NalReader`](crate::nal::NalReader).
+    ///
+    /// A picture is submitted to the driver once a NAL unit shows that it
+    /// has no more slices, or on [`Decoder::flush`].
+    pub fn decode_nal(&mut self, nal: &[u8]) -> Result<()> {
+        let header = NalHeader::parse(nal)?;
+        match header.nal_unit_type {
+            NalUnitType::Slice | NalUnitType::IdrSlice => self.decode_slice(nal),
+            NalUnitType::SliceDataA | NalUnitType::SliceDataB | NalUnitType::SliceDataC => {
+                Err(Error::Unsupported("slice data partitioning"))
+            }
+            NalUnitType::AccessUnitDelimiter
+            | NalUnitType::Sei
+            | NalUnitType::Sps
+            | NalUnitType::Pps
+            | NalUnitType::SubsetSps
+            | NalUnitType::SpsExtension
+            | NalUnitType::EndOfSequence
+            | NalUnitType::EndOfStream => {
+                // These follow the last slice of a picture (7.4.1.2.3).
+                self.finish_picture()?;
+                self.sets.add_nal(nal)?;
+                Ok(())
+            }
+            // Non-base views, and NAL units that do not affect decoding.
+            _ => Ok(()),
+        }
+    }
+
+    /// Submits the last picture and outputs every frame still waiting, as
+    /// at the end of the stream.
+    pub fn flush(&mut self) -> Result<()> {
+        self.finish_picture()?;
+        self.dpb.flush();
+        self.drain_output();
+        Ok(())
+    }
+
+    /// Takes the next decoded frame in display order.
+    pub fn next_frame(&mut self) -> Option<Frame> {
+        self.output.pop_front()
+    }
+
+    fn decode_slice(&mut self, nal: &[u8]) -> Result<()> {
+        let slice = SliceHeader::parse(nal, &mut self.sets)?;
+        if slice.redundant_pic_cnt > 0 {
+            // Only primary coded pictures are decoded.
+            return Ok(());
+        }
+        if matches!(slice.slice_type, SliceType::Sp | SliceType::Si) {
+            return Err(Error::Unsupported("SP and SI slices"));
+        }
+        let new_picture = self
+            .picture
+            .as_ref()
+            .is_some_and(|picture| slice.starts_new_picture(&picture.last_slice));
+        if new_picture {
+            self.finish_picture()?;
+        }
+        if self.picture.is_none() {
+            self.start_picture(&slice)?;
+        }
+        let parameters = self.slice_parameters(&slice, nal.len())?;
+        let picture = self.picture.as_mut().expect("the picture has been started");
+        picture.slices.push((parameters, nal.to_vec()));
+        picture.last_slice = slice;
+        Ok(())
+    }
+
+    fn start_picture(&mut self, slice: &SliceHeader) -> Result<()> {
+        let sps = self
+            .sets
+            .active_sps()
+            .expect("slices activate an SPS")
+            .clone();
+        let pps = self
+            .sets
+            .active_pps()
+            .expect("slices activate a PPS")
+            .clone();
+        let visible_rect = sps.visible_rect()?;
+        self.prepare_session(&sps, &pps)?;
+
+        let session = self
+            .session
+            .as_ref()
+            .expect("the session has been prepared");
+        let frames = self.dpb.frames();
+        let target = match self.dpb.first_field_of(slice) {
+            Some(frame) => frame.handle,
+            None => session
+                .surfaces
+                .find_free(|index| {
+                    index == session.placeholder || frames.iter().any(|frame| frame.handle == index)
+                })
+                .ok_or(Error::NoFreeSurface)?,
+        };
+        // Frames filling a gap in frame_num are never decoded or output:
+        // they show the last decoded reference frame, or the placeholder
+        // surface if there is none.
+        let stand_in = frames
+            .iter()
+            .rev()
+            .find(|frame| frame.is_reference() && !frame.non_existing)
+            .map_or(session.placeholder, |frame| frame.handle);
+        let poc = self.poc.compute(&sps, slice);
+        self.dpb.start_picture(&sps, slice, poc, || stand_in)?;
+        self.drain_output();
+
+        let session = self
+            .session
+            .as_mut()
+            .expect("the session has been prepared");
+        session.visible_rect = visible_rect;
+        let parameters = self.picture_parameters(&sps, &pps, slice, poc, target);
+        self.picture = Some(Picture {
+            target,
+            parameters,
+            iq_matrix: iq_matrix(&pps.scaling_lists),
+            slices: Vec::new(),
+            last_slice: slice.clone(),
+        });
+        Ok(())
+    }
+
+    /// Makes sure the session can decode pictures of `sps`, replacing it if
+    /// needed.
+    fn prepare_session(&mut self, sps: &Sps, pps: &Pps) -> Result<()> {
+        let (candidates, format) = profile_candidates(sps, pps)?;
+        let width = sps.pic_width_in_mbs() * 16;
+        let height = sps.frame_height_in_mbs() * 16;
+        // One more for the placeholder.
+        let num_surfaces = sps.max_dpb_frames() + EXTRA_SURFACES + 1;
+        if let Some(session) = &self.session {
+            if candidates.contains(&session.profile)
+                && session.format == format
+                && session.width == width
+                && session.height == height
+                && session.surfaces.surfaces().len() >= num_surfaces as usize
+            {
+                return Ok(());
+            }
+        }
+        let profile = *candidates
+            .iter()
+            .find(|profile| self.profiles.contains(profile))
+            .ok_or(Error::UnsupportedProfile(candidates[0]))?;
+
+        // Pictures in the old surfaces cannot be referenced by the new
+        // context.
+        self.dpb.flush();
+        self.drain_output();
+        self.session = None;
+
+        let attributes = va::ConfigAttributes {
+            rt_format: Some(format),
+            ..Default::default()
+        };
+        let config = va::Config::new(
+            self.display.clone(),
+            Some(profile),
+            va::Entrypoint::VLD,
+            &attributes,
+        )?;
+        let surfaces = SurfaceSet::new(self.display.clone(), format, width, height, num_surfaces)?;
+        let flags = if sps.frame_mbs_only_flag {
+            va::ContextFlags::PROGRESSIVE
+        } else {
+            va::ContextFlags::empty()
+        };
+        let context = va::Context::new(config, width, height, flags, surfaces.surfaces().to_vec())?;
+        self.session = Some(Session {
+            profile,
+            format,
+            width,
+            height,
+            context,
+            placeholder: surfaces.surfaces().len() - 1,
+            surfaces,
+            visible_rect: sps.visible_rect()?,
+        });
+        Ok(())
+    }
+
+    fn picture_parameters(
+        &self,
+        sps: &Sps,
+        pps: &Pps,
+        slice: &SliceHeader,
+        poc: PicOrderCnt,
+        target: usize,
+    ) -> sys::VAPictureParameterBufferH264 {
+        let structure = slice.structure();
+        let mut parameters = sys::VAPictureParameterBufferH264 {
+            CurrPic: sys::VAPictureH264 {
+                picture_id: self.surface_id(target),
+                frame_idx: slice.frame_num,
+                flags: parity_flags(structure),
+                TopFieldOrderCnt: if structure != PictureStructure::BottomField {
+                    poc.top
+                } else {
+                    0
+                },
+                BottomFieldOrderCnt: if structure != PictureStructure::TopField {
+                    poc.bottom
+                } else {
+                    0
+                },
+                ..Default::default()
+            },
+            ReferenceFrames: [invalid_picture(); 16],
+            picture_width_in_mbs_minus1: (sps.pic_width_in_mbs() - 1) as u16,
+            picture_height_in_mbs_minus1: (sps.frame_height_in_mbs() - 1) as u16,
+            bit_depth_luma_minus8: sps.bit_depth_luma_minus8,
+            bit_depth_chroma_minus8: sps.bit_depth_chroma_minus8,
+            num_ref_frames: sps.max_num_ref_frames,
+            num_slice_groups_minus1: pps.num_slice_groups_minus1,
+            slice_group_map_type: pps
+                .slice_group_map
+                .as_ref()
+                .map_or(0, SliceGroupMap::slice_group_map_type),
+            slice_group_change_rate_minus1: match pps.slice_group_map {
+                Some(SliceGroupMap::Changing {
+                    slice_group_change_rate_minus1,
+                    ..
+                }) => slice_group_change_rate_minus1 as u16,
+                _ => 0,
+            },
+            pic_init_qp_minus26: pps.pic_init_qp_minus26,
+            pic_init_qs_minus26: pps.pic_init_qs_minus26,
+            chroma_qp_index_offset: pps.chroma_qp_index_offset,
+            second_chroma_qp_index_offset: pps.second_chroma_qp_index_offset,
+            frame_num: slice.frame_num as u16,
+            ..Default::default()
+        };
+        let references = self
+            .dpb
+            .frames()
+            .iter()
+            .filter(|frame| frame.is_reference());
+        for (entry, frame) in parameters.ReferenceFrames.iter_mut().zip(references) {
+            *entry = self.reference_frame(frame);
+        }
+        unsafe {
+            let seq = &mut parameters.seq_fields.bits;
+            seq.set_chroma_format_idc(sps.chroma_format_idc as u32);
+            seq.set_residual_colour_transform_flag(sps.separate_colour_plane_flag as u32);
+            seq.set_gaps_in_frame_num_value_allowed_flag(
+                sps.gaps_in_frame_num_value_allowed_flag as u32,
+            );
+            seq.set_frame_mbs_only_flag(sps.frame_mbs_only_flag as u32);
+            seq.set_mb_adaptive_frame_field_flag(sps.mb_adaptive_frame_field_flag as u32);
+            seq.set_direct_8x8_inference_flag(sps.direct_8x8_inference_flag as u32);
+            // Table A-1: no bi-prediction below 8x8 from level 3.1 on.
+            seq.set_MinLumaBiPredSize8x8((sps.level_idc >= 31) as u32);
+            seq.set_log2_max_frame_num_minus4(sps.log2_max_frame_num_minus4 as u32);
+            seq.set_pic_order_cnt_type(sps.pic_order_cnt_type as u32);
+            seq.set_log2_max_pic_order_cnt_lsb_minus4(sps.log2_max_pic_order_cnt_lsb_minus4 as u32);
+            seq.set_delta_pic_order_always_zero_flag(sps.delta_pic_order_always_zero_flag as u32);
+
+            let pic = &mut parameters.pic_fields.bits;
+            pic.set_entropy_coding_mode_flag(pps.entropy_coding_mode_flag as u32);
+            pic.set_weighted_pred_flag(pps.weighted_pred_flag as u32);
+            pic.set_weighted_bipred_idc(pps.weighted_bipred_idc as u32);
+            pic.set_transform_8x8_mode_flag(pps.transform_8x8_mode_flag as u32);
+            pic.set_field_pic_flag(slice.field_pic_flag as u32);
+            pic.set_constrained_intra_pred_flag(pps.constrained_intra_pred_flag as u32);
+            pic.set_pic_order_present_flag(pps.bottom_field_pic_order_in_frame_present_flag as u32);
+            pic.set_deblocking_filter_control_present_flag(
+                pps.deblocking_filter_control_present_flag as u32,
+            );
+            pic.set_redundant_pic_cnt_present_flag(pps.redundant_pic_cnt_present_flag as u32);
+            pic.set_reference_pic_flag((slice.nal_ref_idc != 0) as u32);
+        }
+        parameters
+    }
+
+    fn slice_parameters(
+        &self,
+        slice: &SliceHeader,
+        size: usize,
+    ) -> Result<sys::VASliceParameterBufferH264> {
+        let (list0, list1) = self.dpb.ref_pic_lists(slice)?;
+        let mut parameters = sys::VASliceParameterBufferH264 {
+            slice_data_size: size as u32,
+            slice_data_offset: 0,
+            slice_data_flag: sys::VA_SLICE_DATA_FLAG_ALL,
+            slice_data_bit_offset: slice.header_bit_size as u16,
+            first_mb_in_slice: slice.first_mb_in_slice as u16,
+            slice_type: slice.slice_type as u8,
+            direct_spatial_mv_pred_flag: slice.direct_spatial_mv_pred_flag as u8,
+            num_ref_idx_l0_active_minus1: if slice.slice_type.is_inter() {
+                slice.num_ref_idx_l0_active_minus1
+            } else {
+                0
+            },
+            num_ref_idx_l1_active_minus1: if slice.slice_type.is_b() {
+                slice.num_ref_idx_l1_active_minus1
+            } else {
+                0
+            },
+            cabac_init_idc: slice.cabac_init_idc,
+            slice_qp_delta: slice.slice_qp_delta,
+            disable_deblocking_filter_idc: slice.disable_deblocking_filter_idc,
+            slice_alpha_c0_offset_div2: slice.slice_alpha_c0_offset_div2,
+            slice_beta_offset_div2: slice.slice_beta_offset_div2,
+            RefPicList0: [invalid_picture(); 32],
+            RefPicList1: [invalid_picture(); 32],
+            ..Default::default()
+        };
+        for (entry, &pic) in parameters.RefPicList0.iter_mut().zip(&list0) {
+            *entry = self.reference_pic(pic);
+        }
+        for (entry, &pic) in parameters.RefPicList1.iter_mut().zip(&list1) {
+            *entry = self.reference_pic(pic);
+        }
+
+        if let Some(table) = &slice.pred_weight_table {
+            parameters.luma_log2_weight_denom = table.luma_log2_weight_denom;
+            parameters.chroma_log2_weight_denom = table.chroma_log2_weight_denom;
+            parameters.luma_weight_l0_flag = table.l0.iter().any(|w| w.luma_weight_flag) as u8;
+            parameters.chroma_weight_l0_flag = table.l0.iter().any(|w| w.chroma_weight_flag) as u8;
+            for (i, weight) in table.l0.iter().enumerate() {
+                parameters.luma_weight_l0[i] = weight.luma_weight;
+                parameters.luma_offset_l0[i] = weight.luma_offset;
+                parameters.chroma_weight_l0[i] = weight.chroma_weight;
+                parameters.chroma_offset_l0[i] = weight.chroma_offset;
+            }
+            parameters.luma_weight_l1_flag = table.l1.iter().any(|w| w.luma_weight_flag) as u8;
+            parameters.chroma_weight_l1_flag = table.l1.iter().any(|w| w.chroma_weight_flag) as u8;
+            for (i, weight) in table.l1.iter().enumerate() {
+                parameters.luma_weight_l1[i] = weight.luma_weight;
+                parameters.luma_offset_l1[i] = weight.luma_offset;
+                parameters.chroma_weight_l1[i] = weight.chroma_weight;
+                parameters.chroma_offset_l1[i] = weight.chroma_offset;
+            }
+        }
+        Ok(parameters)
+    }
+
+    /// Submits the picture whose slices have been received, if any.
+    fn finish_picture(&mut self) -> Result<()> {
+        let Some(picture) = self.picture.take() else {
+            return Ok(());
+        };
+        let session = self.session.as_ref().expect("pictures start a session");
+        let context = &session.context;
+        let mut buffers = vec![
+            va::Buffer::new_with_value(
+                context.clone(),
+                va::BufferType::PictureParameter,
+                &picture.parameters,
+            )?,
+            va::Buffer::new_with_value(
+                context.clone(),
+                va::BufferType::IQMatrix,
+                &picture.iq_matrix,
+            )?,
+        ];
+        for (parameters, data) in &picture.slices {
+            buffers.push(va::Buffer::new_with_value(
+                context.clone(),
+                va::BufferType::SliceParameter,
+                parameters,
+            )?);
+            buffers.push(va::Buffer::new_with_data(
+                context.clone(),
+                va::BufferType::SliceData,
+                data,
+            )?);
+        }
+        let mut va_picture = context.begin_picture(session.surfaces.get(picture.target))?;
+        va_picture.render(&buffers)?;
+        va_picture.end()?;
+
+        self.dpb.finish_picture(picture.target)?;
+        self.drain_output();
+        Ok(())
+    }
+
+    /// Moves what the DPB has output to the frame queue.
+    fn drain_output(&mut self) {
+        let Some(session) = &self.session else {
+            return;
+        };
+        while let Some(index) = self.dpb.pop_output() {
+            let frame = session.surfaces.frame(index, session.visible_rect);
+            self.output.push_back(frame);
+        }
+    }
+
+    fn surface_id(&self, index: usize) -> sys::VASurfaceID {
+        let session = self.session.as_ref().expect("pictures start a session");
+        session.surfaces.get(index).handle()
+    }
+
+    /// A frame of the DPB as a reference of the current picture, limited
+    /// to its fields that are references.
+    fn reference_frame(&self, frame: &DpbFrame<usize>) -> sys::VAPictureH264 {
+        let reference = if frame.has(Reference::LongTerm) {
+            Reference::LongTerm
+        } else {
+            Reference::ShortTerm
+        };
+        let top = frame.top.is_some_and(|f| f.reference == reference);
+        let bottom = frame.bottom.is_some_and(|f| f.reference == reference);
+        let structure = match (top, bottom) {
+            (true, false) => PictureStructure::TopField,
+            (false, true) => PictureStructure::BottomField,
+            _ => PictureStructure::Frame,
+        };
+        self.va_picture(frame, structure, reference)
+    }
+
+    fn reference_pic(&self, pic: RefPic) -> sys::VAPictureH264 {
+        let frame = &self.dpb.frames()[pic.frame];
+        let reference = match pic.structure {
+            PictureStructure::Frame if frame.is_frame(Reference::LongTerm) => Reference::LongTerm,
+            PictureStructure::Frame => Reference::ShortTerm,
+            parity => frame
+                .field(parity)
+                .map_or(Reference::ShortTerm, |f| f.reference),
+        };
+        self.va_picture(frame, pic.structure, reference)
+    }
+
+    fn va_picture(
+        &self,
+        frame: &DpbFrame<usize>,
+        structure: PictureStructure,
+        reference: Reference,
+    ) -> sys::VAPictureH264 {
+        let (frame_idx, reference_flag) = match reference {
+            Reference::LongTerm => (
+                frame.long_term_frame_idx,
+                sys::VA_PICTURE_H264_LONG_TERM_REFERENCE,
+            ),
+            _ => (frame.frame_num, sys::VA_PICTURE_H264_SHORT_TERM_REFERENCE),
+        };
+        let poc = |parity: PictureStructure| match structure {
+            PictureStructure::Frame => frame.field(parity).map_or(0, |f| f.poc),
+            _ if structure == parity => frame.field(parity).map_or(0, |f| f.poc),
+            _ => 0,
+        };
+        sys::VAPictureH264 {
+            picture_id: self.surface_id(frame.handle),
+            frame_idx,
+            flags: reference_flag | parity_flags(structure),
+            TopFieldOrderCnt: poc(PictureStructure::TopField),
+            BottomFieldOrderCnt: poc(PictureStructure::BottomField),
+            ..Default::default()
+        }
+    }
+}
+
+/// The VA profiles that can decode a stream, most specific first, and the
+/// format of its surfaces.
+fn profile_candidates(sps: &Sps, pps: &Pps) -> Result<(&'static [va::Profile], va::RtFormat)> {
+    use va::Profile::*;
+
+    if sps.chroma_format_idc > 1 {
+        return Err(Error::Unsupported("4:2:2 and 4:4:4 chroma"));
+    }
+    if pps.num_slice_groups_minus1 > 0 {
+        return Err(Error::Unsupported("slice groups"));
+    }
+    // Monochrome pictures are decoded into 4:2:0 surfaces.
+    match (sps.bit_depth_luma(), sps.bit_depth_chroma()) {
+        (8, 8) => {}
+        (luma, chroma) if luma <= 10 && chroma <= 10 => {
+            return Ok((&[H264High10], va::RtFormat::YUV420_10))
+        }
+        _ => return Err(Error::Unsupported("bit depths above 10")),
+    }
+    let profiles: &'static [va::Profile] = match sps.profile_idc {
+        66 => &[H264ConstrainedBaseline, H264Main, H264High],
+        // Extended profile streams without its extra tools are Main ones.
+        77 | 88 => &[H264Main, H264High],
+        _ => &[H264High],
+    };
+    Ok((profiles, va::RtFormat::YUV420))
+}
+
+fn iq_matrix(lists: &ScalingLists) -> sys::VAIQMatrixBufferH264 {
+    let mut matrix = sys::VAIQMatrixBufferH264::default();
+    for (raster, list) in matrix.ScalingList4x4.iter_mut().zip(&lists.list_4x4) {
+        for (&position, &value) in ZIGZAG_4X4.iter().zip(list) {
+            raster[position] = value;
+        }
+    }
+    // VA only takes the luma 8x8 lists, intra then inter.
+    for (raster, list) in matrix.ScalingList8x8.iter_mut().zip(&lists.list_8x8) {
+        for (&position, &value) in ZIGZAG_8X8.iter().zip(list) {
+            raster[position] = value;
+        }
+    }
+    matrix
+}
+
+fn parity_flags(structure: PictureStructure) -> u32 {
+    match structure {
+        PictureStructure::Frame => 0,
+        PictureStructure::TopField => sys::VA_PICTURE_H264_TOP_FIELD,
+        PictureStructure::BottomField => sys::VA_PICTURE_H264_BOTTOM_FIELD,
+    }
+}
+
+fn invalid_picture() -> sys::VAPictureH264 {
+    sys::VAPictureH264 {
+        picture_id: sys::VA_INVALID_SURFACE,
+        flags: sys::VA_PICTURE_H264_INVALID,
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bits::BitWriter;
+    use va::fake::{self, FakeBackend, FakePicture};
+
+    fn sps_nal(profile_idc: u64, bit_depth_minus8: u32, frame_mbs_only: bool) -> Vec<u8> {
+        let mut w = BitWriter::with_emulation_prevention();
+        w.write_bits(0x67, 8);
+        w.write_bits(profile_idc, 8);
+        w.write_bits(0, 8);
+        w.write_bits(30, 8); // level_idc
+        w.write_ue(0);
+        if profile_idc == 110 {
+            w.write_ue(1); // chroma_format_idc
+            w.write_ue(bit_depth_minus8);
+            w.write_ue(bit_depth_minus8);
+            w.write_bit(false);
+            w.write_bit(false);
+        }
+        w.write_ue(0); // log2_max_frame_num_minus4
+        w.write_ue(0); // pic_order_cnt_type
+        w.write_ue(0);
+        w.write_ue(2); // max_num_ref_frames
+        w.write_bit(false);
+        w.write_ue(1); // pic_width_in_mbs_minus1
+        w.write_ue(1);
+        w.write_bit(frame_mbs_only);
+        if !frame_mbs_only {
+            w.write_bit(false); // mb_adaptive_frame_field_flag
+        }
+        w.write_bit(true);
+        w.write_bit(false);
+        w.write_bit(false);
+        w.write_trailing_bits();
+        w.into_bytes()
+    }
+
+    fn pps_nal() -> Vec<u8> {
+        let mut w = BitWriter::with_emulation_prevention();
+        w.write_bits(0x68, 8);
+        w.write_ue(0);
+        w.write_ue(0);
+        w.write_bit(false); // entropy_coding_mode_flag
+        w.write_bit(false);
+        w.write_ue(0);
+        w.write_ue(0); // num_ref_idx_l0_default_active_minus1
+        w.write_ue(0);
+        w.write_bit(false);
+        w.write_bits(0, 2);
+        w.write_se(0);
+        w.write_se(0);
+        w.write_se(0);
+        w.write_bit(false);
+        w.write_bit(false);
+        w.write_bit(false);
+        w.write_trailing_bits();
+        w.into_bytes()
+    }
+
+    /// A slice covering a whole picture, followed by a byte of slice data.
+    fn slice_nal(slice_type: SliceType, frame_num: u64, poc_lsb: u64, nal_ref_idc: u64) -> Vec<u8> {
+        let idr = frame_num == 0;
+        let mut w = BitWriter::with_emulation_prevention();
+        w.write_bits(nal_ref_idc << 5 | if idr { 5 } else { 1 }, 8);
+        w.write_ue(0);
+        w.write_ue(slice_type as u32);
+        w.write_ue(0);
+        w.write_bits(frame_num, 4);
+        if idr {
+            w.write_ue(0); // idr_pic_id
+        }
+        w.write_bits(poc_lsb, 4);
+        if slice_type.is_b() {
+            w.write_bit(true); // direct_spatial_mv_pred_flag
+        }
+        if slice_type.is_inter() {
+            w.write_bit(false); // num_ref_idx_active_override_flag
+            w.write_bit(false); // ref_pic_list_modification_flag_l0
+        }
+        if slice_type.is_b() {
+            w.write_bit(false);
+        }
+        if nal_ref_idc != 0 {
+            if idr {
+                w.write_bit(false);
+                w.write_bit(false);
+            } else {
+                w.write_bit(false); // adaptive_ref_pic_marking_mode_flag
+            }
+        }
+        w.write_se(-2); // slice_qp_delta
+        w.write_bits(0xa5, 8);
+        w.write_trailing_bits();
+        w.into_bytes()
+    }
+
+    fn setup() -> (FakeBackend, Decoder) {
+        let fake = FakeBackend::default();
+        let display = fake::open_display(&fake.library()).unwrap();
+        (fake, Decoder::new(display).unwrap())
+    }
+
+    fn read<T: Copy>(data: &[u8]) -> T {
+        assert_eq!(data.len(), std::mem::size_of::<T>());
+        unsafe { std::ptr::read_unaligned(data.as_ptr().cast()) }
+    }
+
+    fn slice_parameters(picture: &FakePicture) -> sys::VASliceParameterBufferH264 {
+        read(&picture.buffers[2].data)
+    }
+
+    #[test]
+    fn decodes_in_display_order() {
+        let (fake, mut decoder) = setup();
+        let slices = [
+            slice_nal(SliceType::I, 0, 0, 3),
+            slice_nal(SliceType::P, 1, 8, 2),
+            slice_nal(SliceType::B, 2, 4, 0),
+        ];
+        decoder.decode_nal(&sps_nal(77, 0, true)).unwrap();
+        decoder.decode_nal(&pps_nal()).unwrap();
+        for slice in &slices {
+            decoder.decode_nal(slice).unwrap();
+        }
+        // The last picture is only known to be complete at the end.
+        assert_eq!(fake.pictures().len(), 2);
+        decoder.flush().unwrap();
+
+        let pictures = fake.pictures();
+        assert_eq!(pictures.len(), 3);
+        let types: Vec<_> = pictures[1].buffers.iter().map(|b| b.buffer_type).collect();
+        assert_eq!(
+            types,
+            [
+                Some(va::BufferType::PictureParameter),
+                Some(va::BufferType::IQMatrix),
+                Some(va::BufferType::SliceParameter),
+                Some(va::BufferType::SliceData),
+            ]
+        );
+        for (picture, slice) in pictures.iter().zip(&slices) {
+            assert_eq!(picture.buffers[3].data, *slice);
+        }
+        assert_eq!(fake.violations(), Vec::<String>::new());
+
+        let (i, p, b) = (&pictures[0], &pictures[1], &pictures[2]);
+        let parameters: sys::VAPictureParameterBufferH264 = read(&p.buffers[0].data);
+        assert_eq!(parameters.CurrPic.picture_id, p.target);
+        assert_eq!(parameters.CurrPic.TopFieldOrderCnt, 8);
+        assert_eq!(parameters.picture_width_in_mbs_minus1, 1);
+        assert_eq!(parameters.ReferenceFrames[0].picture_id, i.target);
+        assert_eq!(
+            parameters.ReferenceFrames[0].flags,
+            sys::VA_PICTURE_H264_SHORT_TERM_REFERENCE
+        );
+        assert_eq!(
+            parameters.ReferenceFrames[1].picture_id,
+            sys::VA_INVALID_SURFACE
+        );
+        assert_eq!(
+            unsafe { parameters.pic_fields.bits.reference_pic_flag() },
+            1
+        );
+
+        let iq_matrix: sys::VAIQMatrixBufferH264 = read(&p.buffers[1].data);
+        assert_eq!(iq_matrix.ScalingList4x4, [[16; 16]; 6]);
+
+        let slice = slice_parameters(p);
+        assert_eq!(slice.slice_type, SliceType::P as u8);
+        assert_eq!(slice.slice_qp_delta, -2);
+        assert_eq!(slice.slice_data_size as usize, slices[1].len());
+        // NAL header, three ue(0), frame_num, LSB, one bit each for the
+        // override flag, modification flag and marking, and se(-2).
+        assert_eq!(slice.slice_data_bit_offset, 8 + 3 + 4 + 4 + 3 + 5);
+        assert_eq!(slice.RefPicList0[0].picture_id, i.target);
+        assert_eq!(slice.RefPicList0[1].flags, sys::VA_PICTURE_H264_INVALID);
+
+        let slice = slice_parameters(b);
+        assert_eq!(slice.RefPicList0[0].picture_id, i.target);
+        assert_eq!(slice.RefPicList1[0].picture_id, p.target);
+        assert_eq!(slice.RefPicList1[0].TopFieldOrderCnt, 8);
+
+        let frames: Vec<_> = std::iter::from_fn(|| decoder.next_frame()).collect();
+        let order: Vec<_> = frames.iter().map(|f| f.surface().handle()).collect();
+        assert_eq!(order, [i.target, b.target, p.target]);
+        assert_eq!(
+            frames[0].visible_rect(),
+            Rect {
+                x: 0,
+                y: 0,
+                width: 32,
+                height: 32
+            }
+        );
+    }
+
+    #[test]
+    fn reuses_surfaces_of_dropped_frames() {
+        let (fake, mut decoder) = setup();
+        decoder.decode_nal(&sps_nal(66, 0, true)).unwrap();
+        decoder.decode_nal(&pps_nal()).unwrap();
+        let mut frames = Vec::new();
+        loop {
+            match decoder.decode_nal(&slice_nal(SliceType::I, 0, 0, 3)) {
+                Ok(()) => {
+                    decoder.flush().unwrap();
+                    frames.extend(decoder.next_frame());
+                }
+                Err(err) => {
+                    assert!(matches!(err, Error::NoFreeSurface), "{}", err);
+                    break;
+                }
+            }
+        }
+        // Every surface is held by a frame once the DPB has output them.
+        let surfaces = frames.len();
+        assert!(surfaces > 1);
+        frames.clear();
+        decoder
+            .decode_nal(&slice_nal(SliceType::I, 0, 0, 3))
+            .unwrap();
+        decoder.flush().unwrap();
+        assert!(decoder.next_frame().is_some());
+        assert_eq!(fake.pictures().len(), surfaces + 1);
+    }
+
+    #[test]
+    fn offsets_slice_data_in_the_rbsp() {
+        let (fake, mut decoder) = setup();
+        decoder.decode_nal(&sps_nal(66, 0, true)).unwrap();
+        decoder.decode_nal(&pps_nal()).unwrap();
+        let mut w = BitWriter::with_emulation_prevention();
+        w.write_bits(0x65, 8);
+        w.write_ue(0);
+        w.write_ue(SliceType::I as u32);
+        w.write_ue(0);
+        w.write_bits(0, 4);
+        w.write_ue(16383); // idr_pic_id, long enough to need escaping
+        w.write_bits(0, 4);
+        w.write_bit(false);
+        w.write_bit(false);
+        w.write_se(-2);
+        w.write_bits(0xa5, 8);
+        w.write_trailing_bits();
+        let nal = w.into_bytes();
+        assert!(nal[..7].windows(3).any(|bytes| bytes == [0, 0, 3]));
+        decoder.decode_nal(&nal).unwrap();
+        decoder.flush().unwrap();
+
+        let slice = slice_parameters(&fake.pictures()[0]);
+        assert_eq!(
+            slice.slice_data_bit_offset,
+            8 + 1 + 3 + 1 + 4 + 29 + 4 + 2 + 5
+        );
+    }
+
+    #[test]
+    fn fills_frame_num_gaps_with_decoded_frames() {
+        let (fake, mut decoder) = setup();
+        decoder.decode_nal(&sps_nal(77, 0, true)).unwrap();
+        decoder.decode_nal(&pps_nal()).unwrap();
+        decoder
+            .decode_nal(&slice_nal(SliceType::I, 0, 0, 3))
+            .unwrap();
+        decoder
+            .decode_nal(&slice_nal(SliceType::P, 3, 2, 2))
+            .unwrap();
+        decoder.flush().unwrap();
+
+        let pictures = fake.pictures();
+        let (i, p) = (&pictures[0], &pictures[1]);
+        let parameters: sys::VAPictureParameterBufferH264 = read(&p.buffers[0].data);
+        // Frames 1 and 2 are missing, and have pushed out the I frame.
+        let references: Vec<_> = parameters.ReferenceFrames[..2]
+            .iter()
+            .map(|frame| (frame.picture_id, frame.frame_idx))
+            .collect();
+        assert_eq!(references, [(i.target, 1), (i.target, 2)]);
+        assert_ne!(p.target, i.target);
+        assert_eq!(fake.violations(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn decodes_second_fields_without_a_free_surface() {
+        let (fake, mut decoder) = setup();
+        decoder.decode_nal(&sps_nal(77, 0, false)).unwrap();
+        decoder.decode_nal(&pps_nal()).unwrap();
+        let field = |idr: bool, bottom: bool| {
+            let mut w = BitWriter::with_emulation_prevention();
+            w.write_bits(if idr { 0x65 } else { 0x61 }, 8);
+            w.write_ue(0);
+            w.write_ue(SliceType::I as u32);
+            w.write_ue(0);
+            w.write_bits(0, 4);
+            w.write_bit(true); // field_pic_flag
+            w.write_bit(bottom);
+            if idr {
+                w.write_ue(0);
+            }
+            w.write_bits(u64::from(bottom), 4);
+            if idr {
+                w.write_bit(false);
+                w.write_bit(false);
+            } else {
+                w.write_bit(false);
+            }
+            w.write_se(0);
+            w.write_bits(0xa5, 8);
+            w.write_trailing_bits();
+            w.into_bytes()
+        };
+        decoder.decode_nal(&field(true, false)).unwrap();
+        // Hold every other surface.
+        let session = decoder.session.as_ref().unwrap();
+        let top = decoder.picture.as_ref().unwrap().target;
+        let _held: Vec<_> = std::iter::from_fn(|| {
+            let index = session.surfaces.find_free(|index| index == top)?;
+            Some(session.surfaces.frame(index, session.visible_rect))
+        })
+        .collect();
+        decoder.decode_nal(&field(false, true)).unwrap();
+        decoder.flush().unwrap();
+
+        let pictures = fake.pictures();
+        assert_eq!(pictures.len(), 2);
+        assert_eq!(pictures[1].target, pictures[0].target);
+    }
+
+    #[test]
+    fn rejects_unsupported_streams() {
+        let (_fake, mut decoder) = setup();
+        decoder.decode_nal(&sps_nal(110, 2, true)).unwrap();
+        decoder.decode_nal(&pps_nal()).unwrap();
+        let err = decoder
+            .decode_nal(&slice_nal(SliceType::I, 0, 0, 3))
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            Error::UnsupportedProfile(va::Profile::H264High10)
+        ));
+    }
+}
@@ -0,0 +1,1198 @@
+//! HEVC decoding with the `VAEntrypointVLD` entrypoint.
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use vendec_libva::{self as va, sys};
+
+use super::{Error, Frame, Result, SurfaceSet};
+use crate::h264::Rect;
+use crate::hevc::{
+    Dpb, DpbPicture, NalHeader, NalUnitType, ParameterSets, PocState, Pps, Reference, ScalingLists,
+    SliceSegmentHeader, Sps,
+};
+
+/// Surfaces created beyond the DPB size: one for the picture being decoded,
+/// the rest for frames the caller has not dropped yet.
+const EXTRA_SURFACES: u32 = 4;
+
+/// Entries of `ReferenceFrames` and of the reference picture lists.
+const MAX_REFERENCES: usize = 15;
+
+/// Raster position of each coefficient of a 4x4 block in up-right diagonal
+/// scan order.
+const DIAGONAL_4X4: [usize; 16] = [0, 4, 1, 8, 5, 2, 12, 9, 6, 3, 13, 10, 7, 14, 11, 15];
+
+/// Raster position of each coefficient of an 8x8 block in up-right diagonal
+/// scan order.
+const DIAGONAL_8X8: [usize; 64] = [
+    0, 8, 1, 16, 9, 2, 24, 17, 10, 3, 32, 25, 18, 11, 4, 40, 33, 26, 19, 12, 5, 48, 41, 34, 27, 20,
+    13, 6, 56, 49, 42, 35, 28, 21, 14, 7, 57, 50, 43, 36, 29, 22, 15, 58, 51, 44, 37, 30, 23, 59,
+    52, 45, 38, 31, 60, 53, 46, 39, 61, 54, 47, 62, 55, 63,
+];
+
+/// The VA objects for streams of one profile, format and size.
+struct Session {
+    profile: va::Profile,
+    format: va::RtFormat,
+    width: u32,
+    height: u32,
+    context: Arc<va::Context>,
+    surfaces: SurfaceSet,
+    /// Index of a surface that is never decoded into, standing in for
+    /// missing references when no decoded picture can.
+    placeholder: usize,
+    /// Cropping of the pictures being output.
+    visible_rect: Rect,
+}
+
+/// The picture whose slices are being received.
+struct Picture {
+    /// Index of the surface in the session.
+    target: usize,
+    /// The DPB pictures in `ReferenceFrames`, by index.
+    references: Vec<usize>,
+    parameters: sys::VAPictureParameterBufferHEVCExtension,
+    iq_matrix: Option<sys::VAIQMatrixBufferHEVC>,
+    slices: Vec<(sys::VASliceParameterBufferHEVCExtension, Vec<u8>)>,
+}
+
+/// Decodes an HEVC stream NAL unit by NAL unit.
+///
+/// The VA config and context are created once the first picture shows which
+/// profile, format and size the stream needs, and again whenever that
+/// changes. Only the base layer is decoded, and decoding starts at the first
+/// IRAP picture.
+pub struct Decoder {
+    display: Arc<va::Display>,
+    profiles: Vec<va::Profile>,
+    sets: ParameterSets,
+    poc: PocState,
+    /// Holds surface indices.
+    dpb: Dpb<usize>,
+    session: Option<Session>,
+    picture: Option<Picture>,
+    /// The last independent slice segment header, which dependent slice
+    /// segments take their values from.
+    last_slice: Option<SliceSegmentHeader>,
+    /// `NoRaslOutputFlag` of the last IRAP picture. The RASL pictures
+    /// associated with it are skipped.
+    no_rasl_output_flag: bool,
+    /// Whether the next picture starts the stream or follows an end of
+    /// sequence.
+    first_picture: bool,
+    output: VecDeque<Frame>,
+}
+
+impl Decoder {
+    pub fn new(display: Arc<va::Display>) -> Result<Self> {
+        let profiles = display.query_config_profiles()?;
+        Ok(Self {
+            display,
+            profiles,
+            sets: ParameterSets::new(),
+            poc: PocState::new(),
+            dpb: Dpb::new(),
+            session: None,
+            picture: None,
+            last_slice: None,
+            no_rasl_output_flag: true,
+            first_picture: true,
+            output: VecDeque::new(),
+        })
+    }
+
+    /// Decodes `nal`, a NAL unit with its header and emulation prevention
+    /// bytes, as returned by [`NalReader`](crate::nal::NalReader).
+    ///
+    /// A picture is submitted to the driver once a NAL unit shows that it
+    /// has no more slices, or on [`Decoder::flush`].
+    pub fn decode_nal(&mut self, nal: &[u8]) -> Result<()> {
+        let header = NalHeader::parse(nal)?;
+        if header.nuh_layer_id != 0 {
+            return Ok(());
+        }
+        match header.nal_unit_type {
+            nal_unit_type if nal_unit_type.is_slice() => self.decode_slice(nal),
+            NalUnitType::Vps
+            | NalUnitType::Sps
+            | NalUnitType::Pps
+            | NalUnitType::AccessUnitDelimiter
+            | NalUnitType::PrefixSei => {
+                // These precede the first slice segment of a picture
+                // (7.4.2.4.4).
+                self.finish_picture()?;
+                self.sets.add_nal(nal)?;
+                Ok(())
+            }
+            NalUnitType::EndOfSequence | NalUnitType::EndOfBitstream => {
+                // The next picture is an IRAP one that references nothing
+                // before it, so everything can be output now.
+                self.flush()?;
+                self.first_picture = true;
+                Ok(())
+            }
+            // NAL units that do not affect decoding.
+            _ => Ok(()),
+        }
+    }
+
+    /// Submits the last picture and outputs every frame still waiting, as
+    /// at the end of the stream.
+    pub fn flush(&mut self) -> Result<()> {
+        self.finish_picture()?;
+        self.dpb.flush();
+        self.drain_output();
+        Ok(())
+    }
+
+    /// Takes the next decoded frame in display order.
+    pub fn next_frame(&mut self) -> Option<Frame> {
+        self.output.pop_front()
+    }
+
+    fn decode_slice(&mut self, nal: &[u8]) -> Result<()> {
+        let slice = SliceSegmentHeader::parse(nal, &mut self.sets, self.last_slice.as_ref())?;
+        if slice.first_slice_segment_in_pic_flag {
+            self.finish_picture()?;
+            self.start_picture(&slice)?;
+        }
+        if !slice.dependent_slice_segment_flag {
+            self.last_slice = Some(slice.clone());
+        }
+        if self.picture.is_none() {
+            // The picture is skipped, or its first slice segment is missing.
+            return Ok(());
+        }
+        let parameters = self.slice_parameters(&slice, nal.len())?;
+        let picture = self.picture.as_mut().expect("the picture has been started");
+        picture.slices.push((parameters, nal.to_vec()));
+        Ok(())
+    }
+
+    /// Starts decoding the picture of `slice`, unless it is to be skipped.
+    fn start_picture(&mut self, slice: &SliceSegmentHeader) -> Result<()> {
+        let nal_unit_type = slice.nal_unit_type;
+        if nal_unit_type.is_irap() {
+            // HandleCraAsBlaFlag is never set.
+            self.no_rasl_output_flag =
+                nal_unit_type.is_idr() || nal_unit_type.is_bla() || self.first_picture;
+            self.first_picture = false;
+        } else if self.first_picture || (nal_unit_type.is_rasl() && self.no_rasl_output_flag) {
+            // 8.1.3: the pictures these reference are not available, and
+            // they are neither decoded nor output.
+            return Ok(());
+        }
+
+        let sps = self
+            .sets
+            .active_sps()
+            .expect("slices activate an SPS")
+            .clone();
+        let pps = self
+            .sets
+            .active_pps()
+            .expect("slices activate a PPS")
+            .clone();
+        let visible_rect = sps.visible_rect()?;
+        self.prepare_session(&sps, &pps)?;
+
+        let session = self
+            .session
+            .as_ref()
+            .expect("the session has been prepared");
+        let pictures = self.dpb.pictures();
+        let target = session
+            .surfaces
+            .find_free(|index| {
+                index == session.placeholder
+                    || pictures.iter().any(|picture| picture.handle == index)
+            })
+            .ok_or(Error::NoFreeSurface)?;
+        // Missing references are never output: they show the last decoded
+        // reference picture, or the placeholder surface if there is none.
+        let stand_in = pictures
+            .iter()
+            .rev()
+            .find(|picture| picture.is_reference() && !picture.generated)
+            .map_or(session.placeholder, |picture| picture.handle);
+        let poc = self.poc.compute(&sps, slice, self.no_rasl_output_flag);
+        self.dpb
+            .start_picture(&sps, slice, poc, self.no_rasl_output_flag, || stand_in)?;
+        self.drain_output();
+
+        let session = self
+            .session
+            .as_mut()
+            .expect("the session has been prepared");
+        session.visible_rect = visible_rect;
+        let references: Vec<usize> = (0..self.dpb.pictures().len())
+            .filter(|&index| self.dpb.pictures()[index].is_reference())
+            .take(MAX_REFERENCES)
+            .collect();
+        let parameters = self.picture_parameters(&sps, &pps, slice, poc, target, &references);
+        self.picture = Some(Picture {
+            target,
+            references,
+            parameters,
+            iq_matrix: sps
+                .scaling_list_enabled_flag
+                .then(|| iq_matrix(pps.scaling_lists.as_ref().unwrap_or(&sps.scaling_lists))),
+            slices: Vec::new(),
+        });
+        Ok(())
+    }
+
+    /// Makes sure the session can decode pictures of `sps`, replacing it if
+    /// needed.
+    fn prepare_session(&mut self, sps: &Sps, pps: &Pps) -> Result<()> {
+        let (candidates, format) = profile_candidates(sps, pps)?;
+        let width = sps.pic_width_in_luma_samples;
+        let height = sps.pic_height_in_luma_samples;
+        let num_surfaces = sps
+            .highest_sub_layer_ordering()
+            .max_dec_pic_buffering_minus1 as u32
+            + 1
+            + EXTRA_SURFACES
+            // The placeholder.
+            + 1;
+        if let Some(session) = &self.session {
+            if candidates.contains(&session.profile)
+                && session.format == format
+                && session.width == width
+                && session.height == height
+                && session.surfaces.surfaces().len() >= num_surfaces as usize
+            {
+                return Ok(());
+            }
+        }
+        let profile = *candidates
+            .iter()
+            .find(|profile| self.profiles.contains(profile))
+            .ok_or(Error::UnsupportedProfile(candidates[0]))?;
+
+        // Pictures in the old surfaces cannot be referenced by the new
+        // context.
+        self.dpb.flush();
+        self.drain_output();
+        self.session = None;
+
+        let attributes = va::ConfigAttributes {
+            rt_format: Some(format),
+            ..Default::default()
+        };
+        let config = va::Config::new(
+            self.display.clone(),
+            Some(profile),
+            va::Entrypoint::VLD,
+            &attributes,
+        )?;
+        let surfaces = SurfaceSet::new(self.display.clone(), format, width, height, num_surfaces)?;
+        let context = va::Context::new(
+            config,
+            width,
+            height,
+            va::ContextFlags::PROGRESSIVE,
+            surfaces.surfaces().to_vec(),
+        )?;
+        self.session = Some(Session {
+            profile,
+            format,
+            width,
+            height,
+            context,
+            placeholder: surfaces.surfaces().len() - 1,
+            surfaces,
+            visible_rect: sps.visible_rect()?,
+        });
+        Ok(())
+    }
+
+    fn picture_parameters(
+        &self,
+        sps: &Sps,
+        pps: &Pps,
+        slice: &SliceSegmentHeader,
+        poc: i32,
+        target: usize,
+        references: &[usize],
+    ) -> sys::VAPictureParameterBufferHEVCExtension {
+        let pcm = sps.pcm.unwrap_or_default();
+        let mut base = sys::VAPictureParameterBufferHEVC {
+            CurrPic: sys::VAPictureHEVC {
+                picture_id: self.surface_id(target),
+                pic_order_cnt: poc,
+                flags: 0,
+                ..Default::default()
+            },
+            ReferenceFrames: [invalid_picture(); MAX_REFERENCES],
+            pic_width_in_luma_samples: sps.pic_width_in_luma_samples as u16,
+            pic_height_in_luma_samples: sps.pic_height_in_luma_samples as u16,
+            sps_max_dec_pic_buffering_minus1: sps
+                .highest_sub_layer_ordering()
+                .max_dec_pic_buffering_minus1,
+            bit_depth_luma_minus8: sps.bit_depth_luma_minus8,
+            bit_depth_chroma_minus8: sps.bit_depth_chroma_minus8,
+            pcm_sample_bit_depth_luma_minus1: pcm.pcm_sample_bit_depth_luma_minus1,
+            pcm_sample_bit_depth_chroma_minus1: pcm.pcm_sample_bit_depth_chroma_minus1,
+            log2_min_luma_coding_block_size_minus3: sps.log2_min_luma_coding_block_size_minus3,
+            log2_diff_max_min_luma_coding_block_size: sps.log2_diff_max_min_luma_coding_block_size,
+            log2_min_transform_block_size_minus2: sps.log2_min_luma_transform_block_size_minus2,
+            log2_diff_max_min_transform_block_size: sps.log2_diff_max_min_luma_transform_block_size,
+            log2_min_pcm_luma_coding_block_size_minus3: pcm
+                .log2_min_pcm_luma_coding_block_size_minus3,
+            log2_diff_max_min_pcm_luma_coding_block_size: pcm
+                .log2_diff_max_min_pcm_luma_coding_block_size,
+            max_transform_hierarchy_depth_intra: sps.max_transform_hierarchy_depth_intra,
+            max_transform_hierarchy_depth_inter: sps.max_transform_hierarchy_depth_inter,
+            init_qp_minus26: pps.init_qp_minus26,
+            diff_cu_qp_delta_depth: pps.diff_cu_qp_delta_depth,
+            pps_cb_qp_offset: pps.pps_cb_qp_offset,
+            pps_cr_qp_offset: pps.pps_cr_qp_offset,
+            log2_parallel_merge_level_minus2: pps.log2_parallel_merge_level_minus2,
+            log2_max_pic_order_cnt_lsb_minus4: sps.log2_max_pic_order_cnt_lsb_minus4,
+            num_short_term_ref_pic_sets: sps.short_term_ref_pic_sets.len() as u8,
+            num_long_term_ref_pic_sps: sps.long_term_ref_pics.len() as u8,
+            num_ref_idx_l0_default_active_minus1: pps.num_ref_idx_l0_default_active_minus1,
+            num_ref_idx_l1_default_active_minus1: pps.num_ref_idx_l1_default_active_minus1,
+            pps_beta_offset_div2: pps.pps_beta_offset_div2,
+            pps_tc_offset_div2: pps.pps_tc_offset_div2,
+            num_extra_slice_header_bits: pps.num_extra_slice_header_bits,
+            st_rps_bits: slice.short_term_ref_pic_set_bits,
+            ..Default::default()
+        };
+        if pps.tiles_enabled_flag {
+            base.num_tile_columns_minus1 = pps.num_tile_columns_minus1;
+            base.num_tile_rows_minus1 = pps.num_tile_rows_minus1;
+            for (entry, width) in base
+                .column_width_minus1
+                .iter_mut()
+                .zip(pps.column_widths(sps))
+            {
+                *entry = (width - 1) as u16;
+            }
+            for (entry, height) in base.row_height_minus1.iter_mut().zip(pps.row_heights(sps)) {
+                *entry = (height - 1) as u16;
+            }
+        }
+        let rps = self.dpb.rps();
+        for (entry, &index) in base.ReferenceFrames.iter_mut().zip(references) {
+            let picture = &self.dpb.pictures()[index];
+            let mut flags = 0;
+            if picture.reference == Reference::LongTerm {
+                flags |= sys::VA_PICTURE_HEVC_LONG_TERM_REFERENCE;
+            }
+            if rps.st_curr_before.contains(&index) {
+                flags |= sys::VA_PICTURE_HEVC_RPS_ST_CURR_BEFORE;
+            } else if rps.st_curr_after.contains(&index) {
+                flags |= sys::VA_PICTURE_HEVC_RPS_ST_CURR_AFTER;
+            } else if rps.lt_curr.contains(&index) {
+                flags |= sys::VA_PICTURE_HEVC_RPS_LT_CURR;
+            }
+            *entry = self.va_picture(picture, flags);
+        }
+
+        let nal_unit_type = slice.nal_unit_type;
+        unsafe {
+            let pic = &mut base.pic_fields.bits;
+            pic.set_chroma_format_idc(sps.chroma_format_idc as u32);
+            pic.set_separate_colour_plane_flag(sps.separate_colour_plane_flag as u32);
+            pic.set_pcm_enabled_flag(sps.pcm.is_some() as u32);
+            pic.set_scaling_list_enabled_flag(sps.scaling_list_enabled_flag as u32);
+            pic.set_transform_skip_enabled_flag(pps.transform_skip_enabled_flag as u32);
+            pic.set_amp_enabled_flag(sps.amp_enabled_flag as u32);
+            pic.set_strong_intra_smoothing_enabled_flag(
+                sps.strong_intra_smoothing_enabled_flag as u32,
+            );
+            pic.set_sign_data_hiding_enabled_flag(pps.sign_data_hiding_enabled_flag as u32);
+            pic.set_constrained_intra_pred_flag(pps.constrained_intra_pred_flag as u32);
+            pic.set_cu_qp_delta_enabled_flag(pps.cu_qp_delta_enabled_flag as u32);
+            pic.set_weighted_pred_flag(pps.weighted_pred_flag as u32);
+            pic.set_weighted_bipred_flag(pps.weighted_bipred_flag as u32);
+            pic.set_transquant_bypass_enabled_flag(pps.transquant_bypass_enabled_flag as u32);
+            pic.set_tiles_enabled_flag(pps.tiles_enabled_flag as u32);
+            pic.set_entropy_coding_sync_enabled_flag(pps.entropy_coding_sync_enabled_flag as u32);
+            pic.set_pps_loop_filter_across_slices_enabled_flag(
+                pps.pps_loop_filter_across_slices_enabled_flag as u32,
+            );
+            pic.set_loop_filter_across_tiles_enabled_flag(
+                pps.loop_filter_across_tiles_enabled_flag as u32,
+            );
+            pic.set_pcm_loop_filter_disabled_flag(pcm.pcm_loop_filter_disabled_flag as u32);
+            pic.set_NoPicReorderingFlag(
+                (sps.highest_sub_layer_ordering().max_num_reorder_pics == 0) as u32,
+            );
+
+            let parsing = &mut base.slice_parsing_fields.bits;
+            parsing.set_lists_modification_present_flag(pps.lists_modification_present_flag as u32);
+            parsing.set_long_term_ref_pics_present_flag(sps.long_term_ref_pics_present_flag as u32);
+            parsing.set_sps_temporal_mvp_enabled_flag(sps.sps_temporal_mvp_enabled_flag as u32);
+            parsing.set_cabac_init_present_flag(pps.cabac_init_present_flag as u32);
+            parsing.set_output_flag_present_flag(pps.output_flag_present_flag as u32);
+            parsing.set_dependent_slice_segments_enabled_flag(
+                pps.dependent_slice_segments_enabled_flag as u32,
+            );
+            parsing.set_pps_slice_chroma_qp_offsets_present_flag(
+                pps.pps_slice_chroma_qp_offsets_present_flag as u32,
+            );
+            parsing.set_sample_adaptive_offset_enabled_flag(
+                sps.sample_adaptive_offset_enabled_flag as u32,
+            );
+            parsing.set_deblocking_filter_override_enabled_flag(
+                pps.deblocking_filter_override_enabled_flag as u32,
+            );
+            parsing.set_pps_disable_deblocking_filter_flag(
+                pps.pps_deblocking_filter_disabled_flag as u32,
+            );
+            parsing.set_slice_segment_header_extension_present_flag(
+                pps.slice_segment_header_extension_present_flag as u32,
+            );
+            parsing.set_RapPicFlag(nal_unit_type.is_irap() as u32);
+            parsing.set_IdrPicFlag(nal_unit_type.is_idr() as u32);
+            parsing.set_IntraPicFlag(nal_unit_type.is_irap() as u32);
+        }
+
+        let sps_range = &sps.range_extension;
+        let pps_range = &pps.range_extension;
+        let mut rext = sys::VAPictureParameterBufferHEVCRext {
+            diff_cu_chroma_qp_offset_depth: pps_range.diff_cu_chroma_qp_offset_depth,
+            chroma_qp_offset_list_len_minus1: pps_range.chroma_qp_offset_list_len_minus1,
+            log2_sao_offset_scale_luma: pps_range.log2_sao_offset_scale_luma,
+            log2_sao_offset_scale_chroma: pps_range.log2_sao_offset_scale_chroma,
+            log2_max_transform_skip_block_size_minus2: pps_range
+                .log2_max_transform_skip_block_size_minus2,
+            ..Default::default()
+        };
+        for (entry, &offset) in rext
+            .cb_qp_offset_list
+            .iter_mut()
+            .zip(&pps_range.cb_qp_offset_list)
+        {
+            *entry = offset;
+        }
+        for (entry, &offset) in rext
+            .cr_qp_offset_list
+            .iter_mut()
+            .zip(&pps_range.cr_qp_offset_list)
+        {
+            *entry = offset;
+        }
+        unsafe {
+            let range = &mut rext.range_extension_pic_fields.bits;
+            range.set_transform_skip_rotation_enabled_flag(
+                sps_range.transform_skip_rotation_enabled_flag as u32,
+            );
+            range.set_transform_skip_context_enabled_flag(
+                sps_range.transform_skip_context_enabled_flag as u32,
+            );
+            range.set_implicit_rdpcm_enabled_flag(sps_range.implicit_rdpcm_enabled_flag as u32);
+            range.set_explicit_rdpcm_enabled_flag(sps_range.explicit_rdpcm_enabled_flag as u32);
+            range.set_extended_precision_processing_flag(
+                sps_range.extended_precision_processing_flag as u32,
+            );
+            range.set_intra_smoothing_disabled_flag(sps_range.intra_smoothing_disabled_flag as u32);
+            range.set_high_precision_offsets_enabled_flag(
+                sps_range.high_precision_offsets_enabled_flag as u32,
+            );
+            range.set_persistent_rice_adaptation_enabled_flag(
+                sps_range.persistent_rice_adaptation_enabled_flag as u32,
+            );
+            range.set_cabac_bypass_alignment_enabled_flag(
+                sps_range.cabac_bypass_alignment_enabled_flag as u32,
+            );
+            range.set_cross_component_prediction_enabled_flag(
+                pps_range.cross_component_prediction_enabled_flag as u32,
+            );
+            range.set_chroma_qp_offset_list_enabled_flag(
+                pps_range.chroma_qp_offset_list_enabled_flag as u32,
+            );
+        }
+        sys::VAPictureParameterBufferHEVCExtension {
+            base,
+            rext,
+            ..Default::default()
+        }
+    }
+
+    fn slice_parameters(
+        &self,
+        slice: &SliceSegmentHeader,
+        size: usize,
+    ) -> Result<sys::VASliceParameterBufferHEVCExtension> {
+        let picture = self.picture.as_ref().expect("the picture has been started");
+        let (list0, list1) = self.dpb.ref_pic_lists(slice)?;
+        let mut base = sys::VASliceParameterBufferHEVC {
+            slice_data_size: size as u32,
+            slice_data_offset: 0,
+            slice_data_flag: sys::VA_SLICE_DATA_FLAG_ALL,
+            slice_data_byte_offset: (slice.header_bit_size / 8) as u32,
+            slice_segment_address: slice.slice_segment_address,
+            RefPicList: [[0xff; MAX_REFERENCES]; 2],
+            collocated_ref_idx: if slice.slice_temporal_mvp_enabled_flag {
+                slice.collocated_ref_idx
+            } else {
+                0xff
+            },
+            num_ref_idx_l0_active_minus1: if slice.slice_type.is_inter() {
+                slice.num_ref_idx_l0_active_minus1
+            } else {
+                0
+            },
+            num_ref_idx_l1_active_minus1: if slice.slice_type.is_b() {
+                slice.num_ref_idx_l1_active_minus1
+            } else {
+                0
+            },
+            slice_qp_delta: slice.slice_qp_delta,
+            slice_cb_qp_offset: slice.slice_cb_qp_offset,
+            slice_cr_qp_offset: slice.slice_cr_qp_offset,
+            slice_beta_offset_div2: slice.slice_beta_offset_div2,
+            slice_tc_offset_div2: slice.slice_tc_offset_div2,
+            five_minus_max_num_merge_cand: slice.five_minus_max_num_merge_cand,
+            num_entry_point_offsets: slice.num_entry_point_offsets() as u16,
+            slice_data_num_emu_prevn_bytes: slice.header_emulation_prevention_bytes as u16,
+            ..Default::default()
+        };
+        for (entries, list) in base.RefPicList.iter_mut().zip([list0, list1]) {
+            for (entry, index) in entries.iter_mut().zip(list) {
+                let slot = picture
+                    .references
+                    .iter()
+                    .position(|&reference| reference == index)
+                    .ok_or(Error::Unsupported("more than 15 reference pictures"))?;
+                *entry = slot as u8;
+            }
+        }
+        unsafe {
+            let flags = &mut base.LongSliceFlags.fields;
+            flags.set_dependent_slice_segment_flag(slice.dependent_slice_segment_flag as u32);
+            flags.set_slice_type(slice.slice_type as u32);
+            flags.set_color_plane_id(slice.colour_plane_id as u32);
+            flags.set_slice_sao_luma_flag(slice.slice_sao_luma_flag as u32);
+            flags.set_slice_sao_chroma_flag(slice.slice_sao_chroma_flag as u32);
+            flags.set_mvd_l1_zero_flag(slice.mvd_l1_zero_flag as u32);
+            flags.set_cabac_init_flag(slice.cabac_init_flag as u32);
+            flags.set_slice_temporal_mvp_enabled_flag(slice.slice_temporal_mvp_enabled_flag as u32);
+            flags.set_slice_deblocking_filter_disabled_flag(
+                slice.slice_deblocking_filter_disabled_flag as u32,
+            );
+            flags.set_collocated_from_l0_flag(slice.collocated_from_l0_flag as u32);
+            flags.set_slice_loop_filter_across_slices_enabled_flag(
+                slice.slice_loop_filter_across_slices_enabled_flag as u32,
+            );
+        }
+
+        let mut rext = sys::VASliceParameterBufferHEVCRext {
+            slice_act_y_qp_offset: slice.slice_act_y_qp_offset,
+            slice_act_cb_qp_offset: slice.slice_act_cb_qp_offset,
+            slice_act_cr_qp_offset: slice.slice_act_cr_qp_offset,
+            ..Default::default()
+        };
+        unsafe {
+            let flags = &mut rext.slice_ext_flags.bits;
+            flags.set_cu_chroma_qp_offset_enabled_flag(
+                slice.cu_chroma_qp_offset_enabled_flag as u32,
+            );
+            flags.set_use_integer_mv_flag(slice.use_integer_mv_flag as u32);
+        }
+
+        if let Some(table) = &slice.pred_weight_table {
+            base.luma_log2_weight_denom = table.luma_log2_weight_denom;
+            base.delta_chroma_log2_weight_denom = table.delta_chroma_log2_weight_denom;
+            // The base structure only has room for 8-bit offsets; the range
+            // extension one has them in full for high precision offsets.
+            for (i, weight) in table.l0.iter().enumerate() {
+                base.delta_luma_weight_l0[i] = weight.delta_luma_weight;
+                base.luma_offset_l0[i] = weight.luma_offset as i8;
+                base.delta_chroma_weight_l0[i] = weight.delta_chroma_weight;
+                base.ChromaOffsetL0[i] = weight.chroma_offset.map(|offset| offset as i8);
+                rext.luma_offset_l0[i] = weight.luma_offset;
+                rext.ChromaOffsetL0[i] = weight.chroma_offset;
+            }
+            for (i, weight) in table.l1.iter().enumerate() {
+                base.delta_luma_weight_l1[i] = weight.delta_luma_weight;
+                base.luma_offset_l1[i] = weight.luma_offset as i8;
+                base.delta_chroma_weight_l1[i] = weight.delta_chroma_weight;
+                base.ChromaOffsetL1[i] = weight.chroma_offset.map(|offset| offset as i8);
+                rext.luma_offset_l1[i] = weight.luma_offset;
+                rext.ChromaOffsetL1[i] = weight.chroma_offset;
+            }
+        }
+        Ok(sys::VASliceParameterBufferHEVCExtension { base, rext })
+    }
+
+    /// Submits the picture whose slices have been received, if any.
+    fn finish_picture(&mut self) -> Result<()> {
+        let Some(mut picture) = self.picture.take() else {
+            return Ok(());
+        };
+        if let Some((last, _)) = picture.slices.last_mut() {
+            unsafe { last.base.LongSliceFlags.fields.set_LastSliceOfPic(1) };
+        }
+        let session = self.session.as_ref().expect("pictures start a session");
+        let context = &session.context;
+        // Only the range extension profiles take the extended structures.
+        let extended = !matches!(
+            session.profile,
+            va::Profile::HEVCMain | va::Profile::HEVCMain10
+        );
+        let mut buffers = vec![if extended {
+            va::Buffer::new_with_value(
+                context.clone(),
+                va::BufferType::PictureParameter,
+                &picture.parameters,
+            )?
+        } else {
+            va::Buffer::new_with_value(
+                context.clone(),
+                va::BufferType::PictureParameter,
+                &picture.parameters.base,
+            )?
+        }];
+        if let Some(iq_matrix) = &picture.iq_matrix {
+            buffers.push(va::Buffer::new_with_value(
+                context.clone(),
+                va::BufferType::IQMatrix,
+                iq_matrix,
+            )?);
+        }
+        for (parameters, data) in &picture.slices {
+            buffers.push(if extended {
+                va::Buffer::new_with_value(
+                    context.clone(),
+                    va::BufferType::SliceParameter,
+                    parameters,
+                )?
+            } else {
+                va::Buffer::new_with_value(
+                    context.clone(),
+                    va::BufferType::SliceParameter,
+                    &parameters.base,
+                )?
+            });
+            buffers.push(va::Buffer::new_with_data(
+                context.clone(),
+                va::BufferType::SliceData,
+                data,
+            )?);
+        }
+        let mut va_picture = context.begin_picture(session.surfaces.get(picture.target))?;
+        va_picture.render(&buffers)?;
+        va_picture.end()?;
+
+        self.dpb.finish_picture(picture.target)?;
+        self.drain_output();
+        Ok(())
+    }
+
+    /// Moves what the DPB has output to the frame queue.
+    fn drain_output(&mut self) {
+        let Some(session) = &self.session else {
+            return;
+        };
+        while let Some(index) = self.dpb.pop_output() {
+            let frame = session.surfaces.frame(index, session.visible_rect);
+            self.output.push_back(frame);
+        }
+    }
+
+    fn surface_id(&self, index: usize) -> sys::VASurfaceID {
+        let session = self.session.as_ref().expect("pictures start a session");
+        session.surfaces.get(index).handle()
+    }
+
+    fn va_picture(&self, picture: &DpbPicture<usize>, flags: u32) -> sys::VAPictureHEVC {
+        sys::VAPictureHEVC {
+            picture_id: self.surface_id(picture.handle),
+            pic_order_cnt: picture.poc,
+            flags,
+            ..Default::default()
+        }
+    }
+}
+
+/// The VA profiles that can decode a stream, most specific first, and the
+/// format of its surfaces.
+fn profile_candidates(sps: &Sps, pps: &Pps) -> Result<(&'static [va::Profile], va::RtFormat)> {
+    use va::Profile::*;
+
+    let scc = &sps.scc_extension;
+    if scc.sps_curr_pic_ref_enabled_flag
+        || scc.palette_mode_enabled_flag
+        || scc.motion_vector_resolution_control_idc != 0
+        || scc.intra_boundary_filtering_disabled_flag
+        || pps
+            .scc_extension
+            .residual_adaptive_colour_transform_enabled_flag
+    {
+        return Err(Error::Unsupported("screen content coding tools"));
+    }
+    if sps.chroma_format_idc == 0 {
+        return Err(Error::Unsupported("monochrome"));
+    }
+    if sps.separate_colour_plane_flag {
+        return Err(Error::Unsupported("separate colour planes"));
+    }
+    let bit_depth = sps.bit_depth_luma().max(sps.bit_depth_chroma());
+    if bit_depth > 12 {
+        return Err(Error::Unsupported("bit depths above 12"));
+    }
+    // 4:2:0 streams using range extension tools need a 4:4:4 profile.
+    let range_extension = sps.range_extension != Default::default()
+        || pps.range_extension.cross_component_prediction_enabled_flag
+        || pps.range_extension.chroma_qp_offset_list_enabled_flag
+        || pps
+            .range_extension
+            .log2_max_transform_skip_block_size_minus2
+            != 0;
+    let profiles: &'static [va::Profile] = match (sps.chroma_format_idc, bit_depth) {
+        (1, 8) if !range_extension => &[HEVCMain, HEVCMain10, HEVCMain12],
+        (1, 9..=10) if !range_extension => &[HEVCMain10, HEVCMain12],
+        (1, _) if !range_extension => &[HEVCMain12],
+        (2, ..=10) => &[HEVCMain422_10, HEVCMain422_12],
+        (2, _) => &[HEVCMain422_12],
+        (_, 8) => &[HEVCMain444, HEVCMain444_10, HEVCMain444_12],
+        (_, 9..=10) => &[HEVCMain444_10, HEVCMain444_12],
+        _ => &[HEVCMain444_12],
+    };
+    use va::RtFormat as F;
+    let format = match (sps.chroma_format_idc, bit_depth) {
+        (1, 8) => F::YUV420,
+        (1, 9..=10) => F::YUV420_10,
+        (1, _) => F::YUV420_12,
+        (2, 8) => F::YUV422,
+        (2, 9..=10) => F::YUV422_10,
+        (2, _) => F::YUV422_12,
+        (_, 8) => F::YUV444,
+        (_, 9..=10) => F::YUV444_10,
+        _ => F::YUV444_12,
+    };
+    Ok((profiles, format))
+}
+
+/// VA takes the lists in raster order rather than in the coded diagonal
+/// order. Only the luma 32x32 lists are passed.
+fn iq_matrix(lists: &ScalingLists) -> sys::VAIQMatrixBufferHEVC {
+    fn raster<const N: usize>(diagonal: &[usize; N], list: &[u8; N]) -> [u8; N] {
+        let mut raster = [0; N];
+        for (&position, &value) in diagonal.iter().zip(list) {
+            raster[position] = value;
+        }
+        raster
+    }
+    sys::VAIQMatrixBufferHEVC {
+        ScalingList4x4: lists.list_4x4.map(|list| raster(&DIAGONAL_4X4, &list)),
+        ScalingList8x8: lists.list_8x8.map(|list| raster(&DIAGONAL_8X8, &list)),
+        ScalingList16x16: lists.list_16x16.map(|list| raster(&DIAGONAL_8X8, &list)),
+        ScalingList32x32: [0, 3].map(|i| raster(&DIAGONAL_8X8, &lists.list_32x32[i])),
+        ScalingListDC16x16: lists.dc_16x16,
+        ScalingListDC32x32: [lists.dc_32x32[0], lists.dc_32x32[3]],
+        ..Default::default()
+    }
+}
+
+fn invalid_picture() -> sys::VAPictureHEVC {
+    sys::VAPictureHEVC {
+        picture_id: sys::VA_INVALID_SURFACE,
+        flags: sys::VA_PICTURE_HEVC_INVALID,
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bits::BitWriter;
+    use crate::hevc::SliceType;
+    use va::fake::{self, FakeBackend, FakePicture};
+
+    /// A 64x64 SPS with 16x16 CTBs, 8-bit POC LSBs, five picture buffers
+    /// and up to two pictures reordered.
+    fn sps_nal(chroma_format_idc: u32, bit_depth_minus8: u32) -> Vec<u8> {
+        let mut w = BitWriter::with_emulation_prevention();
+        w.write_bits(0x4201, 16);
+        w.write_bits(0, 4);
+        w.write_bits(0, 3); // sps_max_sub_layers_minus1
+        w.write_bit(true);
+        let profile_idc = match (chroma_format_idc, bit_depth_minus8) {
+            (1, 0) => 1,
+            (1, _) => 2,
+            _ => 4,
+        };
+        w.write_bits(profile_idc, 8);
+        w.write_bits(1 << (31 - profile_idc), 32);
+        w.write_bits(0, 48);
+        w.write_bits(93, 8); // general_level_idc
+        w.write_ue(0);
+        w.write_ue(chroma_format_idc);
+        w.write_ue(64);
+        w.write_ue(64);
+        w.write_bit(false); // conformance_window_flag
+        w.write_ue(bit_depth_minus8);
+        w.write_ue(bit_depth_minus8);
+        w.write_ue(4); // log2_max_pic_order_cnt_lsb_minus4
+        w.write_bit(false);
+        w.write_ue(4); // sps_max_dec_pic_buffering_minus1
+        w.write_ue(2);
+        w.write_ue(0);
+        w.write_ue(0); // log2_min_luma_coding_block_size_minus3
+        w.write_ue(1);
+        w.write_ue(0);
+        w.write_ue(2);
+        w.write_ue(1);
+        w.write_ue(1);
+        w.write_bits(0, 4); // scaling_list_enabled_flag to pcm_enabled_flag
+        w.write_ue(0); // num_short_term_ref_pic_sets
+        w.write_bits(0, 5); // long_term_ref_pics_present_flag to sps_extension_present_flag
+        w.write_trailing_bits();
+        w.into_bytes()
+    }
+
+    /// A PPS allowing dependent slice segments and nothing else optional.
+    fn pps_nal() -> Vec<u8> {
+        let mut w = BitWriter::with_emulation_prevention();
+        w.write_bits(0x4401, 16);
+        w.write_ue(0);
+        w.write_ue(0);
+        w.write_bit(true); // dependent_slice_segments_enabled_flag
+        w.write_bit(false);
+        w.write_bits(0, 3);
+        w.write_bits(0, 2);
+        w.write_ue(0); // num_ref_idx_l0_default_active_minus1
+        w.write_ue(0);
+        w.write_se(0);
+        w.write_bits(0, 3);
+        w.write_se(0);
+        w.write_se(0);
+        w.write_bits(0, 6); // pps_slice_chroma_qp_offsets_present_flag to entropy_coding_sync_enabled_flag
+        w.write_bits(0, 4);
+        w.write_ue(0); // log2_parallel_merge_level_minus2
+        w.write_bits(0, 2);
+        w.write_trailing_bits();
+        w.into_bytes()
+    }
+
+    /// The first slice segment of a picture, with an explicit RPS of
+    /// references at the `negative` and `positive` POC deltas, followed by
+    /// a byte of slice data.
+    fn slice_nal(
+        nal_unit_type: NalUnitType,
+        slice_type: SliceType,
+        poc_lsb: u64,
+        negative: &[u32],
+        positive: &[u32],
+    ) -> Vec<u8> {
+        let mut w = BitWriter::with_emulation_prevention();
+        w.write_bits((nal_unit_type.value() as u64) << 9 | 1, 16);
+        w.write_bit(true); // first_slice_segment_in_pic_flag
+        if nal_unit_type.is_irap() {
+            w.write_bit(false);
+        }
+        w.write_ue(0);
+        w.write_ue(slice_type as u32);
+        if !nal_unit_type.is_idr() {
+            w.write_bits(poc_lsb, 8);
+            w.write_bit(false); // short_term_ref_pic_set_sps_flag
+            w.write_ue(negative.len() as u32);
+            w.write_ue(positive.len() as u32);
+            for deltas in [negative, positive] {
+                let mut previous = 0;
+                for &delta in deltas {
+                    w.write_ue(delta - previous - 1);
+                    w.write_bit(true); // used_by_curr_pic_flag
+                    previous = delta;
+                }
+            }
+        }
+        if slice_type.is_inter() {
+            w.write_bit(false); // num_ref_idx_active_override_flag
+            if slice_type.is_b() {
+                w.write_bit(false);
+            }
+            w.write_ue(0); // five_minus_max_num_merge_cand
+        }
+        w.write_se(-2); // slice_qp_delta
+        w.write_trailing_bits();
+        w.write_bits(0xa5, 8);
+        w.into_bytes()
+    }
+
+    /// A dependent slice segment of a TRAIL_R picture.
+    fn dependent_slice_nal(address: u64) -> Vec<u8> {
+        let mut w = BitWriter::with_emulation_prevention();
+        w.write_bits(0x0201, 16);
+        w.write_bit(false);
+        w.write_ue(0);
+        w.write_bit(true); // dependent_slice_segment_flag
+        w.write_bits(address, 4);
+        w.write_trailing_bits();
+        w.write_bits(0x5a, 8);
+        w.into_bytes()
+    }
+
+    fn setup() -> (FakeBackend, Decoder) {
+        let fake = FakeBackend::default();
+        let display = fake::open_display(&fake.library()).unwrap();
+        (fake, Decoder::new(display).unwrap())
+    }
+
+    fn read<T: Copy>(data: &[u8]) -> T {
+        assert_eq!(data.len(), std::mem::size_of::<T>());
+        unsafe { std::ptr::read_unaligned(data.as_ptr().cast()) }
+    }
+
+    fn slice_parameters(picture: &FakePicture, index: usize) -> sys::VASliceParameterBufferHEVC {
+        read(&picture.buffers[1 + 2 * index].data)
+    }
+
+    fn surfaces(frames: impl Iterator<Item = Frame>) -> Vec<sys::VASurfaceID> {
+        frames.map(|frame| frame.surface().handle()).collect()
+    }
+
+    #[test]
+    fn decodes_in_display_order() {
+        let (fake, mut decoder) = setup();
+        decoder.decode_nal(&sps_nal(1, 0)).unwrap();
+        decoder.decode_nal(&pps_nal()).unwrap();
+        let slices = [
+            slice_nal(NalUnitType::IdrNLp, SliceType::I, 0, &[], &[]),
+            slice_nal(NalUnitType::TrailR, SliceType::P, 8, &[8], &[]),
+            dependent_slice_nal(8),
+            slice_nal(NalUnitType::TrailN, SliceType::B, 4, &[4], &[4]),
+        ];
+        for slice in &slices {
+            decoder.decode_nal(slice).unwrap();
+        }
+        // The last picture is only known to be complete at the end.
+        assert_eq!(fake.pictures().len(), 2);
+        decoder.flush().unwrap();
+
+        let pictures = fake.pictures();
+        assert_eq!(pictures.len(), 3);
+        let types: Vec<_> = pictures[1].buffers.iter().map(|b| b.buffer_type).collect();
+        assert_eq!(
+            types,
+            [
+                Some(va::BufferType::PictureParameter),
+                Some(va::BufferType::SliceParameter),
+                Some(va::BufferType::SliceData),
+                Some(va::BufferType::SliceParameter),
+                Some(va::BufferType::SliceData),
+            ]
+        );
+        assert_eq!(pictures[1].buffers[2].data, slices[1]);
+        assert_eq!(pictures[1].buffers[4].data, slices[2]);
+        assert_eq!(fake.violations(), Vec::<String>::new());
+
+        let (i, p, b) = (&pictures[0], &pictures[1], &pictures[2]);
+        let parameters: sys::VAPictureParameterBufferHEVC = read(&i.buffers[0].data);
+        assert_eq!(parameters.CurrPic.picture_id, i.target);
+        assert_eq!(parameters.pic_width_in_luma_samples, 64);
+        assert_eq!(parameters.log2_diff_max_min_luma_coding_block_size, 1);
+        assert_eq!(
+            parameters.ReferenceFrames[0].flags,
+            sys::VA_PICTURE_HEVC_INVALID
+        );
+        unsafe {
+            assert_eq!(parameters.pic_fields.bits.chroma_format_idc(), 1);
+            assert_eq!(parameters.slice_parsing_fields.bits.IdrPicFlag(), 1);
+            assert_eq!(
+                parameters
+                    .slice_parsing_fields
+                    .bits
+                    .dependent_slice_segments_enabled_flag(),
+                1
+            );
+        }
+
+        let parameters: sys::VAPictureParameterBufferHEVC = read(&p.buffers[0].data);
+        assert_eq!(parameters.CurrPic.pic_order_cnt, 8);
+        assert_eq!(parameters.ReferenceFrames[0].picture_id, i.target);
+        assert_eq!(
+            parameters.ReferenceFrames[0].flags,
+            sys::VA_PICTURE_HEVC_RPS_ST_CURR_BEFORE
+        );
+        assert_eq!(
+            parameters.ReferenceFrames[1].picture_id,
+            sys::VA_INVALID_SURFACE
+        );
+        // num_negative_pics, num_positive_pics, delta_poc_s0_minus1 and
+        // used_by_curr_pic_s0_flag.
+        assert_eq!(parameters.st_rps_bits, 3 + 1 + 7 + 1);
+        unsafe {
+            assert_eq!(parameters.slice_parsing_fields.bits.RapPicFlag(), 0);
+        }
+
+        let slice = slice_parameters(p, 0);
+        assert_eq!(slice.slice_data_size as usize, slices[1].len());
+        // The NAL header, then 33 bits up to the RPS, the RPS, the override
+        // flag, five_minus_max_num_merge_cand, se(-2) and the alignment.
+        assert_eq!(slice.slice_data_byte_offset, 7);
+        assert_eq!(slice.slice_qp_delta, -2);
+        assert_eq!(slice.RefPicList[0][..2], [0, 0xff]);
+        assert_eq!(slice.RefPicList[1][0], 0xff);
+        unsafe {
+            assert_eq!(
+                slice.LongSliceFlags.fields.slice_type(),
+                SliceType::P as u32
+            );
+            assert_eq!(slice.LongSliceFlags.fields.LastSliceOfPic(), 0);
+        }
+        let slice = slice_parameters(p, 1);
+        assert_eq!(slice.slice_segment_address, 8);
+        assert_eq!(slice.slice_data_byte_offset, 3);
+        assert_eq!(slice.slice_qp_delta, -2);
+        assert_eq!(slice.RefPicList[0][0], 0);
+        unsafe {
+            assert_eq!(
+                slice.LongSliceFlags.fields.dependent_slice_segment_flag(),
+                1
+            );
+            assert_eq!(slice.LongSliceFlags.fields.LastSliceOfPic(), 1);
+        }
+
+        let parameters: sys::VAPictureParameterBufferHEVC = read(&b.buffers[0].data);
+        let references: Vec<_> = parameters.ReferenceFrames[..2]
+            .iter()
+            .map(|picture| (picture.picture_id, picture.pic_order_cnt, picture.flags))
+            .collect();
+        assert_eq!(
+            references,
+            [
+                (i.target, 0, sys::VA_PICTURE_HEVC_RPS_ST_CURR_BEFORE),
+                (p.target, 8, sys::VA_PICTURE_HEVC_RPS_ST_CURR_AFTER),
+            ]
+        );
+        let slice = slice_parameters(b, 0);
+        assert_eq!(slice.RefPicList[0][..2], [0, 0xff]);
+        assert_eq!(slice.RefPicList[1][..2], [1, 0xff]);
+
+        let frames = surfaces(std::iter::from_fn(|| decoder.next_frame()));
+        assert_eq!(frames, [i.target, b.target, p.target]);
+    }
+
+    #[test]
+    fn skips_pictures_before_random_access() {
+        let (fake, mut decoder) = setup();
+        decoder.decode_nal(&sps_nal(1, 0)).unwrap();
+        decoder.decode_nal(&pps_nal()).unwrap();
+        let slices = [
+            slice_nal(NalUnitType::TrailR, SliceType::P, 2, &[2], &[]),
+            slice_nal(NalUnitType::Cra, SliceType::I, 8, &[], &[]),
+            // References a picture before the CRA one.
+            slice_nal(NalUnitType::RaslN, SliceType::B, 6, &[2], &[2]),
+            slice_nal(NalUnitType::TrailR, SliceType::P, 12, &[4], &[]),
+        ];
+        for slice in &slices {
+            decoder.decode_nal(slice).unwrap();
+        }
+        assert!(decoder.next_frame().is_none());
+        // The end of sequence outputs every picture.
+        decoder.decode_nal(&[0x48, 0x01]).unwrap();
+        let pictures = fake.pictures();
+        assert_eq!(pictures.len(), 2);
+        let frames = surfaces(std::iter::from_fn(|| decoder.next_frame()));
+        assert_eq!(frames, [pictures[0].target, pictures[1].target]);
+        let parameters: sys::VAPictureParameterBufferHEVC = read(&pictures[1].buffers[0].data);
+        assert_eq!(parameters.CurrPic.pic_order_cnt, 12);
+        assert_eq!(parameters.ReferenceFrames[0].pic_order_cnt, 8);
+
+        // A CRA picture following an end of sequence starts over.
+        decoder
+            .decode_nal(&slice_nal(NalUnitType::Cra, SliceType::I, 40, &[], &[]))
+            .unwrap();
+        decoder
+            .decode_nal(&slice_nal(NalUnitType::RaslR, SliceType::P, 36, &[4], &[]))
+            .unwrap();
+        decoder.flush().unwrap();
+        assert_eq!(fake.pictures().len(), 3);
+        assert_eq!(fake.violations(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn stands_in_for_missing_references() {
+        let (fake, mut decoder) = setup();
+        decoder.decode_nal(&sps_nal(1, 0)).unwrap();
+        decoder.decode_nal(&pps_nal()).unwrap();
+        decoder
+            .decode_nal(&slice_nal(NalUnitType::IdrNLp, SliceType::I, 0, &[], &[]))
+            .unwrap();
+        // References a picture 4 that was never sent.
+        decoder
+            .decode_nal(&slice_nal(
+                NalUnitType::TrailR,
+                SliceType::P,
+                8,
+                &[4, 8],
+                &[],
+            ))
+            .unwrap();
+        decoder.flush().unwrap();
+
+        let pictures = fake.pictures();
+        let (i, p) = (&pictures[0], &pictures[1]);
+        let parameters: sys::VAPictureParameterBufferHEVC = read(&p.buffers[0].data);
+        let mut references: Vec<_> = parameters.ReferenceFrames[..2]
+            .iter()
+            .map(|picture| (picture.picture_id, picture.pic_order_cnt))
+            .collect();
+        references.sort_by_key(|&(_, poc)| poc);
+        assert_eq!(references, [(i.target, 0), (i.target, 4)]);
+        assert_ne!(p.target, i.target);
+        assert_eq!(fake.violations(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn iq_matrix_is_in_raster_order() {
+        let mut lists = ScalingLists::default();
+        lists.list_4x4[1] = std::array::from_fn(|i| i as u8);
+        lists.list_8x8[2] = std::array::from_fn(|i| i as u8);
+        lists.list_16x16[4] = std::array::from_fn(|i| 64 + i as u8);
+        lists.list_32x32[3] = std::array::from_fn(|i| 128 + i as u8);
+        lists.dc_32x32[3] = 20;
+        let matrix = iq_matrix(&lists);
+
+        // The first row and column of a block are its top and left edges
+        // in diagonal order.
+        let list = matrix.ScalingList4x4[1];
+        assert_eq!(list[..4], [0, 2, 5, 9]);
+        assert_eq!([list[4], list[8], list[12], list[15]], [1, 3, 6, 15]);
+        let list = matrix.ScalingList8x8[2];
+        assert_eq!(list[..8], [0, 2, 5, 9, 14, 20, 27, 35]);
+        assert_eq!([list[8], list[16], list[56], list[63]], [1, 3, 28, 63]);
+        assert_eq!(matrix.ScalingList16x16[4][..3], [64, 66, 69]);
+        assert_eq!(matrix.ScalingList16x16[4][8], 65);
+        assert_eq!(matrix.ScalingList32x32[1][..3], [128, 130, 133]);
+        assert_eq!(matrix.ScalingList32x32[1][8], 129);
+        assert_eq!(matrix.ScalingListDC32x32, [16, 20]);
+        assert_eq!(matrix.ScalingList4x4[0], [16; 16]);
+    }
+
+    #[test]
+    fn selects_profiles() {
+        let (fake, mut decoder) = setup();
+        decoder.decode_nal(&sps_nal(1, 2)).unwrap();
+        decoder.decode_nal(&pps_nal()).unwrap();
+        decoder
+            .decode_nal(&slice_nal(NalUnitType::IdrWRadl, SliceType::I, 0, &[], &[]))
+            .unwrap();
+        decoder.flush().unwrap();
+        assert_eq!(
+            decoder.session.as_ref().unwrap().profile,
+            va::Profile::HEVCMain10
+        );
+        let parameters: sys::VAPictureParameterBufferHEVC =
+            read(&fake.pictures()[0].buffers[0].data);
+        assert_eq!(parameters.bit_depth_luma_minus8, 2);
+        assert!(decoder.next_frame().is_some());
+
+        decoder.decode_nal(&sps_nal(2, 0)).unwrap();
+        let err = decoder
+            .decode_nal(&slice_nal(NalUnitType::IdrWRadl, SliceType::I, 0, &[], &[]))
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            Error::UnsupportedProfile(va::Profile::HEVCMain422_10)
+        ));
+    }
+}
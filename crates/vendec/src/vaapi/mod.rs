@@ -1,9 +1,80 @@
-#[cfg(test)]
-mod test {
-    use vendec_libva::Display;
+//! Hardware decoding on VA-API.
+//!
+//! A decoder takes the NAL units or frames of one stream, fills the VA
+//! parameter buffers from the parsed syntax structures and returns decoded
+//! pictures as [`Frame`]s, in display order.
+
+mod frame;
+pub mod h264;
+pub mod hevc;
+
+pub use frame::*;
+
+use vendec_libva as va;
 
-    use super::*;
+#[derive(Debug)]
+pub enum Error {
+    Va(va::Error),
+    H264(crate::h264::Error),
+    Hevc(crate::hevc::Error),
+    /// The stream uses a feature that cannot be decoded on VA-API.
+    Unsupported(&'static str),
+    /// The display cannot decode this profile, the lowest one the stream
+    /// needs.
+    UnsupportedProfile(va::Profile),
+    /// Every surface holds a reference picture or a [`Frame`] that has not
+    /// been dropped.
+    NoFreeSurface,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::Va(err) => write!(f, "{}", err),
+            Error::H264(err) => write!(f, "{}", err),
+            Error::Hevc(err) => write!(f, "{}", err),
+            Error::Unsupported(what) => write!(f, "unsupported: {}", what),
+            Error::UnsupportedProfile(profile) => {
+                write!(f, "the display does not support {:?}", profile)
+            }
+            Error::NoFreeSurface => write!(f, "no free surface to decode into"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Va(err) => Some(err),
+            Error::H264(err) => Some(err),
+            Error::Hevc(err) => Some(err),
+            _ => None,
+        }
+    }
+}
 
+impl From<va::Error> for Error {
+    fn from(err: va::Error) -> Self {
+        Error::Va(err)
+    }
+}
+
+impl From<crate::h264::Error> for Error {
+    fn from(err: crate::h264::Error) -> Self {
+        Error::H264(err)
+    }
+}
+
+impl From<crate::hevc::Error> for Error {
+    fn from(err: crate::hevc::Error) -> Self {
+        Error::Hevc(err)
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[cfg(test)]
+mod test {
     #[test]
     fn test() {}
 }
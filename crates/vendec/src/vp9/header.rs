@@ -0,0 +1,1169 @@
+use super::{Error, Result, MAX_SEGMENTS, NUM_REF_FRAMES, REFS_PER_FRAME, SEG_LVL_MAX};
+use crate::bits::BitReader;
+
+const FRAME_MARKER: u32 = 2;
+const SYNC_CODE: u32 = 0x49_83_42;
+const MIN_TILE_WIDTH_B64: u32 = 4;
+const MAX_TILE_WIDTH_B64: u32 = 64;
+
+/// Bits of the value of each segment feature, and whether a sign follows.
+const SEGMENTATION_FEATURE_BITS: [u32; SEG_LVL_MAX] = [8, 6, 2, 0];
+const SEGMENTATION_FEATURE_SIGNED: [bool; SEG_LVL_MAX] = [true, true, false, false];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FrameType {
+    #[default]
+    KeyFrame,
+    NonKeyFrame,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorSpace {
+    Unknown,
+    #[default]
+    Bt601,
+    Bt709,
+    Smpte170,
+    Smpte240,
+    Bt2020,
+    Reserved,
+    Rgb,
+}
+
+impl From<u8> for ColorSpace {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => ColorSpace::Unknown,
+            1 => ColorSpace::Bt601,
+            2 => ColorSpace::Bt709,
+            3 => ColorSpace::Smpte170,
+            4 => ColorSpace::Smpte240,
+            5 => ColorSpace::Bt2020,
+            6 => ColorSpace::Reserved,
+            _ => ColorSpace::Rgb,
+        }
+    }
+}
+
+/// `interp_filter`, with the values the specification gives the filters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InterpolationFilter {
+    EightTapSmooth,
+    #[default]
+    EightTap,
+    EightTapSharp,
+    Bilinear,
+    /// The filter is chosen per block.
+    Switchable,
+}
+
+/// `color_config()`, and what intra-only frames of profile 0 use instead:
+/// 8-bit BT.601 4:2:0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ColorConfig {
+    /// `BitDepth`.
+    pub bit_depth: u8,
+    pub color_space: ColorSpace,
+    pub color_range: bool,
+    pub subsampling_x: bool,
+    pub subsampling_y: bool,
+}
+
+impl Default for ColorConfig {
+    fn default() -> Self {
+        Self {
+            bit_depth: 8,
+            color_space: ColorSpace::Bt601,
+            color_range: false,
+            subsampling_x: true,
+            subsampling_y: true,
+        }
+    }
+}
+
+impl ColorConfig {
+    fn parse(r: &mut BitReader, profile: u8) -> Result<Self> {
+        let bit_depth = if profile >= 2 {
+            if r.read_bit()? {
+                12
+            } else {
+                10
+            }
+        } else {
+            8
+        };
+        let color_space = ColorSpace::from(r.read_bits(3)? as u8);
+        // Profiles 1 and 3 carry the formats other than 4:2:0.
+        let other_formats = profile == 1 || profile == 3;
+        let mut config = Self {
+            bit_depth,
+            color_space,
+            ..Self::default()
+        };
+        if color_space != ColorSpace::Rgb {
+            config.color_range = r.read_bit()?;
+            if other_formats {
+                config.subsampling_x = r.read_bit()?;
+                config.subsampling_y = r.read_bit()?;
+                if config.subsampling_x && config.subsampling_y {
+                    return Err(Error::InvalidValue {
+                        name: "subsampling_y",
+                        value: 1,
+                    });
+                }
+                read_reserved_zero(r)?;
+            }
+        } else {
+            if !other_formats {
+                return Err(Error::InvalidValue {
+                    name: "color_space",
+                    value: 7,
+                });
+            }
+            config.color_range = true;
+            config.subsampling_x = false;
+            config.subsampling_y = false;
+            read_reserved_zero(r)?;
+        }
+        Ok(config)
+    }
+}
+
+/// `loop_filter_params()`. The deltas carry over from frame to frame
+/// unless updated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoopFilterParams {
+    pub loop_filter_level: u8,
+    pub loop_filter_sharpness: u8,
+    pub loop_filter_delta_enabled: bool,
+    pub loop_filter_delta_update: bool,
+    /// Indexed by reference frame, intra first.
+    pub loop_filter_ref_deltas: [i8; 4],
+    /// For blocks with zero and with other motion vectors.
+    pub loop_filter_mode_deltas: [i8; 2],
+}
+
+impl Default for LoopFilterParams {
+    /// The values `setup_past_independence()` sets.
+    fn default() -> Self {
+        Self {
+            loop_filter_level: 0,
+            loop_filter_sharpness: 0,
+            loop_filter_delta_enabled: true,
+            loop_filter_delta_update: false,
+            loop_filter_ref_deltas: [1, 0, -1, -1],
+            loop_filter_mode_deltas: [0, 0],
+        }
+    }
+}
+
+impl LoopFilterParams {
+    fn parse(&mut self, r: &mut BitReader) -> Result<()> {
+        self.loop_filter_level = r.read_bits(6)? as u8;
+        self.loop_filter_sharpness = r.read_bits(3)? as u8;
+        self.loop_filter_delta_enabled = r.read_bit()?;
+        self.loop_filter_delta_update = false;
+        if self.loop_filter_delta_enabled {
+            self.loop_filter_delta_update = r.read_bit()?;
+            if self.loop_filter_delta_update {
+                for delta in &mut self.loop_filter_ref_deltas {
+                    if r.read_bit()? {
+                        *delta = read_signed(r, 6)? as i8;
+                    }
+                }
+                for delta in &mut self.loop_filter_mode_deltas {
+                    if r.read_bit()? {
+                        *delta = read_signed(r, 6)? as i8;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `quantization_params()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct QuantizationParams {
+    pub base_q_idx: u8,
+    pub delta_q_y_dc: i8,
+    pub delta_q_uv_dc: i8,
+    pub delta_q_uv_ac: i8,
+}
+
+impl QuantizationParams {
+    fn parse(r: &mut BitReader) -> Result<Self> {
+        let base_q_idx = r.read_bits(8)? as u8;
+        let mut read_delta_q = || -> Result<i8> {
+            Ok(if r.read_bit()? {
+                read_signed(r, 4)? as i8
+            } else {
+                0
+            })
+        };
+        Ok(Self {
+            base_q_idx,
+            delta_q_y_dc: read_delta_q()?,
+            delta_q_uv_dc: read_delta_q()?,
+            delta_q_uv_ac: read_delta_q()?,
+        })
+    }
+
+    /// `Lossless`.
+    pub fn lossless(&self) -> bool {
+        self.base_q_idx == 0
+            && self.delta_q_y_dc == 0
+            && self.delta_q_uv_dc == 0
+            && self.delta_q_uv_ac == 0
+    }
+}
+
+/// `segmentation_params()`. The probabilities and the features carry over
+/// from frame to frame unless updated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SegmentationParams {
+    pub segmentation_enabled: bool,
+    pub segmentation_update_map: bool,
+    pub segmentation_tree_probs: [u8; 7],
+    pub segmentation_pred_prob: [u8; 3],
+    pub segmentation_temporal_update: bool,
+    pub segmentation_update_data: bool,
+    pub segmentation_abs_or_delta_update: bool,
+    /// `FeatureEnabled`, by segment and feature.
+    pub feature_enabled: [[bool; SEG_LVL_MAX]; MAX_SEGMENTS],
+    /// `FeatureData`, by segment and feature.
+    pub feature_data: [[i16; SEG_LVL_MAX]; MAX_SEGMENTS],
+}
+
+impl Default for SegmentationParams {
+    fn default() -> Self {
+        Self {
+            segmentation_enabled: false,
+            segmentation_update_map: false,
+            segmentation_tree_probs: [255; 7],
+            segmentation_pred_prob: [255; 3],
+            segmentation_temporal_update: false,
+            segmentation_update_data: false,
+            segmentation_abs_or_delta_update: false,
+            feature_enabled: [[false; SEG_LVL_MAX]; MAX_SEGMENTS],
+            feature_data: [[0; SEG_LVL_MAX]; MAX_SEGMENTS],
+        }
+    }
+}
+
+impl SegmentationParams {
+    fn parse(&mut self, r: &mut BitReader) -> Result<()> {
+        self.segmentation_enabled = r.read_bit()?;
+        self.segmentation_update_map = false;
+        self.segmentation_temporal_update = false;
+        self.segmentation_update_data = false;
+        if !self.segmentation_enabled {
+            return Ok(());
+        }
+        self.segmentation_update_map = r.read_bit()?;
+        if self.segmentation_update_map {
+            for prob in &mut self.segmentation_tree_probs {
+                *prob = read_prob(r)?;
+            }
+            self.segmentation_temporal_update = r.read_bit()?;
+            for prob in &mut self.segmentation_pred_prob {
+                *prob = if self.segmentation_temporal_update {
+                    read_prob(r)?
+                } else {
+                    255
+                };
+            }
+        }
+        self.segmentation_update_data = r.read_bit()?;
+        if self.segmentation_update_data {
+            self.segmentation_abs_or_delta_update = r.read_bit()?;
+            for segment in 0..MAX_SEGMENTS {
+                for feature in 0..SEG_LVL_MAX {
+                    let mut value = 0;
+                    let enabled = r.read_bit()?;
+                    if enabled {
+                        value = r.read_bits(SEGMENTATION_FEATURE_BITS[feature])? as i16;
+                        if SEGMENTATION_FEATURE_SIGNED[feature] && r.read_bit()? {
+                            value = -value;
+                        }
+                    }
+                    self.feature_enabled[segment][feature] = enabled;
+                    self.feature_data[segment][feature] = value;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// `seg_feature_active_idx()`.
+    pub fn feature_active(&self, segment_id: usize, feature: usize) -> bool {
+        self.segmentation_enabled && self.feature_enabled[segment_id][feature]
+    }
+}
+
+/// `uncompressed_header()`, with the state it inherits from earlier
+/// frames filled in.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct FrameHeader {
+    /// `Profile`.
+    pub profile: u8,
+    pub show_existing_frame: bool,
+    pub frame_to_show_map_idx: u8,
+    pub frame_type: FrameType,
+    pub show_frame: bool,
+    pub error_resilient_mode: bool,
+    pub intra_only: bool,
+    pub reset_frame_context: u8,
+    pub color_config: ColorConfig,
+    pub refresh_frame_flags: u8,
+    /// The slot of each of the last, golden and altref references.
+    pub ref_frame_idx: [u8; REFS_PER_FRAME],
+    /// Indexed by reference frame, intra first.
+    pub ref_frame_sign_bias: [bool; 4],
+    /// `FrameWidth`.
+    pub frame_width: u32,
+    /// `FrameHeight`.
+    pub frame_height: u32,
+    pub render_width: u32,
+    pub render_height: u32,
+    pub allow_high_precision_mv: bool,
+    pub interpolation_filter: InterpolationFilter,
+    pub refresh_frame_context: bool,
+    pub frame_parallel_decoding_mode: bool,
+    pub frame_context_idx: u8,
+    pub loop_filter: LoopFilterParams,
+    pub quantization: QuantizationParams,
+    pub segmentation: SegmentationParams,
+    pub tile_cols_log2: u8,
+    pub tile_rows_log2: u8,
+    /// Size of the compressed header.
+    pub header_size_in_bytes: u16,
+    /// Size of the uncompressed header including `trailing_bits()`: the
+    /// offset of the compressed header in the frame.
+    pub uncompressed_header_size: usize,
+}
+
+impl FrameHeader {
+    /// `FrameIsIntra`.
+    pub fn is_intra(&self) -> bool {
+        self.frame_type == FrameType::KeyFrame || self.intra_only
+    }
+
+    /// `MiCols`: the width in 8x8 blocks.
+    pub fn mi_cols(&self) -> u32 {
+        self.frame_width.div_ceil(8)
+    }
+
+    /// `MiRows`.
+    pub fn mi_rows(&self) -> u32 {
+        self.frame_height.div_ceil(8)
+    }
+
+    /// `Sb64Cols`: the width in 64x64 superblocks.
+    pub fn sb64_cols(&self) -> u32 {
+        self.mi_cols().div_ceil(8)
+    }
+
+    fn parse_frame_size(&mut self, r: &mut BitReader) -> Result<()> {
+        self.frame_width = r.read_bits(16)? + 1;
+        self.frame_height = r.read_bits(16)? + 1;
+        Ok(())
+    }
+
+    fn parse_render_size(&mut self, r: &mut BitReader) -> Result<()> {
+        if r.read_bit()? {
+            self.render_width = r.read_bits(16)? + 1;
+            self.render_height = r.read_bits(16)? + 1;
+        } else {
+            self.render_width = self.frame_width;
+            self.render_height = self.frame_height;
+        }
+        Ok(())
+    }
+
+    fn parse_interpolation_filter(&mut self, r: &mut BitReader) -> Result<()> {
+        self.interpolation_filter = if r.read_bit()? {
+            InterpolationFilter::Switchable
+        } else {
+            match r.read_bits(2)? {
+                0 => InterpolationFilter::EightTapSmooth,
+                1 => InterpolationFilter::EightTap,
+                2 => InterpolationFilter::EightTapSharp,
+                _ => InterpolationFilter::Bilinear,
+            }
+        };
+        Ok(())
+    }
+
+    fn parse_tile_info(&mut self, r: &mut BitReader) -> Result<()> {
+        let sb64_cols = self.sb64_cols();
+        let mut min_log2 = 0;
+        while MAX_TILE_WIDTH_B64 << min_log2 < sb64_cols {
+            min_log2 += 1;
+        }
+        let mut max_log2 = 1;
+        while sb64_cols >> max_log2 >= MIN_TILE_WIDTH_B64 {
+            max_log2 += 1;
+        }
+        max_log2 -= 1;
+
+        let mut tile_cols_log2 = min_log2;
+        while tile_cols_log2 < max_log2 && r.read_bit()? {
+            tile_cols_log2 += 1;
+        }
+        self.tile_cols_log2 = tile_cols_log2 as u8;
+        self.tile_rows_log2 = r.read_bit()? as u8;
+        if self.tile_rows_log2 == 1 {
+            self.tile_rows_log2 += r.read_bit()? as u8;
+        }
+        Ok(())
+    }
+}
+
+/// What a reference frame slot holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RefFrame {
+    pub frame_width: u32,
+    pub frame_height: u32,
+    pub color_config: ColorConfig,
+}
+
+/// Parses the frame headers of a stream, keeping what later frames take
+/// from earlier ones: the contents of the reference frame slots, the color
+/// configuration and the loop filter and segmentation parameters.
+#[derive(Debug, Clone, Default)]
+pub struct Parser {
+    ref_frames: [Option<RefFrame>; NUM_REF_FRAMES],
+    color_config: ColorConfig,
+    loop_filter: LoopFilterParams,
+    segmentation: SegmentationParams,
+}
+
+impl Parser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The reference frame slots, as the frames parsed so far have
+    /// refreshed them.
+    pub fn ref_frames(&self) -> &[Option<RefFrame>; NUM_REF_FRAMES] {
+        &self.ref_frames
+    }
+
+    /// Parses the uncompressed header at the start of `frame`, one frame
+    /// of a superframe (see [`split_superframe`](super::split_superframe)),
+    /// and updates the state for the next frames. The state is left as it
+    /// was if the header is invalid.
+    pub fn parse(&mut self, frame: &[u8]) -> Result<FrameHeader> {
+        let mut r = BitReader::new(frame);
+        let mut header = FrameHeader::default();
+        let frame_marker = r.read_bits(2)?;
+        if frame_marker != FRAME_MARKER {
+            return Err(Error::InvalidValue {
+                name: "frame_marker",
+                value: frame_marker as i64,
+            });
+        }
+        let profile_low_bit = r.read_bit()? as u8;
+        let profile_high_bit = r.read_bit()? as u8;
+        header.profile = profile_high_bit << 1 | profile_low_bit;
+        if header.profile == 3 {
+            read_reserved_zero(&mut r)?;
+        }
+
+        header.show_existing_frame = r.read_bit()?;
+        if header.show_existing_frame {
+            header.frame_to_show_map_idx = r.read_bits(3)? as u8;
+            let shown = self.ref_frame(header.frame_to_show_map_idx)?;
+            header.frame_width = shown.frame_width;
+            header.frame_height = shown.frame_height;
+            header.render_width = shown.frame_width;
+            header.render_height = shown.frame_height;
+            header.color_config = shown.color_config;
+            header.show_frame = true;
+            r.align();
+            header.uncompressed_header_size = r.position() / 8;
+            return Ok(header);
+        }
+
+        header.frame_type = if r.read_bit()? {
+            FrameType::NonKeyFrame
+        } else {
+            FrameType::KeyFrame
+        };
+        header.show_frame = r.read_bit()?;
+        header.error_resilient_mode = r.read_bit()?;
+        header.color_config = self.color_config;
+        if header.frame_type == FrameType::KeyFrame {
+            read_frame_sync_code(&mut r)?;
+            header.color_config = ColorConfig::parse(&mut r, header.profile)?;
+            header.parse_frame_size(&mut r)?;
+            header.parse_render_size(&mut r)?;
+            header.refresh_frame_flags = 0xff;
+        } else {
+            if !header.show_frame {
+                header.intra_only = r.read_bit()?;
+            }
+            if !header.error_resilient_mode {
+                header.reset_frame_context = r.read_bits(2)? as u8;
+            }
+            if header.intra_only {
+                read_frame_sync_code(&mut r)?;
+                header.color_config = if header.profile > 0 {
+                    ColorConfig::parse(&mut r, header.profile)?
+                } else {
+                    ColorConfig::default()
+                };
+                header.refresh_frame_flags = r.read_bits(8)? as u8;
+                header.parse_frame_size(&mut r)?;
+                header.parse_render_size(&mut r)?;
+            } else {
+                header.refresh_frame_flags = r.read_bits(8)? as u8;
+                let mut refs = Vec::with_capacity(REFS_PER_FRAME);
+                for i in 0..REFS_PER_FRAME {
+                    header.ref_frame_idx[i] = r.read_bits(3)? as u8;
+                    header.ref_frame_sign_bias[i + 1] = r.read_bit()?;
+                    refs.push(self.ref_frame(header.ref_frame_idx[i])?);
+                }
+                // frame_size_with_refs()
+                let mut found_ref = false;
+                for reference in refs {
+                    found_ref = r.read_bit()?;
+                    if found_ref {
+                        header.frame_width = reference.frame_width;
+                        header.frame_height = reference.frame_height;
+                        break;
+                    }
+                }
+                if !found_ref {
+                    header.parse_frame_size(&mut r)?;
+                }
+                header.parse_render_size(&mut r)?;
+                header.allow_high_precision_mv = r.read_bit()?;
+                header.parse_interpolation_filter(&mut r)?;
+            }
+        }
+
+        if !header.error_resilient_mode {
+            header.refresh_frame_context = r.read_bit()?;
+            header.frame_parallel_decoding_mode = r.read_bit()?;
+        } else {
+            header.frame_parallel_decoding_mode = true;
+        }
+        header.frame_context_idx = r.read_bits(2)? as u8;
+        if header.is_intra() || header.error_resilient_mode {
+            // setup_past_independence()
+            header.loop_filter = LoopFilterParams::default();
+            header.segmentation = SegmentationParams::default();
+            header.frame_context_idx = 0;
+        } else {
+            header.loop_filter = self.loop_filter.clone();
+            header.segmentation = self.segmentation.clone();
+        }
+        header.loop_filter.parse(&mut r)?;
+        header.quantization = QuantizationParams::parse(&mut r)?;
+        header.segmentation.parse(&mut r)?;
+        header.parse_tile_info(&mut r)?;
+        header.header_size_in_bytes = r.read_bits(16)? as u16;
+        // trailing_bits()
+        r.align();
+        header.uncompressed_header_size = r.position() / 8;
+        if header.header_size_in_bytes == 0
+            || header.uncompressed_header_size + header.header_size_in_bytes as usize > frame.len()
+        {
+            return Err(Error::InvalidValue {
+                name: "header_size_in_bytes",
+                value: header.header_size_in_bytes as i64,
+            });
+        }
+
+        self.color_config = header.color_config;
+        self.loop_filter = header.loop_filter.clone();
+        self.segmentation = header.segmentation.clone();
+        let refreshed = RefFrame {
+            frame_width: header.frame_width,
+            frame_height: header.frame_height,
+            color_config: header.color_config,
+        };
+        for (i, slot) in self.ref_frames.iter_mut().enumerate() {
+            if header.refresh_frame_flags & (1 << i) != 0 {
+                *slot = Some(refreshed);
+            }
+        }
+        Ok(header)
+    }
+
+    fn ref_frame(&self, slot: u8) -> Result<RefFrame> {
+        self.ref_frames[slot as usize].ok_or(Error::MissingReference(slot))
+    }
+}
+
+fn read_frame_sync_code(r: &mut BitReader) -> Result<()> {
+    let sync_code = r.read_bits(24)?;
+    if sync_code != SYNC_CODE {
+        return Err(Error::InvalidValue {
+            name: "frame_sync_code",
+            value: sync_code as i64,
+        });
+    }
+    Ok(())
+}
+
+fn read_reserved_zero(r: &mut BitReader) -> Result<()> {
+    if r.read_bit()? {
+        return Err(Error::InvalidValue {
+            name: "reserved_zero",
+            value: 1,
+        });
+    }
+    Ok(())
+}
+
+/// `su(n)`: an `n`-bit magnitude followed by a sign bit.
+fn read_signed(r: &mut BitReader, n: u32) -> Result<i32> {
+    let value = r.read_bits(n)? as i32;
+    Ok(if r.read_bit()? { -value } else { value })
+}
+
+/// `read_prob()`: a coded probability, or 255.
+fn read_prob(r: &mut BitReader) -> Result<u8> {
+    Ok(if r.read_bit()? {
+        r.read_bits(8)? as u8
+    } else {
+        255
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bits::BitWriter;
+    use crate::vp9::{SEG_LVL_ALT_L, SEG_LVL_ALT_Q, SEG_LVL_REF_FRAME, SEG_LVL_SKIP};
+
+    fn write_signed(w: &mut BitWriter, value: i32, n: u32) {
+        w.write_bits(value.unsigned_abs() as u64, n);
+        w.write_bit(value < 0);
+    }
+
+    /// The start of a shown key frame up to `refresh_frame_context`.
+    fn key_frame(profile: u8, width: u32, height: u32) -> BitWriter {
+        let mut w = BitWriter::new();
+        w.write_bits(2, 2); // frame_marker
+        w.write_bit(profile & 1 != 0);
+        w.write_bit(profile & 2 != 0);
+        if profile == 3 {
+            w.write_bit(false);
+        }
+        w.write_bit(false); // show_existing_frame
+        w.write_bit(false); // frame_type
+        w.write_bit(true);
+        w.write_bit(false); // error_resilient_mode
+        w.write_bits(0x49_83_42, 24);
+        if profile >= 2 {
+            w.write_bit(false); // ten_or_twelve_bit
+        }
+        w.write_bits(2, 3); // color_space
+        w.write_bit(true);
+        if profile == 1 || profile == 3 {
+            w.write_bit(true); // subsampling_x
+            w.write_bit(false);
+            w.write_bit(false);
+        }
+        w.write_bits(width as u64 - 1, 16);
+        w.write_bits(height as u64 - 1, 16);
+        w.write_bit(false); // render_and_frame_size_different
+        w
+    }
+
+    /// Writes the parts of a header from `refresh_frame_context` on that
+    /// the tests do not look at, then the compressed header.
+    fn finish(mut w: BitWriter, tile_bits: u32) -> Vec<u8> {
+        w.write_bits(0, 4);
+        w.write_bits(0, 6); // loop_filter_level
+        w.write_bits(0, 3);
+        w.write_bit(false);
+        w.write_bits(1, 8); // base_q_idx
+        w.write_bits(0, 3);
+        w.write_bit(false); // segmentation_enabled
+        w.write_bits(0, tile_bits);
+        write_compressed_header(w)
+    }
+
+    fn write_compressed_header(mut w: BitWriter) -> Vec<u8> {
+        w.write_bits(3, 16); // header_size_in_bytes
+        w.align();
+        w.write_bits(0xabcdef, 24);
+        w.into_bytes()
+    }
+
+    #[test]
+    fn parses_key_frame() {
+        let mut w = key_frame(2, 1920, 1080);
+        w.write_bit(true); // refresh_frame_context
+        w.write_bit(false);
+        w.write_bits(2, 2); // frame_context_idx
+        w.write_bits(36, 6); // loop_filter_level
+        w.write_bits(2, 3);
+        w.write_bit(true);
+        w.write_bit(true); // loop_filter_delta_update
+        for delta in [Some(2), None, Some(-10), None, None, Some(3)] {
+            w.write_bit(delta.is_some());
+            if let Some(delta) = delta {
+                write_signed(&mut w, delta, 6);
+            }
+        }
+        w.write_bits(60, 8); // base_q_idx
+        for delta in [Some(-3), None, Some(5)] {
+            w.write_bit(delta.is_some());
+            if let Some(delta) = delta {
+                write_signed(&mut w, delta, 4);
+            }
+        }
+        w.write_bit(true); // segmentation_enabled
+        w.write_bit(true);
+        for i in 0..7 {
+            w.write_bit(i % 2 == 0); // prob_coded
+            if i % 2 == 0 {
+                w.write_bits(10 * i + 1, 8);
+            }
+        }
+        w.write_bit(false); // segmentation_temporal_update
+        w.write_bit(true);
+        w.write_bit(false); // segmentation_abs_or_delta_update
+        for segment in 0..MAX_SEGMENTS {
+            match segment {
+                1 => {
+                    w.write_bit(true);
+                    write_signed(&mut w, -20, 8);
+                    w.write_bit(true);
+                    write_signed(&mut w, 10, 6);
+                    w.write_bits(0, 2);
+                }
+                2 => {
+                    w.write_bits(0, 2);
+                    w.write_bit(true);
+                    w.write_bits(1, 2); // reference frame
+                    w.write_bit(true);
+                }
+                _ => w.write_bits(0, 4),
+            }
+        }
+        // 30 superblock columns allow up to 4 tile columns.
+        w.write_bit(true); // increment_tile_cols_log2
+        w.write_bit(false);
+        w.write_bit(true); // tile_rows_log2
+        w.write_bit(true);
+        let uncompressed_header_size = (w.position() + 16).div_ceil(8);
+        let frame = write_compressed_header(w);
+
+        let mut parser = Parser::new();
+        let header = parser.parse(&frame).unwrap();
+        assert_eq!(header.profile, 2);
+        assert_eq!(header.frame_type, FrameType::KeyFrame);
+        assert!(header.show_frame && header.is_intra());
+        assert_eq!(
+            header.color_config,
+            ColorConfig {
+                bit_depth: 10,
+                color_space: ColorSpace::Bt709,
+                color_range: true,
+                subsampling_x: true,
+                subsampling_y: true,
+            }
+        );
+        assert_eq!((header.frame_width, header.frame_height), (1920, 1080));
+        assert_eq!((header.render_width, header.render_height), (1920, 1080));
+        assert_eq!(header.refresh_frame_flags, 0xff);
+        assert!(header.refresh_frame_context && !header.frame_parallel_decoding_mode);
+        assert_eq!(header.frame_context_idx, 0);
+        assert_eq!(
+            header.loop_filter,
+            LoopFilterParams {
+                loop_filter_level: 36,
+                loop_filter_sharpness: 2,
+                loop_filter_delta_enabled: true,
+                loop_filter_delta_update: true,
+                loop_filter_ref_deltas: [2, 0, -10, -1],
+                loop_filter_mode_deltas: [0, 3],
+            }
+        );
+        assert_eq!(
+            header.quantization,
+            QuantizationParams {
+                base_q_idx: 60,
+                delta_q_y_dc: -3,
+                delta_q_uv_dc: 0,
+                delta_q_uv_ac: 5,
+            }
+        );
+        assert!(!header.quantization.lossless());
+        let segmentation = &header.segmentation;
+        assert!(segmentation.segmentation_update_map && segmentation.segmentation_update_data);
+        assert_eq!(
+            segmentation.segmentation_tree_probs,
+            [1, 255, 21, 255, 41, 255, 61]
+        );
+        assert_eq!(segmentation.segmentation_pred_prob, [255; 3]);
+        assert_eq!(segmentation.feature_data[1][SEG_LVL_ALT_Q], -20);
+        assert_eq!(segmentation.feature_data[1][SEG_LVL_ALT_L], 10);
+        assert!(segmentation.feature_active(2, SEG_LVL_REF_FRAME));
+        assert_eq!(segmentation.feature_data[2][SEG_LVL_REF_FRAME], 1);
+        assert!(segmentation.feature_active(2, SEG_LVL_SKIP));
+        assert!(!segmentation.feature_active(0, SEG_LVL_ALT_Q));
+        assert_eq!((header.tile_cols_log2, header.tile_rows_log2), (1, 2));
+        assert_eq!(header.header_size_in_bytes, 3);
+        assert_eq!(header.uncompressed_header_size, uncompressed_header_size);
+        assert!(parser.ref_frames().iter().all(|slot| slot.is_some()));
+    }
+
+    #[test]
+    fn parses_inter_frames() {
+        let mut parser = Parser::new();
+        let mut w = key_frame(1, 352, 288);
+        w.write_bits(0, 4);
+        w.write_bits(0, 6); // loop_filter_level
+        w.write_bits(0, 3);
+        w.write_bit(true);
+        w.write_bit(true);
+        w.write_bit(true); // update_ref_delta
+        write_signed(&mut w, 5, 6);
+        w.write_bits(0, 5);
+        w.write_bits(1, 8);
+        w.write_bits(0, 3);
+        w.write_bit(true); // segmentation_enabled
+        w.write_bit(false);
+        w.write_bit(true); // segmentation_update_data
+        w.write_bit(true);
+        w.write_bit(true);
+        w.write_bits(50, 8);
+        w.write_bit(false);
+        w.write_bits(0, 3 + 7 * 4);
+        w.write_bit(false); // tile_rows_log2
+        let key = parser.parse(&write_compressed_header(w)).unwrap();
+        assert_eq!(key.color_config.bit_depth, 8);
+        assert!(key.color_config.subsampling_x && !key.color_config.subsampling_y);
+
+        let mut w = BitWriter::new();
+        w.write_bits(2, 2);
+        w.write_bits(0b10, 2); // profile 1
+        w.write_bit(false);
+        w.write_bit(true); // frame_type
+        w.write_bit(true);
+        w.write_bit(false);
+        w.write_bits(0, 2); // reset_frame_context
+        w.write_bits(0b10, 8);
+        for (slot, sign_bias) in [(0, false), (3, false), (7, true)] {
+            w.write_bits(slot, 3);
+            w.write_bit(sign_bias);
+        }
+        w.write_bit(false); // found_ref
+        w.write_bit(true);
+        w.write_bit(false);
+        w.write_bit(true); // allow_high_precision_mv
+        w.write_bit(false);
+        w.write_bits(2, 2);
+        w.write_bit(false); // refresh_frame_context
+        w.write_bit(true);
+        w.write_bits(3, 2);
+        w.write_bits(10, 6); // loop_filter_level
+        w.write_bits(0, 3);
+        w.write_bit(true);
+        w.write_bit(false); // loop_filter_delta_update
+        w.write_bits(100, 8);
+        w.write_bits(0, 3);
+        w.write_bit(true); // segmentation_enabled
+        w.write_bit(false);
+        w.write_bit(false);
+        // No choice of tile columns at this width.
+        w.write_bit(false); // tile_rows_log2
+        let header = parser.parse(&write_compressed_header(w)).unwrap();
+        assert_eq!(header.frame_type, FrameType::NonKeyFrame);
+        assert!(!header.is_intra());
+        assert_eq!(header.color_config, key.color_config);
+        assert_eq!(header.refresh_frame_flags, 2);
+        assert_eq!(header.ref_frame_idx, [0, 3, 7]);
+        assert_eq!(header.ref_frame_sign_bias, [false, false, false, true]);
+        assert_eq!((header.frame_width, header.frame_height), (352, 288));
+        assert!(header.allow_high_precision_mv);
+        assert_eq!(
+            header.interpolation_filter,
+            InterpolationFilter::EightTapSharp
+        );
+        assert!(header.frame_parallel_decoding_mode);
+        assert_eq!(header.frame_context_idx, 3);
+        // Deltas and segment features carry over.
+        assert_eq!(header.loop_filter.loop_filter_level, 10);
+        assert_eq!(header.loop_filter.loop_filter_ref_deltas, [5, 0, -1, -1]);
+        assert!(header.segmentation.segmentation_abs_or_delta_update);
+        assert!(header.segmentation.feature_active(0, SEG_LVL_ALT_Q));
+        assert_eq!(header.segmentation.feature_data[0][SEG_LVL_ALT_Q], 50);
+        assert_eq!(header.tile_cols_log2, 0);
+
+        // A hidden intra-only frame in a smaller size, then shown.
+        let mut w = BitWriter::new();
+        w.write_bits(2, 2);
+        w.write_bits(0, 3);
+        w.write_bit(true); // frame_type
+        w.write_bit(false);
+        w.write_bit(false);
+        w.write_bit(true); // intra_only
+        w.write_bits(0, 2);
+        w.write_bits(0x49_83_42, 24);
+        w.write_bits(0b100, 8); // refresh_frame_flags
+        w.write_bits(175, 16);
+        w.write_bits(143, 16);
+        w.write_bit(true); // render_and_frame_size_different
+        w.write_bits(99, 16);
+        w.write_bits(99, 16);
+        let header = parser.parse(&finish(w, 1)).unwrap();
+        assert!(header.is_intra() && !header.show_frame);
+        assert_eq!(header.color_config, ColorConfig::default());
+        assert_eq!((header.render_width, header.render_height), (100, 100));
+        assert_eq!(header.loop_filter.loop_filter_ref_deltas, [1, 0, -1, -1]);
+        assert!(!header.segmentation.feature_active(0, SEG_LVL_ALT_Q));
+
+        let header = parser.parse(&[0b1000_1010]).unwrap();
+        assert!(header.show_existing_frame && header.show_frame);
+        assert_eq!(header.frame_to_show_map_idx, 2);
+        assert_eq!((header.frame_width, header.frame_height), (176, 144));
+        assert_eq!(header.uncompressed_header_size, 1);
+        assert_eq!(parser.ref_frames()[1].unwrap().frame_width, 352);
+    }
+
+    /// The start of a shown profile 0 inter frame up to
+    /// `frame_context_idx`, refreshing no slots and taking its size from
+    /// slot 0.
+    fn inter_frame(error_resilient_mode: bool) -> BitWriter {
+        let mut w = BitWriter::new();
+        w.write_bits(2, 2);
+        w.write_bits(0, 3);
+        w.write_bit(true); // frame_type
+        w.write_bit(true);
+        w.write_bit(error_resilient_mode);
+        if !error_resilient_mode {
+            w.write_bits(0, 2); // reset_frame_context
+        }
+        w.write_bits(0, 8); // refresh_frame_flags
+        w.write_bits(0, 3 * 4);
+        w.write_bit(true); // found_ref
+        w.write_bits(0, 2);
+        w.write_bit(true); // is_filter_switchable
+        if !error_resilient_mode {
+            w.write_bits(0, 2);
+        }
+        w.write_bits(0, 2); // frame_context_idx
+        w
+    }
+
+    #[test]
+    fn carries_loop_filter_and_segmentation() {
+        let mut parser = Parser::new();
+        let mut w = key_frame(0, 64, 64);
+        w.write_bits(0, 4);
+        w.write_bits(20, 6); // loop_filter_level
+        w.write_bits(0, 3);
+        w.write_bit(true);
+        w.write_bit(true); // loop_filter_delta_update
+        for delta in [Some(3), None, None, None, None, Some(-2)] {
+            w.write_bit(delta.is_some());
+            if let Some(delta) = delta {
+                write_signed(&mut w, delta, 6);
+            }
+        }
+        w.write_bits(80, 8);
+        w.write_bits(0, 3);
+        w.write_bit(true); // segmentation_enabled
+        w.write_bit(true);
+        w.write_bit(true);
+        w.write_bits(100, 8); // segmentation_tree_probs[0]
+        w.write_bits(0, 6);
+        w.write_bit(false);
+        w.write_bit(true); // segmentation_update_data
+        w.write_bit(false);
+        for segment in 0..MAX_SEGMENTS {
+            if segment == 3 {
+                w.write_bit(true);
+                write_signed(&mut w, -10, 8);
+                w.write_bit(true);
+                write_signed(&mut w, 5, 6);
+                w.write_bits(0, 2);
+            } else {
+                w.write_bits(0, 4);
+            }
+        }
+        w.write_bit(false); // tile_rows_log2
+        parser.parse(&write_compressed_header(w)).unwrap();
+
+        // New deltas for one reference and one mode, and a new map.
+        let mut w = inter_frame(false);
+        w.write_bits(30, 6);
+        w.write_bits(1, 3);
+        w.write_bit(true);
+        w.write_bit(true);
+        for delta in [None, None, Some(4), None, Some(1), None] {
+            w.write_bit(delta.is_some());
+            if let Some(delta) = delta {
+                write_signed(&mut w, delta, 6);
+            }
+        }
+        w.write_bits(90, 8);
+        w.write_bits(0, 3);
+        w.write_bit(true);
+        w.write_bit(true); // segmentation_update_map
+        w.write_bits(0, 6);
+        w.write_bit(true);
+        w.write_bits(200, 8);
+        w.write_bit(true); // segmentation_temporal_update
+        w.write_bit(true);
+        w.write_bits(50, 8);
+        w.write_bits(0, 2);
+        w.write_bit(false); // segmentation_update_data
+        w.write_bit(false);
+        let header = parser.parse(&write_compressed_header(w)).unwrap();
+        assert_eq!(header.loop_filter.loop_filter_ref_deltas, [3, 0, 4, -1]);
+        assert_eq!(header.loop_filter.loop_filter_mode_deltas, [1, -2]);
+        let segmentation = &header.segmentation;
+        assert_eq!(
+            segmentation.segmentation_tree_probs,
+            [255, 255, 255, 255, 255, 255, 200]
+        );
+        assert_eq!(segmentation.segmentation_pred_prob, [50, 255, 255]);
+        assert!(!segmentation.segmentation_update_data);
+        assert!(segmentation.feature_active(3, SEG_LVL_ALT_Q));
+        assert_eq!(segmentation.feature_data[3][SEG_LVL_ALT_Q], -10);
+        assert_eq!(segmentation.feature_data[3][SEG_LVL_ALT_L], 5);
+
+        // Deltas and segmentation off: both are kept for later frames.
+        let mut w = inter_frame(false);
+        w.write_bits(30, 6);
+        w.write_bits(0, 3);
+        w.write_bit(false); // loop_filter_delta_enabled
+        w.write_bits(90, 8);
+        w.write_bits(0, 3);
+        w.write_bit(false); // segmentation_enabled
+        w.write_bit(false);
+        let plain = write_compressed_header(w);
+        let header = parser.parse(&plain).unwrap();
+        assert!(!header.loop_filter.loop_filter_delta_update);
+        assert!(!header.segmentation.feature_active(3, SEG_LVL_ALT_Q));
+        assert_eq!(header.segmentation.feature_data[3][SEG_LVL_ALT_Q], -10);
+
+        // Turned back on without updates, then with new features.
+        let mut w = inter_frame(false);
+        w.write_bits(30, 6);
+        w.write_bits(0, 3);
+        w.write_bit(true);
+        w.write_bit(false); // loop_filter_delta_update
+        w.write_bits(90, 8);
+        w.write_bits(0, 3);
+        w.write_bit(true);
+        w.write_bit(false);
+        w.write_bit(true); // segmentation_update_data
+        w.write_bit(true);
+        w.write_bits(0, 1);
+        w.write_bit(true);
+        write_signed(&mut w, 40, 6);
+        w.write_bits(0, 2 + 7 * 4);
+        w.write_bit(false);
+        let header = parser.parse(&write_compressed_header(w)).unwrap();
+        assert_eq!(header.loop_filter.loop_filter_ref_deltas, [3, 0, 4, -1]);
+        assert_eq!(header.loop_filter.loop_filter_mode_deltas, [1, -2]);
+        let segmentation = &header.segmentation;
+        assert_eq!(segmentation.segmentation_tree_probs[6], 200);
+        assert!(!segmentation.segmentation_temporal_update);
+        assert!(segmentation.segmentation_abs_or_delta_update);
+        assert!(segmentation.feature_active(0, SEG_LVL_ALT_L));
+        assert_eq!(segmentation.feature_data[0][SEG_LVL_ALT_L], 40);
+        assert!(!segmentation.feature_active(3, SEG_LVL_ALT_Q));
+        assert_eq!(segmentation.feature_data[3][SEG_LVL_ALT_Q], 0);
+
+        // An invalid frame updating the deltas changes nothing.
+        let mut w = inter_frame(false);
+        w.write_bits(30, 6);
+        w.write_bits(0, 3);
+        w.write_bit(true);
+        w.write_bit(true);
+        w.write_bit(true);
+        write_signed(&mut w, -5, 6);
+        w.write_bits(0, 5);
+        w.write_bits(90, 8);
+        w.write_bits(0, 3);
+        w.write_bit(false);
+        w.write_bit(false);
+        let mut frame = write_compressed_header(w);
+        frame.pop();
+        assert!(parser.parse(&frame).is_err());
+        let header = parser.parse(&plain).unwrap();
+        assert_eq!(header.loop_filter.loop_filter_ref_deltas, [3, 0, 4, -1]);
+
+        // Error resilient frames start from the defaults.
+        let mut w = inter_frame(true);
+        w.write_bits(30, 6);
+        w.write_bits(0, 3);
+        w.write_bit(true);
+        w.write_bit(false);
+        w.write_bits(90, 8);
+        w.write_bits(0, 3);
+        w.write_bit(false);
+        w.write_bit(false);
+        let header = parser.parse(&write_compressed_header(w)).unwrap();
+        assert_eq!(
+            header.loop_filter,
+            LoopFilterParams {
+                loop_filter_level: 30,
+                ..Default::default()
+            }
+        );
+        assert_eq!(header.segmentation, SegmentationParams::default());
+    }
+
+    #[test]
+    fn rejects_malformed_frames() {
+        let mut parser = Parser::new();
+        assert_eq!(
+            parser.parse(&[0x40]),
+            Err(Error::InvalidValue {
+                name: "frame_marker",
+                value: 1,
+            })
+        );
+        // Nothing has been stored in the slot to show.
+        assert_eq!(parser.parse(&[0x8b]), Err(Error::MissingReference(3)));
+
+        let mut frame = finish(key_frame(0, 64, 64), 1);
+        frame[2] = 0x84;
+        assert!(matches!(
+            parser.parse(&frame),
+            Err(Error::InvalidValue {
+                name: "frame_sync_code",
+                ..
+            })
+        ));
+
+        let mut w = BitWriter::new();
+        // Profile 0, a shown key frame.
+        w.write_bits(0b1000_0010, 8);
+        w.write_bits(0x49_83_42, 24);
+        w.write_bits(7, 3); // color_space
+        assert_eq!(
+            parser.parse(&w.into_bytes()),
+            Err(Error::InvalidValue {
+                name: "color_space",
+                value: 7,
+            })
+        );
+
+        // The compressed header does not fit.
+        let mut frame = finish(key_frame(0, 64, 64), 1);
+        frame.pop();
+        assert!(matches!(
+            parser.parse(&frame),
+            Err(Error::InvalidValue {
+                name: "header_size_in_bytes",
+                value: 3,
+            })
+        ));
+        assert!(parser.ref_frames().iter().all(|slot| slot.is_none()));
+        assert!(parser.parse(&finish(key_frame(0, 64, 64), 1)).is_ok());
+    }
+}
@@ -0,0 +1,82 @@
+//! VP9 bitstream parsing.
+//!
+//! Like the H.264 and HEVC parsers, syntax structures keep the names of the
+//! syntax elements of the VP9 bitstream specification. Only the
+//! uncompressed header is parsed: the compressed header and the tiles are
+//! passed to the driver as they are.
+
+mod header;
+mod quant;
+mod segmentation;
+mod superframe;
+
+pub use header::*;
+pub use quant::*;
+pub use segmentation::*;
+pub use superframe::*;
+
+use crate::bits;
+
+/// Number of reference frame slots.
+pub const NUM_REF_FRAMES: usize = 8;
+/// Number of references an inter frame picks from the slots.
+pub const REFS_PER_FRAME: usize = 3;
+pub const MAX_SEGMENTS: usize = 8;
+
+/// Segment features, indexing `FeatureEnabled` and `FeatureData`.
+pub const SEG_LVL_ALT_Q: usize = 0;
+pub const SEG_LVL_ALT_L: usize = 1;
+pub const SEG_LVL_REF_FRAME: usize = 2;
+pub const SEG_LVL_SKIP: usize = 3;
+pub const SEG_LVL_MAX: usize = 4;
+
+pub const MAX_LOOP_FILTER: u8 = 63;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    Bits(bits::Error),
+    /// A syntax element has a value the specification does not allow.
+    InvalidValue {
+        name: &'static str,
+        value: i64,
+    },
+    /// A frame uses a reference frame slot no frame has been stored in.
+    MissingReference(u8),
+    /// The sizes in a superframe index do not fit in the superframe.
+    InvalidSuperframeIndex,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::Bits(err) => write!(f, "{}", err),
+            Error::InvalidValue { name, value } => write!(f, "invalid {}: {}", name, value),
+            Error::MissingReference(slot) => {
+                write!(f, "reference frame slot {} is empty", slot)
+            }
+            Error::InvalidSuperframeIndex => write!(f, "invalid superframe index"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Bits(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<bits::Error> for Error {
+    fn from(err: bits::Error) -> Self {
+        Error::Bits(err)
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Clamps a quantizer index into `0..=255`.
+pub(crate) fn clip_qindex(qindex: i32) -> u8 {
+    qindex.clamp(0, 255) as u8
+}
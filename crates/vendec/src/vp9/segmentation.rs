@@ -0,0 +1,188 @@
+use super::{
+    ac_q, clip_qindex, dc_q, FrameHeader, MAX_LOOP_FILTER, MAX_SEGMENTS, SEG_LVL_ALT_L,
+    SEG_LVL_ALT_Q, SEG_LVL_REF_FRAME, SEG_LVL_SKIP,
+};
+
+/// What decoding blocks of one segment takes from the frame header: the
+/// fields of `VASegmentParameterVP9`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SegmentParams {
+    pub reference_enabled: bool,
+    /// The reference frame every block of the segment uses, intra first.
+    pub reference: u8,
+    pub skip: bool,
+    /// The loop filter level by reference frame, intra first, and by
+    /// whether the motion vector is zero (0) or not (1), as section 8.8.1
+    /// derives it.
+    pub filter_level: [[u8; 2]; 4],
+    pub luma_ac_quant_scale: i16,
+    pub luma_dc_quant_scale: i16,
+    pub chroma_ac_quant_scale: i16,
+    pub chroma_dc_quant_scale: i16,
+}
+
+impl SegmentParams {
+    /// The parameters of segment `segment_id` of the frame of `header`.
+    pub fn new(header: &FrameHeader, segment_id: usize) -> Self {
+        let segmentation = &header.segmentation;
+        let quantization = &header.quantization;
+        let bit_depth = header.color_config.bit_depth;
+        let qindex = segment_qindex(header, segment_id);
+        let reference_enabled = segmentation.feature_active(segment_id, SEG_LVL_REF_FRAME);
+        Self {
+            reference_enabled,
+            reference: if reference_enabled {
+                segmentation.feature_data[segment_id][SEG_LVL_REF_FRAME] as u8
+            } else {
+                0
+            },
+            skip: segmentation.feature_active(segment_id, SEG_LVL_SKIP),
+            filter_level: filter_levels(header, segment_id),
+            luma_ac_quant_scale: ac_q(bit_depth, qindex, 0),
+            luma_dc_quant_scale: dc_q(bit_depth, qindex, quantization.delta_q_y_dc),
+            chroma_ac_quant_scale: ac_q(bit_depth, qindex, quantization.delta_q_uv_ac),
+            chroma_dc_quant_scale: dc_q(bit_depth, qindex, quantization.delta_q_uv_dc),
+        }
+    }
+
+    /// The parameters of every segment. Only the first one applies when
+    /// segmentation is disabled.
+    pub fn all(header: &FrameHeader) -> [Self; MAX_SEGMENTS] {
+        std::array::from_fn(|segment_id| Self::new(header, segment_id))
+    }
+}
+
+/// `get_qindex()`.
+fn segment_qindex(header: &FrameHeader, segment_id: usize) -> u8 {
+    let segmentation = &header.segmentation;
+    let base_q_idx = header.quantization.base_q_idx;
+    if !segmentation.feature_active(segment_id, SEG_LVL_ALT_Q) {
+        return base_q_idx;
+    }
+    let data = segmentation.feature_data[segment_id][SEG_LVL_ALT_Q] as i32;
+    if segmentation.segmentation_abs_or_delta_update {
+        clip_qindex(data)
+    } else {
+        clip_qindex(base_q_idx as i32 + data)
+    }
+}
+
+fn filter_levels(header: &FrameHeader, segment_id: usize) -> [[u8; 2]; 4] {
+    let segmentation = &header.segmentation;
+    let loop_filter = &header.loop_filter;
+    let clamp = |level: i32| level.clamp(0, MAX_LOOP_FILTER as i32);
+
+    let mut level = loop_filter.loop_filter_level as i32;
+    if segmentation.feature_active(segment_id, SEG_LVL_ALT_L) {
+        let data = segmentation.feature_data[segment_id][SEG_LVL_ALT_L] as i32;
+        level = clamp(if segmentation.segmentation_abs_or_delta_update {
+            data
+        } else {
+            level + data
+        });
+    }
+    if !loop_filter.loop_filter_delta_enabled {
+        return [[level as u8; 2]; 4];
+    }
+    let shift = level >> 5;
+    let mut levels = [[0; 2]; 4];
+    for (ref_frame, modes) in levels.iter_mut().enumerate() {
+        let ref_delta = (loop_filter.loop_filter_ref_deltas[ref_frame] as i32) << shift;
+        for (mode, entry) in modes.iter_mut().enumerate() {
+            // Mode deltas do not apply to intra blocks.
+            let mode_delta = if ref_frame == 0 {
+                0
+            } else {
+                (loop_filter.loop_filter_mode_deltas[mode] as i32) << shift
+            };
+            *entry = clamp(level + ref_delta + mode_delta) as u8;
+        }
+    }
+    levels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vp9::{LoopFilterParams, QuantizationParams, SegmentationParams};
+
+    fn header() -> FrameHeader {
+        let mut segmentation = SegmentationParams {
+            segmentation_enabled: true,
+            ..Default::default()
+        };
+        segmentation.feature_enabled[1][SEG_LVL_ALT_Q] = true;
+        segmentation.feature_data[1][SEG_LVL_ALT_Q] = -20;
+        segmentation.feature_enabled[1][SEG_LVL_ALT_L] = true;
+        segmentation.feature_data[1][SEG_LVL_ALT_L] = 10;
+        segmentation.feature_enabled[2][SEG_LVL_REF_FRAME] = true;
+        segmentation.feature_data[2][SEG_LVL_REF_FRAME] = 1;
+        segmentation.feature_enabled[2][SEG_LVL_SKIP] = true;
+        FrameHeader {
+            loop_filter: LoopFilterParams {
+                loop_filter_level: 36,
+                loop_filter_ref_deltas: [2, 0, -10, -1],
+                loop_filter_mode_deltas: [0, 3],
+                ..Default::default()
+            },
+            quantization: QuantizationParams {
+                base_q_idx: 60,
+                delta_q_y_dc: -3,
+                delta_q_uv_dc: 0,
+                delta_q_uv_ac: 5,
+            },
+            segmentation,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn derives_segment_params() {
+        let mut header = header();
+        let segments = SegmentParams::all(&header);
+        assert_eq!(
+            segments[0],
+            SegmentParams {
+                reference_enabled: false,
+                reference: 0,
+                skip: false,
+                // Deltas are doubled for levels of 32 and up.
+                filter_level: [[40, 40], [36, 42], [16, 22], [34, 40]],
+                luma_ac_quant_scale: 67,
+                luma_dc_quant_scale: 55,
+                chroma_ac_quant_scale: 72,
+                chroma_dc_quant_scale: 57,
+            }
+        );
+        assert_eq!(
+            segments[1],
+            SegmentParams {
+                filter_level: [[50, 50], [46, 52], [26, 32], [44, 50]],
+                luma_ac_quant_scale: 47,
+                luma_dc_quant_scale: 38,
+                chroma_ac_quant_scale: 52,
+                chroma_dc_quant_scale: 41,
+                ..segments[0]
+            }
+        );
+        assert!(segments[2].reference_enabled && segments[2].skip);
+        assert_eq!(segments[2].reference, 1);
+        assert_eq!(segments[3], segments[0]);
+
+        // Absolute values, clamped, and no deltas.
+        header.segmentation.segmentation_abs_or_delta_update = true;
+        header.segmentation.feature_data[1][SEG_LVL_ALT_Q] = 255;
+        header.segmentation.feature_data[1][SEG_LVL_ALT_L] = 63;
+        header.loop_filter.loop_filter_delta_enabled = false;
+        header.color_config.bit_depth = 10;
+        let segment = SegmentParams::new(&header, 1);
+        assert_eq!(segment.filter_level, [[63; 2]; 4]);
+        assert_eq!(segment.luma_ac_quant_scale, 7312);
+        assert_eq!(SegmentParams::new(&header, 0).filter_level, [[36; 2]; 4]);
+
+        // Segment features do nothing without segmentation.
+        header.segmentation.segmentation_enabled = false;
+        let segments = SegmentParams::all(&header);
+        assert!(segments.iter().all(|segment| *segment == segments[0]));
+    }
+}
@@ -0,0 +1,75 @@
+use super::{Error, Result};
+
+/// Splits a chunk of a VP9 stream, as containers store them, into frames.
+///
+/// A superframe (Annex B) packs several frames, typically a hidden one and
+/// the one shown after it, followed by an index of their sizes. Chunks
+/// without an index are a single frame. An index whose first and last
+/// bytes differ is not one, as libvpx also assumes. Frames the index
+/// gives no bytes are left out, as there is nothing to decode.
+pub fn split_superframe(data: &[u8]) -> Result<Vec<&[u8]>> {
+    let Some(&marker) = data.last() else {
+        return Ok(Vec::new());
+    };
+    if marker & 0xe0 != 0xc0 {
+        return Ok(vec![data]);
+    }
+    let frames_in_superframe = (marker & 0x7) as usize + 1;
+    let bytes_per_framesize = ((marker >> 3) & 0x3) as usize + 1;
+    let index_size = 2 + bytes_per_framesize * frames_in_superframe;
+    if data.len() < index_size || data[data.len() - index_size] != marker {
+        return Ok(vec![data]);
+    }
+
+    let frames_size = data.len() - index_size;
+    let sizes = &data[frames_size + 1..data.len() - 1];
+    let mut frames = Vec::with_capacity(frames_in_superframe);
+    let mut offset = 0;
+    for size in sizes.chunks(bytes_per_framesize) {
+        let size = size
+            .iter()
+            .rev()
+            .fold(0, |size, &byte| size << 8 | byte as usize);
+        if size > frames_size - offset {
+            return Err(Error::InvalidSuperframeIndex);
+        }
+        if size != 0 {
+            frames.push(&data[offset..offset + size]);
+            offset += size;
+        }
+    }
+    Ok(frames)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_superframes() {
+        assert_eq!(split_superframe(&[]), Ok(vec![]));
+        let frame = [0x82, 0x49, 0x83, 0x42, 0x00];
+        assert_eq!(split_superframe(&frame), Ok(vec![&frame[..]]));
+
+        // Two frames of 3 and 2 bytes, with 1-byte sizes.
+        let data = [1, 2, 3, 4, 5, 0xc1, 3, 2, 0xc1];
+        assert_eq!(split_superframe(&data), Ok(vec![&data[..3], &data[3..5]]));
+        // 2-byte sizes, and a frame ending in what looks like a marker.
+        let mut data = vec![0xaa; 300];
+        data.push(0xc8);
+        data.extend([0xc9, 0x2c, 0x01, 0x01, 0x00, 0xc9]);
+        let frames = split_superframe(&data).unwrap();
+        assert_eq!(frames.iter().map(|f| f.len()).collect::<Vec<_>>(), [300, 1]);
+        assert_eq!(frames[1], [0xc8]);
+
+        // An empty frame in the middle.
+        let data = [1, 2, 3, 4, 5, 0xc2, 3, 0, 2, 0xc2];
+        assert_eq!(split_superframe(&data), Ok(vec![&data[..3], &data[3..5]]));
+
+        // The first byte of the index does not match: a single frame.
+        let data = [1, 2, 3, 4, 5, 0xc0, 3, 2, 0xc1];
+        assert_eq!(split_superframe(&data), Ok(vec![&data[..]]));
+        let data = [1, 2, 3, 0xc1, 3, 2, 0xc1];
+        assert_eq!(split_superframe(&data), Err(Error::InvalidSuperframeIndex));
+    }
+}